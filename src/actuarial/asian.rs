@@ -0,0 +1,214 @@
+//! Asian options, whose payoff is on the average share price at fixings over a window
+//! rather than the price at maturity. The geometric average is lognormal, so it has a
+//! closed form; the arithmetic average that plans actually use is simulated, with the
+//! geometric one as a control variate.
+
+use crate::actuarial::monte_carlo::{simulate, ControlVariate};
+use crate::actuarial::option_pricing::{normal_cdf, OptionType, ParameterError, PositiveFloat, PositiveInt, Volatility};
+use crate::actuarial::rng::{BrownianBridge, Sampling};
+use xladd_derive::xl_func;
+
+/// When the share price is fixed for the average: `fixings` equally spaced times from
+/// after `averaging_start` up to and including maturity
+pub fn fixing_times(time_to_maturity: f64, averaging_start: f64, fixings: usize) -> Result<Vec<f64>, ParameterError> {
+    if !(time_to_maturity > 0.0 && time_to_maturity.is_finite()) {
+        return Err(ParameterError::InvalidPositiveValue { parameter: "time_to_maturity", value: time_to_maturity });
+    }
+    let averaging_start = PositiveFloat::new(averaging_start, "averaging_start")?.min_f64(time_to_maturity)?.0;
+    let fixings = PositiveInt::new(fixings, "fixings")?.0;
+    let spacing = (time_to_maturity - averaging_start) / fixings as f64;
+    Ok((1..=fixings).map(|k| averaging_start + spacing * k as f64).collect())
+}
+
+/// Closed-form value of an option on the geometric average of the share price at the
+/// given times
+pub fn geometric_asian_price(
+    option_type: OptionType,
+    share_price: f64,
+    strike_price: f64,
+    risk_free: f64,
+    div_rate: f64,
+    sigma: f64,
+    times: &[f64],
+) -> f64 {
+    let n = times.len() as f64;
+    let time_to_maturity = times[times.len() - 1];
+    let mean_time = times.iter().sum::<f64>() / n;
+    // The covariance of log prices at two times is sigma^2 times the earlier of them;
+    // the k-th time is the earlier in 2(n - k) + 1 of the pairs
+    let covariance_sum: f64 = times.iter().enumerate().map(|(k, t)| t * (2.0 * (n - k as f64) - 1.0)).sum();
+    let log_mean = share_price.ln() + (risk_free - div_rate - 0.5 * sigma * sigma) * mean_time;
+    let log_sd = sigma * covariance_sum.sqrt() / n;
+
+    let forward = (log_mean + 0.5 * log_sd * log_sd).exp();
+    let discount = (-risk_free * time_to_maturity).exp();
+    let d2 = (log_mean - strike_price.ln()) / log_sd;
+    let d1 = d2 + log_sd;
+    match option_type {
+        OptionType::Call => discount * (forward * normal_cdf(d1) - strike_price * normal_cdf(d2)),
+        OptionType::Put => discount * (strike_price * normal_cdf(-d2) - forward * normal_cdf(-d1)),
+    }
+}
+
+/// Monte Carlo value of an option on the arithmetic average of the share price at the
+/// given times, and its standard error. Each path is built by a Brownian bridge, the
+/// price at maturity first.
+pub fn arithmetic_asian_price(
+    option_type: OptionType,
+    share_price: f64,
+    strike_price: f64,
+    risk_free: f64,
+    div_rate: f64,
+    sigma: f64,
+    times: &[f64],
+    paths: usize,
+    seed: i32,
+    sampling: Sampling,
+) -> (f64, f64) {
+    let n = times.len() as f64;
+    let discount = (-risk_free * times[times.len() - 1]).exp();
+    let payoff = |average: f64| match option_type {
+        OptionType::Call => discount * (average - strike_price).max(0.0),
+        OptionType::Put => discount * (strike_price - average).max(0.0),
+    };
+    let drifts: Vec<f64> = times.iter().map(|t| (risk_free - div_rate - 0.5 * sigma * sigma) * t).collect();
+    let bridge = BrownianBridge::new(times);
+
+    let chunks = simulate(paths, seed, sampling, times.len(), |normals, paths| {
+        let mut z = vec![0.0; times.len()];
+        let mut brownian = vec![0.0; times.len()];
+        let mut controlled = ControlVariate::default();
+        for _ in 0..paths {
+            normals.fill(&mut z);
+            bridge.build(&z, &mut brownian);
+            let (mut sum, mut log_sum) = (0.0, 0.0);
+            for (drift, w) in drifts.iter().zip(&brownian) {
+                let log_price = share_price.ln() + drift + sigma * w;
+                sum += log_price.exp();
+                log_sum += log_price;
+            }
+            controlled.add(payoff(sum / n), payoff((log_sum / n).exp()));
+        }
+        controlled
+    });
+    let mut controlled = ControlVariate::default();
+    for chunk in &chunks {
+        controlled.merge(chunk);
+    }
+    let expected_control = geometric_asian_price(option_type, share_price, strike_price, risk_free, div_rate, sigma, times);
+    controlled.estimate(expected_control)
+}
+
+/// Value of an option on the geometric average share price, in closed form
+/// * share_price: Current share price
+/// * strike_price: Strike price of the option
+/// * time_to_maturity: Time to maturity in years, which is the last fixing
+/// * risk_free: Risk-free interest rate (continuously compounded)
+/// * div_rate: Dividend yield (continuously compounded)
+/// * sigma: Volatility of the share
+/// * averaging_start: Years until the averaging window opens (0 for from today)
+/// * fixings: Number of equally spaced prices in the average
+/// * call_put: Call or Put (C or P also work)
+/// * ret: The option value
+#[xl_func()]
+pub fn geometric_asian_value(
+    share_price: f64,
+    strike_price: f64,
+    time_to_maturity: f64,
+    risk_free: f64,
+    div_rate: f64,
+    sigma: f64,
+    averaging_start: f64,
+    fixings: i32,
+    call_put: String,
+) -> Result<f64, Box<dyn std::error::Error>> {
+    let option_type = OptionType::new(&call_put)?;
+    let share_price = PositiveFloat::new(share_price, "share_price")?.0;
+    let strike_price = PositiveFloat::new(strike_price, "strike_price")?.0;
+    let sigma = Volatility::new(sigma)?.0;
+    let times = fixing_times(time_to_maturity, averaging_start, fixings.max(0) as usize)?;
+    Ok(geometric_asian_price(option_type, share_price, strike_price, risk_free, div_rate, sigma, &times))
+}
+
+/// Value of an option on the arithmetic average share price, by Monte Carlo with the
+/// geometric average as a control variate
+/// * share_price: Current share price
+/// * strike_price: Strike price of the option
+/// * time_to_maturity: Time to maturity in years, which is the last fixing
+/// * risk_free: Risk-free interest rate (continuously compounded)
+/// * div_rate: Dividend yield (continuously compounded)
+/// * sigma: Volatility of the share
+/// * averaging_start: Years until the averaging window opens (0 for from today)
+/// * fixings: Number of equally spaced prices in the average
+/// * call_put: Call or Put (C or P also work)
+/// * paths: Number of simulated paths
+/// * seed: Seed for the random numbers; the same seed gives the same value
+/// * sampling: Pseudo or Sobol (P or S also work); Sobol's standard error is overstated
+/// * ret: The value, its standard error and the geometric value used as the control
+#[xl_func()]
+pub fn asian_option_value(
+    share_price: f64,
+    strike_price: f64,
+    time_to_maturity: f64,
+    risk_free: f64,
+    div_rate: f64,
+    sigma: f64,
+    averaging_start: f64,
+    fixings: i32,
+    call_put: String,
+    paths: i32,
+    seed: i32,
+    sampling: String,
+) -> Result<Vec<(String, f64)>, Box<dyn std::error::Error>> {
+    let option_type = OptionType::new(&call_put)?;
+    let sampling = Sampling::new(&sampling)?;
+    let share_price = PositiveFloat::new(share_price, "share_price")?.0;
+    let strike_price = PositiveFloat::new(strike_price, "strike_price")?.0;
+    let sigma = Volatility::new(sigma)?.0;
+    let paths = PositiveInt::new(paths.max(0) as usize, "paths")?.0;
+    let times = fixing_times(time_to_maturity, averaging_start, fixings.max(0) as usize)?;
+    let (value, standard_error) =
+        arithmetic_asian_price(option_type, share_price, strike_price, risk_free, div_rate, sigma, &times, paths, seed, sampling);
+    let geometric = geometric_asian_price(option_type, share_price, strike_price, risk_free, div_rate, sigma, &times);
+    Ok(vec![
+        ("Value".to_string(), value),
+        ("Standard error".to_string(), standard_error),
+        ("Geometric value".to_string(), geometric),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actuarial::option_pricing::{black_scholes_call_option_value, black_scholes_put_option_value};
+
+    #[test]
+    fn one_fixing_at_maturity_is_a_european_option() {
+        let times = fixing_times(2.0, 2.0, 1).unwrap();
+        assert_eq!(times, [2.0]);
+        let call = geometric_asian_price(OptionType::Call, 100.0, 95.0, 0.04, 0.01, 0.25, &times);
+        let put = geometric_asian_price(OptionType::Put, 100.0, 95.0, 0.04, 0.01, 0.25, &times);
+        assert!((call - black_scholes_call_option_value(100.0, 95.0, 2.0, 0.04, 0.01, 0.25)).abs() < 1e-9);
+        assert!((put - black_scholes_put_option_value(100.0, 95.0, 2.0, 0.04, 0.01, 0.25)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn arithmetic_average_call_is_worth_more_than_geometric() {
+        let times = fixing_times(1.0, 0.0, 12).unwrap();
+        let geometric = geometric_asian_price(OptionType::Call, 100.0, 100.0, 0.05, 0.0, 0.3, &times);
+        let (arithmetic, standard_error) = arithmetic_asian_price(OptionType::Call, 100.0, 100.0, 0.05, 0.0, 0.3, &times, 20000, 3, Sampling::Pseudo);
+        assert!(arithmetic > geometric + 3.0 * standard_error, "{} {} {}", arithmetic, geometric, standard_error);
+        // The control takes out nearly all of the simulation error
+        assert!(standard_error < 0.01, "{}", standard_error);
+    }
+
+    #[test]
+    fn arithmetic_average_satisfies_put_call_parity() {
+        let times = fixing_times(3.0, 1.0, 24).unwrap();
+        let (call, call_error) = arithmetic_asian_price(OptionType::Call, 100.0, 105.0, 0.04, 0.02, 0.35, &times, 20000, 5, Sampling::Sobol);
+        let (put, put_error) = arithmetic_asian_price(OptionType::Put, 100.0, 105.0, 0.04, 0.02, 0.35, &times, 20000, 5, Sampling::Sobol);
+        let expected_average = times.iter().map(|t| 100.0 * ((0.04 - 0.02) * t).exp()).sum::<f64>() / times.len() as f64;
+        let parity = (-0.04f64 * 3.0).exp() * (expected_average - 105.0);
+        assert!((call - put - parity).abs() < 4.0 * (call_error + put_error), "{} {} {}", call, put, parity);
+    }
+}
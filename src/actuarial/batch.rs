@@ -0,0 +1,148 @@
+//! Valuing a whole table of grants at once, one row per grant, as a reporting date needs.
+//! The rows are independent, so they are valued in parallel on the add-in's thread pool.
+
+use crate::actuarial::option_pricing::{
+    binomial_option_value, trinomial_option_value, TreeModel, OPTION_PARAMETER_NAMES,
+};
+use rayon::prelude::*;
+use xladd_core::variant::Variant;
+use xladd_core::xlcall::xlerrNA;
+use xladd_derive::xl_func;
+
+/// The columns of a grants table, in the order `binomial_option_value` takes them. A
+/// twelfth column, if there is one, picks the tree model for the row.
+const GRANT_COLUMNS: [&str; 11] = OPTION_PARAMETER_NAMES;
+
+/// One row of a grants table
+#[derive(Debug, Clone, PartialEq)]
+pub struct Grant {
+    pub inputs: [f64; 10],
+    pub steps: i32,
+    pub model: TreeModel,
+}
+
+impl Grant {
+    /// The fair value and expected life of the grant
+    pub fn value(&self) -> Result<Vec<f64>, String> {
+        let value = match self.model {
+            TreeModel::Binomial => binomial_option_value,
+            TreeModel::Trinomial => trinomial_option_value,
+        };
+        let [share_price, strike_price, time_to_maturity, vesting_period, risk_free, sigma, div_rate, exit_pre_vesting, exit_post_vesting, multiple] =
+            self.inputs;
+        value(
+            share_price, strike_price, time_to_maturity, vesting_period,
+            risk_free, sigma, div_rate,
+            exit_pre_vesting, exit_post_vesting,
+            multiple,
+            self.steps,
+        )
+        .map_err(|e| e.to_string())
+    }
+}
+
+/// The grant in a row of the table, or None if the row is blank
+fn read_grant(grants: &Variant, row: usize) -> Result<Option<Grant>, String> {
+    let (width, _) = grants.dim();
+    if (0..width).all(|column| grants.at(column, row).is_missing_or_null()) {
+        return Ok(None);
+    }
+    let mut numbers = [0.0; 11];
+    for (column, name) in GRANT_COLUMNS.iter().enumerate() {
+        numbers[column] = f64::try_from(&grants.at(column, row)).map_err(|_| format!("{} is not a number", name))?;
+    }
+    let model = if width > GRANT_COLUMNS.len() && !grants.at(GRANT_COLUMNS.len(), row).is_missing_or_null() {
+        TreeModel::new(&String::from(&grants.at(GRANT_COLUMNS.len(), row))).map_err(|e| e.to_string())?
+    } else {
+        TreeModel::Binomial
+    };
+    let mut inputs = [0.0; 10];
+    inputs.copy_from_slice(&numbers[..10]);
+    Ok(Some(Grant { inputs, steps: numbers[10].round() as i32, model }))
+}
+
+/// Fair value and expected life of every grant in a table, one grant per row
+/// * grants: Columns share_price, strike_price, time_to_maturity, vesting_period, risk_free, sigma, div_rate, exit_pre_vesting, exit_post_vesting, multiple, steps and optionally the model (Binomial or Trinomial), with or without a header row
+/// * ret: Fair value and expected life for each row, level with it; a row that can't be valued shows why and #N/A
+#[xl_func()]
+pub fn value_grants(grants: Variant) -> Result<Vec<Vec<Variant>>, Box<dyn std::error::Error>> {
+    let (width, rows) = grants.dim();
+    if width < GRANT_COLUMNS.len() {
+        return Err(format!("grants needs {} columns, {}, not {}", GRANT_COLUMNS.len(), GRANT_COLUMNS.join(", "), width).into());
+    }
+    let first = grants.at(0, 0);
+    let has_header = !first.is_missing_or_null() && f64::try_from(&first).is_err();
+
+    let mut table = Vec::with_capacity(rows);
+    if has_header {
+        table.push(vec![Variant::from("Fair value"), Variant::from("Expected life")]);
+    }
+    // The range belongs to Excel's calculation thread, so it is read here and only the
+    // grants go to the pool
+    let grants: Vec<_> = (usize::from(has_header)..rows).map(|row| read_grant(&grants, row)).collect();
+    let results: Vec<_> = xladd_core::pool::install(|| {
+        grants
+            .into_par_iter()
+            .map(|grant| grant.and_then(|grant| grant.map(|grant| grant.value()).transpose()))
+            .collect()
+    });
+    for result in results {
+        table.push(match result {
+            Ok(Some(result)) => vec![Variant::from(result[0]), Variant::from(result[1])],
+            Ok(None) => vec![Variant::from(""), Variant::from("")],
+            Err(reason) => vec![Variant::from(reason), Variant::from_err(xlerrNA)],
+        });
+    }
+    if table.is_empty() {
+        return Err("grants has no rows".into());
+    }
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grant_row(values: [f64; 11]) -> Vec<Variant> {
+        values.map(Variant::from).to_vec()
+    }
+
+    #[test]
+    fn values_each_row_as_the_single_grant_function_does() {
+        let mut header = GRANT_COLUMNS.map(Variant::from).to_vec();
+        header.push(Variant::from("model"));
+        let mut trinomial = grant_row([100.0, 90.0, 7.0, 2.0, 0.04, 0.35, 0.01, 0.05, 0.1, 2.0, 200.0]);
+        trinomial.push(Variant::from("T"));
+        let mut binomial = grant_row([100.0, 100.0, 10.0, 3.0, 0.05, 0.3, 0.02, 0.05, 0.08, 2.5, 200.0]);
+        binomial.push(Variant::missing());
+        let table = value_grants(Variant::from(vec![header, binomial, trinomial])).unwrap();
+
+        assert_eq!(table.len(), 3);
+        assert_eq!(String::from(&table[0][0]), "Fair value");
+        let expected = binomial_option_value(100.0, 100.0, 10.0, 3.0, 0.05, 0.3, 0.02, 0.05, 0.08, 2.5, 200).unwrap();
+        assert_eq!(f64::try_from(&table[1][0]).ok(), Some(expected[0]));
+        assert_eq!(f64::try_from(&table[1][1]).ok(), Some(expected[1]));
+        let expected = trinomial_option_value(100.0, 90.0, 7.0, 2.0, 0.04, 0.35, 0.01, 0.05, 0.1, 2.0, 200).unwrap();
+        assert_eq!(f64::try_from(&table[2][0]).ok(), Some(expected[0]));
+    }
+
+    #[test]
+    fn bad_and_blank_rows_keep_their_place() {
+        let blank = vec![Variant::missing(); 11];
+        let mut bad = grant_row([100.0, 100.0, 10.0, 3.0, 0.05, 0.3, 0.02, 0.05, 0.08, 2.5, 200.0]);
+        bad[5] = Variant::from("n/a");
+        let good = grant_row([100.0, 100.0, 10.0, 3.0, 0.05, 0.3, 0.02, 0.05, 0.08, 2.5, 200.0]);
+        let table = value_grants(Variant::from(vec![good.clone(), blank, bad, good])).unwrap();
+
+        assert_eq!(table.len(), 4);
+        assert!(f64::try_from(&table[0][0]).is_ok());
+        assert_eq!(String::from(&table[1][0]), "");
+        assert_eq!(String::from(&table[2][0]), "sigma is not a number");
+        assert_eq!(f64::try_from(&table[3][0]).ok(), f64::try_from(&table[0][0]).ok());
+    }
+
+    #[test]
+    fn too_few_columns_is_an_error() {
+        assert!(value_grants(Variant::from(vec![vec![Variant::from(100.0); 10]])).is_err());
+    }
+}
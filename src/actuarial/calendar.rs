@@ -0,0 +1,291 @@
+//! Business-day calendars: which days are weekends, plus a list of holidays, and how a
+//! date that falls on a day off is rolled to a business day. Dates are Excel serial
+//! numbers, as in the daycount module.
+
+use crate::actuarial::daycount::Date;
+use crate::actuarial::option_pricing::ParameterError;
+use crate::actuarial::ranges::range_numbers;
+use std::collections::BTreeSet;
+use xladd_core::variant::Variant;
+use xladd_derive::xl_func;
+
+const DAY_NAMES: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+/// The last date Excel can show, 31 December 9999
+const MAX_SERIAL: f64 = 2_958_465.0;
+
+/// The date if Excel can show it. Stepping a day at a time from beyond that range might
+/// never end, as adding 1 to a huge serial leaves it unchanged.
+fn check_date(date: f64, parameter: &'static str) -> Result<f64, ParameterError> {
+    if (0.0..=MAX_SERIAL).contains(&date) {
+        Ok(date)
+    } else {
+        Err(ParameterError::InvalidDate { parameter, value: date })
+    }
+}
+
+/// Which days of the week, Monday first, are not business days
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Weekend([bool; 7]);
+
+impl Weekend {
+    pub const SATURDAY_SUNDAY: Weekend = Weekend([false, false, false, false, false, true, true]);
+
+    /// Reads the weekend as typed in a cell: day names such as Sat/Sun or Fri,Sat, or
+    /// seven 0s and 1s from Monday as in WORKDAY.INTL, 1 for a day off
+    pub fn new(value: &str) -> Result<Self, ParameterError> {
+        let invalid = || ParameterError::InvalidWeekend { value: value.to_string() };
+        let value = value.trim().to_ascii_lowercase();
+        let mut days = [false; 7];
+        if value.len() == 7 && value.chars().all(|c| c == '0' || c == '1') {
+            for (day, flag) in days.iter_mut().zip(value.chars()) {
+                *day = flag == '1';
+            }
+        } else {
+            for name in value.split(['/', ',', ' ']).filter(|name| !name.is_empty()) {
+                let day = DAY_NAMES.iter().position(|day| name.starts_with(day)).ok_or_else(invalid)?;
+                days[day] = true;
+            }
+        }
+        if days.iter().all(|&off| off) {
+            return Err(invalid());
+        }
+        Ok(Weekend(days))
+    }
+}
+
+/// How a date that is not a business day is moved to one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Roll {
+    Unadjusted,
+    Following,
+    /// The following business day, unless that is in the next month, then the preceding
+    ModifiedFollowing,
+    Preceding,
+}
+
+impl Roll {
+    /// Reads the convention as typed in a cell: Following, Modified following, Preceding
+    /// or Unadjusted, or F, MF, P or U
+    pub fn new(value: &str) -> Result<Self, ParameterError> {
+        match value.trim().to_ascii_lowercase().replace([' ', '-'], "").as_str() {
+            "unadjusted" | "none" | "u" => Ok(Roll::Unadjusted),
+            "following" | "f" => Ok(Roll::Following),
+            "modifiedfollowing" | "mf" => Ok(Roll::ModifiedFollowing),
+            "preceding" | "p" => Ok(Roll::Preceding),
+            _ => Err(ParameterError::InvalidRoll { value: value.to_string() }),
+        }
+    }
+}
+
+/// A weekend and a set of holidays
+#[derive(Debug, Clone, PartialEq)]
+pub struct Calendar {
+    weekend: Weekend,
+    holidays: BTreeSet<i64>,
+}
+
+impl Calendar {
+    pub fn new(weekend: Weekend, holidays: &[f64]) -> Self {
+        Calendar { weekend, holidays: holidays.iter().map(|&date| date.floor() as i64).collect() }
+    }
+
+    /// A calendar from a weekend cell and a range of holiday dates
+    pub fn from_cells(weekend: &str, holidays: &Variant) -> Result<Self, ParameterError> {
+        let holidays = range_numbers(holidays).map_err(|reason| ParameterError::InvalidSchedule { name: "holidays", reason })?;
+        Ok(Calendar::new(Weekend::new(weekend)?, &holidays))
+    }
+
+    pub fn is_business_day(&self, date: f64) -> bool {
+        let day = date.floor() as i64;
+        // Serial 2 (a Monday in Excel's calendar, a day out from the real one) starts the week
+        let weekday = (day - 2).rem_euclid(7) as usize;
+        !self.weekend.0[weekday] && !self.holidays.contains(&day)
+    }
+
+    /// The date moved to a business day by a roll convention
+    pub fn adjust(&self, date: f64, roll: Roll) -> Result<f64, ParameterError> {
+        let date = check_date(date, "date")?.floor();
+        let step = |direction: f64| {
+            let mut day = date;
+            while !self.is_business_day(day) {
+                day += direction;
+            }
+            day
+        };
+        Ok(match roll {
+            Roll::Unadjusted => date,
+            Roll::Following => step(1.0),
+            Roll::Preceding => step(-1.0),
+            Roll::ModifiedFollowing => {
+                let following = step(1.0);
+                if Date::from_serial(following).month == Date::from_serial(date).month { following } else { step(-1.0) }
+            }
+        })
+    }
+
+    /// The business day `days` business days after a date, or before it if negative. As
+    /// with WORKDAY, a date that is not a business day counts from where it is.
+    pub fn add_business_days(&self, date: f64, days: i32) -> Result<f64, ParameterError> {
+        let start = check_date(date, "start_date")?;
+        let direction = if days < 0 { -1.0 } else { 1.0 };
+        let mut date = start.floor();
+        for _ in 0..days.unsigned_abs() {
+            date += direction;
+            while !self.is_business_day(date) {
+                date += direction;
+            }
+            if !(0.0..=MAX_SERIAL).contains(&date) {
+                return Err(ParameterError::InvalidSchedule {
+                    name: "days",
+                    reason: format!("{} business days from {} is not a date Excel can show", days, start),
+                });
+            }
+        }
+        Ok(date)
+    }
+
+    /// Business days from `start` to `end`, counting both, negative if `end` is earlier
+    pub fn business_days_between(&self, start: f64, end: f64) -> Result<i64, ParameterError> {
+        let start = check_date(start, "start_date")?.floor() as i64;
+        let end = check_date(end, "end_date")?.floor() as i64;
+        let count = |from: i64, to: i64| (from..=to).filter(|&day| self.is_business_day(day as f64)).count() as i64;
+        Ok(if end < start { -count(end, start) } else { count(start, end) })
+    }
+
+    /// Dates every `months` months from `start` until `end`, each rolled to a business
+    /// day. If `start` is the last day of its month, so is every date before rolling.
+    pub fn schedule(&self, start: f64, end: f64, months: u32, roll: Roll) -> Result<Vec<f64>, ParameterError> {
+        let first = Date::from_serial(check_date(start, "start_date")?);
+        let end = check_date(end, "end_date")?;
+        let month_end = first.day == Date::days_in_month(first.year, first.month);
+        let mut dates = Vec::new();
+        for period in 1.. {
+            let mut date = first.add_months(i64::from(months) * period);
+            if month_end {
+                date.day = Date::days_in_month(date.year, date.month);
+            }
+            let date = date.serial();
+            if date > end.floor() {
+                break;
+            }
+            dates.push(self.adjust(date, roll)?);
+        }
+        Ok(dates)
+    }
+}
+
+/// A date moved to a business day
+/// * date: Date to adjust
+/// * roll: Following, Modified following, Preceding or Unadjusted (F, MF, P or U also work)
+/// * weekend: Days off each week, such as Sat/Sun, or seven 0s and 1s from Monday as in WORKDAY.INTL
+/// * holidays: Range of holiday dates, which may be blank
+/// * ret: The adjusted date
+#[xl_func()]
+pub fn business_day(date: f64, roll: String, weekend: String, holidays: Variant) -> Result<f64, Box<dyn std::error::Error>> {
+    Ok(Calendar::from_cells(&weekend, &holidays)?.adjust(date, Roll::new(&roll)?)?)
+}
+
+/// The date a number of business days after another, like WORKDAY.INTL
+/// * start_date: Date to count from
+/// * days: Business days to add, negative to go back
+/// * weekend: Days off each week, such as Sat/Sun, or seven 0s and 1s from Monday as in WORKDAY.INTL
+/// * holidays: Range of holiday dates, which may be blank
+/// * ret: The date
+#[xl_func()]
+pub fn add_business_days(start_date: f64, days: i32, weekend: String, holidays: Variant) -> Result<f64, Box<dyn std::error::Error>> {
+    Ok(Calendar::from_cells(&weekend, &holidays)?.add_business_days(start_date, days)?)
+}
+
+/// Business days between two dates, counting both, like NETWORKDAYS.INTL
+/// * start_date: First date
+/// * end_date: Last date
+/// * weekend: Days off each week, such as Sat/Sun, or seven 0s and 1s from Monday as in WORKDAY.INTL
+/// * holidays: Range of holiday dates, which may be blank
+/// * ret: The number of business days
+#[xl_func()]
+pub fn business_days_between(start_date: f64, end_date: f64, weekend: String, holidays: Variant) -> Result<f64, Box<dyn std::error::Error>> {
+    Ok(Calendar::from_cells(&weekend, &holidays)?.business_days_between(start_date, end_date)? as f64)
+}
+
+/// Dates every few months from a start date, rolled to business days, such as the
+/// month-end reporting dates for amortization_schedule
+/// * start_date: Date the schedule counts from, which is not itself included
+/// * end_date: Last date the schedule may reach
+/// * months: Months between dates, such as 1 for monthly or 3 for quarterly
+/// * roll: Following, Modified following, Preceding or Unadjusted (F, MF, P or U also work)
+/// * weekend: Days off each week, such as Sat/Sun, or seven 0s and 1s from Monday as in WORKDAY.INTL
+/// * holidays: Range of holiday dates, which may be blank
+/// * ret: A column of dates
+#[xl_func()]
+pub fn date_schedule(
+    start_date: f64,
+    end_date: f64,
+    months: i32,
+    roll: String,
+    weekend: String,
+    holidays: Variant,
+) -> Result<Vec<Vec<f64>>, Box<dyn std::error::Error>> {
+    if months < 1 {
+        return Err(ParameterError::InvalidPositiveInt { parameter: "months", value: months.max(0) as usize }.into());
+    }
+    let calendar = Calendar::from_cells(&weekend, &holidays)?;
+    let dates = calendar.schedule(start_date, end_date, months as u32, Roll::new(&roll)?)?;
+    if dates.is_empty() {
+        return Err("end_date is before the first date of the schedule".into());
+    }
+    Ok(dates.into_iter().map(|date| vec![date]).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actuarial::daycount::serial;
+
+    #[test]
+    fn rolls_off_weekends_and_holidays() {
+        // Saturday 30 March 2024, with Good Friday and Easter Monday as holidays
+        let calendar = Calendar::new(Weekend::SATURDAY_SUNDAY, &[serial(2024, 3, 29), serial(2024, 4, 1)]);
+        let saturday = serial(2024, 3, 30);
+        assert_eq!(calendar.adjust(saturday, Roll::Following).unwrap(), serial(2024, 4, 2));
+        assert_eq!(calendar.adjust(saturday, Roll::ModifiedFollowing).unwrap(), serial(2024, 3, 28));
+        assert_eq!(calendar.adjust(saturday, Roll::Preceding).unwrap(), serial(2024, 3, 28));
+        assert_eq!(calendar.adjust(saturday, Roll::Unadjusted).unwrap(), saturday);
+        assert_eq!(calendar.add_business_days(serial(2024, 3, 28), 1).unwrap(), serial(2024, 4, 2));
+        assert_eq!(calendar.add_business_days(serial(2024, 4, 2), -1).unwrap(), serial(2024, 3, 28));
+        assert_eq!(calendar.business_days_between(serial(2024, 3, 25), serial(2024, 4, 5)).unwrap(), 8);
+    }
+
+    #[test]
+    fn dates_excel_cannot_show_are_errors() {
+        let calendar = Calendar::new(Weekend::SATURDAY_SUNDAY, &[]);
+        for date in [1e300, f64::NAN, f64::INFINITY, -1.0, MAX_SERIAL + 1.0] {
+            assert!(calendar.adjust(date, Roll::Following).is_err(), "{}", date);
+        }
+        assert!(calendar.business_days_between(0.0, 1e15).is_err());
+        assert!(calendar.add_business_days(MAX_SERIAL - 5.0, 10).is_err());
+        assert!(calendar.schedule(serial(2024, 1, 31), f64::INFINITY, 1, Roll::Following).is_err());
+        assert_eq!(calendar.adjust(MAX_SERIAL, Roll::Preceding).unwrap(), MAX_SERIAL);
+    }
+
+    #[test]
+    fn reads_weekends_and_rolls_from_cells() {
+        assert_eq!(Weekend::new("Sat/Sun").unwrap(), Weekend::SATURDAY_SUNDAY);
+        assert_eq!(Weekend::new("0000011").unwrap(), Weekend::SATURDAY_SUNDAY);
+        let gulf = Calendar::new(Weekend::new("Fri, Sat").unwrap(), &[]);
+        assert!(!gulf.is_business_day(serial(2024, 3, 29)) && gulf.is_business_day(serial(2024, 3, 31)));
+        assert!(Weekend::new("1111111").is_err() && Weekend::new("Funday").is_err());
+        assert_eq!(Roll::new("Modified Following").unwrap(), Roll::ModifiedFollowing);
+        assert!(Roll::new("nearest").is_err());
+    }
+
+    #[test]
+    fn month_end_schedules_stay_at_month_end() {
+        let calendar = Calendar::new(Weekend::SATURDAY_SUNDAY, &[]);
+        let dates = calendar.schedule(serial(2024, 1, 31), serial(2024, 6, 30), 1, Roll::ModifiedFollowing).unwrap();
+        let expected = [serial(2024, 2, 29), serial(2024, 3, 29), serial(2024, 4, 30), serial(2024, 5, 31), serial(2024, 6, 28)];
+        assert_eq!(dates, expected);
+        let quarterly = calendar.schedule(serial(2024, 1, 15), serial(2024, 12, 31), 3, Roll::Unadjusted).unwrap();
+        assert_eq!(quarterly, [serial(2024, 4, 15), serial(2024, 7, 15), serial(2024, 10, 15)]);
+    }
+}
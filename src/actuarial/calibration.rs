@@ -0,0 +1,140 @@
+//! Estimating the lattice's behavioural inputs from a company's own exercise history. In
+//! the lattice a holder exercises once the share price reaches the multiple of the strike,
+//! unless they leave first and must exercise wherever the price is. So past exercises fall
+//! into two groups: voluntary ones around the multiple, and exits below it.
+
+use crate::actuarial::option_pricing::{ParameterError, PositiveFloat};
+use crate::actuarial::ranges::range_pairs;
+use xladd_core::variant::Variant;
+use xladd_derive::xl_func;
+
+/// Least gap between the highest exit and the lowest voluntary exercise, as a fraction of
+/// the exit's ratio. Closer ratios are taken as one spread-out group, as splitting any
+/// spread of values lowers their squared distance from the groups' means.
+const MIN_RELATIVE_GAP: f64 = 0.1;
+
+/// The exercise multiple and post-vesting exit rate that best fit a set of exercises
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExerciseBehaviour {
+    pub multiple: f64,
+    pub exit_post_vesting: f64,
+    pub voluntary: usize,
+    pub exits: usize,
+    /// Years from vesting to exercise, added up over every exercise
+    pub years_at_risk: f64,
+}
+
+impl ExerciseBehaviour {
+    /// Fits (share price / strike, years from grant) at each exercise. The exercises are
+    /// split in two where the ratios, on a log scale, are closest to their group's mean;
+    /// the multiple is the mean ratio of the upper group and the lower group are exits.
+    /// If no two neighbouring ratios are `MIN_RELATIVE_GAP` apart they are all voluntary.
+    /// Only exercised options count towards the time at risk, so holders yet to exercise
+    /// are not allowed for.
+    pub fn fit(exercises: &[(f64, f64)], vesting_period: f64) -> Result<Self, ParameterError> {
+        let invalid = |reason: String| ParameterError::InvalidSchedule { name: "exercises", reason };
+        let vesting_period = PositiveFloat::new(vesting_period, "vesting_period")?.0;
+        if exercises.len() < 2 {
+            return Err(invalid("it needs at least two exercises".to_string()));
+        }
+        for &(ratio, time) in exercises {
+            if !(ratio >= 1.0 && ratio.is_finite()) {
+                return Err(invalid(format!("share price / strike must be at least 1 at exercise, got {}", ratio)));
+            }
+            if !(time >= vesting_period && time.is_finite()) {
+                return Err(invalid(format!("exercises can't be before vesting at {}, got {}", vesting_period, time)));
+            }
+        }
+
+        let mut logs: Vec<f64> = exercises.iter().map(|&(ratio, _)| ratio.ln()).collect();
+        logs.sort_by(f64::total_cmp);
+        let exits = best_split(&logs);
+        let voluntary = &logs[exits..];
+        let multiple = voluntary.iter().map(|log| log.exp()).sum::<f64>() / voluntary.len() as f64;
+
+        let years_at_risk: f64 = exercises.iter().map(|&(_, time)| time - vesting_period).sum();
+        let exit_post_vesting = if exits == 0 {
+            0.0
+        } else if years_at_risk > 0.0 {
+            // Exits at a constant force, expressed as an annual probability like the lattice's
+            1.0 - (-(exits as f64) / years_at_risk).exp()
+        } else {
+            return Err(invalid("every exercise is at vesting, leaving no time to estimate exits from".to_string()));
+        };
+        Ok(ExerciseBehaviour { multiple, exit_post_vesting, voluntary: voluntary.len(), exits, years_at_risk })
+    }
+}
+
+/// How many of the sorted logs go in the lower group, splitting them in two with the least
+/// squared distance of each value from its group's mean. Only splits at a gap of at least
+/// `MIN_RELATIVE_GAP` are tried, so without one it is 0.
+fn best_split(sorted: &[f64]) -> usize {
+    let n = sorted.len();
+    let (total, total_squares) = sorted.iter().fold((0.0, 0.0), |(sum, squares), x| (sum + x, squares + x * x));
+    let mut best = (0, total_squares - total * total / n as f64);
+    let (mut sum, mut squares) = (0.0, 0.0);
+    for split in 1..n {
+        let x = sorted[split - 1];
+        (sum, squares) = (sum + x, squares + x * x);
+        if sorted[split] - x < MIN_RELATIVE_GAP.ln_1p() {
+            continue;
+        }
+        let (lower, upper) = (split as f64, (n - split) as f64);
+        let spread = squares - sum * sum / lower + (total_squares - squares) - (total - sum).powi(2) / upper;
+        if spread < best.1 {
+            best = (split, spread);
+        }
+    }
+    best.0
+}
+
+/// Exercise multiple and post-vesting exit rate for the binomial tree, from past exercises.
+/// Exercises well below the rest, at least 10% apart, are taken to be holders who left; the
+/// others show the multiple at which holders choose to exercise.
+/// * exercises: Two columns: share price divided by strike at exercise, and years from grant to exercise
+/// * vesting_period: Vesting period of the options exercised, in years
+/// * ret: The multiple, exit rate after vesting, and the counts and time at risk behind them
+#[xl_func()]
+pub fn calibrate_exercise_behaviour(exercises: Variant, vesting_period: f64) -> Result<Vec<(String, f64)>, Box<dyn std::error::Error>> {
+    let exercises = range_pairs(&exercises, "share price / strike and years to exercise")
+        .map_err(|reason| ParameterError::InvalidSchedule { name: "exercises", reason })?;
+    let fit = ExerciseBehaviour::fit(&exercises, vesting_period)?;
+    Ok(vec![
+        ("Exercise multiple".to_string(), fit.multiple),
+        ("Exit rate post-vesting".to_string(), fit.exit_post_vesting),
+        ("Voluntary exercises".to_string(), fit.voluntary as f64),
+        ("Exits".to_string(), fit.exits as f64),
+        ("Years at risk".to_string(), fit.years_at_risk),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn separates_exits_from_voluntary_exercises() {
+        let exercises = [(2.4, 4.0), (2.6, 5.0), (2.5, 6.0), (1.1, 3.5), (1.2, 4.5)];
+        let fit = ExerciseBehaviour::fit(&exercises, 3.0).unwrap();
+        assert_eq!((fit.voluntary, fit.exits), (3, 2));
+        assert!((fit.multiple - 2.5).abs() < 1e-12);
+        assert!((fit.years_at_risk - 8.0).abs() < 1e-12);
+        assert!((fit.exit_post_vesting - (1.0 - (-0.25f64).exp())).abs() < 1e-12);
+    }
+
+    #[test]
+    fn identical_exercises_are_all_voluntary() {
+        let fit = ExerciseBehaviour::fit(&[(2.0, 4.0), (2.0, 5.0)], 3.0).unwrap();
+        assert_eq!((fit.multiple, fit.exit_post_vesting, fit.exits), (2.0, 0.0, 0));
+        assert!(ExerciseBehaviour::fit(&[(2.0, 2.0), (2.0, 5.0)], 3.0).is_err());
+        assert!(ExerciseBehaviour::fit(&[(0.8, 4.0), (2.0, 5.0)], 3.0).is_err());
+    }
+
+    #[test]
+    fn evenly_spread_exercises_are_all_voluntary() {
+        let exercises: Vec<(f64, f64)> = (0..=10).map(|i| (2.0 + 0.1 * i as f64, 4.0 + 0.2 * i as f64)).collect();
+        let fit = ExerciseBehaviour::fit(&exercises, 3.0).unwrap();
+        assert_eq!((fit.voluntary, fit.exits, fit.exit_post_vesting), (11, 0, 0.0));
+        assert!((fit.multiple - 2.5).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,282 @@
+//! Convertible notes and preference shares on a binomial lattice. Following Tsiveriotis
+//! and Fernandes, the value at each node is split into the part that ends up paid in
+//! shares, discounted at the risk-free rate, and the part paid in cash, which carries the
+//! issuer's credit risk and so is discounted at the risk-free rate plus a credit spread.
+//! For a preference share the face value is the liquidation preference and the coupon is
+//! the preferred dividend.
+
+use crate::actuarial::option_pricing::{ParameterError, PositiveFloat, PositiveInt, Volatility};
+use crate::actuarial::ranges::range_pairs;
+use xladd_core::variant::Variant;
+use xladd_derive::xl_func;
+
+/// The terms of a convertible
+#[derive(Debug, Clone, PartialEq)]
+pub struct Convertible {
+    pub face_value: f64,
+    pub coupon_rate: f64,
+    pub coupon_frequency: usize,
+    pub time_to_maturity: f64,
+    /// Shares received for each note converted
+    pub conversion_ratio: f64,
+    /// (time, price): the issuer may call at the price from the time until the next row
+    pub calls: Vec<(f64, f64)>,
+    /// (time, price): the holder may put at the price on the date
+    pub puts: Vec<(f64, f64)>,
+}
+
+/// A convertible's value and the parts of it paid in shares and in cash
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConvertibleValue {
+    pub value: f64,
+    pub equity: f64,
+    pub debt: f64,
+    /// The value as a straight note, without the right to convert
+    pub bond_floor: f64,
+}
+
+impl Convertible {
+    /// The coupon paid at each step of a tree with this many steps, each coupon at the
+    /// step nearest its date
+    fn coupons(&self, steps: usize) -> Vec<f64> {
+        let mut coupons = vec![0.0; steps + 1];
+        if self.coupon_rate == 0.0 || self.coupon_frequency == 0 {
+            return coupons;
+        }
+        let dt = self.time_to_maturity / steps as f64;
+        let coupon = self.face_value * self.coupon_rate / self.coupon_frequency as f64;
+        for time in self.coupon_times() {
+            coupons[(time / dt).round() as usize] += coupon;
+        }
+        coupons
+    }
+
+    /// The times of the coupons, counted back from maturity a whole number of periods
+    fn coupon_times(&self) -> impl Iterator<Item = f64> + '_ {
+        let frequency = self.coupon_frequency as f64;
+        let count = (self.time_to_maturity * frequency).round() as usize;
+        (0..count).map(move |k| self.time_to_maturity - k as f64 / frequency)
+    }
+
+    /// The call price at a time, if the note is callable then
+    fn call_price(&self, time: f64) -> Option<f64> {
+        let after = self.calls.partition_point(|&(start, _)| start <= time + 1e-12);
+        (after > 0).then(|| self.calls[after - 1].1)
+    }
+
+    /// The value of the coupons and face value discounted at the risky rate
+    fn bond_floor(&self, risky_rate: f64) -> f64 {
+        let mut value = self.face_value * (-risky_rate * self.time_to_maturity).exp();
+        if self.coupon_rate != 0.0 && self.coupon_frequency > 0 {
+            let coupon = self.face_value * self.coupon_rate / self.coupon_frequency as f64;
+            value += self.coupon_times().map(|time| coupon * (-risky_rate * time).exp()).sum::<f64>();
+        }
+        value
+    }
+
+    /// Values the convertible on a Cox-Ross-Rubinstein tree. At each node the holder
+    /// converts if that is worth more than holding, or than the call price if the issuer
+    /// calls, and puts if the put price is worth more still. Coupons are paid whatever
+    /// is decided on their date.
+    pub fn value(
+        &self,
+        share_price: f64,
+        risk_free: f64,
+        credit_spread: f64,
+        sigma: f64,
+        div_rate: f64,
+        steps: usize,
+    ) -> Result<ConvertibleValue, ParameterError> {
+        let share_price = PositiveFloat::new(share_price, "share_price")?.0;
+        let sigma = Volatility::new(sigma)?.0;
+        let steps = PositiveInt::new(steps, "steps")?.0;
+        let bond_floor = self.bond_floor(risk_free + credit_spread);
+
+        let dt = self.time_to_maturity / steps as f64;
+        let u = (sigma * dt.sqrt()).exp();
+        let d = 1.0 / u;
+        let p = (((risk_free - div_rate) * dt).exp() - d) / (u - d);
+        if !(0.0..=1.0).contains(&p) {
+            return Err(ParameterError::InvalidProbability { value: p });
+        }
+        let (equity_discount, debt_discount) = ((-risk_free * dt).exp(), (-(risk_free + credit_spread) * dt).exp());
+        let coupons = self.coupons(steps);
+        let price = |i: usize, j: usize| share_price * u.powi(j as i32) * d.powi((i - j) as i32);
+        let puts: Vec<Option<f64>> = {
+            let mut puts = vec![None; steps + 1];
+            for &(time, put_price) in &self.puts {
+                puts[((time / dt).round() as usize).min(steps)] = Some(put_price);
+            }
+            puts
+        };
+
+        // Equity and debt parts at maturity, node j having had j up moves
+        let (mut equity, mut debt): (Vec<f64>, Vec<f64>) = (0..=steps)
+            .map(|j| {
+                let conversion = self.conversion_ratio * price(steps, j);
+                if conversion > self.face_value { (conversion, 0.0) } else { (0.0, self.face_value) }
+            })
+            .unzip();
+        for j in 0..=steps {
+            debt[j] += coupons[steps];
+            if let Some(put_price) = puts[steps] && put_price > equity[j] + debt[j] {
+                (equity[j], debt[j]) = (0.0, put_price + coupons[steps]);
+            }
+        }
+
+        for i in (0..steps).rev() {
+            let call_price = self.call_price(i as f64 * dt);
+            for j in 0..=i {
+                let held_equity = equity_discount * (p * equity[j + 1] + (1.0 - p) * equity[j]);
+                let held_debt = debt_discount * (p * debt[j + 1] + (1.0 - p) * debt[j]);
+                let conversion = self.conversion_ratio * price(i, j);
+                let held = held_equity + held_debt;
+                let called = call_price.map_or(held, |call_price| held.min(call_price));
+                let put = puts[i].unwrap_or(0.0);
+                (equity[j], debt[j]) = if conversion >= called && conversion >= put {
+                    (conversion, 0.0)
+                } else if put > called {
+                    (0.0, put)
+                } else if called < held {
+                    (0.0, called)
+                } else {
+                    (held_equity, held_debt)
+                };
+                debt[j] += coupons[i];
+            }
+        }
+
+        Ok(ConvertibleValue { value: equity[0] + debt[0], equity: equity[0], debt: debt[0], bond_floor })
+    }
+}
+
+/// A call or put schedule from its range, in order of time
+fn schedule(range: &Variant, name: &'static str) -> Result<Vec<(f64, f64)>, ParameterError> {
+    let invalid = |reason: String| ParameterError::InvalidSchedule { name, reason };
+    let points = range_pairs(range, "time and price").map_err(invalid)?;
+    if points.windows(2).any(|pair| pair[1].0 <= pair[0].0) || points.iter().any(|&(time, _)| time < 0.0) {
+        return Err(invalid("times must be non-negative and increasing".to_string()));
+    }
+    Ok(points)
+}
+
+/// Value of a convertible note or preference share on a binomial tree, with the part paid
+/// in cash discounted for the issuer's credit risk
+/// * share_price: Current share price
+/// * face_value: Amount repaid at maturity, or the liquidation preference
+/// * coupon_rate: Annual coupon or preferred dividend as a fraction of the face value
+/// * coupon_frequency: Coupons a year (0 for none)
+/// * time_to_maturity: Years to maturity
+/// * conversion_ratio: Shares received for each note converted
+/// * risk_free: Risk-free interest rate (continuously compounded)
+/// * credit_spread: Issuer's credit spread over the risk-free rate
+/// * sigma: Expected share volatility
+/// * div_rate: Dividend yield (continuously compounded)
+/// * call_schedule: Two columns, time and the price the issuer can call at from then, or blank
+/// * put_schedule: Two columns, date in years and the price the holder can put at then, or blank
+/// * steps: Number of time steps in the tree
+/// * ret: Value, equity and debt components, and the bond floor
+#[xl_func()]
+pub fn convertible_value(
+    share_price: f64,
+    face_value: f64,
+    coupon_rate: f64,
+    coupon_frequency: i32,
+    time_to_maturity: f64,
+    conversion_ratio: f64,
+    risk_free: f64,
+    credit_spread: f64,
+    sigma: f64,
+    div_rate: f64,
+    call_schedule: Variant,
+    put_schedule: Variant,
+    steps: i32,
+) -> Result<Vec<(String, f64)>, Box<dyn std::error::Error>> {
+    let time_to_maturity = time_to_maturity.max(0.0);
+    if time_to_maturity == 0.0 {
+        return Err(ParameterError::InvalidPositiveValue { parameter: "time_to_maturity", value: time_to_maturity }.into());
+    }
+    let convertible = Convertible {
+        face_value: PositiveFloat::new(face_value, "face_value")?.0,
+        coupon_rate,
+        coupon_frequency: coupon_frequency.max(0) as usize,
+        time_to_maturity,
+        conversion_ratio: PositiveFloat::new(conversion_ratio, "conversion_ratio")?.0,
+        calls: schedule(&call_schedule, "call schedule")?,
+        puts: schedule(&put_schedule, "put schedule")?,
+    };
+    let value = convertible.value(share_price, risk_free, credit_spread, sigma, div_rate, steps.max(0) as usize)?;
+    Ok(vec![
+        ("Value".to_string(), value.value),
+        ("Equity component".to_string(), value.equity),
+        ("Debt component".to_string(), value.debt),
+        ("Bond floor".to_string(), value.bond_floor),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actuarial::option_pricing::black_scholes_call_option_value;
+
+    fn note(conversion_ratio: f64, calls: Vec<(f64, f64)>, puts: Vec<(f64, f64)>) -> Convertible {
+        Convertible {
+            face_value: 100.0,
+            coupon_rate: 0.04,
+            coupon_frequency: 2,
+            time_to_maturity: 5.0,
+            conversion_ratio,
+            calls,
+            puts,
+        }
+    }
+
+    #[test]
+    fn without_conversion_it_is_the_bond_floor() {
+        let value = note(1e-9, vec![], vec![]).value(50.0, 0.03, 0.02, 0.3, 0.0, 100).unwrap();
+        assert!((value.value - value.bond_floor).abs() < 1e-9, "{:?}", value);
+        assert_eq!(value.equity, 0.0);
+    }
+
+    #[test]
+    fn monthly_coupons_are_paid_once_a_month() {
+        let mut monthly = note(2.0, vec![], vec![]);
+        monthly.coupon_frequency = 12;
+        let coupons = monthly.coupons(100);
+        assert_eq!(coupons.iter().filter(|&&coupon| coupon > 0.0).count(), 60);
+        assert!((coupons.iter().sum::<f64>() - 20.0).abs() < 1e-9);
+        monthly.coupon_rate = 0.0;
+        let zero = monthly.bond_floor(0.05);
+        monthly.coupon_rate = 0.12;
+        let expected: f64 = (1..=60).map(|month| (-0.05 * month as f64 / 12.0).exp()).sum();
+        assert!((monthly.bond_floor(0.05) - zero - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn zero_coupon_without_credit_risk_is_a_bond_and_a_call() {
+        // With no dividends or coupons it never pays to convert early
+        let mut zero = note(2.0, vec![], vec![]);
+        zero.coupon_rate = 0.0;
+        let value = zero.value(50.0, 0.03, 0.0, 0.3, 0.0, 1000).unwrap();
+        let expected = 100.0 * (-0.03f64 * 5.0).exp() + 2.0 * black_scholes_call_option_value(50.0, 50.0, 5.0, 0.03, 0.0, 0.3);
+        assert!((value.value - expected).abs() < 0.05, "{} {}", value.value, expected);
+    }
+
+    #[test]
+    fn calls_cap_puts_floor_and_credit_risk_lowers_the_value() {
+        let plain = note(2.0, vec![], vec![]).value(50.0, 0.03, 0.02, 0.3, 0.01, 200).unwrap().value;
+        let callable = note(2.0, vec![(1.0, 105.0)], vec![]).value(50.0, 0.03, 0.02, 0.3, 0.01, 200).unwrap().value;
+        let puttable = note(2.0, vec![], vec![(3.0, 110.0)]).value(50.0, 0.03, 0.02, 0.3, 0.01, 200).unwrap().value;
+        let riskier = note(2.0, vec![], vec![]).value(50.0, 0.03, 0.05, 0.3, 0.01, 200).unwrap().value;
+        assert!(callable < plain && puttable > plain && riskier < plain, "{} {} {} {}", plain, callable, puttable, riskier);
+    }
+
+    #[test]
+    fn schedules_read_from_ranges() {
+        let calls = Variant::from(vec![vec![1.0, 105.0], vec![3.0, 102.0]]);
+        let table = convertible_value(50.0, 100.0, 0.04, 2, 5.0, 2.0, 0.03, 0.02, 0.3, 0.01, calls, Variant::from(vec![vec![3.0, 110.0]]), 200).unwrap();
+        assert!(table[0].1 > table[3].1);
+        let unordered = Variant::from(vec![vec![3.0, 105.0], vec![1.0, 102.0]]);
+        assert!(convertible_value(50.0, 100.0, 0.04, 2, 5.0, 2.0, 0.03, 0.02, 0.3, 0.01, unordered, Variant::from(vec![vec![3.0, 110.0]]), 200).is_err());
+    }
+}
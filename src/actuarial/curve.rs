@@ -0,0 +1,457 @@
+//! Discount curves bootstrapped from deposit and par swap rates. Each quote is a par
+//! instrument paying its rate on a fixed schedule and the notional at maturity, so
+//! 1 = rate * sum(accrual * DF) + DF(maturity). Taking the quotes in order of maturity,
+//! the discount factor at each maturity is the one that reprices its quote given the
+//! curve already built. Curves are kept in the handle registry and referred to by handle.
+
+use crate::actuarial::calendar::{Calendar, Roll};
+use crate::actuarial::daycount::{Date, DayCount};
+use crate::actuarial::option_pricing::ParameterError;
+use crate::actuarial::ranges::range_pairs;
+use crate::actuarial::rate_curve::RateCurve;
+use xladd_core::handles;
+use xladd_core::variant::Variant;
+use xladd_derive::xl_func;
+
+/// Times on the curve are years from its valuation date on this basis
+const CURVE_DAY_COUNT: DayCount = DayCount::Act365Fixed;
+
+/// How discount factors between the curve's pillars are found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+    /// Continuously compounded zero rates linear in time
+    LinearZero,
+    /// Log discount factors linear in time, so forward rates are flat between pillars
+    LogLinearDiscount,
+}
+
+impl Interpolation {
+    /// Reads the interpolation as typed in a cell: Linear zero or Log-linear discount, or
+    /// Z or D
+    pub fn new(value: &str) -> Result<Self, ParameterError> {
+        match value.trim().to_ascii_lowercase().replace([' ', '-'], "").as_str() {
+            "linearzero" | "zero" | "z" => Ok(Interpolation::LinearZero),
+            "loglineardiscount" | "loglinear" | "discount" | "d" => Ok(Interpolation::LogLinearDiscount),
+            _ => Err(ParameterError::InvalidInterpolation { value: value.to_string() }),
+        }
+    }
+}
+
+/// A period from a start date, such as 1W, 3M or 10Y
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tenor {
+    Days(i64),
+    Months(i64),
+}
+
+impl Tenor {
+    /// Reads a tenor such as ON, 2W, 6M or 5Y from a cell, or a number of years
+    pub fn from_cell(cell: &Variant) -> Result<Self, String> {
+        if let Ok(years) = f64::try_from(cell) {
+            let months = years * 12.0;
+            if months < 0.5 || (months - months.round()).abs() > 1e-6 {
+                return Err(format!("{} years is not a whole number of months", years));
+            }
+            return Ok(Tenor::Months(months.round() as i64));
+        }
+        let text = String::from(cell).trim().to_ascii_uppercase();
+        if text == "ON" || text == "O/N" {
+            return Ok(Tenor::Days(1));
+        }
+        let invalid = || format!("{} is not a tenor such as 1W, 3M or 5Y", text);
+        let last = text.char_indices().last().map_or(0, |(index, _)| index);
+        let (count, unit) = text.split_at(last);
+        let count: i64 = count.trim().parse().map_err(|_| invalid())?;
+        if count < 1 {
+            return Err(invalid());
+        }
+        match unit {
+            "D" => Ok(Tenor::Days(count)),
+            "W" => Ok(Tenor::Days(7 * count)),
+            "M" => Ok(Tenor::Months(count)),
+            "Y" => Ok(Tenor::Months(12 * count)),
+            _ => Err(invalid()),
+        }
+    }
+
+    /// The date this long after a start, before rolling to a business day
+    pub fn after(self, start: f64) -> f64 {
+        match self {
+            Tenor::Days(days) => start.floor() + days as f64,
+            Tenor::Months(months) => Date::from_serial(start).add_months(months).serial(),
+        }
+    }
+}
+
+/// A par instrument to fit: its fixed payments as (date, accrual in years), the last at
+/// maturity with the notional, and the rate paid
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParQuote {
+    pub payments: Vec<(f64, f64)>,
+    pub rate: f64,
+}
+
+impl ParQuote {
+    /// A deposit paying simple interest on its day count at maturity
+    pub fn deposit(valuation_date: f64, tenor: Tenor, rate: f64, day_count: DayCount, calendar: &Calendar) -> Result<Self, ParameterError> {
+        let maturity = calendar.adjust(tenor.after(valuation_date), Roll::ModifiedFollowing)?;
+        Ok(ParQuote { payments: vec![(maturity, day_count.year_fraction(valuation_date, maturity))], rate })
+    }
+
+    /// A swap's fixed leg, paying every `months` months until the tenor
+    pub fn swap(
+        valuation_date: f64,
+        tenor: Tenor,
+        rate: f64,
+        months: u32,
+        day_count: DayCount,
+        calendar: &Calendar,
+    ) -> Result<Self, String> {
+        let Tenor::Months(tenor_months) = tenor else {
+            return Err("swap tenors must be in months or years".to_string());
+        };
+        if tenor_months % i64::from(months) != 0 {
+            return Err(format!("a {} month swap is not a whole number of {} month periods", tenor_months, months));
+        }
+        let dates = calendar
+            .schedule(valuation_date, tenor.after(valuation_date), months, Roll::ModifiedFollowing)
+            .map_err(|error| error.to_string())?;
+        let mut start = valuation_date;
+        let payments = dates
+            .into_iter()
+            .map(|date| {
+                let accrual = day_count.year_fraction(start, date);
+                start = date;
+                (date, accrual)
+            })
+            .collect();
+        Ok(ParQuote { payments, rate })
+    }
+
+    fn maturity(&self) -> f64 {
+        self.payments.last().map_or(0.0, |&(date, _)| date)
+    }
+}
+
+/// Discount factors from a valuation date
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiscountCurve {
+    valuation_date: f64,
+    /// (years from the valuation date, continuously compounded zero rate), in order of time
+    pillars: Vec<(f64, f64)>,
+    interpolation: Interpolation,
+}
+
+impl DiscountCurve {
+    /// A curve through (date, discount factor) points after the valuation date
+    pub fn new(valuation_date: f64, points: &[(f64, f64)], interpolation: Interpolation) -> Result<Self, ParameterError> {
+        let invalid = |reason: String| ParameterError::InvalidRateCurve { reason };
+        if points.is_empty() {
+            return Err(invalid("it has no points".to_string()));
+        }
+        let mut pillars = Vec::with_capacity(points.len());
+        let mut last_time = 0.0;
+        for &(date, discount) in points {
+            let time = CURVE_DAY_COUNT.year_fraction(valuation_date, date);
+            if time <= last_time {
+                return Err(invalid(format!("dates must be increasing and after the valuation date, got {}", date)));
+            }
+            if !(discount > 0.0 && discount.is_finite()) {
+                return Err(invalid(format!("discount factors must be positive, got {}", discount)));
+            }
+            pillars.push((time, -discount.ln() / time));
+            last_time = time;
+        }
+        Ok(DiscountCurve { valuation_date: valuation_date.floor(), pillars, interpolation })
+    }
+
+    /// Fits discount factors to par quotes, taken in order of maturity
+    pub fn bootstrap(valuation_date: f64, quotes: &[ParQuote], interpolation: Interpolation) -> Result<Self, ParameterError> {
+        let invalid = |reason: String| ParameterError::InvalidRateCurve { reason };
+        let mut quotes: Vec<&ParQuote> = quotes.iter().collect();
+        quotes.sort_by(|a, b| a.maturity().total_cmp(&b.maturity()));
+        let mut points: Vec<(f64, f64)> = Vec::with_capacity(quotes.len());
+        for quote in quotes {
+            let maturity = quote.maturity();
+            if points.last().is_some_and(|&(date, _)| date >= maturity) {
+                return Err(invalid(format!("two quotes mature on {}", maturity)));
+            }
+            // What the quote is worth less par, given the discount factor at its maturity.
+            // It rises with that discount factor, so bisection finds it.
+            let mispricing = |discount: f64| -> Result<f64, ParameterError> {
+                let mut trial = points.clone();
+                trial.push((maturity, discount));
+                let curve = DiscountCurve::new(valuation_date, &trial, interpolation)?;
+                let coupons: f64 =
+                    quote.payments.iter().map(|&(date, accrual)| quote.rate * accrual * curve.discount_at(date)).sum();
+                Ok(coupons + discount - 1.0)
+            };
+            let (mut low, mut high) = (1e-6, 2.0);
+            if mispricing(low)? > 0.0 || mispricing(high)? < 0.0 {
+                return Err(invalid(format!("no discount factor reprices the {} quote maturing on {}", quote.rate, maturity)));
+            }
+            while high - low > 1e-15 {
+                let middle = 0.5 * (low + high);
+                if mispricing(middle)? > 0.0 {
+                    high = middle;
+                } else {
+                    low = middle;
+                }
+            }
+            points.push((maturity, 0.5 * (low + high)));
+        }
+        DiscountCurve::new(valuation_date, &points, interpolation)
+    }
+
+    pub fn valuation_date(&self) -> f64 {
+        self.valuation_date
+    }
+
+    /// Years from the valuation date to a date
+    pub fn time(&self, date: f64) -> f64 {
+        CURVE_DAY_COUNT.year_fraction(self.valuation_date, date)
+    }
+
+    /// The continuously compounded zero rate to a time, held flat before the first pillar
+    /// and after the last
+    pub fn zero_rate(&self, time: f64) -> f64 {
+        let pillars = &self.pillars;
+        let after = pillars.partition_point(|&(pillar, _)| pillar < time);
+        if after == 0 {
+            return pillars[0].1;
+        }
+        if after == pillars.len() {
+            return pillars[after - 1].1;
+        }
+        let ((t1, z1), (t2, z2)) = (pillars[after - 1], pillars[after]);
+        let weight = (time - t1) / (t2 - t1);
+        match self.interpolation {
+            Interpolation::LinearZero => z1 + (z2 - z1) * weight,
+            Interpolation::LogLinearDiscount => (z1 * t1 + (z2 * t2 - z1 * t1) * weight) / time,
+        }
+    }
+
+    pub fn discount(&self, time: f64) -> f64 {
+        (-self.zero_rate(time) * time).exp()
+    }
+
+    pub fn discount_at(&self, date: f64) -> f64 {
+        self.discount(self.time(date))
+    }
+
+    /// Years from the valuation date to a date, which must not be before it
+    fn time_to(&self, date: f64) -> Result<f64, String> {
+        let time = self.time(date);
+        if time < 0.0 {
+            return Err(format!("{} is before the curve's valuation date, {}", date, self.valuation_date));
+        }
+        Ok(time)
+    }
+
+    /// The continuously compounded forward rate between two times
+    pub fn forward_rate(&self, start: f64, end: f64) -> f64 {
+        if end <= start {
+            return self.zero_rate(start);
+        }
+        (self.zero_rate(end) * end - self.zero_rate(start) * start) / (end - start)
+    }
+
+    /// The zero rates at the pillars, for the pricing functions that take a rate curve.
+    /// Between pillars that curve is linear in zero rates whatever this one's interpolation.
+    pub fn rate_curve(&self) -> RateCurve {
+        RateCurve::new(&self.pillars).expect("pillars are increasing with finite rates")
+    }
+}
+
+/// The rows of a two-column range of tenors and rates, skipping blank rows
+fn tenor_quotes(range: &Variant, name: &'static str) -> Result<Vec<(Tenor, f64)>, ParameterError> {
+    let invalid = |reason: String| ParameterError::InvalidSchedule { name, reason };
+    let (width, rows) = range.dim();
+    if width != 2 {
+        return Err(invalid(format!("it needs two columns, tenor and rate, not {}", width)));
+    }
+    let mut quotes = Vec::with_capacity(rows);
+    for row in 0..rows {
+        let (tenor, rate) = (range.at(0, row), range.at(1, row));
+        if tenor.is_missing_or_null() && rate.is_missing_or_null() {
+            continue;
+        }
+        let tenor = Tenor::from_cell(&tenor).map_err(|reason| invalid(format!("row {}: {}", row + 1, reason)))?;
+        let rate = f64::try_from(&rate).map_err(|_| invalid(format!("row {} has no rate", row + 1)))?;
+        quotes.push((tenor, rate));
+    }
+    Ok(quotes)
+}
+
+/// Bootstraps a discount curve from deposit and par swap rates and keeps it, returning a
+/// handle for the curve functions
+/// * valuation_date: Date the curve starts from, on which the deposits and swaps start
+/// * deposits: Two columns: tenor such as ON, 1W, 3M or 1Y (or years as a number), and the simple rate; may be blank
+/// * swaps: Two columns: tenor such as 2Y (or years), and the swap's par fixed rate; may be blank
+/// * fixed_frequency: Fixed payments a year on the swaps: 1, 2, 4 or 12
+/// * deposit_day_count: Day count of the deposit rates, such as ACT/360
+/// * swap_day_count: Day count of the swaps' fixed leg, such as 30/360
+/// * interpolation: Linear zero or Log-linear discount (Z or D also work)
+/// * holidays: Range of holiday dates for rolling payment dates off them and weekends, which may be blank
+/// * ret: A handle to the curve
+#[xl_func()]
+pub fn bootstrap_curve(
+    valuation_date: f64,
+    deposits: Variant,
+    swaps: Variant,
+    fixed_frequency: i32,
+    deposit_day_count: String,
+    swap_day_count: String,
+    interpolation: String,
+    holidays: Variant,
+) -> Result<String, Box<dyn std::error::Error>> {
+    if ![1, 2, 4, 12].contains(&fixed_frequency) {
+        return Err(format!("fixed_frequency must be 1, 2, 4 or 12, got {}", fixed_frequency).into());
+    }
+    let months = 12 / fixed_frequency as u32;
+    let (deposit_day_count, swap_day_count) = (DayCount::new(&deposit_day_count)?, DayCount::new(&swap_day_count)?);
+    let interpolation = Interpolation::new(&interpolation)?;
+    let calendar = Calendar::from_cells("Sat/Sun", &holidays)?;
+
+    let mut quotes: Vec<ParQuote> = tenor_quotes(&deposits, "deposits")?
+        .into_iter()
+        .map(|(tenor, rate)| ParQuote::deposit(valuation_date, tenor, rate, deposit_day_count, &calendar))
+        .collect::<Result<_, _>>()?;
+    for (tenor, rate) in tenor_quotes(&swaps, "swaps")? {
+        quotes.push(
+            ParQuote::swap(valuation_date, tenor, rate, months, swap_day_count, &calendar)
+                .map_err(|reason| ParameterError::InvalidSchedule { name: "swaps", reason })?,
+        );
+    }
+    let curve = DiscountCurve::bootstrap(valuation_date, &quotes, interpolation)?;
+    Ok(handles::insert("Curve", curve))
+}
+
+/// Keeps a discount curve given its discount factors, returning a handle for the curve
+/// functions
+/// * valuation_date: Date the curve starts from, where the discount factor is 1
+/// * discount_factors: Two columns: date after the valuation date, and the discount factor to it
+/// * interpolation: Linear zero or Log-linear discount (Z or D also work)
+/// * ret: A handle to the curve
+#[xl_func()]
+pub fn discount_curve(
+    valuation_date: f64,
+    discount_factors: Variant,
+    interpolation: String,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let points = range_pairs(&discount_factors, "date and discount factor")
+        .map_err(|reason| ParameterError::InvalidRateCurve { reason })?;
+    let curve = DiscountCurve::new(valuation_date, &points, Interpolation::new(&interpolation)?)?;
+    Ok(handles::insert("Curve", curve))
+}
+
+/// Discount factor to a date from a curve
+/// * curve: Handle from bootstrap_curve or discount_curve
+/// * date: Date to discount from, not before the valuation date
+/// * ret: The discount factor
+#[xl_func()]
+pub fn df(curve: String, date: f64) -> Result<f64, Box<dyn std::error::Error>> {
+    let curve = handles::get::<DiscountCurve>(&curve)?;
+    Ok(curve.discount(curve.time_to(date)?))
+}
+
+/// Continuously compounded zero rate to a date from a curve, on ACT/365F
+/// * curve: Handle from bootstrap_curve or discount_curve
+/// * date: Date the rate runs to, not before the valuation date
+/// * ret: The zero rate
+#[xl_func()]
+pub fn zero_rate(curve: String, date: f64) -> Result<f64, Box<dyn std::error::Error>> {
+    let curve = handles::get::<DiscountCurve>(&curve)?;
+    Ok(curve.zero_rate(curve.time_to(date)?))
+}
+
+/// Continuously compounded forward rate between two dates from a curve, on ACT/365F
+/// * curve: Handle from bootstrap_curve or discount_curve
+/// * start_date: Start of the forward period, not before the valuation date
+/// * end_date: End of the forward period, after the start
+/// * ret: The forward rate
+#[xl_func()]
+pub fn fwd_rate(curve: String, start_date: f64, end_date: f64) -> Result<f64, Box<dyn std::error::Error>> {
+    let curve = handles::get::<DiscountCurve>(&curve)?;
+    let (start, end) = (curve.time_to(start_date)?, curve.time_to(end_date)?);
+    if end <= start {
+        return Err(format!("end_date {} must be after start_date {}", end_date, start_date).into());
+    }
+    Ok(curve.forward_rate(start, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actuarial::calendar::Weekend;
+    use crate::actuarial::daycount::serial;
+
+    fn quotes(calendar: &Calendar) -> Vec<ParQuote> {
+        let today = serial(2024, 3, 15);
+        let mut quotes: Vec<ParQuote> = [(Tenor::Months(3), 0.05), (Tenor::Months(6), 0.051)]
+            .into_iter()
+            .map(|(tenor, rate)| ParQuote::deposit(today, tenor, rate, DayCount::Act360, calendar).unwrap())
+            .collect();
+        for (years, rate) in [(1, 0.049), (2, 0.046), (5, 0.042), (10, 0.041)] {
+            quotes.push(ParQuote::swap(today, Tenor::Months(12 * years), rate, 6, DayCount::Thirty360, calendar).unwrap());
+        }
+        quotes
+    }
+
+    #[test]
+    fn bootstrapped_curve_reprices_its_quotes() {
+        let calendar = Calendar::new(Weekend::SATURDAY_SUNDAY, &[]);
+        for interpolation in [Interpolation::LinearZero, Interpolation::LogLinearDiscount] {
+            let curve = DiscountCurve::bootstrap(serial(2024, 3, 15), &quotes(&calendar), interpolation).unwrap();
+            for quote in quotes(&calendar) {
+                let coupons: f64 = quote.payments.iter().map(|&(date, accrual)| quote.rate * accrual * curve.discount_at(date)).sum();
+                let value = coupons + curve.discount_at(quote.maturity());
+                assert!((value - 1.0).abs() < 1e-12, "{:?} {}", interpolation, value);
+            }
+            // The 3 month deposit matures on Saturday 15 June, so pays on Monday the 17th
+            let three_months = curve.discount_at(serial(2024, 6, 17));
+            assert!((three_months - 1.0 / (1.0 + 0.05 * 94.0 / 360.0)).abs() < 1e-14);
+        }
+    }
+
+    #[test]
+    fn log_linear_discounts_have_flat_forwards_between_pillars() {
+        let today = serial(2024, 1, 1);
+        let points = [(today + 365.0, 0.96), (today + 730.0, 0.91)];
+        let curve = DiscountCurve::new(today, &points, Interpolation::LogLinearDiscount).unwrap();
+        let forward = (0.96f64 / 0.91).ln();
+        assert!((curve.forward_rate(1.1, 1.3) - forward).abs() < 1e-12);
+        assert!((curve.forward_rate(1.5, 1.9) - forward).abs() < 1e-12);
+        let linear = DiscountCurve::new(today, &points, Interpolation::LinearZero).unwrap();
+        assert!((linear.zero_rate(1.5) - 0.5 * (curve.zero_rate(1.0) + curve.zero_rate(2.0))).abs() < 1e-15);
+    }
+
+    #[test]
+    fn reads_tenors_and_keeps_the_curve_by_handle() {
+        assert_eq!(Tenor::from_cell(&Variant::from("3M")), Ok(Tenor::Months(3)));
+        assert_eq!(Tenor::from_cell(&Variant::from("2w")), Ok(Tenor::Days(14)));
+        assert_eq!(Tenor::from_cell(&Variant::from(0.5)), Ok(Tenor::Months(6)));
+        assert!(Tenor::from_cell(&Variant::from("3Q")).is_err());
+        assert!(Tenor::from_cell(&Variant::from("5年")).is_err() && Tenor::from_cell(&Variant::from("")).is_err());
+
+        let deposits = Variant::from(vec![vec![Variant::from("ON"), Variant::from(0.05)], vec![Variant::from("6M"), Variant::from(0.051)]]);
+        let swaps = Variant::from(vec![vec![Variant::from("2Y"), Variant::from(0.046)]]);
+        let holidays = Variant::from(vec![vec![serial(2024, 12, 25)]]);
+        let handle = bootstrap_curve(serial(2024, 3, 15), deposits, swaps, 2, "ACT/360".into(), "30/360".into(), "D".into(), holidays).unwrap();
+        let curve = handles::get::<DiscountCurve>(&handle).unwrap();
+        assert!(curve.discount(2.0) < 1.0 && curve.rate_curve().zero_rate(2.0) > 0.04);
+    }
+
+    #[test]
+    fn curve_functions_read_a_user_supplied_curve() {
+        let today = serial(2024, 1, 1);
+        let points = Variant::from(vec![vec![today + 365.0, 0.96], vec![today + 730.0, 0.91]]);
+        let curve = discount_curve(today, points, "Log-linear discount".into()).unwrap();
+        assert!((df(curve.clone(), today + 730.0).unwrap() - 0.91).abs() < 1e-15);
+        assert!((zero_rate(curve.clone(), today + 365.0).unwrap() + 0.96f64.ln()).abs() < 1e-15);
+        let forward = fwd_rate(curve.clone(), today + 365.0, today + 730.0).unwrap();
+        assert!((forward - (0.96f64 / 0.91).ln()).abs() < 1e-12);
+        assert!(df(curve.clone(), today - 1.0).is_err() && fwd_rate(curve, today + 5.0, today + 5.0).is_err());
+        assert!(df("Curve#0".to_string(), today).is_err());
+    }
+}
@@ -0,0 +1,176 @@
+//! Day count conventions, turning the time between two dates into a fraction of a year.
+//! Dates are Excel serial numbers, 1 being 1 January 1900. Excel counts a 29 February
+//! 1900 that never was, so serials before 1 March 1900 are a day out; nothing here is
+//! expected to be that old.
+
+use crate::actuarial::option_pricing::ParameterError;
+use xladd_derive::xl_func;
+
+/// The serial number of 1 January 1970, from which days are counted for the calendar
+const UNIX_EPOCH_SERIAL: i64 = 25569;
+
+/// A calendar date
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Date {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+}
+
+impl Date {
+    /// The date of an Excel serial number, ignoring any time of day
+    pub fn from_serial(serial: f64) -> Self {
+        // Days since 1970 to the proleptic Gregorian calendar (Howard Hinnant's algorithm)
+        let days = serial.floor() as i64 - UNIX_EPOCH_SERIAL + 719_468;
+        let era = days.div_euclid(146_097);
+        let day_of_era = days.rem_euclid(146_097);
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+        let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 } as u32;
+        Date { year: year_of_era + era * 400 + i64::from(month <= 2), month, day }
+    }
+
+    /// The Excel serial number of the date
+    pub fn serial(&self) -> f64 {
+        let year = self.year - i64::from(self.month <= 2);
+        let era = year.div_euclid(400);
+        let year_of_era = year.rem_euclid(400);
+        let shifted_month = i64::from((self.month + 9) % 12);
+        let day_of_year = (153 * shifted_month + 2) / 5 + i64::from(self.day) - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        (era * 146_097 + day_of_era - 719_468 + UNIX_EPOCH_SERIAL) as f64
+    }
+
+    /// The same day a number of months later, or the month's last day if it is shorter
+    pub fn add_months(&self, months: i64) -> Date {
+        let months_on = i64::from(self.month) - 1 + months;
+        let (year, month) = (self.year + months_on.div_euclid(12), months_on.rem_euclid(12) as u32 + 1);
+        Date { year, month, day: self.day.min(Date::days_in_month(year, month)) }
+    }
+
+    pub fn is_leap_year(year: i64) -> bool {
+        (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+    }
+
+    pub fn days_in_month(year: i64, month: u32) -> u32 {
+        match month {
+            2 if Date::is_leap_year(year) => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            _ => 31,
+        }
+    }
+}
+
+/// The serial number of a date, for writing dates in tests
+#[cfg(test)]
+pub(crate) fn serial(year: i64, month: u32, day: u32) -> f64 {
+    Date { year, month, day }.serial()
+}
+
+/// How the days between two dates are counted and divided into years
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DayCount {
+    /// Actual days over 365
+    Act365Fixed,
+    /// Actual days over 360
+    Act360,
+    /// Actual days in each calendar year over that year's length (ISDA)
+    ActAct,
+    /// Months of 30 days and years of 360 (the ISDA bond basis)
+    Thirty360,
+}
+
+impl DayCount {
+    /// Reads the convention as typed in a cell, such as ACT/365F, ACT/360, ACT/ACT or 30/360
+    pub fn new(value: &str) -> Result<Self, ParameterError> {
+        match value.trim().to_ascii_uppercase().replace(' ', "").as_str() {
+            "ACT/365F" | "ACT/365" | "ACT/365FIXED" | "A365F" => Ok(DayCount::Act365Fixed),
+            "ACT/360" | "A360" => Ok(DayCount::Act360),
+            "ACT/ACT" | "ACT/ACTISDA" | "AA" => Ok(DayCount::ActAct),
+            "30/360" | "30/360US" | "BONDBASIS" => Ok(DayCount::Thirty360),
+            _ => Err(ParameterError::InvalidDayCount { value: value.to_string() }),
+        }
+    }
+
+    /// The years from `start` to `end`, negative if `end` is earlier
+    pub fn year_fraction(self, start: f64, end: f64) -> f64 {
+        let (start, end) = (start.floor(), end.floor());
+        if end < start {
+            return -self.year_fraction(end, start);
+        }
+        match self {
+            DayCount::Act365Fixed => (end - start) / 365.0,
+            DayCount::Act360 => (end - start) / 360.0,
+            DayCount::ActAct => {
+                let (first, last) = (Date::from_serial(start), Date::from_serial(end));
+                let year_length = |year: i64| if Date::is_leap_year(year) { 366.0 } else { 365.0 };
+                let new_year = |year: i64| Date { year, month: 1, day: 1 }.serial();
+                if first.year == last.year {
+                    return (end - start) / year_length(first.year);
+                }
+                (new_year(first.year + 1) - start) / year_length(first.year)
+                    + (last.year - first.year - 1) as f64
+                    + (end - new_year(last.year)) / year_length(last.year)
+            }
+            DayCount::Thirty360 => {
+                let (first, last) = (Date::from_serial(start), Date::from_serial(end));
+                let first_day = first.day.min(30);
+                let last_day = if first_day == 30 { last.day.min(30) } else { last.day };
+                let days = 360 * (last.year - first.year)
+                    + 30 * (i64::from(last.month) - i64::from(first.month))
+                    + (i64::from(last_day) - i64::from(first_day));
+                days as f64 / 360.0
+            }
+        }
+    }
+}
+
+/// Years between two dates under a day count convention
+/// * start_date: Start date
+/// * end_date: End date
+/// * day_count: ACT/365F, ACT/360, ACT/ACT or 30/360
+/// * ret: The year fraction, negative if the end date is before the start
+#[xl_func()]
+pub fn year_fraction(start_date: f64, end_date: f64, day_count: String) -> Result<f64, Box<dyn std::error::Error>> {
+    Ok(DayCount::new(&day_count)?.year_fraction(start_date, end_date))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serials_match_excel() {
+        assert_eq!(serial(1970, 1, 1), 25569.0);
+        assert_eq!(serial(2024, 2, 29), 45351.0);
+        assert_eq!(serial(1900, 3, 1), 61.0);
+        for value in [61.0, 36525.0, 45351.0, 45351.75, 73050.0] {
+            assert_eq!(Date::from_serial(value).serial(), value.floor());
+        }
+        assert_eq!(Date::from_serial(45351.0), Date { year: 2024, month: 2, day: 29 });
+    }
+
+    #[test]
+    fn conventions_count_the_days_their_own_way() {
+        let (start, end) = (serial(2023, 7, 31), serial(2024, 8, 31));
+        let days = end - start;
+        assert_eq!(DayCount::Act365Fixed.year_fraction(start, end), days / 365.0);
+        assert_eq!(DayCount::Act360.year_fraction(start, end), days / 360.0);
+        let act_act = 154.0 / 365.0 + 243.0 / 366.0;
+        assert!((DayCount::ActAct.year_fraction(start, end) - act_act).abs() < 1e-15);
+        // 30 July to 30 August, a year and a month later
+        assert_eq!(DayCount::Thirty360.year_fraction(start, end), 390.0 / 360.0);
+        assert_eq!(DayCount::Thirty360.year_fraction(serial(2024, 1, 15), serial(2024, 3, 31)), 76.0 / 360.0);
+        assert_eq!(DayCount::Act360.year_fraction(end, start), -days / 360.0);
+    }
+
+    #[test]
+    fn reads_conventions_from_cells() {
+        assert_eq!(DayCount::new(" act/act ").unwrap(), DayCount::ActAct);
+        assert_eq!(year_fraction(45000.0, 45365.0, "ACT/365F".to_string()).unwrap(), 1.0);
+        assert!(year_fraction(45000.0, 45365.0, "ACT/252".to_string()).is_err());
+    }
+}
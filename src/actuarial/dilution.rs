@@ -0,0 +1,152 @@
+//! Dilution from the shares issued when options are exercised (Galai and Schneller). When
+//! holders exercise, the new shares share the company's equity with the existing ones, so
+//! each option is worth N / (N + M) of an option on the equity per share, where N shares
+//! are outstanding and M options granted. The equity per share includes the options'
+//! own value, S + (M / N) W, so the value W is found by iterating to the fixed point.
+
+use crate::actuarial::option_pricing::{
+    binomial_option_value, black_scholes_call_option_value, black_scholes_put_option_value,
+    OptionType, ParameterError, PositiveFloat,
+};
+use xladd_derive::xl_func;
+
+/// Most iterations of the fixed point; each shrinks the error by at least M / (N + M)
+const MAX_ITERATIONS: usize = 1000;
+
+/// The diluted value of an option, given its undiluted value for any share price. Returns
+/// the value and the result of `value_at` at the equity per share it settles on.
+pub fn dilution_adjusted<T>(
+    share_price: f64,
+    shares_outstanding: f64,
+    options_granted: f64,
+    value_at: impl Fn(f64) -> Result<(f64, T), Box<dyn std::error::Error>>,
+) -> Result<(f64, T), Box<dyn std::error::Error>> {
+    if !(shares_outstanding > 0.0 && shares_outstanding.is_finite()) {
+        return Err(ParameterError::InvalidPositiveValue { parameter: "shares_outstanding", value: shares_outstanding }.into());
+    }
+    let options_granted = PositiveFloat::new(options_granted, "options_granted")?.0;
+    let dilution = shares_outstanding / (shares_outstanding + options_granted);
+    let ratio = options_granted / shares_outstanding;
+
+    let (mut value, mut result) = value_at(share_price)?;
+    value *= dilution;
+    for _ in 0..MAX_ITERATIONS {
+        let (undiluted, next_result) = value_at(share_price + ratio * value)?;
+        let next = dilution * undiluted;
+        result = next_result;
+        let change = (next - value).abs();
+        value = next;
+        if change <= 1e-12 * value.max(1.0) {
+            break;
+        }
+    }
+    Ok((value, result))
+}
+
+/// Black-Scholes value of a warrant or option whose exercise issues new shares
+/// * share_price: Current share price
+/// * strike_price: Strike price of the option
+/// * time_to_maturity: Time to maturity in years
+/// * risk_free: Risk-free interest rate (continuously compounded)
+/// * div_rate: Dividend yield (continuously compounded)
+/// * sigma: Volatility of the company's equity
+/// * shares_outstanding: Number of shares in issue
+/// * options_granted: Number of options granted, each for one new share
+/// * call_put: Call or Put (C or P also work)
+/// * ret: The diluted value of one option
+#[xl_func()]
+pub fn diluted_black_scholes_value(
+    share_price: f64,
+    strike_price: f64,
+    time_to_maturity: f64,
+    risk_free: f64,
+    div_rate: f64,
+    sigma: f64,
+    shares_outstanding: f64,
+    options_granted: f64,
+    call_put: String,
+) -> Result<f64, Box<dyn std::error::Error>> {
+    let value = match OptionType::new(&call_put)? {
+        OptionType::Call => black_scholes_call_option_value,
+        OptionType::Put => black_scholes_put_option_value,
+    };
+    let (value, _) = dilution_adjusted(share_price, shares_outstanding, options_granted, |price| {
+        Ok((value(price, strike_price, time_to_maturity, risk_free, div_rate, sigma), ()))
+    })?;
+    Ok(value)
+}
+
+/// AF function to calculate value of an employee stock option from the binomial tree,
+/// adjusted for the dilution from the shares issued when the grant is exercised
+/// * share_price: Share price at grant date
+/// * strike_price: Price at which the option is exercised
+/// * time_to_maturity: Term to maturity in years
+/// * vesting_period: Term until the end of the vesting period in years
+/// * risk_free: Risk-free rate at the appropriate duration
+/// * sigma: Volatility of the company's equity
+/// * div_rate: Dividend rate
+/// * exit_pre_vesting: Exit rate before the vesting date
+/// * exit_post_vesting: Exit rate after the vesting date
+/// * multiple: Multiple of the strike price at which the holder exercises
+/// * steps: Number of time steps in the tree
+/// * shares_outstanding: Number of shares in issue
+/// * options_granted: Number of options granted, each for one new share
+/// * ret: The diluted value of one option and its expected life
+#[xl_func()]
+pub fn diluted_option_value(
+    share_price: f64,
+    strike_price: f64,
+    time_to_maturity: f64,
+    vesting_period: f64,
+    risk_free: f64,
+    sigma: f64,
+    div_rate: f64,
+    exit_pre_vesting: f64,
+    exit_post_vesting: f64,
+    multiple: f64,
+    steps: i32,
+    shares_outstanding: f64,
+    options_granted: f64,
+) -> Result<Vec<f64>, Box<dyn std::error::Error>> {
+    let (value, expected_life) = dilution_adjusted(share_price, shares_outstanding, options_granted, |price| {
+        let result = binomial_option_value(
+            price, strike_price, time_to_maturity, vesting_period,
+            risk_free, sigma, div_rate,
+            exit_pre_vesting, exit_post_vesting,
+            multiple,
+            steps)?;
+        Ok((result[0], result[1]))
+    })?;
+    Ok(vec![value, expected_life])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actuarial::option_pricing::black_scholes_value;
+
+    #[test]
+    fn no_new_shares_is_no_dilution() {
+        let diluted = diluted_black_scholes_value(100.0, 90.0, 4.0, 0.04, 0.01, 0.3, 1e6, 0.0, "Call".to_string()).unwrap();
+        let plain = black_scholes_value(100.0, 90.0, 4.0, 0.04, 0.01, 0.3, "Call".to_string()).unwrap();
+        assert!((diluted - plain).abs() < 1e-12);
+    }
+
+    #[test]
+    fn diluted_value_is_the_fixed_point() {
+        let (n, m) = (1e6, 2e5);
+        let diluted = diluted_black_scholes_value(100.0, 100.0, 5.0, 0.05, 0.0, 0.35, n, m, "Call".to_string()).unwrap();
+        let equity_per_share = 100.0 + m / n * diluted;
+        let plain = black_scholes_value(equity_per_share, 100.0, 5.0, 0.05, 0.0, 0.35, "Call".to_string()).unwrap();
+        assert!((diluted - n / (n + m) * plain).abs() < 1e-9);
+        assert!(diluted < black_scholes_value(100.0, 100.0, 5.0, 0.05, 0.0, 0.35, "Call".to_string()).unwrap());
+    }
+
+    #[test]
+    fn diluted_tree_value_is_below_the_undiluted_one() {
+        let plain = binomial_option_value(100.0, 100.0, 10.0, 3.0, 0.05, 0.3, 0.02, 0.05, 0.08, 2.5, 200).unwrap();
+        let diluted = diluted_option_value(100.0, 100.0, 10.0, 3.0, 0.05, 0.3, 0.02, 0.05, 0.08, 2.5, 200, 1e6, 1e5).unwrap();
+        assert!(diluted[0] < plain[0] && diluted[0] > plain[0] * 1e6 / 1.1e6, "{:?} {:?}", diluted, plain);
+        assert!(diluted_option_value(100.0, 100.0, 10.0, 3.0, 0.05, 0.3, 0.02, 0.05, 0.08, 2.5, 200, 0.0, 1e5).is_err());
+    }
+}
@@ -0,0 +1,136 @@
+//! The assumptions and result of a grant's valuation, laid out as the share-based payment
+//! note of IFRS 2 or ASC 718 shows them
+
+use crate::actuarial::daycount::Date;
+use crate::actuarial::option_pricing::{binomial_option_value, trinomial_option_value, TreeModel};
+use xladd_derive::xl_func;
+
+/// Multiples at least this high mean the holder never exercises early just because the
+/// share has risen, as `option_value_optimal` assumes
+const OPTIMAL_MULTIPLE: f64 = 1e6;
+
+const MONTHS: [&str; 12] = [
+    "January", "February", "March", "April", "May", "June",
+    "July", "August", "September", "October", "November", "December",
+];
+
+/// Disclosure table for a grant valued with the binomial or trinomial tree, ready to paste
+/// into the financial statements
+/// * valuation_date: Grant or valuation date
+/// * share_price: Share price at grant date
+/// * strike_price: Price at which the option is exercised
+/// * time_to_maturity: Contractual term in years
+/// * vesting_period: Term until the end of the vesting period in years
+/// * risk_free: Risk-free rate at the appropriate duration
+/// * sigma: Expected share volatility
+/// * div_rate: Expected dividend yield
+/// * exit_pre_vesting: Exit rate before the vesting date
+/// * exit_post_vesting: Exit rate after the vesting date
+/// * multiple: Multiple of the strike price at which the holder exercises (1e7 for optimal)
+/// * steps: Number of time steps in the tree
+/// * model: Binomial or Trinomial (B or T also work)
+/// * ret: Two columns, the assumption or result and its value as text
+#[xl_func()]
+pub fn grant_disclosure(
+    valuation_date: f64,
+    share_price: f64,
+    strike_price: f64,
+    time_to_maturity: f64,
+    vesting_period: f64,
+    risk_free: f64,
+    sigma: f64,
+    div_rate: f64,
+    exit_pre_vesting: f64,
+    exit_post_vesting: f64,
+    multiple: f64,
+    steps: i32,
+    model: String,
+) -> Result<Vec<(String, String)>, Box<dyn std::error::Error>> {
+    let model = TreeModel::new(&model)?;
+    let value = match model {
+        TreeModel::Binomial => binomial_option_value,
+        TreeModel::Trinomial => trinomial_option_value,
+    };
+    let result = value(
+        share_price, strike_price, time_to_maturity, vesting_period,
+        risk_free, sigma, div_rate,
+        exit_pre_vesting, exit_post_vesting,
+        multiple,
+        steps,
+    )?;
+
+    let model_name = match model {
+        TreeModel::Binomial => "Binomial lattice",
+        TreeModel::Trinomial => "Trinomial lattice",
+    };
+    let exercise = if multiple >= OPTIMAL_MULTIPLE {
+        "Optimal exercise".to_string()
+    } else {
+        format!("{:.2}x exercise price", multiple)
+    };
+    let rows = [
+        ("Valuation date", date_text(valuation_date)),
+        ("Valuation model", model_name.to_string()),
+        ("Share price at grant date", amount(share_price)),
+        ("Exercise price", amount(strike_price)),
+        ("Expected volatility", percent(sigma)),
+        ("Expected dividend yield", percent(div_rate)),
+        ("Risk-free interest rate", percent(risk_free)),
+        ("Contractual life (years)", amount(time_to_maturity)),
+        ("Vesting period (years)", amount(vesting_period.min(time_to_maturity))),
+        ("Expected exit rate before vesting", percent(exit_pre_vesting)),
+        ("Expected exit rate after vesting", percent(exit_post_vesting)),
+        ("Early exercise assumption", exercise),
+        ("Expected life (years)", amount(result[1])),
+        ("Fair value per option", amount(result[0])),
+    ];
+    Ok(rows.into_iter().map(|(label, value)| (label.to_string(), value)).collect())
+}
+
+fn amount(value: f64) -> String {
+    format!("{:.2}", value)
+}
+
+fn percent(rate: f64) -> String {
+    format!("{:.2}%", rate * 100.0)
+}
+
+/// An Excel serial date, such as 46022, as 31 December 2025
+fn date_text(serial: f64) -> String {
+    let days = serial.floor();
+    // Excel counts 29 February 1900, which never was, so serial 60 has no civil date and
+    // the serials before it are a day ahead of the calendar
+    if days == 60.0 {
+        return "29 February 1900".to_string();
+    }
+    if days < 1.0 {
+        return format!("{}", serial);
+    }
+    let date = Date::from_serial(if days < 60.0 { serial + 1.0 } else { serial });
+    format!("{} {} {}", date.day, MONTHS[date.month as usize - 1], date.year)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_excel_serial_dates() {
+        assert_eq!(date_text(46022.0), "31 December 2025");
+        assert_eq!(date_text(1.0), "1 January 1900");
+        assert_eq!(date_text(59.0), "28 February 1900");
+        assert_eq!(date_text(61.0), "1 March 1900");
+        assert_eq!(date_text(45351.75), "29 February 2024");
+    }
+
+    #[test]
+    fn disclosure_lists_assumptions_and_result() {
+        let table = grant_disclosure(46022.0, 100.0, 100.0, 10.0, 3.0, 0.05, 0.3, 0.02, 0.05, 0.08, 1e7, 200, "Binomial".to_string()).unwrap();
+        let row = |label: &str| table.iter().find(|(name, _)| name == label).map(|(_, value)| value.as_str());
+        assert_eq!(row("Valuation date"), Some("31 December 2025"));
+        assert_eq!(row("Expected volatility"), Some("30.00%"));
+        assert_eq!(row("Early exercise assumption"), Some("Optimal exercise"));
+        let value = binomial_option_value(100.0, 100.0, 10.0, 3.0, 0.05, 0.3, 0.02, 0.05, 0.08, 1e7, 200).unwrap();
+        assert_eq!(row("Fair value per option"), Some(format!("{:.2}", value[0]).as_str()));
+    }
+}
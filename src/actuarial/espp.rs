@@ -0,0 +1,135 @@
+//! Employee share purchase plans (ESPPs), where employees save through payroll over an
+//! offering period and buy shares at its end at a discount, with a lookback to the lower
+//! of the prices at the start and end. As in FASB Technical Bulletin 97-1 (ASC 718-50),
+//! the award is valued as its components: a share for the discount, an at-the-money call
+//! for the lookback and, when the savings are a fixed amount, an at-the-money put for
+//! the extra shares those savings buy if the price falls.
+
+use crate::actuarial::option_pricing::{
+    black_scholes_call_option_value, black_scholes_put_option_value, PositiveFloat, Volatility,
+};
+use xladd_derive::xl_func;
+
+/// The value of an ESPP purchase, split into its components
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EsppValue {
+    pub discount: f64,
+    pub call: f64,
+    pub put: f64,
+}
+
+impl EsppValue {
+    pub fn fair_value(&self) -> f64 {
+        self.discount + self.call + self.put
+    }
+}
+
+/// The components per share at the offering-date price. With a lookback the employee
+/// pays (1 - discount) times the lower price; without one, the price at purchase.
+/// `fixed_savings` is whether the employee saves a fixed amount, so buys more shares when
+/// the price is lower, rather than a fixed number of shares.
+pub fn espp_components(
+    share_price: f64,
+    time_to_maturity: f64,
+    risk_free: f64,
+    div_rate: f64,
+    sigma: f64,
+    discount: f64,
+    lookback: bool,
+    fixed_savings: bool,
+) -> EsppValue {
+    let (call, put) = if lookback {
+        let call = black_scholes_call_option_value(share_price, share_price, time_to_maturity, risk_free, div_rate, sigma);
+        let put = black_scholes_put_option_value(share_price, share_price, time_to_maturity, risk_free, div_rate, sigma);
+        ((1.0 - discount) * call, if fixed_savings { discount * put } else { 0.0 })
+    } else {
+        (0.0, 0.0)
+    };
+    // Savings fixed in money and no lookback buy a known amount more than they cost, paid
+    // in shares at the end of the period
+    let discount = if fixed_savings && !lookback {
+        discount * share_price * (-risk_free * time_to_maturity).exp()
+    } else {
+        discount * share_price * (-div_rate * time_to_maturity).exp()
+    };
+    EsppValue { discount, call, put }
+}
+
+/// Fair value of an ESPP purchase right per share at the offering-date price, with its
+/// discount, call and put components
+/// * share_price: Share price at the start of the offering period
+/// * time_to_maturity: Years from the start of the offering to the purchase date
+/// * risk_free: Risk-free interest rate (continuously compounded)
+/// * div_rate: Dividend yield (continuously compounded)
+/// * sigma: Expected share volatility
+/// * discount: Discount to the purchase price, such as 0.15
+/// * lookback: TRUE if the price is the lower of the start and purchase date prices
+/// * fixed_savings: TRUE if employees save a fixed amount, FALSE for a fixed number of shares
+/// * ret: Discount, call and put components, and the fair value
+#[xl_func()]
+pub fn espp_value(
+    share_price: f64,
+    time_to_maturity: f64,
+    risk_free: f64,
+    div_rate: f64,
+    sigma: f64,
+    discount: f64,
+    lookback: bool,
+    fixed_savings: bool,
+) -> Result<Vec<(String, f64)>, Box<dyn std::error::Error>> {
+    let share_price = PositiveFloat::new(share_price, "share_price")?.0;
+    let time_to_maturity = PositiveFloat::new(time_to_maturity, "time_to_maturity")?.0;
+    let sigma = Volatility::new(sigma)?.0;
+    if !(0.0..1.0).contains(&discount) {
+        return Err(format!("discount must be from 0 to less than 1, got {}", discount).into());
+    }
+    let value = espp_components(share_price, time_to_maturity, risk_free, div_rate, sigma, discount, lookback, fixed_savings);
+    Ok(vec![
+        ("Discount".to_string(), value.discount),
+        ("Call".to_string(), value.call),
+        ("Put".to_string(), value.put),
+        ("Fair value".to_string(), value.fair_value()),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actuarial::option_pricing::normal_pdf;
+
+    /// The discounted expected payoff at the purchase date per share at the offering-date
+    /// price, integrated over the lognormal share price
+    fn integrated(s0: f64, t: f64, r: f64, q: f64, sigma: f64, d: f64, lookback: bool, fixed_savings: bool) -> f64 {
+        let steps = 4000;
+        let width = 16.0 / steps as f64;
+        (0..=steps)
+            .map(|k| {
+                let z = -8.0 + width * k as f64;
+                let st = s0 * ((r - q - 0.5 * sigma * sigma) * t + sigma * t.sqrt() * z).exp();
+                let price = (1.0 - d) * if lookback { s0.min(st) } else { st };
+                // Savings of (1 - d) s0 buy that much over the price; otherwise one share
+                let shares = if fixed_savings { (1.0 - d) * s0 / price } else { 1.0 };
+                let weight = if k == 0 || k == steps { 0.5 } else { 1.0 };
+                weight * width * normal_pdf(z) * shares * (st - price)
+            })
+            .sum::<f64>()
+            * (-r * t).exp()
+    }
+
+    #[test]
+    fn components_add_up_to_the_expected_payoff() {
+        for (lookback, fixed_savings) in [(true, true), (true, false), (false, true), (false, false)] {
+            let value = espp_components(40.0, 0.5, 0.04, 0.015, 0.35, 0.15, lookback, fixed_savings).fair_value();
+            let expected = integrated(40.0, 0.5, 0.04, 0.015, 0.35, 0.15, lookback, fixed_savings);
+            assert!((value - expected).abs() < 1e-3, "{} {} {} {}", lookback, fixed_savings, value, expected);
+        }
+    }
+
+    #[test]
+    fn lookback_without_a_discount_is_an_at_the_money_call() {
+        let table = espp_value(40.0, 0.5, 0.04, 0.015, 0.35, 0.0, true, true).unwrap();
+        let call = black_scholes_call_option_value(40.0, 40.0, 0.5, 0.04, 0.015, 0.35);
+        assert!((table[3].1 - call).abs() < 1e-12);
+        assert!(espp_value(40.0, 0.5, 0.04, 0.015, 0.35, 1.2, true, true).is_err());
+    }
+}
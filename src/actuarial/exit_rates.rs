@@ -0,0 +1,65 @@
+//! Employee exit rates that change with years of service, for the lattices. Staff are
+//! often likelier to leave in their first years than later, so a single rate overstates
+//! exits late in an option's life and understates them early on.
+
+use crate::actuarial::option_pricing::ParameterError;
+
+/// Annual employee exit rates by year of service: the first applies in the first year
+/// after grant, the second in the next, and the last from then on
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExitRates(Vec<f64>);
+
+impl ExitRates {
+    /// The same rate in every year
+    pub fn flat(rate: f64) -> Self {
+        ExitRates(vec![rate])
+    }
+
+    /// Rates for consecutive years of service from grant
+    pub fn new(rates: &[f64]) -> Result<Self, ParameterError> {
+        if rates.is_empty() {
+            return Err(ParameterError::InvalidSchedule { name: "exit rates", reason: "it has no rates".to_string() });
+        }
+        if let Some((year, &rate)) = rates.iter().enumerate().find(|(_, rate)| !(0.0..1.0).contains(*rate)) {
+            return Err(ParameterError::InvalidSchedule {
+                name: "exit rates",
+                reason: format!("rates must be at least 0 and below 1, got {} in year {}", rate, year + 1),
+            });
+        }
+        Ok(ExitRates(rates.to_vec()))
+    }
+
+    /// The rate in the year of service a time falls in
+    pub fn rate(&self, time: f64) -> f64 {
+        let year = time.max(0.0).floor() as usize;
+        self.0[year.min(self.0.len() - 1)]
+    }
+
+    /// The probability of staying from `start` to `end`, each year's rate applying to the
+    /// part of the period in that year
+    pub fn survival(&self, start: f64, end: f64) -> f64 {
+        let mut survival = 1.0;
+        let mut from = start;
+        while from < end {
+            let to = (from.floor() + 1.0).min(end);
+            survival *= (1.0 - self.rate(from)).powf(to - from);
+            from = to;
+        }
+        survival
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn survival_applies_each_years_rate() {
+        let rates = ExitRates::new(&[0.2, 0.1, 0.05]).unwrap();
+        assert_eq!((rates.rate(0.5), rates.rate(1.0), rates.rate(9.0)), (0.2, 0.1, 0.05));
+        let expected = 0.8f64.powf(0.5) * 0.9 * 0.95f64.powf(1.5);
+        assert!((rates.survival(0.5, 3.5) - expected).abs() < 1e-15);
+        assert!((ExitRates::flat(0.1).survival(0.3, 2.8) - 0.9f64.powf(2.5)).abs() < 1e-15);
+        assert!(ExitRates::new(&[0.1, 1.0]).is_err() && ExitRates::new(&[]).is_err());
+    }
+}
@@ -0,0 +1,256 @@
+//! Recognising the cost of a grant over its vesting period. Each tranche's expected value,
+//! after the options expected to be forfeited before it vests, is expensed either over
+//! its own vesting period (the graded method, which IFRS 2 requires), or the whole grant
+//! straight-line over the last tranche's, never behind what has already vested (allowed
+//! for service-only awards under ASC 718).
+
+use crate::actuarial::calendar::{Calendar, Roll};
+use crate::actuarial::daycount::DayCount;
+use crate::actuarial::option_pricing::{ParameterError, VestingSchedule};
+use crate::actuarial::ranges::range_pairs;
+use xladd_core::variant::Variant;
+use xladd_derive::xl_func;
+
+/// Dates become years from grant in proportion to the days between them
+const DAY_COUNT: DayCount = DayCount::Act365Fixed;
+
+/// How a grant's expense is spread over its vesting period
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpenseMethod {
+    Graded,
+    StraightLine,
+}
+
+impl ExpenseMethod {
+    /// Reads the method as typed in a cell: Graded, Straight-line, G or S in any case
+    pub fn new(value: &str) -> Result<Self, ParameterError> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "graded" | "g" => Ok(ExpenseMethod::Graded),
+            "straight-line" | "straight line" | "straightline" | "s" => Ok(ExpenseMethod::StraightLine),
+            _ => Err(ParameterError::InvalidExpenseMethod {
+                value: value.to_string(),
+            }),
+        }
+    }
+}
+
+/// The expected value of a grant's tranches and when they vest
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExpenseTranches(Vec<(f64, f64)>);
+
+impl ExpenseTranches {
+    /// Each tranche's share of the grant value, less the forfeitures expected at an annual
+    /// rate until it vests
+    pub fn new(grant_value: f64, schedule: &VestingSchedule, forfeiture_rate: f64) -> Result<Self, ParameterError> {
+        if !(0.0..1.0).contains(&forfeiture_rate) {
+            return Err(ParameterError::InvalidRate { parameter: "forfeiture_rate", value: forfeiture_rate });
+        }
+        Ok(ExpenseTranches(
+            schedule
+                .0
+                .iter()
+                .map(|tranche| (tranche.time, grant_value * tranche.fraction * (1.0 - forfeiture_rate).powf(tranche.time)))
+                .collect(),
+        ))
+    }
+
+    /// When the last tranche vests
+    pub fn final_vesting(&self) -> f64 {
+        self.0.last().map_or(0.0, |&(time, _)| time)
+    }
+
+    /// Expense recognised by a time under a method
+    pub fn recognised(&self, method: ExpenseMethod, time: f64) -> f64 {
+        match method {
+            ExpenseMethod::Graded => self.graded(time),
+            ExpenseMethod::StraightLine => self.straight_line(time),
+        }
+    }
+
+    /// Expense recognised by a time under the graded method
+    pub fn graded(&self, time: f64) -> f64 {
+        self.0.iter().map(|&(vests, value)| value * earned(time, vests)).sum()
+    }
+
+    /// Expense recognised by a time under the straight-line method
+    pub fn straight_line(&self, time: f64) -> f64 {
+        if time < 0.0 {
+            return 0.0;
+        }
+        let total: f64 = self.0.iter().map(|&(_, value)| value).sum();
+        let vested: f64 = self.0.iter().filter(|&&(vests, _)| vests <= time).map(|&(_, value)| value).sum();
+        (total * earned(time, self.final_vesting())).max(vested)
+    }
+}
+
+/// The part of a service period from 0 to `vests` that has been rendered by a time
+fn earned(time: f64, vests: f64) -> f64 {
+    if vests <= 0.0 { 1.0 } else { (time / vests).clamp(0.0, 1.0) }
+}
+
+/// Expense recognised in each period from grant to final vesting, under the graded and the
+/// straight-line methods
+/// * grant_value: Fair value of the whole grant at grant date
+/// * vesting_schedule: Two columns: years from grant, and the total fraction vested by then
+/// * forfeiture_rate: Annual rate at which options are expected to be forfeited before vesting
+/// * period_length: Length of each period in years, such as 0.25 for quarters
+/// * ret: Period end, then the expense and cumulative expense for each method
+#[xl_func()]
+pub fn expense_schedule(
+    grant_value: f64,
+    vesting_schedule: Variant,
+    forfeiture_rate: f64,
+    period_length: f64,
+) -> Result<Vec<Vec<Variant>>, Box<dyn std::error::Error>> {
+    let points = range_pairs(&vesting_schedule, "time and fraction vested")
+        .map_err(|reason| ParameterError::InvalidVestingSchedule { reason })?;
+    let tranches = ExpenseTranches::new(grant_value, &VestingSchedule::new(&points)?, forfeiture_rate)?;
+    if !(period_length > 0.0 && period_length.is_finite()) {
+        return Err(ParameterError::InvalidPositiveValue { parameter: "period_length", value: period_length }.into());
+    }
+
+    let final_vesting = tranches.final_vesting();
+    let periods = ((final_vesting / period_length - 1e-9).ceil() as usize).max(1);
+    let mut table = vec![
+        ["Period end", "Graded expense", "Graded cumulative", "Straight-line expense", "Straight-line cumulative"]
+            .map(Variant::from)
+            .to_vec(),
+    ];
+    let (mut graded, mut straight_line) = (0.0, 0.0);
+    for period in 1..=periods {
+        let end = (period as f64 * period_length).min(final_vesting);
+        let (graded_to_date, straight_line_to_date) = (tranches.graded(end), tranches.straight_line(end));
+        table.push(
+            [end, graded_to_date - graded, graded_to_date, straight_line_to_date - straight_line, straight_line_to_date]
+                .map(Variant::from)
+                .to_vec(),
+        );
+        (graded, straight_line) = (graded_to_date, straight_line_to_date);
+    }
+    Ok(table)
+}
+
+/// Expense recognised in each reporting period of a calendar, for a grant that vests on
+/// given dates. With the same period ends for every grant, the tables of a portfolio add
+/// up row by row.
+/// * grant_date: Grant date
+/// * grant_value: Fair value of the whole grant at grant date
+/// * vesting_schedule: Two columns: vesting date, and the total fraction vested by then
+/// * forfeiture_rate: Annual rate at which options are expected to be forfeited before vesting
+/// * period_ends: Increasing end dates of the reporting periods, such as month ends; the first period takes all the expense up to its end
+/// * method: Graded or Straight-line (G or S also work)
+/// * roll: How period ends on days off move to business days: Following, Modified following, Preceding or Unadjusted (F, MF, P or U also work)
+/// * weekend: Days off each week, such as Sat/Sun, or seven 0s and 1s from Monday as in WORKDAY.INTL
+/// * holidays: Range of holiday dates, which may be blank
+/// * ret: Period end, expense in the period and cumulative expense
+#[xl_func()]
+pub fn amortization_schedule(
+    grant_date: f64,
+    grant_value: f64,
+    vesting_schedule: Variant,
+    forfeiture_rate: f64,
+    period_ends: Vec<f64>,
+    method: String,
+    roll: String,
+    weekend: String,
+    holidays: Variant,
+) -> Result<Vec<Vec<Variant>>, Box<dyn std::error::Error>> {
+    let method = ExpenseMethod::new(&method)?;
+    let points: Vec<(f64, f64)> = range_pairs(&vesting_schedule, "vesting date and fraction vested")
+        .map_err(|reason| ParameterError::InvalidVestingSchedule { reason })?
+        .into_iter()
+        .map(|(date, vested)| (DAY_COUNT.year_fraction(grant_date, date), vested))
+        .collect();
+    let tranches = ExpenseTranches::new(grant_value, &VestingSchedule::new(&points)?, forfeiture_rate)?;
+    let (calendar, roll) = (Calendar::from_cells(&weekend, &holidays)?, Roll::new(&roll)?);
+    let period_ends = period_ends.iter().map(|&end| calendar.adjust(end, roll)).collect::<Result<Vec<f64>, _>>()?;
+    if period_ends.is_empty() || period_ends.windows(2).any(|pair| pair[1] <= pair[0]) {
+        return Err("period_ends must be increasing dates, once rolled to business days".into());
+    }
+
+    let mut table = vec![["Period end", "Expense", "Cumulative"].map(Variant::from).to_vec()];
+    let mut recognised = 0.0;
+    for &end in &period_ends {
+        let to_date = tranches.recognised(method, DAY_COUNT.year_fraction(grant_date, end));
+        table.push([end, to_date - recognised, to_date].map(Variant::from).to_vec());
+        recognised = to_date;
+    }
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actuarial::daycount::Date;
+
+    fn four_year_schedule() -> VestingSchedule {
+        VestingSchedule::new(&[(1.0, 0.25), (2.0, 0.5), (3.0, 0.75), (4.0, 1.0)]).unwrap()
+    }
+
+    #[test]
+    fn graded_method_front_loads_the_expense() {
+        let tranches = ExpenseTranches::new(1000.0, &four_year_schedule(), 0.0).unwrap();
+        // The first year carries all of the first tranche and part of every other
+        let first_year = 250.0 * (1.0 + 1.0 / 2.0 + 1.0 / 3.0 + 1.0 / 4.0);
+        assert!((tranches.graded(1.0) - first_year).abs() < 1e-9);
+        assert_eq!(tranches.straight_line(1.0), 250.0);
+        assert!((tranches.graded(4.0) - 1000.0).abs() < 1e-9);
+        assert_eq!(tranches.straight_line(4.0), 1000.0);
+    }
+
+    #[test]
+    fn straight_line_keeps_up_with_what_has_vested() {
+        let front_loaded = VestingSchedule::new(&[(1.0, 0.6), (3.0, 1.0)]).unwrap();
+        let tranches = ExpenseTranches::new(900.0, &front_loaded, 0.0).unwrap();
+        assert_eq!(tranches.straight_line(1.0), 540.0);
+        assert_eq!(tranches.straight_line(2.0), 600.0);
+    }
+
+    #[test]
+    fn schedule_expects_forfeitures_and_adds_up() {
+        let schedule = Variant::from(vec![vec![1.0, 0.25], vec![2.0, 0.5], vec![3.0, 0.75], vec![4.0, 1.0]]);
+        let table = expense_schedule(1000.0, schedule, 0.1, 0.25).unwrap();
+        assert_eq!(table.len(), 17);
+        let expected: f64 = (1..=4).map(|year| 250.0 * 0.9f64.powi(year)).sum();
+        let column = |column: usize| table[1..].iter().map(|row| f64::try_from(&row[column]).unwrap()).sum::<f64>();
+        assert!((column(1) - expected).abs() < 1e-9);
+        assert!((column(3) - expected).abs() < 1e-9);
+        assert_eq!(f64::try_from(&table[16][0]).ok(), Some(4.0));
+    }
+
+    #[test]
+    fn amortization_follows_the_reporting_calendar() {
+        let grant = 45000.0;
+        let cliff = Variant::from(vec![vec![grant + 365.0, 1.0]]);
+        let ends = vec![grant - 20.0, grant + 10.0, grant + 100.0, grant + 365.0, grant + 400.0];
+        let table = amortization_schedule(
+            grant, 730.0, cliff, 0.0, ends, "Straight-line".to_string(), "Unadjusted".to_string(), "Sat/Sun".to_string(), Variant::missing(),
+        )
+        .unwrap();
+        let expense: Vec<f64> = table[1..].iter().map(|row| f64::try_from(&row[1]).unwrap()).collect();
+        assert_eq!(expense.len(), 5);
+        for (value, expected) in expense.iter().zip([0.0, 20.0, 180.0, 530.0, 0.0]) {
+            assert!((value - expected).abs() < 1e-9, "{:?}", expense);
+        }
+        assert!(ExpenseMethod::new("G").is_ok() && ExpenseMethod::new("level").is_err());
+    }
+
+    #[test]
+    fn period_ends_roll_to_business_days() {
+        let serial = |month: u32, day: u32| Date { year: 2024, month, day }.serial();
+        let grant = serial(1, 1);
+        let cliff = Variant::from(vec![vec![grant + 366.0, 1.0]]);
+        // Saturday 30 March, rolled back past Good Friday
+        let ends = vec![serial(3, 30), serial(6, 30)];
+        let holidays = Variant::from(vec![vec![serial(3, 29)]]);
+        let table = amortization_schedule(
+            grant, 732.0, cliff, 0.0, ends, "S".to_string(), "Preceding".to_string(), "Sat/Sun".to_string(), holidays,
+        )
+        .unwrap();
+        let end = f64::try_from(&table[1][0]).unwrap();
+        assert_eq!(end, serial(3, 28));
+        assert!((f64::try_from(&table[1][2]).unwrap() - 2.0 * (end - grant)).abs() < 1e-9);
+        // Sunday 30 June
+        assert_eq!(f64::try_from(&table[2][0]).ok(), Some(serial(6, 28)));
+    }
+}
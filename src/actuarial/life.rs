@@ -0,0 +1,406 @@
+//! Life contingencies from a mortality table: survival probabilities, curtate life
+//! expectancy, and the present values of life annuities and assurances, with cash flows
+//! at whole years and interest at an annual effective rate. Nobody survives past the
+//! table's last age. A table can be kept behind a handle with its improvement scale, so
+//! it is read once and shared by every formula that uses it.
+
+use crate::actuarial::option_pricing::ParameterError;
+use crate::actuarial::ranges::range_pairs;
+use std::sync::Arc;
+use xladd_core::handles;
+use xladd_core::variant::Variant;
+use xladd_derive::xl_func;
+
+/// Annual rates at which mortality falls, by age, and the years they apply over
+#[derive(Debug, Clone, PartialEq)]
+pub struct Improvement {
+    first_age: u32,
+    rates: Vec<f64>,
+    /// Calendar year the table's rates are for
+    pub base_year: i32,
+    /// Calendar year the lives are valued in
+    pub valuation_year: i32,
+}
+
+impl Improvement {
+    /// (age, rate) rows in order of age, one for each age
+    pub fn new(rows: &[(f64, f64)], base_year: i32, valuation_year: i32) -> Result<Self, ParameterError> {
+        let first_age = consecutive_ages(rows, "improvement scale")?;
+        if let Some(&(age, rate)) = rows.iter().find(|&&(_, rate)| !(rate < 1.0 && rate.is_finite())) {
+            let reason = format!("improvement at age {} must be below 1, got {}", age, rate);
+            return Err(ParameterError::InvalidMortalityTable { reason });
+        }
+        Ok(Improvement { first_age, rates: rows.iter().map(|&(_, rate)| rate).collect(), base_year, valuation_year })
+    }
+
+    /// The factor on the table's qx at an age, `years` years after the valuation year.
+    /// Ages outside the scale take the rate at its nearest end.
+    fn factor(&self, age: u32, years: u32) -> f64 {
+        let index = (age.saturating_sub(self.first_age) as usize).min(self.rates.len() - 1);
+        (1.0 - self.rates[index]).powi(self.valuation_year + years as i32 - self.base_year)
+    }
+}
+
+/// The first age of rows that must be for consecutive whole ages
+fn consecutive_ages(rows: &[(f64, f64)], name: &str) -> Result<u32, ParameterError> {
+    let invalid = |reason: String| ParameterError::InvalidMortalityTable { reason };
+    let Some(&(first_age, _)) = rows.first() else {
+        return Err(invalid(format!("the {} has no rows", name)));
+    };
+    if !(first_age >= 0.0 && first_age.fract() == 0.0) {
+        return Err(invalid(format!("ages must be whole numbers, got {}", first_age)));
+    }
+    for (row, &(age, _)) in rows.iter().enumerate() {
+        if age != first_age + row as f64 {
+            return Err(invalid(format!("ages in the {} must go up by one a row, got {} in row {}", name, age, row + 1)));
+        }
+    }
+    Ok(first_age as u32)
+}
+
+/// Probabilities of dying within a year, qx, for consecutive whole ages. A select table
+/// also has rates for the first years after selection, such as after underwriting, by
+/// age at selection; the probabilities here are for lives just selected at the age they
+/// are asked for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MortalityTable {
+    first_age: u32,
+    /// Select rates for each year since selection, by age at selection; empty rows for an
+    /// ultimate table
+    select: Vec<Vec<f64>>,
+    /// Ultimate rates, the first for `first_age` plus the select period
+    ultimate: Vec<f64>,
+    improvement: Option<Improvement>,
+}
+
+impl MortalityTable {
+    /// An ultimate table from (age, qx) rows in order of age, one for each age
+    pub fn new(rows: &[(f64, f64)]) -> Result<Self, ParameterError> {
+        let rows: Vec<Vec<f64>> = rows.iter().map(|&(age, q)| vec![age, q]).collect();
+        MortalityTable::from_rows(&rows)
+    }
+
+    /// A table from rows of age, then qx; or for a select table, age at selection, the
+    /// select rates for each year since selection, and the ultimate rate at the age the
+    /// select period ends
+    pub fn from_rows(rows: &[Vec<f64>]) -> Result<Self, ParameterError> {
+        let invalid = |reason: String| ParameterError::InvalidMortalityTable { reason };
+        let ages: Vec<(f64, f64)> = rows.iter().map(|row| (row[0], 0.0)).collect();
+        let first_age = consecutive_ages(&ages, "table")?;
+        let columns = rows[0].len();
+        if columns < 2 {
+            return Err(invalid("it needs an age column and at least one of rates".to_string()));
+        }
+        for row in rows {
+            if let Some(&q) = row[1..].iter().find(|q| !(0.0..=1.0).contains(*q)) {
+                return Err(invalid(format!("qx at age {} must be from 0 to 1, got {}", row[0], q)));
+            }
+        }
+        Ok(MortalityTable {
+            first_age,
+            select: rows.iter().map(|row| row[1..columns - 1].to_vec()).collect(),
+            ultimate: rows.iter().map(|row| row[columns - 1]).collect(),
+            improvement: None,
+        })
+    }
+
+    /// A table from a range of ages and rates, laid out as for `from_rows`, skipping
+    /// blank rows
+    pub fn from_range(range: &Variant) -> Result<Self, ParameterError> {
+        let (columns, rows) = range.dim();
+        let mut table = Vec::with_capacity(rows);
+        for row in 0..rows {
+            let cells: Vec<Variant> = (0..columns).map(|column| range.at(column, row)).collect();
+            if cells.iter().all(|cell| cell.is_missing_or_null()) {
+                continue;
+            }
+            let numbers = cells.iter().map(f64::try_from).collect::<Result<Vec<f64>, _>>();
+            table.push(numbers.map_err(|_| ParameterError::InvalidMortalityTable {
+                reason: format!("row {} is not all numbers", row + 1),
+            })?);
+        }
+        MortalityTable::from_rows(&table)
+    }
+
+    /// The same table with its rates improved from its base year
+    pub fn with_improvement(self, improvement: Improvement) -> Self {
+        MortalityTable { improvement: Some(improvement), ..self }
+    }
+
+    fn select_period(&self) -> u32 {
+        self.select[0].len() as u32
+    }
+
+    /// One past the last age in the table
+    pub fn limiting_age(&self) -> u32 {
+        self.first_age + self.select_period() + self.ultimate.len() as u32
+    }
+
+    /// qx for a life selected at `age`, `years` years on; 1 beyond the end of the table
+    pub fn q(&self, age: u32, years: u32) -> f64 {
+        let row = age.saturating_sub(self.first_age) as usize;
+        let table = if years < self.select_period() {
+            self.select.get(row).map_or(1.0, |rates| rates[years as usize])
+        } else {
+            let ultimate = (age + years).checked_sub(self.first_age + self.select_period());
+            ultimate.and_then(|index| self.ultimate.get(index as usize)).copied().unwrap_or(1.0)
+        };
+        match &self.improvement {
+            Some(improvement) if table < 1.0 => (table * improvement.factor(age + years, years)).min(1.0),
+            _ => table,
+        }
+    }
+
+    /// Checks a life can be selected at an age in the table
+    pub fn check_age(&self, age: u32) -> Result<(), ParameterError> {
+        let last_age = self.first_age + self.select.len() as u32 - 1;
+        if age < self.first_age || age > last_age {
+            return Err(ParameterError::InvalidMortalityTable {
+                reason: format!("age {} is outside the table, {} to {}", age, self.first_age, last_age),
+            });
+        }
+        Ok(())
+    }
+
+    /// The probabilities of surviving from `age` for 0, 1, 2, ... years, to the end of
+    /// the table
+    fn survival_curve(&self, age: u32) -> impl Iterator<Item = (u32, f64)> + '_ {
+        (0..=self.limiting_age().saturating_sub(age)).scan(1.0, move |alive, year| {
+            let survived = *alive;
+            *alive *= 1.0 - self.q(age, year);
+            Some((year, survived))
+        })
+    }
+
+    /// nPx, the probability that a life aged `age` survives `years` years
+    pub fn survival(&self, age: u32, years: u32) -> f64 {
+        (0..years).map(|year| 1.0 - self.q(age, year)).product()
+    }
+
+    /// Curtate life expectancy, the expected number of whole years lived
+    pub fn life_expectancy(&self, age: u32) -> f64 {
+        self.survival_curve(age).skip(1).map(|(_, alive)| alive).sum()
+    }
+
+    /// The value of 1 a year paid at the start of each year while alive, for up to `term`
+    /// years (None for life), at an annual effective interest rate
+    pub fn annuity_due(&self, age: u32, term: Option<u32>, interest: f64) -> f64 {
+        let v = 1.0 / (1.0 + interest);
+        self.survival_curve(age)
+            .take_while(|&(year, _)| term.is_none_or(|term| year < term))
+            .map(|(year, alive)| v.powi(year as i32) * alive)
+            .sum()
+    }
+
+    /// The value of 1 a year paid at the end of each year survived, for up to `term`
+    /// years (None for life)
+    pub fn annuity_immediate(&self, age: u32, term: Option<u32>, interest: f64) -> f64 {
+        let v = 1.0 / (1.0 + interest);
+        self.survival_curve(age)
+            .skip(1)
+            .take_while(|&(year, _)| term.is_none_or(|term| year <= term))
+            .map(|(year, alive)| v.powi(year as i32) * alive)
+            .sum()
+    }
+
+    /// The value of 1 paid at the end of the year of death, if within `term` years (None
+    /// for whole life)
+    pub fn assurance(&self, age: u32, term: Option<u32>, interest: f64) -> f64 {
+        let v = 1.0 / (1.0 + interest);
+        self.survival_curve(age)
+            .take_while(|&(year, _)| term.is_none_or(|term| year < term))
+            .map(|(year, alive)| v.powi(year as i32 + 1) * alive * self.q(age, year))
+            .sum()
+    }
+}
+
+/// The table a life function's first argument gives: a handle from mortality_table, or
+/// the table's range
+fn table_from(cell: &Variant) -> Result<Arc<MortalityTable>, Box<dyn std::error::Error>> {
+    if cell.dim() == (1, 1) && f64::try_from(cell).is_err() {
+        return Ok(handles::get::<MortalityTable>(&String::from(cell))?);
+    }
+    Ok(Arc::new(MortalityTable::from_range(cell)?))
+}
+
+/// Reads a whole age from a cell
+fn whole_age(age: i32) -> Result<u32, ParameterError> {
+    u32::try_from(age).map_err(|_| ParameterError::InvalidMortalityTable { reason: format!("age {} is negative", age) })
+}
+
+/// A term of whole years, 0 for life
+fn term_or_life(term: i32) -> Result<Option<u32>, Box<dyn std::error::Error>> {
+    match term {
+        0 => Ok(None),
+        term if term > 0 => Ok(Some(term as u32)),
+        _ => Err(format!("term must be 0 for life or a number of years, got {}", term).into()),
+    }
+}
+
+fn check_interest(interest: f64) -> Result<f64, ParameterError> {
+    if !(interest > -1.0 && interest.is_finite()) {
+        return Err(ParameterError::InvalidRate { parameter: "interest", value: interest });
+    }
+    Ok(interest)
+}
+
+/// Probability that a life survives a number of years, nPx
+/// * mortality_table: Handle from mortality_table, or two columns: age, and qx, the probability of dying within the year
+/// * age: Age now
+/// * years: Number of years to survive
+/// * ret: The survival probability
+#[xl_func()]
+pub fn survival_probability(mortality_table: Variant, age: i32, years: i32) -> Result<f64, Box<dyn std::error::Error>> {
+    let table = table_from(&mortality_table)?;
+    let age = whole_age(age)?;
+    table.check_age(age)?;
+    if years < 0 {
+        return Err(format!("years must not be negative, got {}", years).into());
+    }
+    Ok(table.survival(age, years as u32))
+}
+
+/// Curtate life expectancy, the expected number of whole years still to be lived
+/// * mortality_table: Handle from mortality_table, or two columns: age, and qx, the probability of dying within the year
+/// * age: Age now
+/// * ret: The curtate expectation of life
+#[xl_func()]
+pub fn life_expectancy(mortality_table: Variant, age: i32) -> Result<f64, Box<dyn std::error::Error>> {
+    let table = table_from(&mortality_table)?;
+    let age = whole_age(age)?;
+    table.check_age(age)?;
+    Ok(table.life_expectancy(age))
+}
+
+/// Value of a life annuity of 1 a year, for life or for a term
+/// * mortality_table: Handle from mortality_table, or two columns: age, and qx, the probability of dying within the year
+/// * age: Age now
+/// * term: Years the annuity is paid for at most, 0 for life
+/// * interest: Annual effective interest rate
+/// * due: TRUE for payments at the start of each year, FALSE for the end
+/// * ret: The present value
+#[xl_func()]
+pub fn life_annuity(mortality_table: Variant, age: i32, term: i32, interest: f64, due: bool) -> Result<f64, Box<dyn std::error::Error>> {
+    let table = table_from(&mortality_table)?;
+    let age = whole_age(age)?;
+    table.check_age(age)?;
+    let (term, interest) = (term_or_life(term)?, check_interest(interest)?);
+    Ok(if due { table.annuity_due(age, term, interest) } else { table.annuity_immediate(age, term, interest) })
+}
+
+/// Value of a life assurance of 1 paid at the end of the year of death, for life or for
+/// a term
+/// * mortality_table: Handle from mortality_table, or two columns: age, and qx, the probability of dying within the year
+/// * age: Age now
+/// * term: Years of cover, 0 for whole life
+/// * interest: Annual effective interest rate
+/// * ret: The present value
+#[xl_func()]
+pub fn life_assurance(mortality_table: Variant, age: i32, term: i32, interest: f64) -> Result<f64, Box<dyn std::error::Error>> {
+    let table = table_from(&mortality_table)?;
+    let age = whole_age(age)?;
+    table.check_age(age)?;
+    Ok(table.assurance(age, term_or_life(term)?, check_interest(interest)?))
+}
+
+/// Keeps a mortality table, with an improvement scale if given, and returns a handle the
+/// life functions take in place of the table's range
+/// * table: Age then qx; or for a select table, age at selection, the select rates for each year since, and the ultimate rate after
+/// * improvement: Two columns: age, and the annual rate at which mortality at that age falls; may be blank for none
+/// * base_year: Calendar year the table's rates are for
+/// * valuation_year: Calendar year the lives are valued in, from which each later year is improved further
+/// * ret: A handle to the table
+#[xl_func()]
+pub fn mortality_table(
+    table: Variant,
+    improvement: Variant,
+    base_year: i32,
+    valuation_year: i32,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut table = MortalityTable::from_range(&table)?;
+    let scale = range_pairs(&improvement, "age and improvement rate")
+        .map_err(|reason| ParameterError::InvalidMortalityTable { reason })?;
+    if !scale.is_empty() {
+        table = table.with_improvement(Improvement::new(&scale, base_year, valuation_year)?);
+    }
+    Ok(handles::insert("MortalityTable", table))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Gompertz-style mortality from 60 to 110
+    fn table() -> MortalityTable {
+        let rows: Vec<(f64, f64)> = (60..=110).map(|age| (age as f64, (0.005 * 1.1f64.powi(age - 60)).min(1.0))).collect();
+        MortalityTable::new(&rows).unwrap()
+    }
+
+    #[test]
+    fn assurance_and_annuity_satisfy_the_standard_identity() {
+        // A = 1 - d a-due, both for whole life and for the endowment over a term
+        let (table, i) = (table(), 0.04);
+        let d = i / (1.0 + i);
+        let whole_life = table.assurance(65, None, i);
+        assert!((whole_life - (1.0 - d * table.annuity_due(65, None, i))).abs() < 1e-12);
+        let endowment = table.assurance(65, Some(10), i) + table.survival(65, 10) / (1.0 + i).powi(10);
+        assert!((endowment - (1.0 - d * table.annuity_due(65, Some(10), i))).abs() < 1e-12);
+    }
+
+    #[test]
+    fn life_expectancy_is_an_annuity_without_interest() {
+        let table = table();
+        assert!((table.life_expectancy(70) - (table.annuity_due(70, None, 0.0) - 1.0)).abs() < 1e-12);
+        assert!((table.survival(60, 2) - 0.995 * (1.0 - 0.0055)).abs() < 1e-15);
+        assert_eq!(table.survival(100, 20), 0.0);
+    }
+
+    #[test]
+    fn functions_read_the_table_from_a_range() {
+        let rows: Vec<Vec<f64>> = (60..=110).map(|age| vec![age as f64, (0.005 * 1.1f64.powi(age - 60)).min(1.0)]).collect();
+        let range = Variant::from(rows);
+        let due = life_annuity(range.clone(), 65, 0, 0.04, true).unwrap();
+        let arrears = life_annuity(range.clone(), 65, 0, 0.04, false).unwrap();
+        assert!((due - 1.0 - arrears).abs() < 1e-12);
+        let term = life_annuity(range.clone(), 65, 5, 0.04, false).unwrap();
+        let expected: f64 = (1..=5).map(|year| table().survival(65, year) / 1.04f64.powi(year as i32)).sum();
+        assert!((term - expected).abs() < 1e-12);
+        assert!(life_expectancy(range.clone(), 59).is_err() && life_assurance(range, 65, -1, 0.04).is_err());
+        assert!(MortalityTable::new(&[(60.0, 0.01), (62.0, 0.02)]).is_err());
+    }
+
+    #[test]
+    fn select_rates_wear_off_into_the_ultimate() {
+        // A two-year select period: age at selection, q[x], q[x]+1, then q at x + 2
+        let rows: Vec<Vec<f64>> = (60..=100)
+            .map(|age| {
+                let ultimate = (0.005 * 1.1f64.powi(age + 2 - 60)).min(1.0);
+                vec![age as f64, 0.5 * ultimate, 0.8 * ultimate, ultimate]
+            })
+            .collect();
+        let select = MortalityTable::from_rows(&rows).unwrap();
+        let ultimate = table();
+        assert_eq!(select.q(70, 0), 0.5 * ultimate.q(72, 0));
+        assert_eq!(select.q(70, 5), ultimate.q(75, 0));
+        // Just selected, a life is healthier than one selected years ago at the same age
+        let selected_two_years_ago: f64 = (1..50).map(|year| select.survival(68, 2 + year) / select.survival(68, 2)).sum();
+        assert!(select.life_expectancy(70) > selected_two_years_ago);
+        assert!(select.assurance(70, None, 0.04) < ultimate.assurance(70, None, 0.04));
+        assert_eq!(select.limiting_age(), 103);
+    }
+
+    #[test]
+    fn improved_tables_are_kept_by_handle() {
+        let rows: Vec<Vec<f64>> = (60..=110).map(|age| vec![age as f64, (0.005 * 1.1f64.powi(age - 60)).min(1.0)]).collect();
+        let scale = Variant::from(vec![vec![60.0, 0.02], vec![61.0, 0.01]]);
+        let handle = mortality_table(Variant::from(rows.clone()), scale, 2020, 2025).unwrap();
+        let improved = handles::get::<MortalityTable>(&handle).unwrap();
+        assert!((improved.q(60, 0) - 0.005 * 0.98f64.powi(5)).abs() < 1e-15);
+        // Later ages take the last rate, improved for each further year
+        assert!((improved.q(60, 10) - table().q(70, 0) * 0.99f64.powi(15)).abs() < 1e-15);
+
+        let expectancy = life_expectancy(Variant::from(handle.as_str()), 65).unwrap();
+        assert!(expectancy > life_expectancy(Variant::from(rows), 65).unwrap());
+        assert!(life_expectancy(Variant::from("MortalityTable#0"), 65).is_err());
+    }
+}
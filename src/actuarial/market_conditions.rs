@@ -0,0 +1,246 @@
+//! Awards with market conditions, where how much vests depends on the share's total
+//! shareholder return (TSR) ranked against a peer group. IFRS 2 builds the condition
+//! into the grant-date fair value, so the award is valued by simulating the subject's
+//! and peers' returns together over the performance period.
+
+use crate::actuarial::exit_rates::ExitRates;
+use crate::actuarial::monte_carlo::{cholesky, correlate, simulate, staying_probabilities, Estimate};
+use crate::actuarial::option_pricing::{ParameterError, PositiveFloat, PositiveInt, Volatility};
+use crate::actuarial::ranges::{range_matrix, range_pairs};
+use crate::actuarial::rng::Sampling;
+use xladd_core::variant::Variant;
+use xladd_derive::xl_func;
+
+/// How much of the award vests at each TSR percentile rank: nothing below the first
+/// percentile, then interpolated linearly, and the last fraction above the last
+#[derive(Debug, Clone, PartialEq)]
+pub struct PayoutCurve(Vec<(f64, f64)>);
+
+/// The fair value of an award and how much of it is expected to vest
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TsrValuation {
+    pub fair_value: f64,
+    pub standard_error: f64,
+    pub expected_vesting: f64,
+}
+
+impl PayoutCurve {
+    /// A curve from (percentile, fraction vested) points, with percentiles from 0 to 1,
+    /// so (0.5, 0.25), (0.75, 1) vests a quarter at the median rising to all of it at the
+    /// upper quartile
+    pub fn new(points: &[(f64, f64)]) -> Result<Self, ParameterError> {
+        let invalid = |reason: String| ParameterError::InvalidPayoutCurve { reason };
+        if points.is_empty() {
+            return Err(invalid("it has no rows".to_string()));
+        }
+        let mut last_percentile = -1.0;
+        for &(percentile, fraction) in points {
+            if !(0.0..=1.0).contains(&percentile) || percentile <= last_percentile {
+                return Err(invalid(format!("percentiles must increase from 0 to 1, got {}", percentile)));
+            }
+            if !(0.0..=1.0).contains(&fraction) {
+                return Err(invalid(format!("fractions vested must be from 0 to 1, got {}", fraction)));
+            }
+            last_percentile = percentile;
+        }
+        Ok(PayoutCurve(points.to_vec()))
+    }
+
+    /// The fraction of the award that vests at a percentile rank
+    pub fn fraction(&self, percentile: f64) -> f64 {
+        let points = &self.0;
+        let after = points.partition_point(|&(point, _)| point <= percentile);
+        if after == 0 {
+            return 0.0;
+        }
+        if after == points.len() {
+            return points[after - 1].1;
+        }
+        let ((p1, f1), (p2, f2)) = (points[after - 1], points[after]);
+        f1 + (f2 - f1) * (percentile - p1) / (p2 - p1)
+    }
+}
+
+/// Values a share award that vests by relative TSR. The first volatility and the first
+/// row and column of the correlations are the subject company's, the rest its peers'.
+/// The subject's percentile is the fraction of peers whose TSR it beats. TSR includes
+/// reinvested dividends, so under the risk-neutral measure every company's grows at the
+/// risk-free rate; the subject's dividend yield only lowers the price of the shares the
+/// holder gets, so set it to 0 if the award earns dividend equivalents. Holders who leave
+/// before the end of the performance period forfeit the award, at the exit rates.
+pub fn relative_tsr_value(
+    share_price: f64,
+    div_rate: f64,
+    volatilities: &[f64],
+    correlations: &[Vec<f64>],
+    risk_free: f64,
+    performance_period: f64,
+    payout: &PayoutCurve,
+    exit_rates: &ExitRates,
+    paths: usize,
+    seed: i32,
+    sampling: Sampling,
+) -> Result<TsrValuation, ParameterError> {
+    PositiveFloat::new(share_price, "share_price")?;
+    PositiveInt::new(paths, "paths")?;
+    if !(performance_period > 0.0 && performance_period.is_finite()) {
+        return Err(ParameterError::InvalidPositiveValue { parameter: "performance_period", value: performance_period });
+    }
+    for &sigma in volatilities {
+        Volatility::new(sigma)?;
+    }
+    let companies = volatilities.len();
+    if companies < 2 {
+        return Err(ParameterError::InvalidCorrelation {
+            reason: "there must be a volatility for the company and at least one peer".to_string(),
+        });
+    }
+    if correlations.len() != companies {
+        return Err(ParameterError::InvalidCorrelation {
+            reason: format!("it has {} rows but there are {} volatilities", correlations.len(), companies),
+        });
+    }
+    let lower = cholesky(correlations)?;
+
+    // Only the random part of each log return decides the ranking; the drift at the
+    // risk-free rate is the same for everyone
+    let scale: Vec<f64> = volatilities.iter().map(|sigma| sigma * performance_period.sqrt()).collect();
+    let spread: Vec<f64> = volatilities.iter().map(|sigma| -0.5 * sigma * sigma * performance_period).collect();
+    let subject_drift = (risk_free - div_rate) * performance_period;
+    let discount = (-risk_free * performance_period).exp();
+    let staying = staying_probabilities(exit_rates, &[performance_period])[0];
+
+    let chunks = simulate(paths, seed, sampling, companies, |normals, paths| {
+        let mut independent = vec![0.0; companies];
+        let mut correlated = vec![0.0; companies];
+        let mut returns = vec![0.0; companies];
+        let (mut value, mut vesting) = (Estimate::default(), Estimate::default());
+        for _ in 0..paths {
+            normals.fill(&mut independent);
+            correlate(&lower, &independent, &mut correlated);
+            for i in 0..companies {
+                returns[i] = spread[i] + scale[i] * correlated[i];
+            }
+            let beaten = returns[1..].iter().filter(|&&peer| peer < returns[0]).count();
+            let fraction = staying * payout.fraction(beaten as f64 / (companies - 1) as f64);
+            let vested_price = share_price * (subject_drift + returns[0]).exp();
+            value.add(discount * vested_price * fraction);
+            vesting.add(fraction);
+        }
+        (value, vesting)
+    });
+    let (mut value, mut vesting) = (Estimate::default(), Estimate::default());
+    for (chunk_value, chunk_vesting) in &chunks {
+        value.merge(chunk_value);
+        vesting.merge(chunk_vesting);
+    }
+
+    Ok(TsrValuation {
+        fair_value: value.mean(),
+        standard_error: value.standard_error(),
+        expected_vesting: vesting.mean(),
+    })
+}
+
+/// Grant-date fair value per share of an award vesting by TSR rank against peers
+/// * share_price: Share price at grant date
+/// * div_rate: Dividend yield of the share, or 0 if the award earns dividend equivalents
+/// * volatilities: The company's volatility, then each peer's
+/// * correlations: Correlation matrix of the company and peers, in the same order
+/// * risk_free: Risk-free rate over the performance period
+/// * performance_period: Years over which TSR is measured
+/// * payout_curve: Two columns: percentile rank (0 to 1), and the fraction that vests
+/// * exit_rates: Annual exit rates for each year of service from grant, forfeiting the award; the last applies to every later year. 0 leaves forfeiture out, as IFRS 2 does for the fair value.
+/// * paths: Number of simulated paths
+/// * seed: Seed for the random numbers; the same seed gives the same value
+/// * sampling: Pseudo or Sobol (P or S also work); Sobol's standard error is overstated
+/// * ret: Fair value, standard error, expected vesting fraction and paths
+#[xl_func()]
+pub fn tsr_award_value(
+    share_price: f64,
+    div_rate: f64,
+    volatilities: Vec<f64>,
+    correlations: Variant,
+    risk_free: f64,
+    performance_period: f64,
+    payout_curve: Variant,
+    exit_rates: Vec<f64>,
+    paths: i32,
+    seed: i32,
+    sampling: String,
+) -> Result<Vec<(String, f64)>, Box<dyn std::error::Error>> {
+    let correlations = range_matrix(&correlations).map_err(|reason| ParameterError::InvalidCorrelation { reason })?;
+    let points = range_pairs(&payout_curve, "percentile and fraction vested")
+        .map_err(|reason| ParameterError::InvalidPayoutCurve { reason })?;
+    let valuation = relative_tsr_value(
+        share_price, div_rate, &volatilities, &correlations,
+        risk_free, performance_period,
+        &PayoutCurve::new(&points)?,
+        &ExitRates::new(&exit_rates)?,
+        paths.max(0) as usize,
+        seed,
+        Sampling::new(&sampling)?,
+    )?;
+    Ok(vec![
+        ("Fair value".to_string(), valuation.fair_value),
+        ("Standard error".to_string(), valuation.standard_error),
+        ("Expected vesting".to_string(), valuation.expected_vesting),
+        ("Paths".to_string(), paths as f64),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actuarial::option_pricing::trinomial_option_value_with_exit_rates;
+
+    fn peer_group(companies: usize, correlation: f64) -> (Vec<f64>, Vec<Vec<f64>>) {
+        let correlations = (0..companies)
+            .map(|i| (0..companies).map(|j| if i == j { 1.0 } else { correlation }).collect())
+            .collect();
+        (vec![0.3; companies], correlations)
+    }
+
+    #[test]
+    fn payout_curve_interpolates_above_the_threshold() {
+        let payout = PayoutCurve::new(&[(0.5, 0.25), (0.75, 1.0)]).unwrap();
+        assert_eq!(payout.fraction(0.49), 0.0);
+        assert_eq!(payout.fraction(0.5), 0.25);
+        assert!((payout.fraction(0.625) - 0.625).abs() < 1e-12);
+        assert_eq!(payout.fraction(0.9), 1.0);
+        assert!(PayoutCurve::new(&[(0.75, 1.0), (0.5, 0.25)]).is_err());
+    }
+
+    #[test]
+    fn award_that_always_vests_is_worth_the_share_less_dividends() {
+        let (volatilities, correlations) = peer_group(5, 0.4);
+        let payout = PayoutCurve::new(&[(0.0, 1.0)]).unwrap();
+        let valuation = relative_tsr_value(50.0, 0.03, &volatilities, &correlations, 0.04, 3.0, &payout, &ExitRates::flat(0.0), 20000, 7, Sampling::Sobol).unwrap();
+        let expected = 50.0 * (-0.03f64 * 3.0).exp();
+        assert!((valuation.fair_value - expected).abs() < 4.0 * valuation.standard_error, "{:?}", valuation);
+        assert_eq!(valuation.expected_vesting, 1.0);
+    }
+
+    #[test]
+    fn identical_peers_rank_the_company_evenly() {
+        let (volatilities, correlations) = peer_group(11, 0.5);
+        let payout = PayoutCurve::new(&[(0.0, 0.0), (1.0, 1.0)]).unwrap();
+        let valuation = relative_tsr_value(10.0, 0.0, &volatilities, &correlations, 0.03, 3.0, &payout, &ExitRates::flat(0.0), 20000, 11, Sampling::Pseudo).unwrap();
+        assert!((valuation.expected_vesting - 0.5).abs() < 0.02, "{:?}", valuation);
+        let again = relative_tsr_value(10.0, 0.0, &volatilities, &correlations, 0.03, 3.0, &payout, &ExitRates::flat(0.0), 20000, 11, Sampling::Pseudo).unwrap();
+        assert_eq!(valuation, again);
+    }
+
+    #[test]
+    fn forfeiture_matches_the_lattice() {
+        // An award that always vests is a zero-strike option exercised at vesting, so the
+        // lattice and the simulation should lose the same value to exits
+        let rates = ExitRates::new(&[0.1, 0.05, 0.02]).unwrap();
+        let (volatilities, correlations) = peer_group(5, 0.4);
+        let payout = PayoutCurve::new(&[(0.0, 1.0)]).unwrap();
+        let valuation = relative_tsr_value(50.0, 0.03, &volatilities, &correlations, 0.04, 3.0, &payout, &rates, 20000, 7, Sampling::Sobol).unwrap();
+        let lattice = trinomial_option_value_with_exit_rates(50.0, 0.0, 6.0, 3.0, 0.04, 0.3, 0.03, &rates, &ExitRates::flat(0.0), 1.0, 300).unwrap()[0];
+        assert!((valuation.fair_value - lattice).abs() < 4.0 * valuation.standard_error, "{:?} {}", valuation, lattice);
+        assert!((valuation.expected_vesting - 0.9 * 0.95 * 0.98).abs() < 1e-12);
+    }
+}
@@ -1,5 +1,28 @@
+pub mod asian;
+pub mod batch;
+pub mod calendar;
+pub mod calibration;
+pub mod cash_settled;
+pub mod convertible;
+pub mod curve;
+pub mod daycount;
+pub mod dilution;
+pub mod disclosure;
+pub mod espp;
+pub mod exit_rates;
+pub mod expense;
+pub mod life;
+pub mod market_conditions;
+pub mod monte_carlo;
 pub mod option_pricing;
+pub mod ranges;
+pub mod rate_curve;
+pub mod rng;
+pub mod sanity;
+pub mod solver;
+pub mod volatility;
 
 // Re-export commonly used functions
 pub use option_pricing::*;
-pub use option_pricing::{OptionParameters, PositiveFloat, PositiveInt, Rate, Volatility};
+pub use option_pricing::{OptionParameters, OptionType, PositiveFloat, PositiveInt, Rate, Volatility};
+pub use rate_curve::RateCurve;
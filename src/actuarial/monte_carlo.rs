@@ -0,0 +1,291 @@
+//! Shared machinery of the Monte Carlo valuations: simulating paths in parallel on the
+//! add-in's thread pool, accumulating estimates with their standard errors, control
+//! variates, and correlating the normals of a subject and its peers. The Asian and market
+//! condition valuations are built on it.
+
+use crate::actuarial::exit_rates::ExitRates;
+use crate::actuarial::option_pricing::ParameterError;
+use crate::actuarial::rng::{Normals, Sampling};
+use rayon::prelude::*;
+
+/// Paths simulated together on one thread
+const CHUNK_PATHS: usize = 4096;
+
+/// Runs `chunk` over `paths` paths split into fixed-size chunks on the add-in's thread
+/// pool, giving it each chunk's normals, `dimensions` per path, and number of paths. The
+/// results come back in chunk order, and each chunk's normals depend only on the seed and
+/// its position, so the value doesn't depend on how many threads there are.
+pub fn simulate<T: Send>(
+    paths: usize,
+    seed: i32,
+    sampling: Sampling,
+    dimensions: usize,
+    chunk: impl Fn(&mut Normals, usize) -> T + Sync,
+) -> Vec<T> {
+    let chunks = paths.div_ceil(CHUNK_PATHS);
+    xladd_core::pool::install(|| {
+        (0..chunks)
+            .into_par_iter()
+            .map(|index| {
+                let first_path = index * CHUNK_PATHS;
+                let mut normals = Normals::new(sampling, dimensions, seed, index, first_path as u64);
+                chunk(&mut normals, CHUNK_PATHS.min(paths - first_path))
+            })
+            .collect()
+    })
+}
+
+/// The probability of still being in service at each of a path's times, decremented a step
+/// at a time by the exit rate for each year of service, as in the lattices. Weighting a
+/// path's value at a time by it allows for forfeiture without simulating each exit.
+pub fn staying_probabilities(exit_rates: &ExitRates, times: &[f64]) -> Vec<f64> {
+    let mut previous = 0.0;
+    times
+        .iter()
+        .scan(1.0, |staying, &time| {
+            *staying *= exit_rates.survival(previous, time);
+            previous = time;
+            Some(*staying)
+        })
+        .collect()
+}
+
+/// Mean and standard error of a Monte Carlo estimate, accumulated a path at a time
+/// (Welford's method, so long runs don't lose precision)
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Estimate {
+    count: usize,
+    mean: f64,
+    squares: f64,
+}
+
+impl Estimate {
+    pub fn add(&mut self, sample: f64) {
+        self.count += 1;
+        let delta = sample - self.mean;
+        self.mean += delta / self.count as f64;
+        self.squares += delta * (sample - self.mean);
+    }
+
+    /// Takes in the samples of another estimate, as if they had been added here
+    pub fn merge(&mut self, other: &Estimate) {
+        if other.count == 0 {
+            return;
+        }
+        if self.count == 0 {
+            *self = *other;
+            return;
+        }
+        let count = self.count + other.count;
+        let weight = other.count as f64 / count as f64;
+        let delta = other.mean - self.mean;
+        self.mean += delta * weight;
+        self.squares += other.squares + delta * delta * self.count as f64 * weight;
+        self.count = count;
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    pub fn standard_error(&self) -> f64 {
+        if self.count < 2 {
+            return 0.0;
+        }
+        (self.squares / (self.count - 1) as f64 / self.count as f64).sqrt()
+    }
+}
+
+/// A Monte Carlo estimate that subtracts the error in a control with a known mean, such
+/// as the geometric average for an arithmetic Asian option. The control is weighted by
+/// its sample covariance with the estimate, which minimises the variance that remains.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ControlVariate {
+    count: usize,
+    mean: f64,
+    control_mean: f64,
+    squares: f64,
+    control_squares: f64,
+    products: f64,
+}
+
+impl ControlVariate {
+    pub fn add(&mut self, sample: f64, control: f64) {
+        self.count += 1;
+        let n = self.count as f64;
+        let delta = sample - self.mean;
+        let control_delta = control - self.control_mean;
+        self.mean += delta / n;
+        self.control_mean += control_delta / n;
+        self.squares += delta * (sample - self.mean);
+        self.control_squares += control_delta * (control - self.control_mean);
+        self.products += delta * (control - self.control_mean);
+    }
+
+    /// Takes in the samples of another estimate, as if they had been added here
+    pub fn merge(&mut self, other: &ControlVariate) {
+        if other.count == 0 {
+            return;
+        }
+        if self.count == 0 {
+            *self = *other;
+            return;
+        }
+        let count = self.count + other.count;
+        let weight = other.count as f64 / count as f64;
+        let pairs = self.count as f64 * weight;
+        let delta = other.mean - self.mean;
+        let control_delta = other.control_mean - self.control_mean;
+        self.mean += delta * weight;
+        self.control_mean += control_delta * weight;
+        self.squares += other.squares + delta * delta * pairs;
+        self.control_squares += other.control_squares + control_delta * control_delta * pairs;
+        self.products += other.products + delta * control_delta * pairs;
+        self.count = count;
+    }
+
+    /// The controlled mean and its standard error, given the control's true mean
+    pub fn estimate(&self, expected_control: f64) -> (f64, f64) {
+        let weight = if self.control_squares > 0.0 { self.products / self.control_squares } else { 0.0 };
+        let mean = self.mean - weight * (self.control_mean - expected_control);
+        if self.count < 2 {
+            return (mean, 0.0);
+        }
+        let residual = (self.squares - weight * self.products).max(0.0);
+        (mean, (residual / (self.count - 1) as f64 / self.count as f64).sqrt())
+    }
+}
+
+/// The lower triangular L with L L' equal to the correlation matrix, which turns
+/// independent normals into correlated ones
+pub fn cholesky(correlations: &[Vec<f64>]) -> Result<Vec<Vec<f64>>, ParameterError> {
+    let invalid = |reason: String| ParameterError::InvalidCorrelation { reason };
+    let n = correlations.len();
+    for (i, row) in correlations.iter().enumerate() {
+        if row.len() != n {
+            return Err(invalid(format!("it must be square, but row {} has {} columns", i + 1, row.len())));
+        }
+        if (row[i] - 1.0).abs() > 1e-12 {
+            return Err(invalid(format!("the diagonal must be 1, got {} in row {}", row[i], i + 1)));
+        }
+        for (j, &value) in row.iter().enumerate() {
+            if !(-1.0..=1.0).contains(&value) || (value - correlations[j][i]).abs() > 1e-12 {
+                return Err(invalid(format!("it must be symmetric and between -1 and 1 at row {}, column {}", i + 1, j + 1)));
+            }
+        }
+    }
+    let mut lower = vec![vec![0.0; n]; n];
+    for i in 0..n {
+        for j in 0..=i {
+            let sum: f64 = (0..j).map(|k| lower[i][k] * lower[j][k]).sum();
+            if i == j {
+                let pivot = correlations[i][i] - sum;
+                if pivot <= 0.0 {
+                    return Err(invalid("it is not positive definite".to_string()));
+                }
+                lower[i][j] = pivot.sqrt();
+            } else {
+                lower[i][j] = (correlations[i][j] - sum) / lower[j][j];
+            }
+        }
+    }
+    Ok(lower)
+}
+
+/// Correlated standard normals from independent ones
+pub fn correlate(lower: &[Vec<f64>], independent: &[f64], correlated: &mut [f64]) {
+    for (i, row) in lower.iter().enumerate() {
+        correlated[i] = row[..=i].iter().zip(independent).map(|(l, z)| l * z).sum();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cholesky_reproduces_the_correlations() {
+        let correlations = vec![vec![1.0, 0.6, 0.3], vec![0.6, 1.0, 0.5], vec![0.3, 0.5, 1.0]];
+        let lower = cholesky(&correlations).unwrap();
+        for i in 0..3 {
+            for j in 0..3 {
+                let product: f64 = (0..3).map(|k| lower[i][k] * lower[j][k]).sum();
+                assert!((product - correlations[i][j]).abs() < 1e-12);
+            }
+        }
+        assert!(cholesky(&[vec![1.0, 0.9], vec![0.8, 1.0]]).is_err());
+        assert!(cholesky(&[vec![1.0, 1.0], vec![1.0, 1.0]]).is_err());
+    }
+
+    #[test]
+    fn perfect_control_leaves_no_error() {
+        let mut controlled = ControlVariate::default();
+        for sample in [1.0, 2.0, 3.0, 4.0] {
+            controlled.add(2.0 * sample + 1.0, sample);
+        }
+        let (mean, standard_error) = controlled.estimate(3.0);
+        assert!((mean - 7.0).abs() < 1e-12);
+        assert!(standard_error < 1e-12);
+    }
+
+    #[test]
+    fn chunks_cover_the_paths_the_same_way_every_time() {
+        let draw = |normals: &mut Normals, paths: usize| {
+            let mut z = [0.0; 2];
+            normals.fill(&mut z);
+            (paths, z)
+        };
+        for sampling in [Sampling::Pseudo, Sampling::Sobol] {
+            let chunks = simulate(10000, 3, sampling, 2, draw);
+            assert_eq!(chunks.iter().map(|&(paths, _)| paths).collect::<Vec<_>>(), [4096, 4096, 1808]);
+            assert_eq!(chunks, simulate(10000, 3, sampling, 2, draw));
+            assert_ne!(chunks[0].1, chunks[1].1);
+            assert_ne!(chunks[0].1, simulate(10000, 4, sampling, 2, draw)[0].1);
+        }
+    }
+
+    #[test]
+    fn merged_estimates_match_one_run() {
+        let samples = [3.0, 1.0, 4.0, 1.0, 5.0, 9.0, 2.0, 6.0];
+        let (mut whole, mut first, mut second) = (Estimate::default(), Estimate::default(), Estimate::default());
+        let (mut controlled, mut left, mut right) = (ControlVariate::default(), ControlVariate::default(), ControlVariate::default());
+        for (i, &sample) in samples.iter().enumerate() {
+            whole.add(sample);
+            controlled.add(sample, sample * sample);
+            if i < 3 {
+                first.add(sample);
+                left.add(sample, sample * sample);
+            } else {
+                second.add(sample);
+                right.add(sample, sample * sample);
+            }
+        }
+        first.merge(&second);
+        left.merge(&right);
+        assert!((first.mean() - whole.mean()).abs() < 1e-12);
+        assert!((first.standard_error() - whole.standard_error()).abs() < 1e-12);
+        let (merged, expected) = (left.estimate(20.0), controlled.estimate(20.0));
+        assert!((merged.0 - expected.0).abs() < 1e-12 && (merged.1 - expected.1).abs() < 1e-12);
+    }
+
+    #[test]
+    fn staying_decrements_each_step() {
+        let rates = ExitRates::new(&[0.2, 0.1]).unwrap();
+        let staying = staying_probabilities(&rates, &[0.5, 1.0, 2.5]);
+        let expected = [0.8f64.sqrt(), 0.8, 0.8 * 0.9f64.powf(1.5)];
+        for (staying, expected) in staying.iter().zip(expected) {
+            assert!((staying - expected).abs() < 1e-15);
+        }
+    }
+
+    #[test]
+    fn estimate_matches_the_sample_statistics() {
+        let mut estimate = Estimate::default();
+        for sample in [1.0, 2.0, 3.0, 4.0] {
+            estimate.add(sample);
+        }
+        assert_eq!(estimate.mean(), 2.5);
+        // Sample variance 5/3 over 4 samples
+        assert!((estimate.standard_error() - (5.0f64 / 3.0 / 4.0).sqrt()).abs() < 1e-15);
+    }
+}
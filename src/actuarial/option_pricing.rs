@@ -1,6 +1,27 @@
 use xladd_derive::xl_func;
+use xladd_core::variant::Variant;
+use crate::actuarial::daycount::DayCount;
+use crate::actuarial::exit_rates::ExitRates;
+use crate::actuarial::rate_curve::RateCurve;
+use crate::actuarial::ranges::{range_labelled, range_pairs};
 use thiserror::Error;
 
+/// The option model's inputs by name, in the order `OptionParameters::new` and
+/// `binomial_option_value` take them
+pub const OPTION_PARAMETER_NAMES: [&str; 11] = [
+    "share_price",
+    "strike_price",
+    "time_to_maturity",
+    "vesting_period",
+    "risk_free",
+    "sigma",
+    "div_rate",
+    "exit_pre_vesting",
+    "exit_post_vesting",
+    "multiple",
+    "steps",
+];
+
 #[derive(Debug, Clone)]
 pub struct OptionParameters {
     pub share_price: PositiveFloat,
@@ -14,6 +35,29 @@ pub struct OptionParameters {
     pub exit_post_vesting: Rate,
     pub multiple: PositiveFloat,
     pub steps: PositiveInt,
+    /// A single cliff at `vesting_period` unless set with `with_vesting_schedule`
+    pub vesting_schedule: VestingSchedule,
+}
+
+/// When the options in a grant vest: each tranche is a fraction of the grant that vests
+/// at its time, and is valued as an option with that vesting period
+#[derive(Debug, Clone, PartialEq)]
+pub struct VestingSchedule(pub Vec<VestingTranche>);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VestingTranche {
+    /// Years until the tranche vests
+    pub time: f64,
+    /// Fraction of the grant in this tranche
+    pub fraction: f64,
+}
+
+/// The value of one tranche of a grant, for the tranche's fraction of it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrancheValue {
+    pub tranche: VestingTranche,
+    pub value: f64,
+    pub expected_life: f64,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
@@ -28,6 +72,32 @@ pub struct Rate(pub f64);
 #[derive(Debug, Clone)]
 pub struct Volatility(pub f64);
 
+/// Which lattice values an employee stock option
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TreeModel {
+    Binomial,
+    /// Converges faster for the same number of steps, which helps on long-dated grants
+    Trinomial,
+}
+
+/// Whether an option is a call or a put
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OptionType {
+    Call,
+    Put,
+}
+
+/// Black-Scholes sensitivities of an option's value, per unit change in each input
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Greeks {
+    pub delta: f64,
+    pub gamma: f64,
+    pub vega: f64,
+    /// Per year, so divide by 365 for the daily decay
+    pub theta: f64,
+    pub rho: f64,
+}
+
 impl OptionParameters {
     pub fn new(
         share_price: f64,
@@ -54,10 +124,164 @@ impl OptionParameters {
             exit_post_vesting: Rate::new(exit_post_vesting, "exit_post_vesting")?,
             multiple: PositiveFloat::new(multiple, "multiple")?,
             steps: PositiveInt::new(steps, "steps")?,
+            vesting_schedule: VestingSchedule::cliff(vesting_period.min(time_to_maturity)),
+        })
+    }
+
+    /// The parameters from (label, value) rows such as ("Share price", 100). Labels are
+    /// the names in `OPTION_PARAMETER_NAMES`, in any case and with spaces for underscores.
+    pub fn from_labelled(rows: &[(String, Variant)]) -> Result<Self, ParameterError> {
+        let invalid = |reason: String| ParameterError::InvalidParameterRange { reason };
+        let mut values: [Option<f64>; 11] = [None; 11];
+        for (label, value) in rows {
+            let index = parameter_index(label).ok_or_else(|| invalid(format!("{} is not an option parameter", label)))?;
+            if values[index].is_some() {
+                return Err(invalid(format!("{} is given twice", OPTION_PARAMETER_NAMES[index])));
+            }
+            let number = f64::try_from(value).map_err(|_| invalid(format!("{} is not a number", label)))?;
+            values[index] = Some(number);
+        }
+        let missing: Vec<&str> = OPTION_PARAMETER_NAMES.iter().zip(&values).filter(|(_, value)| value.is_none()).map(|(name, _)| *name).collect();
+        if !missing.is_empty() {
+            return Err(invalid(format!("it has no {}", missing.join(", "))));
+        }
+        let [share_price, strike_price, time_to_maturity, vesting_period, risk_free, sigma, div_rate, exit_pre_vesting, exit_post_vesting, multiple, steps] =
+            values.map(|value| value.unwrap_or_default());
+        OptionParameters::new(
+            share_price, strike_price, time_to_maturity, vesting_period,
+            risk_free, sigma, div_rate,
+            exit_pre_vesting, exit_post_vesting,
+            multiple,
+            steps.round().max(0.0) as usize)
+    }
+
+    /// Checks each input as `new` does, in the order of `OPTION_PARAMETER_NAMES`, without
+    /// stopping at the first that fails
+    pub fn check_each(values: [f64; 11]) -> [Result<(), ParameterError>; 11] {
+        let [share_price, strike_price, time_to_maturity, vesting_period, risk_free, sigma, div_rate, exit_pre_vesting, exit_post_vesting, multiple, steps] =
+            values;
+        let steps = if steps >= 1.0 && steps.is_finite() { steps.round() as usize } else { 0 };
+        [
+            PositiveFloat::new(share_price, "share_price").map(drop),
+            PositiveFloat::new(strike_price, "strike_price").map(drop),
+            PositiveFloat::new(time_to_maturity, "time_to_maturity").map(drop),
+            PositiveFloat::new(vesting_period, "vesting_period").map(drop),
+            Rate::new(risk_free, "risk_free").map(drop),
+            Volatility::new(sigma).map(drop),
+            Rate::new(div_rate, "div_rate").map(drop),
+            Rate::new(exit_pre_vesting, "exit_pre_vesting").map(drop),
+            Rate::new(exit_post_vesting, "exit_post_vesting").map(drop),
+            PositiveFloat::new(multiple, "multiple").map(drop),
+            PositiveInt::new(steps, "steps").map(drop),
+        ]
+    }
+
+    /// The option value and expected life from the binomial tree. With a vesting schedule
+    /// they are those of the whole grant, the total of its tranches.
+    pub fn binomial_value(&self) -> Result<Vec<f64>, Box<dyn std::error::Error>> {
+        if self.vesting_schedule != VestingSchedule::cliff(self.vesting_period.0) {
+            let total = TrancheValue::total(&self.binomial_tranche_values()?);
+            return Ok(vec![total.value, total.expected_life]);
+        }
+        binomial_option_value(
+            self.share_price.0, self.strike_price.0, self.time_to_maturity.0, self.vesting_period.0,
+            self.risk_free.0, self.sigma.0, self.div_rate.0,
+            self.exit_pre_vesting.0, self.exit_post_vesting.0,
+            self.multiple.0,
+            self.steps.0 as i32)
+    }
+
+    /// Vests the options by a schedule instead of all at `vesting_period`
+    pub fn with_vesting_schedule(mut self, vesting_schedule: VestingSchedule) -> Self {
+        self.vesting_schedule = vesting_schedule;
+        self
+    }
+
+    /// Values each tranche of the vesting schedule with the binomial tree
+    pub fn binomial_tranche_values(&self) -> Result<Vec<TrancheValue>, Box<dyn std::error::Error>> {
+        self.vesting_schedule.value_tranches(|vesting_period| {
+            binomial_option_value(
+                self.share_price.0, self.strike_price.0, self.time_to_maturity.0, vesting_period,
+                self.risk_free.0, self.sigma.0, self.div_rate.0,
+                self.exit_pre_vesting.0, self.exit_post_vesting.0,
+                self.multiple.0,
+                self.steps.0 as i32)
         })
     }
 }
 
+/// The position in `OPTION_PARAMETER_NAMES` of a label typed in a sheet
+fn parameter_index(label: &str) -> Option<usize> {
+    let label = label.trim().to_ascii_lowercase().replace([' ', '-'], "_");
+    OPTION_PARAMETER_NAMES.iter().position(|name| *name == label)
+}
+
+impl TrancheValue {
+    /// The whole grant: the tranches' fractions and values added up, with the expected
+    /// life averaged by value, vesting when the last tranche does
+    pub fn total(tranches: &[TrancheValue]) -> TrancheValue {
+        let value: f64 = tranches.iter().map(|tranche| tranche.value).sum();
+        let fraction: f64 = tranches.iter().map(|tranche| tranche.tranche.fraction).sum();
+        let expected_life = if value != 0.0 {
+            tranches.iter().map(|tranche| tranche.value * tranche.expected_life).sum::<f64>() / value
+        } else {
+            0.0
+        };
+        let time = tranches.last().map_or(0.0, |tranche| tranche.tranche.time);
+        TrancheValue { tranche: VestingTranche { time, fraction }, value, expected_life }
+    }
+}
+
+impl VestingSchedule {
+    /// All the options vesting at once
+    pub fn cliff(vesting_period: f64) -> Self {
+        VestingSchedule(vec![VestingTranche { time: vesting_period, fraction: 1.0 }])
+    }
+
+    /// A schedule from (time, fraction vested) points, the fraction being the total vested
+    /// by then, so 25% a year over four years is (1, 0.25), (2, 0.5), (3, 0.75), (4, 1)
+    pub fn new(points: &[(f64, f64)]) -> Result<Self, ParameterError> {
+        let invalid = |reason: String| ParameterError::InvalidVestingSchedule { reason };
+        if points.is_empty() {
+            return Err(invalid("it has no rows".to_string()));
+        }
+        let mut tranches = Vec::with_capacity(points.len());
+        let (mut last_time, mut vested) = (-1.0, 0.0);
+        for &(time, fraction_vested) in points {
+            if !(time >= 0.0 && time.is_finite()) || time <= last_time {
+                return Err(invalid(format!("times must be non-negative and increasing, got {}", time)));
+            }
+            if !(vested..=1.0).contains(&fraction_vested) {
+                return Err(invalid(format!(
+                    "fractions vested must increase and be at most 1, got {}", fraction_vested
+                )));
+            }
+            tranches.push(VestingTranche { time, fraction: fraction_vested - vested });
+            (last_time, vested) = (time, fraction_vested);
+        }
+        Ok(VestingSchedule(tranches))
+    }
+
+    /// Values every tranche, given the value and expected life of one option that vests
+    /// at a time
+    pub fn value_tranches(
+        &self,
+        mut option_value: impl FnMut(f64) -> Result<Vec<f64>, Box<dyn std::error::Error>>,
+    ) -> Result<Vec<TrancheValue>, Box<dyn std::error::Error>> {
+        self.0
+            .iter()
+            .map(|&tranche| {
+                let result = option_value(tranche.time)?;
+                Ok(TrancheValue {
+                    tranche,
+                    value: tranche.fraction * result[0],
+                    expected_life: result[1],
+                })
+            })
+            .collect()
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum ParameterError {
     #[error("{parameter} must be positive, got {value}")]
@@ -71,6 +295,84 @@ pub enum ParameterError {
     
     #[error("{parameter} must be between 0 and 1, got {value}")]
     InvalidRate { parameter: &'static str, value: f64 },
+
+    #[error("call_put must be Call or Put (or C or P), got {value}")]
+    InvalidOptionType { value: String },
+
+    #[error("Risk-neutral probability is {value}, outside 0 to 1; use more steps")]
+    InvalidProbability { value: f64 },
+
+    #[error("model must be Binomial or Trinomial (or B or T), got {value}")]
+    InvalidTreeModel { value: String },
+
+    #[error("Invalid vesting schedule: {reason}")]
+    InvalidVestingSchedule { reason: String },
+
+    #[error("Invalid rate curve: {reason}")]
+    InvalidRateCurve { reason: String },
+
+    #[error("Invalid correlation matrix: {reason}")]
+    InvalidCorrelation { reason: String },
+
+    #[error("Invalid payout curve: {reason}")]
+    InvalidPayoutCurve { reason: String },
+
+    #[error("sampling must be Pseudo or Sobol (or P or S), got {value}")]
+    InvalidSampling { value: String },
+
+    #[error("Invalid {name}: {reason}")]
+    InvalidSchedule { name: &'static str, reason: String },
+
+    #[error("method must be Graded or Straight-line (or G or S), got {value}")]
+    InvalidExpenseMethod { value: String },
+
+    #[error("day count must be ACT/365F, ACT/360, ACT/ACT or 30/360, got {value}")]
+    InvalidDayCount { value: String },
+
+    #[error("weekend must be day names such as Sat/Sun, or seven 0s and 1s from Monday, leaving a business day; got {value}")]
+    InvalidWeekend { value: String },
+
+    #[error("roll must be Following, Modified following, Preceding or Unadjusted (or F, MF, P or U), got {value}")]
+    InvalidRoll { value: String },
+
+    #[error("{parameter} must be a date from 0 to 2958465 (31/12/9999), got {value}")]
+    InvalidDate { parameter: &'static str, value: f64 },
+
+    #[error("interpolation must be Linear zero or Log-linear discount (or Z or D), got {value}")]
+    InvalidInterpolation { value: String },
+
+    #[error("Invalid mortality table: {reason}")]
+    InvalidMortalityTable { reason: String },
+
+    #[error("Invalid parameter range: {reason}")]
+    InvalidParameterRange { reason: String },
+
+    #[error("method must be Close-to-close, EWMA or GARCH (or C, E or G), got {value}")]
+    InvalidVolatilityMethod { value: String },
+
+    #[error("frequency must be Daily, Weekly or Monthly (or D, W or M), or the number of periods in a year; got {value}")]
+    InvalidFrequency { value: String },
+}
+
+#[derive(Error, Debug)]
+pub enum ImpliedVolatilityError {
+    #[error("Price {price} is below the option's lower no-arbitrage bound of {bound}")]
+    BelowLowerBound { price: f64, bound: f64 },
+
+    #[error("Price {price} is above the option's upper no-arbitrage bound of {bound}")]
+    AboveUpperBound { price: f64, bound: f64 },
+
+    #[error("Implied volatility did not converge in {iterations} iterations")]
+    NoConvergence { iterations: usize },
+}
+
+#[derive(Error, Debug)]
+pub enum ConvergenceError {
+    #[error("Value still changed by {change} at {steps} steps, more than the tolerance of {tolerance}; allow more steps")]
+    NotConverged { steps: usize, change: f64, tolerance: f64 },
+
+    #[error("max_steps of {max_steps} is too few to check convergence; it needs at least {minimum}")]
+    TooFewSteps { max_steps: usize, minimum: usize },
 }
 
 impl PositiveFloat {
@@ -139,6 +441,32 @@ impl Rate {
     }
 }
 
+impl OptionType {
+    /// Reads the flag as typed in a cell: Call, Put, C or P in any case
+    pub fn new(value: &str) -> Result<Self, ParameterError> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "call" | "c" => Ok(OptionType::Call),
+            "put" | "p" => Ok(OptionType::Put),
+            _ => Err(ParameterError::InvalidOptionType {
+                value: value.to_string(),
+            }),
+        }
+    }
+}
+
+impl TreeModel {
+    /// Reads the model as typed in a cell: Binomial, Trinomial, B or T in any case
+    pub fn new(value: &str) -> Result<Self, ParameterError> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "binomial" | "b" => Ok(TreeModel::Binomial),
+            "trinomial" | "t" => Ok(TreeModel::Trinomial),
+            _ => Err(ParameterError::InvalidTreeModel {
+                value: value.to_string(),
+            }),
+        }
+    }
+}
+
 impl Volatility {
     /// Creates a new Rate if the value is positive and finite
     pub fn new(value: f64) -> Result<Self, ParameterError> {
@@ -178,11 +506,7 @@ pub fn black_scholes_call_option_value(
     
     if sigma != 0.0 {
         // Standard Black-Scholes formula
-        let d1 = (share_price / strike_price).ln() 
-            + time_to_maturity * (risk_free - div_rate + 0.5 * sigma * sigma);
-        let d1 = d1 / (sigma * time_to_maturity.sqrt());
-        
-        let d2 = d1 - sigma * time_to_maturity.sqrt();
+        let (d1, d2) = black_scholes_d1_d2(share_price, strike_price, time_to_maturity, risk_free, div_rate, sigma);
         
         let call_option = share_price * (-div_rate * time_to_maturity).exp() * normal_cdf(d1)
             - (-risk_free * time_to_maturity).exp() * strike_price * normal_cdf(d2);
@@ -197,6 +521,360 @@ pub fn black_scholes_call_option_value(
     }
 }
 
+/// Black-Scholes put option value for European options, with the same arguments as
+/// `black_scholes_call_option_value`
+pub fn black_scholes_put_option_value(
+    share_price: f64,
+    strike_price: f64,
+    time_to_maturity: f64,
+    risk_free: f64,
+    div_rate: f64,
+    sigma: f64,
+) -> f64 {
+    // Handle zero strike price case
+    let strike_price = if strike_price == 0.0 { 0.001 } else { strike_price };
+
+    if sigma != 0.0 {
+        let (d1, d2) = black_scholes_d1_d2(share_price, strike_price, time_to_maturity, risk_free, div_rate, sigma);
+
+        (-risk_free * time_to_maturity).exp() * strike_price * normal_cdf(-d2)
+            - share_price * (-div_rate * time_to_maturity).exp() * normal_cdf(-d1)
+    } else {
+        // Zero volatility case - the put pays off when the discounted strike is above the
+        // discounted share price, the other way round to the call
+        let discounted_share_price = share_price * (-div_rate * time_to_maturity).exp();
+        let discounted_strike_price = strike_price * (-risk_free * time_to_maturity).exp();
+
+        (discounted_strike_price - discounted_share_price).max(0.0)
+    }
+}
+
+/// Black-Scholes value of a European call or put
+/// * share_price: Current share price
+/// * strike_price: Strike price of the option
+/// * time_to_maturity: Time to maturity in years
+/// * risk_free: Risk-free interest rate (continuously compounded)
+/// * div_rate: Dividend yield (continuously compounded)
+/// * sigma: Volatility of the share
+/// * call_put: Call or Put (C or P also work)
+/// * ret: The option value
+#[xl_func()]
+pub fn black_scholes_value(
+    share_price: f64,
+    strike_price: f64,
+    time_to_maturity: f64,
+    risk_free: f64,
+    div_rate: f64,
+    sigma: f64,
+    call_put: String,
+) -> Result<f64, Box<dyn std::error::Error>> {
+    let value = match OptionType::new(&call_put)? {
+        OptionType::Call => black_scholes_call_option_value(share_price, strike_price, time_to_maturity, risk_free, div_rate, sigma),
+        OptionType::Put => black_scholes_put_option_value(share_price, strike_price, time_to_maturity, risk_free, div_rate, sigma),
+    };
+    Ok(value)
+}
+
+impl Greeks {
+    /// Analytic Black-Scholes Greeks. They are not defined for a zero volatility or an
+    /// expired option, so both must be positive.
+    pub fn new(
+        option_type: OptionType,
+        share_price: f64,
+        strike_price: f64,
+        time_to_maturity: f64,
+        risk_free: f64,
+        div_rate: f64,
+        sigma: f64,
+    ) -> Result<Self, ParameterError> {
+        Volatility::new(sigma)?;
+        if !(time_to_maturity > 0.0 && time_to_maturity.is_finite()) {
+            return Err(ParameterError::InvalidPositiveValue {
+                parameter: "time_to_maturity",
+                value: time_to_maturity,
+            });
+        }
+        // Handle zero strike price case
+        let strike_price = if strike_price == 0.0 { 0.001 } else { strike_price };
+
+        let (d1, d2) = black_scholes_d1_d2(share_price, strike_price, time_to_maturity, risk_free, div_rate, sigma);
+        let sqrt_t = time_to_maturity.sqrt();
+        let share_discount = (-div_rate * time_to_maturity).exp();
+        let strike_discount = (-risk_free * time_to_maturity).exp();
+        let density = normal_pdf(d1);
+
+        let gamma = share_discount * density / (share_price * sigma * sqrt_t);
+        let vega = share_price * share_discount * density * sqrt_t;
+        let time_decay = -share_price * share_discount * density * sigma / (2.0 * sqrt_t);
+
+        Ok(match option_type {
+            OptionType::Call => Greeks {
+                delta: share_discount * normal_cdf(d1),
+                gamma,
+                vega,
+                theta: time_decay - risk_free * strike_price * strike_discount * normal_cdf(d2)
+                    + div_rate * share_price * share_discount * normal_cdf(d1),
+                rho: strike_price * time_to_maturity * strike_discount * normal_cdf(d2),
+            },
+            OptionType::Put => Greeks {
+                delta: -share_discount * normal_cdf(-d1),
+                gamma,
+                vega,
+                theta: time_decay + risk_free * strike_price * strike_discount * normal_cdf(-d2)
+                    - div_rate * share_price * share_discount * normal_cdf(-d1),
+                rho: -strike_price * time_to_maturity * strike_discount * normal_cdf(-d2),
+            },
+        })
+    }
+
+    /// Analytic Black-76 Greeks, for a positive volatility and time to expiry
+    pub fn black76(
+        option_type: OptionType,
+        forward: f64,
+        strike: f64,
+        vol: f64,
+        t: f64,
+        df: f64,
+    ) -> Result<Self, ParameterError> {
+        Volatility::new(vol)?;
+        if !(t > 0.0 && t.is_finite()) {
+            return Err(ParameterError::InvalidPositiveValue { parameter: "t", value: t });
+        }
+        let forward = PositiveFloat::new(forward, "forward")?.0;
+        // Handle zero strike price case
+        let strike = if strike == 0.0 { 0.001 } else { PositiveFloat::new(strike, "strike")?.0 };
+        let df = PositiveFloat::new(df, "df")?.0;
+
+        let (d1, d2) = black76_d1_d2(forward, strike, t, vol);
+        let sqrt_t = t.sqrt();
+        let density = normal_pdf(d1);
+        let rate = -df.ln() / t;
+        let (delta, value) = match option_type {
+            OptionType::Call => (df * normal_cdf(d1), df * (forward * normal_cdf(d1) - strike * normal_cdf(d2))),
+            OptionType::Put => (-df * normal_cdf(-d1), df * (strike * normal_cdf(-d2) - forward * normal_cdf(-d1))),
+        };
+
+        Ok(Greeks {
+            delta,
+            gamma: df * density / (forward * vol * sqrt_t),
+            vega: df * forward * density * sqrt_t,
+            theta: -df * forward * density * vol / (2.0 * sqrt_t) + rate * value,
+            rho: -t * value,
+        })
+    }
+
+    /// The Greeks as a table of names and values, for Excel
+    fn table(&self) -> Vec<(String, f64)> {
+        vec![
+            ("Delta".to_string(), self.delta),
+            ("Gamma".to_string(), self.gamma),
+            ("Vega".to_string(), self.vega),
+            ("Theta".to_string(), self.theta),
+            ("Rho".to_string(), self.rho),
+        ]
+    }
+}
+
+/// Black-Scholes Greeks of a European call or put, as a table of names and values. Vega
+/// and rho are per 1.00 change in volatility and rate, theta is per year.
+/// * share_price: Current share price
+/// * strike_price: Strike price of the option
+/// * time_to_maturity: Time to maturity in years
+/// * risk_free: Risk-free interest rate (continuously compounded)
+/// * div_rate: Dividend yield (continuously compounded)
+/// * sigma: Volatility of the share
+/// * call_put: Call or Put (C or P also work)
+/// * ret: Delta, gamma, vega, theta and rho, one per row with its name
+#[xl_func()]
+pub fn black_scholes_greeks(
+    share_price: f64,
+    strike_price: f64,
+    time_to_maturity: f64,
+    risk_free: f64,
+    div_rate: f64,
+    sigma: f64,
+    call_put: String,
+) -> Result<Vec<(String, f64)>, Box<dyn std::error::Error>> {
+    let option_type = OptionType::new(&call_put)?;
+    let greeks = Greeks::new(option_type, share_price, strike_price, time_to_maturity, risk_free, div_rate, sigma)?;
+    Ok(greeks.table())
+}
+
+/// Black-76 value of a European call or put on a forward or futures price
+/// * forward: Forward or futures price for the option's expiry
+/// * strike: Strike price of the option
+/// * vol: Volatility of the forward
+/// * t: Time to expiry in years
+/// * df: Discount factor from the payment date
+/// * call_put: Call or Put (C or P also work)
+/// * ret: The option value
+#[xl_func()]
+pub fn black76_value(
+    forward: f64,
+    strike: f64,
+    vol: f64,
+    t: f64,
+    df: f64,
+    call_put: String,
+) -> Result<f64, Box<dyn std::error::Error>> {
+    let option_type = OptionType::new(&call_put)?;
+    let forward = PositiveFloat::new(forward, "forward")?.0;
+    // Handle zero strike price case
+    let strike = if strike == 0.0 { 0.001 } else { PositiveFloat::new(strike, "strike")?.0 };
+    let df = PositiveFloat::new(df, "df")?.0;
+    let t = PositiveFloat::new(t, "t")?.0;
+
+    if vol != 0.0 && t > 0.0 {
+        let (d1, d2) = black76_d1_d2(forward, strike, t, vol);
+        Ok(match option_type {
+            OptionType::Call => df * (forward * normal_cdf(d1) - strike * normal_cdf(d2)),
+            OptionType::Put => df * (strike * normal_cdf(-d2) - forward * normal_cdf(-d1)),
+        })
+    } else {
+        // Zero volatility or expired - the discounted intrinsic value
+        Ok(match option_type {
+            OptionType::Call => df * (forward - strike).max(0.0),
+            OptionType::Put => df * (strike - forward).max(0.0),
+        })
+    }
+}
+
+/// Black-76 Greeks of a European call or put on a forward, as a table of names and
+/// values. Delta and gamma are to the forward. Rate moves the discount factor with the
+/// forward held, and theta holds the forward and the rate implied by the discount factor.
+/// * forward: Forward or futures price for the option's expiry
+/// * strike: Strike price of the option
+/// * vol: Volatility of the forward
+/// * t: Time to expiry in years
+/// * df: Discount factor from the payment date
+/// * call_put: Call or Put (C or P also work)
+/// * ret: Delta, gamma, vega, theta and rho, one per row with its name
+#[xl_func()]
+pub fn black76_greeks(
+    forward: f64,
+    strike: f64,
+    vol: f64,
+    t: f64,
+    df: f64,
+    call_put: String,
+) -> Result<Vec<(String, f64)>, Box<dyn std::error::Error>> {
+    let option_type = OptionType::new(&call_put)?;
+    Ok(Greeks::black76(option_type, forward, strike, vol, t, df)?.table())
+}
+
+/// Implied volatility of a European call or put from its Black-Scholes price
+/// * price: Market price of the option
+/// * spot: Current share price
+/// * strike: Strike price of the option
+/// * t: Time to maturity in years
+/// * r: Risk-free interest rate (continuously compounded)
+/// * q: Dividend yield (continuously compounded)
+/// * call_put: Call or Put (C or P also work)
+/// * ret: The volatility that gives the price
+#[xl_func()]
+pub fn implied_volatility(
+    price: f64,
+    spot: f64,
+    strike: f64,
+    t: f64,
+    r: f64,
+    q: f64,
+    call_put: String,
+) -> Result<f64, Box<dyn std::error::Error>> {
+    const MAX_ITERATIONS: usize = 100;
+    const PRICE_TOLERANCE: f64 = 1e-10;
+    const VOL_TOLERANCE: f64 = 1e-12;
+    // Anything above this is treated as no solution rather than searched for
+    const MAX_VOL: f64 = 100.0;
+
+    let option_type = OptionType::new(&call_put)?;
+    PositiveFloat::new(price, "price")?;
+    PositiveFloat::new(spot, "spot")?;
+    PositiveFloat::new(strike, "strike")?;
+    if !(t > 0.0 && t.is_finite()) {
+        return Err(ParameterError::InvalidPositiveValue { parameter: "t", value: t }.into());
+    }
+
+    // The value at zero and infinite volatility bound the prices any volatility can give
+    let discounted_spot = spot * (-q * t).exp();
+    let discounted_strike = strike * (-r * t).exp();
+    let (lower_bound, upper_bound) = match option_type {
+        OptionType::Call => ((discounted_spot - discounted_strike).max(0.0), discounted_spot),
+        OptionType::Put => ((discounted_strike - discounted_spot).max(0.0), discounted_strike),
+    };
+    if price < lower_bound - PRICE_TOLERANCE {
+        return Err(ImpliedVolatilityError::BelowLowerBound { price, bound: lower_bound }.into());
+    }
+    if price >= upper_bound {
+        return Err(ImpliedVolatilityError::AboveUpperBound { price, bound: upper_bound }.into());
+    }
+    if price <= lower_bound + PRICE_TOLERANCE {
+        return Ok(0.0);
+    }
+
+    let value = |sigma: f64| match option_type {
+        OptionType::Call => black_scholes_call_option_value(spot, strike, t, r, q, sigma),
+        OptionType::Put => black_scholes_put_option_value(spot, strike, t, r, q, sigma),
+    };
+
+    // Keep a bracket around the root, so a Newton step that leaves it can be replaced
+    // by bisection
+    let mut low = 0.0;
+    let mut high = 1.0;
+    while value(high) < price {
+        low = high;
+        high *= 2.0;
+        if high > MAX_VOL {
+            return Err(ImpliedVolatilityError::AboveUpperBound { price, bound: value(MAX_VOL) }.into());
+        }
+    }
+
+    // Brenner and Subrahmanyam's at-the-money approximation as the first guess
+    let mut sigma = ((2.0 * std::f64::consts::PI / t).sqrt() * price / spot).clamp(low + (high - low) * 0.01, high);
+    for _ in 0..MAX_ITERATIONS {
+        let difference = value(sigma) - price;
+        if difference.abs() < PRICE_TOLERANCE {
+            return Ok(sigma);
+        }
+        if difference > 0.0 {
+            high = sigma;
+        } else {
+            low = sigma;
+        }
+        if high - low < VOL_TOLERANCE {
+            return Ok(sigma);
+        }
+        let (d1, _) = black_scholes_d1_d2(spot, strike, t, r, q, sigma);
+        let vega = discounted_spot * normal_pdf(d1) * t.sqrt();
+        let newton = sigma - difference / vega;
+        sigma = if vega > 0.0 && newton > low && newton < high { newton } else { 0.5 * (low + high) };
+    }
+    Err(ImpliedVolatilityError::NoConvergence { iterations: MAX_ITERATIONS }.into())
+}
+
+/// The d1 and d2 terms of the Black-76 formula, for a non-zero volatility
+fn black76_d1_d2(forward: f64, strike: f64, t: f64, vol: f64) -> (f64, f64) {
+    let d1 = ((forward / strike).ln() + 0.5 * vol * vol * t) / (vol * t.sqrt());
+
+    (d1, d1 - vol * t.sqrt())
+}
+
+/// The d1 and d2 terms of the Black-Scholes formula, for a non-zero volatility
+fn black_scholes_d1_d2(
+    share_price: f64,
+    strike_price: f64,
+    time_to_maturity: f64,
+    risk_free: f64,
+    div_rate: f64,
+    sigma: f64,
+) -> (f64, f64) {
+    let d1 = (share_price / strike_price).ln()
+        + time_to_maturity * (risk_free - div_rate + 0.5 * sigma * sigma);
+    let d1 = d1 / (sigma * time_to_maturity.sqrt());
+
+    (d1, d1 - sigma * time_to_maturity.sqrt())
+}
+
 /// Computes the cumulative distribution function (CDF) of the standard normal distribution.
 ///
 /// Uses the Abramowitz and Stegun approximation (formula 7.1.26) for numerical accuracy.
@@ -234,6 +912,11 @@ pub fn normal_cdf(x: f64) -> f64 {
     0.5 * (1.0 + sign * y)
 }
 
+/// The density of the standard normal distribution
+pub fn normal_pdf(x: f64) -> f64 {
+    (-0.5 * x * x).exp() / (2.0 * std::f64::consts::PI).sqrt()
+}
+
 /// Computes the value of an employee stock option using a binomial tree model.
 ///
 /// The function accounts for early exercise behavior, vesting periods, and 
@@ -304,136 +987,1306 @@ pub fn binomial_option_value(
     //     steps,
     // }: OptionParameters,
 ) -> Result<Vec<f64>, Box<dyn std::error::Error>> {
+    binomial_option_value_on_curve(
+        share_price, strike_price, time_to_maturity, vesting_period,
+        &RateCurve::flat(risk_free), sigma, div_rate,
+        exit_pre_vesting, exit_post_vesting,
+        multiple,
+        steps)
+}
+
+/// `binomial_option_value` with the risk-free rate taken from a curve, each step of the
+/// tree discounting and growing at the forward rate over that step
+pub fn binomial_option_value_on_curve(
+    share_price: f64,
+    strike_price: f64,
+    time_to_maturity: f64,
+    vesting_period: f64,
+    rate_curve: &RateCurve,
+    sigma: f64,
+    div_rate: f64,
+    exit_pre_vesting: f64,
+    exit_post_vesting: f64,
+    multiple: f64,
+    steps: i32,
+) -> Result<Vec<f64>, Box<dyn std::error::Error>> {
+    capped_binomial_option_value(
+        share_price, strike_price, time_to_maturity, vesting_period,
+        rate_curve, sigma, div_rate,
+        exit_pre_vesting, exit_post_vesting,
+        multiple,
+        f64::INFINITY,
+        steps)
+}
+
+/// `binomial_option_value_on_curve` with the gain on exercise limited to `cap`
+pub fn capped_binomial_option_value(
+    share_price: f64,
+    strike_price: f64,
+    time_to_maturity: f64,
+    vesting_period: f64,
+    rate_curve: &RateCurve,
+    sigma: f64,
+    div_rate: f64,
+    exit_pre_vesting: f64,
+    exit_post_vesting: f64,
+    multiple: f64,
+    cap: f64,
+    steps: i32,
+) -> Result<Vec<f64>, Box<dyn std::error::Error>> {
+    binomial_option_value_with_exit_rates(
+        share_price, strike_price, time_to_maturity, vesting_period,
+        rate_curve, sigma, div_rate,
+        &ExitRates::flat(exit_pre_vesting), &ExitRates::flat(exit_post_vesting),
+        multiple,
+        cap,
+        steps)
+}
+
+/// `capped_binomial_option_value` with exit rates that change with years of service,
+/// each step of the tree using the rate for the year it falls in
+pub fn binomial_option_value_with_exit_rates(
+    share_price: f64,
+    strike_price: f64,
+    time_to_maturity: f64,
+    vesting_period: f64,
+    rate_curve: &RateCurve,
+    sigma: f64,
+    div_rate: f64,
+    exit_pre_vesting: &ExitRates,
+    exit_post_vesting: &ExitRates,
+    multiple: f64,
+    cap: f64,
+    steps: i32,
+) -> Result<Vec<f64>, Box<dyn std::error::Error>> {
 
     // Input validation and adjustments
-    // let steps = steps.into();
-    let steps = steps as usize;
+    let steps = PositiveInt::new(steps.max(0) as usize, "steps")?.0;
 
     let strike_price = if strike_price == 0.0 { 0.001 } else { strike_price };
     let vesting_period = vesting_period.min(time_to_maturity);
     
     // Early exit for zero maturity
     if time_to_maturity == 0.0 {
-        return Ok(vec![(share_price - strike_price).max(0.0), 0.0]);
+        return Ok(vec![(share_price - strike_price).max(0.0).min(cap), 0.0]);
     };
     
-    // European option shortcut if vesting equals maturity
-    if (vesting_period - time_to_maturity).abs() < f64::EPSILON {
-        return Ok(vec![black_scholes_call_option_value(
-                        share_price, strike_price, time_to_maturity, risk_free,
-                        div_rate, sigma,),
-                    time_to_maturity,]);
+    // European option shortcut if vesting equals maturity; with deterministic rates
+    // Black-Scholes only needs the zero rate to maturity. A capped payoff is a call
+    // spread, long at the strike and short at the strike plus the cap.
+    if !uses_tree(time_to_maturity, vesting_period) {
+        let risk_free = rate_curve.zero_rate(time_to_maturity);
+        let mut value = black_scholes_call_option_value(
+            share_price, strike_price, time_to_maturity, risk_free, div_rate, sigma);
+        if cap.is_finite() {
+            value -= black_scholes_call_option_value(
+                share_price, strike_price + cap, time_to_maturity, risk_free, div_rate, sigma);
+        }
+        return Ok(vec![value, time_to_maturity]);
     }
     
-    // Binomial tree parameters
-    let dt = time_to_maturity / steps as f64;
-    let u = (sigma * dt.sqrt()).exp();
-    let d = 1.0 / u;
-    let r = (risk_free * dt).exp();
-    
-    // Risk-neutral probability (handle zero sigma case)
-    let p = if (u - d).abs() < f64::EPSILON {
-        1.0
-    } else {
-        (((risk_free - div_rate) * dt).exp() - d) / (u - d)
-    };
-    
-    // Vesting period in discrete time steps
-    // let vest_step = ((vesting_period.into() / dt.into()) + 0.001) as usize;
-    let vest_step = ((vesting_period / dt) + 0.001) as usize;
-    
-    // Exit probabilities per time step
-    let px = (1.0 - exit_post_vesting).powf(dt);  // Prob of not exiting post-vesting
-    let qx = 1.0 - px;                            // Prob of exiting post-vesting
-    let px_pre = (1.0 - exit_pre_vesting).powf(dt); // Prob of not exiting pre-vesting
-    
-    // Pre-compute u and d powers for efficiency
-    let u_powers: Vec<f64> = (0..=steps).map(|i| u.powi(i as i32)).collect();
-    let d_powers: Vec<f64> = (0..=steps).map(|i| d.powi(i as i32)).collect();
-    
-    // Initialize matrices using flat arrays for better cache locality
-    let matrix_size = (steps + 1) * (steps + 1);
-    let mut share_price_matrix = vec![0.0; matrix_size];
-    let mut intrinsic_value = vec![0.0; matrix_size];
-    let mut option_value = vec![0.0; matrix_size];
-    let mut macaulay_denominator = vec![0.0; matrix_size];
-    let mut macaulay_numerator = vec![0.0; matrix_size];
-    
-    // Helper closure for 2D indexing into flat arrays
-    let idx = |i: usize, j: usize| i * (steps + 1) + j;
-    
-    // Calculate share prices and intrinsic values at each node
-    for i in (0..=steps).rev() {
-        for j in 0..=i {
-            share_price_matrix[idx(i, j)] = share_price * u_powers[j] * d_powers[i - j];
-            intrinsic_value[idx(i, j)] = (share_price_matrix[idx(i, j)] - strike_price).max(0.0);
+    let tree = BinomialTree::new(
+        time_to_maturity, vesting_period,
+        rate_curve, sigma, div_rate,
+        exit_pre_vesting, exit_post_vesting,
+        steps)?;
+    Ok(tree.value(share_price, strike_price, multiple, cap))
+}
+
+/// AF function to calculate value of an option from the binomial tree where the plan caps
+/// the gain on exercise. Once the cap is reached the holder gains nothing by waiting, so
+/// a cap also shortens the expected life.
+/// * share_price: Share price at grant date
+/// * strike_price: Price at which the option is exercised
+/// * time_to_maturity: Term to maturity in years
+/// * vesting_period: Term until the end of the vesting period in years
+/// * risk_free: Risk-free rate at the appropriate duration
+/// * sigma: Expected share volatility
+/// * div_rate: Dividend rate
+/// * exit_pre_vesting: Exit rate before the vesting date
+/// * exit_post_vesting: Exit rate after the vesting date
+/// * multiple: Multiple of the strike price at which the holder exercises
+/// * cap_multiple: Largest gain as a multiple of the strike price (0 for none)
+/// * cap_amount: Largest gain per option as an amount (0 for none)
+/// * steps: Number of time steps in the tree
+/// * ret: A 1 x 2 array: the option value and its expected life
+#[xl_func()]
+pub fn capped_option_value(
+    share_price: f64,
+    strike_price: f64,
+    time_to_maturity: f64,
+    vesting_period: f64,
+    risk_free: f64,
+    sigma: f64,
+    div_rate: f64,
+    exit_pre_vesting: f64,
+    exit_post_vesting: f64,
+    multiple: f64,
+    cap_multiple: f64,
+    cap_amount: f64,
+    steps: i32,
+) -> Result<Vec<f64>, Box<dyn std::error::Error>> {
+    let cap_multiple = PositiveFloat::new(cap_multiple, "cap_multiple")?.0;
+    let cap_amount = PositiveFloat::new(cap_amount, "cap_amount")?.0;
+    // The lower of the caps that are set
+    let cap = [cap_multiple * strike_price, cap_amount]
+        .into_iter()
+        .filter(|&cap| cap > 0.0)
+        .fold(f64::INFINITY, f64::min);
+    capped_binomial_option_value(
+        share_price, strike_price, time_to_maturity, vesting_period,
+        &RateCurve::flat(risk_free), sigma, div_rate,
+        exit_pre_vesting, exit_post_vesting,
+        multiple,
+        cap,
+        steps)
+}
+
+/// AF function to calculate value of an employee stock option from the binomial tree, with
+/// exit rates that change with years of service, as when staff are likelier to leave early on
+/// * share_price: Share price at grant date
+/// * strike_price: Price at which the option is exercised
+/// * time_to_maturity: Term to maturity in years
+/// * vesting_period: Term until the end of the vesting period in years
+/// * risk_free: Risk-free rate at the appropriate duration
+/// * sigma: Share volatility at the appropriate duration
+/// * div_rate: Dividend rate
+/// * exit_pre_vesting: Annual exit rates before vesting for each year of service from grant; the last applies to every later year
+/// * exit_post_vesting: Annual exit rates after vesting for each year of service from grant; the last applies to every later year
+/// * multiple: Multiple of the strike price at which the holder exercises
+/// * steps: Number of time steps in the tree
+/// * ret: A 1 x 2 array: the option value and its expected life
+#[xl_func()]
+pub fn service_exit_option_value(
+    share_price: f64,
+    strike_price: f64,
+    time_to_maturity: f64,
+    vesting_period: f64,
+    risk_free: f64,
+    sigma: f64,
+    div_rate: f64,
+    exit_pre_vesting: Vec<f64>,
+    exit_post_vesting: Vec<f64>,
+    multiple: f64,
+    steps: i32,
+) -> Result<Vec<f64>, Box<dyn std::error::Error>> {
+    binomial_option_value_with_exit_rates(
+        share_price, strike_price, time_to_maturity, vesting_period,
+        &RateCurve::flat(risk_free), sigma, div_rate,
+        &ExitRates::new(&exit_pre_vesting)?, &ExitRates::new(&exit_post_vesting)?,
+        multiple,
+        f64::INFINITY,
+        steps)
+}
+
+/// AF function to calculate value of an employee stock option from the binomial tree for
+/// holders subject to trading windows, who may not exercise during blackout periods. A
+/// holder who leaves during a blackout still exercises.
+/// * share_price: Share price at grant date
+/// * strike_price: Price at which the option is exercised
+/// * time_to_maturity: Term to maturity in years
+/// * vesting_period: Term until the end of the vesting period in years
+/// * risk_free: Risk-free rate at the appropriate duration
+/// * sigma: Share volatility at the appropriate duration
+/// * div_rate: Dividend rate
+/// * exit_pre_vesting: Exit rate before the vesting date
+/// * exit_post_vesting: Exit rate after the vesting date
+/// * multiple: Multiple of the strike price at which the holder exercises
+/// * steps: Number of time steps in the tree
+/// * blackouts: Two columns: start and end of each blackout period in years from grant, which may be blank
+/// * ret: A 1 x 2 array: the option value and its expected life
+#[xl_func()]
+pub fn blackout_option_value(
+    share_price: f64,
+    strike_price: f64,
+    time_to_maturity: f64,
+    vesting_period: f64,
+    risk_free: f64,
+    sigma: f64,
+    div_rate: f64,
+    exit_pre_vesting: f64,
+    exit_post_vesting: f64,
+    multiple: f64,
+    steps: i32,
+    blackouts: Variant,
+) -> Result<Vec<f64>, Box<dyn std::error::Error>> {
+    let invalid = |reason: String| ParameterError::InvalidSchedule { name: "blackouts", reason };
+    let windows = range_pairs(&blackouts, "start and end in years from grant").map_err(invalid)?;
+    if let Some(&(start, end)) = windows.iter().find(|&&(start, end)| start > end) {
+        return Err(invalid(format!("each blackout must end after it starts, got {} to {}", start, end)).into());
+    }
+    // Without early exercise there is nothing for a blackout to stop
+    if windows.is_empty() || !uses_tree(time_to_maturity, vesting_period) {
+        return binomial_option_value(
+            share_price, strike_price, time_to_maturity, vesting_period,
+            risk_free, sigma, div_rate,
+            exit_pre_vesting, exit_post_vesting,
+            multiple,
+            steps);
+    }
+    let tree_steps = PositiveInt::new(steps.max(0) as usize, "steps")?.0;
+    let strike_price = if strike_price == 0.0 { 0.001 } else { strike_price };
+    let tree = BinomialTree::new(
+        time_to_maturity, vesting_period,
+        &RateCurve::flat(risk_free), sigma, div_rate,
+        &ExitRates::flat(exit_pre_vesting), &ExitRates::flat(exit_post_vesting),
+        tree_steps)?
+    .with_blackouts(&windows);
+    Ok(tree.value(share_price, strike_price, multiple, f64::INFINITY))
+}
+
+/// AF function to calculate value of an employee stock option from the binomial tree, with
+/// a grant's assumptions in one labelled block instead of eleven arguments. A grant that
+/// vests in tranches is valued as graded_option_value does.
+/// * parameters: Two columns: each input of binomial_option_value by name, such as Share price or share_price, and its value; vesting_period may be left out when there is a vesting schedule
+/// * vesting_schedule: Two columns: time in years, and the total fraction vested by then, or a blank range for a single cliff at vesting_period
+/// * ret: A 1 x 2 array: the option value and its expected life
+#[xl_func()]
+pub fn option_value_from_params(parameters: Variant, vesting_schedule: Variant) -> Result<Vec<f64>, Box<dyn std::error::Error>> {
+    let invalid = |reason: String| ParameterError::InvalidParameterRange { reason };
+    let mut rows = range_labelled(&parameters).map_err(invalid)?;
+    let points = if vesting_schedule.is_missing_or_null() {
+        Vec::new()
+    } else {
+        range_pairs(&vesting_schedule, "time and fraction vested").map_err(|reason| ParameterError::InvalidVestingSchedule { reason })?
+    };
+    if points.is_empty() {
+        return OptionParameters::from_labelled(&rows)?.binomial_value();
+    }
+
+    // The grant has vested once the last tranche has
+    let schedule = VestingSchedule::new(&points)?;
+    let last_vesting = schedule.0.last().map_or(0.0, |tranche| tranche.time);
+    let vesting_period = parameter_index("vesting_period");
+    match rows.iter().find(|(label, _)| parameter_index(label) == vesting_period) {
+        None => rows.push(("vesting_period".to_string(), Variant::from(last_vesting))),
+        Some((_, value)) if f64::try_from(value).ok() != Some(last_vesting) => {
+            return Err(invalid(format!("vesting_period must be the last vesting time of the schedule, {}", last_vesting)).into());
         }
+        Some(_) => {}
     }
-    
-    // Initialize terminal conditions at maturity
-    for i in 0..=steps {
-        option_value[idx(steps, i)] = intrinsic_value[idx(steps, i)];
-        macaulay_denominator[idx(steps, i)] = intrinsic_value[idx(steps, i)];
-        macaulay_numerator[idx(steps, i)] = intrinsic_value[idx(steps, i)] * time_to_maturity;
+    OptionParameters::from_labelled(&rows)?.with_vesting_schedule(schedule).binomial_value()
+}
+
+/// AF function to check every input of binomial_option_value at once, so a whole block of
+/// assumptions can be fixed in one go instead of one error at a time
+/// * share_price: Current share price
+/// * strike_price: Strike price of the option
+/// * time_to_maturity: Time to maturity in years
+/// * vesting_period: Vesting period in years
+/// * risk_free: Risk-free interest rate
+/// * sigma: Volatility
+/// * div_rate: Dividend yield
+/// * exit_pre_vesting: Annual rate of employee exits before vesting
+/// * exit_post_vesting: Annual rate of employee exits after vesting
+/// * multiple: Multiple of the strike price at which employees exercise early
+/// * steps: Number of time steps in the binomial tree
+/// * ret: A row per parameter: its name, its value, and OK or what is wrong with it
+#[xl_func()]
+pub fn validate_option_params(
+    share_price: f64,
+    strike_price: f64,
+    time_to_maturity: f64,
+    vesting_period: f64,
+    risk_free: f64,
+    sigma: f64,
+    div_rate: f64,
+    exit_pre_vesting: f64,
+    exit_post_vesting: f64,
+    multiple: f64,
+    steps: f64,
+) -> Result<Vec<Vec<Variant>>, Box<dyn std::error::Error>> {
+    let values = [
+        share_price, strike_price, time_to_maturity, vesting_period,
+        risk_free, sigma, div_rate,
+        exit_pre_vesting, exit_post_vesting,
+        multiple,
+        steps,
+    ];
+    let mut table = vec![["Parameter", "Value", "Check"].map(Variant::from).to_vec()];
+    for ((name, value), check) in OPTION_PARAMETER_NAMES.iter().zip(values).zip(OptionParameters::check_each(values)) {
+        let message = check.map_or_else(|error| error.to_string(), |()| "OK".to_string());
+        table.push(vec![Variant::from(*name), Variant::from(value), Variant::from(message.as_str())]);
+    }
+    Ok(table)
+}
+
+/// Steps in the coarsest tree of `converged_option_value`
+const CONVERGENCE_START_STEPS: usize = 25;
+
+/// Fewest steps `converged_option_value` can check convergence with: two extrapolated
+/// values, the finer averaging trees of 4N and 4N + 1 steps
+const CONVERGENCE_MIN_STEPS: usize = 4 * CONVERGENCE_START_STEPS + 1;
+
+/// AF function to calculate value of an option from the binomial tree, adding steps until
+/// the value settles instead of taking a step count. A tree's value zigzags as the step
+/// count goes from odd to even, so each level averages N and N + 1 steps; doubling N then
+/// roughly halves the error, which Richardson extrapolation takes out. The steps double
+/// until two extrapolated values are within the tolerance. An exercise multiple acts as a
+/// barrier whose place between the nodes moves with the step count, so those values
+/// settle more slowly and erratically and want a looser tolerance.
+/// * share_price: Share price at grant date
+/// * strike_price: Price at which the option is exercised
+/// * time_to_maturity: Term to maturity in years
+/// * vesting_period: Term until the end of the vesting period in years
+/// * risk_free: Risk-free rate at the appropriate duration
+/// * sigma: Expected share volatility
+/// * div_rate: Dividend rate
+/// * exit_pre_vesting: Exit rate before the vesting date
+/// * exit_post_vesting: Exit rate after the vesting date
+/// * multiple: Multiple of the strike price at which the holder exercises
+/// * tolerance: Largest change in value accepted as converged, such as 0.001
+/// * max_steps: Most steps to try before giving up, at least 101
+/// * ret: The converged value, expected life and the steps in the finest tree
+#[xl_func()]
+pub fn converged_option_value(
+    share_price: f64,
+    strike_price: f64,
+    time_to_maturity: f64,
+    vesting_period: f64,
+    risk_free: f64,
+    sigma: f64,
+    div_rate: f64,
+    exit_pre_vesting: f64,
+    exit_post_vesting: f64,
+    multiple: f64,
+    tolerance: f64,
+    max_steps: i32,
+) -> Result<Vec<(String, f64)>, Box<dyn std::error::Error>> {
+    if !(tolerance > 0.0 && tolerance.is_finite()) {
+        return Err(ParameterError::InvalidPositiveValue { parameter: "tolerance", value: tolerance }.into());
+    }
+    let max_steps = PositiveInt::new(max_steps.max(0) as usize, "max_steps")?.0;
+    if max_steps < CONVERGENCE_MIN_STEPS {
+        return Err(ConvergenceError::TooFewSteps { max_steps, minimum: CONVERGENCE_MIN_STEPS }.into());
+    }
+    let value = |steps: usize| {
+        binomial_option_value(
+            share_price, strike_price, time_to_maturity, vesting_period,
+            risk_free, sigma, div_rate,
+            exit_pre_vesting, exit_post_vesting,
+            multiple,
+            steps as i32)
+    };
+    let table = |result: &[f64], steps: usize| {
+        vec![
+            ("Value".to_string(), result[0]),
+            ("Expected life".to_string(), result[1]),
+            ("Steps".to_string(), steps as f64),
+        ]
+    };
+    if !uses_tree(time_to_maturity, vesting_period) {
+        return Ok(table(&value(1)?, 0));
+    }
+
+    let averaged = |steps: usize| -> Result<Vec<f64>, Box<dyn std::error::Error>> {
+        let (odd, even) = (value(steps)?, value(steps + 1)?);
+        Ok(odd.iter().zip(&even).map(|(a, b)| 0.5 * (a + b)).collect())
+    };
+    let mut steps = CONVERGENCE_START_STEPS;
+    let mut coarse = averaged(steps)?;
+    let mut last: Option<Vec<f64>> = None;
+    let mut change = f64::INFINITY;
+    while 2 * steps < max_steps {
+        steps *= 2;
+        let fine = averaged(steps)?;
+        let extrapolated: Vec<f64> = fine.iter().zip(&coarse).map(|(f, c)| 2.0 * f - c).collect();
+        if let Some(last) = &last {
+            change = (extrapolated[0] - last[0]).abs();
+            if change < tolerance {
+                return Ok(table(&extrapolated, steps + 1));
+            }
+        }
+        last = Some(extrapolated);
+        coarse = fine;
     }
+    Err(ConvergenceError::NotConverged { steps: steps + 1, change, tolerance }.into())
+}
+
+/// Binomial values of one grant over a grid of share prices (rows) and volatilities
+/// (columns). The tree's moves, rates and exit probabilities for each volatility are
+/// worked out once and shared by every share price.
+pub fn binomial_value_grid(
+    share_prices: &[f64],
+    sigmas: &[f64],
+    strike_price: f64,
+    time_to_maturity: f64,
+    vesting_period: f64,
+    rate_curve: &RateCurve,
+    div_rate: f64,
+    exit_pre_vesting: f64,
+    exit_post_vesting: f64,
+    multiple: f64,
+    steps: i32,
+) -> Result<Vec<Vec<f64>>, Box<dyn std::error::Error>> {
+    let tree_steps = PositiveInt::new(steps.max(0) as usize, "steps")?.0;
+    let mut grid = vec![Vec::with_capacity(sigmas.len()); share_prices.len()];
+    for &sigma in sigmas {
+        if uses_tree(time_to_maturity, vesting_period) {
+            let tree = BinomialTree::new(
+                time_to_maturity, vesting_period,
+                rate_curve, sigma, div_rate,
+                &ExitRates::flat(exit_pre_vesting), &ExitRates::flat(exit_post_vesting),
+                tree_steps)?;
+            let strike_price = if strike_price == 0.0 { 0.001 } else { strike_price };
+            for (row, &share_price) in grid.iter_mut().zip(share_prices) {
+                row.push(tree.value(share_price, strike_price, multiple, f64::INFINITY)[0]);
+            }
+        } else {
+            for (row, &share_price) in grid.iter_mut().zip(share_prices) {
+                let value = binomial_option_value_on_curve(
+                    share_price, strike_price, time_to_maturity, vesting_period,
+                    rate_curve, sigma, div_rate,
+                    exit_pre_vesting, exit_post_vesting,
+                    multiple,
+                    steps)?;
+                row.push(value[0]);
+            }
+        }
+    }
+    Ok(grid)
+}
+
+/// Grid of option values, one row per share price and one column per volatility, in place
+/// of a data table that rebuilds the tree for every cell
+/// * share_prices: Share prices, one per row of the result
+/// * volatilities: Volatilities, one per column of the result
+/// * strike_price: Price at which the option is exercised
+/// * time_to_maturity: Term to maturity in years
+/// * vesting_period: Term until the end of the vesting period in years
+/// * risk_free: Risk-free rate at the appropriate duration
+/// * div_rate: Dividend rate
+/// * exit_pre_vesting: Exit rate before the vesting date
+/// * exit_post_vesting: Exit rate after the vesting date
+/// * multiple: Multiple of the strike price at which the holder exercises
+/// * steps: Number of time steps in the tree
+/// * ret: The option values
+#[xl_func()]
+pub fn option_value_grid(
+    share_prices: Vec<f64>,
+    volatilities: Vec<f64>,
+    strike_price: f64,
+    time_to_maturity: f64,
+    vesting_period: f64,
+    risk_free: f64,
+    div_rate: f64,
+    exit_pre_vesting: f64,
+    exit_post_vesting: f64,
+    multiple: f64,
+    steps: i32,
+) -> Result<Vec<Vec<f64>>, Box<dyn std::error::Error>> {
+    binomial_value_grid(
+        &share_prices, &volatilities,
+        strike_price, time_to_maturity, vesting_period,
+        &RateCurve::flat(risk_free), div_rate,
+        exit_pre_vesting, exit_post_vesting,
+        multiple,
+        steps)
+}
+
+/// Whether an option needs the tree at all: one that has expired, or that can only be
+/// exercised at maturity, has a closed form
+fn uses_tree(time_to_maturity: f64, vesting_period: f64) -> bool {
+    time_to_maturity != 0.0 && (vesting_period.min(time_to_maturity) - time_to_maturity).abs() >= f64::EPSILON
+}
+
+/// The parts of the binomial tree that don't depend on the share price or strike, so
+/// that one tree can value a range of share prices
+struct BinomialTree {
+    steps: usize,
+    dt: f64,
+    time_to_maturity: f64,
+    step_rates: Vec<(f64, f64)>,
+    vest_step: usize,
+    /// Probability of not exiting over each step, before and after vesting
+    survival_pre: Vec<f64>,
+    survival_post: Vec<f64>,
+    /// Whether the holder may choose to exercise after each step, false in a blackout
+    exercisable: Vec<bool>,
+    u_powers: Vec<f64>,
+    d_powers: Vec<f64>,
+}
+
+impl BinomialTree {
+    fn new(
+        time_to_maturity: f64,
+        vesting_period: f64,
+        rate_curve: &RateCurve,
+        sigma: f64,
+        div_rate: f64,
+        exit_pre_vesting: &ExitRates,
+        exit_post_vesting: &ExitRates,
+        steps: usize,
+    ) -> Result<Self, ParameterError> {
+        let vesting_period = vesting_period.min(time_to_maturity);
+
+        // Binomial tree parameters
+        let dt = time_to_maturity / steps as f64;
+        let u = (sigma * dt.sqrt()).exp();
+        let d = 1.0 / u;
+    
+        // One-period growth factor and risk-neutral probability (handle zero sigma case)
+        // for each step, from the forward rate over it
+        let step_rates: Vec<(f64, f64)> = (0..steps)
+            .map(|i| {
+                let risk_free = rate_curve.forward_rate(i as f64 * dt, (i + 1) as f64 * dt);
+                let p = if (u - d).abs() < f64::EPSILON {
+                    1.0
+                } else {
+                    (((risk_free - div_rate) * dt).exp() - d) / (u - d)
+                };
+                if !(0.0..=1.0).contains(&p) {
+                    return Err(ParameterError::InvalidProbability { value: p });
+                }
+                Ok(((risk_free * dt).exp(), p))
+            })
+            .collect::<Result<_, _>>()?;
+    
+        // Vesting period in discrete time steps
+        // let vest_step = ((vesting_period.into() / dt.into()) + 0.001) as usize;
+        let vest_step = ((vesting_period / dt) + 0.001) as usize;
+    
+        // Exit probabilities per time step, from the rate for the year of service
+        let survival_pre: Vec<f64> = (0..steps).map(|i| exit_pre_vesting.survival(i as f64 * dt, (i + 1) as f64 * dt)).collect();
+        let survival_post: Vec<f64> = (0..steps).map(|i| exit_post_vesting.survival(i as f64 * dt, (i + 1) as f64 * dt)).collect();
     
-    // Backward induction through the binomial tree
+        // Pre-compute u and d powers for efficiency
+        let u_powers: Vec<f64> = (0..=steps).map(|i| u.powi(i as i32)).collect();
+        let d_powers: Vec<f64> = (0..=steps).map(|i| d.powi(i as i32)).collect();
+
+        let exercisable = vec![true; steps + 1];
+        Ok(BinomialTree { steps, dt, time_to_maturity, step_rates, vest_step, survival_pre, survival_post, exercisable, u_powers, d_powers })
+    }
+
+    /// Stops voluntary exercise at steps in any of the (start, end) windows, in years from
+    /// grant. A holder who leaves during a window still exercises.
+    fn with_blackouts(mut self, windows: &[(f64, f64)]) -> Self {
+        for (i, exercisable) in self.exercisable.iter_mut().enumerate() {
+            let time = i as f64 * self.dt;
+            *exercisable = !windows.iter().any(|&(start, end)| start <= time && time <= end);
+        }
+        self
+    }
+
+    /// The option value and expected life for one share price and strike, the gain on
+    /// exercise being at most `cap`
+    fn value(&self, share_price: f64, strike_price: f64, multiple: f64, cap: f64) -> Vec<f64> {
+        let root = self.roll_back(share_price, strike_price, multiple, cap, |_, _| {});
+        vec![root.values[0], root.expected_life()]
+    }
+
+    /// Values every node of the tree, keeping them all. Only for trees small enough to lay
+    /// out, as it takes memory in the square of the steps.
+    fn lattice(&self, share_price: f64, strike_price: f64, multiple: f64, cap: f64) -> Lattice {
+        let width = self.steps + 1;
+        let mut share_prices = vec![0.0; width * width];
+        let mut option_values = vec![0.0; width * width];
+        self.roll_back(share_price, strike_price, multiple, cap, |i, row| {
+            for j in 0..=i {
+                share_prices[i * width + j] = self.share_price_at(share_price, i, j);
+                option_values[i * width + j] = row.values[j];
+            }
+        });
+        Lattice { share_prices, option_values }
+    }
+
+    /// The share price after i steps with j up moves
+    fn share_price_at(&self, share_price: f64, i: usize, j: usize) -> f64 {
+        share_price * self.u_powers[j] * self.d_powers[i - j]
+    }
+
+    /// Backward induction from maturity, keeping only the step being valued and the one
+    /// after it, so memory grows with the steps rather than their square. Each step's row
+    /// is shown to `visit` once valued, from maturity back; the row at grant is returned.
+    fn roll_back(&self, share_price: f64, strike_price: f64, multiple: f64, cap: f64, mut visit: impl FnMut(usize, &Row)) -> Row {
+        let BinomialTree { steps, dt, time_to_maturity, ref step_rates, vest_step, ref survival_pre, ref survival_post, ref exercisable, .. } = *self;
+        let intrinsic_value = |i: usize, j: usize| (self.share_price_at(share_price, i, j) - strike_price).max(0.0).min(cap);
+
+        // Terminal conditions at maturity
+        let mut next = Row::new(steps);
+        for j in 0..=steps {
+            let intrinsic = intrinsic_value(steps, j);
+            next.values[j] = intrinsic;
+            next.denominators[j] = intrinsic;
+            next.numerators[j] = intrinsic * time_to_maturity;
+        }
+        visit(steps, &next);
+
+        let mut row = Row::new(steps);
+        for i in (0..steps).rev() {
+            let (r, p) = step_rates[i];
+            let (px_pre, px) = (survival_pre[i], survival_post[i]);
+            let qx = 1.0 - px;
+            for j in 0..=i {
+                let pv_option_one_period = (p * next.values[j + 1] + (1.0 - p) * next.values[j]) / r;
+                let continuing_denominator = p * next.denominators[j + 1] + (1.0 - p) * next.denominators[j];
+                let continuing_numerator = p * next.numerators[j + 1] + (1.0 - p) * next.numerators[j];
+
+                if i >= vest_step {
+                    // Post-vesting period: optimal exercise or multiple trigger, outside blackouts
+                    let intrinsic = intrinsic_value(i, j);
+                    let should_exercise = exercisable[i]
+                        && (intrinsic > pv_option_one_period
+                            || self.share_price_at(share_price, i, j) >= strike_price * multiple);
+
+                    if should_exercise {
+                        row.values[j] = intrinsic;
+                        row.denominators[j] = intrinsic;
+                        row.numerators[j] = intrinsic * (i as f64) * dt;
+                    } else {
+                        row.values[j] = px * pv_option_one_period + qx * intrinsic;
+                        row.denominators[j] = px * continuing_denominator + (1.0 - px) * intrinsic;
+                        row.numerators[j] = px * continuing_numerator + (1.0 - px) * intrinsic * (i as f64) * dt;
+                    }
+                } else {
+                    // Pre-vesting period: cannot exercise, only exit rate applies
+                    row.values[j] = px_pre * pv_option_one_period;
+                    row.denominators[j] = continuing_denominator;
+                    row.numerators[j] = continuing_numerator;
+                }
+            }
+            visit(i, &row);
+            std::mem::swap(&mut row, &mut next);
+        }
+        next
+    }
+}
+
+/// The nodes of one step of the tree, by number of up moves: the option value, and the
+/// sums behind the expected life (the Macaulay duration of the exercise payoffs)
+struct Row {
+    values: Vec<f64>,
+    numerators: Vec<f64>,
+    denominators: Vec<f64>,
+}
+
+impl Row {
+    fn new(steps: usize) -> Self {
+        Row { values: vec![0.0; steps + 1], numerators: vec![0.0; steps + 1], denominators: vec![0.0; steps + 1] }
+    }
+
+    /// The expected life at the first node
+    fn expected_life(&self) -> f64 {
+        if self.denominators[0] != 0.0 { self.numerators[0] / self.denominators[0] } else { 0.0 }
+    }
+}
+
+/// Every node of a valued tree, the node after i steps with j up moves at i * (steps + 1) + j
+struct Lattice {
+    share_prices: Vec<f64>,
+    option_values: Vec<f64>,
+}
+
+/// AF function to calculate value of an option from the binomial tree along with the
+/// parameters the tree is built from, so a reviewer can check the model's setup. With a
+/// flat rate every step has the same growth and probability. When vesting is at
+/// maturity the value is the Black-Scholes one and the tree is shown for reference only.
+/// * share_price: Share price at grant date
+/// * strike_price: Price at which the option is exercised
+/// * time_to_maturity: Term to maturity in years
+/// * vesting_period: Term until the end of the vesting period in years
+/// * risk_free: Risk-free rate at the appropriate duration
+/// * sigma: Expected share volatility
+/// * div_rate: Dividend rate
+/// * exit_pre_vesting: Exit rate before the vesting date
+/// * exit_post_vesting: Exit rate after the vesting date
+/// * multiple: Multiple of the strike price at which the holder exercises
+/// * steps: Number of time steps in the tree
+/// * ret: The value and expected life, then dt, u, d, p and the other model parameters
+#[xl_func()]
+pub fn binomial_audit(
+    share_price: f64,
+    strike_price: f64,
+    time_to_maturity: f64,
+    vesting_period: f64,
+    risk_free: f64,
+    sigma: f64,
+    div_rate: f64,
+    exit_pre_vesting: f64,
+    exit_post_vesting: f64,
+    multiple: f64,
+    steps: i32,
+) -> Result<Vec<(String, f64)>, Box<dyn std::error::Error>> {
+    let tree_steps = PositiveInt::new(steps.max(0) as usize, "steps")?.0;
+    if !(time_to_maturity > 0.0 && time_to_maturity.is_finite()) {
+        return Err(ParameterError::InvalidPositiveValue { parameter: "time_to_maturity", value: time_to_maturity }.into());
+    }
+    let result = binomial_option_value(
+        share_price, strike_price, time_to_maturity, vesting_period,
+        risk_free, sigma, div_rate,
+        exit_pre_vesting, exit_post_vesting,
+        multiple,
+        steps)?;
+    let tree = BinomialTree::new(
+        time_to_maturity, vesting_period,
+        &RateCurve::flat(risk_free), sigma, div_rate,
+        &ExitRates::flat(exit_pre_vesting), &ExitRates::flat(exit_post_vesting),
+        tree_steps)?;
+    let (growth, p) = tree.step_rates[0];
+    let strike_price = if strike_price == 0.0 { 0.001 } else { strike_price };
+    Ok(vec![
+        ("Value".to_string(), result[0]),
+        ("Expected life".to_string(), result[1]),
+        ("Closed form".to_string(), if uses_tree(time_to_maturity, vesting_period) { 0.0 } else { 1.0 }),
+        ("Steps".to_string(), tree_steps as f64),
+        ("dt".to_string(), tree.dt),
+        ("u".to_string(), tree.u_powers[1]),
+        ("d".to_string(), tree.d_powers[1]),
+        ("p".to_string(), p),
+        ("Growth per step".to_string(), growth),
+        ("Vest step".to_string(), tree.vest_step as f64),
+        ("Vest time".to_string(), tree.vest_step as f64 * tree.dt),
+        ("Survival per step pre-vesting".to_string(), tree.survival_pre[0]),
+        ("Survival per step post-vesting".to_string(), tree.survival_post[tree.vest_step.min(tree_steps - 1)]),
+        ("Exercise price trigger".to_string(), strike_price * multiple),
+    ])
+}
+
+/// Most steps `binomial_tree` will lay out, keeping the output to a readable size
+const MAX_AUDIT_STEPS: usize = 250;
+
+/// AF function laying out the binomial tree node by node for audit. Columns are time
+/// steps and rows count the down moves, so the top row is the highest share price;
+/// cells with no node are blank.
+/// * share_price: Share price at grant date
+/// * strike_price: Price at which the option is exercised
+/// * time_to_maturity: Term to maturity in years
+/// * vesting_period: Term until the end of the vesting period in years
+/// * risk_free: Risk-free rate at the appropriate duration
+/// * sigma: Expected share volatility
+/// * div_rate: Dividend rate
+/// * exit_pre_vesting: Exit rate before the vesting date
+/// * exit_post_vesting: Exit rate after the vesting date
+/// * multiple: Multiple of the strike price at which the holder exercises
+/// * output: Share price, Option value or Both (S, V or B also work)
+/// * steps: Number of time steps in the tree, at most 250
+/// * ret: A row of times, then the share price and/or option value lattices
+#[xl_func()]
+pub fn binomial_tree(
+    share_price: f64,
+    strike_price: f64,
+    time_to_maturity: f64,
+    vesting_period: f64,
+    risk_free: f64,
+    sigma: f64,
+    div_rate: f64,
+    exit_pre_vesting: f64,
+    exit_post_vesting: f64,
+    multiple: f64,
+    output: String,
+    steps: i32,
+) -> Result<Vec<Vec<Variant>>, Box<dyn std::error::Error>> {
+    let (share_prices, option_values) = match output.trim().to_ascii_lowercase().as_str() {
+        "share price" | "s" => (true, false),
+        "option value" | "v" => (false, true),
+        "both" | "b" => (true, true),
+        _ => return Err(format!("output must be Share price, Option value or Both (or S, V or B), got {}", output).into()),
+    };
+    let steps = PositiveInt::new(steps.max(0) as usize, "steps")?.0;
+    if steps > MAX_AUDIT_STEPS {
+        return Err(format!("steps must be at most {} to lay out the tree, got {}", MAX_AUDIT_STEPS, steps).into());
+    }
+    let time_to_maturity = PositiveFloat::new(time_to_maturity, "time_to_maturity")?.0;
+    if time_to_maturity == 0.0 {
+        return Err(ParameterError::InvalidPositiveValue { parameter: "time_to_maturity", value: time_to_maturity }.into());
+    }
+    let strike_price = if strike_price == 0.0 { 0.001 } else { strike_price };
+
+    let tree = BinomialTree::new(
+        time_to_maturity, vesting_period,
+        &RateCurve::flat(risk_free), sigma, div_rate,
+        &ExitRates::flat(exit_pre_vesting), &ExitRates::flat(exit_post_vesting),
+        steps)?;
+    let lattice = tree.lattice(share_price, strike_price, multiple, f64::INFINITY);
+
+    let mut table = vec![
+        std::iter::once(Variant::from("Time"))
+            .chain((0..=steps).map(|i| Variant::from(i as f64 * tree.dt)))
+            .collect::<Vec<_>>(),
+    ];
+    let mut lay_out = |label: &str, nodes: &[f64]| {
+        for down in 0..=steps {
+            let mut row = vec![Variant::from(if down == 0 { label } else { "" })];
+            row.extend((0..=steps).map(|i| {
+                if down <= i { Variant::from(nodes[i * (steps + 1) + i - down]) } else { Variant::from("") }
+            }));
+            table.push(row);
+        }
+    };
+    if share_prices {
+        lay_out("Share price", &lattice.share_prices);
+    }
+    if option_values {
+        lay_out("Option value", &lattice.option_values);
+    }
+    Ok(table)
+}
+
+/// Computes the value of an employee stock option using a trinomial tree, with the same
+/// vesting, exit and exercise multiple rules as `binomial_option_value`. The share
+/// moves up, down or not at all each step, with a log spacing of sigma * sqrt(3 dt), so
+/// the volatility must be positive.
+///
+/// # Parameters
+///
+/// * `share_price`: Current price of the underlying share
+/// * `strike_price`: Strike (exercise) price of the option
+/// * `time_to_maturity`: Total time to maturity of the option, in years
+/// * `vesting_period`: Vesting period during which the option cannot be exercised, in years
+/// * `risk_free`: Annualized risk-free interest rate
+/// * `sigma`: Volatility of the underlying share
+/// * `div_rate`: Dividend yield of the share
+/// * `exit_pre_vesting`: Annualized probability of employee exit before vesting
+/// * `exit_post_vesting`: Annualized probability of employee exit after vesting
+/// * `multiple`: Multiple of the strike price at which the holder exercises
+/// * `steps`: Number of time steps in the trinomial tree
+///
+/// # Returns
+///
+/// The present value and Macaulay duration (expected life) of the option
+#[xl_func()]
+pub fn trinomial_option_value(
+    share_price: f64,
+    strike_price: f64,
+    time_to_maturity: f64,
+    vesting_period: f64,
+    risk_free: f64,
+    sigma: f64,
+    div_rate: f64,
+    exit_pre_vesting: f64,
+    exit_post_vesting: f64,
+    multiple: f64,
+    steps: i32,
+) -> Result<Vec<f64>, Box<dyn std::error::Error>> {
+    trinomial_option_value_with_exit_rates(
+        share_price, strike_price, time_to_maturity, vesting_period,
+        risk_free, sigma, div_rate,
+        &ExitRates::flat(exit_pre_vesting), &ExitRates::flat(exit_post_vesting),
+        multiple,
+        steps)
+}
+
+/// AF function to calculate value of an employee stock option from the trinomial tree, with
+/// exit rates that change with years of service, as when staff are likelier to leave early on
+/// * share_price: Share price at grant date
+/// * strike_price: Price at which the option is exercised
+/// * time_to_maturity: Term to maturity in years
+/// * vesting_period: Term until the end of the vesting period in years
+/// * risk_free: Risk-free rate at the appropriate duration
+/// * sigma: Share volatility at the appropriate duration
+/// * div_rate: Dividend rate
+/// * exit_pre_vesting: Annual exit rates before vesting for each year of service from grant; the last applies to every later year
+/// * exit_post_vesting: Annual exit rates after vesting for each year of service from grant; the last applies to every later year
+/// * multiple: Multiple of the strike price at which the holder exercises
+/// * steps: Number of time steps in the tree
+/// * ret: A 1 x 2 array: the option value and its expected life
+#[xl_func()]
+pub fn service_exit_trinomial_value(
+    share_price: f64,
+    strike_price: f64,
+    time_to_maturity: f64,
+    vesting_period: f64,
+    risk_free: f64,
+    sigma: f64,
+    div_rate: f64,
+    exit_pre_vesting: Vec<f64>,
+    exit_post_vesting: Vec<f64>,
+    multiple: f64,
+    steps: i32,
+) -> Result<Vec<f64>, Box<dyn std::error::Error>> {
+    trinomial_option_value_with_exit_rates(
+        share_price, strike_price, time_to_maturity, vesting_period,
+        risk_free, sigma, div_rate,
+        &ExitRates::new(&exit_pre_vesting)?, &ExitRates::new(&exit_post_vesting)?,
+        multiple,
+        steps)
+}
+
+/// `trinomial_option_value` with exit rates that change with years of service, each step
+/// of the tree using the rate for the year it falls in
+pub fn trinomial_option_value_with_exit_rates(
+    share_price: f64,
+    strike_price: f64,
+    time_to_maturity: f64,
+    vesting_period: f64,
+    risk_free: f64,
+    sigma: f64,
+    div_rate: f64,
+    exit_pre_vesting: &ExitRates,
+    exit_post_vesting: &ExitRates,
+    multiple: f64,
+    steps: i32,
+) -> Result<Vec<f64>, Box<dyn std::error::Error>> {
+    let steps = PositiveInt::new(steps.max(0) as usize, "steps")?.0;
+
+    let strike_price = if strike_price == 0.0 { 0.001 } else { strike_price };
+    let vesting_period = vesting_period.min(time_to_maturity);
+
+    // Early exit for zero maturity
+    if time_to_maturity == 0.0 {
+        return Ok(vec![(share_price - strike_price).max(0.0), 0.0]);
+    };
+
+    // European option shortcut if vesting equals maturity
+    if (vesting_period - time_to_maturity).abs() < f64::EPSILON {
+        return Ok(vec![black_scholes_call_option_value(
+                        share_price, strike_price, time_to_maturity, risk_free,
+                        div_rate, sigma,),
+                    time_to_maturity,]);
+    }
+    let sigma = Volatility::new(sigma)?.0;
+
+    // Trinomial tree parameters, matching the drift and variance of the log share price
+    let dt = time_to_maturity / steps as f64;
+    let dx = sigma * (3.0 * dt).sqrt();
+    let u = dx.exp();
+    let nu = risk_free - div_rate - 0.5 * sigma * sigma;
+    let spread = (sigma * sigma * dt + nu * nu * dt * dt) / (dx * dx);
+    let pu = 0.5 * (spread + nu * dt / dx);
+    let pd = 0.5 * (spread - nu * dt / dx);
+    let pm = 1.0 - pu - pd;
+    for probability in [pu, pm, pd] {
+        if !(0.0..=1.0).contains(&probability) {
+            return Err(ParameterError::InvalidProbability { value: probability }.into());
+        }
+    }
+    let r = (risk_free * dt).exp();
+
+    // Vesting period in discrete time steps
+    let vest_step = ((vesting_period / dt) + 0.001) as usize;
+
+    // Exit probabilities per time step, from the rate for the year of service
+    let survival_pre: Vec<f64> = (0..steps).map(|i| exit_pre_vesting.survival(i as f64 * dt, (i + 1) as f64 * dt)).collect();
+    let survival_post: Vec<f64> = (0..steps).map(|i| exit_post_vesting.survival(i as f64 * dt, (i + 1) as f64 * dt)).collect();
+
+    // Share price at step i, node k (k runs from 0 to 2i, with i the middle node)
+    let u_powers: Vec<f64> = (0..=2 * steps).map(|m| u.powi(m as i32 - steps as i32)).collect();
+    let node_price = |i: usize, k: usize| share_price * u_powers[k + steps - i];
+    let intrinsic = |i: usize, k: usize| (node_price(i, k) - strike_price).max(0.0);
+
+    // Terminal conditions at maturity; each step only needs the one after it
+    let mut option_value: Vec<f64> = (0..=2 * steps).map(|k| intrinsic(steps, k)).collect();
+    let mut macaulay_denominator = option_value.clone();
+    let mut macaulay_numerator: Vec<f64> = option_value.iter().map(|value| value * time_to_maturity).collect();
+
+    // Backward induction through the trinomial tree
     for i in (0..steps).rev() {
-        for j in 0..=i {
-            let pv_option_one_period = 
-                (p * option_value[idx(i + 1, j + 1)] + (1.0 - p) * option_value[idx(i + 1, j)]) / r;
-            
+        let expected = |values: &[f64], k: usize| pu * values[k + 2] + pm * values[k + 1] + pd * values[k];
+        let mut next_value = vec![0.0; 2 * i + 1];
+        let mut next_denominator = vec![0.0; 2 * i + 1];
+        let mut next_numerator = vec![0.0; 2 * i + 1];
+        let (px_pre, px) = (survival_pre[i], survival_post[i]);
+        let qx = 1.0 - px;
+        for k in 0..=2 * i {
+            let pv_option_one_period = expected(&option_value, k) / r;
+            let continuing_denominator = expected(&macaulay_denominator, k);
+            let continuing_numerator = expected(&macaulay_numerator, k);
+            let intrinsic_value = intrinsic(i, k);
+
             if i >= vest_step {
                 // Post-vesting period: optimal exercise or multiple trigger
-                let should_exercise = intrinsic_value[idx(i, j)] > pv_option_one_period
-                    || share_price_matrix[idx(i, j)] >= strike_price * multiple;
-                
+                let should_exercise = intrinsic_value > pv_option_one_period
+                    || node_price(i, k) >= strike_price * multiple;
+
                 if should_exercise {
-                    option_value[idx(i, j)] = intrinsic_value[idx(i, j)];
-                    macaulay_denominator[idx(i, j)] = intrinsic_value[idx(i, j)];
-                    macaulay_numerator[idx(i, j)] = intrinsic_value[idx(i, j)] * (i as f64) * dt;
+                    next_value[k] = intrinsic_value;
+                    next_denominator[k] = intrinsic_value;
+                    next_numerator[k] = intrinsic_value * (i as f64) * dt;
                 } else {
-                    option_value[idx(i, j)] = px * pv_option_one_period + qx * intrinsic_value[idx(i, j)];
-                    
-                    macaulay_denominator[idx(i, j)] = px * (
-                        p * macaulay_denominator[idx(i + 1, j + 1)]
-                        + (1.0 - p) * macaulay_denominator[idx(i + 1, j)]
-                    ) + (1.0 - px) * intrinsic_value[idx(i, j)];
-                    
-                    macaulay_numerator[idx(i, j)] = px * (
-                        p * macaulay_numerator[idx(i + 1, j + 1)]
-                        + (1.0 - p) * macaulay_numerator[idx(i + 1, j)]
-                    ) + (1.0 - px) * intrinsic_value[idx(i, j)] * (i as f64) * dt;
+                    next_value[k] = px * pv_option_one_period + qx * intrinsic_value;
+                    next_denominator[k] = px * continuing_denominator + (1.0 - px) * intrinsic_value;
+                    next_numerator[k] = px * continuing_numerator + (1.0 - px) * intrinsic_value * (i as f64) * dt;
                 }
             } else {
                 // Pre-vesting period: cannot exercise, only exit rate applies
-                option_value[idx(i, j)] = px_pre * pv_option_one_period;
-                
-                macaulay_denominator[idx(i, j)] = 
-                    p * macaulay_denominator[idx(i + 1, j + 1)]
-                    + (1.0 - p) * macaulay_denominator[idx(i + 1, j)];
-                    
-                macaulay_numerator[idx(i, j)] = 
-                    p * macaulay_numerator[idx(i + 1, j + 1)]
-                    + (1.0 - p) * macaulay_numerator[idx(i + 1, j)];
+                next_value[k] = px_pre * pv_option_one_period;
+                next_denominator[k] = continuing_denominator;
+                next_numerator[k] = continuing_numerator;
             }
         }
+        option_value = next_value;
+        macaulay_denominator = next_denominator;
+        macaulay_numerator = next_numerator;
     }
-    
+
     // Calculate expected life using Macaulay duration approach
-    let expected_life = if macaulay_denominator[idx(0, 0)] != 0.0 {
-        macaulay_numerator[idx(0, 0)] / macaulay_denominator[idx(0, 0)]
+    let expected_life = if macaulay_denominator[0] != 0.0 {
+        macaulay_numerator[0] / macaulay_denominator[0]
     } else {
         0.0
     };
-    
-    Ok(vec![option_value[idx(0, 0)], expected_life])
+
+    Ok(vec![option_value[0], expected_life])
+}
+
+/// Value of an employee stock option grant that vests in tranches, from the binomial tree.
+/// Each tranche is valued as its own grant, and the total's expected life is the average
+/// of the tranches' weighted by value.
+/// * share_price: Share price at grant date
+/// * strike_price: Price at which the option is exercised
+/// * time_to_maturity: Term to maturity in years
+/// * vesting_schedule: Two columns: time in years, and the total fraction vested by then
+/// * risk_free: Risk-free rate at the appropriate duration
+/// * sigma: Share volatility at the appropriate duration
+/// * div_rate: Dividend rate
+/// * exit_pre_vesting: Exit rate before the vesting date
+/// * exit_post_vesting: Exit rate after the vesting date
+/// * multiple: Multiple of the strike price at which the holder exercises
+/// * steps: Number of time steps in the tree
+/// * ret: A row per tranche of its vesting time, fraction, value and expected life, then the total
+#[xl_func()]
+pub fn graded_option_value(
+    share_price: f64,
+    strike_price: f64,
+    time_to_maturity: f64,
+    vesting_schedule: Variant,
+    risk_free: f64,
+    sigma: f64,
+    div_rate: f64,
+    exit_pre_vesting: f64,
+    exit_post_vesting: f64,
+    multiple: f64,
+    steps: i32,
+) -> Result<Vec<Vec<Variant>>, Box<dyn std::error::Error>> {
+    let points = range_pairs(&vesting_schedule, "time and fraction vested")
+        .map_err(|reason| ParameterError::InvalidVestingSchedule { reason })?;
+    let schedule = VestingSchedule::new(&points)?;
+    let tranches = schedule.value_tranches(|vesting_period| {
+        binomial_option_value(
+            share_price, strike_price, time_to_maturity, vesting_period,
+            risk_free, sigma, div_rate,
+            exit_pre_vesting, exit_post_vesting,
+            multiple,
+            steps)
+    })?;
+
+    let total = TrancheValue::total(&tranches);
+    let mut table = vec![["Vesting", "Fraction", "Value", "Expected life"].map(Variant::from).to_vec()];
+    for tranche in &tranches {
+        table.push(
+            [tranche.tranche.time, tranche.tranche.fraction, tranche.value, tranche.expected_life]
+                .map(Variant::from)
+                .to_vec(),
+        );
+    }
+    table.push(vec![Variant::from("Total"), Variant::from(total.tranche.fraction), Variant::from(total.value), Variant::from(total.expected_life)]);
+    Ok(table)
+}
+
+/// Value of each tranche of a grant that vests on given dates, from the binomial tree, with
+/// the dates an expense system books each tranche against. Years are ACT/365F from grant.
+/// * grant_date: Grant date
+/// * share_price: Share price at grant date
+/// * strike_price: Price at which the option is exercised
+/// * expiry_date: Date the options expire
+/// * vesting_schedule: Two columns: vesting date, and the total fraction vested by then
+/// * risk_free: Risk-free rate at the appropriate duration
+/// * sigma: Share volatility at the appropriate duration
+/// * div_rate: Dividend rate
+/// * exit_pre_vesting: Exit rate before the vesting date
+/// * exit_post_vesting: Exit rate after the vesting date
+/// * multiple: Multiple of the strike price at which the holder exercises
+/// * steps: Number of time steps in the tree
+/// * ret: A row per tranche of its vest date, years to vest, fraction, value per option, value and expected life, then the total
+#[xl_func()]
+pub fn tranche_values(
+    grant_date: f64,
+    share_price: f64,
+    strike_price: f64,
+    expiry_date: f64,
+    vesting_schedule: Variant,
+    risk_free: f64,
+    sigma: f64,
+    div_rate: f64,
+    exit_pre_vesting: f64,
+    exit_post_vesting: f64,
+    multiple: f64,
+    steps: i32,
+) -> Result<Vec<Vec<Variant>>, Box<dyn std::error::Error>> {
+    let years = |date: f64| DayCount::Act365Fixed.year_fraction(grant_date, date);
+    let dates = range_pairs(&vesting_schedule, "vesting date and fraction vested")
+        .map_err(|reason| ParameterError::InvalidVestingSchedule { reason })?;
+    let points: Vec<(f64, f64)> = dates.iter().map(|&(date, vested)| (years(date), vested)).collect();
+    let schedule = VestingSchedule::new(&points)?;
+    let time_to_maturity = years(expiry_date);
+    let tranches = schedule.value_tranches(|vesting_period| {
+        binomial_option_value(
+            share_price, strike_price, time_to_maturity, vesting_period,
+            risk_free, sigma, div_rate,
+            exit_pre_vesting, exit_post_vesting,
+            multiple,
+            steps)
+    })?;
+
+    let total = TrancheValue::total(&tranches);
+    let header = ["Vest date", "Years to vest", "Fraction", "Value per option", "Value", "Expected life"];
+    let mut table = vec![header.map(Variant::from).to_vec()];
+    let per_option = |tranche: &TrancheValue| if tranche.tranche.fraction > 0.0 { tranche.value / tranche.tranche.fraction } else { 0.0 };
+    for (tranche, &(date, _)) in tranches.iter().zip(&dates) {
+        table.push(
+            [date, tranche.tranche.time, tranche.tranche.fraction, per_option(tranche), tranche.value, tranche.expected_life]
+                .map(Variant::from)
+                .to_vec(),
+        );
+    }
+    let mut total_row = vec![Variant::from("Total"), Variant::from("")];
+    total_row.extend([total.tranche.fraction, per_option(&total), total.value, total.expected_life].map(Variant::from));
+    table.push(total_row);
+    Ok(table)
 }
 
+/// AF function to calculate value of an option from the binomial tree, with the risk free
+/// rate interpolated from a curve instead of flat over the term
+/// * share_price: Share price at grant date
+/// * strike_price: Price at which the option is exercised
+/// * time_to_maturity: Term to maturity in years
+/// * vesting_period: Term until the end of the vesting period in years
+/// * rate_curve: Two columns: tenor in years, and the continuously compounded zero rate
+/// * sigma: Share volatility at the appropriate duration
+/// * div_rate: Dividend rate
+/// * exit_pre_vesting: Exit rate before the vesting date
+/// * exit_post_vesting: Exit rate after the vesting date
+/// * multiple: Multiple of the strike price at which the holder exercises
+/// * steps: Number of time steps in the tree
+/// * ret: The option value and its expected life
+#[xl_func()]
+pub fn curve_option_value(
+    share_price: f64,
+    strike_price: f64,
+    time_to_maturity: f64,
+    vesting_period: f64,
+    rate_curve: Variant,
+    sigma: f64,
+    div_rate: f64,
+    exit_pre_vesting: f64,
+    exit_post_vesting: f64,
+    multiple: f64,
+    steps: i32,
+) -> Result<Vec<f64>, Box<dyn std::error::Error>> {
+    let points = range_pairs(&rate_curve, "tenor and rate")
+        .map_err(|reason| ParameterError::InvalidRateCurve { reason })?;
+    binomial_option_value_on_curve(
+        share_price, strike_price, time_to_maturity, vesting_period,
+        &RateCurve::new(&points)?, sigma, div_rate,
+        exit_pre_vesting, exit_post_vesting,
+        multiple,
+        steps)
+}
+
+/// Employee stock option value from the binomial or trinomial tree
+/// * share_price: Share price at grant date
+/// * strike_price: Price at which the option is exercised
+/// * time_to_maturity: Term to maturity in years
+/// * vesting_period: Term until the end of the vesting period in years
+/// * risk_free: Risk-free rate at the appropriate duration
+/// * sigma: Share volatility at the appropriate duration
+/// * div_rate: Dividend rate
+/// * exit_pre_vesting: Exit rate before the vesting date
+/// * exit_post_vesting: Exit rate after the vesting date
+/// * multiple: Multiple of the strike price at which the holder exercises
+/// * steps: Number of time steps in the tree
+/// * model: Binomial or Trinomial (B or T also work)
+/// * ret: The option value and its expected life
+#[xl_func()]
+pub fn tree_option_value(
+    share_price: f64,
+    strike_price: f64,
+    time_to_maturity: f64,
+    vesting_period: f64,
+    risk_free: f64,
+    sigma: f64,
+    div_rate: f64,
+    exit_pre_vesting: f64,
+    exit_post_vesting: f64,
+    multiple: f64,
+    steps: i32,
+    model: String,
+) -> Result<Vec<f64>, Box<dyn std::error::Error>> {
+    let value = match TreeModel::new(&model)? {
+        TreeModel::Binomial => binomial_option_value,
+        TreeModel::Trinomial => trinomial_option_value,
+    };
+    value(
+        share_price, strike_price, time_to_maturity, vesting_period,
+        risk_free, sigma, div_rate,
+        exit_pre_vesting, exit_post_vesting,
+        multiple,
+        steps,
+    )
+}
+
+/// American call or put value from a Cox-Ross-Rubinstein binomial tree, exercised
+/// whenever that is worth more than holding on
+/// * share_price: Current share price
+/// * strike_price: Strike price of the option
+/// * time_to_maturity: Time to maturity in years
+/// * risk_free: Risk-free interest rate (continuously compounded)
+/// * div_rate: Dividend yield (continuously compounded)
+/// * sigma: Volatility of the share
+/// * steps: Number of time steps in the tree (500 is usually plenty)
+/// * call_put: Call or Put (C or P also work)
+/// * ret: The option value
+#[xl_func()]
+pub fn american_option_value(
+    share_price: f64,
+    strike_price: f64,
+    time_to_maturity: f64,
+    risk_free: f64,
+    div_rate: f64,
+    sigma: f64,
+    steps: i32,
+    call_put: String,
+) -> Result<f64, Box<dyn std::error::Error>> {
+    let option_type = OptionType::new(&call_put)?;
+    let share_price = PositiveFloat::new(share_price, "share_price")?.0;
+    let strike_price = PositiveFloat::new(strike_price, "strike_price")?.0;
+    let time_to_maturity = PositiveFloat::new(time_to_maturity, "time_to_maturity")?.0;
+    let steps = PositiveInt::new(steps.max(0) as usize, "steps")?.0;
+
+    let payoff = |price: f64| match option_type {
+        OptionType::Call => (price - strike_price).max(0.0),
+        OptionType::Put => (strike_price - price).max(0.0),
+    };
+
+    // Early exit for zero maturity
+    if time_to_maturity == 0.0 {
+        return Ok(payoff(share_price));
+    }
+    let sigma = Volatility::new(sigma)?.0;
+
+    let dt = time_to_maturity / steps as f64;
+    let u = (sigma * dt.sqrt()).exp();
+    let d = 1.0 / u;
+    let p = (((risk_free - div_rate) * dt).exp() - d) / (u - d);
+    if !(0.0..=1.0).contains(&p) {
+        return Err(ParameterError::InvalidProbability { value: p }.into());
+    }
+    let discount = (-risk_free * dt).exp();
+
+    // Pre-compute u and d powers for efficiency
+    let u_powers: Vec<f64> = (0..=steps).map(|i| u.powi(i as i32)).collect();
+    let d_powers: Vec<f64> = (0..=steps).map(|i| d.powi(i as i32)).collect();
+
+    // Option values at maturity, node j having had j up moves
+    let mut option_value: Vec<f64> = (0..=steps)
+        .map(|j| payoff(share_price * u_powers[j] * d_powers[steps - j]))
+        .collect();
+
+    // Backward induction, exercising early where the payoff beats the continuation value
+    for i in (0..steps).rev() {
+        for j in 0..=i {
+            let continuation = discount * (p * option_value[j + 1] + (1.0 - p) * option_value[j]);
+            let exercise = payoff(share_price * u_powers[j] * d_powers[i - j]);
+            option_value[j] = continuation.max(exercise);
+        }
+    }
+
+    Ok(option_value[0])
+}
 
 #[cfg(test)]
 mod tests {
-    // use super::*;
+    use super::*;
 
     // #[test]
     // fn test_zero_maturity() {
@@ -477,4 +2330,388 @@ mod tests {
     //     assert!(result[1] > 0.0);
     //     assert!(result[1] <= 1.0);
     // }
+
+    #[test]
+    fn call_and_put_satisfy_put_call_parity() {
+        let (share_price, strike_price, time_to_maturity, risk_free, div_rate) = (100.0, 95.0, 1.5, 0.04, 0.01);
+        let call = black_scholes_value(share_price, strike_price, time_to_maturity, risk_free, div_rate, 0.25, "Call".to_string()).unwrap();
+        let put = black_scholes_value(share_price, strike_price, time_to_maturity, risk_free, div_rate, 0.25, "p".to_string()).unwrap();
+        let forward = share_price * (-div_rate * time_to_maturity).exp() - strike_price * (-risk_free * time_to_maturity).exp();
+        assert!((call - put - forward).abs() < 1e-6);
+    }
+
+    #[test]
+    fn zero_volatility_put_is_discounted_intrinsic_value() {
+        let put = black_scholes_put_option_value(90.0, 100.0, 1.0, 0.05, 0.0, 0.0);
+        assert!((put - (100.0 * (-0.05f64).exp() - 90.0)).abs() < 1e-12);
+        assert_eq!(black_scholes_put_option_value(110.0, 100.0, 1.0, 0.05, 0.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn greeks_match_finite_differences() {
+        let value = |share_price: f64, time_to_maturity: f64, risk_free: f64, sigma: f64| {
+            black_scholes_put_option_value(share_price, 105.0, time_to_maturity, risk_free, 0.02, sigma)
+        };
+        let greeks = Greeks::new(OptionType::Put, 100.0, 105.0, 0.75, 0.03, 0.02, 0.3).unwrap();
+        let h = 1e-4;
+        // The normal CDF approximation is good to about 1e-7, which limits the accuracy
+        assert!((greeks.delta - (value(100.0 + h, 0.75, 0.03, 0.3) - value(100.0 - h, 0.75, 0.03, 0.3)) / (2.0 * h)).abs() < 1e-2);
+        assert!((greeks.vega - (value(100.0, 0.75, 0.03, 0.3 + h) - value(100.0, 0.75, 0.03, 0.3 - h)) / (2.0 * h)).abs() < 1e-2);
+        assert!((greeks.rho - (value(100.0, 0.75, 0.03 + h, 0.3) - value(100.0, 0.75, 0.03 - h, 0.3)) / (2.0 * h)).abs() < 1e-2);
+        assert!((greeks.theta + (value(100.0, 0.75 + h, 0.03, 0.3) - value(100.0, 0.75 - h, 0.03, 0.3)) / (2.0 * h)).abs() < 1e-2);
+    }
+
+    #[test]
+    fn greeks_table_has_a_labeled_row_each() {
+        let table = black_scholes_greeks(100.0, 100.0, 1.0, 0.05, 0.0, 0.2, "Call".to_string()).unwrap();
+        let names: Vec<&str> = table.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["Delta", "Gamma", "Vega", "Theta", "Rho"]);
+        assert!(black_scholes_greeks(100.0, 100.0, 1.0, 0.05, 0.0, 0.0, "Call".to_string()).is_err());
+    }
+
+    #[test]
+    fn implied_volatility_recovers_the_pricing_volatility() {
+        for (strike_price, sigma, call_put) in [(100.0, 0.2, "Call"), (80.0, 0.15, "Put"), (150.0, 0.8, "Put"), (40.0, 1.5, "C")] {
+            let price = black_scholes_value(100.0, strike_price, 2.0, 0.03, 0.01, sigma, call_put.to_string()).unwrap();
+            let implied = implied_volatility(price, 100.0, strike_price, 2.0, 0.03, 0.01, call_put.to_string()).unwrap();
+            assert!((implied - sigma).abs() < 1e-6, "{} {}: {}", call_put, strike_price, implied);
+        }
+    }
+
+    #[test]
+    fn implied_volatility_rejects_prices_outside_the_bounds() {
+        let above = implied_volatility(101.0, 100.0, 90.0, 1.0, 0.05, 0.0, "Call".to_string()).unwrap_err();
+        assert!(above.to_string().contains("upper no-arbitrage bound"), "{}", above);
+        let below = implied_volatility(5.0, 100.0, 120.0, 1.0, 0.0, 0.0, "Put".to_string()).unwrap_err();
+        assert!(below.to_string().contains("lower no-arbitrage bound"), "{}", below);
+        assert_eq!(implied_volatility(20.0, 100.0, 120.0, 1.0, 0.0, 0.0, "Put".to_string()).unwrap(), 0.0);
+    }
+
+    #[test]
+    fn american_options_match_known_values() {
+        // Without dividends an American call is never exercised early
+        let american = american_option_value(100.0, 100.0, 1.0, 0.05, 0.0, 0.2, 1000, "Call".to_string()).unwrap();
+        let european = black_scholes_call_option_value(100.0, 100.0, 1.0, 0.05, 0.0, 0.2);
+        assert!((american - european).abs() < 0.01, "{} {}", american, european);
+        // Longstaff and Schwartz's benchmark put, 4.487 on a fine finite difference grid
+        let put = american_option_value(36.0, 40.0, 1.0, 0.06, 0.0, 0.2, 1000, "Put".to_string()).unwrap();
+        assert!((put - 4.487).abs() < 0.005, "{}", put);
+        assert!(put > black_scholes_put_option_value(36.0, 40.0, 1.0, 0.06, 0.0, 0.2));
+    }
+
+    #[test]
+    fn rolling_back_two_rows_matches_the_full_lattice_and_scales() {
+        // The value the tree gave when it kept every node
+        let value = binomial_option_value(100.0, 90.0, 7.0, 3.0, 0.05, 0.35, 0.02, 0.05, 0.08, 2.5, 500).unwrap();
+        assert!((value[0] - 33.32665962854838).abs() < 1e-10 && (value[1] - 4.291493155448026).abs() < 1e-10, "{:?}", value);
+
+        // Without dividends or exits a call is never exercised early, so it is a European call
+        let large = binomial_option_value(100.0, 90.0, 7.0, 3.0, 0.05, 0.35, 0.0, 0.0, 0.0, 1e7, 10_000).unwrap();
+        let european = black_scholes_call_option_value(100.0, 90.0, 7.0, 0.05, 0.0, 0.35);
+        assert!((large[0] - european).abs() < 2e-3, "{} {}", large[0], european);
+        assert!(binomial_option_value(100.0, 90.0, 7.0, 3.0, 0.05, 0.35, 0.02, 0.05, 0.08, 2.5, 0).is_err());
+        assert!(binomial_option_value(100.0, 90.0, 7.0, 3.0, 0.05, 0.35, 0.02, 0.05, 0.08, 2.5, -5).is_err());
+    }
+
+    #[test]
+    fn binomial_tree_rejects_probabilities_outside_0_to_1() {
+        // A high rate over two-year steps grows the share by more than an up move
+        let message = binomial_option_value(100.0, 100.0, 10.0, 3.0, 0.5, 0.05, 0.0, 0.0, 0.0, 2.5, 5).unwrap_err().to_string();
+        assert!(message.starts_with("Risk-neutral probability"), "{}", message);
+        assert!(binomial_option_value(100.0, 100.0, 10.0, 3.0, 0.5, 0.05, 0.0, 0.0, 0.0, 2.5, 2000).is_ok());
+    }
+
+    #[test]
+    fn trinomial_and_binomial_trees_agree() {
+        let value = |model: &str, steps: i32| {
+            tree_option_value(100.0, 100.0, 10.0, 3.0, 0.05, 0.3, 0.02, 0.05, 0.08, 2.5, steps, model.to_string()).unwrap()
+        };
+        // Where the exercise multiple falls between nodes makes both wobble a little
+        let binomial = value("Binomial", 2000);
+        let trinomial = value("T", 1000);
+        assert!((binomial[0] - trinomial[0]).abs() < 0.1, "{:?} {:?}", binomial, trinomial);
+        assert!((binomial[1] - trinomial[1]).abs() < 0.1, "{:?} {:?}", binomial, trinomial);
+    }
+
+    #[test]
+    fn trinomial_tree_without_exits_or_dividends_is_a_european_call() {
+        let trinomial = trinomial_option_value(100.0, 90.0, 2.0, 0.5, 0.04, 0.25, 0.0, 0.0, 0.0, 1e7, 400).unwrap();
+        let european = black_scholes_call_option_value(100.0, 90.0, 2.0, 0.04, 0.0, 0.25);
+        assert!((trinomial[0] - european).abs() < 0.02, "{} {}", trinomial[0], european);
+    }
+
+    #[test]
+    fn vesting_schedule_splits_the_grant_into_tranches() {
+        let schedule = VestingSchedule::new(&[(1.0, 0.25), (2.0, 0.5), (4.0, 1.0)]).unwrap();
+        let fractions: Vec<f64> = schedule.0.iter().map(|tranche| tranche.fraction).collect();
+        assert_eq!(fractions, [0.25, 0.25, 0.5]);
+        assert!(VestingSchedule::new(&[(2.0, 0.5), (1.0, 1.0)]).is_err());
+        assert!(VestingSchedule::new(&[(1.0, 0.5), (2.0, 0.4)]).is_err());
+        assert!(VestingSchedule::new(&[(1.0, 1.5)]).is_err());
+    }
+
+    #[test]
+    fn graded_vesting_values_each_tranche_as_a_cliff() {
+        let parameters = OptionParameters::new(100.0, 100.0, 7.0, 3.0, 0.05, 0.3, 0.02, 0.05, 0.08, 2.5, 200).unwrap();
+        let cliff = parameters.binomial_tranche_values().unwrap();
+        let single = binomial_option_value(100.0, 100.0, 7.0, 3.0, 0.05, 0.3, 0.02, 0.05, 0.08, 2.5, 200).unwrap();
+        assert_eq!((cliff.len(), cliff[0].value, cliff[0].expected_life), (1, single[0], single[1]));
+
+        let graded = parameters
+            .with_vesting_schedule(VestingSchedule::new(&[(1.0, 0.5), (3.0, 1.0)]).unwrap())
+            .binomial_tranche_values()
+            .unwrap();
+        let early = binomial_option_value(100.0, 100.0, 7.0, 1.0, 0.05, 0.3, 0.02, 0.05, 0.08, 2.5, 200).unwrap();
+        assert!((graded[0].value - 0.5 * early[0]).abs() < 1e-12);
+        assert!((graded[1].value - 0.5 * single[0]).abs() < 1e-12);
+    }
+
+    #[test]
+    fn graded_option_value_reads_the_schedule_range() {
+        let schedule = Variant::from(vec![vec![1.0, 0.5], vec![3.0, 1.0]]);
+        let table = graded_option_value(100.0, 100.0, 7.0, schedule, 0.05, 0.3, 0.02, 0.05, 0.08, 2.5, 200).unwrap();
+        assert_eq!(table.len(), 4);
+        assert_eq!(String::from(&table[3][0]), "Total");
+        assert_eq!(f64::try_from(&table[3][1]).ok(), Some(1.0));
+    }
+
+    #[test]
+    fn exit_rates_by_year_of_service() {
+        let flat = binomial_option_value(100.0, 100.0, 7.0, 3.0, 0.05, 0.3, 0.02, 0.05, 0.08, 2.5, 210).unwrap();
+        let same = service_exit_option_value(100.0, 100.0, 7.0, 3.0, 0.05, 0.3, 0.02, vec![0.05; 3], vec![0.08], 2.5, 210).unwrap();
+        assert!((flat[0] - same[0]).abs() < 1e-12 && (flat[1] - same[1]).abs() < 1e-12);
+
+        // Leaving more often once vested cuts both the value and the expected life
+        let rising = service_exit_option_value(100.0, 100.0, 7.0, 3.0, 0.05, 0.3, 0.02, vec![0.05], vec![0.08, 0.08, 0.08, 0.15], 2.5, 210).unwrap();
+        assert!(rising[0] < flat[0] && rising[1] < flat[1]);
+        // Rates before vesting don't matter after it
+        let ignored = service_exit_option_value(100.0, 100.0, 7.0, 3.0, 0.05, 0.3, 0.02, vec![0.05], vec![0.5, 0.5, 0.5, 0.08], 2.5, 210).unwrap();
+        assert!((flat[0] - ignored[0]).abs() < 1e-12);
+
+        // The trinomial tree takes the same rates
+        let flat = trinomial_option_value(100.0, 100.0, 7.0, 3.0, 0.05, 0.3, 0.02, 0.05, 0.08, 2.5, 210).unwrap();
+        let same = service_exit_trinomial_value(100.0, 100.0, 7.0, 3.0, 0.05, 0.3, 0.02, vec![0.05; 3], vec![0.08], 2.5, 210).unwrap();
+        assert!((flat[0] - same[0]).abs() < 1e-12 && (flat[1] - same[1]).abs() < 1e-12);
+        let rising = service_exit_trinomial_value(100.0, 100.0, 7.0, 3.0, 0.05, 0.3, 0.02, vec![0.05], vec![0.08, 0.08, 0.08, 0.15], 2.5, 210).unwrap();
+        assert!(rising[0] < flat[0] && rising[1] < flat[1]);
+    }
+
+    #[test]
+    fn blackouts_delay_exercise() {
+        let open = binomial_option_value(100.0, 100.0, 7.0, 3.0, 0.05, 0.3, 0.02, 0.05, 0.08, 2.5, 210).unwrap();
+        let none = blackout_option_value(100.0, 100.0, 7.0, 3.0, 0.05, 0.3, 0.02, 0.05, 0.08, 2.5, 210, Variant::from(vec![vec![9.0, 10.0]])).unwrap();
+        assert_eq!(open, none);
+
+        // Holding on past the multiple is worth more than exercising there, and takes longer
+        let windows = Variant::from(vec![vec![3.0, 3.5], vec![4.0, 4.5], vec![5.0, 5.5]]);
+        let blacked_out = blackout_option_value(100.0, 100.0, 7.0, 3.0, 0.05, 0.3, 0.02, 0.05, 0.08, 2.5, 210, windows).unwrap();
+        assert!(blacked_out[0] > open[0] && blacked_out[1] > open[1], "{:?} {:?}", blacked_out, open);
+        // Blacking out everything after vesting leaves exercise to exits and maturity
+        let always = blackout_option_value(100.0, 100.0, 7.0, 3.0, 0.05, 0.3, 0.02, 0.05, 0.0, 2.5, 210, Variant::from(vec![vec![0.0, 7.0]])).unwrap();
+        let european = black_scholes_call_option_value(100.0, 100.0, 7.0, 0.05, 0.02, 0.3) * 0.95f64.powf(3.0);
+        assert!((always[0] - european).abs() < 0.05, "{} {}", always[0], european);
+    }
+
+    #[test]
+    fn tranche_values_are_dated_and_add_up() {
+        let grant = 45000.0;
+        let schedule = Variant::from(vec![vec![grant + 365.0, 0.5], vec![grant + 1095.0, 1.0]]);
+        let table = tranche_values(grant, 100.0, 100.0, grant + 2555.0, schedule, 0.05, 0.3, 0.02, 0.05, 0.08, 2.5, 200).unwrap();
+        let by_years = graded_option_value(100.0, 100.0, 7.0, Variant::from(vec![vec![1.0, 0.5], vec![3.0, 1.0]]), 0.05, 0.3, 0.02, 0.05, 0.08, 2.5, 200).unwrap();
+        let number = |table: &Vec<Vec<Variant>>, row: usize, column: usize| f64::try_from(&table[row][column]).unwrap();
+        assert_eq!(number(&table, 2, 0), grant + 1095.0);
+        for row in 1..=3 {
+            assert!((number(&table, row, 4) - number(&by_years, row, 2)).abs() < 1e-12);
+            assert!((number(&table, row, 5) - number(&by_years, row, 3)).abs() < 1e-12);
+        }
+        assert!((number(&table, 1, 3) - 2.0 * number(&table, 1, 4)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn binomial_tree_on_a_curve() {
+        let flat = binomial_option_value_on_curve(100.0, 100.0, 10.0, 3.0, &RateCurve::flat(0.05), 0.3, 0.02, 0.05, 0.08, 2.5, 300).unwrap();
+        let single = binomial_option_value(100.0, 100.0, 10.0, 3.0, 0.05, 0.3, 0.02, 0.05, 0.08, 2.5, 300).unwrap();
+        assert!((flat[0] - single[0]).abs() < 1e-9 && (flat[1] - single[1]).abs() < 1e-9);
+
+        // An upward sloping curve is worth more to a call than its short end alone
+        let curve = RateCurve::new(&[(1.0, 0.02), (10.0, 0.06)]).unwrap();
+        let sloped = binomial_option_value_on_curve(100.0, 100.0, 10.0, 3.0, &curve, 0.3, 0.02, 0.05, 0.08, 2.5, 300).unwrap();
+        let short = binomial_option_value(100.0, 100.0, 10.0, 3.0, 0.02, 0.3, 0.02, 0.05, 0.08, 2.5, 300).unwrap();
+        assert!(sloped[0] > short[0]);
+
+        // Without early exercise the tree matches Black-Scholes at the zero rate to maturity
+        let european = binomial_option_value_on_curve(100.0, 100.0, 10.0, 9.999, &curve, 0.3, 0.0, 0.0, 0.0, 1e7, 1000).unwrap();
+        let analytic = black_scholes_call_option_value(100.0, 100.0, 10.0, curve.zero_rate(10.0), 0.0, 0.3);
+        assert!((european[0] - analytic).abs() < 0.05, "{} {}", european[0], analytic);
+    }
+
+    #[test]
+    fn black76_is_black_scholes_on_the_forward() {
+        let (share_price, strike, t, risk_free, div_rate, vol): (f64, f64, f64, f64, f64, f64) = (100.0, 110.0, 2.0, 0.05, 0.02, 0.3);
+        let forward = share_price * ((risk_free - div_rate) * t).exp();
+        let df = (-risk_free * t).exp();
+        for call_put in ["Call", "Put"] {
+            let black76 = black76_value(forward, strike, vol, t, df, call_put.to_string()).unwrap();
+            let black_scholes = black_scholes_value(share_price, strike, t, risk_free, div_rate, vol, call_put.to_string()).unwrap();
+            assert!((black76 - black_scholes).abs() < 1e-9, "{}: {} {}", call_put, black76, black_scholes);
+        }
+        assert_eq!(black76_value(90.0, 100.0, 0.0, 1.0, 0.95, "Put".to_string()).unwrap(), 0.95 * 10.0);
+    }
+
+    #[test]
+    fn black76_greeks_match_finite_differences() {
+        let value = |forward: f64, vol: f64, t: f64, df: f64| black76_value(forward, 50.0, vol, t, df, "Call".to_string()).unwrap();
+        let greeks = Greeks::black76(OptionType::Call, 48.0, 50.0, 0.35, 1.25, 0.96).unwrap();
+        let h = 1e-4;
+        assert!((greeks.delta - (value(48.0 + h, 0.35, 1.25, 0.96) - value(48.0 - h, 0.35, 1.25, 0.96)) / (2.0 * h)).abs() < 1e-2);
+        assert!((greeks.vega - (value(48.0, 0.35 + h, 1.25, 0.96) - value(48.0, 0.35 - h, 1.25, 0.96)) / (2.0 * h)).abs() < 1e-2);
+        // Theta holds the rate, so the discount factor moves with time
+        let rate = -0.96f64.ln() / 1.25;
+        let later = value(48.0, 0.35, 1.25 - h, (-rate * (1.25 - h)).exp());
+        let earlier = value(48.0, 0.35, 1.25 + h, (-rate * (1.25 + h)).exp());
+        assert!((greeks.theta - (later - earlier) / (2.0 * h)).abs() < 1e-2);
+        assert_eq!(black76_greeks(48.0, 50.0, 0.35, 1.25, 0.96, "Put".to_string()).unwrap().len(), 5);
+    }
+
+    #[test]
+    fn value_grid_matches_single_valuations() {
+        let share_prices = [80.0, 100.0, 125.0];
+        let sigmas = [0.2, 0.45];
+        for vesting_period in [3.0, 10.0] {
+            let grid = option_value_grid(share_prices.to_vec(), sigmas.to_vec(), 100.0, 10.0, vesting_period, 0.05, 0.02, 0.05, 0.08, 2.5, 150).unwrap();
+            assert_eq!((grid.len(), grid[0].len()), (3, 2));
+            for (row, &share_price) in share_prices.iter().enumerate() {
+                for (column, &sigma) in sigmas.iter().enumerate() {
+                    let single = binomial_option_value(share_price, 100.0, 10.0, vesting_period, 0.05, sigma, 0.02, 0.05, 0.08, 2.5, 150).unwrap();
+                    assert_eq!(grid[row][column], single[0]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_call_put_flag_is_an_error() {
+        let error = black_scholes_value(100.0, 100.0, 1.0, 0.05, 0.0, 0.2, "Straddle".to_string()).unwrap_err();
+        assert!(error.to_string().contains("Straddle"));
+    }
+
+    #[test]
+    fn converged_value_matches_a_fine_tree() {
+        let fine = |multiple: f64| {
+            let odd = binomial_option_value(100.0, 100.0, 10.0, 3.0, 0.05, 0.3, 0.02, 0.05, 0.08, multiple, 3001).unwrap();
+            let even = binomial_option_value(100.0, 100.0, 10.0, 3.0, 0.05, 0.3, 0.02, 0.05, 0.08, multiple, 3000).unwrap();
+            0.5 * (odd[0] + even[0])
+        };
+        let row = |table: &[(String, f64)], label: &str| table.iter().find(|(name, _)| name == label).unwrap().1;
+
+        let optimal = converged_option_value(100.0, 100.0, 10.0, 3.0, 0.05, 0.3, 0.02, 0.05, 0.08, 1e7, 1e-3, 5000).unwrap();
+        assert!((row(&optimal, "Value") - fine(1e7)).abs() < 2e-3, "{:?}", optimal);
+        assert!(row(&optimal, "Steps") < 1000.0);
+
+        // The exercise barrier makes the value converge irregularly, so it needs a looser tolerance
+        let barrier = converged_option_value(100.0, 100.0, 10.0, 3.0, 0.05, 0.3, 0.02, 0.05, 0.08, 2.5, 0.1, 5000).unwrap();
+        assert!((row(&barrier, "Value") - fine(2.5)).abs() < 0.05, "{:?}", barrier);
+        assert!(converged_option_value(100.0, 100.0, 10.0, 3.0, 0.05, 0.3, 0.02, 0.05, 0.08, 2.5, 1e-6, 200).is_err());
+
+        // Two extrapolations need trees of up to 101 steps
+        let too_few = converged_option_value(100.0, 100.0, 10.0, 3.0, 0.05, 0.3, 0.02, 0.05, 0.08, 1e7, 10.0, 100).unwrap_err();
+        assert!(too_few.to_string().contains("at least 101"), "{}", too_few);
+        let fewest = converged_option_value(100.0, 100.0, 10.0, 3.0, 0.05, 0.3, 0.02, 0.05, 0.08, 1e7, 10.0, 101).unwrap();
+        assert_eq!(row(&fewest, "Steps"), 101.0);
+    }
+
+    #[test]
+    fn cap_lowers_value_and_expected_life() {
+        let uncapped = binomial_option_value(100.0, 100.0, 10.0, 3.0, 0.05, 0.3, 0.02, 0.05, 0.08, 2.5, 200).unwrap();
+        assert_eq!(capped_option_value(100.0, 100.0, 10.0, 3.0, 0.05, 0.3, 0.02, 0.05, 0.08, 2.5, 0.0, 0.0, 200).unwrap(), uncapped);
+
+        let capped = capped_option_value(100.0, 100.0, 10.0, 3.0, 0.05, 0.3, 0.02, 0.05, 0.08, 1e7, 1.0, 0.0, 200).unwrap();
+        let optimal = binomial_option_value(100.0, 100.0, 10.0, 3.0, 0.05, 0.3, 0.02, 0.05, 0.08, 1e7, 200).unwrap();
+        assert!(capped[0] < optimal[0] && capped[1] < optimal[1], "{:?} {:?}", capped, optimal);
+        // The lower cap applies
+        let amount = capped_option_value(100.0, 100.0, 10.0, 3.0, 0.05, 0.3, 0.02, 0.05, 0.08, 1e7, 5.0, 100.0, 200).unwrap();
+        assert_eq!(amount, capped);
+    }
+
+    #[test]
+    fn capped_european_option_is_a_call_spread() {
+        let capped = capped_option_value(100.0, 100.0, 5.0, 5.0, 0.05, 0.3, 0.02, 0.0, 0.0, 1e7, 0.5, 0.0, 200).unwrap();
+        let spread = black_scholes_call_option_value(100.0, 100.0, 5.0, 0.05, 0.02, 0.3)
+            - black_scholes_call_option_value(100.0, 150.0, 5.0, 0.05, 0.02, 0.3);
+        assert!((capped[0] - spread).abs() < 1e-12);
+    }
+
+    #[test]
+    fn audit_tree_lays_out_the_nodes_that_give_the_value() {
+        let table = binomial_tree(100.0, 100.0, 4.0, 1.0, 0.05, 0.3, 0.02, 0.05, 0.08, 2.5, "Both".to_string(), 8).unwrap();
+        assert_eq!((table.len(), table[0].len()), (1 + 2 * 9, 10));
+        let number = |row: usize, column: usize| f64::try_from(&table[row][column]).unwrap();
+        let value = binomial_option_value(100.0, 100.0, 4.0, 1.0, 0.05, 0.3, 0.02, 0.05, 0.08, 2.5, 8).unwrap();
+        assert_eq!(number(10, 1), value[0]);
+        assert_eq!(number(1, 1), 100.0);
+        // After one step the top row has gone up and the next down, by reciprocal moves
+        assert!((number(1, 2) * number(2, 2) - 100.0 * 100.0).abs() < 1e-9);
+        assert_eq!(number(0, 9), 4.0);
+        assert!(f64::try_from(&table[2][1]).is_err());
+        assert!(binomial_tree(100.0, 100.0, 4.0, 1.0, 0.05, 0.3, 0.02, 0.05, 0.08, 2.5, "S".to_string(), 251).is_err());
+    }
+
+    #[test]
+    fn audit_shows_the_parameters_behind_the_value() {
+        let audit = binomial_audit(100.0, 100.0, 4.0, 1.0, 0.05, 0.3, 0.02, 0.05, 0.08, 2.5, 100).unwrap();
+        let row = |label: &str| audit.iter().find(|(name, _)| name == label).unwrap().1;
+        let value = binomial_option_value(100.0, 100.0, 4.0, 1.0, 0.05, 0.3, 0.02, 0.05, 0.08, 2.5, 100).unwrap();
+        assert_eq!(row("Value"), value[0]);
+        assert_eq!(row("dt"), 0.04);
+        assert!((row("u") - (0.3 * 0.2f64).exp()).abs() < 1e-15 && (row("u") * row("d") - 1.0).abs() < 1e-15);
+        assert!((row("p") - (((0.05f64 - 0.02) * 0.04).exp() - row("d")) / (row("u") - row("d"))).abs() < 1e-15);
+        assert_eq!(row("Vest step"), 25.0);
+        assert!((row("Survival per step pre-vesting") - 0.95f64.powf(0.04)).abs() < 1e-15);
+        assert_eq!(row("Closed form"), 0.0);
+    }
+
+    #[test]
+    fn parameters_read_from_a_labelled_block() {
+        let labels = ["Share price", "strike_price", "Time to maturity", "VESTING PERIOD", "risk_free", "sigma", "Div rate", "exit pre-vesting", "exit_post_vesting", "multiple", "steps"];
+        let values = [100.0, 100.0, 7.0, 3.0, 0.05, 0.3, 0.02, 0.05, 0.08, 2.5, 200.0];
+        let row = |label: &str, value: f64| vec![Variant::from(label), Variant::from(value)];
+        let block: Vec<Vec<Variant>> = labels.iter().zip(values).map(|(label, value)| row(label, value)).collect();
+        let blank = || Variant::from(vec![vec![Variant::missing(), Variant::missing()]]);
+        let value = option_value_from_params(Variant::from(block.clone()), blank()).unwrap();
+        assert_eq!(value, binomial_option_value(100.0, 100.0, 7.0, 3.0, 0.05, 0.3, 0.02, 0.05, 0.08, 2.5, 200).unwrap());
+
+        let message = option_value_from_params(Variant::from(block[..9].to_vec()), blank()).unwrap_err().to_string();
+        assert!(message.contains("multiple, steps"), "{}", message);
+        let mut unknown = block.clone();
+        unknown.push(row("strike", 90.0));
+        assert!(option_value_from_params(Variant::from(unknown), blank()).is_err());
+    }
+
+    #[test]
+    fn labelled_parameters_value_graded_grants() {
+        let labels = ["share_price", "strike_price", "time_to_maturity", "risk_free", "sigma", "div_rate", "exit_pre_vesting", "exit_post_vesting", "multiple", "steps"];
+        let values = [100.0, 100.0, 7.0, 0.05, 0.3, 0.02, 0.05, 0.08, 2.5, 200.0];
+        let mut block: Vec<Vec<Variant>> = labels.iter().zip(values).map(|(label, value)| vec![Variant::from(*label), Variant::from(value)]).collect();
+        let schedule = || Variant::from(vec![vec![1.0, 0.5], vec![3.0, 1.0]]);
+        let value = option_value_from_params(Variant::from(block.clone()), schedule()).unwrap();
+
+        let graded = graded_option_value(100.0, 100.0, 7.0, schedule(), 0.05, 0.3, 0.02, 0.05, 0.08, 2.5, 200).unwrap();
+        let total = graded.last().unwrap();
+        assert_eq!(f64::try_from(&total[2]).ok(), Some(value[0]));
+        assert_eq!(f64::try_from(&total[3]).ok(), Some(value[1]));
+
+        // A vesting period that disagrees with the schedule is a mistake
+        block.push(vec![Variant::from("vesting_period"), Variant::from(2.0)]);
+        assert!(option_value_from_params(Variant::from(block), schedule()).is_err());
+    }
+
+    #[test]
+    fn validation_reports_every_bad_parameter() {
+        let table = validate_option_params(100.0, 100.0, 7.0, 3.0, 5.0, 0.0, 0.02, 0.05, 0.08, 2.5, 0.0).unwrap();
+        assert_eq!(table.len(), 12);
+        let checks: Vec<String> = table[1..].iter().map(|row| String::from(&row[2])).collect();
+        let failed: Vec<&str> = OPTION_PARAMETER_NAMES.iter().zip(&checks).filter(|(_, check)| *check != "OK").map(|(name, _)| *name).collect();
+        assert_eq!(failed, ["risk_free", "sigma", "steps"]);
+        assert!(checks[4].contains("between 0 and 1"), "{}", checks[4]);
+    }
 }
@@ -0,0 +1,73 @@
+//! Reading the ranges of numbers that the pricing functions take, such as curves,
+//! schedules and correlation matrices
+
+use xladd_core::variant::Variant;
+
+/// The rows of a two-column range of numbers, skipping blank rows. Errors are the reason
+/// the range is no good.
+pub fn range_pairs(range: &Variant, columns: &str) -> Result<Vec<(f64, f64)>, String> {
+    let (width, rows) = range.dim();
+    if width != 2 {
+        return Err(format!("it needs two columns, {}, not {}", columns, width));
+    }
+    let mut pairs = Vec::with_capacity(rows);
+    for row in 0..rows {
+        let (first, second) = (range.at(0, row), range.at(1, row));
+        if first.is_missing_or_null() && second.is_missing_or_null() {
+            continue;
+        }
+        let number = |cell: &Variant| f64::try_from(cell).map_err(|_| format!("row {} is not two numbers", row + 1));
+        pairs.push((number(&first)?, number(&second)?));
+    }
+    Ok(pairs)
+}
+
+/// The rows of a two-column range of labels and values, skipping blank rows
+pub fn range_labelled(range: &Variant) -> Result<Vec<(String, Variant)>, String> {
+    let (width, rows) = range.dim();
+    if width != 2 {
+        return Err(format!("it needs two columns, label and value, not {}", width));
+    }
+    let mut labelled = Vec::with_capacity(rows);
+    for row in 0..rows {
+        let (label, value) = (range.at(0, row), range.at(1, row));
+        if label.is_missing_or_null() && value.is_missing_or_null() {
+            continue;
+        }
+        labelled.push((String::from(&label), value));
+    }
+    Ok(labelled)
+}
+
+/// The numbers of a range of any shape, row by row, skipping blank cells
+pub fn range_numbers(range: &Variant) -> Result<Vec<f64>, String> {
+    let (columns, rows) = range.dim();
+    let mut numbers = Vec::with_capacity(columns * rows);
+    for row in 0..rows {
+        for column in 0..columns {
+            let cell = range.at(column, row);
+            if cell.is_missing_or_null() {
+                continue;
+            }
+            numbers.push(
+                f64::try_from(&cell).map_err(|_| format!("row {}, column {} is not a number", row + 1, column + 1))?,
+            );
+        }
+    }
+    Ok(numbers)
+}
+
+/// The numbers of a range, row by row
+pub fn range_matrix(range: &Variant) -> Result<Vec<Vec<f64>>, String> {
+    let (columns, rows) = range.dim();
+    (0..rows)
+        .map(|row| {
+            (0..columns)
+                .map(|column| {
+                    f64::try_from(&range.at(column, row))
+                        .map_err(|_| format!("row {}, column {} is not a number", row + 1, column + 1))
+                })
+                .collect()
+        })
+        .collect()
+}
@@ -0,0 +1,82 @@
+//! Risk-free rates that change with term, for the lattices, which would otherwise take one
+//! rate over an option's whole life. The curve holds zero rates, and each step of a
+//! lattice is discounted at the forward rate over it. Bootstrapped curves are turned into
+//! one of these to value options on them.
+
+use crate::actuarial::option_pricing::ParameterError;
+
+/// A risk-free zero curve: continuously compounded zero rates at increasing tenors in
+/// years, interpolated linearly between them and held flat beyond the first and last
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateCurve(Vec<(f64, f64)>);
+
+impl RateCurve {
+    /// The same rate at every tenor
+    pub fn flat(rate: f64) -> Self {
+        RateCurve(vec![(0.0, rate)])
+    }
+
+    /// A curve from (tenor, rate) points
+    pub fn new(points: &[(f64, f64)]) -> Result<Self, ParameterError> {
+        let invalid = |reason: String| ParameterError::InvalidRateCurve { reason };
+        if points.is_empty() {
+            return Err(invalid("it has no rows".to_string()));
+        }
+        let mut last_tenor = -1.0;
+        for &(tenor, rate) in points {
+            if !(tenor >= 0.0 && tenor.is_finite()) || tenor <= last_tenor {
+                return Err(invalid(format!("tenors must be non-negative and increasing, got {}", tenor)));
+            }
+            if !(rate > -1.0 && rate.is_finite()) {
+                return Err(invalid(format!("rates must be finite and above -1, got {}", rate)));
+            }
+            last_tenor = tenor;
+        }
+        Ok(RateCurve(points.to_vec()))
+    }
+
+    /// The zero rate for a tenor
+    pub fn zero_rate(&self, tenor: f64) -> f64 {
+        let points = &self.0;
+        let after = points.partition_point(|&(point, _)| point < tenor);
+        if after == 0 {
+            return points[0].1;
+        }
+        if after == points.len() {
+            return points[after - 1].1;
+        }
+        let ((t1, r1), (t2, r2)) = (points[after - 1], points[after]);
+        r1 + (r2 - r1) * (tenor - t1) / (t2 - t1)
+    }
+
+    /// The continuously compounded forward rate from `start` to `end`, the rate that takes
+    /// the discount factor at `start` to the one at `end`
+    pub fn forward_rate(&self, start: f64, end: f64) -> f64 {
+        if end <= start {
+            return self.zero_rate(start);
+        }
+        (self.zero_rate(end) * end - self.zero_rate(start) * start) / (end - start)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interpolates_between_tenors_and_holds_flat_outside() {
+        let curve = RateCurve::new(&[(1.0, 0.02), (5.0, 0.04), (10.0, 0.045)]).unwrap();
+        assert_eq!(curve.zero_rate(0.5), 0.02);
+        assert!((curve.zero_rate(3.0) - 0.03).abs() < 1e-15);
+        assert_eq!(curve.zero_rate(20.0), 0.045);
+        // The forwards over consecutive periods compound back to the zero rate
+        let forwards = curve.forward_rate(0.0, 2.0) * 2.0 + curve.forward_rate(2.0, 7.0) * 5.0;
+        assert!((forwards - curve.zero_rate(7.0) * 7.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn rejects_unordered_tenors() {
+        assert!(RateCurve::new(&[(5.0, 0.04), (1.0, 0.02)]).is_err());
+        assert!(RateCurve::new(&[]).is_err());
+    }
+}
@@ -0,0 +1,345 @@
+//! Random numbers for the Monte Carlo valuations. Paths come from the valuation's seed
+//! alone, so a recalculation gives the same value and a different seed shows the
+//! simulation error. Besides pseudo-random normals there are Sobol points, which spread
+//! the paths more evenly and so converge faster, especially with the path built by a
+//! Brownian bridge so that the best spread coordinates decide its overall shape.
+
+use crate::actuarial::option_pricing::ParameterError;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rand_distr::StandardNormal;
+use std::collections::VecDeque;
+
+/// Where the normals of a simulation come from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sampling {
+    Pseudo,
+    Sobol,
+}
+
+impl Sampling {
+    /// Reads the sampling as typed in a cell: Pseudo, Sobol, P or S in any case
+    pub fn new(value: &str) -> Result<Self, ParameterError> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "pseudo" | "p" => Ok(Sampling::Pseudo),
+            "sobol" | "s" => Ok(Sampling::Sobol),
+            _ => Err(ParameterError::InvalidSampling {
+                value: value.to_string(),
+            }),
+        }
+    }
+}
+
+/// Degree and coefficients of the primitive polynomial, and the initial direction numbers,
+/// of Sobol dimensions 2 to 21 from Joe and Kuo's new-joe-kuo-6.21201 table. The first
+/// dimension is the van der Corput sequence.
+const DIRECTIONS: [(usize, u32, &[u32]); 20] = [
+    (1, 0, &[1]),
+    (2, 1, &[1, 3]),
+    (3, 1, &[1, 3, 1]),
+    (3, 2, &[1, 1, 1]),
+    (4, 1, &[1, 1, 3, 3]),
+    (4, 4, &[1, 3, 5, 13]),
+    (5, 2, &[1, 1, 5, 5, 17]),
+    (5, 4, &[1, 1, 5, 5, 5]),
+    (5, 7, &[1, 1, 7, 11, 19]),
+    (5, 11, &[1, 1, 5, 1, 1]),
+    (5, 13, &[1, 1, 1, 3, 11]),
+    (5, 14, &[1, 3, 5, 5, 31]),
+    (6, 1, &[1, 3, 3, 9, 7, 49]),
+    (6, 13, &[1, 1, 1, 15, 21, 21]),
+    (6, 16, &[1, 3, 1, 13, 27, 49]),
+    (6, 19, &[1, 1, 1, 15, 7, 5]),
+    (6, 22, &[1, 3, 1, 15, 13, 25]),
+    (6, 25, &[1, 1, 5, 5, 19, 61]),
+    (7, 1, &[1, 3, 7, 11, 23, 15, 103]),
+    (7, 4, &[1, 3, 7, 13, 13, 15, 69]),
+];
+
+/// Coordinates of a path that come from Sobol points; any more are pseudo-random
+pub const SOBOL_DIMENSIONS: usize = DIRECTIONS.len() + 1;
+
+const BITS: usize = 32;
+
+/// The direction numbers of a dimension, scaled to 32-bit fractions
+fn direction_numbers(dimension: usize) -> [u32; BITS] {
+    let mut v = [0; BITS];
+    if dimension == 0 {
+        for (bit, v) in v.iter_mut().enumerate() {
+            *v = 1 << (BITS - 1 - bit);
+        }
+        return v;
+    }
+    let (degree, coefficients, initial) = DIRECTIONS[dimension - 1];
+    for (bit, &m) in initial.iter().enumerate() {
+        v[bit] = m << (BITS - 1 - bit);
+    }
+    for bit in degree..BITS {
+        let mut x = v[bit - degree] ^ (v[bit - degree] >> degree);
+        for k in 1..degree {
+            if coefficients >> (degree - 1 - k) & 1 == 1 {
+                x ^= v[bit - k];
+            }
+        }
+        v[bit] = x;
+    }
+    v
+}
+
+/// A Sobol sequence in Gray code order, optionally scrambled by a random digital shift,
+/// which keeps the points as evenly spread while letting the seed choose the set
+#[derive(Debug, Clone)]
+pub struct Sobol {
+    directions: Vec<[u32; BITS]>,
+    shift: Vec<u32>,
+    state: Vec<u32>,
+    index: u64,
+}
+
+impl Sobol {
+    /// The sequence in up to `SOBOL_DIMENSIONS` dimensions, continuing after point `start`.
+    /// Point 0, at the origin, is never returned.
+    pub fn new(dimensions: usize, start: u64) -> Self {
+        let directions: Vec<_> = (0..dimensions.min(SOBOL_DIMENSIONS)).map(direction_numbers).collect();
+        let gray = start ^ (start >> 1);
+        let state = directions
+            .iter()
+            .map(|v| (0..BITS).filter(|&bit| gray >> bit & 1 == 1).fold(0, |x, bit| x ^ v[bit]))
+            .collect();
+        Sobol { shift: vec![0; directions.len()], directions, state, index: start }
+    }
+
+    /// The same sequence with every coordinate shifted by random bits
+    pub fn shifted(mut self, rng: &mut StdRng) -> Self {
+        for shift in self.shift.iter_mut() {
+            *shift = rng.random();
+        }
+        self
+    }
+
+    pub fn dimensions(&self) -> usize {
+        self.directions.len()
+    }
+
+    /// The next point, with coordinates strictly between 0 and 1
+    pub fn next_point(&mut self, point: &mut [f64]) {
+        self.index += 1;
+        let bit = self.index.trailing_zeros() as usize;
+        for (((x, v), shift), p) in self.state.iter_mut().zip(&self.directions).zip(&self.shift).zip(point) {
+            *x ^= v[bit];
+            *p = ((*x ^ shift) as f64 + 0.5) / (1u64 << BITS) as f64;
+        }
+    }
+}
+
+/// Standard normals for a chunk of paths, the same number for every path
+pub enum Normals {
+    Pseudo(StdRng),
+    Sobol { sobol: Sobol, uniforms: Vec<f64>, rng: StdRng },
+}
+
+impl Normals {
+    /// The normals for paths `first_path` onwards, which chunk `chunk` of a valuation with
+    /// this seed simulates. Pseudo-random chunks each have a generator seeded from the seed
+    /// and the chunk; Sobol chunks are consecutive runs of one shifted sequence.
+    pub fn new(sampling: Sampling, dimensions: usize, seed: i32, chunk: usize, first_path: u64) -> Self {
+        let seed = (seed as u32 as u64) << 32;
+        let rng = StdRng::seed_from_u64(seed | chunk as u64);
+        match sampling {
+            Sampling::Pseudo => Normals::Pseudo(rng),
+            Sampling::Sobol => {
+                // The last chunk number, which no valuation reaches, seeds the shift
+                let mut shift = StdRng::seed_from_u64(seed | u32::MAX as u64);
+                let sobol = Sobol::new(dimensions, first_path).shifted(&mut shift);
+                Normals::Sobol { uniforms: vec![0.0; sobol.dimensions()], sobol, rng }
+            }
+        }
+    }
+
+    /// One path's normals
+    pub fn fill(&mut self, normals: &mut [f64]) {
+        match self {
+            Normals::Pseudo(rng) => {
+                for z in normals.iter_mut() {
+                    *z = rng.sample(StandardNormal);
+                }
+            }
+            Normals::Sobol { sobol, uniforms, rng } => {
+                sobol.next_point(uniforms);
+                let (quasi, pseudo) = normals.split_at_mut(uniforms.len().min(normals.len()));
+                for (z, &u) in quasi.iter_mut().zip(uniforms.iter()) {
+                    *z = inverse_normal_cdf(u);
+                }
+                for z in pseudo.iter_mut() {
+                    *z = rng.sample(StandardNormal);
+                }
+            }
+        }
+    }
+}
+
+/// Builds a Brownian motion at increasing times from normals: the first sets the value at
+/// the last time, the next the one midway, and so on by halves, so the leading normals
+/// carry most of the path's variance
+#[derive(Debug, Clone)]
+pub struct BrownianBridge {
+    last_scale: f64,
+    steps: Vec<BridgeStep>,
+}
+
+/// A point filled in between two known ones, or between time 0 and a known one
+#[derive(Debug, Clone)]
+struct BridgeStep {
+    point: usize,
+    left: Option<usize>,
+    right: usize,
+    left_weight: f64,
+    right_weight: f64,
+    scale: f64,
+}
+
+impl BrownianBridge {
+    /// A bridge over times after 0, at least one of them
+    pub fn new(times: &[f64]) -> Self {
+        let last = times.len() - 1;
+        let mut steps = Vec::with_capacity(last);
+        // Points still to fill between a known point (or time 0) and a known point, widest first
+        let mut gaps = VecDeque::from([(None, last)]);
+        while let Some((left, right)) = gaps.pop_front() {
+            let first = left.map_or(0, |left| left + 1);
+            if first >= right {
+                continue;
+            }
+            let point = (first + right - 1) / 2;
+            let (start, time, end) = (left.map_or(0.0, |left| times[left]), times[point], times[right]);
+            steps.push(BridgeStep {
+                point,
+                left,
+                right,
+                left_weight: (end - time) / (end - start),
+                right_weight: (time - start) / (end - start),
+                scale: ((time - start) * (end - time) / (end - start)).sqrt(),
+            });
+            gaps.push_back((left, point));
+            gaps.push_back((Some(point), right));
+        }
+        BrownianBridge { last_scale: times[last].sqrt(), steps }
+    }
+
+    /// The Brownian motion at each time, from as many normals as there are times
+    pub fn build(&self, normals: &[f64], path: &mut [f64]) {
+        path[path.len() - 1] = self.last_scale * normals[0];
+        for (step, z) in self.steps.iter().zip(&normals[1..]) {
+            let left = step.left.map_or(0.0, |left| path[left]);
+            path[step.point] = step.left_weight * left + step.right_weight * path[step.right] + step.scale * z;
+        }
+    }
+}
+
+/// The standard normal quantile of a probability strictly between 0 and 1 (Acklam's
+/// rational approximation, accurate to about 1e-9)
+pub fn inverse_normal_cdf(p: f64) -> f64 {
+    const A: [f64; 6] = [
+        -3.969683028665376e+01, 2.209460984245205e+02, -2.759285104469687e+02,
+        1.38357751867269e+02, -3.066479806614716e+01, 2.506628277459239e+00,
+    ];
+    const B: [f64; 5] = [
+        -5.447609879822406e+01, 1.615858368580409e+02, -1.556989798598866e+02,
+        6.680131188771972e+01, -1.328068155288572e+01,
+    ];
+    const C: [f64; 6] = [
+        -7.784894002430293e-03, -3.223964580411365e-01, -2.400758277161838e+00,
+        -2.549732539343734e+00, 4.374664141464968e+00, 2.938163982698783e+00,
+    ];
+    const D: [f64; 4] = [
+        7.784695709041462e-03, 3.224671290700398e-01, 2.445134137142996e+00,
+        3.754408661907416e+00,
+    ];
+    const LOW: f64 = 0.02425;
+
+    let tail = |q: f64| {
+        (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5])
+            / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0)
+    };
+    if p < LOW {
+        tail((-2.0 * p.ln()).sqrt())
+    } else if p > 1.0 - LOW {
+        -tail((-2.0 * (1.0 - p).ln()).sqrt())
+    } else {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q
+            / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actuarial::option_pricing::normal_cdf;
+
+    #[test]
+    fn sobol_points_match_the_published_sequence() {
+        let mut sobol = Sobol::new(3, 0);
+        let mut point = [0.0; 3];
+        for expected in [[0.5, 0.5, 0.5], [0.75, 0.25, 0.25], [0.25, 0.75, 0.75], [0.375, 0.375, 0.625]] {
+            sobol.next_point(&mut point);
+            assert!(point.iter().zip(expected).all(|(p, e)| (p - e).abs() < 1e-9), "{:?}", point);
+        }
+        // Starting part way gives the same points as running up to there
+        let mut later = Sobol::new(3, 4);
+        let mut again = [0.0; 3];
+        sobol.next_point(&mut point);
+        later.next_point(&mut again);
+        assert_eq!(point, again);
+    }
+
+    #[test]
+    fn sobol_points_fill_every_interval_once() {
+        let mut sobol = Sobol::new(SOBOL_DIMENSIONS, 0);
+        let mut point = [0.0; SOBOL_DIMENSIONS];
+        let mut counts = vec![[0; 256]; SOBOL_DIMENSIONS];
+        // Points 1 to 255 and the origin, point 0, that is skipped
+        for dimension in counts.iter_mut() {
+            dimension[0] += 1;
+        }
+        for _ in 1..256 {
+            sobol.next_point(&mut point);
+            for (dimension, &x) in counts.iter_mut().zip(&point) {
+                dimension[(x * 256.0) as usize] += 1;
+            }
+        }
+        assert!(counts.iter().all(|dimension| dimension.iter().all(|&count| count == 1)));
+    }
+
+    #[test]
+    fn bridge_has_the_covariance_of_brownian_motion() {
+        let times = [0.25, 0.5, 1.0, 1.5, 2.0, 3.0];
+        let bridge = BrownianBridge::new(&times);
+        // The path is linear in the normals, so its covariance is the sum of the paths
+        // built from each unit normal
+        let paths: Vec<Vec<f64>> = (0..times.len())
+            .map(|k| {
+                let mut normals = vec![0.0; times.len()];
+                normals[k] = 1.0;
+                let mut path = vec![0.0; times.len()];
+                bridge.build(&normals, &mut path);
+                path
+            })
+            .collect();
+        for i in 0..times.len() {
+            for j in 0..times.len() {
+                let covariance: f64 = paths.iter().map(|path| path[i] * path[j]).sum();
+                assert!((covariance - times[i].min(times[j])).abs() < 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn inverse_normal_cdf_inverts_the_cdf() {
+        for p in [1e-6, 0.01, 0.02425, 0.2, 0.5, 0.7, 0.99, 1.0 - 1e-6] {
+            assert!((normal_cdf(inverse_normal_cdf(p)) - p).abs() < 2e-7, "{}", p);
+        }
+        assert!(Sampling::new("s").is_ok() && Sampling::new("halton").is_err());
+    }
+}
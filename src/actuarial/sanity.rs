@@ -0,0 +1,167 @@
+//! Cheap checks on a pair of call and put values and the inputs behind them: put-call
+//! parity, the no-arbitrage bounds on each value, and inputs that look like they were
+//! typed as percentages. Most wrong valuations come from a volatility of 30 meant as 0.30
+//! or a rate of 5 meant as 0.05, and these show up here long before anyone reads a tree.
+
+use crate::actuarial::option_pricing::{ParameterError, PositiveFloat};
+use xladd_core::variant::Variant;
+use xladd_derive::xl_func;
+
+/// Differences smaller than this fraction of the share price are taken as rounding, such
+/// as values shown to the cent
+const TOLERANCE: f64 = 1e-4;
+
+/// Volatilities above this are taken to be percentages
+const MAX_PLAUSIBLE_VOLATILITY: f64 = 3.0;
+
+/// Rates and yields above this are taken to be percentages
+const MAX_PLAUSIBLE_RATE: f64 = 0.25;
+
+/// The outcome of one check: what was checked, the number it turned on, and a warning if
+/// it failed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Check {
+    pub name: &'static str,
+    pub value: f64,
+    pub warning: Option<String>,
+}
+
+impl Check {
+    fn new(name: &'static str, value: f64, failed: bool, warning: impl FnOnce() -> String) -> Self {
+        Check { name, value, warning: failed.then(warning) }
+    }
+}
+
+/// Checks call and put values for the same strike and maturity against each other, their
+/// bounds and their inputs. American options may be exercised early, so parity only
+/// bounds the difference between them and the bounds are on undiscounted prices.
+pub fn option_checks(
+    call_value: f64,
+    put_value: f64,
+    share_price: f64,
+    strike_price: f64,
+    time_to_maturity: f64,
+    risk_free: f64,
+    div_rate: f64,
+    sigma: f64,
+    american: bool,
+) -> Result<Vec<Check>, ParameterError> {
+    for (value, parameter) in [
+        (call_value, "call_value"),
+        (put_value, "put_value"),
+        (share_price, "share_price"),
+        (strike_price, "strike_price"),
+        (time_to_maturity, "time_to_maturity"),
+        (sigma, "sigma"),
+    ] {
+        PositiveFloat::new(value, parameter)?;
+    }
+    let tolerance = TOLERANCE * share_price.max(strike_price);
+    let forward_share = share_price * (-div_rate * time_to_maturity).exp();
+    let discounted_strike = strike_price * (-risk_free * time_to_maturity).exp();
+
+    let european_difference = forward_share - discounted_strike;
+    let (parity_low, parity_high) =
+        if american { (forward_share - strike_price, share_price - discounted_strike) } else { (european_difference, european_difference) };
+    let difference = call_value - put_value;
+    let parity_gap = if difference < parity_low { difference - parity_low } else if difference > parity_high { difference - parity_high } else { 0.0 };
+
+    let exercisable = |european: f64, now: f64| if american { european.max(now) } else { european }.max(0.0);
+    let call_floor = exercisable(european_difference, share_price - strike_price);
+    let put_floor = exercisable(-european_difference, strike_price - share_price);
+    let call_cap = if american { share_price } else { forward_share };
+    let put_cap = if american { strike_price } else { discounted_strike };
+
+    Ok(vec![
+        Check::new("Put-call parity gap", parity_gap, parity_gap.abs() > tolerance, || {
+            format!("call less put is {} away from parity; are they for the same strike and maturity?", parity_gap)
+        }),
+        Check::new("Call lower bound", call_floor, call_value < call_floor - tolerance, || {
+            format!("the call is worth less than its lower bound of {}", call_floor)
+        }),
+        Check::new("Call upper bound", call_cap, call_value > call_cap + tolerance, || {
+            format!("the call is worth more than the share it buys, {}", call_cap)
+        }),
+        Check::new("Put lower bound", put_floor, put_value < put_floor - tolerance, || {
+            format!("the put is worth less than its lower bound of {}", put_floor)
+        }),
+        Check::new("Put upper bound", put_cap, put_value > put_cap + tolerance, || {
+            format!("the put is worth more than the strike it receives, {}", put_cap)
+        }),
+        Check::new("Volatility", sigma, sigma > MAX_PLAUSIBLE_VOLATILITY, || {
+            format!("a volatility of {} is {}%; enter 30% as 0.30", sigma, sigma * 100.0)
+        }),
+        Check::new("Risk-free rate", risk_free, risk_free.abs() > MAX_PLAUSIBLE_RATE, || {
+            format!("a rate of {} is {}%; enter 5% as 0.05", risk_free, risk_free * 100.0)
+        }),
+        Check::new("Dividend yield", div_rate, div_rate.abs() > MAX_PLAUSIBLE_RATE, || {
+            format!("a yield of {} is {}%; enter 2% as 0.02", div_rate, div_rate * 100.0)
+        }),
+    ])
+}
+
+/// Sanity checks on a call and a put with the same strike and maturity: put-call parity,
+/// each value's no-arbitrage bounds, and inputs that look like percentages
+/// * call_value: Value of the call
+/// * put_value: Value of the put
+/// * share_price: Current share price
+/// * strike_price: Strike price of both options
+/// * time_to_maturity: Time to maturity in years
+/// * risk_free: Risk-free interest rate (continuously compounded)
+/// * div_rate: Dividend yield (continuously compounded)
+/// * sigma: Volatility the values were calculated with
+/// * american: TRUE if the options may be exercised early, which loosens parity to a range
+/// * ret: A row per check: what was checked, the number it turned on, and OK or a warning
+#[xl_func()]
+pub fn option_sanity_check(
+    call_value: f64,
+    put_value: f64,
+    share_price: f64,
+    strike_price: f64,
+    time_to_maturity: f64,
+    risk_free: f64,
+    div_rate: f64,
+    sigma: f64,
+    american: bool,
+) -> Result<Vec<Vec<Variant>>, Box<dyn std::error::Error>> {
+    let checks = option_checks(
+        call_value, put_value, share_price, strike_price, time_to_maturity, risk_free, div_rate, sigma, american,
+    )?;
+    let mut table = vec![["Check", "Value", "Result"].map(Variant::from).to_vec()];
+    for check in checks {
+        let result = check.warning.unwrap_or_else(|| "OK".to_string());
+        table.push(vec![Variant::from(check.name), Variant::from(check.value), Variant::from(result.as_str())]);
+    }
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actuarial::option_pricing::{black_scholes_call_option_value, black_scholes_put_option_value};
+
+    fn warnings(checks: &[Check]) -> Vec<&'static str> {
+        checks.iter().filter(|check| check.warning.is_some()).map(|check| check.name).collect()
+    }
+
+    #[test]
+    fn black_scholes_values_pass_every_check() {
+        let call = black_scholes_call_option_value(100.0, 110.0, 3.0, 0.04, 0.02, 0.3);
+        let put = black_scholes_put_option_value(100.0, 110.0, 3.0, 0.04, 0.02, 0.3);
+        let checks = option_checks(call, put, 100.0, 110.0, 3.0, 0.04, 0.02, 0.3, false).unwrap();
+        assert_eq!(warnings(&checks), Vec::<&str>::new());
+        assert!(checks[0].value.abs() < 1e-12);
+        // An American put is worth more than the European one, within the American range
+        let checks = option_checks(call, put + 1.0, 100.0, 110.0, 3.0, 0.04, 0.02, 0.3, true).unwrap();
+        assert_eq!(warnings(&checks), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn percentages_and_broken_bounds_are_flagged() {
+        let checks = option_checks(5.0, 0.5, 100.0, 90.0, 1.0, 5.0, 0.02, 30.0, false).unwrap();
+        assert_eq!(warnings(&checks), ["Put-call parity gap", "Call lower bound", "Volatility", "Risk-free rate"]);
+        let table = option_sanity_check(5.0, 0.5, 100.0, 90.0, 1.0, 5.0, 0.02, 30.0, false).unwrap();
+        assert!(String::from(&table[6][2]).contains("enter 30% as 0.30"));
+        assert!(option_checks(-1.0, 1.0, 100.0, 90.0, 1.0, 0.05, 0.02, 0.3, false).is_err());
+    }
+}
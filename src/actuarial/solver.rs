@@ -0,0 +1,225 @@
+//! Goal seek over the pricers: the strike or volatility that gives an option a target
+//! value, found by Brent's method, which keeps a bracket around the answer like bisection
+//! but closes in on it by interpolation when the value behaves.
+
+use crate::actuarial::option_pricing::{binomial_option_value, PositiveFloat};
+use thiserror::Error;
+use xladd_derive::xl_func;
+
+/// Most evaluations of the pricer before giving up
+const MAX_ITERATIONS: usize = 200;
+
+/// Highest volatility searched for a target value
+const MAX_VOLATILITY: f64 = 10.0;
+
+/// An x and the function's value there
+type Point = (f64, f64);
+
+#[derive(Error, Debug)]
+pub enum SolverError {
+    #[error("No {parameter} from {low} to {high} gives a value of {target}; the values there are {value_low} to {value_high}")]
+    NotBracketed { parameter: &'static str, target: f64, low: f64, high: f64, value_low: f64, value_high: f64 },
+
+    #[error("Solving for {parameter} did not converge in {iterations} iterations")]
+    NoConvergence { parameter: &'static str, iterations: usize },
+}
+
+/// The x between `low` and `high` where `f` is zero, to within `tolerance`, by Brent's
+/// method. `f` must have opposite signs at the two ends, as `bracket` makes sure.
+pub fn brent(
+    parameter: &'static str,
+    mut f: impl FnMut(f64) -> Result<f64, Box<dyn std::error::Error>>,
+    (low, f_low): Point,
+    (high, f_high): Point,
+    tolerance: f64,
+) -> Result<f64, Box<dyn std::error::Error>> {
+    let (mut a, mut fa, mut b, mut fb) = (low, f_low, high, f_high);
+    let (mut c, mut fc) = (b, fb);
+    let (mut d, mut e) = (b - a, b - a);
+    for _ in 0..MAX_ITERATIONS {
+        if (fb > 0.0) == (fc > 0.0) {
+            // Keep the root between b and c
+            (c, fc) = (a, fa);
+            (d, e) = (b - a, b - a);
+        }
+        if fc.abs() < fb.abs() {
+            (a, fa) = (b, fb);
+            (b, fb) = (c, fc);
+            (c, fc) = (a, fa);
+        }
+        let tol = 2.0 * f64::EPSILON * b.abs() + 0.5 * tolerance;
+        let middle = 0.5 * (c - b);
+        if middle.abs() <= tol || fb == 0.0 {
+            return Ok(b);
+        }
+        if e.abs() >= tol && fa.abs() > fb.abs() {
+            // Secant step, or inverse quadratic interpolation once there are three points
+            let s = fb / fa;
+            let (mut p, mut q) = if a == c {
+                (2.0 * middle * s, 1.0 - s)
+            } else {
+                let (q, r) = (fa / fc, fb / fc);
+                (s * (2.0 * middle * q * (q - r) - (b - a) * (r - 1.0)), (q - 1.0) * (r - 1.0) * (s - 1.0))
+            };
+            if p > 0.0 {
+                q = -q;
+            } else {
+                p = -p;
+            }
+            if 2.0 * p < (3.0 * middle * q - (tol * q).abs()).min((e * q).abs()) {
+                (e, d) = (d, p / q);
+            } else {
+                (d, e) = (middle, middle);
+            }
+        } else {
+            (d, e) = (middle, middle);
+        }
+        (a, fa) = (b, fb);
+        b += if d.abs() > tol { d } else { tol.copysign(middle) };
+        fb = f(b)?;
+    }
+    Err(SolverError::NoConvergence { parameter, iterations: MAX_ITERATIONS }.into())
+}
+
+/// Ends of a bracket around a zero of `f`, with `f` at each. From `low` and `high`, `high`
+/// is doubled until `f` changes sign or passes `limit`.
+fn bracket(
+    parameter: &'static str,
+    target: f64,
+    mut f: impl FnMut(f64) -> Result<f64, Box<dyn std::error::Error>>,
+    low: f64,
+    mut high: f64,
+    limit: f64,
+) -> Result<(Point, Point), Box<dyn std::error::Error>> {
+    let f_low = f(low)?;
+    let mut f_high = f(high)?;
+    while (f_low > 0.0) == (f_high > 0.0) && high < limit {
+        high = (2.0 * high).min(limit);
+        f_high = f(high)?;
+    }
+    if (f_low > 0.0) == (f_high > 0.0) {
+        return Err(SolverError::NotBracketed {
+            parameter, target, low, high, value_low: f_low + target, value_high: f_high + target,
+        }
+        .into());
+    }
+    Ok(((low, f_low), (high, f_high)))
+}
+
+/// The strike price that gives an employee stock option a target value from the binomial
+/// tree, such as the strike for a grant of a set value
+/// * target_value: Value the option should have
+/// * share_price: Share price at grant date
+/// * time_to_maturity: Term to maturity in years
+/// * vesting_period: Term until the end of the vesting period in years
+/// * risk_free: Risk-free rate at the appropriate duration
+/// * sigma: Share volatility at the appropriate duration
+/// * div_rate: Dividend rate
+/// * exit_pre_vesting: Exit rate before the vesting date
+/// * exit_post_vesting: Exit rate after the vesting date
+/// * multiple: Multiple of the strike price at which the holder exercises
+/// * steps: Number of time steps in the tree
+/// * ret: The strike price
+#[xl_func()]
+pub fn solve_strike_for_value(
+    target_value: f64,
+    share_price: f64,
+    time_to_maturity: f64,
+    vesting_period: f64,
+    risk_free: f64,
+    sigma: f64,
+    div_rate: f64,
+    exit_pre_vesting: f64,
+    exit_post_vesting: f64,
+    multiple: f64,
+    steps: i32,
+) -> Result<f64, Box<dyn std::error::Error>> {
+    let share_price = PositiveFloat::new(share_price, "share_price")?.0;
+    let difference = |strike_price: f64| -> Result<f64, Box<dyn std::error::Error>> {
+        let value = binomial_option_value(
+            share_price, strike_price, time_to_maturity, vesting_period,
+            risk_free, sigma, div_rate,
+            exit_pre_vesting, exit_post_vesting,
+            multiple,
+            steps)?;
+        Ok(value[0] - target_value)
+    };
+    // The value falls towards zero as the strike rises, from about the share price
+    let scale = share_price.max(f64::MIN_POSITIVE);
+    let (low, high) = bracket("strike_price", target_value, difference, 1e-6 * scale, scale, 1e6 * scale)?;
+    brent("strike_price", difference, low, high, 1e-10 * scale)
+}
+
+/// The volatility that gives an employee stock option a target value from the binomial
+/// tree, such as the volatility implied by a valuation done elsewhere
+/// * target_value: Value the option should have
+/// * share_price: Share price at grant date
+/// * strike_price: Price at which the option is exercised
+/// * time_to_maturity: Term to maturity in years
+/// * vesting_period: Term until the end of the vesting period in years
+/// * risk_free: Risk-free rate at the appropriate duration
+/// * div_rate: Dividend rate
+/// * exit_pre_vesting: Exit rate before the vesting date
+/// * exit_post_vesting: Exit rate after the vesting date
+/// * multiple: Multiple of the strike price at which the holder exercises
+/// * steps: Number of time steps in the tree
+/// * ret: The volatility
+#[xl_func()]
+pub fn solve_vol_for_value(
+    target_value: f64,
+    share_price: f64,
+    strike_price: f64,
+    time_to_maturity: f64,
+    vesting_period: f64,
+    risk_free: f64,
+    div_rate: f64,
+    exit_pre_vesting: f64,
+    exit_post_vesting: f64,
+    multiple: f64,
+    steps: i32,
+) -> Result<f64, Box<dyn std::error::Error>> {
+    let difference = |sigma: f64| -> Result<f64, Box<dyn std::error::Error>> {
+        let value = binomial_option_value(
+            share_price, strike_price, time_to_maturity, vesting_period,
+            risk_free, sigma, div_rate,
+            exit_pre_vesting, exit_post_vesting,
+            multiple,
+            steps)?;
+        Ok(value[0] - target_value)
+    };
+    // Below about |r - q| √dt the tree's probabilities leave 0 to 1, so start above that
+    let dt = time_to_maturity / f64::from(steps.max(1));
+    let lowest = (2.0 * (risk_free - div_rate).abs() * dt.sqrt()).max(1e-4);
+    let (low, high) = bracket("sigma", target_value, difference, lowest, lowest.max(1.0), MAX_VOLATILITY)?;
+    brent("sigma", difference, low, high, 1e-12)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn brent_finds_roots_of_awkward_functions() {
+        let cube = |x: f64| -> Result<f64, Box<dyn std::error::Error>> { Ok((x - 1.0).powi(3)) };
+        let root = brent("x", cube, (0.0, -1.0), (3.0, 8.0), 1e-12).unwrap();
+        assert!((root - 1.0).abs() < 1e-4);
+        let step = |x: f64| -> Result<f64, Box<dyn std::error::Error>> { Ok(if x < 0.3 { -1.0 } else { 1.0 }) };
+        assert!((brent("x", step, (0.0, -1.0), (1.0, 1.0), 1e-12).unwrap() - 0.3).abs() < 1e-11);
+    }
+
+    #[test]
+    fn solvers_recover_the_inputs_of_a_value() {
+        let value = binomial_option_value(100.0, 90.0, 7.0, 3.0, 0.05, 0.35, 0.02, 0.05, 0.08, 2.5, 200).unwrap()[0];
+        let strike = solve_strike_for_value(value, 100.0, 7.0, 3.0, 0.05, 0.35, 0.02, 0.05, 0.08, 2.5, 200).unwrap();
+        assert!((strike - 90.0).abs() < 1e-6, "{}", strike);
+        let sigma = solve_vol_for_value(value, 100.0, 90.0, 7.0, 3.0, 0.05, 0.02, 0.05, 0.08, 2.5, 200).unwrap();
+        assert!((sigma - 0.35).abs() < 1e-6, "{}", sigma);
+    }
+
+    #[test]
+    fn unreachable_values_are_reported() {
+        let message = solve_strike_for_value(150.0, 100.0, 7.0, 3.0, 0.05, 0.35, 0.02, 0.05, 0.08, 2.5, 100).unwrap_err().to_string();
+        assert!(message.contains("No strike_price"), "{}", message);
+        assert!(solve_vol_for_value(0.5, 100.0, 90.0, 7.0, 3.0, 0.05, 0.02, 0.05, 0.08, 2.5, 100).is_err());
+    }
+}
@@ -0,0 +1,251 @@
+//! Historical volatility from a series of share prices, the usual starting point for a
+//! grant's volatility assumption. Returns are logs of consecutive price ratios, and every
+//! estimate is annualised by the number of returns in a year.
+
+use crate::actuarial::option_pricing::ParameterError;
+use crate::actuarial::ranges::range_numbers;
+use xladd_core::variant::Variant;
+use xladd_derive::xl_func;
+
+/// Decay of the EWMA variance per return, RiskMetrics' daily value
+const EWMA_DECAY: f64 = 0.94;
+
+/// Fewest returns a GARCH(1,1) fit is attempted on
+const MIN_GARCH_RETURNS: usize = 30;
+
+/// How a volatility is estimated from returns
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VolatilityMethod {
+    /// The sample standard deviation, every return weighted equally
+    CloseToClose,
+    /// The current volatility, weighting recent returns more (RiskMetrics)
+    Ewma,
+    /// The long-run volatility of a GARCH(1,1) fitted by maximum likelihood
+    Garch,
+}
+
+impl VolatilityMethod {
+    /// Reads the method as typed in a cell: Close-to-close, EWMA or GARCH, or C, E or G
+    pub fn new(value: &str) -> Result<Self, ParameterError> {
+        match value.trim().to_ascii_lowercase().replace([' ', '-'], "").as_str() {
+            "closetoclose" | "c" => Ok(VolatilityMethod::CloseToClose),
+            "ewma" | "e" => Ok(VolatilityMethod::Ewma),
+            "garch" | "garch(1,1)" | "g" => Ok(VolatilityMethod::Garch),
+            _ => Err(ParameterError::InvalidVolatilityMethod { value: value.to_string() }),
+        }
+    }
+}
+
+/// Returns in a year for a frequency typed in a cell: Daily, Weekly or Monthly (D, W or
+/// M), or the number itself
+pub fn periods_per_year(value: &str) -> Result<f64, ParameterError> {
+    let periods = match value.trim().to_ascii_lowercase().as_str() {
+        "daily" | "d" => 252.0,
+        "weekly" | "w" => 52.0,
+        "monthly" | "m" => 12.0,
+        other => other.parse().unwrap_or(f64::NAN),
+    };
+    if periods > 0.0 && periods.is_finite() {
+        Ok(periods)
+    } else {
+        Err(ParameterError::InvalidFrequency { value: value.to_string() })
+    }
+}
+
+/// The log returns of a price series
+pub fn log_returns(prices: &[f64]) -> Result<Vec<f64>, ParameterError> {
+    if let Some(&price) = prices.iter().find(|&&price| !(price > 0.0 && price.is_finite())) {
+        return Err(ParameterError::InvalidPositiveValue { parameter: "prices", value: price });
+    }
+    Ok(prices.windows(2).map(|pair| (pair[1] / pair[0]).ln()).collect())
+}
+
+/// The volatility per period of a series of returns
+pub fn volatility(returns: &[f64], method: VolatilityMethod) -> Result<f64, ParameterError> {
+    let few = |needed: usize| ParameterError::InvalidSchedule {
+        name: "prices",
+        reason: format!("it needs at least {} prices, got {}", needed + 1, returns.len() + 1),
+    };
+    if returns.len() < 2 {
+        return Err(few(2));
+    }
+    let n = returns.len() as f64;
+    let mean = returns.iter().sum::<f64>() / n;
+    let sample_variance = returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (n - 1.0);
+    let variance = match method {
+        VolatilityMethod::CloseToClose => sample_variance,
+        VolatilityMethod::Ewma => {
+            returns.iter().fold(sample_variance, |variance, r| EWMA_DECAY * variance + (1.0 - EWMA_DECAY) * r * r)
+        }
+        VolatilityMethod::Garch => {
+            if returns.len() < MIN_GARCH_RETURNS {
+                return Err(few(MIN_GARCH_RETURNS));
+            }
+            Garch::fit(returns, sample_variance).long_run_variance
+        }
+    };
+    Ok(variance.sqrt())
+}
+
+/// A GARCH(1,1) variance, σ²ₜ = ω + α r²ₜ₋₁ + β σ²ₜ₋₁, written in terms of its long-run
+/// variance ω / (1 - α - β)
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Garch {
+    long_run_variance: f64,
+    alpha: f64,
+    beta: f64,
+}
+
+impl Garch {
+    /// The model from unconstrained numbers: the log of the long-run variance against the
+    /// sample's, and logits of the persistence α + β and of α's share of it
+    fn from_free(free: &[f64; 3], sample_variance: f64) -> Self {
+        let logistic = |x: f64| 1.0 / (1.0 + (-x).exp());
+        let (persistence, share) = (logistic(free[1]), logistic(free[2]));
+        Garch {
+            long_run_variance: sample_variance * free[0].exp(),
+            alpha: persistence * share,
+            beta: persistence * (1.0 - share),
+        }
+    }
+
+    /// Twice the negative log-likelihood of the returns under normal innovations, leaving
+    /// out the constant, starting from the sample variance
+    fn negative_log_likelihood(&self, returns: &[f64], sample_variance: f64) -> f64 {
+        let omega = self.long_run_variance * (1.0 - self.alpha - self.beta);
+        let mut variance = sample_variance;
+        let mut total = 0.0;
+        for r in returns {
+            total += variance.ln() + r * r / variance;
+            variance = omega + self.alpha * r * r + self.beta * variance;
+        }
+        total
+    }
+
+    /// The maximum likelihood fit, from a start of typical daily persistence
+    fn fit(returns: &[f64], sample_variance: f64) -> Self {
+        let objective = |free: &[f64; 3]| {
+            let model = Garch::from_free(free, sample_variance);
+            let value = model.negative_log_likelihood(returns, sample_variance);
+            if value.is_finite() { value } else { f64::INFINITY }
+        };
+        let logit = |p: f64| (p / (1.0 - p)).ln();
+        let start = [0.0, logit(0.95), logit(0.1 / 0.95)];
+        Garch::from_free(&nelder_mead(objective, start), sample_variance)
+    }
+}
+
+/// The minimum of a function of three numbers by the Nelder-Mead simplex, from a start
+fn nelder_mead(f: impl Fn(&[f64; 3]) -> f64, start: [f64; 3]) -> [f64; 3] {
+    const MAX_ITERATIONS: usize = 2000;
+    const TOLERANCE: f64 = 1e-10;
+
+    let mut simplex: Vec<([f64; 3], f64)> = (0..4)
+        .map(|vertex| {
+            let mut point = start;
+            if vertex > 0 {
+                point[vertex - 1] += 0.5;
+            }
+            (point, f(&point))
+        })
+        .collect();
+    let towards = |from: &[f64; 3], to: &[f64; 3], by: f64| -> [f64; 3] { std::array::from_fn(|i| from[i] + by * (to[i] - from[i])) };
+
+    for _ in 0..MAX_ITERATIONS {
+        simplex.sort_by(|a, b| a.1.total_cmp(&b.1));
+        let (best, worst) = (simplex[0].1, simplex[3].1);
+        if (worst - best).abs() <= TOLERANCE * (best.abs() + TOLERANCE) {
+            break;
+        }
+        let centroid: [f64; 3] = std::array::from_fn(|i| simplex[..3].iter().map(|(point, _)| point[i]).sum::<f64>() / 3.0);
+        let worst_point = simplex[3].0;
+        let reflected = towards(&centroid, &worst_point, -1.0);
+        let reflected_value = f(&reflected);
+        if reflected_value < best {
+            let expanded = towards(&centroid, &worst_point, -2.0);
+            let expanded_value = f(&expanded);
+            simplex[3] = if expanded_value < reflected_value { (expanded, expanded_value) } else { (reflected, reflected_value) };
+        } else if reflected_value < simplex[2].1 {
+            simplex[3] = (reflected, reflected_value);
+        } else {
+            let contracted = towards(&centroid, &worst_point, 0.5);
+            let contracted_value = f(&contracted);
+            if contracted_value < worst {
+                simplex[3] = (contracted, contracted_value);
+            } else {
+                // Shrink everything towards the best point
+                let best_point = simplex[0].0;
+                for vertex in &mut simplex[1..] {
+                    vertex.0 = towards(&best_point, &vertex.0, 0.5);
+                    vertex.1 = f(&vertex.0);
+                }
+            }
+        }
+    }
+    simplex.sort_by(|a, b| a.1.total_cmp(&b.1));
+    simplex[0].0
+}
+
+/// Annualised historical volatility of a share from its prices
+/// * prices: Range of prices in date order, one per period; blank cells are skipped
+/// * frequency: Daily, Weekly or Monthly (D, W or M), or the number of prices in a year
+/// * method: Close-to-close for the sample volatility, EWMA for the current volatility with a decay of 0.94, or GARCH for the long-run volatility of a GARCH(1,1) fit (C, E or G also work)
+/// * ret: The annualised volatility
+#[xl_func()]
+pub fn hist_vol(prices: Variant, frequency: String, method: String) -> Result<f64, Box<dyn std::error::Error>> {
+    let prices = range_numbers(&prices).map_err(|reason| ParameterError::InvalidSchedule { name: "prices", reason })?;
+    let periods = periods_per_year(&frequency)?;
+    let returns = log_returns(&prices)?;
+    Ok(volatility(&returns, VolatilityMethod::new(&method)?)? * periods.sqrt())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actuarial::rng::{Normals, Sampling};
+
+    #[test]
+    fn close_to_close_and_ewma_by_hand() {
+        let prices = Variant::from(vec![vec![100.0], vec![110.0], vec![99.0], vec![99.0]]);
+        let returns = [1.1f64.ln(), 0.9f64.ln(), 0.0];
+        let mean = returns.iter().sum::<f64>() / 3.0;
+        let variance = returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / 2.0;
+        let annual = hist_vol(prices.clone(), "Weekly".to_string(), "Close-to-close".to_string()).unwrap();
+        assert!((annual - (variance * 52.0).sqrt()).abs() < 1e-12);
+
+        let ewma = returns.iter().fold(variance, |v, r| 0.94 * v + 0.06 * r * r);
+        let annual = hist_vol(prices, "12".to_string(), "EWMA".to_string()).unwrap();
+        assert!((annual - (ewma * 12.0).sqrt()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn garch_recovers_the_volatility_of_a_simulated_series() {
+        // Daily returns from a GARCH(1,1) with a long-run volatility of 30% a year
+        let (alpha, beta, long_run) = (0.08, 0.9, 0.3f64.powi(2) / 252.0);
+        let omega = long_run * (1.0 - alpha - beta);
+        let mut normals = Normals::new(Sampling::Pseudo, 1, 7, 0, 0);
+        let (mut variance, mut price, mut z) = (long_run, 100.0, [0.0]);
+        let mut prices = vec![price];
+        for _ in 0..4000 {
+            normals.fill(&mut z);
+            let r = variance.sqrt() * z[0];
+            price *= r.exp();
+            prices.push(price);
+            variance = omega + alpha * r * r + beta * variance;
+        }
+        let returns = log_returns(&prices).unwrap();
+        let fitted = Garch::fit(&returns, volatility(&returns, VolatilityMethod::CloseToClose).unwrap().powi(2));
+        assert!((fitted.alpha - alpha).abs() < 0.04 && (fitted.beta - beta).abs() < 0.06, "{:?}", fitted);
+        let annual = volatility(&returns, VolatilityMethod::Garch).unwrap() * 252f64.sqrt();
+        assert!((annual - 0.3).abs() < 0.03, "{}", annual);
+    }
+
+    #[test]
+    fn reads_methods_and_frequencies() {
+        assert_eq!(VolatilityMethod::new("GARCH(1,1)").unwrap(), VolatilityMethod::Garch);
+        assert!(VolatilityMethod::new("Parkinson").is_err());
+        assert_eq!(periods_per_year("daily").unwrap(), 252.0);
+        assert!(periods_per_year("fortnightly").is_err() && periods_per_year("-5").is_err());
+        assert!(log_returns(&[100.0, 0.0]).is_err());
+    }
+}
@@ -1,3 +1,7 @@
+use xladd_core::about;
+use xladd_core::config;
+use xladd_core::logging::{self, LogConfig};
+use xladd_core::welcome;
 use xladd_core::Reg;
 
 mod actuarial;
@@ -10,8 +14,13 @@ pub use actuarial::*;
 
 #[unsafe(no_mangle)]
 pub extern "system" fn xlAutoOpen() -> i32 {
+    logging::init(LogConfig::default()); // Writes xll_rust.log next to the xll
+    config::load();                      // Reads addin.toml next to the xll, if there is one
+    about::set(xladd_core::build_info!()); // For xl_addin_version and the About box
     let reg = Reg::new();
-    reg.register_all_functions();  // Automatically finds and registers all #[xl_func] functions
+    let summary = reg.register_all_functions(); // Automatically finds and registers all #[xl_func] functions
+    reg.register_all_commands();   // Hidden commands, e.g. the timer callback used by the scheduler
+    welcome::show_after(&summary); // Only if [welcome] in addin.toml asks for it
     1
 }
 
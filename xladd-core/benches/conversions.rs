@@ -0,0 +1,86 @@
+//! Benchmarks of the work done on every call from Excel: converting arguments in,
+//! building results and the wrapper #[xl_func] puts round each function. Run with
+//!
+//! cargo bench -p xladd-core --features testing
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use xladd_core::registrator::inventory;
+use xladd_core::testing;
+use xladd_core::variant::{Variant, VariantRef};
+use xladd_core::xlcall::LPXLOPER12;
+use xladd_derive::xl_func;
+
+#[xl_func]
+fn bench_add(a: f64, b: f64) -> f64 {
+    a + b
+}
+
+#[xl_func]
+fn bench_total(values: Vec<f64>) -> f64 {
+    values.iter().sum()
+}
+
+#[xl_func]
+fn bench_label(name: String) -> String {
+    format!("{} (checked)", name)
+}
+
+fn round_trips(c: &mut Criterion) {
+    let mut group = c.benchmark_group("round_trip");
+    group.bench_function("f64", |b| {
+        b.iter(|| {
+            let result = LPXLOPER12::from(Variant::from(black_box(1.5)));
+            let value = f64::try_from(unsafe { VariantRef::from_ptr(result) }).unwrap_or_default();
+            unsafe { testing::free_result(result) };
+            value
+        })
+    });
+    group.bench_function("string", |b| {
+        b.iter(|| {
+            let result = LPXLOPER12::from(Variant::from(black_box("a label of some length")));
+            let value = String::from(unsafe { VariantRef::from_ptr(result) });
+            unsafe { testing::free_result(result) };
+            value
+        })
+    });
+    group.finish();
+}
+
+fn arrays(c: &mut Criterion) {
+    let mut group = c.benchmark_group("arrays");
+    let grid: Vec<Vec<f64>> = (0..100).map(|row| (0..100).map(|column| (row * 100 + column) as f64).collect()).collect();
+    group.bench_function("build 100x100 numbers", |b| b.iter(|| Variant::from(black_box(grid.clone()))));
+    group.bench_function("build 100x10 strings", |b| b.iter(|| testing::strings(black_box(100), 10)));
+    let mut numbers = testing::numbers(100, 100);
+    let numbers_arg = testing::arg(&mut numbers);
+    group.bench_function("read 100x100 numbers", |b| {
+        b.iter(|| Vec::<f64>::try_from(unsafe { VariantRef::from_ptr(black_box(numbers_arg)) }).unwrap_or_default())
+    });
+    let mut strings = testing::strings(100, 10);
+    let strings_arg = testing::arg(&mut strings);
+    group.bench_function("read 100x10 strings", |b| {
+        b.iter(|| Vec::<String>::from(unsafe { VariantRef::from_ptr(black_box(strings_arg)) }))
+    });
+    group.bench_function("clone 100x100 numbers", |b| b.iter(|| black_box(&numbers).clone()));
+    group.bench_function("transpose 100x100 numbers", |b| b.iter(|| black_box(&numbers).transpose()));
+    group.finish();
+}
+
+fn wrappers(c: &mut Criterion) {
+    testing::install_stub();
+    let mut group = c.benchmark_group("wrapper");
+    let mut a = Variant::from(1.5);
+    let mut b_value = Variant::from(2.5);
+    let (a, b_value) = (testing::arg(&mut a), testing::arg(&mut b_value));
+    group.bench_function("two numbers", |b| b.iter(|| unsafe { testing::free_result(xl_bench_add(black_box(a), black_box(b_value))) }));
+    let mut grid = testing::numbers(100, 100);
+    let grid = testing::arg(&mut grid);
+    group.bench_function("100x100 numbers", |b| b.iter(|| unsafe { testing::free_result(xl_bench_total(black_box(grid))) }));
+    let mut name = Variant::from("Main book");
+    let name = testing::arg(&mut name);
+    group.bench_function("string in and out", |b| b.iter(|| unsafe { testing::free_result(xl_bench_label(black_box(name))) }));
+    group.finish();
+}
+
+criterion_group!(benches, round_trips, arrays, wrappers);
+criterion_main!(benches);
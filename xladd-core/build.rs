@@ -0,0 +1,9 @@
+//! Compiles the structured exception handler of the guard, which needs MSVC's __try and
+//! __except
+
+fn main() {
+    println!("cargo:rerun-if-changed=src/seh.c");
+    if std::env::var("CARGO_CFG_TARGET_ENV").as_deref() == Ok("msvc") {
+        cc::Build::new().file("src/seh.c").compile("xladd_seh");
+    }
+}
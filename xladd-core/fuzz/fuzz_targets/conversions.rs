@@ -0,0 +1,14 @@
+//! Converts values built from arbitrary bytes the way arguments from Excel are converted.
+//! Run from xladd-core with
+//!
+//! cargo +nightly fuzz run conversions
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use xladd_core::fuzzing::{self, FuzzValue};
+
+fuzz_target!(|data: &[u8]| {
+    let mut value = FuzzValue::from_bytes(data);
+    fuzzing::convert_all(&mut value);
+});
@@ -0,0 +1,105 @@
+//! What build of the add-in is loaded, so support can confirm it from the user's sheet
+//! with `=xl_addin_version()`, or from the About box of the `xl_about` command.
+//!
+//! The version comes from the add-in's Cargo.toml. The git hash and build time come from
+//! the `XLADD_GIT_HASH` and `XLADD_BUILD_TIME` environment variables at compile time,
+//! which the add-in's build script sets. Pass the details on from xlAutoOpen:
+//!
+//! xladd_core::about::set(xladd_core::build_info!());
+
+use crate::commands;
+use crate::registrator::{CommandRegistration, FunctionRegistration};
+use crate::variant::Variant;
+use crate::xlcall::LPXLOPER12;
+
+use std::fmt;
+use std::sync::OnceLock;
+
+/// The name, version and origin of a build
+#[derive(Debug, Clone, Copy)]
+pub struct BuildInfo {
+    pub name: &'static str,
+    pub version: &'static str,
+    /// The git commit built from, if known
+    pub git_hash: Option<&'static str>,
+    /// When the build was made, if known
+    pub built: Option<&'static str>,
+}
+
+impl fmt::Display for BuildInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.name, self.version)?;
+        match (self.git_hash, self.built) {
+            (Some(hash), Some(built)) => write!(f, " ({}, built {})", hash, built),
+            (Some(hash), None) => write!(f, " ({})", hash),
+            (None, Some(built)) => write!(f, " (built {})", built),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+/// The build details of the crate the macro is used in
+#[macro_export]
+macro_rules! build_info {
+    () => {
+        $crate::about::BuildInfo {
+            name: env!("CARGO_PKG_NAME"),
+            version: env!("CARGO_PKG_VERSION"),
+            git_hash: option_env!("XLADD_GIT_HASH"),
+            built: option_env!("XLADD_BUILD_TIME"),
+        }
+    };
+}
+
+static BUILD: OnceLock<BuildInfo> = OnceLock::new();
+
+/// Records the add-in's build details. Only the first call has any effect.
+pub fn set(info: BuildInfo) {
+    let _ = BUILD.set(info);
+}
+
+/// The add-in's build details, or those of xladd-core if the add-in has not set them
+pub fn build() -> BuildInfo {
+    *BUILD.get_or_init(|| build_info!())
+}
+
+/// Returns the name, version, git hash and build time of the add-in, such as
+/// "xll_rust 0.1.0 (3f2a9c1, built 2026-10-18 09:30 UTC)"
+#[unsafe(no_mangle)]
+pub extern "system" fn xl_addin_version() -> LPXLOPER12 {
+    LPXLOPER12::from(Variant::from(build().to_string().as_str()))
+}
+
+inventory::submit! {
+    FunctionRegistration {
+        xl_name: "xl_addin_version",
+        arg_types: "Q$",
+        arg_names: "",
+        category: "Add-in Diagnostics",
+        description: "Shows the version and build of this add-in",
+        arg_infos: &[],
+        help_topic: "",
+    }
+}
+
+/// Command that shows the build details in an alert
+#[unsafe(no_mangle)]
+pub extern "system" fn xl_about() -> i32 {
+    let build = build();
+    let mut message = format!("{}\nVersion {}", build.name, build.version);
+    if let Some(hash) = build.git_hash {
+        message.push_str(&format!("\nCommit {}", hash));
+    }
+    if let Some(built) = build.built {
+        message.push_str(&format!("\nBuilt {}", built));
+    }
+    commands::alert(&message);
+    1
+}
+
+inventory::submit! {
+    CommandRegistration {
+        xl_name: "xl_about",
+        shortcut: "",
+    }
+}
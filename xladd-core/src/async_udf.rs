@@ -0,0 +1,132 @@
+//! Asynchronous worksheet functions. Excel passes these an extra async handle argument and
+//! carries on calculating other cells; the result is delivered later through xlAsyncReturn.
+//! This module owns the tokio runtime the work runs on. Only available with the `async`
+//! feature.
+//!
+//! Declare an `async fn` with `#[xl_func]` and the macro registers it as an async UDF:
+//!
+//! #[xl_func(category="Market data")]
+//! async fn quote(symbol: String) -> Result<f64, Error> {
+//!     fetch_quote(&symbol).await
+//! }
+//!
+//! xlAsyncReturn is the one callback Excel accepts from threads other than its own, so
+//! results are returned straight from the runtime's worker threads.
+
+use crate::entrypoint::excel12;
+use crate::registrator::CommandRegistration;
+use crate::variant::Variant;
+use crate::xlcall::{xlAsyncReturn, xlEventRegister, LPXLOPER12};
+use log::debug;
+
+use std::future::Future;
+use std::sync::Mutex;
+use tokio::runtime::{Builder, Handle, Runtime};
+use tokio::task::AbortHandle;
+
+/// Event id passed to xlEventRegister for a cancelled recalculation
+const XLEVENT_CALCULATION_CANCELED: i32 = 2;
+const CANCEL_COMMAND: &str = "xl_async_calc_canceled";
+
+static RUNTIME: Mutex<Option<Runtime>> = Mutex::new(None);
+/// Tasks that may still be running, so they can be stopped if Excel cancels the calculation
+static RUNNING: Mutex<Vec<AbortHandle>> = Mutex::new(Vec::new());
+
+/// The runtime async functions run on, started the first time it is needed. Use this to
+/// spawn other background work that should share the same threads.
+pub fn runtime() -> Handle {
+    let mut runtime = RUNTIME.lock().unwrap_or_else(|e| e.into_inner());
+    runtime
+        .get_or_insert_with(|| {
+            Builder::new_multi_thread()
+                .enable_all()
+                .thread_name("xladd-async")
+                .build()
+                .expect("failed to start the async runtime")
+        })
+        .handle()
+        .clone()
+}
+
+/// The handle Excel passes to an async function, used to deliver its result
+pub struct AsyncHandle(Variant);
+
+// The handle is an opaque value that Excel allows to be returned from any thread
+unsafe impl Send for AsyncHandle {}
+
+impl AsyncHandle {
+    /// Copies the async handle argument of a function registered with an `X` argument
+    ///
+    /// # Safety
+    /// The pointer must be the async handle Excel passed to the current call
+    pub unsafe fn from_raw(handle: LPXLOPER12) -> AsyncHandle {
+        AsyncHandle(Variant::from(handle))
+    }
+
+    /// Hands the result to Excel. Returns false if Excel no longer wants it, for example
+    /// because the calculation was cancelled.
+    pub fn complete(self, value: Variant) -> bool {
+        let result = excel12(xlAsyncReturn, &mut [self.0, value]);
+        match bool::try_from(&result) {
+            Ok(true) => true,
+            _ => {
+                debug!("AsyncReturn: result = {}", result);
+                false
+            }
+        }
+    }
+}
+
+/// Runs `future` on the async runtime and returns its value to Excel when it completes
+pub fn spawn<F>(handle: AsyncHandle, future: F)
+where
+    F: Future<Output = Variant> + Send + 'static,
+{
+    let task = runtime().spawn(async move {
+        let value = future.await;
+        handle.complete(value);
+    });
+    let mut running = RUNNING.lock().unwrap_or_else(|e| e.into_inner());
+    running.retain(|task| !task.is_finished());
+    running.push(task.abort_handle());
+}
+
+fn abort_running() {
+    let running = std::mem::take(&mut *RUNNING.lock().unwrap_or_else(|e| e.into_inner()));
+    for task in running.iter() {
+        task.abort();
+    }
+}
+
+/// Asks Excel to tell us when a recalculation is cancelled. This is called from
+/// Reg::register_all_commands, once the command has been registered.
+pub(crate) fn register_events() {
+    let result = excel12(
+        xlEventRegister,
+        &mut [Variant::from(CANCEL_COMMAND), Variant::from(XLEVENT_CALCULATION_CANCELED)],
+    );
+    debug!("EventRegister({}): result = {}", CANCEL_COMMAND, result);
+}
+
+/// Command run by Excel when the user interrupts a recalculation. Excel has already
+/// dropped the pending cells, so the work behind them is stopped.
+#[unsafe(no_mangle)]
+pub extern "system" fn xl_async_calc_canceled() -> i32 {
+    abort_running();
+    1
+}
+
+inventory::submit! {
+    CommandRegistration {
+        xl_name: CANCEL_COMMAND,
+        shortcut: "",
+    }
+}
+
+/// Stops outstanding work and the runtime. This is called from xlAutoClose.
+pub fn shutdown() {
+    abort_running();
+    if let Some(runtime) = RUNTIME.lock().unwrap_or_else(|e| e.into_inner()).take() {
+        runtime.shutdown_background();
+    }
+}
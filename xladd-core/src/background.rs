@@ -0,0 +1,165 @@
+//! Background calculation for hosts without native async functions. A heavy function
+//! hands its work to [`compute`], which returns a placeholder straight away and runs the
+//! work on a pool of worker threads. The calling cell is made volatile while the work is
+//! pending, and once results are ready a timer started by [`start_recalc_timer`] asks Excel
+//! to recalculate, so the cell picks up the cached result and stops being volatile.
+//!
+//! # Example
+//!
+//! #[xl_func]
+//! fn slow_price(spot: f64, vol: f64) -> Variant {
+//!     background::compute(&format!("slow_price|{}|{}", spot, vol), move || price(spot, vol))
+//! }
+//!
+//! and in xlAutoOpen:
+//!
+//! background::start_recalc_timer(Duration::from_millis(500));
+
+use crate::entrypoint::excel12;
+use crate::guard;
+use crate::scheduler::{self, TimerId};
+use crate::variant::Variant;
+use crate::volatile;
+use crate::xlcall::{xlcCalculateNow, xlerrValue};
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The value shown in a cell while its result is being calculated
+pub const CALCULATING: &str = "#CALCULATING...";
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A finished result. Variants own their memory outright, so they can safely be handed
+/// from a worker thread to Excel's thread.
+struct Finished(Variant);
+unsafe impl Send for Finished {}
+
+enum Slot {
+    Pending,
+    Ready(Finished),
+}
+
+static RESULTS: Mutex<Option<HashMap<String, Slot>>> = Mutex::new(None);
+static WORKERS: Mutex<Option<Sender<Job>>> = Mutex::new(None);
+static RECALC_TIMER: Mutex<Option<TimerId>> = Mutex::new(None);
+/// Set when a result has arrived that Excel has not yet recalculated for
+static NEEDS_RECALC: AtomicBool = AtomicBool::new(false);
+
+fn with_results<T>(f: impl FnOnce(&mut HashMap<String, Slot>) -> T) -> T {
+    f(RESULTS.lock().unwrap_or_else(|e| e.into_inner()).get_or_insert_with(HashMap::new))
+}
+
+/// Starts the worker threads the first time they are needed, one per core
+fn submit(job: Job) {
+    let mut workers = WORKERS.lock().unwrap_or_else(|e| e.into_inner());
+    let sender = workers.get_or_insert_with(|| {
+        let (sender, receiver) = channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let count = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(2);
+        for i in 0..count {
+            let receiver = receiver.clone();
+            std::thread::Builder::new()
+                .name(format!("xladd-background-{}", i))
+                .spawn(move || loop {
+                    let job = receiver.lock().unwrap_or_else(|e| e.into_inner()).recv();
+                    match job {
+                        Ok(job) => job(),
+                        Err(_) => break, // the pool has been shut down
+                    }
+                })
+                .expect("failed to start background worker");
+        }
+        sender
+    });
+    let _ = sender.send(job);
+}
+
+/// Returns the result for `key` if it has been calculated, otherwise starts calculating it
+/// in the background and returns the [`CALCULATING`] placeholder. The key must identify
+/// the calculation, so include the function name and all the arguments. Call this from a
+/// worksheet function that is not registered as thread-safe. If the work panics or faults,
+/// the result is #VALUE!.
+pub fn compute<F, T>(key: &str, work: F) -> Variant
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Into<Variant>,
+{
+    let ready = with_results(|results| match results.get(key) {
+        Some(Slot::Ready(finished)) => Some(Some(finished.0.clone())),
+        Some(Slot::Pending) => Some(None),
+        None => {
+            results.insert(key.to_string(), Slot::Pending);
+            None
+        }
+    });
+
+    match ready {
+        Some(Some(value)) => {
+            volatile::set_volatile(false);
+            value
+        }
+        Some(None) => {
+            volatile::set_volatile(true);
+            Variant::from(CALCULATING)
+        }
+        None => {
+            let key = key.to_string();
+            submit(Box::new(move || {
+                // A panic is logged by the guard and must not end the worker thread
+                let value = guard::protect(work).map_or_else(|_| Variant::from_err(xlerrValue), Into::into);
+                with_results(|results| {
+                    // The entry may have been cleared while we were working
+                    if let Some(slot) = results.get_mut(&key) {
+                        *slot = Slot::Ready(Finished(value));
+                        NEEDS_RECALC.store(true, Ordering::Release);
+                    }
+                });
+            }));
+            volatile::set_volatile(true);
+            Variant::from(CALCULATING)
+        }
+    }
+}
+
+/// Whether a result for `key` is waiting to be picked up
+pub fn is_ready(key: &str) -> bool {
+    with_results(|results| matches!(results.get(key), Some(Slot::Ready(_))))
+}
+
+/// Forgets a cached result, so the next call calculates it again
+pub fn forget(key: &str) {
+    with_results(|results| results.remove(key));
+}
+
+/// Forgets all cached results. Work already running still finishes, but is discarded.
+pub fn clear() {
+    with_results(|results| results.clear());
+}
+
+/// Checks for finished work every `interval`, and recalculates when there is some. This
+/// must be called from a command context, such as xlAutoOpen.
+pub fn start_recalc_timer(interval: Duration) {
+    let mut timer = RECALC_TIMER.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(id) = timer.take() {
+        scheduler::cancel(id);
+    }
+    *timer = Some(scheduler::schedule_every(interval, || {
+        if NEEDS_RECALC.swap(false, Ordering::AcqRel) {
+            excel12(xlcCalculateNow, &mut []);
+        }
+    }));
+}
+
+/// Stops the recalculation timer and the worker threads. This is called from xlAutoClose.
+pub fn shutdown() {
+    if let Some(id) = RECALC_TIMER.lock().unwrap_or_else(|e| e.into_inner()).take() {
+        scheduler::cancel(id);
+    }
+    // Dropping the sender ends the workers once they finish their current job
+    WORKERS.lock().unwrap_or_else(|e| e.into_inner()).take();
+    clear();
+}
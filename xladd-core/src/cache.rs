@@ -0,0 +1,548 @@
+//! Results of expensive worksheet functions, remembered by their inputs. A function marked
+//! `#[xl_func(cache)]` returns the stored result when it is called again with the same
+//! arguments, so pure functions over inputs that rarely change are not recomputed on
+//! every F9. Each function has its own time to live and capacity, set on the attribute
+//! (`cache_ttl = 3600, cache_capacity = 500`) or at run time with [`configure`]. The
+//! `xl_cache_clear` command empties the cache, e.g. after the data behind it has changed.
+//!
+//! Only successful results are stored.
+//!
+//! With `cache_persist` on the attribute, results are also written to disk, by default
+//! under %LOCALAPPDATA%\<xll name>\cache, and read back in later Excel sessions, so
+//! reopening a workbook does not rerun long valuations whose inputs have not changed.
+//! Stored results are tied to the add-in's version, so a new release starts afresh.
+
+use crate::registrator::CommandRegistration;
+use crate::variant::{Variant, VariantRef};
+use crate::xlcall::{
+    xltypeBool, xltypeErr, xltypeInt, xltypeMask, xltypeMissing, xltypeMulti, xltypeNil, xltypeNum,
+    xltypeStr, LPXLOPER12,
+};
+use bincode::{Decode, Encode};
+use log::{debug, warn};
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use windows::Win32::Foundation::HMODULE;
+use windows::Win32::System::LibraryLoader::{
+    GetModuleFileNameW, GetModuleHandleExW, GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS,
+    GET_MODULE_HANDLE_EX_FLAG_UNCHANGED_REFCOUNT,
+};
+use windows::core::PCWSTR;
+
+const CLEAR_COMMAND: &str = "xl_cache_clear";
+/// Changed whenever the layout of the files on disk changes
+const DISK_FORMAT: u32 = 1;
+
+/// How long results of a function are kept, and how many
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheConfig {
+    /// How long a result stays valid, or None to keep it until it is cleared or evicted
+    pub ttl: Option<Duration>,
+    /// How many results are kept in memory; the least recently used is dropped to make room
+    pub capacity: usize,
+    /// Whether results are also written to disk and kept across Excel sessions
+    pub persist: bool,
+    /// Results persisted under a different version are ignored
+    pub version: &'static str,
+}
+
+impl Default for CacheConfig {
+    fn default() -> CacheConfig {
+        CacheConfig { ttl: None, capacity: DEFAULT_CAPACITY.load(Ordering::Relaxed), persist: false, version: "" }
+    }
+}
+
+/// The capacity of functions that do not set their own
+static DEFAULT_CAPACITY: AtomicUsize = AtomicUsize::new(1000);
+
+/// Changes how many results are kept by functions that do not set `cache_capacity`. This
+/// only affects functions that have not been called yet.
+pub fn set_default_capacity(capacity: usize) {
+    DEFAULT_CAPACITY.store(capacity, Ordering::Relaxed);
+}
+
+/// A stored result. Variants own their memory outright, so they can be shared between
+/// Excel's calculation threads.
+struct Cached(Variant);
+unsafe impl Send for Cached {}
+
+struct Entry {
+    value: Cached,
+    stored: Instant,
+    last_used: u64,
+}
+
+struct FunctionCache {
+    config: CacheConfig,
+    entries: HashMap<Vec<u8>, Entry>,
+    /// Counts lookups, to tell which entry was used least recently
+    clock: u64,
+}
+
+impl FunctionCache {
+    fn new(config: CacheConfig) -> FunctionCache {
+        FunctionCache { config, entries: HashMap::new(), clock: 0 }
+    }
+
+    fn evict_expired(&mut self) {
+        if let Some(ttl) = self.config.ttl {
+            self.entries.retain(|_, entry| entry.stored.elapsed() < ttl);
+        }
+    }
+}
+
+static CACHES: Mutex<Option<HashMap<&'static str, FunctionCache>>> = Mutex::new(None);
+/// Where persisted results go, or None for the default folder
+static DIRECTORY: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+fn with_cache<R>(function: &'static str, defaults: CacheConfig, f: impl FnOnce(&mut FunctionCache) -> R) -> R {
+    let mut caches = CACHES.lock().unwrap_or_else(|e| e.into_inner());
+    let cache = caches.get_or_insert_with(HashMap::new).entry(function).or_insert_with(|| FunctionCache::new(defaults));
+    f(cache)
+}
+
+/// Changes the settings for `function`, overriding those on its attribute. Stored results
+/// are kept, apart from those the new settings no longer allow.
+pub fn configure(function: &'static str, config: CacheConfig) {
+    with_cache(function, config, |cache| {
+        cache.config = config;
+        cache.evict_expired();
+        while cache.entries.len() > config.capacity {
+            evict_least_recent(cache);
+        }
+    });
+}
+
+/// Encodes the arguments Excel passed into a key that identifies them exactly. The
+/// arguments are read where they are, as Excel owns them.
+pub fn key(args: &[LPXLOPER12]) -> Vec<u8> {
+    let mut key = Vec::new();
+    for &arg in args {
+        encode(unsafe { VariantRef::from_ptr(arg) }, &mut key);
+    }
+    key
+}
+
+fn encode(value: VariantRef, key: &mut Vec<u8>) {
+    let xltype = value.xltype();
+    key.extend_from_slice(&xltype.to_le_bytes());
+    if xltype == xltypeNum {
+        let number = f64::try_from(value).unwrap_or_default();
+        key.extend_from_slice(&number.to_bits().to_le_bytes());
+    } else if xltype == xltypeMulti {
+        let (columns, rows) = value.dim();
+        key.extend_from_slice(&(columns as u32).to_le_bytes());
+        key.extend_from_slice(&(rows as u32).to_le_bytes());
+        for row in 0..rows {
+            for column in 0..columns {
+                if let Some(element) = value.at(column, row) {
+                    encode(element, key);
+                }
+            }
+        }
+    } else {
+        // Strings, booleans and errors are told apart by their type. A string's text is
+        // read from Excel's memory; the others point at nothing, so are copied bit for bit.
+        let text = if xltype == xltypeStr { String::from(value) } else { value.to_variant().to_string() };
+        key.extend_from_slice(&(text.len() as u32).to_le_bytes());
+        key.extend_from_slice(text.as_bytes());
+    }
+}
+
+/// The stored result of `function` for the arguments encoded in `key`, if there is one
+/// that has not expired. Persisted results are looked for on disk if not in memory.
+pub fn get(function: &'static str, defaults: CacheConfig, key: &[u8]) -> Option<Variant> {
+    let (found, config) = with_cache(function, defaults, |cache| {
+        cache.clock += 1;
+        let clock = cache.clock;
+        let ttl = cache.config.ttl;
+        let found = match cache.entries.get_mut(key) {
+            Some(entry) if ttl.is_some_and(|ttl| entry.stored.elapsed() >= ttl) => {
+                cache.entries.remove(key);
+                None
+            }
+            Some(entry) => {
+                entry.last_used = clock;
+                Some(entry.value.0.clone())
+            }
+            None => None,
+        };
+        (found, cache.config)
+    });
+    if found.is_some() || !config.persist {
+        return found;
+    }
+    // The disk is read outside the lock, so other functions are not held up
+    let (value, age) = load(function, &config, key)?;
+    let stored = Instant::now().checked_sub(age).unwrap_or_else(Instant::now);
+    remember(function, defaults, key.to_vec(), &value, stored);
+    Some(value)
+}
+
+/// Stores the result of `function` for the arguments encoded in `key`
+pub fn insert(function: &'static str, defaults: CacheConfig, key: Vec<u8>, value: &Variant) {
+    let config = with_cache(function, defaults, |cache| cache.config);
+    if config.persist {
+        save(function, &config, &key, value);
+    }
+    remember(function, defaults, key, value, Instant::now());
+}
+
+/// Keeps a result in memory
+fn remember(function: &'static str, defaults: CacheConfig, key: Vec<u8>, value: &Variant, stored: Instant) {
+    with_cache(function, defaults, |cache| {
+        if cache.config.capacity == 0 {
+            return;
+        }
+        if !cache.entries.contains_key(&key) && cache.entries.len() >= cache.config.capacity {
+            cache.evict_expired();
+            if cache.entries.len() >= cache.config.capacity {
+                evict_least_recent(cache);
+            }
+        }
+        cache.clock += 1;
+        let entry = Entry { value: Cached(value.clone()), stored, last_used: cache.clock };
+        cache.entries.insert(key, entry);
+    });
+}
+
+fn evict_least_recent(cache: &mut FunctionCache) {
+    let oldest = cache.entries.iter().min_by_key(|(_, entry)| entry.last_used).map(|(key, _)| key.clone());
+    if let Some(oldest) = oldest {
+        cache.entries.remove(&oldest);
+    }
+}
+
+/// Forgets the stored results of `function`, including any on disk
+pub fn clear_function(function: &str) {
+    if let Some(caches) = CACHES.lock().unwrap_or_else(|e| e.into_inner()).as_mut()
+        && let Some(cache) = caches.get_mut(function)
+    {
+        cache.entries.clear();
+    }
+    remove_folder(&directory().join(function));
+}
+
+/// Forgets all results held in memory, keeping each function's settings. Results on disk
+/// are kept for the next session; see [`clear_persisted`].
+pub fn clear() {
+    if let Some(caches) = CACHES.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+        for cache in caches.values_mut() {
+            cache.entries.clear();
+        }
+    }
+}
+
+/// How many results are stored for each function
+pub fn sizes() -> Vec<(&'static str, usize)> {
+    let caches = CACHES.lock().unwrap_or_else(|e| e.into_inner());
+    caches.iter().flatten().map(|(function, cache)| (*function, cache.entries.len())).collect()
+}
+
+/// Deletes all results written to disk
+pub fn clear_persisted() {
+    remove_folder(&directory());
+}
+
+fn remove_folder(folder: &Path) {
+    if let Err(e) = std::fs::remove_dir_all(folder)
+        && e.kind() != std::io::ErrorKind::NotFound
+    {
+        warn!("cannot delete cached results in {}: {}", folder.display(), e);
+    }
+}
+
+/// Writes persisted results to a different folder from now on. None goes back to the
+/// default, %LOCALAPPDATA%\<xll name>\cache.
+pub fn set_directory(directory: Option<PathBuf>) {
+    *DIRECTORY.lock().unwrap_or_else(|e| e.into_inner()) = directory;
+}
+
+/// The folder persisted results are written to
+pub fn directory() -> PathBuf {
+    if let Some(directory) = DIRECTORY.lock().unwrap_or_else(|e| e.into_inner()).clone() {
+        return directory;
+    }
+    let base = std::env::var_os("LOCALAPPDATA").map_or_else(std::env::temp_dir, PathBuf::from);
+    base.join(addin_name()).join("cache")
+}
+
+/// The file name of this xll without its extension. The module is found from the address
+/// of a function in it, as this may run on a calculation thread, where Excel is not asked.
+pub(crate) fn addin_name() -> String {
+    let mut module = HMODULE::default();
+    let mut name = [0u16; 260];
+    let length = unsafe {
+        let address = PCWSTR(addin_name as *const () as *const u16);
+        let flags = GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS | GET_MODULE_HANDLE_EX_FLAG_UNCHANGED_REFCOUNT;
+        match GetModuleHandleExW(flags, address, &mut module) {
+            Ok(()) => GetModuleFileNameW(Some(module), &mut name) as usize,
+            Err(_) => 0,
+        }
+    };
+    let path = PathBuf::from(String::from_utf16_lossy(&name[..length.min(name.len())]));
+    path.file_stem().map_or_else(|| "xladd".to_string(), |stem| stem.to_string_lossy().into_owned())
+}
+
+/// A result as written to disk
+#[derive(Encode, Decode)]
+enum StoredValue {
+    Number(f64),
+    Text(String),
+    Bool(bool),
+    Error(u32),
+    Nil,
+    Missing,
+    /// Values row by row
+    Array { rows: u32, columns: u32, values: Vec<StoredValue> },
+}
+
+impl StoredValue {
+    /// None for values that cannot be kept across sessions, such as references
+    fn from_variant(value: &Variant) -> Option<StoredValue> {
+        let mut copy = value.clone();
+        let xloper = copy.as_mut_xloper();
+        let xltype = xloper.xltype & xltypeMask;
+        if xltype == xltypeNum || xltype == xltypeInt {
+            f64::try_from(value).ok().map(StoredValue::Number)
+        } else if xltype == xltypeStr {
+            Some(StoredValue::Text(String::from(value)))
+        } else if xltype == xltypeBool {
+            bool::try_from(value).ok().map(StoredValue::Bool)
+        } else if xltype == xltypeErr {
+            xloper.val.as_err(xloper.xltype).map(|code| StoredValue::Error(code as u32))
+        } else if xltype == xltypeNil {
+            Some(StoredValue::Nil)
+        } else if xltype == xltypeMissing {
+            Some(StoredValue::Missing)
+        } else if xltype == xltypeMulti {
+            let (columns, rows) = value.dim();
+            let mut values = Vec::with_capacity(rows * columns);
+            for row in 0..rows {
+                for column in 0..columns {
+                    values.push(StoredValue::from_variant(&value.at(column, row))?);
+                }
+            }
+            Some(StoredValue::Array { rows: rows as u32, columns: columns as u32, values })
+        } else {
+            None
+        }
+    }
+
+    fn into_variant(self) -> Variant {
+        match self {
+            StoredValue::Number(number) => Variant::from(number),
+            StoredValue::Text(text) => Variant::from(text),
+            StoredValue::Bool(value) => Variant::from(value),
+            StoredValue::Error(code) => Variant::from_err(code),
+            StoredValue::Nil => Variant::default(),
+            StoredValue::Missing => Variant::missing(),
+            StoredValue::Array { columns, values, .. } => {
+                let mut values = values.into_iter();
+                let mut rows = Vec::new();
+                loop {
+                    let row: Vec<Variant> = values.by_ref().take(columns as usize).map(StoredValue::into_variant).collect();
+                    if row.is_empty() {
+                        break;
+                    }
+                    rows.push(row);
+                }
+                Variant::from(rows)
+            }
+        }
+    }
+}
+
+/// One persisted result, with what it was stored for
+#[derive(Encode, Decode)]
+struct DiskEntry {
+    format: u32,
+    version: String,
+    /// The encoded arguments, compared in full as the file name is only a hash of them
+    key: Vec<u8>,
+    /// Seconds since the Unix epoch
+    stored: u64,
+    value: StoredValue,
+}
+
+/// The file a result is persisted in, named by a hash of the version and arguments
+fn disk_path(function: &str, config: &CacheConfig, key: &[u8]) -> PathBuf {
+    // FNV-1a, which unlike the std hasher gives the same hash in every build
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in config.version.as_bytes().iter().chain([0u8].iter()).chain(key) {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    directory().join(function).join(format!("{:016x}.bin", hash))
+}
+
+/// Reads a persisted result with its age, deleting it if it has expired or was written
+/// by a different version
+fn load(function: &str, config: &CacheConfig, key: &[u8]) -> Option<(Variant, Duration)> {
+    let path = disk_path(function, config, key);
+    let bytes = std::fs::read(&path).ok()?;
+    let decoded = bincode::decode_from_slice::<DiskEntry, _>(&bytes, bincode::config::standard());
+    let entry = match decoded {
+        Ok((entry, _)) if entry.format == DISK_FORMAT && entry.version == config.version => entry,
+        _ => {
+            let _ = std::fs::remove_file(&path);
+            return None;
+        }
+    };
+    if entry.key != key {
+        return None; // a different set of arguments with the same hash
+    }
+    let stored = UNIX_EPOCH + Duration::from_secs(entry.stored);
+    let age = SystemTime::now().duration_since(stored).unwrap_or_default();
+    if config.ttl.is_some_and(|ttl| age >= ttl) {
+        let _ = std::fs::remove_file(&path);
+        return None;
+    }
+    Some((entry.value.into_variant(), age))
+}
+
+/// Writes a result to disk. It goes to a temporary file first, so a result being read by
+/// another Excel process is never seen half written.
+fn save(function: &str, config: &CacheConfig, key: &[u8], value: &Variant) {
+    let Some(value) = StoredValue::from_variant(value) else { return };
+    let stored = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let entry = DiskEntry { format: DISK_FORMAT, version: config.version.to_string(), key: key.to_vec(), stored, value };
+    let bytes = match bincode::encode_to_vec(&entry, bincode::config::standard()) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            warn!("cannot encode a cached result of {}: {}", function, e);
+            return;
+        }
+    };
+    let path = disk_path(function, config, key);
+    let temporary = path.with_extension(format!("{}.tmp", std::process::id()));
+    let written = path
+        .parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|()| std::fs::write(&temporary, bytes))
+        .and_then(|()| std::fs::rename(&temporary, &path));
+    if let Err(e) = written {
+        let _ = std::fs::remove_file(&temporary);
+        warn!("cannot persist a cached result of {} to {}: {}", function, path.display(), e);
+    }
+}
+
+/// Command that empties the cache, in memory and on disk. Cells showing cached results
+/// keep them until they are next calculated.
+#[unsafe(no_mangle)]
+pub extern "system" fn xl_cache_clear() -> i32 {
+    clear();
+    clear_persisted();
+    debug!("result cache cleared");
+    1
+}
+
+inventory::submit! {
+    CommandRegistration {
+        xl_name: CLEAR_COMMAND,
+        shortcut: "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(capacity: usize, ttl: Option<Duration>) -> CacheConfig {
+        CacheConfig { ttl, capacity, persist: false, version: "" }
+    }
+
+    fn number(value: Option<Variant>) -> Option<f64> {
+        value.and_then(|value| f64::try_from(&value).ok())
+    }
+
+    fn key_of(mut value: Variant) -> Vec<u8> {
+        key(&[value.as_mut_xloper()])
+    }
+
+    #[test]
+    fn least_recently_used_result_is_evicted() {
+        let two = config(2, None);
+        insert("test_lru", two, vec![1], &Variant::from(1.0));
+        insert("test_lru", two, vec![2], &Variant::from(2.0));
+        // Using the first leaves the second as the least recently used
+        assert_eq!(number(get("test_lru", two, &[1])), Some(1.0));
+        insert("test_lru", two, vec![3], &Variant::from(3.0));
+        assert_eq!(number(get("test_lru", two, &[2])), None);
+        assert_eq!(number(get("test_lru", two, &[1])), Some(1.0));
+        assert_eq!(number(get("test_lru", two, &[3])), Some(3.0));
+
+        // Shrinking the cache drops the least recently used first
+        configure("test_lru", config(1, None));
+        assert_eq!(number(get("test_lru", two, &[1])), None);
+        assert_eq!(number(get("test_lru", two, &[3])), Some(3.0));
+    }
+
+    #[test]
+    fn results_expire_after_their_time_to_live() {
+        let short = config(10, Some(Duration::from_millis(50)));
+        insert("test_ttl", short, vec![1], &Variant::from(1.0));
+        assert_eq!(number(get("test_ttl", short, &[1])), Some(1.0));
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(number(get("test_ttl", short, &[1])), None);
+        assert!(sizes().contains(&("test_ttl", 0)));
+
+        // A zero capacity keeps nothing
+        let none = config(0, None);
+        insert("test_no_capacity", none, vec![1], &Variant::from(1.0));
+        assert_eq!(number(get("test_no_capacity", none, &[1])), None);
+    }
+
+    #[test]
+    fn keys_tell_types_and_shapes_apart() {
+        assert_eq!(key_of(Variant::from(1.5)), key_of(Variant::from(1.5)));
+        assert_ne!(key_of(Variant::from(1.5)), key_of(Variant::from("1.5")));
+        assert_ne!(key_of(Variant::from(true)), key_of(Variant::from("TRUE")));
+        let row = Variant::from(vec![vec![Variant::from(1.0), Variant::from(2.0)]]);
+        let column = Variant::from(vec![vec![Variant::from(1.0)], vec![Variant::from(2.0)]]);
+        assert_ne!(key_of(row.clone()), key_of(column));
+        assert_eq!(key_of(row.clone()), key_of(row));
+    }
+
+    #[test]
+    fn persisted_results_are_read_back_for_their_version() {
+        use crate::xlcall::xlerrNA;
+
+        let folder = std::env::temp_dir().join(format!("xladd-cache-test-{}", std::process::id()));
+        set_directory(Some(folder.clone()));
+        let first = CacheConfig { ttl: None, capacity: 10, persist: true, version: "1" };
+        let second = CacheConfig { version: "2", ..first };
+        let value = Variant::from(vec![
+            vec![Variant::from(1.5), Variant::from("text"), Variant::default()],
+            vec![Variant::from(true), Variant::from_err(xlerrNA), Variant::missing()],
+        ]);
+        save("test_disk", &first, &[7], &value);
+
+        let (loaded, age) = load("test_disk", &first, &[7]).expect("the saved result");
+        assert!(age < Duration::from_secs(60));
+        assert_eq!(loaded.dim(), (3, 2));
+        for row in 0..2 {
+            for column in 0..3 {
+                assert_eq!(loaded.at(column, row).to_string(), value.at(column, row).to_string());
+            }
+        }
+
+        // The file name hashes the version and the arguments the same way in every build
+        assert_eq!(disk_path("test_disk", &first, &[7]), folder.join("test_disk").join("460be61818a744a9.bin"));
+        assert_ne!(disk_path("test_disk", &first, &[7]), disk_path("test_disk", &first, &[8]));
+        assert!(load("test_disk", &second, &[7]).is_none());
+        assert!(load("test_disk", &first, &[8]).is_none());
+
+        // An expired result is deleted
+        let expired = CacheConfig { ttl: Some(Duration::ZERO), ..first };
+        assert!(load("test_disk", &expired, &[7]).is_none());
+        assert!(!disk_path("test_disk", &first, &[7]).exists());
+
+        set_directory(None);
+        let _ = std::fs::remove_dir_all(&folder);
+    }
+}
@@ -0,0 +1,103 @@
+//! Cell areas identified by sheet and position, such as the cells calling the current
+//! worksheet function. Excel's own references point at memory Excel owns, so an area
+//! is kept as plain numbers and turned back into a reference when Excel needs one.
+
+use crate::entrypoint::excel12;
+use crate::variant::Variant;
+use crate::xlcall::{
+    xlSheetNm, xlfCaller, xlref12, xltypeMask, xltypeRef, xltypeSRef, Xloper12MRef, Xloper12Value,
+    IDSHEET, LPXLOPER12, XLMREF12, XLOPER12,
+};
+
+/// A rectangle of cells on one sheet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct CellArea {
+    /// Excel's IDSHEET, kept as a number so areas can be shared between threads
+    pub sheet: usize,
+    pub first_row: i32,
+    pub last_row: i32,
+    pub first_column: i32,
+    pub last_column: i32,
+}
+
+/// Storage for the one area of a reference made by [`CellArea::to_reference`]
+pub(crate) fn empty_area() -> XLMREF12 {
+    XLMREF12 { count: 0, reftbl: [xlref12 { rwFirst: 0, rwLast: 0, colFirst: 0, colLast: 0 }] }
+}
+
+impl CellArea {
+    /// The cells calling the current worksheet function. Commands, and functions called
+    /// from VBA or other non-cell callers, have none.
+    pub fn calling_cells() -> Option<CellArea> {
+        let caller = excel12(xlfCaller, &mut []);
+        CellArea::from_reference(&caller, None)
+    }
+
+    /// The first area of a reference. A single-sheet reference has no sheet of its own, so
+    /// it takes `sheet`, and is rejected if that is None.
+    pub fn from_reference(reference: &Variant, sheet: Option<usize>) -> Option<CellArea> {
+        let mut reference = reference.clone();
+        let xloper = reference.as_mut_xloper();
+        let xltype = xloper.xltype & xltypeMask;
+        if xltype == xltypeRef {
+            let mref = xloper.val.as_mref(xloper.xltype)?;
+            if mref.lpmref.is_null() {
+                return None;
+            }
+            let area = unsafe { (*mref.lpmref).reftbl[0] };
+            Some(CellArea::new(mref.idSheet as usize, &area))
+        } else if xltype == xltypeSRef {
+            let sref = xloper.val.as_sref(xloper.xltype)?;
+            sheet.map(|sheet| CellArea::new(sheet, &sref.ref_))
+        } else {
+            None
+        }
+    }
+
+    fn new(sheet: usize, area: &xlref12) -> CellArea {
+        CellArea {
+            sheet,
+            first_row: area.rwFirst,
+            last_row: area.rwLast,
+            first_column: area.colFirst,
+            last_column: area.colLast,
+        }
+    }
+
+    /// A reference to the area, which Excel reads but never frees. `storage` holds the
+    /// area and must outlive the reference.
+    pub fn to_reference(self, storage: &mut XLMREF12) -> XLOPER12 {
+        *storage = XLMREF12 {
+            count: 1,
+            reftbl: [xlref12 {
+                rwFirst: self.first_row,
+                rwLast: self.last_row,
+                colFirst: self.first_column,
+                colLast: self.last_column,
+            }],
+        };
+        XLOPER12 {
+            xltype: xltypeRef,
+            val: Xloper12Value { mref: Xloper12MRef { lpmref: storage, idSheet: self.sheet as IDSHEET } },
+        }
+    }
+
+    /// The sheet name and R1C1 address, e.g. "[Book1]Sheet1!R2C3"
+    pub fn address(&self) -> String {
+        let mut storage = empty_area();
+        let mut reference = self.to_reference(&mut storage);
+        let sheet = excel12(xlSheetNm, &mut [Variant::from(&mut reference as LPXLOPER12)]);
+        let mut address = format!("{}!R{}C{}", sheet, self.first_row + 1, self.first_column + 1);
+        if self.last_row != self.first_row || self.last_column != self.first_column {
+            address.push_str(&format!(":R{}C{}", self.last_row + 1, self.last_column + 1));
+        }
+        address
+    }
+
+    /// Whether this area contains the top left cell of `other`, on the same sheet
+    pub fn contains(&self, other: &CellArea) -> bool {
+        self.sheet == other.sheet
+            && (self.first_row..=self.last_row).contains(&other.first_row)
+            && (self.first_column..=self.last_column).contains(&other.first_column)
+    }
+}
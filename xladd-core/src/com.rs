@@ -0,0 +1,560 @@
+//! Minimal late-bound COM automation support, used to talk to the hosting Excel
+//! Application object for things the C API cannot do. Everything goes through
+//! IDispatch, so no type libraries are needed. Only available with the `com` feature.
+//!
+//! COM objects belong to Excel's main thread, so only use these from commands, ribbon
+//! callbacks or xlAutoOpen -- never from a worksheet function running on a calc thread.
+
+use crate::entrypoint::excel12;
+use crate::xlcall::xlGetHwnd;
+
+use std::mem::ManuallyDrop;
+use widestring::U16CString;
+use windows::Win32::Foundation::{HWND, VARIANT_BOOL};
+use windows::Win32::System::Com::{
+    CLSIDFromProgID, IDispatch, DISPATCH_FLAGS, DISPATCH_METHOD, DISPATCH_PROPERTYGET,
+    DISPATCH_PROPERTYPUT, DISPPARAMS, SAFEARRAY, SAFEARRAYBOUND,
+};
+use windows::Win32::System::Ole::{
+    GetActiveObject, SafeArrayCreate, SafeArrayGetDim, SafeArrayGetElement, SafeArrayGetLBound,
+    SafeArrayGetUBound, SafeArrayPutElement, DISPID_PROPERTYPUT,
+};
+use windows::Win32::System::Variant::{
+    VariantChangeType, VariantClear, VARIANT, VAR_CHANGE_FLAGS, VARENUM, VT_ARRAY, VT_BOOL, VT_BSTR,
+    VT_DISPATCH, VT_EMPTY, VT_ERROR, VT_I4, VT_R8, VT_VARIANT,
+};
+use windows::Win32::UI::Accessibility::AccessibleObjectFromWindow;
+use windows::Win32::UI::WindowsAndMessaging::{FindWindowExW, OBJID_NATIVEOM};
+use windows::core::{Interface, BSTR, GUID, PCWSTR, Result};
+
+/// An owned COM VARIANT, cleared when dropped
+pub struct ComVariant(VARIANT);
+
+impl ComVariant {
+    /// An empty VARIANT, used for results and to signal "no value"
+    pub fn empty() -> ComVariant {
+        ComVariant(VARIANT::default())
+    }
+
+    /// A missing optional argument, as expected by IDispatch::Invoke
+    pub fn missing() -> ComVariant {
+        let mut v = ComVariant::empty();
+        unsafe {
+            let inner = &mut v.0.Anonymous.Anonymous;
+            inner.vt = VT_ERROR;
+            inner.Anonymous.scode = 0x8002_0004u32 as i32; // DISP_E_PARAMNOTFOUND
+        }
+        v
+    }
+
+    /// An Excel error value such as #N/A, given one of the xlerr codes
+    pub fn excel_error(xlerr: u32) -> ComVariant {
+        let mut v = ComVariant::empty();
+        unsafe {
+            let inner = &mut v.0.Anonymous.Anonymous;
+            inner.vt = VT_ERROR;
+            // The same encoding as CVErr in VBA
+            inner.Anonymous.scode = (0x800A_0000u32 | (2000 + xlerr)) as i32;
+        }
+        v
+    }
+
+    fn vt(&self) -> VARENUM {
+        unsafe { self.0.Anonymous.Anonymous.vt }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vt() == VT_EMPTY
+    }
+
+    fn coerce(&self, vt: VARENUM) -> Option<ComVariant> {
+        let mut dest = ComVariant::empty();
+        unsafe { VariantChangeType(&mut dest.0, &self.0, VAR_CHANGE_FLAGS(0), vt).ok()? };
+        Some(dest)
+    }
+
+    /// Converts the value to a string using the COM coercion rules
+    pub fn to_string_value(&self) -> Option<String> {
+        let v = self.coerce(VT_BSTR)?;
+        Some(unsafe { v.0.Anonymous.Anonymous.Anonymous.bstrVal.to_string() })
+    }
+
+    /// Converts the value to a number using the COM coercion rules
+    pub fn to_f64(&self) -> Option<f64> {
+        let v = self.coerce(VT_R8)?;
+        Some(unsafe { v.0.Anonymous.Anonymous.Anonymous.dblVal })
+    }
+
+    /// Converts the value to a boolean using the COM coercion rules
+    pub fn to_bool(&self) -> Option<bool> {
+        let v = self.coerce(VT_BOOL)?;
+        Some(unsafe { v.0.Anonymous.Anonymous.Anonymous.boolVal.as_bool() })
+    }
+
+    /// Extracts an automation object, if this VARIANT contains one
+    pub fn to_dispatch(&self) -> Option<Dispatch> {
+        if self.vt() != VT_DISPATCH {
+            return None;
+        }
+        unsafe {
+            let dispatch: &Option<IDispatch> = &self.0.Anonymous.Anonymous.Anonymous.pdispVal;
+            dispatch.clone().map(Dispatch)
+        }
+    }
+
+    /// Unpacks a 2D array of values, as returned by `Range.Value` for multi-cell ranges.
+    /// A single value is returned as a 1x1 table.
+    pub fn to_table(&self) -> Vec<Vec<ComVariant>> {
+        if self.vt() != VARENUM(VT_ARRAY.0 | VT_VARIANT.0) {
+            return vec![vec![self.clone()]];
+        }
+        unsafe {
+            let array: *const SAFEARRAY = self.0.Anonymous.Anonymous.Anonymous.parray;
+            if array.is_null() || SafeArrayGetDim(array) != 2 {
+                return Vec::new();
+            }
+            let bounds = |dim| Some((SafeArrayGetLBound(array, dim).ok()?, SafeArrayGetUBound(array, dim).ok()?));
+            let (Some((row_lo, row_hi)), Some((col_lo, col_hi))) = (bounds(1), bounds(2)) else {
+                return Vec::new();
+            };
+            (row_lo..=row_hi)
+                .map(|row| {
+                    (col_lo..=col_hi)
+                        .map(|col| {
+                            let mut cell = ComVariant::empty();
+                            let indices = [row, col];
+                            let _ = SafeArrayGetElement(array, indices.as_ptr(), &mut cell.0 as *mut VARIANT as *mut _);
+                            cell
+                        })
+                        .collect()
+                })
+                .collect()
+        }
+    }
+
+    /// Packs a table of values into a 2D array, suitable for assigning to `Range.Value`.
+    /// Short rows are padded with empty values.
+    pub fn from_table(rows: &[Vec<ComVariant>]) -> ComVariant {
+        let mut v = ComVariant::empty();
+        let array = pack_table(rows, 1);
+        if !array.is_null() {
+            unsafe {
+                let inner = &mut v.0.Anonymous.Anonymous;
+                inner.vt = VARENUM(VT_ARRAY.0 | VT_VARIANT.0);
+                inner.Anonymous.parray = array;
+            }
+        }
+        v
+    }
+
+    /// Wraps a borrowed VARIANT, for example an argument passed to a COM callback
+    ///
+    /// # Safety
+    /// The pointer must point to a valid, initialized VARIANT
+    pub unsafe fn from_borrowed(v: *const VARIANT) -> ComVariant {
+        let mut copy = ComVariant::empty();
+        unsafe {
+            let _ = windows::Win32::System::Variant::VariantCopy(&mut copy.0, v);
+        }
+        copy
+    }
+
+    pub(crate) fn into_raw(self) -> VARIANT {
+        let v = ManuallyDrop::new(self);
+        unsafe { std::ptr::read(&v.0) }
+    }
+}
+
+/// Packs a table of values into a new 2D SAFEARRAY of VARIANTs, indexed [row, column] from
+/// `lower_bound`. The caller owns the array. Returns null if it could not be allocated.
+pub(crate) fn pack_table(rows: &[Vec<ComVariant>], lower_bound: i32) -> *mut SAFEARRAY {
+    let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+    let bounds = [
+        SAFEARRAYBOUND { cElements: rows.len() as u32, lLbound: lower_bound },
+        SAFEARRAYBOUND { cElements: columns as u32, lLbound: lower_bound },
+    ];
+    unsafe {
+        let array = SafeArrayCreate(VT_VARIANT, 2, bounds.as_ptr());
+        if array.is_null() {
+            return array;
+        }
+        for (r, row) in rows.iter().enumerate() {
+            for (c, cell) in row.iter().enumerate() {
+                let indices = [r as i32 + lower_bound, c as i32 + lower_bound];
+                // SafeArrayPutElement copies the VARIANT, so the source keeps ownership
+                let _ = SafeArrayPutElement(array, indices.as_ptr(), &cell.0 as *const VARIANT as *const _);
+            }
+        }
+        array
+    }
+}
+
+/// Reads a 1D SAFEARRAY of VARIANTs, such as the topic strings passed to an RTD server
+///
+/// # Safety
+/// The pointer must be null or point to a valid SAFEARRAY of VARIANTs
+#[cfg(feature = "rtd")]
+pub(crate) unsafe fn unpack_vector(array: *const SAFEARRAY) -> Vec<ComVariant> {
+    unsafe {
+        if array.is_null() || SafeArrayGetDim(array) != 1 {
+            return Vec::new();
+        }
+        let (Ok(lo), Ok(hi)) = (SafeArrayGetLBound(array, 1), SafeArrayGetUBound(array, 1)) else {
+            return Vec::new();
+        };
+        (lo..=hi)
+            .map(|i| {
+                let mut cell = ComVariant::empty();
+                let _ = SafeArrayGetElement(array, &i, &mut cell.0 as *mut VARIANT as *mut _);
+                cell
+            })
+            .collect()
+    }
+}
+
+impl Clone for ComVariant {
+    fn clone(&self) -> ComVariant {
+        unsafe { ComVariant::from_borrowed(&self.0) }
+    }
+}
+
+impl Drop for ComVariant {
+    fn drop(&mut self) {
+        let _ = unsafe { VariantClear(&mut self.0) };
+    }
+}
+
+impl From<&str> for ComVariant {
+    fn from(s: &str) -> ComVariant {
+        let mut v = ComVariant::empty();
+        unsafe {
+            let inner = &mut v.0.Anonymous.Anonymous;
+            inner.vt = VT_BSTR;
+            inner.Anonymous.bstrVal = ManuallyDrop::new(BSTR::from(s));
+        }
+        v
+    }
+}
+
+impl From<f64> for ComVariant {
+    fn from(num: f64) -> ComVariant {
+        let mut v = ComVariant::empty();
+        unsafe {
+            let inner = &mut v.0.Anonymous.Anonymous;
+            inner.vt = VT_R8;
+            inner.Anonymous.dblVal = num;
+        }
+        v
+    }
+}
+
+impl From<i32> for ComVariant {
+    fn from(num: i32) -> ComVariant {
+        let mut v = ComVariant::empty();
+        unsafe {
+            let inner = &mut v.0.Anonymous.Anonymous;
+            inner.vt = VT_I4;
+            inner.Anonymous.lVal = num;
+        }
+        v
+    }
+}
+
+impl From<bool> for ComVariant {
+    fn from(b: bool) -> ComVariant {
+        let mut v = ComVariant::empty();
+        unsafe {
+            let inner = &mut v.0.Anonymous.Anonymous;
+            inner.vt = VT_BOOL;
+            inner.Anonymous.boolVal = VARIANT_BOOL::from(b);
+        }
+        v
+    }
+}
+
+impl From<&Dispatch> for ComVariant {
+    fn from(d: &Dispatch) -> ComVariant {
+        let mut v = ComVariant::empty();
+        unsafe {
+            let inner = &mut v.0.Anonymous.Anonymous;
+            inner.vt = VT_DISPATCH;
+            inner.Anonymous.pdispVal = ManuallyDrop::new(Some(d.0.clone()));
+        }
+        v
+    }
+}
+
+/// A late-bound automation object, accessed by member name
+#[derive(Clone)]
+pub struct Dispatch(IDispatch);
+
+impl Dispatch {
+    /// Wraps an IDispatch interface pointer
+    pub fn new(dispatch: IDispatch) -> Dispatch {
+        Dispatch(dispatch)
+    }
+
+    /// The underlying interface pointer
+    pub fn as_raw(&self) -> &IDispatch {
+        &self.0
+    }
+
+    fn dispid(&self, name: &str) -> Result<i32> {
+        let wname = U16CString::from_str_truncate(name);
+        let names = [PCWSTR(wname.as_ptr())];
+        let mut dispid = 0;
+        unsafe { self.0.GetIDsOfNames(&GUID::zeroed(), names.as_ptr(), 1, 0, &mut dispid)? };
+        Ok(dispid)
+    }
+
+    fn invoke(&self, name: &str, flags: DISPATCH_FLAGS, args: Vec<ComVariant>) -> Result<ComVariant> {
+        let dispid = self.dispid(name)?;
+
+        // IDispatch expects the arguments in reverse order
+        let mut raw_args: Vec<VARIANT> = args.into_iter().rev().map(ComVariant::into_raw).collect();
+        let mut put_id = DISPID_PROPERTYPUT;
+        let params = DISPPARAMS {
+            rgvarg: raw_args.as_mut_ptr(),
+            rgdispidNamedArgs: if flags == DISPATCH_PROPERTYPUT { &mut put_id } else { std::ptr::null_mut() },
+            cArgs: raw_args.len() as u32,
+            cNamedArgs: if flags == DISPATCH_PROPERTYPUT { 1 } else { 0 },
+        };
+
+        let mut result = ComVariant::empty();
+        let outcome = unsafe {
+            self.0.Invoke(dispid, &GUID::zeroed(), 0, flags, &params, Some(&mut result.0), None, None)
+        };
+        for arg in raw_args.iter_mut() {
+            let _ = unsafe { VariantClear(arg) };
+        }
+        outcome.map(|_| result)
+    }
+
+    /// Reads a property
+    pub fn get(&self, name: &str) -> Result<ComVariant> {
+        self.invoke(name, DISPATCH_PROPERTYGET, Vec::new())
+    }
+
+    /// Reads a parameterized property, such as `Range("A1")` or `Item(1)`
+    pub fn get_with(&self, name: &str, args: Vec<ComVariant>) -> Result<ComVariant> {
+        self.invoke(name, DISPATCH_PROPERTYGET | DISPATCH_METHOD, args)
+    }
+
+    /// Reads a property that is itself an automation object
+    pub fn get_object(&self, name: &str) -> Result<Dispatch> {
+        self.get(name)?.to_dispatch().ok_or_else(|| no_object(name))
+    }
+
+    /// Writes a property
+    pub fn put(&self, name: &str, value: ComVariant) -> Result<()> {
+        self.invoke(name, DISPATCH_PROPERTYPUT, vec![value]).map(|_| ())
+    }
+
+    /// Calls a method
+    pub fn call(&self, name: &str, args: Vec<ComVariant>) -> Result<ComVariant> {
+        self.invoke(name, DISPATCH_METHOD, args)
+    }
+}
+
+fn no_object(name: &str) -> windows::core::Error {
+    windows::core::Error::new(windows::Win32::Foundation::E_NOINTERFACE, format!("{} is not an object", name))
+}
+
+/// The Excel Application object hosting this add-in, with helpers for the common
+/// workbook manipulations the C API cannot do, such as formatting cells or adding sheets.
+/// Anything not covered can be reached through [`Application::dispatch`].
+///
+/// ```ignore
+/// let app = Application::get()?;
+/// let sheet = app.add_sheet("Results")?;
+/// let header = Range::on_sheet(&sheet, "A1:B1")?;
+/// header.set_values(&[vec!["Name".into(), "Value".into()]])?;
+/// header.set_bold(true)?;
+/// ```
+#[derive(Clone)]
+pub struct Application(Dispatch);
+
+impl Application {
+    /// Looks up the hosting Excel instance. Only call this from command context.
+    pub fn get() -> Result<Application> {
+        excel_application().map(Application)
+    }
+
+    /// The raw automation object, for anything not wrapped here
+    pub fn dispatch(&self) -> &Dispatch {
+        &self.0
+    }
+
+    pub fn active_workbook(&self) -> Result<Dispatch> {
+        self.0.get_object("ActiveWorkbook")
+    }
+
+    pub fn active_sheet(&self) -> Result<Dispatch> {
+        self.0.get_object("ActiveSheet")
+    }
+
+    /// A range on the active sheet, given an A1-style address such as "B2:D10"
+    pub fn range(&self, address: &str) -> Result<Range> {
+        Range::on_sheet(&self.active_sheet()?, address)
+    }
+
+    /// The current selection, if it is a range
+    pub fn selection(&self) -> Result<Range> {
+        self.0.get_object("Selection").map(Range)
+    }
+
+    /// Adds a worksheet after the last sheet of the active workbook and gives it a name
+    pub fn add_sheet(&self, name: &str) -> Result<Dispatch> {
+        let sheets = self.active_workbook()?.get_object("Worksheets")?;
+        let count = sheets.get("Count")?.to_f64().unwrap_or(1.0) as i32;
+        let last = sheets.get_with("Item", vec![ComVariant::from(count)])?;
+        let sheet = sheets
+            .call("Add", vec![ComVariant::missing(), last])?
+            .to_dispatch()
+            .ok_or_else(|| no_object("Worksheets.Add"))?;
+        sheet.put("Name", ComVariant::from(name))?;
+        Ok(sheet)
+    }
+
+    /// A worksheet of an open workbook, by their names
+    pub fn worksheet(&self, workbook: &str, sheet: &str) -> Result<Dispatch> {
+        let workbook = self.0.get_object("Workbooks")?.get_with("Item", vec![ComVariant::from(workbook)])?;
+        let workbook = workbook.to_dispatch().ok_or_else(|| no_object("Workbooks.Item"))?;
+        let sheet = workbook.get_object("Worksheets")?.get_with("Item", vec![ComVariant::from(sheet)])?;
+        sheet.to_dispatch().ok_or_else(|| no_object("Worksheets.Item"))
+    }
+
+    /// Shows a message in the status bar, or restores the default text when `None`
+    pub fn set_status_bar(&self, text: Option<&str>) -> Result<()> {
+        self.0.put("StatusBar", text.map_or(ComVariant::from(false), ComVariant::from))
+    }
+
+    pub fn set_screen_updating(&self, on: bool) -> Result<()> {
+        self.0.put("ScreenUpdating", ComVariant::from(on))
+    }
+}
+
+/// A worksheet range
+#[derive(Clone)]
+pub struct Range(pub(crate) Dispatch);
+
+impl Range {
+    /// A range on the given worksheet, given an A1-style address
+    pub fn on_sheet(sheet: &Dispatch, address: &str) -> Result<Range> {
+        sheet
+            .get_with("Range", vec![ComVariant::from(address)])?
+            .to_dispatch()
+            .map(Range)
+            .ok_or_else(|| no_object(address))
+    }
+
+    /// The raw automation object, for anything not wrapped here
+    pub fn dispatch(&self) -> &Dispatch {
+        &self.0
+    }
+
+    pub fn address(&self) -> Result<String> {
+        Ok(self.0.get("Address")?.to_string_value().unwrap_or_default())
+    }
+
+    /// The values in the range, row by row
+    pub fn values(&self) -> Result<Vec<Vec<ComVariant>>> {
+        Ok(self.0.get("Value")?.to_table())
+    }
+
+    /// Writes a table of values, starting at the top-left cell of the range.
+    /// The range is resized to fit the table.
+    pub fn set_values(&self, rows: &[Vec<ComVariant>]) -> Result<()> {
+        if rows.is_empty() {
+            return Ok(());
+        }
+        let columns = rows.iter().map(Vec::len).max().unwrap_or(0).max(1);
+        let target = self.0.get_with("Resize", vec![ComVariant::from(rows.len() as i32), ComVariant::from(columns as i32)])?;
+        let target = target.to_dispatch().ok_or_else(|| no_object("Resize"))?;
+        target.put("Value", ComVariant::from_table(rows))
+    }
+
+    /// Writes the same value, or an A1-style formula, to every cell of the range
+    pub fn set_formula(&self, formula: &str) -> Result<()> {
+        self.0.put("Formula", ComVariant::from(formula))
+    }
+
+    /// The range of the same top-left cell with a different size
+    pub fn resize(&self, rows: usize, columns: usize) -> Result<Range> {
+        let resized = self.0.get_with("Resize", vec![ComVariant::from(rows as i32), ComVariant::from(columns as i32)])?;
+        resized.to_dispatch().map(Range).ok_or_else(|| no_object("Resize"))
+    }
+
+    /// The array formula of the range, or the formula of its top-left cell
+    pub fn formula_array(&self) -> Result<String> {
+        Ok(self.0.get("FormulaArray")?.to_string_value().unwrap_or_default())
+    }
+
+    /// Enters an A1-style formula as a single array formula over the whole range
+    pub fn set_formula_array(&self, formula: &str) -> Result<()> {
+        self.0.put("FormulaArray", ComVariant::from(formula))
+    }
+
+    pub fn clear_contents(&self) -> Result<()> {
+        self.0.call("ClearContents", Vec::new()).map(|_| ())
+    }
+
+    /// Sets the number format, for example "0.00%" or "yyyy-mm-dd"
+    pub fn set_number_format(&self, format: &str) -> Result<()> {
+        self.0.put("NumberFormat", ComVariant::from(format))
+    }
+
+    pub fn set_bold(&self, bold: bool) -> Result<()> {
+        self.0.get_object("Font")?.put("Bold", ComVariant::from(bold))
+    }
+
+    /// Sets the fill colour, given as 0xRRGGBB
+    pub fn set_fill_color(&self, rgb: u32) -> Result<()> {
+        // Excel colours are BGR
+        let bgr = ((rgb & 0xFF) << 16) | (rgb & 0xFF00) | ((rgb >> 16) & 0xFF);
+        self.0.get_object("Interior")?.put("Color", ComVariant::from(bgr as i32))
+    }
+
+    pub fn autofit_columns(&self) -> Result<()> {
+        self.0.get_object("EntireColumn")?.call("AutoFit", Vec::new()).map(|_| ())
+    }
+}
+
+/// Finds the Excel Application object hosting this add-in. This goes via the native
+/// object model of a workbook window, so it picks the right instance when several copies
+/// of Excel are running, and falls back to the running object table if no workbook
+/// window exists yet.
+pub(crate) fn excel_application() -> Result<Dispatch> {
+    if let Some(app) = application_from_window() {
+        return Ok(app);
+    }
+
+    unsafe {
+        let progid = U16CString::from_str_truncate("Excel.Application");
+        let clsid = CLSIDFromProgID(PCWSTR(progid.as_ptr()))?;
+        let mut unknown = None;
+        GetActiveObject(&clsid, None, &mut unknown)?;
+        let unknown = unknown.ok_or_else(|| no_object("Excel.Application"))?;
+        Ok(Dispatch(unknown.cast::<IDispatch>()?))
+    }
+}
+
+fn application_from_window() -> Option<Dispatch> {
+    let hwnd = i32::from(&excel12(xlGetHwnd, &mut []));
+    if hwnd == 0 {
+        return None;
+    }
+
+    unsafe {
+        let main = HWND(hwnd as isize as *mut _);
+        let desk_class = U16CString::from_str_truncate("XLDESK");
+        let desk = FindWindowExW(Some(main), None, PCWSTR(desk_class.as_ptr()), PCWSTR::null()).ok()?;
+        let window_class = U16CString::from_str_truncate("EXCEL7");
+        let window = FindWindowExW(Some(desk), None, PCWSTR(window_class.as_ptr()), PCWSTR::null()).ok()?;
+
+        let mut raw = std::ptr::null_mut();
+        AccessibleObjectFromWindow(window, OBJID_NATIVEOM.0 as u32, &IDispatch::IID, &mut raw).ok()?;
+        let window = Dispatch(IDispatch::from_raw(raw));
+        window.get_object("Application").ok()
+    }
+}
@@ -0,0 +1,153 @@
+//! In-process COM server support, shared by the ribbon and RTD servers. The xll itself
+//! serves the COM classes: each one is registered for the current user under its ProgID
+//! and CLSID, pointing at the xll, and created through the exported DllGetClassObject.
+
+use crate::entrypoint::excel12;
+use crate::xlcall::xlGetName;
+use log::warn;
+
+use std::ffi::c_void;
+use std::sync::Mutex;
+use widestring::U16CString;
+use windows::Win32::Foundation::{CLASS_E_CLASSNOTAVAILABLE, CLASS_E_NOAGGREGATION, E_POINTER, S_FALSE};
+use windows::Win32::System::Com::{IClassFactory, IClassFactory_Impl};
+use windows::Win32::System::Registry::{
+    RegCloseKey, RegCreateKeyExW, RegDeleteTreeW, RegSetValueExW, HKEY, HKEY_CURRENT_USER,
+    KEY_WRITE, REG_OPTION_NON_VOLATILE, REG_SZ, REG_VALUE_TYPE,
+};
+use windows::core::{implement, Interface, IUnknown, Ref, BOOL, GUID, HRESULT, PCWSTR, Result};
+
+/// Creates a new instance of a COM class
+type Constructor = fn() -> IUnknown;
+
+/// COM classes served by this xll, with a constructor for each
+static CLASSES: Mutex<Vec<(GUID, Constructor)>> = Mutex::new(Vec::new());
+
+/// Makes a class available to DllGetClassObject, replacing any earlier class with the same CLSID
+pub(crate) fn register_class(clsid: GUID, create: Constructor) {
+    let mut classes = CLASSES.lock().unwrap_or_else(|e| e.into_inner());
+    classes.retain(|(id, _)| *id != clsid);
+    classes.push((clsid, create));
+}
+
+pub(crate) fn unregister_class(clsid: GUID) {
+    CLASSES.lock().unwrap_or_else(|e| e.into_inner()).retain(|(id, _)| *id != clsid);
+}
+
+/// Parses a CLSID, with or without braces
+pub(crate) fn parse_clsid(clsid: &str) -> GUID {
+    GUID::try_from(clsid.trim_matches(|c| c == '{' || c == '}')).unwrap_or_else(|_| {
+        warn!("invalid CLSID {}", clsid);
+        GUID::zeroed()
+    })
+}
+
+/// Registry key of a ProgID under the current user's classes
+pub(crate) fn prog_id_key(prog_id: &str) -> String {
+    format!("Software\\Classes\\{}", prog_id)
+}
+
+/// Registry key of a CLSID under the current user's classes
+pub(crate) fn clsid_key(clsid: GUID) -> String {
+    format!("Software\\Classes\\CLSID\\{{{:?}}}", clsid)
+}
+
+/// Registers the xll as the in-process server of a class, for the current user only
+pub(crate) fn register_server(prog_id: &str, clsid: GUID) -> Result<()> {
+    let dll_name = String::from(&excel12(xlGetName, &mut []));
+    let class_key = clsid_key(clsid);
+    set_registry_value(&format!("{}\\InprocServer32", class_key), "", &dll_name)?;
+    set_registry_value(&format!("{}\\InprocServer32", class_key), "ThreadingModel", "Apartment")?;
+    set_registry_value(&format!("{}\\ProgID", class_key), "", prog_id)?;
+    set_registry_value(&format!("{}\\CLSID", prog_id_key(prog_id)), "", &format!("{{{:?}}}", clsid))
+}
+
+/// Removes the registry entries written by `register_server`
+pub(crate) fn unregister_server(prog_id: &str, clsid: GUID) {
+    delete_registry_key(&clsid_key(clsid));
+    delete_registry_key(&prog_id_key(prog_id));
+}
+
+pub(crate) fn set_registry_value(key: &str, name: &str, value: &str) -> Result<()> {
+    let data: Vec<u8> = U16CString::from_str_truncate(value)
+        .as_slice_with_nul()
+        .iter()
+        .flat_map(|c| c.to_le_bytes())
+        .collect();
+    write_registry(key, name, REG_SZ, &data)
+}
+
+#[cfg(feature = "ribbon")]
+pub(crate) fn set_registry_dword(key: &str, name: &str, value: u32) -> Result<()> {
+    write_registry(key, name, windows::Win32::System::Registry::REG_DWORD, &value.to_le_bytes())
+}
+
+fn write_registry(key: &str, name: &str, kind: REG_VALUE_TYPE, data: &[u8]) -> Result<()> {
+    let wkey = U16CString::from_str_truncate(key);
+    let wname = U16CString::from_str_truncate(name);
+    unsafe {
+        let mut hkey = HKEY::default();
+        RegCreateKeyExW(HKEY_CURRENT_USER, PCWSTR(wkey.as_ptr()), None, PCWSTR::null(),
+            REG_OPTION_NON_VOLATILE, KEY_WRITE, None, &mut hkey, None).ok()?;
+        let result = RegSetValueExW(hkey, PCWSTR(wname.as_ptr()), None, kind, Some(data)).ok();
+        let _ = RegCloseKey(hkey);
+        result
+    }
+}
+
+pub(crate) fn delete_registry_key(key: &str) {
+    let wkey = U16CString::from_str_truncate(key);
+    unsafe {
+        let _ = RegDeleteTreeW(HKEY_CURRENT_USER, PCWSTR(wkey.as_ptr()));
+    }
+}
+
+#[implement(IClassFactory)]
+struct ClassFactory {
+    create: Constructor,
+}
+
+impl IClassFactory_Impl for ClassFactory_Impl {
+    fn CreateInstance(&self, outer: Ref<'_, IUnknown>, riid: *const GUID, object: *mut *mut c_void) -> Result<()> {
+        if outer.is_some() {
+            return Err(CLASS_E_NOAGGREGATION.into());
+        }
+        let unknown = (self.create)();
+        unsafe { unknown.query(riid, object).ok() }
+    }
+
+    fn LockServer(&self, _lock: BOOL) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// COM entry point, called when Excel creates one of our COM objects from this xll
+///
+/// # Safety
+/// Called by COM with valid GUID pointers and an out pointer for the class factory
+#[unsafe(no_mangle)]
+pub unsafe extern "system" fn DllGetClassObject(clsid: *const GUID, riid: *const GUID, object: *mut *mut c_void) -> HRESULT {
+    if clsid.is_null() || object.is_null() {
+        return E_POINTER;
+    }
+    let wanted = unsafe { *clsid };
+    let create = CLASSES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .find(|(id, _)| *id == wanted)
+        .map(|(_, create)| *create);
+    match create {
+        Some(create) => {
+            let factory: IClassFactory = ClassFactory { create }.into();
+            unsafe { factory.query(riid, object) }
+        }
+        None => CLASS_E_CLASSNOTAVAILABLE,
+    }
+}
+
+/// COM entry point. The xll is unloaded by Excel, not COM, so never agree to unload.
+#[unsafe(no_mangle)]
+pub extern "system" fn DllCanUnloadNow() -> HRESULT {
+    S_FALSE
+}
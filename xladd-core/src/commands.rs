@@ -0,0 +1,110 @@
+//! Helpers for use from commands (macros), i.e. functions registered with
+//! `#[xl_command]` or run from xlAutoOpen. None of these can be called from a
+//! worksheet function.
+
+use crate::entrypoint::excel12;
+use crate::variant::Variant;
+use crate::xlcall::{xlcAlert, xlcCalculation, xlcEcho, xlcOnKey, xlfGetDocument, xlfGetWorkspace};
+use log::debug;
+
+use std::sync::Mutex;
+
+/// Keys bound by this add-in, so they can be released when the add-in closes
+static BOUND_KEYS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Shows a message in an Excel alert box with an OK button
+pub fn alert(message: &str) {
+    let result = excel12(xlcAlert, &mut [Variant::from(message), Variant::from(2)]);
+    debug!("Alert({}): result = {}", message, result);
+}
+
+/// Binds a key combination to a registered command, so pressing the keys runs it. The key
+/// text uses the xlcOnKey syntax: `^` for Ctrl, `+` for Shift, `%` for Alt, and braces
+/// for special keys, e.g. `"^+R"` or `"%{F9}"`.
+pub fn bind_key(key: &str, command: &str) {
+    let result = excel12(xlcOnKey, &mut [Variant::from(key), Variant::from(command)]);
+    debug!("OnKey({}, {}): result = {}", key, command, result);
+
+    let mut keys = BOUND_KEYS.lock().unwrap_or_else(|e| e.into_inner());
+    if !keys.iter().any(|k| k == key) {
+        keys.push(key.to_string());
+    }
+}
+
+/// Restores the normal Excel behaviour of a key combination bound by `bind_key`
+pub fn unbind_key(key: &str) {
+    let result = excel12(xlcOnKey, &mut [Variant::from(key)]);
+    debug!("OnKey({}): result = {}", key, result);
+    BOUND_KEYS.lock().unwrap_or_else(|e| e.into_inner()).retain(|k| k != key);
+}
+
+/// Releases every key bound by this add-in. This is called from xlAutoClose.
+pub fn unbind_all_keys() {
+    let keys = std::mem::take(&mut *BOUND_KEYS.lock().unwrap_or_else(|e| e.into_inner()));
+    for key in keys.iter() {
+        excel12(xlcOnKey, &mut [Variant::from(key)]);
+    }
+}
+
+// CALCULATION modes, as returned by GET.DOCUMENT(14)
+const CALC_MANUAL: i32 = 3;
+const GET_DOCUMENT_CALC_MODE: i32 = 14;
+const GET_WORKSPACE_SCREEN_UPDATING: i32 = 40;
+
+/// Turns off screen updating and automatic calculation while a command writes a lot of
+/// cells, and puts both back the way they were when dropped, including when the command
+/// returns early or panics.
+///
+/// ```ignore
+/// let _guard = ExcelStateGuard::new();
+/// // ... bulk writes ...
+/// ```
+pub struct ExcelStateGuard {
+    screen_updating: Option<bool>,
+    calc_mode: Option<i32>,
+}
+
+impl ExcelStateGuard {
+    /// Suspends both screen updating and automatic calculation
+    pub fn new() -> ExcelStateGuard {
+        Self::suspend(true, true)
+    }
+
+    /// Suspends only the parts asked for
+    pub fn suspend(screen_updating: bool, calculation: bool) -> ExcelStateGuard {
+        let mut guard = ExcelStateGuard { screen_updating: None, calc_mode: None };
+        if screen_updating {
+            let current = excel12(xlfGetWorkspace, &mut [Variant::from(GET_WORKSPACE_SCREEN_UPDATING)]);
+            guard.screen_updating = Some(bool::try_from(&current).unwrap_or(true));
+            excel12(xlcEcho, &mut [Variant::from(false)]);
+        }
+        if calculation {
+            let current = excel12(xlfGetDocument, &mut [Variant::from(GET_DOCUMENT_CALC_MODE)]);
+            match f64::try_from(&current) {
+                Ok(mode) => {
+                    guard.calc_mode = Some(mode as i32);
+                    excel12(xlcCalculation, &mut [Variant::from(CALC_MANUAL)]);
+                }
+                Err(_) => debug!("GetDocument(14): result = {}", current),
+            }
+        }
+        guard
+    }
+}
+
+impl Default for ExcelStateGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for ExcelStateGuard {
+    fn drop(&mut self) {
+        if let Some(mode) = self.calc_mode.take() {
+            excel12(xlcCalculation, &mut [Variant::from(mode)]);
+        }
+        if let Some(on) = self.screen_updating.take() {
+            excel12(xlcEcho, &mut [Variant::from(on)]);
+        }
+    }
+}
@@ -0,0 +1,481 @@
+//! Settings read at startup from `addin.toml` in the same folder as the xll, so a
+//! deployment can be tuned without recompiling. The file is optional, as is every entry
+//! in it:
+//!
+//! [functions]
+//! prefix = "acme"                  # xl_npv is registered as acme_npv
+//! negotiate_prefix = true          # own prefix if another xladd add-in has this one's
+//! language = "fr"                  # the language of Function Wizard text, if not Excel's
+//! deferred = true                  # register all but the core functions after startup
+//! core = ["xl_npv", "Math"]        # functions or categories to register at startup
+//! batch_size = 50
+//! on_collision = "rename"          # or "skip", for functions whose name is already taken
+//! collision_suffix = "_acme"
+//! category_root = "Acme"           # puts every category under Acme, e.g. "Acme | Math"
+//! category_separator = " | "       # between the levels of a category
+//! help_url = "https://docs.acme.com/functions/{name}"
+//! overrides = "D:\\Acme\\functions.json" # changes to descriptions and categories
+//!
+//! [categories]
+//! "Math" = "Analytics | Math"      # moves functions to another Function Wizard category
+//!
+//! [log]
+//! level = "debug"                  # off, error, warn, info, debug or trace
+//! path = "C:\\Logs\\acme.log"
+//! debugger = false
+//!
+//! [cache]
+//! capacity = 500                   # for functions that do not set cache_capacity
+//! directory = "D:\\AcmeCache"
+//!
+//! [pool]
+//! threads = 4
+//!
+//! [welcome]
+//! show = true                      # a welcome notice once a session, until dismissed
+//! message = "Functions are under Acme in the Function Wizard"
+//! support_url = "https://support.acme.com/addin"
+//!
+//! [features]
+//! experimental_curves = true       # read with Config::feature
+//!
+//! [settings]
+//! pricing_server = "https://..."   # anything else the add-in wants, read with Config::setting
+//!
+//! Call [`load`] from xlAutoOpen after the logger is installed, and before registering
+//! functions, as the prefix and categories apply to registration.
+//!
+//! Environment variables override the file, which is the quickest switch to flip on a
+//! user's machine when something goes wrong:
+//!
+//! * `XLADD_CONFIG` - a settings file to read instead of the one next to the xll
+//! * `XLADD_LOG_LEVEL`, `XLADD_LOG_PATH` - as `level` and `path` under `[log]`
+//! * `XLADD_PREFIX`, `XLADD_LANGUAGE` - as `prefix` and `language` under `[functions]`
+//! * `XLADD_DISABLE` - functions, categories or groups not to register, separated by commas
+//! * `XLADD_DRY_RUN` - set to 1 to log what would be registered without registering it
+//! * `XLADD_CACHE_CAPACITY`, `XLADD_POOL_THREADS` - as under `[cache]` and `[pool]`
+
+use crate::cache;
+use crate::entrypoint::excel12;
+use crate::logging;
+use crate::manifest;
+use crate::namespace;
+use crate::registrator;
+use crate::xlcall::xlGetName;
+use log::{info, warn, LevelFilter};
+use serde::de::DeserializeOwned;
+use serde_derive::Deserialize;
+use thiserror::Error;
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// The name of the file looked for next to the xll
+pub const FILE_NAME: &str = "addin.toml";
+
+/// The prefix the xl_func macro gives function names by default
+const DEFAULT_PREFIX: &str = "xl_";
+/// Written between the levels of a category unless the settings say otherwise
+const DEFAULT_CATEGORY_SEPARATOR: &str = " | ";
+/// The start of the names of environment variables that override settings
+const ENV_PREFIX: &str = "XLADD_";
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("Cannot read {0}: {1}")]
+    Read(PathBuf, std::io::Error),
+    #[error("Invalid settings in {0}: {1}")]
+    Parse(PathBuf, Box<toml::de::Error>),
+}
+
+/// Renaming of registered functions
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct FunctionSettings {
+    /// Replaces the default `xl` prefix of function names
+    pub prefix: Option<String>,
+    /// Takes a prefix made from the xll's file name if another add-in built on xladd
+    /// already uses this one's, true unless given. See [namespace](crate::namespace).
+    pub negotiate_prefix: Option<bool>,
+    /// The language of the [translations](crate::locale) to register with, instead of Excel's
+    pub language: Option<String>,
+    /// Functions not to register, by exported name, category or [group](crate::groups)
+    pub disabled: Vec<String>,
+    /// Logs each registration instead of making it
+    pub dry_run: bool,
+    /// Registers only the `core` functions in xlAutoOpen, and the rest a batch at a time
+    /// once Excel has started
+    pub deferred: bool,
+    /// With `deferred`, the functions or categories registered straight away
+    pub core: Vec<String>,
+    /// With `deferred`, how many functions are registered at a time
+    pub batch_size: Option<usize>,
+    /// The top level of every category
+    pub category_root: Option<String>,
+    /// Written between the levels of a category, " | " unless given
+    pub category_separator: Option<String>,
+    /// What happens to a function whose name is already taken
+    pub on_collision: Collision,
+    /// With `on_collision = "rename"`, added to a name that is taken, "_2" unless given
+    pub collision_suffix: Option<String>,
+    /// Help page of functions that do not give their own, with `{name}` standing for the
+    /// function's name in Excel
+    pub help_url: Option<String>,
+    /// A [function override file](crate::manifest) to read instead of the one next to the xll
+    pub overrides: Option<PathBuf>,
+}
+
+/// What to do when a function's name in Excel is taken, by another function of the add-in
+/// or by another add-in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Collision {
+    /// Register the function under its name with the collision suffix
+    #[default]
+    Rename,
+    /// Leave the function unregistered
+    Skip,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct LogSettings {
+    pub level: Option<String>,
+    pub path: Option<PathBuf>,
+    pub debugger: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct CacheSettings {
+    /// How many results each cached function keeps, unless its attribute says otherwise
+    pub capacity: Option<usize>,
+    /// Where persisted results are written
+    pub directory: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct PoolSettings {
+    pub threads: Option<usize>,
+}
+
+/// The [welcome](crate::welcome) notice shown after the functions are registered
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct WelcomeSettings {
+    pub show: bool,
+    /// Shown under the name and version of the add-in
+    pub message: Option<String>,
+    pub support_url: Option<String>,
+}
+
+/// The contents of `addin.toml`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub functions: FunctionSettings,
+    /// Function Wizard categories, mapped from the name given in code to the name shown
+    pub categories: HashMap<String, String>,
+    pub log: LogSettings,
+    pub cache: CacheSettings,
+    pub pool: PoolSettings,
+    pub welcome: WelcomeSettings,
+    /// Switches for optional behaviour of the add-in
+    pub features: HashMap<String, bool>,
+    /// Values for the add-in's own use
+    pub settings: toml::Table,
+}
+
+impl Config {
+    /// Reads settings from a file
+    pub fn from_file(path: &Path) -> Result<Config, ConfigError> {
+        let text = std::fs::read_to_string(path).map_err(|e| ConfigError::Read(path.to_path_buf(), e))?;
+        toml::from_str(&text).map_err(|e| ConfigError::Parse(path.to_path_buf(), Box::new(e)))
+    }
+
+    /// Whether a feature is switched on. Features not in the file are off.
+    pub fn feature(&self, name: &str) -> bool {
+        self.features.get(name).copied().unwrap_or(false)
+    }
+
+    /// A value from the `[settings]` table, or None if it is missing or of the wrong type
+    pub fn setting<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let value = self.settings.get(key)?.clone();
+        match value.try_into() {
+            Ok(value) => Some(value),
+            Err(e) => {
+                warn!("setting {} in {} is not valid: {}", key, FILE_NAME, e);
+                None
+            }
+        }
+    }
+
+    /// The name a function is registered under in Excel. Functions with the default `xl_`
+    /// prefix get the configured one instead; those named in code are left alone.
+    pub fn excel_name(&self, name: &str) -> String {
+        match (&self.functions.prefix, name.strip_prefix(DEFAULT_PREFIX)) {
+            (Some(prefix), Some(rest)) if !prefix.is_empty() => format!("{}_{}", prefix, rest),
+            _ => name.to_string(),
+        }
+    }
+
+    /// The Function Wizard category a function is shown in. Categories can have levels,
+    /// written in code with any of `|`, `/` or `::` between them. The longest leading
+    /// levels with an entry in `[categories]` are replaced by it, the levels are joined
+    /// with the configured separator, and the root goes in front.
+    pub fn category(&self, category: &str) -> String {
+        let separator = self.functions.category_separator.as_deref().unwrap_or(DEFAULT_CATEGORY_SEPARATOR);
+        let mut levels = category_levels(category);
+        let mapping = (1..=levels.len()).rev().find_map(|mapped| {
+            let (_, to) = self.categories.iter().find(|(from, _)| category_levels(from) == levels[..mapped])?;
+            Some((mapped, to))
+        });
+        if let Some((mapped, to)) = mapping {
+            levels.splice(..mapped, category_levels(to));
+        }
+        if let Some(root) = self.functions.category_root.as_deref().filter(|root| !root.is_empty())
+            && levels.first().map(String::as_str) != Some(root)
+        {
+            levels.insert(0, root.to_string());
+        }
+        levels.join(separator)
+    }
+
+    /// The help topic of a function without one of its own, from `help_url`
+    pub fn help_topic(&self, name: &str) -> Option<String> {
+        let url = self.functions.help_url.as_deref().filter(|url| !url.is_empty())?;
+        Some(registrator::help_topic(&url.replace("{name}", name)))
+    }
+
+    /// Whether a function is left unregistered, by its own name, its category or its group
+    pub fn is_disabled(&self, name: &str, category: &str, group: Option<&str>) -> bool {
+        self.functions.disabled.iter().any(|disabled| {
+            disabled.eq_ignore_ascii_case(name) || disabled == category || Some(disabled.as_str()) == group
+        })
+    }
+
+    /// Whether a function waits to be registered after startup, when registration is
+    /// deferred
+    pub fn is_deferred(&self, name: &str, category: &str) -> bool {
+        self.functions.deferred
+            && !self.functions.core.iter().any(|core| core.eq_ignore_ascii_case(name) || core == category)
+    }
+
+    /// Overrides settings with any `XLADD_` environment variables that are set
+    pub fn apply_environment(&mut self) {
+        if let Some(level) = environment("LOG_LEVEL") {
+            self.log.level = Some(level);
+        }
+        if let Some(path) = environment("LOG_PATH") {
+            self.log.path = Some(PathBuf::from(path));
+        }
+        if let Some(prefix) = environment("PREFIX") {
+            self.functions.prefix = Some(prefix);
+        }
+        if let Some(language) = environment("LANGUAGE") {
+            self.functions.language = Some(language);
+        }
+        if let Some(disabled) = environment("DISABLE") {
+            let names = disabled.split(',').map(str::trim).filter(|name| !name.is_empty());
+            self.functions.disabled.extend(names.map(str::to_string));
+        }
+        if let Some(dry_run) = environment("DRY_RUN") {
+            self.functions.dry_run = matches!(dry_run.to_ascii_lowercase().as_str(), "1" | "true" | "yes" | "on");
+        }
+        if let Some(capacity) = environment("CACHE_CAPACITY") {
+            match capacity.parse() {
+                Ok(capacity) => self.cache.capacity = Some(capacity),
+                Err(_) => warn!("{}CACHE_CAPACITY is not a number: {}", ENV_PREFIX, capacity),
+            }
+        }
+        if let Some(threads) = environment("POOL_THREADS") {
+            match threads.parse() {
+                Ok(threads) => self.pool.threads = Some(threads),
+                Err(_) => warn!("{}POOL_THREADS is not a number: {}", ENV_PREFIX, threads),
+            }
+        }
+    }
+}
+
+/// The levels of a category, trimmed, without empty ones
+fn category_levels(category: &str) -> Vec<String> {
+    category
+        .replace("::", "|")
+        .split(['|', '/'])
+        .map(str::trim)
+        .filter(|level| !level.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// The value of an `XLADD_` environment variable, if it is set and not empty
+fn environment(name: &str) -> Option<String> {
+    let variable = format!("{}{}", ENV_PREFIX, name);
+    let value = std::env::var(&variable).ok().filter(|value| !value.trim().is_empty())?;
+    info!("{} is set to {}", variable, value);
+    Some(value.trim().to_string())
+}
+
+static CONFIG: Mutex<Option<Arc<Config>>> = Mutex::new(None);
+static PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// The settings in force. Until [`load`] is called, or if there is no file, these are
+/// the defaults.
+pub fn current() -> Arc<Config> {
+    CONFIG.lock().unwrap_or_else(|e| e.into_inner()).get_or_insert_with(Default::default).clone()
+}
+
+/// The file the settings were read from, if there was one
+pub fn path() -> Option<PathBuf> {
+    PATH.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Reads `addin.toml` from the xll's folder, if it is there, overrides it from the
+/// environment, reads any [function overrides](crate::manifest), and applies its log,
+/// cache and thread pool settings. A file that cannot be read is logged and the defaults
+/// are used. This can only be called from xlAutoOpen or a command, as it asks Excel for
+/// the xll's path.
+pub fn load() -> Arc<Config> {
+    let dll_name = String::from(&excel12(xlGetName, &mut []));
+    let path = match environment("CONFIG") {
+        Some(path) => Some(PathBuf::from(path)),
+        None if dll_name.is_empty() => None,
+        None => Some(Path::new(&dll_name).with_file_name(FILE_NAME)).filter(|path| path.exists()),
+    };
+    *PATH.lock().unwrap_or_else(|e| e.into_inner()) = None;
+    let mut config = match path {
+        None => Config::default(),
+        Some(path) => match Config::from_file(&path) {
+            Ok(config) => {
+                info!("settings read from {}", path.display());
+                *PATH.lock().unwrap_or_else(|e| e.into_inner()) = Some(path);
+                config
+            }
+            Err(e) => {
+                warn!("{}; using the default settings", e);
+                Config::default()
+            }
+        },
+    };
+    config.apply_environment();
+    if config.functions.negotiate_prefix != Some(false)
+        && !dll_name.is_empty()
+        && let Some(prefix) = namespace::negotiate(&dll_name, config.functions.prefix.as_deref())
+    {
+        config.functions.prefix = Some(prefix);
+    }
+    manifest::load_default_overrides(&config, &dll_name);
+    set(config)
+}
+
+/// Puts settings in force, as if they had been read from the file
+pub fn set(config: Config) -> Arc<Config> {
+    apply(&config);
+    let config = Arc::new(config);
+    *CONFIG.lock().unwrap_or_else(|e| e.into_inner()) = Some(config.clone());
+    config
+}
+
+/// Passes settings on to the parts of the add-in they control
+fn apply(config: &Config) {
+    if let Some(level) = &config.log.level {
+        match level.parse::<LevelFilter>() {
+            Ok(level) => logging::set_level(level),
+            Err(_) => warn!("unknown log level {} in {}", level, FILE_NAME),
+        }
+    }
+    if config.log.path.is_some() {
+        logging::set_path(config.log.path.clone());
+    }
+    if let Some(debugger) = config.log.debugger {
+        logging::set_debugger(debugger);
+    }
+    if let Some(capacity) = config.cache.capacity {
+        cache::set_default_capacity(capacity);
+    }
+    if config.cache.directory.is_some() {
+        cache::set_directory(config.cache.directory.clone());
+    }
+    #[cfg(feature = "rayon")]
+    if let Some(threads) = config.pool.threads {
+        crate::pool::set_threads(threads);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SETTINGS: &str = r#"
+        [functions]
+        prefix = "acme"
+        disabled = ["XL_SLOW", "Experimental"]
+        deferred = true
+        core = ["xl_npv", "Math"]
+
+        [cache]
+        capacity = 500
+
+        [features]
+        experimental_curves = true
+
+        [settings]
+        pricing_server = "https://prices"
+        retries = 3
+    "#;
+
+    fn parse(text: &str) -> Config {
+        toml::from_str(text).expect("valid settings")
+    }
+
+    #[test]
+    fn reads_settings_and_leaves_the_rest_at_their_defaults() {
+        let config = parse(SETTINGS);
+        assert_eq!(config.cache.capacity, Some(500));
+        assert_eq!(config.pool.threads, None);
+        assert_eq!(config.functions.on_collision, Collision::Rename);
+        assert!(config.feature("experimental_curves"));
+        assert!(!config.feature("anything_else"));
+        assert_eq!(config.setting::<String>("pricing_server").as_deref(), Some("https://prices"));
+        assert_eq!(config.setting::<u32>("retries"), Some(3));
+        assert_eq!(config.setting::<u32>("pricing_server"), None);
+    }
+
+    #[test]
+    fn names_functions_and_picks_those_to_register() {
+        let config = parse(SETTINGS);
+        assert_eq!(config.excel_name("xl_npv"), "acme_npv");
+        assert_eq!(config.excel_name("price_bond"), "price_bond");
+        assert_eq!(Config::default().excel_name("xl_npv"), "xl_npv");
+
+        assert!(config.is_disabled("xl_slow", "Math", None));
+        assert!(config.is_disabled("xl_new", "Experimental", None));
+        assert!(!config.is_disabled("xl_npv", "Math", Some("curves")));
+
+        assert!(!config.is_deferred("XL_NPV", "Finance"));
+        assert!(!config.is_deferred("xl_sum", "Math"));
+        assert!(config.is_deferred("xl_irr", "Finance"));
+        assert!(!Config::default().is_deferred("xl_irr", "Finance"));
+    }
+
+    #[test]
+    fn environment_variables_override_the_file() {
+        // No other test reads XLADD_ variables, so setting them here races with nothing
+        unsafe {
+            std::env::set_var("XLADD_PREFIX", " corp ");
+            std::env::set_var("XLADD_DISABLE", "xl_a, ,Beta");
+            std::env::set_var("XLADD_DRY_RUN", "Yes");
+            std::env::set_var("XLADD_POOL_THREADS", "many");
+            std::env::set_var("XLADD_CACHE_CAPACITY", "");
+        }
+        let mut config = parse(SETTINGS);
+        config.apply_environment();
+        assert_eq!(config.excel_name("xl_npv"), "corp_npv");
+        assert_eq!(config.functions.disabled, ["XL_SLOW", "Experimental", "xl_a", "Beta"]);
+        assert!(config.functions.dry_run);
+        assert_eq!(config.pool.threads, None);
+        assert_eq!(config.cache.capacity, Some(500));
+    }
+}
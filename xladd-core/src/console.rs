@@ -0,0 +1,262 @@
+//! A live log window. The `xl_toggle_console` command opens a window showing the add-in's
+//! log as it is written, starting with the most recent lines, and closes it again. This is
+//! the easiest way to see what an add-in is doing on a user's machine, with nothing else to
+//! install. The window runs its own message loop on a separate thread, so it stays
+//! responsive while Excel is busy calculating.
+
+use crate::registrator::CommandRegistration;
+use log::warn;
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use widestring::U16CString;
+use windows::Win32::Foundation::{HWND, LPARAM, LRESULT, RECT, WPARAM};
+use windows::Win32::Graphics::Gdi::{GetStockObject, ANSI_FIXED_FONT};
+use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows::Win32::UI::Controls::{EM_REPLACESEL, EM_SETLIMITTEXT, EM_SETSEL};
+use windows::Win32::UI::WindowsAndMessaging::{
+    CreateWindowExW, DefWindowProcW, DispatchMessageW, GetClientRect, GetMessageW,
+    GetWindowTextLengthW, MoveWindow, PostMessageW, PostQuitMessage, RegisterClassW,
+    SendMessageW, SetForegroundWindow, ShowWindow, TranslateMessage, UnregisterClassW,
+    CW_USEDEFAULT, ES_AUTOVSCROLL, ES_MULTILINE, ES_READONLY, MSG, SW_SHOW, WINDOW_EX_STYLE,
+    WINDOW_STYLE, WM_APP, WM_CLOSE, WM_DESTROY, WM_SETFONT, WM_SIZE, WNDCLASSW, WS_CHILD,
+    WS_OVERLAPPEDWINDOW, WS_VISIBLE, WS_VSCROLL,
+};
+use windows::core::PCWSTR;
+
+/// How many recent lines are kept to fill a newly opened window
+const HISTORY_LINES: usize = 1000;
+/// The window drops its oldest text when it holds more than this many characters
+const MAX_WINDOW_CHARS: i32 = 4 * 1024 * 1024;
+/// Posted to the window when there are new lines to show
+const WM_NEW_LINES: u32 = WM_APP + 1;
+const TOGGLE_COMMAND: &str = "xl_toggle_console";
+
+struct Console {
+    /// The top-level window, or zero while it is being created
+    window: isize,
+    thread: Option<JoinHandle<()>>,
+}
+
+/// Recent log lines, and the lines the window has not shown yet
+struct Lines {
+    history: VecDeque<String>,
+    unseen: Vec<String>,
+}
+
+static CONSOLE: Mutex<Option<Console>> = Mutex::new(None);
+static LINES: Mutex<Lines> = Mutex::new(Lines { history: VecDeque::new(), unseen: Vec::new() });
+/// The edit control showing the log, only touched on the window's thread
+static EDIT: Mutex<isize> = Mutex::new(0);
+
+/// Passes a log line to the console. This is called by the logger for every line written,
+/// whether or not the window is open.
+pub(crate) fn append(line: &str) {
+    {
+        let mut lines = LINES.lock().unwrap_or_else(|e| e.into_inner());
+        if lines.history.len() == HISTORY_LINES {
+            lines.history.pop_front();
+        }
+        lines.history.push_back(line.to_string());
+    }
+    let window = CONSOLE.lock().unwrap_or_else(|e| e.into_inner()).as_ref().map_or(0, |c| c.window);
+    if window != 0 {
+        LINES.lock().unwrap_or_else(|e| e.into_inner()).unseen.push(line.to_string());
+        unsafe {
+            let _ = PostMessageW(Some(HWND(window as *mut _)), WM_NEW_LINES, WPARAM(0), LPARAM(0));
+        }
+    }
+}
+
+/// Whether the window is open
+pub fn is_open() -> bool {
+    CONSOLE.lock().unwrap_or_else(|e| e.into_inner()).as_ref().is_some_and(|c| c.window != 0)
+}
+
+/// Opens the window, or brings it to the front if it is already open
+pub fn open() {
+    let mut console = CONSOLE.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(current) = console.as_ref() {
+        if current.window != 0 {
+            unsafe {
+                let _ = SetForegroundWindow(HWND(current.window as *mut _));
+            }
+            return;
+        }
+        if current.thread.as_ref().is_some_and(|thread| !thread.is_finished()) {
+            return; // still being created
+        }
+    }
+    // The user closed the previous window, so its thread has finished
+    if let Some(thread) = console.take().and_then(|previous| previous.thread) {
+        let _ = thread.join();
+    }
+    let thread = std::thread::Builder::new().name("xladd-console".to_string()).spawn(run_window);
+    match thread {
+        Ok(thread) => *console = Some(Console { window: 0, thread: Some(thread) }),
+        Err(e) => warn!("cannot start the log console: {}", e),
+    }
+}
+
+/// Closes the window and waits for its thread to finish. This is called from xlAutoClose,
+/// as the thread must not outlive the xll.
+pub fn close() {
+    let thread = loop {
+        let mut console = CONSOLE.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(current) = console.as_mut() else { return };
+        let finished = current.thread.as_ref().is_none_or(|thread| thread.is_finished());
+        if current.window != 0 || finished {
+            if current.window != 0 {
+                unsafe {
+                    let _ = PostMessageW(Some(HWND(current.window as *mut _)), WM_CLOSE, WPARAM(0), LPARAM(0));
+                }
+            }
+            break current.thread.take();
+        }
+        // The window is still being created, so wait until there is one to close
+        drop(console);
+        std::thread::sleep(Duration::from_millis(10));
+    };
+    // The lock is released first, as the window thread takes it while closing down
+    if let Some(thread) = thread {
+        let _ = thread.join();
+    }
+    CONSOLE.lock().unwrap_or_else(|e| e.into_inner()).take();
+}
+
+/// Opens the window if it is closed, and closes it if it is open
+pub fn toggle() {
+    if is_open() {
+        close();
+    } else {
+        open();
+    }
+}
+
+/// Command that shows or hides the log console
+#[unsafe(no_mangle)]
+pub extern "system" fn xl_toggle_console() -> i32 {
+    toggle();
+    1
+}
+
+inventory::submit! {
+    CommandRegistration {
+        xl_name: TOGGLE_COMMAND,
+        shortcut: "",
+    }
+}
+
+fn run_window() {
+    // Named after our window procedure, so each xll in the process has its own class
+    let class_name = U16CString::from_str_truncate(format!("XladdLogConsole{:x}", window_proc as *const () as usize));
+    let title = U16CString::from_str_truncate("Add-in log");
+    let edit_class = U16CString::from_str_truncate("EDIT");
+    unsafe {
+        let instance = GetModuleHandleW(PCWSTR::null()).unwrap_or_default();
+        let class = WNDCLASSW {
+            lpfnWndProc: Some(window_proc),
+            hInstance: instance.into(),
+            lpszClassName: PCWSTR(class_name.as_ptr()),
+            ..Default::default()
+        };
+        // Fails harmlessly if the class is still registered from an earlier window
+        RegisterClassW(&class);
+
+        let window = CreateWindowExW(WINDOW_EX_STYLE(0), PCWSTR(class_name.as_ptr()), PCWSTR(title.as_ptr()),
+            WS_OVERLAPPEDWINDOW, CW_USEDEFAULT, CW_USEDEFAULT, 900, 500, None, None, Some(instance.into()), None);
+        let window = match window {
+            Ok(window) => window,
+            Err(e) => {
+                warn!("cannot create the log console: {}", e);
+                CONSOLE.lock().unwrap_or_else(|e| e.into_inner()).take();
+                return;
+            }
+        };
+        let edit_style = WS_CHILD | WS_VISIBLE | WS_VSCROLL
+            | WINDOW_STYLE((ES_MULTILINE | ES_READONLY | ES_AUTOVSCROLL) as u32);
+        if let Ok(edit) = CreateWindowExW(WINDOW_EX_STYLE(0), PCWSTR(edit_class.as_ptr()), PCWSTR::null(),
+            edit_style, 0, 0, 0, 0, Some(window), None, Some(instance.into()), None)
+        {
+            SendMessageW(edit, WM_SETFONT, Some(WPARAM(GetStockObject(ANSI_FIXED_FONT).0 as usize)), Some(LPARAM(1)));
+            SendMessageW(edit, EM_SETLIMITTEXT, Some(WPARAM(0)), Some(LPARAM(0)));
+            *EDIT.lock().unwrap_or_else(|e| e.into_inner()) = edit.0 as isize;
+            let history: Vec<String> = LINES.lock().unwrap_or_else(|e| e.into_inner()).history.iter().cloned().collect();
+            show_lines(edit, &history);
+        }
+        resize_edit(window);
+        let _ = ShowWindow(window, SW_SHOW);
+        if let Some(console) = CONSOLE.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+            console.window = window.0 as isize;
+        }
+
+        let mut message = MSG::default();
+        while GetMessageW(&mut message, None, 0, 0).as_bool() {
+            let _ = TranslateMessage(&message);
+            DispatchMessageW(&message);
+        }
+
+        *EDIT.lock().unwrap_or_else(|e| e.into_inner()) = 0;
+        let _ = UnregisterClassW(PCWSTR(class_name.as_ptr()), Some(instance.into()));
+    }
+}
+
+unsafe extern "system" fn window_proc(window: HWND, message: u32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    match message {
+        WM_NEW_LINES => {
+            let unseen = std::mem::take(&mut LINES.lock().unwrap_or_else(|e| e.into_inner()).unseen);
+            let edit = *EDIT.lock().unwrap_or_else(|e| e.into_inner());
+            if edit != 0 {
+                show_lines(HWND(edit as *mut _), &unseen);
+            }
+            LRESULT(0)
+        }
+        WM_SIZE => {
+            resize_edit(window);
+            LRESULT(0)
+        }
+        WM_DESTROY => {
+            // The thread finishes once the message loop ends
+            if let Some(console) = CONSOLE.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+                console.window = 0;
+            }
+            LINES.lock().unwrap_or_else(|e| e.into_inner()).unseen.clear();
+            unsafe { PostQuitMessage(0) };
+            LRESULT(0)
+        }
+        _ => unsafe { DefWindowProcW(window, message, wparam, lparam) },
+    }
+}
+
+fn resize_edit(window: HWND) {
+    let edit = *EDIT.lock().unwrap_or_else(|e| e.into_inner());
+    if edit == 0 {
+        return;
+    }
+    let mut client = RECT::default();
+    unsafe {
+        if GetClientRect(window, &mut client).is_ok() {
+            let _ = MoveWindow(HWND(edit as *mut _), 0, 0, client.right, client.bottom, true);
+        }
+    }
+}
+
+/// Adds lines to the end of the edit control, dropping old text once it gets long
+fn show_lines(edit: HWND, lines: &[String]) {
+    if lines.is_empty() {
+        return;
+    }
+    let text = U16CString::from_str_truncate(lines.concat());
+    unsafe {
+        if GetWindowTextLengthW(edit) > MAX_WINDOW_CHARS {
+            SendMessageW(edit, EM_SETSEL, Some(WPARAM(0)), Some(LPARAM((MAX_WINDOW_CHARS / 2) as isize)));
+            let empty = U16CString::default();
+            SendMessageW(edit, EM_REPLACESEL, Some(WPARAM(0)), Some(LPARAM(empty.as_ptr() as isize)));
+        }
+        let end = GetWindowTextLengthW(edit) as usize;
+        SendMessageW(edit, EM_SETSEL, Some(WPARAM(end)), Some(LPARAM(end as isize)));
+        SendMessageW(edit, EM_REPLACESEL, Some(WPARAM(0)), Some(LPARAM(text.as_ptr() as isize)));
+    }
+}
@@ -0,0 +1,107 @@
+//! Coalescing of rapid repeated calls. A function marked `#[xl_func(debounce = 500)]` that
+//! is called again with the same arguments within 500 milliseconds of its last result gets
+//! that result back instead of running again. This keeps volatile functions, and those
+//! recalculated on every edit or event, from hammering an external service while the
+//! user types, without holding on to results the way the [cache](crate::cache) does.
+//!
+//! Errors are remembered as well, so a failing service is not retried on every keystroke.
+//! Calls that arrive while the first one is still running are not held back.
+
+use crate::variant::Variant;
+use log::trace;
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// A result Excel was given. Variants own their memory outright, so they can be shared
+/// between Excel's calculation threads.
+struct Recent(Variant);
+unsafe impl Send for Recent {}
+
+struct FunctionWindow {
+    window: Duration,
+    results: HashMap<Vec<u8>, (Instant, Recent)>,
+}
+
+static WINDOWS: Mutex<Option<HashMap<&'static str, FunctionWindow>>> = Mutex::new(None);
+
+fn with_window<R>(function: &'static str, default: Duration, f: impl FnOnce(&mut FunctionWindow) -> R) -> R {
+    let mut windows = WINDOWS.lock().unwrap_or_else(|e| e.into_inner());
+    let window = windows
+        .get_or_insert_with(HashMap::new)
+        .entry(function)
+        .or_insert_with(|| FunctionWindow { window: default, results: HashMap::new() });
+    f(window)
+}
+
+/// Changes how long results of `function` are reused, overriding its attribute. A zero
+/// window turns debouncing off.
+pub fn set_window(function: &'static str, window: Duration) {
+    with_window(function, window, |function| {
+        function.window = window;
+        function.results.retain(|_, (stored, _)| stored.elapsed() < window);
+    });
+}
+
+/// The result `function` last returned for these arguments, if it is recent enough.
+/// The key is made by [`cache::key`](crate::cache::key).
+pub fn recent(function: &'static str, default: Duration, key: &[u8]) -> Option<Variant> {
+    with_window(function, default, |window| {
+        let (stored, result) = window.results.get(key)?;
+        if stored.elapsed() < window.window {
+            trace!("{} called again within {:?}, returning its last result", function, window.window);
+            Some(result.0.clone())
+        } else {
+            None
+        }
+    })
+}
+
+/// Remembers what `function` returned for these arguments, dropping results that are too
+/// old to be used again
+pub fn record(function: &'static str, default: Duration, key: Vec<u8>, result: &Variant) {
+    with_window(function, default, |window| {
+        if window.window.is_zero() {
+            return;
+        }
+        let limit = window.window;
+        window.results.retain(|_, (stored, _)| stored.elapsed() < limit);
+        window.results.insert(key, (Instant::now(), Recent(result.clone())));
+    });
+}
+
+/// Forgets every remembered result. This is called from xlAutoClose.
+pub fn clear() {
+    WINDOWS.lock().unwrap_or_else(|e| e.into_inner()).take();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn number(value: Option<Variant>) -> Option<f64> {
+        value.and_then(|value| f64::try_from(&value).ok())
+    }
+
+    #[test]
+    fn results_are_reused_within_the_window() {
+        let window = Duration::from_millis(50);
+        record("test_window", window, vec![1], &Variant::from(1.0));
+        assert_eq!(number(recent("test_window", window, &[1])), Some(1.0));
+        assert_eq!(number(recent("test_window", window, &[2])), None);
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(number(recent("test_window", window, &[1])), None);
+    }
+
+    #[test]
+    fn a_zero_window_turns_debouncing_off() {
+        let window = Duration::from_secs(60);
+        record("test_off", window, vec![1], &Variant::from(1.0));
+        set_window("test_off", Duration::ZERO);
+        assert_eq!(number(recent("test_off", window, &[1])), None);
+        record("test_off", window, vec![1], &Variant::from(2.0));
+        set_window("test_off", window);
+        assert_eq!(number(recent("test_off", window, &[1])), None);
+    }
+}
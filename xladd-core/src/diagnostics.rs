@@ -0,0 +1,37 @@
+//! Built-in diagnostic functions registered by every add-in built on xladd-core.
+
+use crate::entrypoint::{entry_point_diagnostics, retry_entry_point_resolution};
+use crate::registrator::FunctionRegistration;
+use crate::variant::Variant;
+use crate::xlcall::LPXLOPER12;
+
+/// Reports how the Excel12 entry point was resolved, retrying the resolution if it
+/// previously failed. Returns a two-column table of labels and values.
+#[unsafe(no_mangle)]
+pub extern "system" fn xl_diagnostics() -> LPXLOPER12 {
+    let resolved = retry_entry_point_resolution();
+    let state = entry_point_diagnostics();
+
+    let mut rows = vec![
+        ("Entry point resolved".to_string(), resolved.to_string()),
+        ("Resolved via".to_string(), state.resolved_via.unwrap_or("-").to_string()),
+        ("Resolution attempts".to_string(), state.attempts.to_string()),
+    ];
+    for failure in state.failures.iter() {
+        rows.push(("Failure".to_string(), failure.clone()));
+    }
+
+    LPXLOPER12::from(Variant::from(rows))
+}
+
+inventory::submit! {
+    FunctionRegistration {
+        xl_name: "xl_diagnostics",
+        arg_types: "Q",
+        arg_names: "",
+        category: "Add-in Diagnostics",
+        description: "Reports the state of the connection between this add-in and Excel",
+        arg_infos: &[],
+        help_topic: "",
+    }
+}
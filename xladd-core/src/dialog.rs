@@ -0,0 +1,221 @@
+//! Simple native dialogs built with xlfDialogBox, for commands that need to ask the user
+//! for a few parameters. The dialog is laid out top to bottom in the order the controls
+//! are added, with OK and Cancel buttons at the bottom.
+//!
+//! # Example
+//!
+//! let answer = Dialog::new("Re-price portfolio")
+//!     .text_box("book", "Book name", "Main")
+//!     .number_box("vol", "Volatility", 0.25)
+//!     .list("model", "Model", &["Black-Scholes", "Binomial"], 0)
+//!     .check_box("verbose", "Show workings", false)
+//!     .show();
+//! if let Some(answer) = answer {
+//!     let vol = answer.number("vol").unwrap_or(0.25);
+//! }
+
+use crate::entrypoint::excel12;
+use crate::variant::Variant;
+use crate::xlcall::{xlfDialogBox, xlfSetName};
+use log::debug;
+
+use std::collections::HashMap;
+
+// Item codes from the DIALOG.BOX documentation
+const DEFAULT_OK_BUTTON: i32 = 1;
+const CANCEL_BUTTON: i32 = 2;
+const STATIC_TEXT: i32 = 5;
+const TEXT_EDIT: i32 = 6;
+const INTEGER_EDIT: i32 = 7;
+const NUMBER_EDIT: i32 = 8;
+const LIST_BOX: i32 = 15;
+const CHECK_BOX: i32 = 13;
+
+// Layout, in dialog units
+const WIDTH: i32 = 320;
+const MARGIN: i32 = 10;
+const LINE: i32 = 18;
+const BUTTON_WIDTH: i32 = 75;
+const BUTTON_HEIGHT: i32 = 22;
+
+struct Control {
+    key: Option<String>,
+    code: i32,
+    text: String,
+    initial: Variant,
+    height: i32,
+    choices: Vec<String>,
+}
+
+/// Builder for a dialog. Controls that return a value are identified by a key, which is
+/// used to look the value up in the [`DialogResult`].
+pub struct Dialog {
+    title: String,
+    controls: Vec<Control>,
+}
+
+impl Dialog {
+    pub fn new(title: &str) -> Dialog {
+        Dialog { title: title.to_string(), controls: Vec::new() }
+    }
+
+    fn push(mut self, key: Option<&str>, code: i32, text: &str, initial: Variant, height: i32) -> Dialog {
+        self.controls.push(Control {
+            key: key.map(str::to_string),
+            code,
+            text: text.to_string(),
+            initial,
+            height,
+            choices: Vec::new(),
+        });
+        self
+    }
+
+    /// Adds a line of static text
+    pub fn label(self, text: &str) -> Dialog {
+        self.push(None, STATIC_TEXT, text, Variant::default(), LINE - 4)
+    }
+
+    /// Adds a labelled edit box for free text
+    pub fn text_box(self, key: &str, label: &str, initial: &str) -> Dialog {
+        self.label(label).push(Some(key), TEXT_EDIT, "", Variant::from(initial), LINE)
+    }
+
+    /// Adds a labelled edit box that only accepts whole numbers
+    pub fn integer_box(self, key: &str, label: &str, initial: i32) -> Dialog {
+        self.label(label).push(Some(key), INTEGER_EDIT, "", Variant::from(initial), LINE)
+    }
+
+    /// Adds a labelled edit box that only accepts numbers
+    pub fn number_box(self, key: &str, label: &str, initial: f64) -> Dialog {
+        self.label(label).push(Some(key), NUMBER_EDIT, "", Variant::from(initial), LINE)
+    }
+
+    /// Adds a labelled list to choose from. `selected` is the zero-based initial choice.
+    pub fn list(self, key: &str, label: &str, choices: &[&str], selected: usize) -> Dialog {
+        let visible = choices.len().clamp(1, 6) as i32;
+        let mut dialog = self.label(label).push(
+            Some(key),
+            LIST_BOX,
+            "",
+            Variant::from((selected + 1) as i32),
+            visible * (LINE - 4) + 4,
+        );
+        if let Some(list) = dialog.controls.last_mut() {
+            list.choices = choices.iter().map(|c| c.to_string()).collect();
+        }
+        dialog
+    }
+
+    /// Adds a check box
+    pub fn check_box(self, key: &str, text: &str, checked: bool) -> Dialog {
+        self.push(Some(key), CHECK_BOX, text, Variant::from(checked), LINE)
+    }
+
+    /// Shows the dialog and waits for the user. Returns None if the user cancelled, or if
+    /// the dialog could not be shown, for instance because this is not a command context.
+    pub fn show(self) -> Option<DialogResult> {
+        // List boxes take their items from a name, so define a temporary one for each list
+        let mut list_names = Vec::new();
+        let mut y = MARGIN;
+        let mut rows = Vec::with_capacity(self.controls.len() + 3);
+        rows.push(Vec::new()); // dialog row, filled in once the height is known
+        for control in self.controls.iter() {
+            let mut text = Variant::from(control.text.as_str());
+            if !control.choices.is_empty() {
+                let name = format!("__xladd_dialog_list{}", list_names.len() + 1);
+                let choices: Vec<&str> = control.choices.iter().map(String::as_str).collect();
+                excel12(xlfSetName, &mut [Variant::from(name.as_str()), Variant::from(choices)]);
+                text = Variant::from(name.as_str());
+                list_names.push(name);
+            }
+            rows.push(vec![
+                Variant::from(control.code),
+                Variant::from(MARGIN),
+                Variant::from(y),
+                Variant::from(WIDTH - 2 * MARGIN),
+                Variant::from(control.height),
+                text,
+                control.initial.clone(),
+            ]);
+            y += control.height + 4;
+        }
+
+        y += MARGIN;
+        for (code, text, x) in [
+            (DEFAULT_OK_BUTTON, "OK", WIDTH - 2 * (BUTTON_WIDTH + MARGIN)),
+            (CANCEL_BUTTON, "Cancel", WIDTH - BUTTON_WIDTH - MARGIN),
+        ] {
+            rows.push(vec![
+                Variant::from(code),
+                Variant::from(x),
+                Variant::from(y),
+                Variant::from(BUTTON_WIDTH),
+                Variant::from(BUTTON_HEIGHT),
+                Variant::from(text),
+                Variant::default(),
+            ]);
+        }
+        rows[0] = vec![
+            Variant::default(),
+            Variant::default(),
+            Variant::default(),
+            Variant::from(WIDTH),
+            Variant::from(y + BUTTON_HEIGHT + MARGIN),
+            Variant::from(self.title.as_str()),
+            Variant::default(),
+        ];
+
+        let result = excel12(xlfDialogBox, &mut [Variant::from(rows)]);
+        for name in list_names.iter() {
+            excel12(xlfSetName, &mut [Variant::from(name.as_str())]);
+        }
+
+        // Excel returns FALSE on cancel, otherwise the definition table with the
+        // final values filled into the last column
+        let (columns, row_count) = result.dim();
+        if columns < 7 || row_count != self.controls.len() + 3 {
+            debug!("DialogBox({}): result = {}", self.title, result);
+            return None;
+        }
+        let mut values = HashMap::new();
+        for (i, control) in self.controls.iter().enumerate() {
+            if let Some(key) = &control.key {
+                values.insert(key.clone(), result.at(6, i + 1));
+            }
+        }
+        Some(DialogResult { values })
+    }
+}
+
+/// The values the user entered, looked up by control key
+pub struct DialogResult {
+    values: HashMap<String, Variant>,
+}
+
+impl DialogResult {
+    /// The raw value of a control
+    pub fn value(&self, key: &str) -> Option<&Variant> {
+        self.values.get(key)
+    }
+
+    /// The contents of an edit box
+    pub fn text(&self, key: &str) -> Option<String> {
+        self.value(key).map(String::from)
+    }
+
+    /// The contents of a number or integer edit box
+    pub fn number(&self, key: &str) -> Option<f64> {
+        self.value(key).and_then(|v| f64::try_from(v).ok())
+    }
+
+    /// The zero-based index of the item chosen in a list
+    pub fn choice(&self, key: &str) -> Option<usize> {
+        self.number(key).filter(|&n| n >= 1.0).map(|n| n as usize - 1)
+    }
+
+    /// Whether a check box was ticked
+    pub fn checked(&self, key: &str) -> bool {
+        self.value(key).and_then(|v| bool::try_from(v).ok()).unwrap_or(false)
+    }
+}
@@ -1,13 +1,14 @@
 //! Entry point code for xladd-core, based on the sample C++ code
 //! supplied with the Microsoft Excel12 SDK
 
-use crate::registrator::debug_print;
 use crate::variant::Variant;
 use crate::xlcall::{xlFree, xlretFailed, LPXLOPER12, XLOPER12};
+use log::{debug, error, trace};
 
-use std::{ffi::CStr, mem, ptr, sync::Once};
+use std::{ffi::CStr, mem, ptr};
+use std::sync::{Mutex, Once};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use widestring::U16CString;
-use windows::Win32::Foundation::HMODULE;
 use windows::Win32::System::LibraryLoader::{GetModuleHandleW, GetProcAddress};
 use windows::core::{PCWSTR, PCSTR};
 use libc::c_int;
@@ -16,7 +17,8 @@ const EXCEL12ENTRYPT: &[u8] = b"MdCallBack12\0";
 const XLCALL32DLL: &str = "XLCall32";
 const XLCALL32ENTRYPT: &[u8] = b"GetExcel12EntryPt\0";
 
-type EXCEL12PROC = extern "system" fn(
+/// The signature of Excel's Excel12 callback, and of a stand-in for it
+pub type EXCEL12PROC = extern "system" fn(
     xlfn: c_int,
     count: c_int,
     rgpxloper12: *const LPXLOPER12,
@@ -25,17 +27,42 @@ type EXCEL12PROC = extern "system" fn(
 type FNGETEXCEL12ENTRYPT = extern "system" fn() -> usize;
 
 static INIT: Once = Once::new();
-static mut XLCALL_HMODULE: HMODULE = HMODULE(std::ptr::null_mut());
-static mut PEXCEL12: usize = 0;
+static PEXCEL12: AtomicUsize = AtomicUsize::new(0);
+static RESOLVE_STATE: Mutex<EntryPointDiagnostics> = Mutex::new(EntryPointDiagnostics::new());
+static REPORTED: AtomicBool = AtomicBool::new(false);
+
+/// Records how the Excel12 entry point was (or was not) resolved. If resolution fails,
+/// every call into Excel returns xlretFailed, so this is the first place to look when
+/// an add-in loads but all its functions return blanks.
+#[derive(Debug, Clone)]
+pub struct EntryPointDiagnostics {
+    /// Number of resolution attempts made so far
+    pub attempts: u32,
+    /// The route that produced the entry point, if any
+    pub resolved_via: Option<&'static str>,
+    /// Reasons recorded for each failed step of the most recent attempt
+    pub failures: Vec<String>,
+}
+
+impl EntryPointDiagnostics {
+    const fn new() -> EntryPointDiagnostics {
+        EntryPointDiagnostics { attempts: 0, resolved_via: None, failures: Vec::new() }
+    }
+
+    /// True if calls into Excel can be made
+    pub fn is_resolved(&self) -> bool {
+        self.resolved_via.is_some()
+    }
+}
 
 /// Call into Excel, passing a function number as defined in xlcall and a slice
 /// of Variant, and returning a Variant. Consult Excel SDK documentation to find
 /// the number and type of parameters and the expected result.
 pub fn excel12(xlfn: u32, opers: &mut [Variant]) -> Variant {
-    debug_print(&format!("FuncID:{}, {} args)", xlfn, opers.len()));
+    trace!("FuncID:{}, {} args)", xlfn, opers.len());
     let mut args: Vec<LPXLOPER12> = Vec::with_capacity(opers.len());
     for oper in opers.iter_mut() {
-        debug_print(&format!("arg: {}", oper));
+        trace!("arg: {}", oper);
         args.push(oper.as_mut_xloper());
     }
     let mut result = Variant::default();
@@ -43,64 +70,133 @@ pub fn excel12(xlfn: u32, opers: &mut [Variant]) -> Variant {
     match res {
         0 => result,
         v => {
-            debug_print(&format!("ReturnCode {}", v));
+            debug!("ReturnCode {}", v);
             result
         }
     }
 }
 
 fn fetch_excel12_entry_pt() {
-    INIT.call_once(|| {
-        unsafe {
-            if XLCALL_HMODULE.0.is_null() {
-                let wcstr = U16CString::from_str(XLCALL32DLL).unwrap();
-                XLCALL_HMODULE = GetModuleHandleW(PCWSTR(wcstr.as_ptr())).unwrap_or(HMODULE(std::ptr::null_mut()));
-                if !XLCALL_HMODULE.0.is_null() {
-                    let cstr = CStr::from_bytes_with_nul(XLCALL32ENTRYPT).unwrap();
-                    if let Some(proc_addr) = GetProcAddress(XLCALL_HMODULE, PCSTR(cstr.as_ptr().cast())) {
-                        let entry_pt: usize = proc_addr as usize;
+    INIT.call_once(resolve_excel12_entry_pt);
+}
+
+/// Attempts to find the Excel12 callback, first via XLCall32.dll and then via the
+/// MdCallBack12 export of the host executable, recording why each route failed. The
+/// failures are reported by [`report_unresolved`].
+fn resolve_excel12_entry_pt() {
+    let mut state = RESOLVE_STATE.lock().unwrap_or_else(|e| e.into_inner());
+    state.attempts += 1;
+    state.failures.clear();
+    state.resolved_via = None;
+
+    unsafe {
+        let wcstr = U16CString::from_str(XLCALL32DLL).unwrap();
+        match GetModuleHandleW(PCWSTR(wcstr.as_ptr())) {
+            Ok(hmodule) if !hmodule.0.is_null() => {
+                let cstr = CStr::from_bytes_with_nul(XLCALL32ENTRYPT).unwrap();
+                match GetProcAddress(hmodule, PCSTR(cstr.as_ptr().cast())) {
+                    Some(proc_addr) => {
+                        let get_entry_pt = mem::transmute::<usize, FNGETEXCEL12ENTRYPT>(proc_addr as usize);
+                        let entry_pt = get_entry_pt();
                         if entry_pt != 0 {
-                            PEXCEL12 = mem::transmute::<usize, FNGETEXCEL12ENTRYPT>(entry_pt)();
+                            PEXCEL12.store(entry_pt, Ordering::Release);
+                            state.resolved_via = Some("XLCall32!GetExcel12EntryPt");
+                        } else {
+                            state.failures.push("GetExcel12EntryPt returned a null entry point".to_string());
                         }
                     }
+                    None => state.failures.push(format!(
+                        "{} does not export GetExcel12EntryPt", XLCALL32DLL)),
                 }
             }
+            Ok(_) => state.failures.push(format!("{} module handle is null", XLCALL32DLL)),
+            Err(e) => state.failures.push(format!("{} is not loaded: {}", XLCALL32DLL, e)),
+        }
 
-            if PEXCEL12 == 0 {
-                XLCALL_HMODULE = GetModuleHandleW(PCWSTR::null()).unwrap_or(HMODULE(std::ptr::null_mut()));
-                if !XLCALL_HMODULE.0.is_null() {
+        if state.resolved_via.is_none() {
+            match GetModuleHandleW(PCWSTR::null()) {
+                Ok(hmodule) if !hmodule.0.is_null() => {
                     let cstr = CStr::from_bytes_with_nul(EXCEL12ENTRYPT).unwrap();
-                    if let Some(proc_addr) = GetProcAddress(XLCALL_HMODULE, PCSTR(cstr.as_ptr().cast())) {
-                        PEXCEL12 = proc_addr as usize;
+                    match GetProcAddress(hmodule, PCSTR(cstr.as_ptr().cast())) {
+                        Some(proc_addr) => {
+                            PEXCEL12.store(proc_addr as usize, Ordering::Release);
+                            state.resolved_via = Some("MdCallBack12");
+                        }
+                        None => state.failures.push(
+                            "host executable does not export MdCallBack12 (not running inside Excel?)".to_string()),
                     }
                 }
+                Ok(_) => state.failures.push("host executable module handle is null".to_string()),
+                Err(e) => state.failures.push(format!("cannot get host executable module: {}", e)),
             }
         }
-    });
+    }
+}
+
+/// Returns a snapshot of the entry point resolution state, resolving first if needed.
+pub fn entry_point_diagnostics() -> EntryPointDiagnostics {
+    fetch_excel12_entry_pt();
+    RESOLVE_STATE.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Retries resolution of the Excel12 entry point if it previously failed. Returns true
+/// if the entry point is available afterwards.
+pub fn retry_entry_point_resolution() -> bool {
+    fetch_excel12_entry_pt();
+    if PEXCEL12.load(Ordering::Acquire) == 0 {
+        resolve_excel12_entry_pt();
+    }
+    PEXCEL12.load(Ordering::Acquire) != 0
+}
+
+/// Sends every call into Excel to the given function instead, so benchmarks and tests can
+/// run the add-in's code outside Excel. This replaces the real entry point if it has
+/// already been resolved, so it should never be called inside Excel.
+pub fn set_excel12_entry_point(entry_point: EXCEL12PROC) {
+    INIT.call_once(|| {});
+    PEXCEL12.store(entry_point as usize, Ordering::Release);
+    let mut state = RESOLVE_STATE.lock().unwrap_or_else(|e| e.into_inner());
+    state.resolved_via = Some("set_excel12_entry_point");
+    state.failures.clear();
+}
+
+/// Logs why the entry point could not be resolved, if it could not, the first time it is
+/// called. Registration calls this from xlAutoOpen, on Excel's main thread. Without the
+/// entry point there is no Excel alert to raise, so the log and =xl_diagnostics() are
+/// where the failure shows.
+pub(crate) fn report_unresolved() {
+    let state = entry_point_diagnostics();
+    if state.is_resolved() || REPORTED.swap(true, Ordering::AcqRel) {
+        return;
+    }
+    error!(
+        "The add-in could not find the Excel12 entry point, so its functions cannot talk to Excel: {}. \
+        Call =xl_diagnostics() for details.",
+        state.failures.join("; "));
 }
 
 pub fn excel12v(xlfn: i32, oper_res: &mut XLOPER12, opers: &[LPXLOPER12]) -> i32 {
     fetch_excel12_entry_pt();
 
-    unsafe {
-        if PEXCEL12 == 0 {
-            xlretFailed as i32
-        } else {
-            let p = opers.as_ptr();
-            let len = opers.len();
-            mem::transmute::<usize, EXCEL12PROC>(PEXCEL12)(xlfn, len as i32, p, oper_res)
-        }
+    let pexcel12 = PEXCEL12.load(Ordering::Acquire);
+    if pexcel12 == 0 {
+        xlretFailed as i32
+    } else {
+        let p = opers.as_ptr();
+        let len = opers.len();
+        unsafe { mem::transmute::<usize, EXCEL12PROC>(pexcel12)(xlfn, len as i32, p, oper_res) }
     }
 }
 
 pub fn excel_free(xloper: LPXLOPER12) -> i32 {
     fetch_excel12_entry_pt();
 
-    unsafe {
-        if PEXCEL12 == 0 {
-            xlretFailed as i32
-        } else {
-            mem::transmute::<usize, EXCEL12PROC>(PEXCEL12)(
+    let pexcel12 = PEXCEL12.load(Ordering::Acquire);
+    if pexcel12 == 0 {
+        xlretFailed as i32
+    } else {
+        unsafe {
+            mem::transmute::<usize, EXCEL12PROC>(pexcel12)(
                 xlFree as i32,
                 1,
                 &xloper,
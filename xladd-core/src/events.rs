@@ -0,0 +1,242 @@
+//! Excel application events forwarded to Rust callbacks. An event sink for the
+//! Application's AppEvents interface is connected the first time a handler is added,
+//! and disconnected again in xlAutoClose. Only available with the `events` feature.
+//!
+//! Typical use is to invalidate cached results when an input area is edited:
+//!
+//! watch_range("Inputs", "B2:B20", |_changed| cache.clear());
+//!
+//! Handlers run on Excel's main thread while Excel is waiting for them, so keep them short.
+
+// The method names are fixed by the COM interface definitions
+#![allow(non_snake_case)]
+
+use crate::com::{excel_application, Application, ComVariant, Dispatch, Range};
+use log::warn;
+
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use windows::Win32::Foundation::{DISP_E_UNKNOWNNAME, E_POINTER, VARIANT_TRUE};
+use windows::Win32::System::Com::{
+    IConnectionPoint, IConnectionPointContainer, IDispatch, IDispatch_Impl, ITypeInfo, DISPATCH_FLAGS,
+    DISPPARAMS, EXCEPINFO,
+};
+use windows::Win32::System::Variant::{VARIANT, VT_BOOL, VT_BYREF};
+use windows::core::{implement, IUnknown, Interface, GUID, PCWSTR, Result};
+
+/// The AppEvents dispinterface of Excel.Application
+const DIID_APP_EVENTS: GUID = GUID::from_u128(0x00024413_0000_0000_c000_000000000046);
+
+// Dispatch ids from the Excel type library
+const DISPID_SHEET_SELECTION_CHANGE: i32 = 0x616;
+const DISPID_SHEET_CHANGE: i32 = 0x61c;
+const DISPID_WORKBOOK_OPEN: i32 = 0x61f;
+const DISPID_WORKBOOK_BEFORE_CLOSE: i32 = 0x622;
+const DISPID_WORKBOOK_BEFORE_SAVE: i32 = 0x623;
+
+/// Identifies a handler, so it can be removed with [`unsubscribe`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SubscriptionId(u64);
+
+type RangeHandler = Box<dyn FnMut(&Dispatch, &Range) + Send>;
+
+enum Handler {
+    SheetChange(RangeHandler),
+    SelectionChange(RangeHandler),
+    WorkbookOpen(Box<dyn FnMut(&Dispatch) + Send>),
+    WorkbookBeforeClose(Box<dyn FnMut(&Dispatch) + Send>),
+    WorkbookBeforeSave(Box<dyn FnMut(&Dispatch) -> bool + Send>),
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+static HANDLERS: Mutex<Vec<(SubscriptionId, Handler)>> = Mutex::new(Vec::new());
+static CONNECTION: Mutex<Option<Connection>> = Mutex::new(None);
+
+struct Connection {
+    point: IConnectionPoint,
+    cookie: u32,
+}
+
+// The connection is only ever touched on Excel's main thread
+unsafe impl Send for Connection {}
+
+fn subscribe(handler: Handler) -> SubscriptionId {
+    let id = SubscriptionId(NEXT_ID.fetch_add(1, Ordering::Relaxed));
+    HANDLERS.lock().unwrap_or_else(|e| e.into_inner()).push((id, handler));
+    if let Err(e) = connect() {
+        warn!("could not connect to Excel events: {}", e);
+    }
+    id
+}
+
+/// Calls `f` with the sheet and the changed range whenever cells are edited
+pub fn on_sheet_change<F: FnMut(&Dispatch, &Range) + Send + 'static>(f: F) -> SubscriptionId {
+    subscribe(Handler::SheetChange(Box::new(f)))
+}
+
+/// Calls `f` with the sheet and the new selection whenever the selection moves
+pub fn on_selection_change<F: FnMut(&Dispatch, &Range) + Send + 'static>(f: F) -> SubscriptionId {
+    subscribe(Handler::SelectionChange(Box::new(f)))
+}
+
+/// Calls `f` with the workbook whenever a workbook is opened
+pub fn on_workbook_open<F: FnMut(&Dispatch) + Send + 'static>(f: F) -> SubscriptionId {
+    subscribe(Handler::WorkbookOpen(Box::new(f)))
+}
+
+/// Calls `f` with the workbook when it is about to close. The user may still cancel the
+/// close when asked to save changes, so the workbook can stay open after this.
+pub fn on_workbook_before_close<F: FnMut(&Dispatch) + Send + 'static>(f: F) -> SubscriptionId {
+    subscribe(Handler::WorkbookBeforeClose(Box::new(f)))
+}
+
+/// Calls `f` with the workbook before it is saved. Returning true cancels the save.
+pub fn on_workbook_before_save<F: FnMut(&Dispatch) -> bool + Send + 'static>(f: F) -> SubscriptionId {
+    subscribe(Handler::WorkbookBeforeSave(Box::new(f)))
+}
+
+/// Calls `f` with the changed cells whenever an edit touches `address` on the named
+/// sheet. An empty sheet name matches any sheet.
+pub fn watch_range<F: FnMut(&Range) + Send + 'static>(sheet_name: &str, address: &str, mut f: F) -> SubscriptionId {
+    let sheet_name = sheet_name.to_string();
+    let address = address.to_string();
+    on_sheet_change(move |sheet, target| {
+        let name = sheet.get("Name").ok().and_then(|n| n.to_string_value()).unwrap_or_default();
+        if !sheet_name.is_empty() && !name.eq_ignore_ascii_case(&sheet_name) {
+            return;
+        }
+        match intersect(sheet, target, &address) {
+            Ok(Some(changed)) => f(&changed),
+            Ok(None) => {}
+            Err(e) => warn!("watch_range {}!{} failed: {}", name, address, e),
+        }
+    })
+}
+
+fn intersect(sheet: &Dispatch, target: &Range, address: &str) -> Result<Option<Range>> {
+    let watched = Range::on_sheet(sheet, address)?;
+    let app = Application::get()?;
+    let overlap = app.dispatch().call(
+        "Intersect",
+        vec![ComVariant::from(target.dispatch()), ComVariant::from(watched.dispatch())],
+    )?;
+    Ok(overlap.to_dispatch().map(Range))
+}
+
+/// Removes a handler. The event sink stays connected until xlAutoClose.
+pub fn unsubscribe(id: SubscriptionId) {
+    HANDLERS.lock().unwrap_or_else(|e| e.into_inner()).retain(|(handler_id, _)| *handler_id != id);
+}
+
+fn connect() -> Result<()> {
+    let mut connection = CONNECTION.lock().unwrap_or_else(|e| e.into_inner());
+    if connection.is_some() {
+        return Ok(());
+    }
+    let app = excel_application()?;
+    let container = app.as_raw().cast::<IConnectionPointContainer>()?;
+    unsafe {
+        let point = container.FindConnectionPoint(&DIID_APP_EVENTS)?;
+        let sink: IUnknown = AppEventSink.into();
+        let cookie = point.Advise(&sink)?;
+        *connection = Some(Connection { point, cookie });
+    }
+    Ok(())
+}
+
+/// Disconnects the event sink and drops all handlers. This is called from xlAutoClose.
+pub fn disconnect() {
+    if let Some(connection) = CONNECTION.lock().unwrap_or_else(|e| e.into_inner()).take()
+        && let Err(e) = unsafe { connection.point.Unadvise(connection.cookie) }
+    {
+        warn!("could not disconnect from Excel events: {}", e);
+    }
+    HANDLERS.lock().unwrap_or_else(|e| e.into_inner()).clear();
+}
+
+/// Runs every handler with `f`. The handlers are taken out of the list while they run,
+/// so a handler may subscribe or unsubscribe without deadlocking.
+fn dispatch_to_handlers(mut f: impl FnMut(&mut Handler)) {
+    let mut running = std::mem::take(&mut *HANDLERS.lock().unwrap_or_else(|e| e.into_inner()));
+    for (_, handler) in running.iter_mut() {
+        f(handler);
+    }
+    let mut handlers = HANDLERS.lock().unwrap_or_else(|e| e.into_inner());
+    running.append(&mut handlers);
+    *handlers = running;
+}
+
+#[implement(IDispatch)]
+struct AppEventSink;
+
+impl IDispatch_Impl for AppEventSink_Impl {
+    fn GetTypeInfoCount(&self) -> Result<u32> {
+        Ok(0)
+    }
+
+    fn GetTypeInfo(&self, _itinfo: u32, _lcid: u32) -> Result<ITypeInfo> {
+        Err(DISP_E_UNKNOWNNAME.into())
+    }
+
+    fn GetIDsOfNames(&self, _riid: *const GUID, _names: *const PCWSTR, _count: u32, _lcid: u32, _dispids: *mut i32) -> Result<()> {
+        Err(DISP_E_UNKNOWNNAME.into())
+    }
+
+    fn Invoke(&self, dispid: i32, _riid: *const GUID, _lcid: u32, _flags: DISPATCH_FLAGS, params: *const DISPPARAMS,
+        _result: *mut VARIANT, _excepinfo: *mut EXCEPINFO, _argerr: *mut u32) -> Result<()> {
+        if params.is_null() {
+            return Err(E_POINTER.into());
+        }
+        let params = unsafe { &*params };
+        // Arguments arrive in reverse order
+        let arg = |n: usize| -> Option<Dispatch> {
+            let count = params.cArgs as usize;
+            if n >= count {
+                return None;
+            }
+            unsafe { ComVariant::from_borrowed(params.rgvarg.add(count - 1 - n)) }.to_dispatch()
+        };
+
+        match dispid {
+            DISPID_SHEET_CHANGE | DISPID_SHEET_SELECTION_CHANGE => {
+                let (Some(sheet), Some(target)) = (arg(0), arg(1)) else { return Ok(()) };
+                let target = Range(target);
+                dispatch_to_handlers(|handler| match handler {
+                    Handler::SheetChange(f) if dispid == DISPID_SHEET_CHANGE => f(&sheet, &target),
+                    Handler::SelectionChange(f) if dispid == DISPID_SHEET_SELECTION_CHANGE => f(&sheet, &target),
+                    _ => {}
+                });
+            }
+            DISPID_WORKBOOK_OPEN | DISPID_WORKBOOK_BEFORE_CLOSE => {
+                let Some(workbook) = arg(0) else { return Ok(()) };
+                dispatch_to_handlers(|handler| match handler {
+                    Handler::WorkbookOpen(f) if dispid == DISPID_WORKBOOK_OPEN => f(&workbook),
+                    Handler::WorkbookBeforeClose(f) if dispid == DISPID_WORKBOOK_BEFORE_CLOSE => f(&workbook),
+                    _ => {}
+                });
+            }
+            DISPID_WORKBOOK_BEFORE_SAVE => {
+                let Some(workbook) = arg(0) else { return Ok(()) };
+                let mut cancel = false;
+                dispatch_to_handlers(|handler| {
+                    if let Handler::WorkbookBeforeSave(f) = handler {
+                        cancel |= f(&workbook)
+                    }
+                });
+                // Cancel is the last argument, passed by reference
+                if cancel && params.cArgs >= 3 {
+                    unsafe {
+                        let cancel_arg = &(*params.rgvarg).Anonymous.Anonymous;
+                        let flag = cancel_arg.Anonymous.pboolVal;
+                        if cancel_arg.vt.0 == VT_BYREF.0 | VT_BOOL.0 && !flag.is_null() {
+                            *flag = VARIANT_TRUE;
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+        // Unhandled events are ignored rather than reported, as Excel fires many of them
+        Ok(())
+    }
+}
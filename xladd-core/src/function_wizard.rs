@@ -0,0 +1,63 @@
+//! Detecting the Function Wizard. While the user types arguments into the Function
+//! Arguments dialog, Excel calls the function after every keystroke to preview its
+//! result, so a slow function makes the dialog crawl. A function can check
+//! [`is_in_function_wizard`] first and return something cheap instead:
+//!
+//! if function_wizard::is_in_function_wizard() {
+//!     return Ok(Variant::from("(preview)"));
+//! }
+//!
+//! Excel has no API for this, so the check looks for Excel's dialog window in this
+//! process. The Find and Replace dialogs use the same window class and also count,
+//! although no function is called while they are open.
+
+use windows::Win32::Foundation::{HWND, LPARAM};
+use windows::Win32::System::Threading::GetCurrentProcessId;
+use windows::Win32::UI::WindowsAndMessaging::{EnumWindows, GetClassNameW, GetWindowThreadProcessId, IsWindowVisible};
+use windows::core::BOOL;
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The start of the class name of Excel's dialogs, such as "bosa_sdm_XL9"
+const DIALOG_CLASS: &str = "bosa_sdm_XL";
+/// How long an answer is reused, as a function is often called for many cells at once
+const RECHECK_AFTER: Duration = Duration::from_millis(100);
+
+static LAST: Mutex<Option<(Instant, bool)>> = Mutex::new(None);
+
+/// Whether the Function Wizard is open in this Excel. This can be called from any thread.
+pub fn is_in_function_wizard() -> bool {
+    let mut last = LAST.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((checked, open)) = *last
+        && checked.elapsed() < RECHECK_AFTER
+    {
+        return open;
+    }
+    let open = find_dialog();
+    *last = Some((Instant::now(), open));
+    open
+}
+
+/// Looks through the top-level windows for a visible Excel dialog of this process
+fn find_dialog() -> bool {
+    let mut found = false;
+    // EnumWindows fails when the callback stops it early, which is how it reports a find
+    let _ = unsafe { EnumWindows(Some(check_window), LPARAM(&mut found as *mut bool as isize)) };
+    found
+}
+
+unsafe extern "system" fn check_window(window: HWND, found: LPARAM) -> BOOL {
+    let mut process = 0;
+    unsafe { GetWindowThreadProcessId(window, Some(&mut process)) };
+    if process != unsafe { GetCurrentProcessId() } || !unsafe { IsWindowVisible(window) }.as_bool() {
+        return BOOL(1);
+    }
+    let mut class = [0u16; 64];
+    let length = unsafe { GetClassNameW(window, &mut class) }.max(0) as usize;
+    if String::from_utf16_lossy(&class[..length]).starts_with(DIALOG_CLASS) {
+        unsafe { *(found.0 as *mut bool) = true };
+        return BOOL(0);
+    }
+    BOOL(1)
+}
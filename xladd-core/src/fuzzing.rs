@@ -0,0 +1,157 @@
+//! Values built from arbitrary bytes, for fuzzing the conversions of arguments from
+//! Excel. Compiled when fuzzing, as cargo-fuzz sets `--cfg fuzzing`, or with the
+//! `testing` feature. The targets are in `xladd-core/fuzz`:
+//!
+//! cargo +nightly fuzz run conversions
+//!
+//! A [`FuzzValue`] may have any xltype, including unknown types and odd combinations of
+//! bits other than the ownership bits, which Excel never sets on an argument. Strings may
+//! have any length and content, and arrays any shape, down to negative dimensions and a
+//! null element pointer. The memory a value points at always matches what it claims to
+//! hold, as Excel's does, so a crash is a bug in the conversions rather than in the value.
+
+#![allow(non_upper_case_globals)]
+
+use crate::variant::{Variant, VariantRef};
+use crate::xlcall::{
+    xlbitDLLFree, xlbitXLFree, xlmref12, xlref12, xltypeMask, xltypeMulti, xltypeRef, xltypeSRef, xltypeStr, Xloper12Array,
+    Xloper12MRef, Xloper12SRef, Xloper12Value, LPXLOPER12, XLOPER12,
+};
+
+/// The largest array built, in each direction
+const MAX_DIMENSION: i32 = 32;
+
+/// An XLOPER12 built from bytes, with the memory it points at
+pub struct FuzzValue {
+    root: Box<XLOPER12>,
+    // Kept alive for the pointers in root and cells
+    _strings: Vec<Vec<u16>>,
+    _cells: Vec<XLOPER12>,
+    // Boxed so they do not move as more are added
+    _references: Vec<Box<[xlmref12]>>,
+}
+
+impl FuzzValue {
+    /// Builds a value, reading as many bytes as it needs. Missing bytes read as zero.
+    pub fn from_bytes(data: &[u8]) -> FuzzValue {
+        let mut bytes = Bytes(data);
+        let mut strings = Vec::new();
+        let mut references = Vec::new();
+        let mut root = Box::new(scalar(&mut bytes, &mut strings, &mut references));
+        let mut cells = Vec::new();
+        if root.xltype & xltypeMask == xltypeMulti {
+            let rows = bytes.i8() as i32 % MAX_DIMENSION;
+            let columns = bytes.i8() as i32 % MAX_DIMENSION;
+            let null = bytes.u8() == 0xff;
+            let count = if rows > 0 && columns > 0 { (rows * columns) as usize } else { 0 };
+            cells = (0..count).map(|_| scalar(&mut bytes, &mut strings, &mut references)).collect();
+            let lparray = if null || cells.is_empty() { std::ptr::null_mut() } else { cells.as_mut_ptr() };
+            root.val = Xloper12Value { array: Xloper12Array { lparray, rows, columns } };
+        }
+        FuzzValue { root, _strings: strings, _cells: cells, _references: references }
+    }
+
+    /// The pointer Excel would pass as an argument
+    pub fn as_ptr(&mut self) -> LPXLOPER12 {
+        &mut *self.root
+    }
+
+    pub fn as_ref(&self) -> VariantRef<'_> {
+        unsafe { VariantRef::from_ptr(&*self.root as *const XLOPER12 as LPXLOPER12) }
+    }
+}
+
+/// Runs every conversion an argument may go through, and the Variant methods that read
+/// arrays, on a value. None of them may crash, whatever the value.
+pub fn convert_all(value: &mut FuzzValue) {
+    let borrowed = value.as_ref();
+    let _ = f64::try_from(borrowed);
+    let _ = bool::try_from(borrowed);
+    let _ = String::from(borrowed);
+    let _ = i32::from(borrowed);
+    let _ = u32::from(borrowed);
+    let _ = Vec::<f64>::try_from(borrowed);
+    let _ = Vec::<String>::from(borrowed);
+    let (columns, rows) = borrowed.dim();
+    let _ = borrowed.at(columns.saturating_sub(1), rows.saturating_sub(1));
+
+    let variant = Variant::from(value.as_ptr());
+    let _ = f64::try_from(&variant);
+    let _ = Vec::<f64>::try_from(&variant);
+    let _ = Vec::<String>::from(&variant);
+    let _ = variant.to_string();
+    let _ = variant.at(0, 0);
+    let _ = variant.transpose();
+    // A reference may span the whole sheet, which concat would fill in cell by cell
+    if !variant.is_ref() {
+        let _ = Variant::concat(&[variant.clone(), variant], true);
+    }
+}
+
+/// A single value, which is never an array with elements
+fn scalar(bytes: &mut Bytes, strings: &mut Vec<Vec<u16>>, references: &mut Vec<Box<[xlmref12]>>) -> XLOPER12 {
+    let xltype = bytes.u32() & !(xlbitDLLFree | xlbitXLFree);
+    let mut val = Xloper12Value { num: f64::from_bits(bytes.u64()) };
+    match xltype & xltypeMask {
+        xltypeStr => {
+            // The length is counted in UTF-16 units, and any unit may follow, valid or not
+            let length = bytes.u16();
+            let mut string = Vec::with_capacity(length as usize + 1);
+            string.push(length);
+            string.extend((0..length).map(|_| bytes.u16()));
+            val = Xloper12Value { str: string.as_mut_ptr() };
+            strings.push(string);
+        }
+        xltypeMulti => {
+            val = Xloper12Value { array: Xloper12Array { lparray: std::ptr::null_mut(), rows: 0, columns: 0 } };
+        }
+        xltypeSRef => {
+            val = Xloper12Value { sref: Xloper12SRef { count: bytes.u16(), ref_: reference(bytes) } };
+        }
+        xltypeRef => {
+            let mut mref: Box<[xlmref12]> = Box::new([xlmref12 { count: bytes.u16(), reftbl: [reference(bytes)] }]);
+            let lpmref = if bytes.u8() == 0xff { std::ptr::null_mut() } else { mref.as_mut_ptr() };
+            references.push(mref);
+            val = Xloper12Value { mref: Xloper12MRef { lpmref, idSheet: std::ptr::null_mut() } };
+        }
+        _ => {}
+    }
+    XLOPER12 { val, xltype }
+}
+
+fn reference(bytes: &mut Bytes) -> xlref12 {
+    xlref12 { rwFirst: bytes.u32() as i32, rwLast: bytes.u32() as i32, colFirst: bytes.u32() as i32, colLast: bytes.u32() as i32 }
+}
+
+/// Reads numbers from the front of the fuzzer's bytes
+struct Bytes<'a>(&'a [u8]);
+
+impl Bytes<'_> {
+    fn take<const N: usize>(&mut self) -> [u8; N] {
+        let mut taken = [0; N];
+        let count = N.min(self.0.len());
+        taken[..count].copy_from_slice(&self.0[..count]);
+        self.0 = &self.0[count..];
+        taken
+    }
+
+    fn u8(&mut self) -> u8 {
+        self.take::<1>()[0]
+    }
+
+    fn i8(&mut self) -> i8 {
+        self.u8() as i8
+    }
+
+    fn u16(&mut self) -> u16 {
+        u16::from_le_bytes(self.take())
+    }
+
+    fn u32(&mut self) -> u32 {
+        u32::from_le_bytes(self.take())
+    }
+
+    fn u64(&mut self) -> u64 {
+        u64::from_le_bytes(self.take())
+    }
+}
@@ -0,0 +1,113 @@
+//! Groups of functions that can be switched off and on while Excel is running, for example
+//! the functions of a licensing tier, or experimental ones. A function joins a group with
+//! `#[xl_func(group = "pro")]`, or with a [`FunctionGroup`]:
+//!
+//! xladd_core::registrator::inventory::submit! {
+//!     xladd_core::groups::FunctionGroup { xl_name: "xl_npv", group: "pro" }
+//! }
+//!
+//! A disabled group's functions are unregistered, so cells using them show #NAME? until it
+//! is enabled again. Groups can also be disabled from the start by listing them under
+//! `disabled` in the [settings](crate::config), and users can switch them with the
+//! `xl_function_groups` command.
+
+use crate::commands;
+use crate::input;
+use crate::registrator::{self, CommandRegistration};
+use log::info;
+
+use std::collections::BTreeSet;
+use std::sync::Mutex;
+
+/// Puts a function in a group
+pub struct FunctionGroup {
+    /// The exported name of the function
+    pub xl_name: &'static str,
+    pub group: &'static str,
+}
+
+inventory::collect!(FunctionGroup);
+
+/// Groups disabled since Excel started
+static DISABLED: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+/// The group a function is in, if any
+pub fn group(xl_name: &str) -> Option<&'static str> {
+    inventory::iter::<FunctionGroup>.into_iter().find(|member| member.xl_name == xl_name).map(|member| member.group)
+}
+
+/// Every group with at least one function, in order
+pub fn groups() -> Vec<&'static str> {
+    let groups: BTreeSet<&'static str> = inventory::iter::<FunctionGroup>.into_iter().map(|member| member.group).collect();
+    groups.into_iter().collect()
+}
+
+/// Whether the functions of a group are available, as far as switching groups at runtime
+/// goes. The settings may still disable them.
+pub fn is_enabled(group: &str) -> bool {
+    !DISABLED.lock().unwrap_or_else(|e| e.into_inner()).contains(group)
+}
+
+/// Whether a function's group, if it has one, has been disabled
+pub(crate) fn is_disabled(group: Option<&str>) -> bool {
+    group.is_some_and(|group| !is_enabled(group))
+}
+
+/// Unregisters the functions of a group, and keeps them from being registered until the
+/// group is enabled again. This can only be called from a command. Returns how many
+/// functions were unregistered.
+pub fn disable(group: &str) -> usize {
+    DISABLED.lock().unwrap_or_else(|e| e.into_inner()).insert(group.to_string());
+    let unregistered = inventory::iter::<FunctionGroup>
+        .into_iter()
+        .filter(|member| member.group == group && registrator::unregister(member.xl_name))
+        .count();
+    info!("group {} disabled, {} functions unregistered", group, unregistered);
+    unregistered
+}
+
+/// Registers the functions of a disabled group again. This can only be called from a
+/// command. Returns how many functions were registered.
+pub fn enable(group: &str) -> usize {
+    DISABLED.lock().unwrap_or_else(|e| e.into_inner()).remove(group);
+    let registered = registrator::register_matching(|registration| self::group(registration.xl_name) == Some(group));
+    info!("group {} enabled, {} functions registered", group, registered);
+    registered
+}
+
+/// Command that lists the groups and switches the one the user names
+#[unsafe(no_mangle)]
+pub extern "system" fn xl_function_groups() -> i32 {
+    let groups = groups();
+    if groups.is_empty() {
+        commands::alert("This add-in has no function groups.");
+        return 1;
+    }
+    let listing: Vec<String> = groups
+        .iter()
+        .map(|group| format!("{} ({})", group, if is_enabled(group) { "enabled" } else { "disabled" }))
+        .collect();
+    let prompt = format!("Groups: {}\n\nName a group to switch it on or off.", listing.join(", "));
+    let Some(answer) = input::input_text(&prompt, "Function groups", "") else {
+        return 1;
+    };
+    let answer = answer.trim();
+    let Some(group) = groups.iter().find(|group| group.eq_ignore_ascii_case(answer)) else {
+        commands::alert(&format!("There is no function group called {}.", answer));
+        return 1;
+    };
+    let message = if is_enabled(group) {
+        format!("{} functions in {} are now disabled.", disable(group), group)
+    } else {
+        format!("{} functions in {} are now enabled.", enable(group), group)
+    };
+    commands::alert(&message);
+    1
+}
+
+inventory::submit! {
+    CommandRegistration {
+        xl_name: "xl_function_groups",
+        shortcut: "",
+    }
+}
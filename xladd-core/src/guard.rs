@@ -0,0 +1,218 @@
+//! Protection of the Excel process from faults in worksheet functions. A Rust panic, or a
+//! hardware exception such as an access violation in a native library, would otherwise
+//! take Excel down with every open workbook. Wrappers generated by `#[xl_func]` run the
+//! function through [`protect`], which turns the fault into `#VALUE!` and logs it.
+//!
+//! Hardware exceptions are caught by a structured exception handler, the `__try` and
+//! `__except` of a small C shim, which returns to [`protect`] with an error once the fault
+//! is handled. MSVC alone compiles the shim, so with other toolchains the fault is left to
+//! Excel. The frames between the shim and the fault are abandoned without unwinding, so
+//! their destructors do not run: locks they held stay held and native state they were
+//! changing may be inconsistent. The add-in carries on, but should be reloaded once the
+//! fault has been looked into. Stack overflows cannot be recovered from.
+//!
+//! A [minidump](crate::minidump) is written for each hardware exception caught, while the
+//! faulting frames are still on the stack.
+
+use crate::minidump;
+use log::error;
+
+use std::any::Any;
+use std::fmt;
+use std::future::Future;
+use std::panic;
+use std::pin::Pin;
+use std::sync::Once;
+use std::task::{Context, Poll};
+
+/// Hardware exceptions that are caught, with their names
+const RECOVERABLE: &[(u32, &str)] = &[
+    (0xC000_0005, "access violation"),
+    (0xC000_0006, "in-page error"),
+    (0xC000_001D, "illegal instruction"),
+    (0xC000_008C, "array bounds exceeded"),
+    (0xC000_008E, "floating-point division by zero"),
+    (0xC000_0094, "integer division by zero"),
+    (0xC000_0095, "integer overflow"),
+    (0xC000_0096, "privileged instruction"),
+    (0x8000_0002, "datatype misalignment"),
+];
+
+static INSTALL: Once = Once::new();
+
+/// A hardware exception raised inside [`protect`]. [`guarded`] carries it as a panic
+/// payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StructuredException {
+    /// The exception code, e.g. 0xC0000005 for an access violation
+    pub code: u32,
+    /// The address of the faulting instruction
+    pub address: usize,
+}
+
+impl fmt::Display for StructuredException {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = RECOVERABLE.iter().find(|(code, _)| *code == self.code).map_or("exception", |(_, name)| name);
+        write!(f, "{} (0x{:08X}) at {:#x}", name, self.code, self.address)
+    }
+}
+
+/// Installs the panic hook and prepares for minidumps. This happens the first time
+/// [`protect`] is called, so it only needs calling directly to cover other code.
+pub fn install() {
+    INSTALL.call_once(|| {
+        minidump::prepare();
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let thread = std::thread::current();
+            let location = info.location().map_or_else(String::new, |l| format!(" at {}:{}", l.file(), l.line()));
+            error!(
+                "panic in thread '{}'{}: {}\n{}",
+                thread.name().unwrap_or("unnamed"),
+                location,
+                panic_message(info.payload()),
+                std::backtrace::Backtrace::force_capture()
+            );
+            previous(info);
+        }));
+    });
+}
+
+/// Runs `f`, catching any panic or hardware exception it raises. The error is a
+/// description of the fault, which has already been logged with a backtrace.
+pub fn protect<R>(f: impl FnOnce() -> R) -> Result<R, String> {
+    install();
+    match seh::call(f) {
+        Ok(result) => result.map_err(|payload| panic_message(payload.as_ref())),
+        Err(fault) => Err(fault.to_string()),
+    }
+}
+
+/// Runs `f`, turning hardware exceptions it raises into panics that unwind to the caller.
+/// This is for work handed to another thread, whose panic is passed back to a caller that
+/// is inside [`protect`].
+pub fn guarded<R>(f: impl FnOnce() -> R) -> R {
+    install();
+    match seh::call(f) {
+        Ok(Ok(value)) => value,
+        Ok(Err(payload)) => panic::resume_unwind(payload),
+        Err(fault) => panic::panic_any(fault),
+    }
+}
+
+/// The message of a panic payload
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else if let Some(fault) = payload.downcast_ref::<StructuredException>() {
+        fault.to_string()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+/// A future whose polls run inside [`protect`], so it completes with an error if it faults
+pub(crate) struct Protected<F>(pub F);
+
+impl<F: Future> Future for Protected<F> {
+    type Output = Result<F::Output, String>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // The future is never moved out of the pinned wrapper
+        let future = unsafe { self.map_unchecked_mut(|protected| &mut protected.0) };
+        match protect(|| future.poll(cx)) {
+            Ok(Poll::Ready(value)) => Poll::Ready(Ok(value)),
+            Ok(Poll::Pending) => Poll::Pending,
+            Err(fault) => Poll::Ready(Err(fault)),
+        }
+    }
+}
+
+#[cfg(target_env = "msvc")]
+mod seh {
+    use super::{StructuredException, RECOVERABLE};
+    use crate::minidump;
+    use log::error;
+
+    use std::cell::Cell;
+    use std::ffi::c_void;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::path::PathBuf;
+    use windows::Win32::System::Diagnostics::Debug::{EXCEPTION_CONTINUE_SEARCH, EXCEPTION_POINTERS};
+
+    /// Tells `__except` to handle the exception, abandoning the frames above it
+    const EXCEPTION_EXECUTE_HANDLER: i32 = 1;
+
+    unsafe extern "C" {
+        /// Calls `body(data)` inside `__try`, returning 1 if `filter` handled an exception
+        /// it raised and 0 otherwise (seh.c)
+        fn xladd_seh_call(
+            body: unsafe extern "C" fn(*mut c_void),
+            data: *mut c_void,
+            filter: unsafe extern "C" fn(*mut EXCEPTION_POINTERS) -> i32,
+        ) -> i32;
+    }
+
+    thread_local! {
+        /// The exception handled on this thread
+        static FAULT: Cell<Option<StructuredException>> = const { Cell::new(None) };
+        /// The minidump written for that exception
+        static DUMP: Cell<Option<PathBuf>> = const { Cell::new(None) };
+    }
+
+    /// The closure to run and, once it has, what it returned or the panic it raised
+    struct Call<F, R> {
+        f: Option<F>,
+        result: Option<std::thread::Result<R>>,
+    }
+
+    /// Runs `f` inside `__try`, catching a panic before it reaches the C frame
+    pub(super) fn call<F: FnOnce() -> R, R>(f: F) -> Result<std::thread::Result<R>, StructuredException> {
+        let mut call = Call { f: Some(f), result: None };
+        let faulted = unsafe { xladd_seh_call(body::<F, R>, &mut call as *mut Call<F, R> as *mut c_void, filter) };
+        match call.result {
+            Some(result) if faulted == 0 => Ok(result),
+            _ => {
+                let fault = FAULT.with(|f| f.take()).unwrap_or(StructuredException { code: 0, address: 0 });
+                if let Some(path) = DUMP.with(|dump| dump.take()) {
+                    error!("{}: minidump written to {}", fault, path.display());
+                }
+                Err(fault)
+            }
+        }
+    }
+
+    unsafe extern "C" fn body<F: FnOnce() -> R, R>(data: *mut c_void) {
+        let call = unsafe { &mut *(data as *mut Call<F, R>) };
+        if let Some(f) = call.f.take() {
+            call.result = Some(panic::catch_unwind(AssertUnwindSafe(f)));
+        }
+    }
+
+    unsafe extern "C" fn filter(info: *mut EXCEPTION_POINTERS) -> i32 {
+        let record = unsafe { &*(*info).ExceptionRecord };
+        let code = record.ExceptionCode.0 as u32;
+        if !RECOVERABLE.iter().any(|(recoverable, _)| *recoverable == code) {
+            return EXCEPTION_CONTINUE_SEARCH; // includes panics, C++ exceptions and breakpoints
+        }
+        let fault = StructuredException { code, address: record.ExceptionAddress as usize };
+        // Nothing is logged until the handler has run, as the faulting code may hold the
+        // locks logging needs
+        FAULT.with(|f| f.set(Some(fault)));
+        DUMP.with(|dump| dump.set(unsafe { minidump::write(info) }));
+        EXCEPTION_EXECUTE_HANDLER
+    }
+}
+
+/// Without the shim hardware exceptions are left to Excel, and only panics are caught
+#[cfg(not(target_env = "msvc"))]
+mod seh {
+    use super::StructuredException;
+    use std::panic::{self, AssertUnwindSafe};
+
+    pub(super) fn call<F: FnOnce() -> R, R>(f: F) -> Result<std::thread::Result<R>, StructuredException> {
+        Ok(panic::catch_unwind(AssertUnwindSafe(f)))
+    }
+}
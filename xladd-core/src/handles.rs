@@ -0,0 +1,290 @@
+//! Registry of Rust objects referenced from the worksheet by handle. Large objects such as
+//! calibrated curves are built once, stored here, and passed to other functions as a short
+//! handle string like "YieldCurve#42" instead of being re-sent as ranges on every call.
+//!
+//! # Example
+//!
+//! #[xl_func]
+//! fn curve_create(dates: Vec<f64>, rates: Vec<f64>) -> Result<String, Box<dyn Error>> {
+//!     Ok(handles::insert("YieldCurve", YieldCurve::fit(&dates, &rates)?))
+//! }
+//!
+//! #[xl_func]
+//! fn curve_rate(curve: String, date: f64) -> Result<f64, Box<dyn Error>> {
+//!     Ok(handles::get::<YieldCurve>(&curve)?.rate(date))
+//! }
+
+use crate::caller::{empty_area, CellArea};
+use crate::entrypoint::excel12v;
+use crate::registrator::FunctionRegistration;
+use crate::scheduler::{self, TimerId};
+use crate::variant::{Variant, XLAddError};
+use crate::xlcall::{xlCoerce, xlSheetNm, xlerrNA, LPXLOPER12};
+use log::debug;
+
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+type Object = Arc<dyn Any + Send + Sync>;
+
+struct Entry {
+    object: Object,
+    rust_type: &'static str,
+    created: Instant,
+    owner: Option<CellArea>,
+    generation: u64,
+}
+
+struct Registry {
+    objects: BTreeMap<String, Entry>,
+    /// The latest handle and generation created by each cell, for each type name
+    latest: BTreeMap<(CellArea, String), (String, u64)>,
+}
+
+static HANDLES: Mutex<Registry> = Mutex::new(Registry { objects: BTreeMap::new(), latest: BTreeMap::new() });
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+static COLLECTOR: Mutex<Option<TimerId>> = Mutex::new(None);
+
+fn registry() -> MutexGuard<'static, Registry> {
+    HANDLES.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// What the registry knows about a handle
+#[derive(Debug, Clone)]
+pub struct HandleInfo {
+    pub handle: String,
+    /// The name given when the object was inserted, e.g. "YieldCurve"
+    pub type_name: String,
+    /// The Rust type of the stored object
+    pub rust_type: &'static str,
+    pub age_seconds: f64,
+    /// The cells that created the object, e.g. "[Book1]Sheet1!R2C3", if it was created by a formula
+    pub owner: Option<String>,
+    /// How many objects of this type the owning cells have created, including this one
+    pub generation: u64,
+}
+
+/// Stores an object and returns a new handle for it, made from `type_name` and a unique
+/// number. When called from a worksheet function, the object is tied to the calling
+/// cells: the object they created last time with the same type name is released, and
+/// `collect_garbage` releases this one once the cells no longer show its handle.
+///
+/// This asks Excel for the calling cells, so from threads other than Excel's own, use
+/// `insert_untracked` instead.
+pub fn insert<T: Any + Send + Sync>(type_name: &str, object: T) -> String {
+    store(type_name, object, CellArea::calling_cells())
+}
+
+/// Stores an object that is not tied to any cells. It lives until it is removed.
+pub fn insert_untracked<T: Any + Send + Sync>(type_name: &str, object: T) -> String {
+    store(type_name, object, None)
+}
+
+fn store<T: Any + Send + Sync>(type_name: &str, object: T, owner: Option<CellArea>) -> String {
+    let handle = format!("{}#{}", type_name, NEXT_ID.fetch_add(1, Ordering::Relaxed));
+    let mut registry = registry();
+    let mut generation = 1;
+    if let Some(owner) = owner {
+        let key = (owner, type_name.to_string());
+        if let Some((previous, previous_generation)) = registry.latest.remove(&key) {
+            // The cells have been recalculated, so their previous object is no longer shown
+            registry.objects.remove(&previous);
+            generation = previous_generation + 1;
+        }
+        registry.latest.insert(key, (handle.clone(), generation));
+    }
+    let entry = Entry {
+        object: Arc::new(object),
+        rust_type: std::any::type_name::<T>(),
+        created: Instant::now(),
+        owner,
+        generation,
+    };
+    registry.objects.insert(handle.clone(), entry);
+    handle
+}
+
+/// Looks up the object behind a handle. Fails if the handle is unknown or refers to an
+/// object of a different type.
+pub fn get<T: Any + Send + Sync>(handle: &str) -> Result<Arc<T>, XLAddError> {
+    let object = registry()
+        .objects
+        .get(handle)
+        .map(|entry| entry.object.clone())
+        .ok_or_else(|| XLAddError::InvalidHandle(handle.to_string()))?;
+    object.downcast::<T>().map_err(|_| {
+        XLAddError::InvalidData(format!("{} is not a {}", handle, std::any::type_name::<T>()))
+    })
+}
+
+/// Whether a handle refers to a stored object
+pub fn contains(handle: &str) -> bool {
+    registry().objects.contains_key(handle)
+}
+
+/// Drops the object behind a handle. Returns false if the handle was unknown. Callers
+/// still holding the object from `get` keep it alive until they are done with it.
+pub fn remove(handle: &str) -> bool {
+    let mut registry = registry();
+    registry.latest.retain(|_, (latest, _)| latest != handle);
+    registry.objects.remove(handle).is_some()
+}
+
+/// Drops every stored object
+pub fn clear() {
+    let mut registry = registry();
+    registry.objects.clear();
+    registry.latest.clear();
+}
+
+fn describe(handle: &str, entry: &Entry) -> HandleInfo {
+    HandleInfo {
+        handle: handle.to_string(),
+        type_name: handle.rsplit_once('#').map_or(handle, |(name, _)| name).to_string(),
+        rust_type: entry.rust_type,
+        age_seconds: entry.created.elapsed().as_secs_f64(),
+        owner: entry.owner.map(|owner| owner.address()),
+        generation: entry.generation,
+    }
+}
+
+/// Describes a single handle, or None if it is unknown
+pub fn info(handle: &str) -> Option<HandleInfo> {
+    registry().objects.get(handle).map(|entry| describe(handle, entry))
+}
+
+/// Describes every stored handle, in handle order
+pub fn list() -> Vec<HandleInfo> {
+    registry()
+        .objects
+        .iter()
+        .map(|(handle, entry)| describe(handle, entry))
+        .collect()
+}
+
+/// Whether the owning cells still show the handle. Cells on a deleted sheet, or whose
+/// formula has been cleared or replaced, no longer do.
+fn still_shown(handle: &str, owner: &CellArea) -> bool {
+    let mut storage = empty_area();
+    let mut reference = owner.to_reference(&mut storage);
+    let mut sheet = Variant::default();
+    if excel12v(xlSheetNm as i32, sheet.as_mut_xloper(), &[&mut reference as LPXLOPER12]) != 0 {
+        return false; // the sheet has gone
+    }
+    let mut values = Variant::default();
+    if excel12v(xlCoerce as i32, values.as_mut_xloper(), &[&mut reference as LPXLOPER12]) != 0 {
+        return true; // we cannot tell, so keep the object
+    }
+    let (columns, rows) = values.dim();
+    (0..rows).any(|row| (0..columns).any(|column| String::from(&values.at(column, row)) == handle))
+}
+
+/// Releases objects whose creating cells no longer show their handle, returning how many
+/// were released. Objects not created by a formula are left alone. This reads cells, so it
+/// must be called from a command context, outside of recalculation.
+pub fn collect_garbage() -> usize {
+    let owned: Vec<(String, CellArea)> = registry()
+        .objects
+        .iter()
+        .filter_map(|(handle, entry)| entry.owner.map(|owner| (handle.clone(), owner)))
+        .collect();
+    let stale: Vec<String> = owned
+        .into_iter()
+        .filter(|(handle, owner)| !still_shown(handle, owner))
+        .map(|(handle, _)| handle)
+        .collect();
+    for handle in stale.iter() {
+        remove(handle);
+    }
+    if !stale.is_empty() {
+        debug!("collect_garbage: released {} handles", stale.len());
+    }
+    stale.len()
+}
+
+/// Runs `collect_garbage` every `interval`. This must be called from a command context,
+/// such as xlAutoOpen.
+pub fn start_collector(interval: Duration) {
+    let mut collector = COLLECTOR.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(id) = collector.take() {
+        scheduler::cancel(id);
+    }
+    *collector = Some(scheduler::schedule_every(interval, || {
+        collect_garbage();
+    }));
+}
+
+/// Frees the object behind a handle. Returns TRUE if it was freed, FALSE if the handle was unknown.
+#[unsafe(no_mangle)]
+pub extern "system" fn xl_free_handle(handle: LPXLOPER12) -> LPXLOPER12 {
+    let handle = String::from(&Variant::from(handle));
+    LPXLOPER12::from(Variant::from(remove(&handle)))
+}
+
+inventory::submit! {
+    FunctionRegistration {
+        xl_name: "xl_free_handle",
+        arg_types: "QQ$",
+        arg_names: "handle",
+        category: "Add-in Diagnostics",
+        description: "Frees the object behind a handle",
+        arg_infos: &[],
+        help_topic: "",
+    }
+}
+
+/// Describes a handle as a two-column table of labels and values, or #N/A if it is unknown.
+/// With no handle, lists every stored handle with its type and age.
+#[unsafe(no_mangle)]
+pub extern "system" fn xl_handle_info(handle: LPXLOPER12) -> LPXLOPER12 {
+    let handle = Variant::from(handle);
+    let result = if handle.is_missing_or_null() {
+        let mut rows = vec![vec![
+            Variant::from("Handle"),
+            Variant::from("Type"),
+            Variant::from("Rust type"),
+            Variant::from("Age (s)"),
+            Variant::from("Created by"),
+            Variant::from("Generation"),
+        ]];
+        for info in list() {
+            rows.push(vec![
+                Variant::from(info.handle),
+                Variant::from(info.type_name),
+                Variant::from(info.rust_type),
+                Variant::from(info.age_seconds),
+                Variant::from(info.owner.unwrap_or_default()),
+                Variant::from(info.generation as f64),
+            ]);
+        }
+        Variant::from(rows)
+    } else {
+        match info(&String::from(&handle)) {
+            Some(info) => Variant::from(vec![
+                ("Handle".to_string(), info.handle),
+                ("Type".to_string(), info.type_name),
+                ("Rust type".to_string(), info.rust_type.to_string()),
+                ("Age (s)".to_string(), format!("{:.0}", info.age_seconds)),
+                ("Created by".to_string(), info.owner.unwrap_or_else(|| "-".to_string())),
+                ("Generation".to_string(), info.generation.to_string()),
+            ]),
+            None => Variant::from_err(xlerrNA),
+        }
+    };
+    LPXLOPER12::from(result)
+}
+
+inventory::submit! {
+    FunctionRegistration {
+        xl_name: "xl_handle_info",
+        arg_types: "QQ$",
+        arg_names: "handle",
+        category: "Add-in Diagnostics",
+        description: "Describes the object behind a handle, or lists all handles if none is given",
+        arg_infos: &[],
+        help_topic: "",
+    }
+}
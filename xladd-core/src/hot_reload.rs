@@ -0,0 +1,326 @@
+//! Development mode in which the xll is a thin loader for a companion "logic" dll, which
+//! is reloaded whenever it is rebuilt, so functions can be changed without restarting
+//! Excel. The loader's xlAutoOpen calls [`start`] with the path of the logic dll, which is
+//! built as usual with `#[xl_func]` and this feature turned on:
+//!
+//! #[unsafe(no_mangle)]
+//! pub extern "system" fn xlAutoOpen() -> i32 {
+//!     logging::init(LogConfig::default());
+//!     hot_reload::start(Path::new(r"C:\dev\pricing\target\debug\pricing.dll"));
+//!     1
+//! }
+//!
+//! Excel can only call functions exported by the xll itself, so the loader exports a bank
+//! of generic slot functions and registers one for each function of the logic dll, under
+//! its name. A slot passes its arguments straight on to the function it points at. The
+//! logic dll is loaded from a copy, so the original can be overwritten by the linker;
+//! every second the loader checks whether it has changed, and if so unregisters the
+//! functions, unloads the old copy, loads the new one and registers them again.
+//!
+//! This is for development only. Both dlls must be built with the same version of this
+//! crate, functions may take at most [`SLOT_ARGS`] arguments, and the logic dll's
+//! commands are not registered.
+
+use crate::config;
+use crate::entrypoint::excel12;
+use crate::registrator::FunctionRegistration;
+use crate::scheduler::{self, TimerId};
+use crate::variant::Variant;
+use crate::xlcall::{xlGetName, xlerrName, xlfRegister, xlfUnregister, LPXLOPER12};
+use log::{debug, info, warn};
+
+use std::ffi::c_void;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime};
+use windows::Win32::Foundation::{FreeLibrary, HMODULE};
+use windows::Win32::System::LibraryLoader::{GetProcAddress, LoadLibraryW};
+use windows::core::{HSTRING, PCSTR};
+
+/// The most arguments a reloadable function can take
+pub const SLOT_ARGS: usize = 32;
+/// How often the logic dll is checked for changes
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Exported by every dll built with this feature, to describe its functions to the loader
+const MANIFEST_EXPORT: &str = "xladd_hot_reload_manifest";
+/// Separates the fields of a function in the manifest; functions are separated by newlines
+const FIELD_SEPARATOR: char = '\t';
+
+type SlotTarget = extern "system" fn(LPXLOPER12, LPXLOPER12, LPXLOPER12, LPXLOPER12, LPXLOPER12, LPXLOPER12, LPXLOPER12, LPXLOPER12, LPXLOPER12, LPXLOPER12, LPXLOPER12, LPXLOPER12, LPXLOPER12, LPXLOPER12, LPXLOPER12, LPXLOPER12, LPXLOPER12, LPXLOPER12, LPXLOPER12, LPXLOPER12, LPXLOPER12, LPXLOPER12, LPXLOPER12, LPXLOPER12, LPXLOPER12, LPXLOPER12, LPXLOPER12, LPXLOPER12, LPXLOPER12, LPXLOPER12, LPXLOPER12, LPXLOPER12) -> LPXLOPER12;
+type ManifestSink = extern "system" fn(*mut c_void, *const u8, usize);
+
+/// Where each slot forwards to, or zero if it is not in use
+static SLOTS: [AtomicUsize; SLOT_COUNT] = [const { AtomicUsize::new(0) }; SLOT_COUNT];
+
+struct Loaded {
+    /// The dll as built
+    source: PathBuf,
+    /// The copy that is loaded, and its handle as an integer
+    shadow: Option<(PathBuf, usize)>,
+    /// When the source was last loaded, or seen to change
+    modified: Option<SystemTime>,
+    /// Whether the source has changed since the last check, and may still be being written
+    changing: bool,
+    /// Names and register ids of the slots registered with Excel
+    registered: Vec<(String, Variant)>,
+    /// How many times the logic dll has been loaded, to give each copy its own name
+    generation: u32,
+    timer: Option<TimerId>,
+}
+
+unsafe impl Send for Loaded {}
+
+static LOADED: Mutex<Option<Loaded>> = Mutex::new(None);
+
+/// Loads the logic dll, registers its functions and starts watching it for changes. This
+/// can only be called from xlAutoOpen or a command.
+pub fn start(logic: &Path) {
+    stop();
+    let mut loaded = Loaded {
+        source: logic.to_path_buf(),
+        shadow: None,
+        modified: modified(logic),
+        changing: false,
+        registered: Vec::new(),
+        generation: 0,
+        timer: None,
+    };
+    reload(&mut loaded);
+    loaded.timer = Some(scheduler::schedule_every(POLL_INTERVAL, poll));
+    *LOADED.lock().unwrap_or_else(|e| e.into_inner()) = Some(loaded);
+}
+
+/// Unregisters the logic dll's functions and unloads it. This is called from xlAutoClose.
+pub fn stop() {
+    let Some(mut loaded) = LOADED.lock().unwrap_or_else(|e| e.into_inner()).take() else {
+        return;
+    };
+    if let Some(timer) = loaded.timer.take() {
+        scheduler::cancel(timer);
+    }
+    unload(&mut loaded);
+}
+
+/// Reloads the logic dll once it has changed and the linker has finished writing it
+fn poll() {
+    let mut guard = LOADED.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(loaded) = guard.as_mut() else {
+        return;
+    };
+    let modified = modified(&loaded.source);
+    if modified.is_none() || modified == loaded.modified {
+        if loaded.changing && modified.is_some() {
+            loaded.changing = false;
+            reload(loaded);
+        }
+        return;
+    }
+    // Wait for one quiet interval, in case the file is still being written
+    loaded.modified = modified;
+    loaded.changing = true;
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+fn reload(loaded: &mut Loaded) {
+    unload(loaded);
+    loaded.generation += 1;
+    let stem = loaded.source.file_stem().map_or_else(|| "logic".into(), |stem| stem.to_string_lossy().into_owned());
+    let shadow = std::env::temp_dir().join("xladd-hot-reload").join(format!("{}-{}-{}.dll", stem, std::process::id(), loaded.generation));
+    let copied = std::fs::create_dir_all(shadow.parent().unwrap_or(Path::new(".")))
+        .and_then(|()| std::fs::copy(&loaded.source, &shadow));
+    if let Err(e) = copied {
+        warn!("cannot copy {} to {}: {}", loaded.source.display(), shadow.display(), e);
+        return;
+    }
+    let module = match unsafe { LoadLibraryW(&HSTRING::from(shadow.as_os_str())) } {
+        Ok(module) => module,
+        Err(e) => {
+            warn!("cannot load {}: {}", loaded.source.display(), e);
+            return;
+        }
+    };
+    loaded.shadow = Some((shadow, module.0 as usize));
+    let Some(manifest) = read_manifest(module) else {
+        warn!("{} does not export {}; is it built with the hot-reload feature?", loaded.source.display(), MANIFEST_EXPORT);
+        return;
+    };
+    register(loaded, module, &manifest);
+}
+
+/// Calls the logic dll's manifest export and returns the text it gives
+fn read_manifest(module: HMODULE) -> Option<String> {
+    extern "system" fn append(context: *mut c_void, text: *const u8, length: usize) {
+        let manifest = unsafe { &mut *(context as *mut String) };
+        let bytes = unsafe { std::slice::from_raw_parts(text, length) };
+        manifest.push_str(&String::from_utf8_lossy(bytes));
+    }
+    let name = format!("{}\0", MANIFEST_EXPORT);
+    let export = unsafe { GetProcAddress(module, PCSTR(name.as_ptr())) }?;
+    let export: extern "system" fn(ManifestSink, *mut c_void) = unsafe { std::mem::transmute(export) };
+    let mut manifest = String::new();
+    export(append, &mut manifest as *mut String as *mut c_void);
+    Some(manifest)
+}
+
+/// Points a slot at each function in the manifest and registers it with Excel
+fn register(loaded: &mut Loaded, module: HMODULE, manifest: &str) {
+    let dll_name = excel12(xlGetName, &mut []);
+    let config = config::current();
+    let mut slot = 0;
+    for line in manifest.lines().filter(|line| !line.is_empty()) {
+        let fields: Vec<&str> = line.split(FIELD_SEPARATOR).collect();
+        let [xl_name, arg_types, arg_names, category, description, arg_descriptions @ ..] = fields.as_slice() else {
+            warn!("unreadable manifest entry: {}", line);
+            continue;
+        };
+        if slot == SLOT_COUNT {
+            warn!("only {} functions can be reloaded; {} and later ones are not registered", SLOT_COUNT, xl_name);
+            break;
+        }
+        if arg_descriptions.len() > SLOT_ARGS {
+            warn!("{} takes more than {} arguments and cannot be reloaded", xl_name, SLOT_ARGS);
+            continue;
+        }
+        let name = format!("{}\0", xl_name);
+        let Some(function) = (unsafe { GetProcAddress(module, PCSTR(name.as_ptr())) }) else {
+            warn!("{} is not exported from {}", xl_name, loaded.source.display());
+            continue;
+        };
+        SLOTS[slot].store(function as usize, Ordering::SeqCst);
+        let excel_name = config.excel_name(xl_name);
+        let mut opers = vec![
+            dll_name.clone(),
+            Variant::from(SLOT_NAMES[slot]),
+            Variant::from(*arg_types),
+            Variant::from(excel_name.as_str()),
+            Variant::from(*arg_names),
+            Variant::from(1), // type 1 means useable anywhere
+            Variant::from(config.category(category).as_str()),
+            Variant::missing(), // no shortcut
+            Variant::missing(), // no help url
+            Variant::from(*description),
+        ];
+        opers.extend(arg_descriptions.iter().map(|&description| Variant::from(description)));
+        let id = excel12(xlfRegister, opers.as_mut_slice());
+        debug!("Registered {} through {}: result = {}", excel_name, SLOT_NAMES[slot], id);
+        loaded.registered.push((excel_name, id));
+        slot += 1;
+    }
+    info!("loaded {} with {} functions", loaded.source.display(), slot);
+}
+
+/// Unregisters the functions and unloads the logic dll, giving it the chance to stop its
+/// own threads first
+fn unload(loaded: &mut Loaded) {
+    for (name, id) in loaded.registered.drain(..) {
+        let result = excel12(xlfUnregister, &mut [id]);
+        debug!("Unregistered {}: result = {}", name, result);
+    }
+    for slot in &SLOTS {
+        slot.store(0, Ordering::SeqCst);
+    }
+    let Some((shadow, module)) = loaded.shadow.take() else {
+        return;
+    };
+    let module = HMODULE(module as *mut c_void);
+    unsafe {
+        if let Some(close) = GetProcAddress(module, PCSTR(c"xlAutoClose".as_ptr().cast())) {
+            let close: extern "system" fn() -> i32 = std::mem::transmute(close);
+            close();
+        }
+        if let Err(e) = FreeLibrary(module) {
+            warn!("cannot unload {}: {}", shadow.display(), e);
+            return;
+        }
+    }
+    // The copies of earlier builds are no longer needed
+    let _ = std::fs::remove_file(&shadow);
+}
+
+/// Describes the functions of this dll to a loader. Each is one line of tab-separated
+/// fields: exported name, type string, argument names, category, description and the
+/// description of each argument.
+#[unsafe(no_mangle)]
+pub extern "system" fn xladd_hot_reload_manifest(sink: ManifestSink, context: *mut c_void) {
+    let clean = |text: &str| text.replace(['\t', '\r', '\n'], " ");
+    let mut manifest = String::new();
+    for registration in inventory::iter::<FunctionRegistration> {
+        let mut fields = vec![
+            clean(registration.xl_name),
+            clean(registration.arg_types),
+            clean(registration.arg_names),
+            clean(registration.category),
+            clean(registration.description),
+        ];
+        fields.extend(registration.arg_infos.iter().map(|arg_info| clean(arg_info.description)));
+        manifest.push_str(&fields.join(&FIELD_SEPARATOR.to_string()));
+        manifest.push('\n');
+    }
+    sink(context, manifest.as_ptr(), manifest.len());
+}
+
+/// Calls the function a slot points at, with every argument Excel may have passed. Excel
+/// only passes as many as the function was registered with, and the function only reads
+/// those, so the rest are never looked at.
+fn forward(slot: usize, args: [LPXLOPER12; SLOT_ARGS]) -> LPXLOPER12 {
+    let target = SLOTS[slot].load(Ordering::SeqCst);
+    if target == 0 {
+        return LPXLOPER12::from(Variant::from_err(xlerrName));
+    }
+    let target: SlotTarget = unsafe { std::mem::transmute(target) };
+    let [a0, a1, a2, a3, a4, a5, a6, a7, a8, a9, a10, a11, a12, a13, a14, a15, a16, a17, a18, a19, a20, a21, a22, a23, a24, a25, a26, a27, a28, a29, a30, a31] = args;
+    target(a0, a1, a2, a3, a4, a5, a6, a7, a8, a9, a10, a11, a12, a13, a14, a15, a16, a17, a18, a19, a20, a21, a22, a23, a24, a25, a26, a27, a28, a29, a30, a31)
+}
+
+macro_rules! slots {
+    ($($name:ident = $slot:literal,)*) => {
+        $(
+            #[unsafe(no_mangle)]
+            extern "system" fn $name(a0: LPXLOPER12, a1: LPXLOPER12, a2: LPXLOPER12, a3: LPXLOPER12, a4: LPXLOPER12, a5: LPXLOPER12, a6: LPXLOPER12, a7: LPXLOPER12, a8: LPXLOPER12, a9: LPXLOPER12, a10: LPXLOPER12, a11: LPXLOPER12, a12: LPXLOPER12, a13: LPXLOPER12, a14: LPXLOPER12, a15: LPXLOPER12, a16: LPXLOPER12, a17: LPXLOPER12, a18: LPXLOPER12, a19: LPXLOPER12, a20: LPXLOPER12, a21: LPXLOPER12, a22: LPXLOPER12, a23: LPXLOPER12, a24: LPXLOPER12, a25: LPXLOPER12, a26: LPXLOPER12, a27: LPXLOPER12, a28: LPXLOPER12, a29: LPXLOPER12, a30: LPXLOPER12, a31: LPXLOPER12) -> LPXLOPER12 {
+                forward($slot, [a0, a1, a2, a3, a4, a5, a6, a7, a8, a9, a10, a11, a12, a13, a14, a15, a16, a17, a18, a19, a20, a21, a22, a23, a24, a25, a26, a27, a28, a29, a30, a31])
+            }
+        )*
+        /// The exported names of the slots
+        const SLOT_NAMES: &[&str] = &[$(stringify!($name)),*];
+        const SLOT_COUNT: usize = SLOT_NAMES.len();
+    };
+}
+
+slots! {
+    xladd_slot_0 = 0, xladd_slot_1 = 1, xladd_slot_2 = 2, xladd_slot_3 = 3, xladd_slot_4 = 4, xladd_slot_5 = 5, xladd_slot_6 = 6, xladd_slot_7 = 7,
+    xladd_slot_8 = 8, xladd_slot_9 = 9, xladd_slot_10 = 10, xladd_slot_11 = 11, xladd_slot_12 = 12, xladd_slot_13 = 13, xladd_slot_14 = 14, xladd_slot_15 = 15,
+    xladd_slot_16 = 16, xladd_slot_17 = 17, xladd_slot_18 = 18, xladd_slot_19 = 19, xladd_slot_20 = 20, xladd_slot_21 = 21, xladd_slot_22 = 22, xladd_slot_23 = 23,
+    xladd_slot_24 = 24, xladd_slot_25 = 25, xladd_slot_26 = 26, xladd_slot_27 = 27, xladd_slot_28 = 28, xladd_slot_29 = 29, xladd_slot_30 = 30, xladd_slot_31 = 31,
+    xladd_slot_32 = 32, xladd_slot_33 = 33, xladd_slot_34 = 34, xladd_slot_35 = 35, xladd_slot_36 = 36, xladd_slot_37 = 37, xladd_slot_38 = 38, xladd_slot_39 = 39,
+    xladd_slot_40 = 40, xladd_slot_41 = 41, xladd_slot_42 = 42, xladd_slot_43 = 43, xladd_slot_44 = 44, xladd_slot_45 = 45, xladd_slot_46 = 46, xladd_slot_47 = 47,
+    xladd_slot_48 = 48, xladd_slot_49 = 49, xladd_slot_50 = 50, xladd_slot_51 = 51, xladd_slot_52 = 52, xladd_slot_53 = 53, xladd_slot_54 = 54, xladd_slot_55 = 55,
+    xladd_slot_56 = 56, xladd_slot_57 = 57, xladd_slot_58 = 58, xladd_slot_59 = 59, xladd_slot_60 = 60, xladd_slot_61 = 61, xladd_slot_62 = 62, xladd_slot_63 = 63,
+    xladd_slot_64 = 64, xladd_slot_65 = 65, xladd_slot_66 = 66, xladd_slot_67 = 67, xladd_slot_68 = 68, xladd_slot_69 = 69, xladd_slot_70 = 70, xladd_slot_71 = 71,
+    xladd_slot_72 = 72, xladd_slot_73 = 73, xladd_slot_74 = 74, xladd_slot_75 = 75, xladd_slot_76 = 76, xladd_slot_77 = 77, xladd_slot_78 = 78, xladd_slot_79 = 79,
+    xladd_slot_80 = 80, xladd_slot_81 = 81, xladd_slot_82 = 82, xladd_slot_83 = 83, xladd_slot_84 = 84, xladd_slot_85 = 85, xladd_slot_86 = 86, xladd_slot_87 = 87,
+    xladd_slot_88 = 88, xladd_slot_89 = 89, xladd_slot_90 = 90, xladd_slot_91 = 91, xladd_slot_92 = 92, xladd_slot_93 = 93, xladd_slot_94 = 94, xladd_slot_95 = 95,
+    xladd_slot_96 = 96, xladd_slot_97 = 97, xladd_slot_98 = 98, xladd_slot_99 = 99, xladd_slot_100 = 100, xladd_slot_101 = 101, xladd_slot_102 = 102, xladd_slot_103 = 103,
+    xladd_slot_104 = 104, xladd_slot_105 = 105, xladd_slot_106 = 106, xladd_slot_107 = 107, xladd_slot_108 = 108, xladd_slot_109 = 109, xladd_slot_110 = 110, xladd_slot_111 = 111,
+    xladd_slot_112 = 112, xladd_slot_113 = 113, xladd_slot_114 = 114, xladd_slot_115 = 115, xladd_slot_116 = 116, xladd_slot_117 = 117, xladd_slot_118 = 118, xladd_slot_119 = 119,
+    xladd_slot_120 = 120, xladd_slot_121 = 121, xladd_slot_122 = 122, xladd_slot_123 = 123, xladd_slot_124 = 124, xladd_slot_125 = 125, xladd_slot_126 = 126, xladd_slot_127 = 127,
+    xladd_slot_128 = 128, xladd_slot_129 = 129, xladd_slot_130 = 130, xladd_slot_131 = 131, xladd_slot_132 = 132, xladd_slot_133 = 133, xladd_slot_134 = 134, xladd_slot_135 = 135,
+    xladd_slot_136 = 136, xladd_slot_137 = 137, xladd_slot_138 = 138, xladd_slot_139 = 139, xladd_slot_140 = 140, xladd_slot_141 = 141, xladd_slot_142 = 142, xladd_slot_143 = 143,
+    xladd_slot_144 = 144, xladd_slot_145 = 145, xladd_slot_146 = 146, xladd_slot_147 = 147, xladd_slot_148 = 148, xladd_slot_149 = 149, xladd_slot_150 = 150, xladd_slot_151 = 151,
+    xladd_slot_152 = 152, xladd_slot_153 = 153, xladd_slot_154 = 154, xladd_slot_155 = 155, xladd_slot_156 = 156, xladd_slot_157 = 157, xladd_slot_158 = 158, xladd_slot_159 = 159,
+    xladd_slot_160 = 160, xladd_slot_161 = 161, xladd_slot_162 = 162, xladd_slot_163 = 163, xladd_slot_164 = 164, xladd_slot_165 = 165, xladd_slot_166 = 166, xladd_slot_167 = 167,
+    xladd_slot_168 = 168, xladd_slot_169 = 169, xladd_slot_170 = 170, xladd_slot_171 = 171, xladd_slot_172 = 172, xladd_slot_173 = 173, xladd_slot_174 = 174, xladd_slot_175 = 175,
+    xladd_slot_176 = 176, xladd_slot_177 = 177, xladd_slot_178 = 178, xladd_slot_179 = 179, xladd_slot_180 = 180, xladd_slot_181 = 181, xladd_slot_182 = 182, xladd_slot_183 = 183,
+    xladd_slot_184 = 184, xladd_slot_185 = 185, xladd_slot_186 = 186, xladd_slot_187 = 187, xladd_slot_188 = 188, xladd_slot_189 = 189, xladd_slot_190 = 190, xladd_slot_191 = 191,
+    xladd_slot_192 = 192, xladd_slot_193 = 193, xladd_slot_194 = 194, xladd_slot_195 = 195, xladd_slot_196 = 196, xladd_slot_197 = 197, xladd_slot_198 = 198, xladd_slot_199 = 199,
+    xladd_slot_200 = 200, xladd_slot_201 = 201, xladd_slot_202 = 202, xladd_slot_203 = 203, xladd_slot_204 = 204, xladd_slot_205 = 205, xladd_slot_206 = 206, xladd_slot_207 = 207,
+    xladd_slot_208 = 208, xladd_slot_209 = 209, xladd_slot_210 = 210, xladd_slot_211 = 211, xladd_slot_212 = 212, xladd_slot_213 = 213, xladd_slot_214 = 214, xladd_slot_215 = 215,
+    xladd_slot_216 = 216, xladd_slot_217 = 217, xladd_slot_218 = 218, xladd_slot_219 = 219, xladd_slot_220 = 220, xladd_slot_221 = 221, xladd_slot_222 = 222, xladd_slot_223 = 223,
+    xladd_slot_224 = 224, xladd_slot_225 = 225, xladd_slot_226 = 226, xladd_slot_227 = 227, xladd_slot_228 = 228, xladd_slot_229 = 229, xladd_slot_230 = 230, xladd_slot_231 = 231,
+    xladd_slot_232 = 232, xladd_slot_233 = 233, xladd_slot_234 = 234, xladd_slot_235 = 235, xladd_slot_236 = 236, xladd_slot_237 = 237, xladd_slot_238 = 238, xladd_slot_239 = 239,
+    xladd_slot_240 = 240, xladd_slot_241 = 241, xladd_slot_242 = 242, xladd_slot_243 = 243, xladd_slot_244 = 244, xladd_slot_245 = 245, xladd_slot_246 = 246, xladd_slot_247 = 247,
+    xladd_slot_248 = 248, xladd_slot_249 = 249, xladd_slot_250 = 250, xladd_slot_251 = 251, xladd_slot_252 = 252, xladd_slot_253 = 253, xladd_slot_254 = 254, xladd_slot_255 = 255,
+}
@@ -0,0 +1,117 @@
+//! Prompts for commands: Excel's input box, and the standard Windows open and save
+//! file dialogs. These block until the user responds, so only call them from commands.
+//!
+//! # Example
+//!
+//! if let Some(path) = open_file("Import trades", &[("CSV files", "*.csv"), ("All files", "*.*")]) {
+//!     import_trades(&path);
+//! }
+
+use crate::entrypoint::excel12;
+use crate::variant::Variant;
+use crate::xlcall::{xlGetHwnd, xlfInput};
+
+use std::path::PathBuf;
+use widestring::{U16CString, U16String};
+use windows::Win32::Foundation::HWND;
+use windows::Win32::UI::Controls::Dialogs::{
+    GetOpenFileNameW, GetSaveFileNameW, OFN_FILEMUSTEXIST, OFN_NOCHANGEDIR, OFN_OVERWRITEPROMPT,
+    OFN_PATHMUSTEXIST, OPEN_FILENAME_FLAGS, OPENFILENAMEW,
+};
+use windows::core::{PCWSTR, PWSTR};
+
+// Value types accepted by INPUT
+const INPUT_NUMBER: i32 = 1;
+const INPUT_TEXT: i32 = 2;
+
+/// Longest path the file dialogs will return
+const MAX_PATH_CHARS: usize = 32 * 1024;
+
+fn input_box(prompt: &str, title: &str, input_type: i32, default: Variant) -> Option<Variant> {
+    let result = excel12(
+        xlfInput,
+        &mut [Variant::from(prompt), Variant::from(input_type), Variant::from(title), default],
+    );
+    // INPUT returns FALSE if the user cancels
+    match bool::try_from(&result) {
+        Ok(false) => None,
+        _ => Some(result),
+    }
+}
+
+/// Asks the user to type some text. Returns None if they cancel.
+pub fn input_text(prompt: &str, title: &str, default: &str) -> Option<String> {
+    input_box(prompt, title, INPUT_TEXT, Variant::from(default)).map(|v| String::from(&v))
+}
+
+/// Asks the user to type a number. Excel rejects anything that is not a number and asks
+/// again. Returns None if they cancel.
+pub fn input_number(prompt: &str, title: &str, default: Option<f64>) -> Option<f64> {
+    let default = default.map_or_else(Variant::missing, Variant::from);
+    input_box(prompt, title, INPUT_NUMBER, default).and_then(|v| f64::try_from(&v).ok())
+}
+
+/// Shows the standard open file dialog. Filters are (description, pattern) pairs such as
+/// ("CSV files", "*.csv"); separate several patterns with semicolons.
+pub fn open_file(title: &str, filters: &[(&str, &str)]) -> Option<PathBuf> {
+    file_dialog(title, filters, "", OFN_FILEMUSTEXIST | OFN_PATHMUSTEXIST, false)
+}
+
+/// Shows the standard save file dialog, starting with the given file name. The user is
+/// asked before an existing file is overwritten.
+pub fn save_file(title: &str, default_name: &str, filters: &[(&str, &str)]) -> Option<PathBuf> {
+    file_dialog(title, filters, default_name, OFN_OVERWRITEPROMPT | OFN_PATHMUSTEXIST, true)
+}
+
+fn file_dialog(title: &str, filters: &[(&str, &str)], default_name: &str, flags: OPEN_FILENAME_FLAGS, save: bool) -> Option<PathBuf> {
+    // The filter is a list of nul-separated pairs, terminated by an extra nul
+    let mut filter = U16String::new();
+    for (description, pattern) in filters {
+        filter.push_str(description);
+        filter.push_char('\0');
+        filter.push_str(pattern);
+        filter.push_char('\0');
+    }
+    filter.push_char('\0');
+
+    let mut file = vec![0u16; MAX_PATH_CHARS];
+    let default_name: Vec<u16> = default_name.encode_utf16().take(MAX_PATH_CHARS - 1).collect();
+    file[..default_name.len()].copy_from_slice(&default_name);
+
+    // Use the default extension of the first filter, e.g. "csv" for "*.csv"
+    let extension = filters
+        .first()
+        .and_then(|(_, pattern)| pattern.split(';').next())
+        .and_then(|pattern| pattern.strip_prefix("*."))
+        .filter(|ext| !ext.contains('*'))
+        .unwrap_or("");
+    let extension = U16CString::from_str_truncate(extension);
+    let title = U16CString::from_str_truncate(title);
+
+    let owner = i32::from(&excel12(xlGetHwnd, &mut []));
+    let mut dialog = OPENFILENAMEW {
+        lStructSize: std::mem::size_of::<OPENFILENAMEW>() as u32,
+        hwndOwner: HWND(owner as isize as *mut _),
+        lpstrFilter: if filters.is_empty() { PCWSTR::null() } else { PCWSTR(filter.as_ptr()) },
+        nFilterIndex: 1,
+        lpstrFile: PWSTR(file.as_mut_ptr()),
+        nMaxFile: file.len() as u32,
+        lpstrTitle: PCWSTR(title.as_ptr()),
+        lpstrDefExt: if extension.is_empty() { PCWSTR::null() } else { PCWSTR(extension.as_ptr()) },
+        Flags: flags | OFN_NOCHANGEDIR,
+        ..Default::default()
+    };
+
+    let chosen = unsafe {
+        if save {
+            GetSaveFileNameW(&mut dialog)
+        } else {
+            GetOpenFileNameW(&mut dialog)
+        }
+    };
+    if !chosen.as_bool() {
+        return None;
+    }
+    let len = file.iter().position(|&c| c == 0).unwrap_or(file.len());
+    Some(PathBuf::from(String::from_utf16_lossy(&file[..len])))
+}
@@ -0,0 +1,175 @@
+//! Instrumentation of worksheet function calls. Every wrapper generated by `#[xl_func]`
+//! opens a [`CallSpan`] around the call. With the `tracing` feature this is a `tracing`
+//! span named "udf", with the function name, a summary of the arguments, the duration in
+//! microseconds and whether the call succeeded, so a subscriber can write a trace file for
+//! finding slow or failing functions across a workbook. Without the feature only the call
+//! counts and latencies reported by `xl_stats` are kept.
+//!
+//! To record a trace, enable the feature and install a subscriber in xlAutoOpen, e.g. a
+//! `tracing-subscriber` fmt layer writing JSON to a file.
+
+use crate::caller::CellArea;
+use crate::guard::{self, Protected};
+use crate::last_error;
+use crate::stats;
+use crate::variant::Variant;
+use crate::xlcall::{xlerrValue, LPXLOPER12};
+use log::warn;
+
+use std::cell::Cell;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+/// Longest text shown for a single argument in the argument summary
+#[cfg(feature = "tracing")]
+const MAX_ARG_CHARS: usize = 40;
+
+/// A span around one call of a worksheet function. It records the outcome and duration
+/// when dropped.
+pub struct CallSpan {
+    function: &'static str,
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+    start: Instant,
+    failed: AtomicBool,
+    /// The calling cells, if they were looked up when the call started
+    caller: Option<Option<CellArea>>,
+}
+
+/// Keeps a [`CallSpan`] entered, so events and spans in the function nest inside it
+pub struct Entered<'a> {
+    #[cfg(feature = "tracing")]
+    _entered: tracing::span::Entered<'a>,
+    _span: PhantomData<&'a CallSpan>,
+}
+
+thread_local! {
+    /// How many sync worksheet functions are running on this thread
+    static ENTERED: Cell<u32> = const { Cell::new(0) };
+}
+
+/// Whether the current thread is running a worksheet function for Excel, and so may call
+/// back into Excel
+pub fn in_worksheet_function() -> bool {
+    ENTERED.with(|entered| entered.get() > 0)
+}
+
+impl CallSpan {
+    /// Opens a span for a call of `function` with the arguments Excel passed
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    pub fn new(function: &'static str, args: &[LPXLOPER12]) -> CallSpan {
+        #[cfg(feature = "tracing")]
+        {
+            use tracing::field::Empty;
+            let span = tracing::info_span!("udf", function, args = Empty, duration_us = Empty, outcome = Empty);
+            if !span.is_disabled() {
+                span.record("args", summarize(args).as_str());
+            }
+            CallSpan { function, span, start: Instant::now(), failed: AtomicBool::new(false), caller: None }
+        }
+        #[cfg(not(feature = "tracing"))]
+        CallSpan { function, start: Instant::now(), failed: AtomicBool::new(false), caller: None }
+    }
+
+    /// Opens a span for an async function. The calling cells are looked up straight away,
+    /// as the work finishes on another thread, where Excel cannot be asked.
+    pub fn new_async(function: &'static str, args: &[LPXLOPER12]) -> CallSpan {
+        let mut span = CallSpan::new(function, args);
+        span.caller = Some(CellArea::calling_cells());
+        span
+    }
+
+    /// Enters the span for the rest of the current scope
+    pub fn enter(&self) -> Entered<'_> {
+        ENTERED.with(|entered| entered.set(entered.get() + 1));
+        Entered {
+            #[cfg(feature = "tracing")]
+            _entered: self.span.enter(),
+            _span: PhantomData,
+        }
+    }
+
+    /// Marks the call as failed. The full details are kept for `xl_last_error`.
+    pub fn fail(&self, details: &str) {
+        self.failed.store(true, Ordering::Relaxed);
+        warn!("{} failed: {}", self.function, details);
+        #[cfg(feature = "tracing")]
+        self.span.in_scope(|| tracing::warn!(error = details, "function failed"));
+        let cell = self.caller.unwrap_or_else(CellArea::calling_cells);
+        last_error::record(self.function, cell, details.to_string());
+    }
+
+    /// Runs the body of a sync function. A panic or hardware fault in it marks the call as
+    /// failed and returns #VALUE! instead of taking Excel down.
+    pub fn protect(&self, body: impl FnOnce() -> LPXLOPER12) -> LPXLOPER12 {
+        match guard::protect(body) {
+            Ok(result) => result,
+            Err(fault) => {
+                self.fail(&format!("panicked: {}", fault));
+                LPXLOPER12::from(Variant::from_err(xlerrValue))
+            }
+        }
+    }
+
+    /// Runs the work of an async function inside the span, which stays open until the work
+    /// is done. An error is given as its message, which becomes the function's value, and
+    /// its full details, which mark the call as failed. A panic gives #VALUE!.
+    pub async fn run<F: Future<Output = Result<Variant, (String, String)>>>(self, future: F) -> Variant {
+        #[cfg(feature = "tracing")]
+        let future = tracing::Instrument::instrument(future, self.span.clone());
+        match Protected(future).await {
+            Ok(Ok(value)) => value,
+            Ok(Err((message, details))) => {
+                self.fail(&details);
+                Variant::from(message)
+            }
+            Err(fault) => {
+                self.fail(&format!("panicked: {}", fault));
+                Variant::from_err(xlerrValue)
+            }
+        }
+    }
+}
+
+impl Drop for Entered<'_> {
+    fn drop(&mut self) {
+        ENTERED.with(|entered| entered.set(entered.get() - 1));
+    }
+}
+
+impl Drop for CallSpan {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed();
+        let failed = self.failed.load(Ordering::Relaxed);
+        stats::record(self.function, elapsed, failed);
+        #[cfg(feature = "tracing")]
+        {
+            self.span.record("duration_us", elapsed.as_micros() as u64);
+            self.span.record("outcome", if failed { "error" } else { "ok" });
+        }
+    }
+}
+
+/// Describes the arguments briefly: scalars by value, arrays and ranges by size
+#[cfg(feature = "tracing")]
+fn summarize(args: &[LPXLOPER12]) -> String {
+    args.iter()
+        .map(|&arg| {
+            let value = Variant::from(arg);
+            match value.dim() {
+                (0, 0) => "missing".to_string(),
+                (1, 1) => {
+                    let text = value.to_string();
+                    match text.char_indices().nth(MAX_ARG_CHARS) {
+                        Some((end, _)) => format!("{}...", &text[..end]),
+                        None => text,
+                    }
+                }
+                (columns, rows) => format!("[{}x{}]", rows, columns),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -0,0 +1,175 @@
+//! Full details of the errors behind terse cell messages. When a worksheet function fails,
+//! the cell only shows the top-level message; the complete error, including its chain of
+//! sources, is kept here by calling cell and can be read back with `xl_last_error`.
+//!
+//! =xl_last_error(B4)   the last error raised by the formula in B4
+//! =xl_last_error()     the last error raised anywhere
+
+use crate::caller::CellArea;
+use crate::entrypoint::excel12;
+use crate::registrator::FunctionRegistration;
+use crate::variant::Variant;
+use crate::xlcall::{xlfCaller, LPXLOPER12};
+
+use std::collections::{BTreeMap, VecDeque};
+use std::error::Error;
+use std::fmt::Display;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// How many cells' errors are remembered before the oldest are forgotten
+const MAX_ERRORS: usize = 1000;
+
+/// A failed call of a worksheet function
+#[derive(Debug, Clone)]
+pub struct ErrorRecord {
+    pub function: &'static str,
+    /// The error and each of its sources, one per line
+    pub details: String,
+    pub time: SystemTime,
+}
+
+struct Errors {
+    by_cell: BTreeMap<CellArea, ErrorRecord>,
+    /// Cells in the order their errors were recorded, oldest first
+    order: VecDeque<CellArea>,
+    latest: Option<ErrorRecord>,
+}
+
+static ERRORS: Mutex<Errors> = Mutex::new(Errors { by_cell: BTreeMap::new(), order: VecDeque::new(), latest: None });
+
+/// Remembers an error raised by `function` when called from `cell`
+pub(crate) fn record(function: &'static str, cell: Option<CellArea>, details: String) {
+    let record = ErrorRecord { function, details, time: SystemTime::now() };
+    let mut errors = ERRORS.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(cell) = cell {
+        if errors.by_cell.insert(cell, record.clone()).is_some() {
+            errors.order.retain(|c| *c != cell);
+        }
+        errors.order.push_back(cell);
+        while errors.order.len() > MAX_ERRORS {
+            if let Some(oldest) = errors.order.pop_front() {
+                errors.by_cell.remove(&oldest);
+            }
+        }
+    }
+    errors.latest = Some(record);
+}
+
+/// The last error raised by the formula in the given cell
+pub(crate) fn for_cell(cell: &CellArea) -> Option<ErrorRecord> {
+    let errors = ERRORS.lock().unwrap_or_else(|e| e.into_inner());
+    errors.by_cell.iter().find(|(area, _)| area.contains(cell)).map(|(_, record)| record.clone())
+}
+
+/// The last error raised by any worksheet function
+pub fn latest() -> Option<ErrorRecord> {
+    ERRORS.lock().unwrap_or_else(|e| e.into_inner()).latest.clone()
+}
+
+/// Forgets all recorded errors
+pub fn clear() {
+    let mut errors = ERRORS.lock().unwrap_or_else(|e| e.into_inner());
+    errors.by_cell.clear();
+    errors.order.clear();
+    errors.latest = None;
+}
+
+/// Formats an error with its chain of sources, one per line
+pub fn error_chain(error: &(dyn Error + 'static)) -> String {
+    let mut details = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        details.push_str(&format!("\ncaused by: {}", cause));
+        source = cause.source();
+    }
+    details
+}
+
+/// Wraps the error of a worksheet function so the generated wrapper can describe it in
+/// as much detail as its type allows. Used through the [`describe_error!`](crate::describe_error) macro.
+pub struct ErrorDetails<'a, T: ?Sized>(pub &'a T);
+
+/// Errors implementing `std::error::Error`, described with their sources
+pub trait DescribeError {
+    fn describe(&self) -> String;
+}
+
+impl<T: Error + 'static> DescribeError for &&ErrorDetails<'_, T> {
+    fn describe(&self) -> String {
+        error_chain(self.0)
+    }
+}
+
+/// Boxed errors, described with their sources
+pub trait DescribeBoxedError {
+    fn describe(&self) -> String;
+}
+
+impl DescribeBoxedError for &ErrorDetails<'_, Box<dyn Error>> {
+    fn describe(&self) -> String {
+        error_chain(self.0.as_ref())
+    }
+}
+
+impl DescribeBoxedError for &ErrorDetails<'_, Box<dyn Error + Send + Sync>> {
+    fn describe(&self) -> String {
+        error_chain(self.0.as_ref())
+    }
+}
+
+/// Anything else that can be displayed, such as a String
+pub trait DescribeDisplay {
+    fn describe(&self) -> String;
+}
+
+impl<T: Display + ?Sized> DescribeDisplay for ErrorDetails<'_, T> {
+    fn describe(&self) -> String {
+        self.0.to_string()
+    }
+}
+
+/// Describes an error value of any displayable type, including its sources when it is a
+/// `std::error::Error` or a boxed one
+#[macro_export]
+macro_rules! describe_error {
+    ($error:expr) => {{
+        #[allow(unused_imports)]
+        use $crate::last_error::{DescribeBoxedError, DescribeDisplay, DescribeError};
+        (&&$crate::last_error::ErrorDetails(&$error)).describe()
+    }};
+}
+
+/// Returns the full details of the last error raised by the formula in the given cell, or
+/// by any formula if no cell is given
+#[unsafe(no_mangle)]
+pub extern "system" fn xl_last_error(cell: LPXLOPER12) -> LPXLOPER12 {
+    let cell = Variant::from(cell);
+    let record = if cell.is_missing_or_null() {
+        latest()
+    } else {
+        // A reference to the calling sheet comes without a sheet id, so take the caller's
+        let caller_sheet = CellArea::from_reference(&excel12(xlfCaller, &mut []), None).map(|area| area.sheet);
+        match CellArea::from_reference(&cell, caller_sheet) {
+            Some(area) => for_cell(&area),
+            None => return LPXLOPER12::from(Variant::from("#Not a cell reference")),
+        }
+    };
+    let result = match record {
+        Some(record) => Variant::from(format!("{}: {}", record.function, record.details)),
+        None => Variant::from("No error recorded"),
+    };
+    LPXLOPER12::from(result)
+}
+
+inventory::submit! {
+    FunctionRegistration {
+        xl_name: "xl_last_error",
+        arg_types: "QU",
+        arg_names: "cell",
+        category: "Add-in Diagnostics",
+        description: "Shows the full details of the last error raised by a cell's formula",
+        arg_infos: &[],
+        help_topic: "",
+    }
+}
@@ -1,6 +1,64 @@
+pub mod about;
+#[cfg(feature = "async")]
+pub mod async_udf;
+pub mod background;
+pub mod cache;
+mod caller;
+#[cfg(feature = "com")]
+pub mod com;
+#[cfg(feature = "com-server")]
+pub mod com_server;
+pub mod commands;
+pub mod config;
+pub mod console;
+pub mod debounce;
+pub mod diagnostics;
+pub mod dialog;
 pub mod entrypoint;
+#[cfg(feature = "events")]
+pub mod events;
+pub mod function_wizard;
+#[cfg(any(fuzzing, feature = "testing"))]
+pub mod fuzzing;
+pub mod groups;
+pub mod guard;
+pub mod handles;
+#[cfg(feature = "hot-reload")]
+pub mod hot_reload;
+pub mod input;
+pub mod instrument;
+pub mod last_error;
+pub mod locale;
+pub mod logging;
+pub mod manifest;
+#[cfg(feature = "memory-tracking")]
+pub mod memory;
+pub mod menu;
+pub mod minidump;
+#[cfg(feature = "testing")]
+pub mod mock_excel;
+pub mod namespace;
+pub mod paging;
+#[cfg(feature = "rayon")]
+pub mod pool;
+pub mod progress;
+pub mod registration_report;
 pub mod registrator;
+#[cfg(feature = "com")]
+pub mod resize;
+#[cfg(feature = "ribbon")]
+pub mod ribbon;
+#[cfg(feature = "rtd")]
+pub mod rtd;
+pub mod scheduler;
+pub mod stats;
+#[cfg(feature = "testing")]
+pub mod testing;
 pub mod variant;
+pub mod volatile;
+pub mod watchdog;
+pub mod welcome;
+pub mod workbook_state;
 pub mod xlauto;
 pub mod xlcall;
 
@@ -0,0 +1,137 @@
+//! Function Wizard text in the language of the user's Excel. A [`Translation`] gives the
+//! category, description and argument help of one function in one language; when the
+//! add-in registers its functions, the translation for Excel's language is used in place
+//! of the text from the doc comments, which is kept for languages without one. A
+//! translation can also give the function a name in that language, which is registered
+//! as well as the usual one, so both `xl_npv` and `xl_van` work in French Excel.
+//!
+//! Translations are collected like the functions themselves:
+//!
+//! xladd_core::registrator::inventory::submit! {
+//!     xladd_core::locale::Translation {
+//!         xl_name: "xl_npv",
+//!         language: "fr",
+//!         name: "xl_van",
+//!         category: "Finance",
+//!         description: "Valeur actuelle nette d'une série de flux",
+//!         arg_descriptions: &["Taux d'actualisation", "Flux de trésorerie"],
+//!     }
+//! }
+//!
+//! Excel's language is found from the country code of its version. It can be overridden
+//! with `language` under `[functions]` in the [settings](crate::config).
+
+use crate::config;
+use crate::entrypoint::excel12;
+use crate::variant::Variant;
+use crate::xlcall::xlfGetWorkspace;
+use log::debug;
+
+/// Text for one function in one language. Empty fields keep the text from the code.
+pub struct Translation {
+    /// The exported name of the function
+    pub xl_name: &'static str,
+    /// Two-letter ISO 639-1 code, such as "fr" or "de"
+    pub language: &'static str,
+    /// A name in this language to register the function under too, or empty for none
+    pub name: &'static str,
+    pub category: &'static str,
+    pub description: &'static str,
+    /// Help for each argument, in order
+    pub arg_descriptions: &'static [&'static str],
+}
+
+inventory::collect!(Translation);
+
+const GET_WORKSPACE_COUNTRY: i32 = 37;
+
+/// Languages of the country codes Excel reports for its versions, which are mostly
+/// international dialling codes
+const COUNTRY_LANGUAGES: &[(i32, &str)] = &[
+    (1, "en"),
+    (2, "fr"), // Canadian French
+    (3, "es"), // Latin America
+    (7, "ru"),
+    (30, "el"),
+    (31, "nl"),
+    (32, "nl"), // Belgium
+    (33, "fr"),
+    (34, "es"),
+    (36, "hu"),
+    (39, "it"),
+    (41, "de"), // Switzerland
+    (42, "cs"),
+    (43, "de"), // Austria
+    (44, "en"),
+    (45, "da"),
+    (46, "sv"),
+    (47, "nb"),
+    (48, "pl"),
+    (49, "de"),
+    (55, "pt"),
+    (61, "en"),
+    (64, "en"),
+    (66, "th"),
+    (81, "ja"),
+    (82, "ko"),
+    (84, "vi"),
+    (86, "zh"),
+    (90, "tr"),
+    (351, "pt"),
+    (354, "is"),
+    (358, "fi"),
+    (886, "zh"),
+    (966, "ar"),
+    (972, "he"),
+];
+
+/// The language of the user's Excel as an ISO 639-1 code, or the one in the settings if
+/// there is one. This can only be called from xlAutoOpen or a command.
+pub fn language() -> String {
+    if let Some(language) = config::current().functions.language.clone() {
+        return language;
+    }
+    let workspace = excel12(xlfGetWorkspace, &mut [Variant::from(GET_WORKSPACE_COUNTRY)]);
+    let country = f64::try_from(&workspace.at(0, 0)).map_or(1, |country| country as i32);
+    let language = COUNTRY_LANGUAGES.iter().find(|(code, _)| *code == country).map_or("en", |(_, language)| language);
+    debug!("Excel country code {}, language {}", country, language);
+    language.to_string()
+}
+
+/// The translation of a function into a language, if there is one
+pub fn translation(xl_name: &str, language: &str) -> Option<&'static Translation> {
+    inventory::iter::<Translation>
+        .into_iter()
+        .find(|translation| translation.xl_name == xl_name && translation.language.eq_ignore_ascii_case(language))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    inventory::submit! {
+        Translation {
+            xl_name: "xl_test_npv",
+            language: "fr",
+            name: "xl_test_van",
+            category: "Finance",
+            description: "Valeur actuelle nette",
+            arg_descriptions: &["Taux", "Flux"],
+        }
+    }
+
+    #[test]
+    fn finds_translations_by_function_and_language() {
+        let french = translation("xl_test_npv", "FR").expect("a French translation");
+        assert_eq!(french.name, "xl_test_van");
+        assert!(translation("xl_test_npv", "de").is_none());
+        assert!(translation("xl_test_irr", "fr").is_none());
+    }
+
+    #[test]
+    fn countries_have_one_language() {
+        for (index, (country, _)) in COUNTRY_LANGUAGES.iter().enumerate() {
+            assert!(COUNTRY_LANGUAGES[index + 1..].iter().all(|(other, _)| other != country), "{} twice", country);
+        }
+    }
+}
@@ -0,0 +1,218 @@
+//! Backend for the `log` facade. Messages from the add-in and from xladd-core go to a
+//! rolling log file, by default next to the xll with a .log extension, and to
+//! OutputDebugString so they show up in a debugger or DebugView, as well as to the log
+//! console opened by the `xl_toggle_console` command. Call [`init`] at the
+//! start of xlAutoOpen; the level and file can be changed at any time afterwards.
+//!
+//! # Example
+//!
+//! logging::init(LogConfig { level: LevelFilter::Debug, ..LogConfig::default() });
+//! log::info!("pricing library loaded");
+
+use crate::console;
+use crate::entrypoint::excel12;
+use crate::xlcall::xlGetName;
+
+use log::{LevelFilter, Log, Metadata, Record};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use widestring::U16CString;
+use windows::Win32::System::Diagnostics::Debug::OutputDebugStringW;
+use windows::Win32::System::SystemInformation::GetLocalTime;
+use windows::core::PCWSTR;
+
+/// How the log is written
+#[derive(Debug, Clone)]
+pub struct LogConfig {
+    /// The most detailed level that is written
+    pub level: LevelFilter,
+    /// The log file, or None for a file next to the xll with a .log extension
+    pub path: Option<PathBuf>,
+    /// Whether to write to the log file at all
+    pub file: bool,
+    /// Whether to send messages to OutputDebugString
+    pub debugger: bool,
+    /// Size at which the file is rolled over to a numbered backup
+    pub max_file_bytes: u64,
+    /// How many numbered backups to keep
+    pub backups: usize,
+}
+
+impl Default for LogConfig {
+    fn default() -> LogConfig {
+        LogConfig {
+            level: LevelFilter::Info,
+            path: None,
+            file: true,
+            debugger: true,
+            max_file_bytes: 10 * 1024 * 1024,
+            backups: 3,
+        }
+    }
+}
+
+struct Sinks {
+    config: LogConfig,
+    path: Option<PathBuf>,
+    file: Option<File>,
+    written: u64,
+}
+
+static SINKS: Mutex<Option<Sinks>> = Mutex::new(None);
+static LOGGER: Logger = Logger;
+
+struct Logger;
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let time = unsafe { GetLocalTime() };
+        let line = format!(
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:03} {:5} [{}] {}\r\n",
+            time.wYear, time.wMonth, time.wDay, time.wHour, time.wMinute, time.wSecond,
+            time.wMilliseconds, record.level(), record.target(), record.args()
+        );
+
+        {
+            let mut sinks = SINKS.lock().unwrap_or_else(|e| e.into_inner());
+            let Some(sinks) = sinks.as_mut() else { return };
+            if sinks.config.debugger {
+                let text = U16CString::from_str_truncate(&line);
+                unsafe { OutputDebugStringW(PCWSTR(text.as_ptr())) };
+            }
+            sinks.write(line.as_bytes());
+        }
+        console::append(&line);
+    }
+
+    fn flush(&self) {
+        if let Some(file) = SINKS.lock().unwrap_or_else(|e| e.into_inner()).as_mut().and_then(|s| s.file.as_mut()) {
+            let _ = file.flush();
+        }
+    }
+}
+
+impl Sinks {
+    fn open(config: LogConfig) -> Sinks {
+        let path = if config.file { config.path.clone().or_else(default_path) } else { None };
+        let mut sinks = Sinks { config, path, file: None, written: 0 };
+        sinks.reopen();
+        sinks
+    }
+
+    fn reopen(&mut self) {
+        self.file = None;
+        self.written = 0;
+        let Some(path) = self.path.as_ref() else { return };
+        match OpenOptions::new().create(true).append(true).open(path) {
+            Ok(file) => {
+                self.written = file.metadata().map(|m| m.len()).unwrap_or(0);
+                self.file = Some(file);
+            }
+            Err(e) => {
+                let text = U16CString::from_str_truncate(format!("cannot open log file {}: {}\r\n", path.display(), e));
+                unsafe { OutputDebugStringW(PCWSTR(text.as_ptr())) };
+            }
+        }
+    }
+
+    fn write(&mut self, line: &[u8]) {
+        if self.file.is_none() {
+            return;
+        }
+        if self.written + line.len() as u64 > self.config.max_file_bytes {
+            self.roll();
+        }
+        if let Some(file) = self.file.as_mut()
+            && file.write_all(line).is_ok()
+        {
+            self.written += line.len() as u64;
+        }
+    }
+
+    /// Renames log to log.1, log.1 to log.2 and so on, dropping the oldest, and starts a new file
+    fn roll(&mut self) {
+        let Some(path) = self.path.clone() else { return };
+        self.file = None;
+        if self.config.backups == 0 {
+            let _ = std::fs::remove_file(&path);
+        } else {
+            let _ = std::fs::remove_file(backup_path(&path, self.config.backups));
+            for n in (1..self.config.backups).rev() {
+                let _ = std::fs::rename(backup_path(&path, n), backup_path(&path, n + 1));
+            }
+            let _ = std::fs::rename(&path, backup_path(&path, 1));
+        }
+        self.reopen();
+    }
+}
+
+fn backup_path(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+/// The xll's own path with a .log extension
+fn default_path() -> Option<PathBuf> {
+    let dll_name = String::from(&excel12(xlGetName, &mut []));
+    if dll_name.is_empty() {
+        None
+    } else {
+        Some(Path::new(&dll_name).with_extension("log"))
+    }
+}
+
+/// Installs the logger as the backend of the `log` facade. If the add-in has already
+/// installed a different logger, that one is kept and this returns false.
+pub fn init(config: LogConfig) -> bool {
+    let level = config.level;
+    // Opened before taking the lock, as finding the xll's path calls into Excel, which logs
+    let sinks = Sinks::open(config);
+    *SINKS.lock().unwrap_or_else(|e| e.into_inner()) = Some(sinks);
+    match log::set_logger(&LOGGER) {
+        Ok(()) => {
+            log::set_max_level(level);
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// Changes the most detailed level that is written
+pub fn set_level(level: LevelFilter) {
+    if let Some(sinks) = SINKS.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+        sinks.config.level = level;
+    }
+    log::set_max_level(level);
+}
+
+/// Writes to a different log file from now on. None goes back to the file next to the xll.
+pub fn set_path(path: Option<PathBuf>) {
+    let resolved = path.clone().or_else(default_path);
+    if let Some(sinks) = SINKS.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+        sinks.config.path = path;
+        sinks.path = if sinks.config.file { resolved } else { None };
+        sinks.reopen();
+    }
+}
+
+/// Turns the OutputDebugString sink on or off
+pub fn set_debugger(enabled: bool) {
+    if let Some(sinks) = SINKS.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+        sinks.config.debugger = enabled;
+    }
+}
+
+/// The log file currently written to, if any
+pub fn log_path() -> Option<PathBuf> {
+    SINKS.lock().unwrap_or_else(|e| e.into_inner()).as_ref().and_then(|sinks| sinks.path.clone())
+}
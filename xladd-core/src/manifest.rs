@@ -0,0 +1,294 @@
+//! The registration table as JSON, and overrides of it read at startup. [`export`], or the
+//! `xl_export_manifest` command, writes every function with the name, category and text it
+//! is registered with. A deployment can then change the text of its functions without a
+//! rebuild, by putting `functions.json` next to the xll (or naming another file with
+//! `overrides` under `[functions]` in the [settings](crate::config)):
+//!
+//! {
+//!     "xl_npv": { "category": "Acme | Valuation", "description": "Net present value at the desk rate" },
+//!     "xl_beta_curve": { "disabled": true }
+//! }
+//!
+//! Every field of an override is optional. Argument descriptions are only used if there is
+//! one for each argument. An exported table, which is keyed the same way, can be edited
+//! and used as the override file; its other fields are ignored.
+//!
+//! [`snapshot`] is the table as built, before any settings, overrides or translations, so
+//! it does not need Excel. A test can keep it in a golden file with [`check_snapshot`],
+//! so that a renamed argument or changed type string, which would break workbooks already
+//! using the function, shows up in review:
+//!
+//! #[test]
+//! fn registrations_are_unchanged() {
+//!     manifest::check_snapshot(Path::new("tests/registrations.json")).unwrap();
+//! }
+//!
+//! The file is written if it does not exist, or if `XLADD_UPDATE_SNAPSHOTS` is set, to
+//! accept a change on purpose.
+
+use crate::commands;
+use crate::config::{self, Config};
+use crate::groups;
+use crate::input;
+use crate::locale;
+use crate::registrator::{self, CommandRegistration, FunctionRegistration};
+use log::{info, warn};
+use serde_derive::{Deserialize, Serialize};
+use thiserror::Error;
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// The name of the override file looked for next to the xll
+pub const OVERRIDES_FILE_NAME: &str = "functions.json";
+
+/// Set to write the snapshot file rather than compare against it
+pub const UPDATE_SNAPSHOTS_VAR: &str = "XLADD_UPDATE_SNAPSHOTS";
+
+#[derive(Debug, Error)]
+pub enum ManifestError {
+    #[error("Cannot read {0}: {1}")]
+    Read(PathBuf, std::io::Error),
+    #[error("Cannot write {0}: {1}")]
+    Write(PathBuf, std::io::Error),
+    #[error("Invalid JSON in {0}: {1}")]
+    Json(PathBuf, serde_json::Error),
+    #[error("The registrations no longer match {0}; set XLADD_UPDATE_SNAPSHOTS to accept the change:\n{1}")]
+    SnapshotChanged(PathBuf, String),
+}
+
+/// One function of the registration table
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// The exported name
+    pub xl_name: String,
+    /// The name in Excel
+    pub name: String,
+    pub arg_types: String,
+    pub arg_names: String,
+    pub category: String,
+    pub description: String,
+    pub arg_descriptions: Vec<String>,
+    pub help_topic: String,
+    pub group: Option<String>,
+    pub disabled: bool,
+    pub registered: bool,
+}
+
+/// Changes to the registration of one function
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Override {
+    pub category: Option<String>,
+    pub description: Option<String>,
+    pub arg_descriptions: Option<Vec<String>>,
+    pub help_topic: Option<String>,
+    pub disabled: bool,
+}
+
+/// One function as it is built, for snapshot tests
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotEntry {
+    pub arg_types: String,
+    pub arg_names: String,
+    pub category: String,
+    pub description: String,
+    pub help_topic: String,
+    pub arguments: Vec<SnapshotArgument>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotArgument {
+    pub name: String,
+    pub description: String,
+}
+
+static OVERRIDES: Mutex<Option<HashMap<String, Override>>> = Mutex::new(None);
+
+/// The registration table, as the functions would be registered now. This can only be
+/// called from xlAutoOpen or a command, as it asks Excel for its language.
+pub fn manifest() -> Vec<ManifestEntry> {
+    let config = config::current();
+    let language = locale::language();
+    inventory::iter::<FunctionRegistration>
+        .into_iter()
+        .map(|registration| entry(&config, &language, registration))
+        .collect()
+}
+
+fn entry(config: &Config, language: &str, registration: &FunctionRegistration) -> ManifestEntry {
+    let overridden = override_for(registration.xl_name).unwrap_or_default();
+    let translation = locale::translation(registration.xl_name, language);
+    let category = overridden.category.as_deref().unwrap_or(match translation {
+        Some(translation) if !translation.category.is_empty() => translation.category,
+        _ => registration.category,
+    });
+    let description = overridden.description.unwrap_or_else(|| match translation {
+        Some(translation) if !translation.description.is_empty() => translation.description.to_string(),
+        _ => registration.description.to_string(),
+    });
+    let arg_descriptions = match overridden.arg_descriptions {
+        Some(arg_descriptions) if arg_descriptions.len() == registration.arg_infos.len() => arg_descriptions,
+        _ => registration.arg_infos.iter().map(|arg_info| arg_info.description.to_string()).collect(),
+    };
+    let help_topic = overridden.help_topic.unwrap_or_else(|| registration.help_topic.to_string());
+    let group = groups::group(registration.xl_name);
+    let names = registrator::registered_names(registration.xl_name);
+    ManifestEntry {
+        xl_name: registration.xl_name.to_string(),
+        name: names.first().cloned().unwrap_or_else(|| config.excel_name(registration.xl_name)),
+        arg_types: registration.arg_types.to_string(),
+        arg_names: registration.arg_names.to_string(),
+        category: config.category(category),
+        description,
+        arg_descriptions,
+        help_topic,
+        group: group.map(str::to_string),
+        disabled: overridden.disabled || config.is_disabled(registration.xl_name, registration.category, group),
+        registered: !names.is_empty(),
+    }
+}
+
+/// Writes the registration table to a file as JSON, keyed by exported name
+pub fn export(path: &Path) -> Result<(), ManifestError> {
+    let table: BTreeMap<String, ManifestEntry> =
+        manifest().into_iter().map(|entry| (entry.xl_name.clone(), entry)).collect();
+    let json = serde_json::to_string_pretty(&table).map_err(|e| ManifestError::Json(path.to_path_buf(), e))?;
+    std::fs::write(path, json).map_err(|e| ManifestError::Write(path.to_path_buf(), e))
+}
+
+/// Every function as it is built, keyed by exported name, as pretty-printed JSON. The
+/// order is fixed, so the text only changes when a function does.
+pub fn snapshot() -> String {
+    let mut json = serde_json::to_string_pretty(&snapshot_table()).unwrap_or_default();
+    json.push('\n');
+    json
+}
+
+fn snapshot_table() -> BTreeMap<String, SnapshotEntry> {
+    inventory::iter::<FunctionRegistration>
+        .into_iter()
+        .map(|registration| {
+            let entry = SnapshotEntry {
+                arg_types: registration.arg_types.to_string(),
+                arg_names: registration.arg_names.to_string(),
+                category: registration.category.to_string(),
+                description: registration.description.to_string(),
+                help_topic: registration.help_topic.to_string(),
+                arguments: registration
+                    .arg_infos
+                    .iter()
+                    .map(|arg_info| SnapshotArgument {
+                        name: arg_info.name.to_string(),
+                        description: arg_info.description.to_string(),
+                    })
+                    .collect(),
+            };
+            (registration.xl_name.to_string(), entry)
+        })
+        .collect()
+}
+
+/// Compares the [`snapshot`] with a golden file, failing with a list of the functions
+/// added, removed or changed. The file is written instead if it does not exist, or if
+/// `XLADD_UPDATE_SNAPSHOTS` is set.
+pub fn check_snapshot(path: &Path) -> Result<(), ManifestError> {
+    if !path.exists() || std::env::var_os(UPDATE_SNAPSHOTS_VAR).is_some() {
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent).map_err(|e| ManifestError::Write(path.to_path_buf(), e))?;
+        }
+        return std::fs::write(path, snapshot()).map_err(|e| ManifestError::Write(path.to_path_buf(), e));
+    }
+    let text = std::fs::read_to_string(path).map_err(|e| ManifestError::Read(path.to_path_buf(), e))?;
+    let golden: BTreeMap<String, SnapshotEntry> =
+        serde_json::from_str(&text).map_err(|e| ManifestError::Json(path.to_path_buf(), e))?;
+    let current = snapshot_table();
+    let mut changes = Vec::new();
+    for (xl_name, entry) in &current {
+        match golden.get(xl_name) {
+            None => changes.push(format!("added {}", xl_name)),
+            Some(was) if was != entry => changes.push(format!("changed {}: {}", xl_name, changed_fields(was, entry).join(", "))),
+            Some(_) => {}
+        }
+    }
+    changes.extend(golden.keys().filter(|xl_name| !current.contains_key(*xl_name)).map(|xl_name| format!("removed {}", xl_name)));
+    if changes.is_empty() {
+        Ok(())
+    } else {
+        Err(ManifestError::SnapshotChanged(path.to_path_buf(), changes.join("\n")))
+    }
+}
+
+/// The fields that differ between two versions of a function
+fn changed_fields(was: &SnapshotEntry, now: &SnapshotEntry) -> Vec<&'static str> {
+    [
+        ("arg_types", was.arg_types != now.arg_types),
+        ("arg_names", was.arg_names != now.arg_names),
+        ("category", was.category != now.category),
+        ("description", was.description != now.description),
+        ("help_topic", was.help_topic != now.help_topic),
+        ("arguments", was.arguments != now.arguments),
+    ]
+    .into_iter()
+    .filter_map(|(field, changed)| changed.then_some(field))
+    .collect()
+}
+
+/// Reads overrides from a file, replacing any read before
+pub fn load_overrides(path: &Path) -> Result<usize, ManifestError> {
+    let text = std::fs::read_to_string(path).map_err(|e| ManifestError::Read(path.to_path_buf(), e))?;
+    let overrides: HashMap<String, Override> =
+        serde_json::from_str(&text).map_err(|e| ManifestError::Json(path.to_path_buf(), e))?;
+    let count = overrides.len();
+    *OVERRIDES.lock().unwrap_or_else(|e| e.into_inner()) = Some(overrides);
+    Ok(count)
+}
+
+/// Reads the override file next to the xll, or the one in the settings, if there is one.
+/// This is called from [`config::load`](crate::config::load).
+pub(crate) fn load_default_overrides(config: &Config, dll_name: &str) {
+    OVERRIDES.lock().unwrap_or_else(|e| e.into_inner()).take();
+    let path = match &config.functions.overrides {
+        Some(path) => path.clone(),
+        None if dll_name.is_empty() => return,
+        None => Path::new(dll_name).with_file_name(OVERRIDES_FILE_NAME),
+    };
+    if config.functions.overrides.is_none() && !path.exists() {
+        return;
+    }
+    match load_overrides(&path) {
+        Ok(count) => info!("{} function overrides read from {}", count, path.display()),
+        Err(e) => warn!("{}; functions are registered as built", e),
+    }
+}
+
+/// The override of a function, if there is one
+pub fn override_for(xl_name: &str) -> Option<Override> {
+    OVERRIDES.lock().unwrap_or_else(|e| e.into_inner()).as_ref()?.get(xl_name).cloned()
+}
+
+/// Whether the override file disables a function
+pub fn is_disabled(xl_name: &str) -> bool {
+    override_for(xl_name).is_some_and(|overridden| overridden.disabled)
+}
+
+/// Command that asks where to save the registration table, and saves it there
+#[unsafe(no_mangle)]
+pub extern "system" fn xl_export_manifest() -> i32 {
+    let Some(path) = input::save_file("Export functions", "functions.json", &[("JSON files", "*.json")]) else {
+        return 1;
+    };
+    match export(&path) {
+        Ok(()) => info!("registration table written to {}", path.display()),
+        Err(e) => commands::alert(&e.to_string()),
+    }
+    1
+}
+
+inventory::submit! {
+    CommandRegistration {
+        xl_name: "xl_export_manifest",
+        shortcut: "",
+    }
+}
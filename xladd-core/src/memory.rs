@@ -0,0 +1,133 @@
+//! Counts of the strings and arrays Variants allocate and free, to find leaks in long
+//! sessions. A Variant owns its string or array only while it carries xlbitDLLFree, and a
+//! value returned to Excel is only given back through xlAutoFree12 if it does, so a path
+//! that misses either leaks quietly. With the `memory-tracking` feature each allocation
+//! and free is counted, and `=xl_memory_report()` shows what is still live. A live count
+//! that keeps growing while the sheet recalculates points at the leak.
+//!
+//! The counts cost an atomic add per allocation, so leave the feature off in release
+//! builds.
+
+use crate::registrator::FunctionRegistration;
+use crate::variant::Variant;
+use crate::xlcall::LPXLOPER12;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static STRINGS_ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static STRINGS_FREED: AtomicUsize = AtomicUsize::new(0);
+static STRING_BYTES: AtomicUsize = AtomicUsize::new(0);
+static ARRAYS_ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static ARRAYS_FREED: AtomicUsize = AtomicUsize::new(0);
+static ARRAY_BYTES: AtomicUsize = AtomicUsize::new(0);
+static RETURNED: AtomicUsize = AtomicUsize::new(0);
+static FREED_BY_EXCEL: AtomicUsize = AtomicUsize::new(0);
+
+/// The counts since the add-in was loaded
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MemoryStats {
+    pub strings_allocated: usize,
+    pub strings_freed: usize,
+    /// Bytes held by live strings
+    pub string_bytes: usize,
+    pub arrays_allocated: usize,
+    pub arrays_freed: usize,
+    /// Bytes held by live arrays, not counting the strings in them
+    pub array_bytes: usize,
+    /// Values handed to Excel as a function result
+    pub returned: usize,
+    /// Values Excel handed back through xlAutoFree12
+    pub freed_by_excel: usize,
+}
+
+impl MemoryStats {
+    pub fn live_strings(&self) -> usize {
+        self.strings_allocated.saturating_sub(self.strings_freed)
+    }
+
+    pub fn live_arrays(&self) -> usize {
+        self.arrays_allocated.saturating_sub(self.arrays_freed)
+    }
+
+    /// Results Excel has not handed back. Results are only handed back if they carry
+    /// xlbitDLLFree, so this grows with every other result too.
+    pub fn unreturned(&self) -> usize {
+        self.returned.saturating_sub(self.freed_by_excel)
+    }
+}
+
+/// The counts now
+pub fn stats() -> MemoryStats {
+    MemoryStats {
+        strings_allocated: STRINGS_ALLOCATED.load(Ordering::Relaxed),
+        strings_freed: STRINGS_FREED.load(Ordering::Relaxed),
+        string_bytes: STRING_BYTES.load(Ordering::Relaxed),
+        arrays_allocated: ARRAYS_ALLOCATED.load(Ordering::Relaxed),
+        arrays_freed: ARRAYS_FREED.load(Ordering::Relaxed),
+        array_bytes: ARRAY_BYTES.load(Ordering::Relaxed),
+        returned: RETURNED.load(Ordering::Relaxed),
+        freed_by_excel: FREED_BY_EXCEL.load(Ordering::Relaxed),
+    }
+}
+
+/// A string of this many UTF-16 units, counting the length at its start, was allocated
+pub(crate) fn string_allocated(units: usize) {
+    STRINGS_ALLOCATED.fetch_add(1, Ordering::Relaxed);
+    STRING_BYTES.fetch_add(units * 2, Ordering::Relaxed);
+}
+
+pub(crate) fn string_freed(units: usize) {
+    STRINGS_FREED.fetch_add(1, Ordering::Relaxed);
+    STRING_BYTES.fetch_sub(units * 2, Ordering::Relaxed);
+}
+
+/// An array of this many Variants was allocated
+pub(crate) fn array_allocated(len: usize) {
+    ARRAYS_ALLOCATED.fetch_add(1, Ordering::Relaxed);
+    ARRAY_BYTES.fetch_add(len * size_of::<Variant>(), Ordering::Relaxed);
+}
+
+pub(crate) fn array_freed(len: usize) {
+    ARRAYS_FREED.fetch_add(1, Ordering::Relaxed);
+    ARRAY_BYTES.fetch_sub(len * size_of::<Variant>(), Ordering::Relaxed);
+}
+
+pub(crate) fn returned() {
+    RETURNED.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn freed_by_excel() {
+    FREED_BY_EXCEL.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Returns a table of the Variant strings and arrays allocated, freed and still live, and
+/// of the results handed to Excel and handed back
+#[unsafe(no_mangle)]
+pub extern "system" fn xl_memory_report() -> LPXLOPER12 {
+    let stats = stats();
+    let rows = vec![
+        ("Strings allocated".to_string(), stats.strings_allocated as f64),
+        ("Strings freed".to_string(), stats.strings_freed as f64),
+        ("Live strings".to_string(), stats.live_strings() as f64),
+        ("Live string bytes".to_string(), stats.string_bytes as f64),
+        ("Arrays allocated".to_string(), stats.arrays_allocated as f64),
+        ("Arrays freed".to_string(), stats.arrays_freed as f64),
+        ("Live arrays".to_string(), stats.live_arrays() as f64),
+        ("Live array bytes".to_string(), stats.array_bytes as f64),
+        ("Results returned".to_string(), stats.returned as f64),
+        ("Results freed by Excel".to_string(), stats.freed_by_excel as f64),
+    ];
+    LPXLOPER12::from(Variant::from(rows))
+}
+
+inventory::submit! {
+    FunctionRegistration {
+        xl_name: "xl_memory_report",
+        arg_types: "Q!",
+        arg_names: "",
+        category: "Add-in Diagnostics",
+        description: "Counts the strings and arrays allocated and freed by this add-in, to find leaks",
+        arg_infos: &[],
+        help_topic: "",
+    }
+}
@@ -0,0 +1,123 @@
+//! Custom menus built with the legacy xlfAddMenu/xlfAddCommand functions. In Excel 2007
+//! and later these appear under the Add-ins tab of the ribbon, which gives a way of
+//! exposing commands to users without any COM code.
+//!
+//! # Example
+//!
+//! Menu::new("My Add-in")
+//!     .item("Re-price all", "xl_reprice_all")
+//!     .separator()
+//!     .item_with_status("About", "xl_about", "Shows the add-in version")
+//!     .install();
+
+use crate::entrypoint::excel12;
+use crate::variant::Variant;
+use crate::xlcall::{xlfAddCommand, xlfAddMenu, xlfDeleteMenu};
+use log::debug;
+
+use std::sync::Mutex;
+
+/// The worksheet menu bar, which is where Excel 2007+ picks up legacy menus
+const WORKSHEET_MENU_BAR: i32 = 10;
+
+/// Menus installed by this add-in, so they can be removed when the add-in closes
+static INSTALLED_MENUS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+struct MenuItem {
+    text: String,
+    command: String,
+    status: String,
+}
+
+/// Builder for a custom menu whose items run registered commands
+pub struct Menu {
+    name: String,
+    items: Vec<MenuItem>,
+}
+
+impl Menu {
+    /// Starts a menu with the given name. Use `&` before a letter to mark the access key.
+    pub fn new(name: &str) -> Menu {
+        Menu { name: name.to_string(), items: Vec::new() }
+    }
+
+    /// Adds an item that runs the named command, e.g. one registered with `#[xl_command]`
+    pub fn item(self, text: &str, command: &str) -> Menu {
+        self.item_with_status(text, command, "")
+    }
+
+    /// Adds an item with a message that is shown in the status bar when it is selected
+    pub fn item_with_status(mut self, text: &str, command: &str, status: &str) -> Menu {
+        self.items.push(MenuItem {
+            text: text.to_string(),
+            command: command.to_string(),
+            status: status.to_string(),
+        });
+        self
+    }
+
+    /// Adds a separator line
+    pub fn separator(mut self) -> Menu {
+        self.items.push(MenuItem { text: "-".to_string(), command: String::new(), status: String::new() });
+        self
+    }
+
+    /// Adds the menu to the worksheet menu bar, replacing any menu of the same name left
+    /// behind by a previous load of the add-in. Must be called from a command context,
+    /// such as xlAutoOpen. Returns false if Excel rejected the menu.
+    pub fn install(self) -> bool {
+        remove_menu(&self.name);
+
+        // The menu description is a table: the first row holds the menu name and the
+        // following rows hold item text, macro, shortcut (unused on Windows) and status text
+        let mut rows = vec![vec![
+            Variant::from(self.name.as_str()),
+            Variant::from(""),
+            Variant::from(""),
+            Variant::from(""),
+        ]];
+        for item in self.items.iter() {
+            rows.push(vec![
+                Variant::from(item.text.as_str()),
+                Variant::from(item.command.as_str()),
+                Variant::from(""),
+                Variant::from(item.status.as_str()),
+            ]);
+        }
+
+        let result = excel12(xlfAddMenu, &mut [Variant::from(WORKSHEET_MENU_BAR), Variant::from(rows)]);
+        debug!("AddMenu({}): result = {}", self.name, result);
+        // Excel returns the position of the new menu, or an error
+        let installed = f64::try_from(&result).is_ok();
+        if installed {
+            INSTALLED_MENUS.lock().unwrap_or_else(|e| e.into_inner()).push(self.name);
+        }
+        installed
+    }
+}
+
+/// Adds a single item to an existing menu, for example one of Excel's own menus or one
+/// installed with `Menu::install`. Returns false if Excel rejected the item.
+pub fn add_menu_item(menu: &str, text: &str, command: &str) -> bool {
+    let item = vec![vec![Variant::from(text), Variant::from(command)]];
+    let result = excel12(
+        xlfAddCommand,
+        &mut [Variant::from(WORKSHEET_MENU_BAR), Variant::from(menu), Variant::from(item)],
+    );
+    debug!("AddCommand({}, {}): result = {}", menu, text, result);
+    f64::try_from(&result).is_ok()
+}
+
+/// Removes a menu from the worksheet menu bar
+pub fn remove_menu(name: &str) {
+    excel12(xlfDeleteMenu, &mut [Variant::from(WORKSHEET_MENU_BAR), Variant::from(name)]);
+    INSTALLED_MENUS.lock().unwrap_or_else(|e| e.into_inner()).retain(|menu| menu != name);
+}
+
+/// Removes every menu installed by this add-in. This is called from xlAutoClose.
+pub fn remove_all_menus() {
+    let menus = std::mem::take(&mut *INSTALLED_MENUS.lock().unwrap_or_else(|e| e.into_inner()));
+    for menu in menus.iter() {
+        excel12(xlfDeleteMenu, &mut [Variant::from(WORKSHEET_MENU_BAR), Variant::from(menu)]);
+    }
+}
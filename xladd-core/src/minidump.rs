@@ -0,0 +1,162 @@
+//! Minidumps of faults caught by the [guard](crate::guard). When a worksheet function
+//! raises a hardware exception, a dump of the process is written before the fault is
+//! turned into `#VALUE!`, so a crash on a user's machine can be opened in a debugger
+//! afterwards. Dumps go next to the log file by default, named after it, and only the
+//! most recent few are kept.
+//!
+//! # Example
+//!
+//! minidump::configure(DumpConfig { size: DumpSize::WithData, ..DumpConfig::default() });
+
+use crate::logging;
+
+use std::fs::File;
+use std::os::windows::io::AsRawHandle;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use windows::Win32::Foundation::HANDLE;
+use windows::Win32::System::Diagnostics::Debug::{
+    MiniDumpNormal, MiniDumpWithDataSegs, MiniDumpWithFullMemory, MiniDumpWithHandleData,
+    MiniDumpWithIndirectlyReferencedMemory, MiniDumpWithThreadInfo, MiniDumpWithUnloadedModules,
+    MiniDumpWriteDump, EXCEPTION_POINTERS, MINIDUMP_EXCEPTION_INFORMATION, MINIDUMP_TYPE,
+};
+use windows::Win32::System::SystemInformation::GetLocalTime;
+use windows::Win32::System::Threading::{GetCurrentProcess, GetCurrentProcessId, GetCurrentThreadId};
+
+/// How much of the process goes into a dump
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpSize {
+    /// Thread stacks and the list of loaded modules, usually well under a megabyte
+    Small,
+    /// Also global variables and the memory that stacks point at, enough to inspect most
+    /// values in a debugger. Typically a few tens of megabytes.
+    WithData,
+    /// The whole of Excel's memory, which can run to gigabytes
+    Full,
+}
+
+/// Where and how dumps are written
+#[derive(Debug, Clone)]
+pub struct DumpConfig {
+    /// Whether to write dumps at all
+    pub enabled: bool,
+    /// The folder for dumps, or None for the folder of the log file
+    pub directory: Option<PathBuf>,
+    pub size: DumpSize,
+    /// How many dumps to keep; older ones are deleted as new ones are written
+    pub max_files: usize,
+}
+
+impl Default for DumpConfig {
+    fn default() -> DumpConfig {
+        DumpConfig { enabled: true, directory: None, size: DumpSize::Small, max_files: 5 }
+    }
+}
+
+/// The configuration with the folder and file name resolved, so writing a dump does not
+/// have to ask the logger or Excel
+struct Settings {
+    config: DumpConfig,
+    directory: PathBuf,
+    prefix: String,
+}
+
+static SETTINGS: Mutex<Option<Settings>> = Mutex::new(None);
+
+/// Changes where and how dumps are written
+pub fn configure(config: DumpConfig) {
+    let settings = resolve(config);
+    *SETTINGS.lock().unwrap_or_else(|e| e.into_inner()) = Some(settings);
+}
+
+/// Resolves the default settings, unless dumps have been configured already. This is
+/// called when the guard is installed.
+pub(crate) fn prepare() {
+    if SETTINGS.lock().unwrap_or_else(|e| e.into_inner()).is_none() {
+        configure(DumpConfig::default());
+    }
+}
+
+fn resolve(config: DumpConfig) -> Settings {
+    let log_path = logging::log_path();
+    let directory = config
+        .directory
+        .clone()
+        .or_else(|| log_path.as_deref().and_then(Path::parent).map(Path::to_path_buf))
+        .unwrap_or_else(std::env::temp_dir);
+    let prefix = log_path
+        .as_deref()
+        .and_then(Path::file_stem)
+        .map_or_else(|| "xladd".to_string(), |stem| stem.to_string_lossy().into_owned());
+    Settings { config, directory, prefix }
+}
+
+/// The dumps written so far that are still on disk, oldest first
+pub fn dump_files() -> Vec<PathBuf> {
+    let settings = SETTINGS.lock().unwrap_or_else(|e| e.into_inner());
+    settings.as_ref().map_or_else(Vec::new, existing_dumps)
+}
+
+fn existing_dumps(settings: &Settings) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(&settings.directory) else { return Vec::new() };
+    let mut dumps: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension().is_some_and(|extension| extension == "dmp")
+                && path.file_name().is_some_and(|name| name.to_string_lossy().starts_with(&settings.prefix))
+        })
+        .collect();
+    // The names start with the time they were written
+    dumps.sort();
+    dumps
+}
+
+/// Writes a dump for the exception being handled on the current thread, returning its
+/// path. This runs inside the exception filter, so it gives up rather than wait for a
+/// lock the faulting code may hold.
+#[cfg_attr(not(target_env = "msvc"), allow(dead_code))]
+pub(crate) unsafe fn write(info: *mut EXCEPTION_POINTERS) -> Option<PathBuf> {
+    let settings = SETTINGS.try_lock().ok()?;
+    let settings = settings.as_ref().filter(|settings| settings.config.enabled)?;
+    let _ = std::fs::create_dir_all(&settings.directory);
+    let time = unsafe { GetLocalTime() };
+    let process_id = unsafe { GetCurrentProcessId() };
+    let path = settings.directory.join(format!(
+        "{}-{:04}{:02}{:02}-{:02}{:02}{:02}{:03}-{}.dmp",
+        settings.prefix, time.wYear, time.wMonth, time.wDay, time.wHour, time.wMinute, time.wSecond,
+        time.wMilliseconds, process_id
+    ));
+    let file = File::create(&path).ok()?;
+    let exception = MINIDUMP_EXCEPTION_INFORMATION {
+        ThreadId: unsafe { GetCurrentThreadId() },
+        ExceptionPointers: info,
+        ClientPointers: false.into(),
+    };
+    let written = unsafe {
+        MiniDumpWriteDump(GetCurrentProcess(), process_id, HANDLE(file.as_raw_handle()),
+            dump_type(settings.config.size), Some(&exception), None, None)
+    };
+    drop(file);
+    if written.is_err() {
+        let _ = std::fs::remove_file(&path);
+        return None;
+    }
+    let dumps = existing_dumps(settings);
+    for old in dumps.iter().take(dumps.len().saturating_sub(settings.config.max_files.max(1))) {
+        let _ = std::fs::remove_file(old);
+    }
+    Some(path)
+}
+
+fn dump_type(size: DumpSize) -> MINIDUMP_TYPE {
+    match size {
+        DumpSize::Small => MiniDumpNormal | MiniDumpWithThreadInfo | MiniDumpWithUnloadedModules,
+        DumpSize::WithData => {
+            MiniDumpWithDataSegs | MiniDumpWithIndirectlyReferencedMemory | MiniDumpWithThreadInfo
+                | MiniDumpWithUnloadedModules
+        }
+        DumpSize::Full => {
+            MiniDumpWithFullMemory | MiniDumpWithHandleData | MiniDumpWithThreadInfo | MiniDumpWithUnloadedModules
+        }
+    }
+}
@@ -0,0 +1,378 @@
+//! A stand-in for Excel that records every call the add-in makes and answers the common
+//! ones with canned data, so registration, the wrappers `#[xl_func]` generates and the
+//! conversions can be tested with `cargo test`. Only available with the `testing`
+//! feature.
+//!
+//! [`MockExcel::install`] takes over the Excel12 entry point until the mock is dropped.
+//! Only one mock can be installed at a time, so tests that install one run one after
+//! another:
+//!
+//! let excel = MockExcel::install();
+//! excel.caller(4, 2).cell(SHEET, 0, 0, Variant::from(1.5));
+//! Reg::new().register_all_functions();
+//! assert_eq!(excel.registration("xl_total").unwrap().arg_types, "QQ");
+//!
+//! Without a canned answer, the mock answers:
+//!
+//! * `xlGetName` with the dll name, `C:\Addins\test.xll` unless set
+//! * `xlfRegister` with a new register id, recording the strings
+//! * `xlfRegisterId` with the id of a registered procedure, or #NAME?
+//! * `xlfEvaluate` with the id of a registered function name, or #NAME?
+//! * `xlfCaller` with the calling cell, or #REF! if there is none
+//! * `xlSheetNm` with the name of the sheet of a reference, failing for an unknown sheet
+//! * `xlCoerce` with the values of the cells of a reference, or the argument itself
+//! * `xlFree` by freeing what the mock handed out
+//!
+//! and every other function with an empty result. Strings and arrays are handed out
+//! owned by "Excel", with xlbitXLFree, so the add-in's xlFree path is exercised too.
+
+#![allow(non_upper_case_globals)]
+
+use crate::entrypoint::set_excel12_entry_point;
+use crate::testing;
+use crate::variant::Variant;
+use crate::xlcall::{
+    xlCoerce, xlFree, xlGetName, xlSheetNm, xlbitDLLFree, xlbitXLFree, xlerrName, xlerrRef, xlfCaller, xlfEvaluate,
+    xlfRegister, xlfRegisterId, xlref12, xlretFailed, xltypeMask, xltypeMulti, xltypeRef, xltypeSRef,
+    xltypeStr, Xloper12MRef, Xloper12Value, LPXLOPER12, XLMREF12, XLOPER12,
+};
+use libc::c_int;
+
+use std::collections::HashMap;
+use std::mem;
+use std::sync::{Mutex, MutexGuard};
+
+/// The sheet id of the default sheet, named `[Book1]Sheet1`
+pub const SHEET: usize = 1;
+
+/// The first register id handed out
+const FIRST_REGISTER_ID: f64 = 1000.0;
+
+/// Held by the installed mock, so mocks in parallel tests wait for each other
+static INSTALLED: Mutex<()> = Mutex::new(());
+static HOST: Mutex<Option<Host>> = Mutex::new(None);
+
+/// A call the add-in made into Excel
+#[derive(Debug)]
+pub struct Call {
+    pub xlfn: u32,
+    /// Copies of the arguments. A multi-sheet reference is kept as a single-sheet one, as
+    /// the memory of its areas belongs to the caller.
+    pub args: Vec<Variant>,
+}
+
+/// The strings of an xlfRegister call, as they were passed
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Registration {
+    pub procedure: String,
+    pub arg_types: String,
+    pub function_text: String,
+    pub argument_text: String,
+    /// 1 for a function, 2 for a command
+    pub macro_type: f64,
+    pub category: String,
+    pub shortcut: String,
+    pub help_topic: String,
+    pub function_help: String,
+    pub argument_help: Vec<String>,
+    pub register_id: f64,
+}
+
+/// What the mock knows and has seen
+struct Host {
+    dll_name: String,
+    caller: Option<(usize, i32, i32)>,
+    sheets: HashMap<usize, String>,
+    cells: HashMap<(usize, i32, i32), Variant>,
+    answers: HashMap<u32, Variant>,
+    calls: Vec<Call>,
+    registrations: Vec<Registration>,
+}
+
+// Variants hold raw pointers, but everything they point at is owned by the host, and the
+// host is only reached through its mutex
+unsafe impl Send for Host {}
+
+impl Host {
+    fn new() -> Host {
+        Host {
+            dll_name: r"C:\Addins\test.xll".to_string(),
+            caller: None,
+            sheets: HashMap::from([(SHEET, "[Book1]Sheet1".to_string())]),
+            cells: HashMap::new(),
+            answers: HashMap::new(),
+            calls: Vec::new(),
+            registrations: Vec::new(),
+        }
+    }
+}
+
+/// The installed mock. Dropping it puts back the stub of [`testing::install_stub`].
+pub struct MockExcel {
+    _installed: MutexGuard<'static, ()>,
+}
+
+impl MockExcel {
+    /// Installs a fresh mock, waiting for any other to be dropped first
+    pub fn install() -> MockExcel {
+        let installed = INSTALLED.lock().unwrap_or_else(|e| e.into_inner());
+        *host() = Some(Host::new());
+        set_excel12_entry_point(callback);
+        MockExcel { _installed: installed }
+    }
+
+    /// Sets the full path xlGetName answers with
+    pub fn dll_name(&self, dll_name: &str) -> &MockExcel {
+        with_host(|host| host.dll_name = dll_name.to_string());
+        self
+    }
+
+    /// Makes the cell at the zero-based row and column of the default sheet the caller
+    pub fn caller(&self, row: i32, column: i32) -> &MockExcel {
+        with_host(|host| host.caller = Some((SHEET, row, column)));
+        self
+    }
+
+    /// Adds a sheet, or renames one
+    pub fn sheet(&self, sheet: usize, name: &str) -> &MockExcel {
+        with_host(|host| host.sheets.insert(sheet, name.to_string()));
+        self
+    }
+
+    /// Removes a sheet, as if it had been deleted. Its cells are kept.
+    pub fn remove_sheet(&self, sheet: usize) -> &MockExcel {
+        with_host(|host| host.sheets.remove(&sheet));
+        self
+    }
+
+    /// Sets the value xlCoerce reads from a cell
+    pub fn cell(&self, sheet: usize, row: i32, column: i32, value: Variant) -> &MockExcel {
+        with_host(|host| host.cells.insert((sheet, row, column), value));
+        self
+    }
+
+    /// Answers every call of a function with a value, instead of the default answer
+    pub fn answer(&self, xlfn: u32, value: Variant) -> &MockExcel {
+        with_host(|host| host.answers.insert(xlfn, value));
+        self
+    }
+
+    /// Every call made so far, apart from xlFree
+    pub fn calls(&self) -> Vec<Call> {
+        with_host(|host| host.calls.iter().map(|call| Call { xlfn: call.xlfn, args: call.args.clone() }).collect())
+    }
+
+    /// The calls made so far to one function
+    pub fn calls_to(&self, xlfn: u32) -> Vec<Call> {
+        self.calls().into_iter().filter(|call| call.xlfn == xlfn).collect()
+    }
+
+    /// Every function and command registered so far, in order
+    pub fn registrations(&self) -> Vec<Registration> {
+        with_host(|host| host.registrations.clone())
+    }
+
+    /// The last registration of an exported procedure
+    pub fn registration(&self, procedure: &str) -> Option<Registration> {
+        self.registrations().into_iter().rev().find(|registration| registration.procedure == procedure)
+    }
+
+    /// Forgets the calls made so far, keeping the registrations and canned data
+    pub fn clear_calls(&self) {
+        with_host(|host| host.calls.clear());
+    }
+}
+
+impl Drop for MockExcel {
+    fn drop(&mut self) {
+        testing::install_stub();
+        host().take();
+    }
+}
+
+fn host() -> MutexGuard<'static, Option<Host>> {
+    HOST.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn with_host<T>(f: impl FnOnce(&mut Host) -> T) -> T {
+    f(host().as_mut().expect("the mock Excel is installed"))
+}
+
+extern "system" fn callback(xlfn: c_int, count: c_int, opers: *const LPXLOPER12, result: LPXLOPER12) -> c_int {
+    let args: Vec<LPXLOPER12> = if opers.is_null() || count <= 0 {
+        Vec::new()
+    } else {
+        unsafe { std::slice::from_raw_parts(opers, count as usize) }.to_vec()
+    };
+    let xlfn = xlfn as u32;
+    if xlfn == xlFree {
+        args.into_iter().for_each(free);
+        return 0;
+    }
+    let mut host = host();
+    let Some(host) = host.as_mut() else {
+        return xlretFailed as c_int;
+    };
+    host.calls.push(Call { xlfn, args: args.iter().map(|&arg| copy(arg)).collect() });
+    let answer = match host.answers.get(&xlfn) {
+        Some(answer) => Some(answer.clone()),
+        None => default_answer(host, xlfn, &args),
+    };
+    match answer {
+        Some(answer) => {
+            if !result.is_null() {
+                hand_out(answer, result);
+            }
+            0
+        }
+        None => xlretFailed as c_int,
+    }
+}
+
+/// The answer without a canned one, or None if the call fails
+fn default_answer(host: &mut Host, xlfn: u32, args: &[LPXLOPER12]) -> Option<Variant> {
+    let arg = |index: usize| args.get(index).map(|&arg| Variant::from(arg)).unwrap_or_default();
+    let answer = match xlfn {
+        xlGetName => Variant::from(host.dll_name.as_str()),
+        xlfRegister => {
+            let text = |index: usize| String::from(&arg(index));
+            let register_id = FIRST_REGISTER_ID + host.registrations.len() as f64;
+            host.registrations.push(Registration {
+                procedure: text(1),
+                arg_types: text(2),
+                function_text: text(3),
+                argument_text: text(4),
+                macro_type: f64::try_from(&arg(5)).unwrap_or(1.0),
+                category: text(6),
+                shortcut: text(7),
+                help_topic: text(8),
+                function_help: text(9),
+                argument_help: (10..args.len()).map(text).collect(),
+                register_id,
+            });
+            Variant::from(register_id)
+        }
+        xlfRegisterId => {
+            let procedure = String::from(&arg(1));
+            find_id(host, |registration| registration.procedure == procedure)
+        }
+        xlfEvaluate => {
+            let name = String::from(&arg(0));
+            find_id(host, |registration| registration.function_text.eq_ignore_ascii_case(&name))
+        }
+        xlfCaller => match host.caller {
+            Some((sheet, row, column)) => reference(sheet, row, row, column, column),
+            None => Variant::from_err(xlerrRef),
+        },
+        xlSheetNm => {
+            let sheet = area(host, args.first().copied()).map_or(SHEET, |(sheet, _)| sheet);
+            Variant::from(host.sheets.get(&sheet)?.as_str())
+        }
+        xlCoerce => match area(host, args.first().copied()) {
+            Some((sheet, area)) => cells(host, sheet, &area),
+            None => arg(0).clone(),
+        },
+        _ => Variant::default(),
+    };
+    Some(answer)
+}
+
+/// The register id of the last registration that matches, or #NAME?
+fn find_id(host: &Host, matches: impl Fn(&Registration) -> bool) -> Variant {
+    host.registrations
+        .iter()
+        .rev()
+        .find(|registration| matches(registration))
+        .map_or_else(|| Variant::from_err(xlerrName), |registration| Variant::from(registration.register_id))
+}
+
+/// The sheet and first area of a reference. A single-sheet reference is on the sheet of
+/// the caller, or the default sheet.
+fn area(host: &Host, reference: Option<LPXLOPER12>) -> Option<(usize, xlref12)> {
+    let xloper = unsafe { &*reference? };
+    match xloper.xltype & xltypeMask {
+        xltypeSRef => {
+            let sheet = host.caller.map_or(SHEET, |(sheet, _, _)| sheet);
+            xloper.val.as_sref(xloper.xltype).map(|sref| (sheet, sref.ref_))
+        }
+        xltypeRef => {
+            let mref = xloper.val.as_mref(xloper.xltype)?;
+            if mref.lpmref.is_null() {
+                return None;
+            }
+            Some((mref.idSheet as usize, unsafe { (*mref.lpmref).reftbl[0] }))
+        }
+        _ => None,
+    }
+}
+
+/// The values of the cells in an area, a single value for a single cell
+fn cells(host: &Host, sheet: usize, area: &xlref12) -> Variant {
+    let value = |row: i32, column: i32| host.cells.get(&(sheet, row, column)).cloned().unwrap_or_default();
+    if area.rwFirst == area.rwLast && area.colFirst == area.colLast {
+        return value(area.rwFirst, area.colFirst);
+    }
+    let rows: Vec<Vec<Variant>> = (area.rwFirst..=area.rwLast)
+        .map(|row| (area.colFirst..=area.colLast).map(|column| value(row, column)).collect())
+        .collect();
+    Variant::from(rows)
+}
+
+/// A reference to an area of a sheet, with its area in memory the mock owns until xlFree
+fn reference(sheet: usize, first_row: i32, last_row: i32, first_column: i32, last_column: i32) -> Variant {
+    let area = xlref12 { rwFirst: first_row, rwLast: last_row, colFirst: first_column, colLast: last_column };
+    let lpmref = Box::into_raw(Box::new(XLMREF12 { count: 1, reftbl: [area] }));
+    let mut value = Variant::default();
+    *value.as_mut_xloper() =
+        XLOPER12 { xltype: xltypeRef, val: Xloper12Value { mref: Xloper12MRef { lpmref, idSheet: sheet as _ } } };
+    value
+}
+
+/// A copy of an argument that does not point into the caller's memory
+fn copy(arg: LPXLOPER12) -> Variant {
+    let xloper = unsafe { &*arg };
+    if xloper.xltype & xltypeMask == xltypeRef
+        && let Some(mref) = xloper.val.as_mref(xloper.xltype)
+        && !mref.lpmref.is_null()
+    {
+        let area = unsafe { (*mref.lpmref).reftbl[0] };
+        return Variant::as_sref(area.rwFirst, area.rwLast, area.colFirst, area.colLast);
+    }
+    Variant::from(arg).clone()
+}
+
+/// Writes an answer to the result, handing ownership of its memory to "Excel"
+fn hand_out(answer: Variant, result: LPXLOPER12) {
+    let mut answer = mem::ManuallyDrop::new(answer);
+    let mut xloper = *answer.as_mut_xloper();
+    xloper.xltype &= xltypeMask;
+    if matches!(xloper.xltype, xltypeStr | xltypeMulti | xltypeRef) {
+        xloper.xltype |= xlbitXLFree;
+    }
+    unsafe { *result = xloper };
+}
+
+/// Frees a value the mock handed out. Strings and arrays were allocated by a Variant, so
+/// they are given back to one to drop.
+fn free(xloper: LPXLOPER12) {
+    if xloper.is_null() {
+        return;
+    }
+    let mut value = unsafe { *xloper };
+    if value.xltype & xlbitXLFree == 0 {
+        return;
+    }
+    unsafe { (*xloper).xltype &= !xlbitXLFree };
+    if value.xltype & xltypeMask == xltypeRef {
+        if let Some(mref) = value.val.as_mref(value.xltype)
+            && !mref.lpmref.is_null()
+        {
+            drop(unsafe { Box::from_raw(mref.lpmref) });
+        }
+        return;
+    }
+    value.xltype = value.xltype & xltypeMask | xlbitDLLFree;
+    let mut owned = Variant::default();
+    *owned.as_mut_xloper() = value;
+    drop(owned);
+}
@@ -0,0 +1,226 @@
+//! Sharing Excel with other add-ins built on xladd. Each one registers its functions as
+//! `xl_...` unless told otherwise, so a second add-in in the same session would find
+//! every name taken. The add-ins loaded in an Excel process keep a table of the prefix
+//! each one uses, in shared memory named after the process and guarded by a named mutex.
+//! When [`config::load`](crate::config::load) finds that another add-in already has this
+//! one's prefix, it gives this add-in one of its own, made from the file name of the xll:
+//! the functions of `acme_curves.xll` become `acme_curves_npv` and so on.
+//!
+//! The first add-in loaded keeps its prefix. Setting `prefix` in the
+//! [settings](crate::config) fixes the prefix but still joins the table, and
+//! `negotiate_prefix = false` leaves the table alone altogether.
+
+use log::{debug, info, warn};
+use windows::Win32::Foundation::{CloseHandle, HANDLE, INVALID_HANDLE_VALUE, WAIT_ABANDONED, WAIT_OBJECT_0};
+use windows::Win32::System::Memory::{
+    CreateFileMappingW, FILE_MAP_ALL_ACCESS, MEMORY_MAPPED_VIEW_ADDRESS, MapViewOfFile, PAGE_READWRITE, UnmapViewOfFile,
+};
+use windows::Win32::System::Threading::{CreateMutexW, GetCurrentProcessId, ReleaseMutex, WaitForSingleObject};
+use windows::core::PCWSTR;
+use widestring::U16CString;
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// The prefix of function names when nothing else is set, without its underscore
+pub const DEFAULT_PREFIX: &str = "xl";
+/// The size of the shared table, which is JSON after a four byte length
+const TABLE_SIZE: usize = 64 * 1024;
+/// How long to wait for another add-in to finish with the table, in milliseconds
+const LOCK_TIMEOUT: u32 = 5000;
+
+/// The shared table this add-in has joined, as handle values so it can live in a static
+struct Session {
+    mutex: isize,
+    mapping: isize,
+    view: usize,
+    dll_name: String,
+}
+
+static SESSION: Mutex<Option<Session>> = Mutex::new(None);
+
+/// Claims a prefix for this add-in in the shared table, returning the prefix to use
+/// instead of the one wanted if another add-in already has it. `wanted` is the
+/// configured prefix, or None for the default. If the table cannot be opened, the add-in
+/// goes ahead with the prefix it wanted.
+pub fn negotiate(dll_name: &str, wanted: Option<&str>) -> Option<String> {
+    let mut session = SESSION.lock().unwrap_or_else(|e| e.into_inner());
+    if session.is_none() {
+        match open(dll_name) {
+            Ok(opened) => *session = Some(opened),
+            Err(e) => {
+                warn!("cannot share the function prefix with other add-ins: {}", e);
+                return None;
+            }
+        }
+    }
+    let session = session.as_ref()?;
+    let _lock = session.lock()?;
+    let mut table = session.read();
+    table.remove(dll_name);
+    let prefix = match wanted {
+        Some(prefix) => prefix.to_string(),
+        None => DEFAULT_PREFIX.to_string(),
+    };
+    let holder = table.iter().find(|(_, taken)| **taken == prefix).map(|(holder, _)| holder.clone());
+    let chosen = match (wanted, holder) {
+        (_, None) => prefix.clone(),
+        (Some(_), Some(holder)) => {
+            warn!("{} also registers functions with the prefix {}", holder, prefix);
+            prefix.clone()
+        }
+        (None, Some(holder)) => {
+            let own = free_prefix(&own_prefix(dll_name), &table);
+            info!("{} has the prefix {}, so this add-in uses {}", holder, prefix, own);
+            own
+        }
+    };
+    table.insert(dll_name.to_string(), chosen.clone());
+    session.write(&table);
+    (chosen != prefix).then_some(chosen)
+}
+
+/// Takes this add-in out of the shared table, so another can have its prefix. Called
+/// from xlAutoClose.
+pub fn leave() {
+    let Some(session) = SESSION.lock().unwrap_or_else(|e| e.into_inner()).take() else {
+        return;
+    };
+    if let Some(_lock) = session.lock() {
+        let mut table = session.read();
+        table.remove(&session.dll_name);
+        session.write(&table);
+    }
+    unsafe {
+        let _ = UnmapViewOfFile(MEMORY_MAPPED_VIEW_ADDRESS { Value: session.view as *mut _ });
+        let _ = CloseHandle(HANDLE(session.mapping as *mut _));
+        let _ = CloseHandle(HANDLE(session.mutex as *mut _));
+    }
+}
+
+/// The add-ins in the shared table, by path, with the prefix each uses
+pub fn siblings() -> BTreeMap<String, String> {
+    let session = SESSION.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(session) = session.as_ref() else {
+        return BTreeMap::new();
+    };
+    let Some(_lock) = session.lock() else {
+        return BTreeMap::new();
+    };
+    session.read()
+}
+
+/// A prefix made from the file name of the xll, such as `acme_curves` for
+/// `C:\Addins\Acme Curves.xll`
+fn own_prefix(dll_name: &str) -> String {
+    let stem = Path::new(dll_name).file_stem().map(|stem| stem.to_string_lossy().to_lowercase()).unwrap_or_default();
+    let mut prefix: String = stem.chars().map(|c| if c.is_alphanumeric() { c } else { '_' }).collect();
+    if !prefix.starts_with(|c: char| c.is_alphabetic()) {
+        prefix.insert_str(0, "addin_");
+    }
+    prefix
+}
+
+/// The prefix, or the prefix with a number after it if another add-in has it already
+fn free_prefix(prefix: &str, table: &BTreeMap<String, String>) -> String {
+    let taken = |candidate: &str| table.values().any(|held| held == candidate);
+    if !taken(prefix) {
+        return prefix.to_string();
+    }
+    (2..).map(|n| format!("{}{}", prefix, n)).find(|candidate| !taken(candidate)).unwrap_or_default()
+}
+
+fn open(dll_name: &str) -> windows::core::Result<Session> {
+    let name = format!("Local\\xladd-prefixes-{}", unsafe { GetCurrentProcessId() });
+    let mutex_name = U16CString::from_str_truncate(format!("{}-lock", name));
+    let mapping_name = U16CString::from_str_truncate(&name);
+    unsafe {
+        let mutex = CreateMutexW(None, false, PCWSTR(mutex_name.as_ptr()))?;
+        let mapping =
+            match CreateFileMappingW(INVALID_HANDLE_VALUE, None, PAGE_READWRITE, 0, TABLE_SIZE as u32, PCWSTR(mapping_name.as_ptr())) {
+                Ok(mapping) => mapping,
+                Err(e) => {
+                    let _ = CloseHandle(mutex);
+                    return Err(e);
+                }
+            };
+        // A new mapping is zeroed, which reads as an empty table
+        let view = MapViewOfFile(mapping, FILE_MAP_ALL_ACCESS, 0, 0, TABLE_SIZE);
+        if view.Value.is_null() {
+            let e = windows::core::Error::from_win32();
+            let _ = CloseHandle(mapping);
+            let _ = CloseHandle(mutex);
+            return Err(e);
+        }
+        debug!("joined the shared prefix table {}", name);
+        Ok(Session { mutex: mutex.0 as isize, mapping: mapping.0 as isize, view: view.Value as usize, dll_name: dll_name.to_string() })
+    }
+}
+
+/// Held while the table is read or written
+struct TableLock(isize);
+
+impl Drop for TableLock {
+    fn drop(&mut self) {
+        let _ = unsafe { ReleaseMutex(HANDLE(self.0 as *mut _)) };
+    }
+}
+
+impl Session {
+    fn lock(&self) -> Option<TableLock> {
+        let wait = unsafe { WaitForSingleObject(HANDLE(self.mutex as *mut _), LOCK_TIMEOUT) };
+        // An abandoned mutex is still ours; the add-in that held it has gone
+        if wait == WAIT_OBJECT_0 || wait == WAIT_ABANDONED {
+            Some(TableLock(self.mutex))
+        } else {
+            warn!("timed out waiting for the shared prefix table");
+            None
+        }
+    }
+
+    fn read(&self) -> BTreeMap<String, String> {
+        let bytes = unsafe { std::slice::from_raw_parts(self.view as *const u8, TABLE_SIZE) };
+        let length = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+        if length == 0 || length > TABLE_SIZE - 4 {
+            return BTreeMap::new();
+        }
+        serde_json::from_slice(&bytes[4..4 + length]).unwrap_or_default()
+    }
+
+    fn write(&self, table: &BTreeMap<String, String>) {
+        let Ok(json) = serde_json::to_vec(table) else {
+            return;
+        };
+        if json.len() > TABLE_SIZE - 4 {
+            warn!("too many add-ins to share the prefix table");
+            return;
+        }
+        let bytes = unsafe { std::slice::from_raw_parts_mut(self.view as *mut u8, TABLE_SIZE) };
+        bytes[..4].copy_from_slice(&(json.len() as u32).to_le_bytes());
+        bytes[4..4 + json.len()].copy_from_slice(&json);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefixes_come_from_the_file_name() {
+        assert_eq!(own_prefix(r"C:\Addins\Acme Curves.xll"), "acme_curves");
+        assert_eq!(own_prefix(r"C:\Addins\risk-tools.v2.xll"), "risk_tools_v2");
+        assert_eq!(own_prefix(r"C:\Addins\3d.xll"), "addin_3d");
+        assert_eq!(own_prefix(""), "addin_");
+    }
+
+    #[test]
+    fn taken_prefixes_get_a_number() {
+        let mut table = BTreeMap::new();
+        assert_eq!(free_prefix("acme", &table), "acme");
+        table.insert(r"C:\a.xll".to_string(), "acme".to_string());
+        table.insert(r"C:\b.xll".to_string(), "acme2".to_string());
+        assert_eq!(free_prefix("acme", &table), "acme3");
+        assert_eq!(free_prefix("xl", &table), "xl");
+    }
+}
@@ -0,0 +1,112 @@
+//! Large results shown a page at a time. Returning a 500,000-row array to a cell is slow,
+//! and more than anyone can read, so a function can store its result with [`insert`] and
+//! return the handle instead. The sheet then shows any part of it with the built-in
+//! functions `xl_page(handle, page, page_size)` and `xl_rows(handle)`.
+//!
+//! # Example
+//!
+//! #[xl_func]
+//! fn trades_load(book: String) -> Result<String, Box<dyn Error>> {
+//!     let rows: Vec<Vec<Variant>> = load_trades(&book)?;
+//!     Ok(paging::insert("Trades", Variant::from(rows)))
+//! }
+//!
+//! and in the sheet, =xl_page(A1, B1, 1000) shows page B1 of the trades.
+
+use crate::handles;
+use crate::registrator::FunctionRegistration;
+use crate::variant::{Variant, XLAddError};
+use crate::xlcall::{xlerrNA, xlerrValue, LPXLOPER12};
+
+/// The page size of xl_page when none is given
+pub const DEFAULT_PAGE_SIZE: usize = 1000;
+
+/// A stored result. Variants own their memory outright and are only read once stored, so
+/// they can be shared between Excel's calculation threads.
+struct Paged(Variant);
+unsafe impl Send for Paged {}
+unsafe impl Sync for Paged {}
+
+/// Stores a result and returns a handle for paging through it, tied to the calling cells
+/// like any other [handle](crate::handles)
+pub fn insert(type_name: &str, result: Variant) -> String {
+    handles::insert(type_name, Paged(result))
+}
+
+/// The number of rows and columns of a stored result
+pub fn dim(handle: &str) -> Result<(usize, usize), XLAddError> {
+    let (columns, rows) = handles::get::<Paged>(handle)?.0.dim();
+    Ok((rows, columns))
+}
+
+/// Rows `(page - 1) * page_size` up to `page * page_size` of a stored result, counting
+/// pages from 1. The last page may be short. Fails if the page is past the end.
+pub fn page(handle: &str, page: usize, page_size: usize) -> Result<Variant, XLAddError> {
+    let paged = handles::get::<Paged>(handle)?;
+    let (columns, rows) = paged.0.dim();
+    let first = page.saturating_sub(1).saturating_mul(page_size);
+    if page == 0 || page_size == 0 || first >= rows {
+        return Err(XLAddError::InvalidData(format!("{} has no page {} of {} rows", handle, page, page_size)));
+    }
+    let last = rows.min(first + page_size);
+    let values: Vec<Vec<Variant>> =
+        (first..last).map(|row| (0..columns).map(|column| paged.0.at(column, row)).collect()).collect();
+    Ok(Variant::from(values))
+}
+
+/// Shows one page of a stored result. Pages count from 1 and hold 1000 rows unless a page
+/// size is given. Gives #N/A for a page past the end and #VALUE! for an unknown handle.
+#[unsafe(no_mangle)]
+pub extern "system" fn xl_page(handle: LPXLOPER12, page_number: LPXLOPER12, page_size: LPXLOPER12) -> LPXLOPER12 {
+    let handle = String::from(&Variant::from(handle));
+    let page_number = Variant::from(page_number);
+    let page_number = if page_number.is_missing_or_null() { Ok(1.0) } else { f64::try_from(&page_number) };
+    let page_size = Variant::from(page_size);
+    let page_size = if page_size.is_missing_or_null() { Ok(DEFAULT_PAGE_SIZE as f64) } else { f64::try_from(&page_size) };
+    let result = match (page_number, page_size) {
+        (Ok(page_number), Ok(page_size)) if page_number >= 1.0 && page_size >= 1.0 => {
+            if !handles::contains(&handle) {
+                Variant::from_err(xlerrValue)
+            } else {
+                page(&handle, page_number as usize, page_size as usize).unwrap_or_else(|_| Variant::from_err(xlerrNA))
+            }
+        }
+        _ => Variant::from_err(xlerrValue),
+    };
+    LPXLOPER12::from(result)
+}
+
+inventory::submit! {
+    FunctionRegistration {
+        xl_name: "xl_page",
+        arg_types: "QQQQ$",
+        arg_names: "handle,page,page_size",
+        category: "Add-in Diagnostics",
+        description: "Shows one page of a large result stored behind a handle",
+        arg_infos: &[],
+        help_topic: "",
+    }
+}
+
+/// The number of rows in a stored result, or #VALUE! for an unknown handle
+#[unsafe(no_mangle)]
+pub extern "system" fn xl_rows(handle: LPXLOPER12) -> LPXLOPER12 {
+    let handle = String::from(&Variant::from(handle));
+    let result = match dim(&handle) {
+        Ok((rows, _)) => Variant::from(rows as f64),
+        Err(_) => Variant::from_err(xlerrValue),
+    };
+    LPXLOPER12::from(result)
+}
+
+inventory::submit! {
+    FunctionRegistration {
+        xl_name: "xl_rows",
+        arg_types: "QQ$",
+        arg_names: "handle",
+        category: "Add-in Diagnostics",
+        description: "Counts the rows of a large result stored behind a handle",
+        arg_infos: &[],
+        help_topic: "",
+    }
+}
@@ -0,0 +1,88 @@
+//! One thread pool shared by every heavy function in the add-in. Excel runs thread-safe
+//! functions on as many calculation threads as there are cores; if each of those calls
+//! starts its own parallel work, the machine ends up with many times more busy threads
+//! than cores. A function marked `#[xl_func(pool)]` runs its body on this pool instead,
+//! and any rayon parallelism inside it (`par_iter` and friends) runs on the same threads,
+//! so the total stays bounded by the pool size.
+//!
+//! The pool is started the first time it is used, with one thread per core unless
+//! [`set_threads`] has been called.
+
+use crate::guard;
+use log::debug;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How long xlAutoClose waits for the threads to finish
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+struct Pool {
+    pool: Arc<rayon::ThreadPool>,
+    threads: usize,
+}
+
+static POOL: Mutex<Option<Pool>> = Mutex::new(None);
+/// The number of threads to start, or zero for one per core
+static THREADS: AtomicUsize = AtomicUsize::new(0);
+/// Threads of any pool we started that have not exited yet
+static RUNNING: AtomicUsize = AtomicUsize::new(0);
+
+/// Sets how many threads the pool has. If the pool is already running, work submitted
+/// from now on goes to a new pool of that size, and the old one ends once its work is done.
+pub fn set_threads(threads: usize) {
+    THREADS.store(threads, Ordering::SeqCst);
+    let mut pool = POOL.lock().unwrap_or_else(|e| e.into_inner());
+    if pool.as_ref().is_some_and(|pool| pool.threads != threads) {
+        *pool = None;
+    }
+}
+
+/// The number of threads work runs on
+pub fn threads() -> usize {
+    match THREADS.load(Ordering::SeqCst) {
+        0 => std::thread::available_parallelism().map(|n| n.get()).unwrap_or(2),
+        threads => threads,
+    }
+}
+
+/// The shared pool, started if necessary
+pub fn pool() -> Arc<rayon::ThreadPool> {
+    let mut pool = POOL.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(pool) = pool.as_ref() {
+        return pool.pool.clone();
+    }
+    let threads = threads();
+    let built = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .thread_name(|i| format!("xladd-pool-{}", i))
+        .start_handler(|_| {
+            RUNNING.fetch_add(1, Ordering::SeqCst);
+        })
+        .exit_handler(|_| {
+            RUNNING.fetch_sub(1, Ordering::SeqCst);
+        })
+        .build()
+        .expect("failed to start the thread pool");
+    debug!("thread pool started with {} threads", threads);
+    let built = Arc::new(built);
+    *pool = Some(Pool { pool: built.clone(), threads });
+    built
+}
+
+/// Runs `work` on the pool and waits for its result. Hardware faults on the pool's
+/// threads are turned into panics, which are passed back to the caller.
+pub fn install<R: Send>(work: impl FnOnce() -> R + Send) -> R {
+    pool().install(|| guard::guarded(work))
+}
+
+/// Stops the pool and waits for its threads to exit, as they must not outlive the xll.
+/// This is called from xlAutoClose.
+pub fn shutdown() {
+    POOL.lock().unwrap_or_else(|e| e.into_inner()).take();
+    let start = Instant::now();
+    while RUNNING.load(Ordering::SeqCst) > 0 && start.elapsed() < SHUTDOWN_TIMEOUT {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
@@ -0,0 +1,169 @@
+//! Progress of long-running functions. A [`ProgressReporter`] shows how far the work has
+//! got in Excel's status bar and tells the function when to give up: when the user
+//! presses Esc, or when a [timeout](crate::watchdog) has passed. Calls into Excel are
+//! throttled, so reporting from an inner loop costs next to nothing.
+//!
+//! A worksheet function can take a `&mut ProgressReporter` argument, which the generated
+//! wrapper supplies and Excel never sees:
+//!
+//! #[xl_func]
+//! fn simulate(paths: f64, progress: &mut ProgressReporter) -> Result<f64, String> {
+//!     for i in 0..paths as usize {
+//!         if !progress.report((i + 1) as f64 / paths) {
+//!             return Err("cancelled".to_string());
+//!         }
+//!         ...
+//!     }
+//! }
+//!
+//! The status bar can only be written from Excel's main thread, so functions running on
+//! other calculation threads just check for cancellation, and work moved to a thread of
+//! the add-in's own only checks for a timeout. Worksheet functions may not be allowed to
+//! change the status bar through the C API; with the `com` feature, the Application
+//! object is used instead.
+
+use crate::entrypoint::{excel12, excel12v};
+use crate::instrument;
+use crate::variant::Variant;
+use crate::watchdog;
+use crate::xlcall::{xlAbort, xlcMessage, LPXLOPER12};
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+use windows::Win32::System::Threading::GetCurrentThreadId;
+
+/// How often Excel is called by default
+const DEFAULT_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The id of Excel's main thread, or zero until the add-in is opened
+static MAIN_THREAD: AtomicU32 = AtomicU32::new(0);
+
+/// Remembers the current thread as Excel's main thread. This is called from xlAutoOpen.
+pub(crate) fn remember_main_thread() {
+    MAIN_THREAD.store(unsafe { GetCurrentThreadId() }, Ordering::Relaxed);
+}
+
+fn on_main_thread() -> bool {
+    let main = MAIN_THREAD.load(Ordering::Relaxed);
+    main != 0 && main == unsafe { GetCurrentThreadId() }
+}
+
+/// Status bar progress and cancellation for one piece of long-running work
+pub struct ProgressReporter {
+    label: String,
+    message: String,
+    fraction: f64,
+    interval: Duration,
+    /// When Excel was last called, or None if it has not been yet
+    last_update: Option<Instant>,
+    cancelled: bool,
+    /// Whether the status bar is showing our text
+    showing: bool,
+}
+
+impl ProgressReporter {
+    /// A reporter whose status bar text starts with `label`, usually the function name
+    pub fn new(label: &str) -> ProgressReporter {
+        ProgressReporter {
+            label: label.to_string(),
+            message: String::new(),
+            fraction: 0.0,
+            interval: DEFAULT_INTERVAL,
+            last_update: None,
+            cancelled: false,
+            showing: false,
+        }
+    }
+
+    /// Calls Excel at most once per `interval`
+    pub fn with_interval(mut self, interval: Duration) -> ProgressReporter {
+        self.interval = interval;
+        self
+    }
+
+    /// Records how much of the work is done, from 0 to 1. Returns false once the work
+    /// should stop.
+    pub fn report(&mut self, fraction: f64) -> bool {
+        self.fraction = fraction.clamp(0.0, 1.0);
+        self.update()
+    }
+
+    /// Records progress as a count of steps done out of a total
+    pub fn step(&mut self, done: usize, total: usize) -> bool {
+        self.report(if total == 0 { 1.0 } else { done as f64 / total as f64 })
+    }
+
+    /// Shows a message after the percentage, such as the current stage of the work
+    pub fn set_message(&mut self, message: &str) -> bool {
+        self.message = message.to_string();
+        self.update()
+    }
+
+    /// Whether the work should stop, checking with Excel if it is time to
+    pub fn is_cancelled(&mut self) -> bool {
+        !self.update()
+    }
+
+    /// How much of the work was last reported done
+    pub fn fraction(&self) -> f64 {
+        self.fraction
+    }
+
+    /// Calls Excel if the interval has passed. Returns false once the work should stop.
+    fn update(&mut self) -> bool {
+        if self.cancelled || watchdog::should_stop() {
+            self.cancelled = true;
+            return false;
+        }
+        if self.last_update.is_some_and(|last| last.elapsed() < self.interval) {
+            return true;
+        }
+        self.last_update = Some(Instant::now());
+        // Excel may only be called on its own threads, not on those of a pool or timeout
+        let main_thread = on_main_thread();
+        if !main_thread && !instrument::in_worksheet_function() {
+            return true;
+        }
+        // Keeps Excel responsive, and tells us if the user pressed Esc
+        if bool::try_from(&excel12(xlAbort, &mut [])).unwrap_or(false) {
+            self.cancelled = true;
+            return false;
+        }
+        if main_thread {
+            let text = if self.message.is_empty() {
+                format!("{}: {:.0}%", self.label, self.fraction * 100.0)
+            } else {
+                format!("{}: {:.0}% {}", self.label, self.fraction * 100.0, self.message)
+            };
+            self.showing = set_status_bar(Some(&text));
+        }
+        true
+    }
+}
+
+impl Drop for ProgressReporter {
+    /// Gives the status bar back to Excel
+    fn drop(&mut self) {
+        if self.showing {
+            set_status_bar(None);
+        }
+    }
+}
+
+/// Shows text in the status bar, or restores Excel's own text. Returns whether it worked.
+fn set_status_bar(text: Option<&str>) -> bool {
+    let mut args = match text {
+        Some(text) => [Variant::from(true), Variant::from(text)],
+        None => [Variant::from(false), Variant::missing()],
+    };
+    let pointers: Vec<LPXLOPER12> = args.iter_mut().map(|arg| arg.as_mut_xloper() as LPXLOPER12).collect();
+    let mut result = Variant::default();
+    if excel12v(xlcMessage as i32, result.as_mut_xloper(), &pointers) == 0 {
+        return true;
+    }
+    #[cfg(feature = "com")]
+    if let Ok(application) = crate::com::Application::get() {
+        return application.set_status_bar(text).is_ok();
+    }
+    false
+}
@@ -0,0 +1,137 @@
+//! What happened to each function at registration. Excel gives no error when it refuses
+//! a registration; the function is simply missing, and cells calling it show #NAME?. The
+//! [registrator](crate::registrator) checks each function's strings against Excel's limits
+//! before registering it, and records Excel's answer here. Failures are logged as they
+//! happen, and `=xl_registration_report()` shows the lot in the sheet.
+//!
+//! With `dry_run` in the [settings](crate::config), the functions are checked and recorded
+//! without being registered.
+
+use crate::registrator::FunctionRegistration;
+use crate::variant::Variant;
+use crate::xlcall::LPXLOPER12;
+use log::warn;
+
+use std::sync::Mutex;
+
+/// The most characters Excel takes in each registration string
+pub const MAX_TEXT_CHARS: usize = 255;
+/// The most arguments a function can have
+pub const MAX_ARGUMENTS: usize = 255;
+
+/// How the registration of one function went
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Registered,
+    Failed(String),
+    DryRun,
+}
+
+/// The registration of one function under one name
+#[derive(Debug, Clone)]
+pub struct RegistrationRecord {
+    /// The exported name
+    pub xl_name: &'static str,
+    /// The name in Excel
+    pub name: String,
+    pub outcome: Outcome,
+    /// Strings that Excel would cut short or reject
+    pub problems: Vec<String>,
+}
+
+static RECORDS: Mutex<Vec<RegistrationRecord>> = Mutex::new(Vec::new());
+
+/// What each string passed to xlfRegister after the dll name is, up to the argument
+/// descriptions
+const FIELDS: [&str; 7] = ["exported name", "type", "name", "argument names", "category", "help topic", "description"];
+
+/// Checks the strings a function is about to be registered with, in the order of
+/// [`FIELDS`], returning a description of each one Excel would cut short or reject
+pub fn check(registration: &FunctionRegistration, name: &str, strings: &[&str], arg_descriptions: &[&str]) -> Vec<String> {
+    let mut problems = Vec::new();
+    let valid_start = name.chars().next().is_some_and(|c| c.is_alphabetic() || c == '_');
+    if !valid_start || !name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '.') {
+        problems.push(format!("\"{}\" is not a valid function name", name));
+    }
+    for (i, text) in strings.iter().chain(arg_descriptions).enumerate() {
+        let length = text.encode_utf16().count();
+        if length > MAX_TEXT_CHARS {
+            let field = FIELDS.get(i).map_or_else(|| format!("description of argument {}", i + 1 - FIELDS.len()), |field| field.to_string());
+            problems.push(format!("the {} is {} characters long; Excel takes {}", field, length, MAX_TEXT_CHARS));
+        }
+    }
+    let arg_names = registration.arg_names.split(',').filter(|arg_name| !arg_name.trim().is_empty()).count();
+    if arg_names > MAX_ARGUMENTS {
+        problems.push(format!("there are {} arguments; Excel takes {}", arg_names, MAX_ARGUMENTS));
+    }
+    if registration.arg_infos.len() > arg_names {
+        problems.push(format!("there are {} argument descriptions for {} argument names", registration.arg_infos.len(), arg_names));
+    }
+    problems
+}
+
+/// Adds a function to the report, logging it if it failed or has problems
+pub(crate) fn record(record: RegistrationRecord) {
+    if let Outcome::Failed(result) = &record.outcome {
+        warn!("Excel did not register {} as {}: {}", record.xl_name, record.name, result);
+    }
+    for problem in &record.problems {
+        warn!("{}: {}", record.xl_name, problem);
+    }
+    RECORDS.lock().unwrap_or_else(|e| e.into_inner()).push(record);
+}
+
+/// Forgets the report, at the start of a registration pass
+pub(crate) fn clear() {
+    RECORDS.lock().unwrap_or_else(|e| e.into_inner()).clear();
+}
+
+/// Every registration since the last full registration pass
+pub fn records() -> Vec<RegistrationRecord> {
+    RECORDS.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Returns a table of the functions registered, with what Excel made of each and any
+/// problems with their strings. Only failures and problems are shown if `failed_only` is
+/// TRUE.
+#[unsafe(no_mangle)]
+pub extern "system" fn xl_registration_report(failed_only: LPXLOPER12) -> LPXLOPER12 {
+    let failed_only = Variant::from(failed_only);
+    let failed_only = !failed_only.is_missing_or_null() && bool::try_from(&failed_only).unwrap_or(false);
+    let mut rows = vec![vec![
+        Variant::from("Function"),
+        Variant::from("Name"),
+        Variant::from("Outcome"),
+        Variant::from("Problems"),
+    ]];
+    for record in records() {
+        let failed = matches!(record.outcome, Outcome::Failed(_));
+        if failed_only && !failed && record.problems.is_empty() {
+            continue;
+        }
+        let outcome = match &record.outcome {
+            Outcome::Registered => "registered".to_string(),
+            Outcome::Failed(result) => format!("failed: {}", result),
+            Outcome::DryRun => "dry run".to_string(),
+        };
+        rows.push(vec![
+            Variant::from(record.xl_name),
+            Variant::from(record.name.as_str()),
+            Variant::from(outcome.as_str()),
+            Variant::from(record.problems.join("; ").as_str()),
+        ]);
+    }
+    LPXLOPER12::from(Variant::from(rows))
+}
+
+inventory::submit! {
+    FunctionRegistration {
+        xl_name: "xl_registration_report",
+        arg_types: "QQ$",
+        arg_names: "failed_only",
+        category: "Add-in Diagnostics",
+        description: "Lists the functions registered by this add-in, and any that Excel refused",
+        arg_infos: &[],
+        help_topic: "",
+    }
+}
@@ -1,13 +1,27 @@
-use crate::entrypoint::excel12;
+use crate::commands;
+use crate::config::{self, Collision, Config};
+use crate::entrypoint::{self, excel12, excel12v};
+use crate::groups;
+use crate::locale;
+use crate::manifest;
+use crate::registration_report::{self, Outcome, RegistrationRecord};
+use crate::scheduler;
 use crate::variant::Variant;
-use crate::xlcall::{xlGetName, xlfRegister};
-use log::debug;
+use crate::xlcall::{
+    xlGetName, xlUDF, xlerrName, xlerrValue, xlfEvaluate, xlfRegister, xlfRegisterId, xlfSetName, xlfUnregister, xltypeMissing, xltypeNum, xltypeStr, Xloper12Value, LPXLOPER12, XLOPER12,
+};
+use log::{debug, info, warn};
+
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Mutex;
+use std::time::Duration;
 
 // Re-export inventory for the macro to use
 pub use inventory;
 
-// Collect all function registrations
+// Collect all function and command registrations
 inventory::collect!(FunctionRegistration);
+inventory::collect!(CommandRegistration);
 
 pub struct ArgInfo {
     pub name: &'static str,
@@ -22,6 +36,16 @@ pub struct FunctionRegistration {
     pub category: &'static str,
     pub description: &'static str,
     pub arg_infos: &'static [ArgInfo],  // Changed from Vec<ArgInfo>
+    /// Opened by "Help on this function": a web page, or `file.chm!topic_id`. Empty for
+    /// the configured `help_url`, if any.
+    pub help_topic: &'static str,
+}
+
+/// A command (macro) exported from the xll. Commands take no arguments, return an
+/// integer and can only be run by Excel, for example from a timer, menu or shortcut key.
+pub struct CommandRegistration {
+    pub xl_name: &'static str,
+    pub shortcut: &'static str, // xlcOnKey key text such as "^+R", or empty for none
 }
 
 /// Allow xlls to register their exported functions with Excel. These functions
@@ -34,8 +58,9 @@ impl Reg {
     /// Creates a registrator. Internally, it finds the name of this dll.
     pub fn new() -> Reg {
         let dll_name = excel12(xlGetName, &mut []);
-        debug_print(&format!("addin loaded from: {}", dll_name));
-
+        info!("addin loaded from: {}", dll_name);
+        // Reg is created in xlAutoOpen, which runs on Excel's main thread
+        crate::progress::remember_main_thread();
         Reg { dll_name }
     }
 
@@ -88,7 +113,7 @@ impl Reg {
         help_text: &str,
         arg_infos: &[ArgInfo],
     ) {
-        let mut opers = vec![
+        let opers = vec![
             self.dll_name.clone(),
             Variant::from(name),
             Variant::from(arg_types),
@@ -100,7 +125,10 @@ impl Reg {
             Variant::missing(), // no help url
             Variant::from(help_text),
         ];
+        self.register_function(name, opers, arg_infos);
+    }
 
+    fn register_function(&self, name: &str, mut opers: Vec<Variant>, arg_infos: &[ArgInfo]) {
         // Add argument descriptions using the structured approach
         for arg_info in arg_infos.iter() {
             // Use a format similar to XLW: just the description
@@ -108,32 +136,609 @@ impl Reg {
         }
 
         let result = excel12(xlfRegister, opers.as_mut_slice());
-        debug_print(&format!("Registered {} with structured args: result = {}", name, result));
+        debug!("Registered {} with structured args: result = {}", name, result);
     }
-    /// Registers all functions that have been collected by the inventory macro.
-    pub fn register_all_functions(&self) {
+
+    /// Adds an exported command to Excel. Commands are registered as macro type 2 so they
+    /// do not appear in the function wizard, but can be run by name, for example by
+    /// xlcOnTime. This function can only be called from within xlAutoOpen.
+    pub fn add_command(&self, name: &str) {
+        let mut opers = vec![
+            self.dll_name.clone(),
+            Variant::from(name),
+            Variant::from("J"),
+            Variant::from(name),
+            Variant::missing(), // no arguments
+            Variant::from(2), // type 2 means command
+        ];
+
+        let result = excel12(xlfRegister, opers.as_mut_slice());
+        debug!("Registered command {}: result = {}", name, result);
+    }
+
+    /// Registers all commands that have been collected by the inventory macro, and binds
+    /// any shortcut keys they declare.
+    pub fn register_all_commands(&self) {
+        for registration in inventory::iter::<CommandRegistration> {
+            self.add_command(registration.xl_name);
+            if !registration.shortcut.is_empty() {
+                commands::bind_key(registration.shortcut, registration.xl_name);
+            }
+        }
+        #[cfg(feature = "async")]
+        crate::async_udf::register_events();
+        #[cfg(feature = "com")]
+        crate::resize::register_events();
+        #[cfg(feature = "events")]
+        crate::workbook_state::watch_workbook_close();
+    }
+
+    /// Registers all functions that have been collected by the inventory macro, with the
+    /// prefix and categories of the [configuration](crate::config). The strings for every
+    /// function are encoded up front into one [`RegistrationBatch`], and Excel is then
+    /// called once per function with arguments pointing into it.
+    ///
+    /// If registration is `deferred` in the configuration, only the core functions are
+    /// registered now. The rest follow in batches from a timer once Excel is up, or one at
+    /// a time when Excel asks for them through xlAutoRegister12.
+    pub fn register_all_functions(&self) -> RegistrationSummary {
+        entrypoint::report_unresolved();
+        let config = config::current();
+        let language = locale::language();
+        let mut summary = RegistrationSummary::default();
+        let mut batch = RegistrationBatch::default();
+        let mut deferred = RegistrationBatch::default();
+        let mut dry_run = RegistrationBatch::default();
+        let mut taken = HashSet::new();
+        registration_report::clear();
         for registration in inventory::iter::<FunctionRegistration> {
-            self.add(
-                registration.xl_name,
-                registration.arg_types,
-                registration.arg_names,
-                registration.category,
-                registration.description,
-                registration.arg_infos  // &[ArgInfo] rather than &Vec<ArgInfo>
-            );
+            if is_disabled(&config, registration) {
+                info!("{} is disabled and not registered", registration.xl_name);
+                summary.disabled += 1;
+                continue;
+            }
+            if config.functions.dry_run {
+                info!(
+                    "dry run: would register {} as {}({}) with type {} in {}",
+                    registration.xl_name,
+                    config.excel_name(registration.xl_name),
+                    registration.arg_names,
+                    registration.arg_types,
+                    config.category(registration.category)
+                );
+                dry_run.push(&config, &language, registration, config.excel_name(registration.xl_name));
+                continue;
+            }
+            let batch = if config.is_deferred(registration.xl_name, registration.category) { &mut deferred } else { &mut batch };
+            for name in free_names(&config, &language, registration.xl_name, &mut taken, &mut summary.collisions) {
+                batch.push(&config, &language, registration, name);
+            }
         }
+        for index in 0..batch.functions.len() {
+            let result = batch.register(&self.dll_name, index);
+            if f64::try_from(&result).is_ok() {
+                summary.registered += 1;
+            } else {
+                summary.failed.push(batch.functions[index].xl_name);
+            }
+        }
+        for function in dry_run.functions {
+            registration_report::record(RegistrationRecord {
+                xl_name: function.xl_name,
+                name: function.name,
+                outcome: Outcome::DryRun,
+                problems: function.problems,
+            });
+        }
+        summary.deferred = deferred.functions.len();
+        if !deferred.functions.is_empty() {
+            let batch_size = config.functions.batch_size.unwrap_or(DEFAULT_BATCH_SIZE).max(1);
+            info!("registering {} functions later, {} at a time", deferred.functions.len(), batch_size);
+            *DEFERRED.lock().unwrap_or_else(|e| e.into_inner()) =
+                Some(Deferred { dll_name: String::from(&self.dll_name), batch: deferred, next: 0 });
+            scheduler::schedule_in(DEFERRED_INTERVAL, move || register_deferred(batch_size));
+        }
+        if !summary.collisions.is_empty() {
+            commands::alert(&format!(
+                "{} function names were already taken:\n\n{}",
+                summary.collisions.len(),
+                summary.collisions.join("\n")
+            ));
+        }
+        summary
+    }
+}
+
+/// How a registration pass went
+#[derive(Debug, Clone, Default)]
+pub struct RegistrationSummary {
+    pub registered: usize,
+    /// Functions Excel would not register, by exported name
+    pub failed: Vec<&'static str>,
+    /// Functions left to register after startup
+    pub deferred: usize,
+    /// Functions disabled by the settings or their group
+    pub disabled: usize,
+    /// What was done about each name that was already taken
+    pub collisions: Vec<String>,
+}
+
+/// Command that unregisters every function, reloads the settings and registers the
+/// functions again, then shows how it went. This picks up changes to `addin.toml`, such
+/// as a new prefix, without restarting Excel.
+#[unsafe(no_mangle)]
+pub extern "system" fn xl_reregister() -> i32 {
+    clear_deferred();
+    let names: Vec<&'static str> = REGISTER_IDS.lock().unwrap_or_else(|e| e.into_inner()).keys().copied().collect();
+    let unregistered = names.into_iter().filter(|name| unregister(name)).count();
+    config::load();
+    let summary = Reg::new().register_all_functions();
+    info!("re-registered functions: {:?}", summary);
+    let mut message = format!(
+        "Unregistered {} functions and registered {}.\n{} failed, {} deferred, {} disabled.",
+        unregistered,
+        summary.registered,
+        summary.failed.len(),
+        summary.deferred,
+        summary.disabled
+    );
+    if !summary.failed.is_empty() {
+        message.push_str(&format!("\n\nFailed: {}", summary.failed.join(", ")));
+    }
+    commands::alert(&message);
+    1
+}
+
+inventory::submit! {
+    CommandRegistration {
+        xl_name: "xl_reregister",
+        shortcut: "",
+    }
+}
+
+/// Registers the functions that match a predicate and are not registered already, after
+/// startup. This can only be called from a command. Returns how many were registered.
+pub(crate) fn register_matching(predicate: impl Fn(&FunctionRegistration) -> bool) -> usize {
+    let config = config::current();
+    let language = locale::language();
+    let dll_name = excel12(xlGetName, &mut []);
+    let mut batch = RegistrationBatch::default();
+    let mut taken: HashSet<String> = REGISTER_IDS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .values()
+        .flatten()
+        .map(|(name, _)| name.to_lowercase())
+        .collect();
+    let mut collisions = Vec::new();
+    for registration in inventory::iter::<FunctionRegistration> {
+        if predicate(registration)
+            && !is_registered(registration.xl_name)
+            && !is_disabled(&config, registration)
+        {
+            for name in free_names(&config, &language, registration.xl_name, &mut taken, &mut collisions) {
+                batch.push(&config, &language, registration, name);
+            }
+        }
+    }
+    for index in 0..batch.functions.len() {
+        batch.register(&dll_name, index);
+    }
+    batch.functions.len()
+}
+
+/// Whether a function is left unregistered by the settings, the override file or its group
+fn is_disabled(config: &Config, registration: &FunctionRegistration) -> bool {
+    let group = groups::group(registration.xl_name);
+    config.is_disabled(registration.xl_name, registration.category, group)
+        || manifest::is_disabled(registration.xl_name)
+        || groups::is_disabled(group)
+}
+
+/// A help topic as Excel wants it. Web pages need `!0` on the end, which is added if it
+/// is missing; help files already give a topic id after the `!`.
+pub fn help_topic(topic: &str) -> String {
+    let is_web = topic.starts_with("http://") || topic.starts_with("https://");
+    if is_web && !topic.ends_with("!0") {
+        format!("{}!0", topic)
+    } else {
+        topic.to_string()
+    }
+}
+
+/// Added to a taken name unless the settings give a suffix
+const DEFAULT_COLLISION_SUFFIX: &str = "_2";
+
+/// The names a function is registered under: its usual name and, if it has a translation
+/// with one, its name in Excel's language. Names that are taken are renamed or left out.
+fn free_names(
+    config: &Config,
+    language: &str,
+    xl_name: &str,
+    taken: &mut HashSet<String>,
+    collisions: &mut Vec<String>,
+) -> Vec<String> {
+    let localized = locale::translation(xl_name, language).map_or("", |translation| translation.name);
+    let mut names = vec![config.excel_name(xl_name)];
+    if !localized.is_empty() && !localized.eq_ignore_ascii_case(&names[0]) {
+        names.push(localized.to_string());
+    }
+    names.into_iter().filter_map(|name| free_name(config, xl_name, name, taken, collisions)).collect()
+}
+
+/// The name a function can be registered under without shadowing another function, or
+/// None if it is to be skipped. `taken` holds the names given out so far, in lower case,
+/// and a message is added to `collisions` for a name that is taken.
+fn free_name(
+    config: &Config,
+    xl_name: &str,
+    name: String,
+    taken: &mut HashSet<String>,
+    collisions: &mut Vec<String>,
+) -> Option<String> {
+    let Some(holder) = name_holder(&name, taken) else {
+        taken.insert(name.to_lowercase());
+        return Some(name);
+    };
+    let renamed = match config.functions.on_collision {
+        Collision::Skip => None,
+        Collision::Rename => {
+            let suffix = config.functions.collision_suffix.as_deref().unwrap_or(DEFAULT_COLLISION_SUFFIX);
+            Some(format!("{}{}", name, suffix)).filter(|renamed| name_holder(renamed, taken).is_none())
+        }
+    };
+    let message = match &renamed {
+        Some(renamed) => format!("{} is {}, so {} is registered as {}", name, holder, xl_name, renamed),
+        None => format!("{} is {}, so {} is not registered", name, holder, xl_name),
+    };
+    warn!("{}", message);
+    collisions.push(message);
+    if let Some(renamed) = &renamed {
+        taken.insert(renamed.to_lowercase());
+    }
+    renamed
+}
+
+/// Who has a name already, if anyone
+fn name_holder(name: &str, taken: &HashSet<String>) -> Option<&'static str> {
+    if taken.contains(&name.to_lowercase()) {
+        Some("used by another function of this add-in")
+    } else if is_defined_elsewhere(name) {
+        Some("already registered by another add-in")
+    } else {
+        None
+    }
+}
+
+/// Whether Excel knows a name that is not one of ours. The name of a registered function
+/// evaluates to its register id, and an unknown name to #NAME?.
+fn is_defined_elsewhere(name: &str) -> bool {
+    let value = excel12(xlfEvaluate, &mut [Variant::from(name)]);
+    let Ok(id) = f64::try_from(&value) else {
+        return false;
+    };
+    !REGISTER_IDS.lock().unwrap_or_else(|e| e.into_inner()).values().flatten().any(|(_, own)| *own == id)
+}
+
+/// Whether a function is registered with Excel at the moment, under any name
+pub fn is_registered(xl_name: &str) -> bool {
+    REGISTER_IDS.lock().unwrap_or_else(|e| e.into_inner()).contains_key(xl_name)
+}
+
+/// The names a function is registered under in Excel, which may include a localized one
+pub fn registered_names(xl_name: &str) -> Vec<String> {
+    let ids = REGISTER_IDS.lock().unwrap_or_else(|e| e.into_inner());
+    ids.get(xl_name).map_or_else(Vec::new, |names| names.iter().map(|(name, _)| name.clone()).collect())
+}
+
+/// Every registered function by exported name, with the names it is registered under
+pub fn registered_functions() -> Vec<(&'static str, Vec<String>)> {
+    let ids = REGISTER_IDS.lock().unwrap_or_else(|e| e.into_inner());
+    ids.iter().map(|(xl_name, names)| (*xl_name, names.iter().map(|(name, _)| name.clone()).collect())).collect()
+}
+
+/// The register id Excel gave a function under its main name, or None if it is not
+/// registered
+pub fn register_id(xl_name: &str) -> Option<f64> {
+    let ids = REGISTER_IDS.lock().unwrap_or_else(|e| e.into_inner());
+    ids.get(xl_name).and_then(|names| names.first()).map(|(_, id)| *id)
+}
+
+/// Calls one of the add-in's own worksheet functions through Excel, by exported name, as
+/// a formula would. Excel converts the arguments as the function's type string says, and
+/// the function sees Excel as its caller. This can only be called from a command or a
+/// macro-type function.
+///
+/// Functions registered some other way than by the inventory, such as with [`Reg::add`],
+/// are looked up with xlfRegisterId. Returns #NAME? if the function is not registered.
+pub fn call_own_function(xl_name: &str, args: &[Variant]) -> Variant {
+    let id = match register_id(xl_name) {
+        Some(id) => id,
+        None => {
+            let dll_name = excel12(xlGetName, &mut []);
+            let id = excel12(xlfRegisterId, &mut [dll_name, Variant::from(xl_name)]);
+            match f64::try_from(&id) {
+                Ok(id) => id,
+                Err(_) => {
+                    warn!("{} is not registered, so cannot be called", xl_name);
+                    return Variant::from_err(xlerrName);
+                }
+            }
+        }
+    };
+    let mut opers = Vec::with_capacity(args.len() + 1);
+    opers.push(Variant::from(id));
+    opers.extend(args.iter().cloned());
+    excel12(xlUDF, &mut opers)
+}
+
+fn is_registered_as(xl_name: &str, name: &str) -> bool {
+    let ids = REGISTER_IDS.lock().unwrap_or_else(|e| e.into_inner());
+    ids.get(xl_name).is_some_and(|names| names.iter().any(|(registered, _)| registered == name))
+}
+
+/// Removes a registered function from Excel under all its names, so it can no longer be
+/// called and no longer shows in the Function Wizard. This can only be called from a
+/// command. Returns false if the function was not registered.
+pub(crate) fn unregister(xl_name: &str) -> bool {
+    let Some(names) = REGISTER_IDS.lock().unwrap_or_else(|e| e.into_inner()).remove(xl_name) else {
+        return false;
+    };
+    for (name, id) in names {
+        // Excel keeps the name of a worksheet function defined until it is deleted
+        let result = excel12(xlfSetName, &mut [Variant::from(name.as_str())]);
+        debug!("SetName({}): result = {}", name, result);
+        let result = excel12(xlfUnregister, &mut [Variant::from(id)]);
+        debug!("Unregistered {} as {}: result = {}", xl_name, name, result);
+    }
+    true
+}
+
+/// The names in Excel and register ids of each registered function, by exported name
+static REGISTER_IDS: Mutex<BTreeMap<&'static str, Vec<(String, f64)>>> = Mutex::new(BTreeMap::new());
+
+/// How many deferred functions are registered at a time, unless the configuration says
+const DEFAULT_BATCH_SIZE: usize = 50;
+/// The pause between batches of deferred functions, which leaves Excel free in between
+const DEFERRED_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Functions still to be registered after startup
+struct Deferred {
+    dll_name: String,
+    batch: RegistrationBatch,
+    /// The first function of the next batch
+    next: usize,
+}
+
+static DEFERRED: Mutex<Option<Deferred>> = Mutex::new(None);
+
+/// Registers the next batch of deferred functions, and schedules the one after, from a timer
+fn register_deferred(batch_size: usize) {
+    let mut deferred = DEFERRED.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(state) = deferred.as_mut() else {
+        return;
+    };
+    let dll_name = Variant::from(state.dll_name.as_str());
+    let end = state.batch.functions.len().min(state.next + batch_size);
+    for index in state.next..end {
+        let function = &state.batch.functions[index];
+        if !function.registered
+            && !is_registered_as(function.xl_name, &function.name)
+            && !groups::is_disabled(groups::group(function.xl_name))
+        {
+            state.batch.register(&dll_name, index);
+        }
+    }
+    state.next = end;
+    if end < state.batch.functions.len() {
+        drop(deferred);
+        scheduler::schedule_in(DEFERRED_INTERVAL, move || register_deferred(batch_size));
+    } else {
+        info!("all {} deferred functions are registered", end);
+        *deferred = None;
+    }
+}
+
+/// Registers a deferred function straight away, when Excel asks for it by its Excel name
+/// through xlAutoRegister12. Returns the result of xlfRegister, or #VALUE! if no such
+/// function is waiting.
+pub fn register_on_demand(name: &str) -> Variant {
+    let mut deferred = DEFERRED.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(state) = deferred.as_mut() else {
+        return Variant::from_err(xlerrValue);
+    };
+    let Some(index) = state.batch.functions.iter().position(|function| function.name.eq_ignore_ascii_case(name)) else {
+        return Variant::from_err(xlerrValue);
+    };
+    let function = &state.batch.functions[index];
+    if function.registered || groups::is_disabled(groups::group(function.xl_name)) {
+        return Variant::from_err(xlerrValue);
+    }
+    info!("{} registered on first use", name);
+    let dll_name = Variant::from(state.dll_name.as_str());
+    state.batch.register(&dll_name, index)
+}
+
+/// Forgets functions still waiting to be registered. This is called from xlAutoClose.
+pub fn clear_deferred() {
+    DEFERRED.lock().unwrap_or_else(|e| e.into_inner()).take();
+}
+
+/// The strings needed to register a set of functions, encoded once into one buffer of
+/// length-prefixed UTF-16, which is what Excel expects
+#[derive(Default)]
+struct RegistrationBatch {
+    text: Vec<u16>,
+    functions: Vec<BatchedFunction>,
+}
+
+struct BatchedFunction {
+    xl_name: &'static str,
+    /// The name in Excel
+    name: String,
+    registered: bool,
+    /// What Excel would cut short or reject
+    problems: Vec<String>,
+    /// Offsets in the text of the exported name, type, Excel name, argument names,
+    /// category, help topic, description and each argument description
+    strings: Vec<usize>,
+}
+
+impl RegistrationBatch {
+    /// Adds a function under the given name, with its category and text taken from any
+    /// [override](crate::manifest), the configuration and any translation into Excel's
+    /// language
+    fn push(&mut self, config: &Config, language: &str, registration: &FunctionRegistration, name: String) {
+        let overridden = manifest::override_for(registration.xl_name).unwrap_or_default();
+        let translation = locale::translation(registration.xl_name, language);
+        let category = match (&overridden.category, translation) {
+            (Some(category), _) => category.as_str(),
+            (None, Some(translation)) if !translation.category.is_empty() => translation.category,
+            _ => registration.category,
+        };
+        let category = config.category(category);
+        let description = match (&overridden.description, translation) {
+            (Some(description), _) => description.as_str(),
+            (None, Some(translation)) if !translation.description.is_empty() => translation.description,
+            _ => registration.description,
+        };
+        let arg_descriptions: Vec<&str> = match (&overridden.arg_descriptions, translation) {
+            (Some(arg_descriptions), _) if arg_descriptions.len() == registration.arg_infos.len() => {
+                arg_descriptions.iter().map(String::as_str).collect()
+            }
+            (_, Some(translation)) if translation.arg_descriptions.len() == registration.arg_infos.len() => {
+                translation.arg_descriptions.to_vec()
+            }
+            (_, Some(translation)) if !translation.arg_descriptions.is_empty() => {
+                warn!(
+                    "the {} translation of {} has {} argument descriptions rather than {}",
+                    translation.language,
+                    registration.xl_name,
+                    translation.arg_descriptions.len(),
+                    registration.arg_infos.len()
+                );
+                registration.arg_infos.iter().map(|arg_info| arg_info.description).collect()
+            }
+            _ => registration.arg_infos.iter().map(|arg_info| arg_info.description).collect(),
+        };
+        let help_topic = match (&overridden.help_topic, registration.help_topic) {
+            (Some(topic), _) => help_topic(topic),
+            (None, "") => config.help_topic(&name).unwrap_or_default(),
+            (None, topic) => help_topic(topic),
+        };
+        let fixed =
+            [registration.xl_name, registration.arg_types, &name, registration.arg_names, &category, &help_topic, description];
+        let problems = registration_report::check(registration, &name, &fixed, &arg_descriptions);
+        let strings = fixed.iter().chain(&arg_descriptions).map(|text| self.add_text(text)).collect();
+        self.functions.push(BatchedFunction { xl_name: registration.xl_name, name, registered: false, problems, strings });
+    }
+
+    /// Calls xlfRegister for one function in the batch
+    fn register(&mut self, dll_name: &Variant, index: usize) -> Variant {
+        let mut dll_name = dll_name.clone();
+        let mut macro_type = XLOPER12 { xltype: xltypeNum, val: Xloper12Value { num: 1.0 } }; // useable anywhere
+        let mut missing = XLOPER12 { xltype: xltypeMissing, val: Xloper12Value { num: 0.0 } };
+        let function = &mut self.functions[index];
+        // Excel only reads the strings, so they can point straight into the buffer
+        let mut strings: Vec<XLOPER12> = function
+            .strings
+            .iter()
+            .map(|&offset| XLOPER12 {
+                xltype: xltypeStr,
+                val: Xloper12Value { str: self.text[offset..].as_ptr() as *mut u16 },
+            })
+            .collect();
+        let [procedure, arg_types, name, arg_names, category, help_topic, description, arg_descriptions @ ..] =
+            strings.as_mut_slice()
+        else {
+            return Variant::from_err(xlerrValue);
+        };
+        let help_topic = if self.text[function.strings[5]] == 0 { &mut missing as LPXLOPER12 } else { help_topic as LPXLOPER12 };
+        let mut args: Vec<LPXLOPER12> = vec![
+            dll_name.as_mut_xloper() as LPXLOPER12,
+            procedure as LPXLOPER12,
+            arg_types as LPXLOPER12,
+            name as LPXLOPER12,
+            arg_names as LPXLOPER12,
+            &mut macro_type as LPXLOPER12,
+            category as LPXLOPER12,
+            &mut missing as LPXLOPER12, // no shortcut
+            help_topic,
+            description as LPXLOPER12,
+        ];
+        args.extend(arg_descriptions.iter_mut().map(|arg| arg as LPXLOPER12));
+        let mut result = Variant::default();
+        let status = excel12v(xlfRegister as i32, result.as_mut_xloper(), &args);
+        debug!("Registered {}: status = {}, result = {}", function.xl_name, status, result);
+        function.registered = true;
+        let outcome = match f64::try_from(&result) {
+            Ok(id) => {
+                let mut ids = REGISTER_IDS.lock().unwrap_or_else(|e| e.into_inner());
+                ids.entry(function.xl_name).or_default().push((function.name.clone(), id));
+                Outcome::Registered
+            }
+            Err(_) => Outcome::Failed(format!("status {}, result {}", status, result)),
+        };
+        registration_report::record(RegistrationRecord {
+            xl_name: function.xl_name,
+            name: function.name.clone(),
+            outcome,
+            problems: function.problems.clone(),
+        });
+        result
+    }
+
+    fn add_text(&mut self, text: &str) -> usize {
+        let offset = self.text.len();
+        self.text.push(0);
+        self.text.extend(text.encode_utf16().take(255));
+        self.text[offset] = (self.text.len() - offset - 1) as u16;
+        offset
     }
-    
 }
 
 impl Default for Reg {
     fn default() -> Reg {
         let dll_name = excel12(xlGetName, &mut []);
-        debug_print(&format!("addin loaded from: {}", dll_name));
+        info!("addin loaded from: {}", dll_name);
+        // Reg is created in xlAutoOpen, which runs on Excel's main thread
+        crate::progress::remember_main_thread();
         Reg { dll_name }
     }
 }
 
-pub(crate) fn debug_print(message: &str) {
-    debug!("{}", message);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn web_help_topics_end_in_bang_zero() {
+        assert_eq!(help_topic("https://docs.acme.com/npv"), "https://docs.acme.com/npv!0");
+        assert_eq!(help_topic("https://docs.acme.com/npv!0"), "https://docs.acme.com/npv!0");
+        assert_eq!(help_topic(r"C:\Help\acme.chm!1001"), r"C:\Help\acme.chm!1001");
+
+        let mut config = Config::default();
+        assert_eq!(config.help_topic("acme_npv"), None);
+        config.functions.help_url = Some("http://docs/{name}".to_string());
+        assert_eq!(config.help_topic("acme_npv").as_deref(), Some("http://docs/acme_npv!0"));
+    }
+
+    #[test]
+    fn taken_names_are_renamed_or_skipped() {
+        // There is no Excel here, so only names taken by this add-in collide
+        let mut config = Config::default();
+        let mut taken = HashSet::new();
+        let mut collisions = Vec::new();
+        let mut free = |config: &Config, name: &str| free_name(config, "xl_npv", name.to_string(), &mut taken, &mut collisions);
+        assert_eq!(free(&config, "acme_npv").as_deref(), Some("acme_npv"));
+        assert_eq!(free(&config, "ACME_NPV").as_deref(), Some("ACME_NPV_2"));
+        // The renamed name is taken as well
+        assert_eq!(free(&config, "acme_npv"), None);
+
+        config.functions.collision_suffix = Some("_x".to_string());
+        assert_eq!(free(&config, "acme_npv").as_deref(), Some("acme_npv_x"));
+        config.functions.on_collision = Collision::Skip;
+        assert_eq!(free(&config, "acme_npv"), None);
+        assert_eq!(collisions.len(), 4);
+    }
 }
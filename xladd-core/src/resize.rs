@@ -0,0 +1,152 @@
+//! Array formulas that grow or shrink to fit their result, for versions of Excel without
+//! dynamic arrays. There, a function returning a 10x3 array from a formula entered in one
+//! cell shows only the top-left value, and the user has to select the right range and
+//! enter the formula with Ctrl+Shift+Enter. A function marked `#[xl_func(resize)]`, or one
+//! passing its result through [`resize`], notes when the calling range is the wrong size;
+//! once the calculation ends, a hidden command re-enters the formula as an array formula
+//! over a range of the right size. Excel versions with dynamic arrays spill the result
+//! themselves, and are left alone.
+//!
+//! The formula is re-entered through the Application object, as the C API cannot read
+//! array formulas.
+
+use crate::caller::{empty_area, CellArea};
+use crate::com::{Application, Range};
+use crate::entrypoint::excel12;
+use crate::registrator::CommandRegistration;
+use crate::variant::Variant;
+use crate::xlcall::{xlEventRegister, xlSheetNm, LPXLOPER12};
+use log::{debug, warn};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// Event id passed to xlEventRegister for the end of a recalculation
+const XLEVENT_CALCULATION_ENDED: i32 = 1;
+const RESIZE_COMMAND: &str = "xl_resize_pending";
+
+/// Whether Excel spills arrays itself: not yet known, no or yes
+const UNKNOWN: u8 = 0;
+const NO_DYNAMIC_ARRAYS: u8 = 1;
+const DYNAMIC_ARRAYS: u8 = 2;
+static DYNAMIC: AtomicU8 = AtomicU8::new(UNKNOWN);
+
+/// Calling ranges to resize, with the rows and columns they should have
+static PENDING: Mutex<BTreeMap<CellArea, (usize, usize)>> = Mutex::new(BTreeMap::new());
+/// Resizes that failed, so they are not tried on every calculation
+static FAILED: Mutex<BTreeSet<(CellArea, usize, usize)>> = Mutex::new(BTreeSet::new());
+
+/// Passes a worksheet function's result through, noting whether its calling range needs
+/// to change size to show all of it
+pub fn resize(result: Variant) -> Variant {
+    if DYNAMIC.load(Ordering::Relaxed) == DYNAMIC_ARRAYS {
+        return result;
+    }
+    let Some(caller) = CellArea::calling_cells() else {
+        return result;
+    };
+    let (columns, rows) = result.dim();
+    let caller_rows = (caller.last_row - caller.first_row + 1) as usize;
+    let caller_columns = (caller.last_column - caller.first_column + 1) as usize;
+    if rows == 0 || columns == 0 || (rows, columns) == (caller_rows, caller_columns) {
+        return result;
+    }
+    if FAILED.lock().unwrap_or_else(|e| e.into_inner()).contains(&(caller, rows, columns)) {
+        return result;
+    }
+    PENDING.lock().unwrap_or_else(|e| e.into_inner()).insert(caller, (rows, columns));
+    result
+}
+
+/// Asks Excel to run the resize command whenever a calculation ends
+pub(crate) fn register_events() {
+    let result = excel12(
+        xlEventRegister,
+        &mut [Variant::from(RESIZE_COMMAND), Variant::from(XLEVENT_CALCULATION_ENDED)],
+    );
+    debug!("EventRegister({}): result = {}", RESIZE_COMMAND, result);
+}
+
+/// Command run by Excel at the end of each calculation, which re-enters the formulas
+/// noted by [`resize`] over ranges of the right size
+#[unsafe(no_mangle)]
+pub extern "system" fn xl_resize_pending() -> i32 {
+    let pending = std::mem::take(&mut *PENDING.lock().unwrap_or_else(|e| e.into_inner()));
+    if pending.is_empty() {
+        return 1;
+    }
+    let Ok(application) = Application::get() else {
+        warn!("cannot resize array formulas without the Application object");
+        return 1;
+    };
+    if DYNAMIC.load(Ordering::Relaxed) == UNKNOWN {
+        let spills = application
+            .dispatch()
+            .call("Evaluate", vec!["=ROWS(SEQUENCE(2))".into()])
+            .ok()
+            .and_then(|rows| rows.to_f64())
+            == Some(2.0);
+        DYNAMIC.store(if spills { DYNAMIC_ARRAYS } else { NO_DYNAMIC_ARRAYS }, Ordering::Relaxed);
+        if spills {
+            debug!("Excel has dynamic arrays, so array formulas are not resized");
+            return 1;
+        }
+    }
+    for (caller, (rows, columns)) in pending {
+        if let Err(e) = resize_range(&application, caller, rows, columns) {
+            warn!("cannot resize the array formula in {} to {}x{}: {}", caller.address(), rows, columns, e);
+            FAILED.lock().unwrap_or_else(|e| e.into_inner()).insert((caller, rows, columns));
+        }
+    }
+    1
+}
+
+inventory::submit! {
+    CommandRegistration {
+        xl_name: RESIZE_COMMAND,
+        shortcut: "",
+    }
+}
+
+/// Enters the formula of a calling range again over `rows` by `columns` cells from its
+/// top-left cell
+fn resize_range(application: &Application, caller: CellArea, rows: usize, columns: usize) -> windows::core::Result<()> {
+    let mut storage = empty_area();
+    let mut reference = caller.to_reference(&mut storage);
+    // The sheet comes back as "[Book1.xlsx]Sheet1"
+    let sheet = String::from(&excel12(xlSheetNm, &mut [Variant::from(&mut reference as LPXLOPER12)]));
+    let (workbook, sheet) = sheet.trim_start_matches('[').split_once(']').unwrap_or(("", &sheet));
+    let sheet = application.worksheet(workbook, sheet)?;
+    let old = Range::on_sheet(&sheet, &a1_area(caller.first_row, caller.first_column, caller.last_row, caller.last_column))?;
+    let formula = old.formula_array()?;
+    if !formula.starts_with('=') {
+        return Ok(()); // the formula has been replaced by a value since
+    }
+    let new = old.resize(rows, columns)?;
+    old.clear_contents()?;
+    if let Err(e) = new.set_formula_array(&formula) {
+        // Put the formula back where it was, e.g. when the new range would overwrite data
+        let _ = old.set_formula_array(&formula);
+        return Err(e);
+    }
+    debug!("resized the array formula {} in {} to {}x{}", formula, caller.address(), rows, columns);
+    Ok(())
+}
+
+/// An A1-style address such as "B2:D10", from zero-based rows and columns
+fn a1_area(first_row: i32, first_column: i32, last_row: i32, last_column: i32) -> String {
+    format!("{}{}:{}{}", column_letters(first_column), first_row + 1, column_letters(last_column), last_row + 1)
+}
+
+fn column_letters(column: i32) -> String {
+    let mut letters = Vec::new();
+    let mut n = column + 1;
+    while n > 0 {
+        let remainder = (n - 1) % 26;
+        letters.push(b'A' + remainder as u8);
+        n = (n - 1) / 26;
+    }
+    letters.reverse();
+    String::from_utf8(letters).unwrap_or_default()
+}
@@ -0,0 +1,235 @@
+//! Ribbon customization. Excel only asks COM add-ins for ribbon XML, so this module
+//! implements a small in-process COM add-in (IDTExtensibility2 plus IRibbonExtensibility)
+//! served from the xll itself, and connects it to Excel through the COMAddIns collection.
+//! Only available with the `ribbon` feature.
+//!
+//! Buttons run registered commands by setting `onAction="RunTagMacro"` and putting the
+//! command name in the `tag` attribute:
+//!
+//! <button id="reprice" label="Re-price all" onAction="RunTagMacro" tag="xl_reprice_all"/>
+//!
+//! # Example
+//!
+//! Call from xlAutoOpen, after the commands have been registered:
+//!
+//! Ribbon::new("MyAddin.Ribbon", "{6D2F0C0B-8E57-4E0D-9C67-3C4A0E35C1A2}", RIBBON_XML).install();
+
+// The method names are fixed by the COM interface definitions
+#![allow(non_snake_case)]
+
+use crate::com::{excel_application, ComVariant, Dispatch};
+use crate::com_server::{
+    delete_registry_key, parse_clsid, register_class, register_server, set_registry_dword,
+    set_registry_value, unregister_class, unregister_server,
+};
+use crate::registrator::CommandRegistration;
+use log::warn;
+
+use std::ffi::c_void;
+use std::sync::Mutex;
+use windows::Win32::Foundation::{
+    CLASS_E_CLASSNOTAVAILABLE, DISP_E_MEMBERNOTFOUND, DISP_E_UNKNOWNNAME, E_POINTER,
+};
+use windows::Win32::System::Com::{
+    IDispatch, IDispatch_Impl, IDispatch_Vtbl, ITypeInfo, DISPATCH_FLAGS, DISPPARAMS, EXCEPINFO, SAFEARRAY,
+};
+use windows::Win32::System::Variant::VARIANT;
+use windows::core::{implement, interface, BSTR, GUID, HRESULT, PCWSTR, Result};
+
+const DISPID_RUN_TAG_MACRO: i32 = 1;
+const DISPID_ON_LOAD: i32 = 2;
+
+#[interface("B65AD801-ABAF-11D0-BB8B-00A0C90F2744")]
+unsafe trait IDTExtensibility2: IDispatch {
+    fn OnConnection(&self, application: *mut c_void, mode: i32, addin: *mut c_void, custom: *mut *mut SAFEARRAY) -> HRESULT;
+    fn OnDisconnection(&self, mode: i32, custom: *mut *mut SAFEARRAY) -> HRESULT;
+    fn OnAddInsUpdate(&self, custom: *mut *mut SAFEARRAY) -> HRESULT;
+    fn OnStartupComplete(&self, custom: *mut *mut SAFEARRAY) -> HRESULT;
+    fn OnBeginShutdown(&self, custom: *mut *mut SAFEARRAY) -> HRESULT;
+}
+
+#[interface("000C0396-0000-0000-C000-000000000046")]
+unsafe trait IRibbonExtensibility: IDispatch {
+    fn GetCustomUI(&self, ribbon_id: BSTR, xml: *mut BSTR) -> HRESULT;
+}
+
+struct RibbonState {
+    prog_id: String,
+    clsid: GUID,
+    xml: String,
+    ribbon_ui: Option<Dispatch>,
+}
+
+// The COM objects are only ever touched on Excel's main thread
+unsafe impl Send for RibbonState {}
+
+static RIBBON: Mutex<Option<RibbonState>> = Mutex::new(None);
+
+fn with_ribbon<T>(f: impl FnOnce(&mut RibbonState) -> T) -> Option<T> {
+    RIBBON.lock().unwrap_or_else(|e| e.into_inner()).as_mut().map(f)
+}
+
+/// A custom ribbon, defined by customUI XML supplied by the add-in author
+pub struct Ribbon {
+    prog_id: String,
+    clsid: GUID,
+    xml: String,
+}
+
+impl Ribbon {
+    /// Creates a ribbon. The ProgID and CLSID identify the COM add-in that carries the
+    /// ribbon, and must be unique to your add-in. Generate a fresh GUID for the CLSID.
+    pub fn new(prog_id: &str, clsid: &str, xml: &str) -> Ribbon {
+        Ribbon { prog_id: prog_id.to_string(), clsid: parse_clsid(clsid), xml: xml.to_string() }
+    }
+
+    /// Loads the ribbon into Excel. The COM add-in is registered for the current user only
+    /// while Excel connects to it, and the registry entries are removed again afterwards.
+    pub fn install(self) -> Result<()> {
+        let prog_id = self.prog_id.clone();
+        let clsid = self.clsid;
+        register_class(clsid, || RibbonAddin.into());
+        *RIBBON.lock().unwrap_or_else(|e| e.into_inner()) = Some(RibbonState {
+            prog_id: self.prog_id,
+            clsid: self.clsid,
+            xml: self.xml,
+            ribbon_ui: None,
+        });
+
+        let addin_key = format!("Software\\Microsoft\\Office\\Excel\\Addins\\{}", prog_id);
+        register_server(&prog_id, clsid)?;
+        set_registry_value(&addin_key, "FriendlyName", &prog_id)?;
+        set_registry_dword(&addin_key, "LoadBehavior", 0)?;
+
+        let connected = connect_addin(&prog_id, true);
+
+        unregister_server(&prog_id, clsid);
+        delete_registry_key(&addin_key);
+        connected
+    }
+}
+
+/// Asks Excel to fetch the ribbon callbacks again, e.g. after labels or enabled state change
+pub fn invalidate_ribbon() {
+    if let Some(Some(ribbon_ui)) = with_ribbon(|state| state.ribbon_ui.clone())
+        && let Err(e) = ribbon_ui.call("Invalidate", Vec::new())
+    {
+        warn!("ribbon invalidate failed: {}", e);
+    }
+}
+
+/// Disconnects the ribbon add-in. This is called from xlAutoClose.
+pub fn uninstall_ribbon() {
+    if let Some((prog_id, clsid)) = with_ribbon(|state| (state.prog_id.clone(), state.clsid)) {
+        let _ = connect_addin(&prog_id, false);
+        unregister_class(clsid);
+        *RIBBON.lock().unwrap_or_else(|e| e.into_inner()) = None;
+    }
+}
+
+fn connect_addin(prog_id: &str, connect: bool) -> Result<()> {
+    let com_addins = excel_application()?.get_object("COMAddIns")?;
+    if connect {
+        com_addins.call("Update", Vec::new())?;
+    }
+    let addin = com_addins
+        .get_with("Item", vec![ComVariant::from(prog_id)])?
+        .to_dispatch()
+        .ok_or_else(|| windows::core::Error::new(CLASS_E_CLASSNOTAVAILABLE, "COM add-in not found"))?;
+    addin.put("Connect", ComVariant::from(connect))
+}
+
+/// Runs a registered command named by a ribbon control's tag. This goes through
+/// Application.Run, so the command runs in a proper macro context.
+fn run_tag_macro(control: &ComVariant) -> Result<()> {
+    let tag = control
+        .to_dispatch()
+        .and_then(|control| control.get("Tag").ok())
+        .and_then(|tag| tag.to_string_value())
+        .unwrap_or_default();
+
+    if !inventory::iter::<CommandRegistration>.into_iter().any(|cmd| cmd.xl_name == tag) {
+        warn!("ribbon tag '{}' is not a registered command", tag);
+        return Err(windows::core::Error::new(DISP_E_MEMBERNOTFOUND, format!("unknown command {}", tag)));
+    }
+    excel_application()?.call("Run", vec![ComVariant::from(tag.as_str())]).map(|_| ())
+}
+
+#[implement(IDTExtensibility2, IRibbonExtensibility)]
+struct RibbonAddin;
+
+impl IDispatch_Impl for RibbonAddin_Impl {
+    fn GetTypeInfoCount(&self) -> Result<u32> {
+        Ok(0)
+    }
+
+    fn GetTypeInfo(&self, _itinfo: u32, _lcid: u32) -> Result<ITypeInfo> {
+        Err(DISP_E_UNKNOWNNAME.into())
+    }
+
+    fn GetIDsOfNames(&self, _riid: *const GUID, names: *const PCWSTR, count: u32, _lcid: u32, dispids: *mut i32) -> Result<()> {
+        if names.is_null() || dispids.is_null() || count == 0 {
+            return Err(E_POINTER.into());
+        }
+        let name = unsafe { (*names).to_string() }.unwrap_or_default();
+        let dispid = match name.as_str() {
+            "RunTagMacro" => DISPID_RUN_TAG_MACRO,
+            "OnLoad" => DISPID_ON_LOAD,
+            _ => return Err(DISP_E_UNKNOWNNAME.into()),
+        };
+        unsafe { *dispids = dispid };
+        Ok(())
+    }
+
+    fn Invoke(&self, dispid: i32, _riid: *const GUID, _lcid: u32, _flags: DISPATCH_FLAGS, params: *const DISPPARAMS,
+        _result: *mut VARIANT, _excepinfo: *mut EXCEPINFO, _argerr: *mut u32) -> Result<()> {
+        if params.is_null() {
+            return Err(E_POINTER.into());
+        }
+        let params = unsafe { &*params };
+        // Arguments arrive in reverse order, so the control (or ribbon) is the last one
+        let first_arg = if params.cArgs == 0 {
+            ComVariant::empty()
+        } else {
+            unsafe { ComVariant::from_borrowed(params.rgvarg.add(params.cArgs as usize - 1)) }
+        };
+
+        match dispid {
+            DISPID_RUN_TAG_MACRO => run_tag_macro(&first_arg),
+            DISPID_ON_LOAD => {
+                with_ribbon(|state| state.ribbon_ui = first_arg.to_dispatch());
+                Ok(())
+            }
+            _ => Err(DISP_E_MEMBERNOTFOUND.into()),
+        }
+    }
+}
+
+impl IDTExtensibility2_Impl for RibbonAddin_Impl {
+    unsafe fn OnConnection(&self, _application: *mut c_void, _mode: i32, _addin: *mut c_void, _custom: *mut *mut SAFEARRAY) -> HRESULT {
+        HRESULT(0)
+    }
+    unsafe fn OnDisconnection(&self, _mode: i32, _custom: *mut *mut SAFEARRAY) -> HRESULT {
+        HRESULT(0)
+    }
+    unsafe fn OnAddInsUpdate(&self, _custom: *mut *mut SAFEARRAY) -> HRESULT {
+        HRESULT(0)
+    }
+    unsafe fn OnStartupComplete(&self, _custom: *mut *mut SAFEARRAY) -> HRESULT {
+        HRESULT(0)
+    }
+    unsafe fn OnBeginShutdown(&self, _custom: *mut *mut SAFEARRAY) -> HRESULT {
+        HRESULT(0)
+    }
+}
+
+impl IRibbonExtensibility_Impl for RibbonAddin_Impl {
+    unsafe fn GetCustomUI(&self, _ribbon_id: BSTR, xml: *mut BSTR) -> HRESULT {
+        if xml.is_null() {
+            return E_POINTER;
+        }
+        let ribbon_xml = with_ribbon(|state| state.xml.clone()).unwrap_or_default();
+        unsafe { xml.write(BSTR::from(ribbon_xml)) };
+        HRESULT(0)
+    }
+}
@@ -0,0 +1,414 @@
+//! Real-time data. This module implements an in-process IRtdServer served from the xll,
+//! so cells can show ticking values (prices, job status, clocks) without volatile
+//! functions or polling. Only available with the `rtd` feature.
+//!
+//! Topics are usually declared with `#[xl_rtd]`, which also registers a worksheet function
+//! that calls RTD, so users write `=xl_price("AAPL")` rather than `=RTD(...)`:
+//!
+//! #[xl_rtd]
+//! fn price(symbol: String, sink: TopicSink) {
+//!     feed::subscribe(&symbol, move |p| sink.update(p));
+//! }
+//!
+//! #[xl_rtd]
+//! fn clock(seconds: u64) -> impl Iterator<Item = String> + Send {
+//!     std::iter::repeat_with(move || { sleep(Duration::from_secs(seconds)); now() })
+//! }
+//!
+//! The server must be installed from xlAutoOpen, with a ProgID and CLSID unique to the add-in:
+//!
+//! RtdServer::new("MyAddin.Rtd", "{0F6A1C51-3B0E-4F8E-9D55-2C8E5B0D7A11}").install();
+//!
+//! Values may be published from any thread. They are collected and handed to Excel on its
+//! main thread, which then fetches them at its throttle interval (two seconds by default).
+
+// The method names are fixed by the COM interface definitions
+#![allow(non_snake_case)]
+
+use crate::com::{pack_table, unpack_vector, ComVariant};
+use crate::com_server::{parse_clsid, register_class, register_server, unregister_class, unregister_server};
+use crate::entrypoint::excel12;
+use crate::variant::Variant;
+use crate::xlcall::{xlerrNA, xlfRtd};
+use log::warn;
+
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::c_void;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use windows::Win32::Foundation::{DISP_E_UNKNOWNNAME, E_NOTIMPL, E_POINTER, HWND, VARIANT_BOOL, VARIANT_TRUE};
+use windows::Win32::System::Com::{
+    IDispatch, IDispatch_Impl, IDispatch_Vtbl, ITypeInfo, DISPATCH_FLAGS, DISPPARAMS, EXCEPINFO, SAFEARRAY,
+};
+use windows::Win32::System::Variant::VARIANT;
+use windows::Win32::UI::WindowsAndMessaging::{KillTimer, SetTimer};
+use windows::core::{implement, interface, Interface, GUID, HRESULT, PCWSTR, Result};
+
+/// How often the main thread checks for published values, in milliseconds
+const NOTIFY_INTERVAL_MS: u32 = 100;
+
+#[interface("A43788C1-D91B-11D3-8F39-00C04F3651B8")]
+unsafe trait IRTDUpdateEvent: IDispatch {
+    fn UpdateNotify(&self) -> HRESULT;
+    fn get_HeartbeatInterval(&self, value: *mut i32) -> HRESULT;
+    fn put_HeartbeatInterval(&self, value: i32) -> HRESULT;
+    fn Disconnect(&self) -> HRESULT;
+}
+
+#[interface("EC0E6191-DB51-11D3-8F3E-00C04F3651B8")]
+unsafe trait IRtdServer: IDispatch {
+    fn ServerStart(&self, callback: *mut c_void, result: *mut i32) -> HRESULT;
+    fn ConnectData(&self, topic_id: i32, strings: *mut *mut SAFEARRAY, get_new_values: *mut VARIANT_BOOL, value: *mut VARIANT) -> HRESULT;
+    fn RefreshData(&self, topic_count: *mut i32, data: *mut *mut SAFEARRAY) -> HRESULT;
+    fn DisconnectData(&self, topic_id: i32) -> HRESULT;
+    fn Heartbeat(&self, result: *mut i32) -> HRESULT;
+    fn ServerTerminate(&self) -> HRESULT;
+}
+
+/// A topic source, collected from `#[xl_rtd]` functions. `start` is called on Excel's
+/// main thread when a cell first asks for the topic, with the remaining RTD strings as
+/// arguments, and should hand the sink to whatever produces the values and return quickly.
+pub struct RtdRegistration {
+    pub topic: &'static str,
+    pub start: fn(&[String], TopicSink),
+}
+
+inventory::collect!(RtdRegistration);
+
+/// A value published to a topic
+#[derive(Clone, Debug, PartialEq)]
+pub enum RtdValue {
+    Number(f64),
+    Text(String),
+    Bool(bool),
+    NotAvailable,
+}
+
+impl From<f64> for RtdValue {
+    fn from(v: f64) -> RtdValue {
+        RtdValue::Number(v)
+    }
+}
+
+impl From<i32> for RtdValue {
+    fn from(v: i32) -> RtdValue {
+        RtdValue::Number(v as f64)
+    }
+}
+
+impl From<bool> for RtdValue {
+    fn from(v: bool) -> RtdValue {
+        RtdValue::Bool(v)
+    }
+}
+
+impl From<&str> for RtdValue {
+    fn from(v: &str) -> RtdValue {
+        RtdValue::Text(v.to_string())
+    }
+}
+
+impl From<String> for RtdValue {
+    fn from(v: String) -> RtdValue {
+        RtdValue::Text(v)
+    }
+}
+
+impl From<&RtdValue> for ComVariant {
+    fn from(v: &RtdValue) -> ComVariant {
+        match v {
+            RtdValue::Number(n) => ComVariant::from(*n),
+            RtdValue::Text(s) => ComVariant::from(s.as_str()),
+            RtdValue::Bool(b) => ComVariant::from(*b),
+            RtdValue::NotAvailable => ComVariant::excel_error(xlerrNA),
+        }
+    }
+}
+
+struct TopicShared {
+    id: i32,
+    connected: AtomicBool,
+    on_disconnect: Mutex<Vec<Box<dyn FnOnce() + Send>>>,
+}
+
+/// Publishes values to one topic. Sinks can be cloned and sent to other threads.
+#[derive(Clone)]
+pub struct TopicSink(Arc<TopicShared>);
+
+impl TopicSink {
+    /// Publishes a new value. Returns false once Excel no longer wants the topic, which
+    /// is the signal for the producer to stop.
+    pub fn update<T: Into<RtdValue>>(&self, value: T) -> bool {
+        if !self.is_connected() {
+            return false;
+        }
+        PENDING.lock().unwrap_or_else(|e| e.into_inner()).insert(self.0.id, value.into());
+        DIRTY.store(true, Ordering::Release);
+        true
+    }
+
+    /// Whether any cell still refers to the topic
+    pub fn is_connected(&self) -> bool {
+        self.0.connected.load(Ordering::Acquire)
+    }
+
+    /// Runs `f` when Excel disconnects the topic, e.g. to unsubscribe from a feed. If the
+    /// topic is already disconnected, `f` runs straight away.
+    pub fn on_disconnect<F: FnOnce() + Send + 'static>(&self, f: F) {
+        if self.is_connected() {
+            self.0.on_disconnect.lock().unwrap_or_else(|e| e.into_inner()).push(Box::new(f));
+        } else {
+            f();
+        }
+    }
+
+    fn disconnect(&self) {
+        self.0.connected.store(false, Ordering::Release);
+        let callbacks = std::mem::take(&mut *self.0.on_disconnect.lock().unwrap_or_else(|e| e.into_inner()));
+        for f in callbacks {
+            f();
+        }
+    }
+}
+
+/// Drives a blocking iterator on its own thread, publishing each item until the topic is
+/// disconnected. The thread ends when the iterator does, or at the first item produced
+/// after the disconnect.
+pub fn spawn_iter<I>(source: I, sink: TopicSink)
+where
+    I: IntoIterator + Send + 'static,
+    I::Item: Into<RtdValue>,
+{
+    std::thread::spawn(move || {
+        for item in source {
+            if !sink.update(item) {
+                break;
+            }
+        }
+    });
+}
+
+/// Drives an async stream on its own thread, publishing each item until the topic is
+/// disconnected. Streams that need a particular runtime, such as tokio timers, must be
+/// driven by that runtime instead, publishing through the sink.
+pub fn spawn_stream<S>(source: S, sink: TopicSink)
+where
+    S: futures::Stream + Send + 'static,
+    S::Item: Into<RtdValue>,
+{
+    spawn_iter(futures::executor::block_on_stream(Box::pin(source)), sink);
+}
+
+/// Values published since Excel last called RefreshData, by topic id
+static PENDING: Mutex<BTreeMap<i32, RtdValue>> = Mutex::new(BTreeMap::new());
+static DIRTY: AtomicBool = AtomicBool::new(false);
+static SERVER: Mutex<Option<ServerState>> = Mutex::new(None);
+
+struct ServerState {
+    prog_id: String,
+    clsid: GUID,
+    callback: Option<IRTDUpdateEvent>,
+    timer: usize,
+    topics: HashMap<i32, TopicSink>,
+}
+
+// The COM objects are only ever touched on Excel's main thread
+unsafe impl Send for ServerState {}
+
+fn with_server<T>(f: impl FnOnce(&mut ServerState) -> T) -> Option<T> {
+    SERVER.lock().unwrap_or_else(|e| e.into_inner()).as_mut().map(f)
+}
+
+/// The RTD server of this add-in
+pub struct RtdServer {
+    prog_id: String,
+    clsid: GUID,
+}
+
+impl RtdServer {
+    /// The ProgID is the name worksheets pass to RTD, and together with the CLSID must be
+    /// unique to your add-in. Generate a fresh GUID for the CLSID.
+    pub fn new(prog_id: &str, clsid: &str) -> RtdServer {
+        RtdServer { prog_id: prog_id.to_string(), clsid: parse_clsid(clsid) }
+    }
+
+    /// Registers the server for the current user, so Excel can create it when a cell first
+    /// calls RTD. The registration is removed again in xlAutoClose.
+    pub fn install(self) -> Result<()> {
+        register_class(self.clsid, || RtdServerObject.into());
+        register_server(&self.prog_id, self.clsid)?;
+        *SERVER.lock().unwrap_or_else(|e| e.into_inner()) = Some(ServerState {
+            prog_id: self.prog_id,
+            clsid: self.clsid,
+            callback: None,
+            timer: 0,
+            topics: HashMap::new(),
+        });
+        Ok(())
+    }
+}
+
+/// Removes the server registration. This is called from xlAutoClose.
+pub fn uninstall_rtd() {
+    if let Some(state) = SERVER.lock().unwrap_or_else(|e| e.into_inner()).take() {
+        for sink in state.topics.values() {
+            sink.disconnect();
+        }
+        unregister_class(state.clsid);
+        unregister_server(&state.prog_id, state.clsid);
+    }
+}
+
+/// Calls RTD for a topic of this add-in's server. This is what the worksheet functions
+/// generated by `#[xl_rtd]` do.
+pub fn rtd(topic: &str, args: &[String]) -> Variant {
+    let Some(prog_id) = with_server(|state| state.prog_id.clone()) else {
+        return Variant::from("#ERR RTD server not installed");
+    };
+    let mut rtd_args = vec![Variant::from(prog_id.as_str()), Variant::from(""), Variant::from(topic)];
+    rtd_args.extend(args.iter().map(Variant::from));
+    excel12(xlfRtd, &mut rtd_args)
+}
+
+/// Runs on Excel's main thread, and tells Excel when values are waiting
+unsafe extern "system" fn notify_timer(_hwnd: HWND, _msg: u32, _id: usize, _time: u32) {
+    if !DIRTY.swap(false, Ordering::AcqRel) {
+        return;
+    }
+    if let Some(Some(callback)) = with_server(|state| state.callback.clone()) {
+        let _ = unsafe { callback.UpdateNotify() };
+    }
+}
+
+fn connect_topic(topic_id: i32, strings: Vec<String>) -> ComVariant {
+    let Some((topic, args)) = strings.split_first() else {
+        return ComVariant::excel_error(xlerrNA);
+    };
+    let Some(registration) = inventory::iter::<RtdRegistration>
+        .into_iter()
+        .find(|reg| reg.topic.eq_ignore_ascii_case(topic))
+    else {
+        warn!("RTD topic '{}' is not registered", topic);
+        return ComVariant::from(format!("#ERR unknown topic {}", topic).as_str());
+    };
+
+    let sink = TopicSink(Arc::new(TopicShared {
+        id: topic_id,
+        connected: AtomicBool::new(true),
+        on_disconnect: Mutex::new(Vec::new()),
+    }));
+    with_server(|state| state.topics.insert(topic_id, sink.clone()));
+    (registration.start)(args, sink);
+
+    // Use any value the source published straight away, otherwise show #N/A until it does
+    match PENDING.lock().unwrap_or_else(|e| e.into_inner()).remove(&topic_id) {
+        Some(value) => ComVariant::from(&value),
+        None => ComVariant::excel_error(xlerrNA),
+    }
+}
+
+#[implement(IRtdServer)]
+struct RtdServerObject;
+
+impl IDispatch_Impl for RtdServerObject_Impl {
+    fn GetTypeInfoCount(&self) -> Result<u32> {
+        Ok(0)
+    }
+
+    fn GetTypeInfo(&self, _itinfo: u32, _lcid: u32) -> Result<ITypeInfo> {
+        Err(DISP_E_UNKNOWNNAME.into())
+    }
+
+    fn GetIDsOfNames(&self, _riid: *const GUID, _names: *const PCWSTR, _count: u32, _lcid: u32, _dispids: *mut i32) -> Result<()> {
+        Err(DISP_E_UNKNOWNNAME.into())
+    }
+
+    // Excel binds to IRtdServer through its vtable, so late binding is not supported
+    fn Invoke(&self, _dispid: i32, _riid: *const GUID, _lcid: u32, _flags: DISPATCH_FLAGS, _params: *const DISPPARAMS,
+        _result: *mut VARIANT, _excepinfo: *mut EXCEPINFO, _argerr: *mut u32) -> Result<()> {
+        Err(E_NOTIMPL.into())
+    }
+}
+
+impl IRtdServer_Impl for RtdServerObject_Impl {
+    unsafe fn ServerStart(&self, callback: *mut c_void, result: *mut i32) -> HRESULT {
+        if result.is_null() {
+            return E_POINTER;
+        }
+        let callback = unsafe { IRTDUpdateEvent::from_raw_borrowed(&callback) }.cloned();
+        let timer = unsafe { SetTimer(None, 0, NOTIFY_INTERVAL_MS, Some(notify_timer)) };
+        let started = with_server(|state| {
+            state.callback = callback;
+            state.timer = timer;
+        });
+        unsafe { *result = if started.is_some() { 1 } else { 0 } };
+        HRESULT(0)
+    }
+
+    unsafe fn ConnectData(&self, topic_id: i32, strings: *mut *mut SAFEARRAY, get_new_values: *mut VARIANT_BOOL, value: *mut VARIANT) -> HRESULT {
+        if strings.is_null() || value.is_null() {
+            return E_POINTER;
+        }
+        let strings = unsafe { unpack_vector(*strings) }
+            .iter()
+            .map(|s| s.to_string_value().unwrap_or_default())
+            .collect();
+        let initial = connect_topic(topic_id, strings);
+        unsafe {
+            value.write(initial.into_raw());
+            if !get_new_values.is_null() {
+                *get_new_values = VARIANT_TRUE;
+            }
+        }
+        HRESULT(0)
+    }
+
+    unsafe fn RefreshData(&self, topic_count: *mut i32, data: *mut *mut SAFEARRAY) -> HRESULT {
+        if topic_count.is_null() || data.is_null() {
+            return E_POINTER;
+        }
+        let pending = std::mem::take(&mut *PENDING.lock().unwrap_or_else(|e| e.into_inner()));
+        // Excel expects a 2 x n array of topic ids and values
+        let ids = pending.keys().map(|&id| ComVariant::from(id)).collect();
+        let values = pending.values().map(ComVariant::from).collect();
+        unsafe {
+            *data = pack_table(&[ids, values], 0);
+            *topic_count = pending.len() as i32;
+        }
+        HRESULT(0)
+    }
+
+    unsafe fn DisconnectData(&self, topic_id: i32) -> HRESULT {
+        if let Some(Some(sink)) = with_server(|state| state.topics.remove(&topic_id)) {
+            sink.disconnect();
+        }
+        PENDING.lock().unwrap_or_else(|e| e.into_inner()).remove(&topic_id);
+        HRESULT(0)
+    }
+
+    unsafe fn Heartbeat(&self, result: *mut i32) -> HRESULT {
+        if result.is_null() {
+            return E_POINTER;
+        }
+        unsafe { *result = 1 };
+        HRESULT(0)
+    }
+
+    unsafe fn ServerTerminate(&self) -> HRESULT {
+        let state = with_server(|state| {
+            let timer = std::mem::take(&mut state.timer);
+            state.callback = None;
+            (timer, std::mem::take(&mut state.topics))
+        });
+        if let Some((timer, topics)) = state {
+            if timer != 0 {
+                let _ = unsafe { KillTimer(None, timer) };
+            }
+            for sink in topics.values() {
+                sink.disconnect();
+            }
+        }
+        PENDING.lock().unwrap_or_else(|e| e.into_inner()).clear();
+        HRESULT(0)
+    }
+}
@@ -0,0 +1,142 @@
+//! Timer callbacks scheduled with xlcOnTime. Excel runs a hidden command exported by
+//! this module when a timer is due, and the command runs the Rust callbacks on Excel's
+//! main thread. This means callbacks may safely call back into Excel, unlike code
+//! running on a background thread.
+//!
+//! Timers can only be scheduled from a command context, such as xlAutoOpen, a menu
+//! item or another timer callback. They cannot be scheduled from a worksheet function.
+
+use crate::entrypoint::excel12;
+use crate::guard;
+use crate::registrator::CommandRegistration;
+use crate::variant::Variant;
+use crate::xlcall::{xlcOnTime, xlfNow};
+use log::debug;
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Name of the hidden command that Excel invokes when a timer is due
+const TIMER_COMMAND: &str = "xl_timer_tick";
+const SECONDS_PER_DAY: f64 = 86_400.0;
+
+/// Identifies a scheduled timer, so that it can be cancelled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TimerId(u64);
+
+enum Callback {
+    Once(Box<dyn FnOnce() + Send>),
+    Recurring(Box<dyn FnMut() + Send>, Duration),
+}
+
+struct Timer {
+    due: f64, // Excel serial date-time
+    callback: Callback,
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+static TIMERS: Mutex<BTreeMap<TimerId, Timer>> = Mutex::new(BTreeMap::new());
+
+/// Runs the callback once, after the given delay
+pub fn schedule_in(delay: Duration, callback: impl FnOnce() + Send + 'static) -> TimerId {
+    schedule(delay, Callback::Once(Box::new(callback)))
+}
+
+/// Runs the callback repeatedly, first after the given interval and then every interval
+/// until it is cancelled
+pub fn schedule_every(interval: Duration, callback: impl FnMut() + Send + 'static) -> TimerId {
+    schedule(interval, Callback::Recurring(Box::new(callback), interval))
+}
+
+/// Cancels a timer. Returns false if the timer has already run or been cancelled.
+pub fn cancel(id: TimerId) -> bool {
+    lock_timers().remove(&id).is_some()
+}
+
+/// Cancels all timers, and tells Excel to forget the pending xlcOnTime requests. This
+/// is called from xlAutoClose.
+pub fn cancel_all() {
+    let timers = std::mem::take(&mut *lock_timers());
+    for timer in timers.values() {
+        // A time may have been requested more than once by the re-arming in the tick
+        while on_time(timer.due, false) {}
+    }
+}
+
+fn schedule(delay: Duration, callback: Callback) -> TimerId {
+    let id = TimerId(NEXT_ID.fetch_add(1, Ordering::Relaxed));
+    let due = excel_now() + delay.as_secs_f64() / SECONDS_PER_DAY;
+    lock_timers().insert(id, Timer { due, callback });
+    on_time(due, true);
+    id
+}
+
+fn lock_timers() -> std::sync::MutexGuard<'static, BTreeMap<TimerId, Timer>> {
+    TIMERS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn excel_now() -> f64 {
+    f64::try_from(&excel12(xlfNow, &mut [])).unwrap_or(0.0)
+}
+
+/// Asks Excel to run (or with insert=false, to stop running) the timer command at the
+/// given serial date-time. Returns whether Excel accepted the request.
+fn on_time(due: f64, insert: bool) -> bool {
+    let mut opers = vec![
+        Variant::from(due),
+        Variant::from(TIMER_COMMAND),
+        Variant::missing(), // no tolerance
+        Variant::from(insert),
+    ];
+    let result = excel12(xlcOnTime, opers.as_mut_slice());
+    debug!("OnTime({}, {}): result = {}", due, insert, result);
+    bool::try_from(&result).unwrap_or(false)
+}
+
+/// The hidden command run by Excel when a timer is due. Runs all the due callbacks,
+/// outside the lock so that they can schedule or cancel other timers, each inside the
+/// guard so a panic is logged rather than unwinding into Excel. Excel may run the command
+/// up to a second early, before the timer it was asked for is due, so the command is
+/// asked for again at the earliest time still to come.
+#[unsafe(no_mangle)]
+pub extern "system" fn xl_timer_tick() -> i32 {
+    let now = excel_now();
+    let due: Vec<(TimerId, Timer)> = {
+        let mut timers = lock_timers();
+        let ids: Vec<TimerId> = timers.iter()
+            .filter(|(_, timer)| timer.due <= now)
+            .map(|(id, _)| *id)
+            .collect();
+        ids.into_iter()
+            .filter_map(|id| timers.remove(&id).map(|timer| (id, timer)))
+            .collect()
+    };
+
+    for (id, timer) in due {
+        match timer.callback {
+            Callback::Once(callback) => {
+                let _ = guard::protect(callback);
+            }
+            Callback::Recurring(mut callback, interval) => {
+                let _ = guard::protect(&mut callback);
+                let due = excel_now() + interval.as_secs_f64() / SECONDS_PER_DAY;
+                lock_timers().insert(id, Timer { due, callback: Callback::Recurring(callback, interval) });
+            }
+        }
+    }
+
+    let next = lock_timers().values().map(|timer| timer.due).min_by(f64::total_cmp);
+    if let Some(next) = next {
+        on_time(next, true);
+    }
+    1
+}
+
+inventory::submit! {
+    CommandRegistration {
+        xl_name: TIMER_COMMAND,
+        shortcut: "",
+    }
+}
@@ -0,0 +1,24 @@
+/*
+ * The structured exception handler of the guard (guard.rs). A hardware exception, such
+ * as an access violation in a native library, unwinds to the __except block here and
+ * comes back to Rust as a return value, rather than taking Excel down. Only MSVC has
+ * __try and __except, so build.rs compiles this for MSVC targets only.
+ */
+#include <windows.h>
+
+typedef void (*xladd_body)(void *data);
+typedef int (*xladd_filter)(EXCEPTION_POINTERS *exception);
+
+/*
+ * Calls body(data). Returns 0 once it returns, or 1 if it raised an exception that the
+ * filter chose to handle, returning EXCEPTION_EXECUTE_HANDLER. The body must not unwind.
+ */
+int xladd_seh_call(xladd_body body, void *data, xladd_filter filter)
+{
+    __try {
+        body(data);
+        return 0;
+    } __except (filter(GetExceptionInformation())) {
+        return 1;
+    }
+}
@@ -0,0 +1,104 @@
+//! Call metrics for worksheet functions. Every call through a wrapper generated by
+//! `#[xl_func]` is counted here, with its latency and whether it failed, and the built-in
+//! `xl_stats()` function shows the totals. Sorting that table by total time is usually the
+//! quickest way to find out why a workbook is slow to recalculate.
+
+use crate::registrator::FunctionRegistration;
+use crate::variant::Variant;
+use crate::xlcall::LPXLOPER12;
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Totals for one worksheet function
+#[derive(Debug, Clone, Default)]
+pub struct FunctionStats {
+    pub calls: u64,
+    pub errors: u64,
+    pub total: Duration,
+    pub min: Duration,
+    pub max: Duration,
+}
+
+impl FunctionStats {
+    /// Mean latency per call
+    pub fn average(&self) -> Duration {
+        if self.calls == 0 {
+            Duration::ZERO
+        } else {
+            self.total / self.calls as u32
+        }
+    }
+}
+
+static STATS: Mutex<BTreeMap<&'static str, FunctionStats>> = Mutex::new(BTreeMap::new());
+
+/// Records one call of `function`. This is called as each call finishes.
+pub fn record(function: &'static str, elapsed: Duration, failed: bool) {
+    let mut stats = STATS.lock().unwrap_or_else(|e| e.into_inner());
+    let entry = stats.entry(function).or_default();
+    if entry.calls == 0 || elapsed < entry.min {
+        entry.min = elapsed;
+    }
+    entry.max = entry.max.max(elapsed);
+    entry.calls += 1;
+    entry.total += elapsed;
+    if failed {
+        entry.errors += 1;
+    }
+}
+
+/// The totals for every function called so far, by function name
+pub fn snapshot() -> Vec<(&'static str, FunctionStats)> {
+    STATS.lock().unwrap_or_else(|e| e.into_inner()).iter().map(|(name, stats)| (*name, stats.clone())).collect()
+}
+
+/// Forgets all totals
+pub fn reset() {
+    STATS.lock().unwrap_or_else(|e| e.into_inner()).clear();
+}
+
+fn millis(duration: Duration) -> Variant {
+    Variant::from(duration.as_secs_f64() * 1000.0)
+}
+
+/// Returns a table of call counts, error counts and latencies in milliseconds for each
+/// function, with a header row. Pass TRUE to reset the totals after reading them.
+#[unsafe(no_mangle)]
+pub extern "system" fn xl_stats(reset_after: LPXLOPER12) -> LPXLOPER12 {
+    let reset_after = bool::try_from(&Variant::from(reset_after)).unwrap_or(false);
+    let mut rows = vec![
+        ["Function", "Calls", "Errors", "Total (ms)", "Min (ms)", "Avg (ms)", "Max (ms)"]
+            .into_iter()
+            .map(Variant::from)
+            .collect::<Vec<_>>(),
+    ];
+    for (name, stats) in snapshot() {
+        rows.push(vec![
+            Variant::from(name),
+            Variant::from(stats.calls as f64),
+            Variant::from(stats.errors as f64),
+            millis(stats.total),
+            millis(stats.min),
+            millis(stats.average()),
+            millis(stats.max),
+        ]);
+    }
+    if reset_after {
+        reset();
+    }
+    LPXLOPER12::from(Variant::from(rows))
+}
+
+inventory::submit! {
+    FunctionRegistration {
+        xl_name: "xl_stats",
+        arg_types: "QQ$",
+        arg_names: "reset",
+        category: "Add-in Diagnostics",
+        description: "Shows call counts and latencies of this add-in's functions",
+        arg_infos: &[],
+        help_topic: "",
+    }
+}
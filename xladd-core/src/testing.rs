@@ -0,0 +1,65 @@
+//! Hooks for running the add-in's code outside Excel, in benchmarks and tests. Only
+//! available with the `testing` feature.
+//!
+//! [`install_stub`] answers every call into Excel with success and an empty result, which
+//! is enough for the conversions and for the wrappers `#[xl_func]` generates. Tests that
+//! need Excel to answer, or want to see what was asked of it, install a
+//! [`MockExcel`](crate::mock_excel::MockExcel) instead. The value
+//! builders make arguments shaped like the ones Excel passes, and [`free_result`] hands a
+//! result back the way Excel does once it has copied it:
+//!
+//! testing::install_stub();
+//! let mut grid = testing::numbers(100, 10);
+//! let result = xl_total(testing::arg(&mut grid));
+//! unsafe { testing::free_result(result) };
+
+use crate::entrypoint::set_excel12_entry_point;
+use crate::variant::Variant;
+use crate::xlauto::xlAutoFree12;
+use crate::xlcall::{xlbitDLLFree, xltypeNil, LPXLOPER12};
+use libc::c_int;
+
+/// Sends every call into Excel to a stub that succeeds with an empty result
+pub fn install_stub() {
+    set_excel12_entry_point(stub);
+}
+
+extern "system" fn stub(_xlfn: c_int, _count: c_int, _opers: *const LPXLOPER12, result: LPXLOPER12) -> c_int {
+    if !result.is_null() {
+        unsafe { (*result).xltype = xltypeNil };
+    }
+    0
+}
+
+/// An array of numbers, counting up from 0 across each row in turn
+pub fn numbers(rows: usize, columns: usize) -> Variant {
+    let grid: Vec<Vec<f64>> = (0..rows).map(|row| (0..columns).map(|column| (row * columns + column) as f64).collect()).collect();
+    Variant::from(grid)
+}
+
+/// An array of short strings, such as "r2c5"
+pub fn strings(rows: usize, columns: usize) -> Variant {
+    let grid: Vec<Vec<Variant>> = (0..rows)
+        .map(|row| (0..columns).map(|column| Variant::from(format!("r{}c{}", row, column))).collect())
+        .collect();
+    Variant::from(grid)
+}
+
+/// The pointer to pass a value as an argument, as Excel would. The value keeps ownership
+/// of its data, and must outlive the call.
+pub fn arg(value: &mut Variant) -> LPXLOPER12 {
+    value.as_mut_xloper()
+}
+
+/// Frees a result returned by a function, as Excel does after copying it. Only results
+/// that carry xlbitDLLFree are handed back; the rest belong to the add-in.
+///
+/// # Safety
+///
+/// The result must have been returned by one of the add-in's functions, and not freed
+/// already.
+pub unsafe fn free_result(result: LPXLOPER12) {
+    if !result.is_null() && unsafe { (*result).xltype } & xlbitDLLFree != 0 {
+        xlAutoFree12(result);
+    }
+}
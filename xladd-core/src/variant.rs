@@ -1,5 +1,6 @@
 #![allow(non_snake_case, non_camel_case_types, non_upper_case_globals)]
 
+use std::cell::{Cell, UnsafeCell};
 use std::{fmt, mem, slice};
 //#[cfg(feature = "try_from")]
 use crate::entrypoint::excel_free;
@@ -47,6 +48,9 @@ pub enum XLAddError {
     
     #[error("Array dimension error: {0}")]
     DimensionError(String),
+
+    #[error("Invalid handle [{0}]")]
+    InvalidHandle(String),
 }
 
 const xltypeStr_xlbitDLLFree: u32 = xltypeStr | xlbitDLLFree;
@@ -113,7 +117,7 @@ impl Variant {
         columns = columns.max(2);
 
         // If the array is too big, return an error string
-        if rows as i32 > XL_MAX_ROWS || columns as i32 > XL_MAX_COLS {
+        if rows > XL_MAX_ROWS as usize || columns > XL_MAX_COLS as usize {
             return Self::from("#ERR resulting array is too big");
         }
 
@@ -160,6 +164,8 @@ impl Variant {
 
         let lparray = array.as_mut_ptr() as LPXLOPER12;
         mem::forget(array);
+        #[cfg(feature = "memory-tracking")]
+        crate::memory::array_allocated(size);
 
         Variant(XLOPER12 {
             xltype: xltypeMulti,
@@ -184,7 +190,7 @@ impl Variant {
         // We have an array that we need to transpose. Create a vector of
         // Variant to contain the elements.
         let dim = self.dim();
-        if dim.0 as i32 > XL_MAX_ROWS || dim.1 as i32 > XL_MAX_COLS {
+        if dim.0 > XL_MAX_ROWS as usize || dim.1 > XL_MAX_COLS as usize {
             return Self::from("#ERR resulting array is too big");
         }
 
@@ -201,6 +207,8 @@ impl Variant {
         // Return as a Variant
         let lparray = array.as_mut_ptr() as LPXLOPER12;
         mem::forget(array);
+        #[cfg(feature = "memory-tracking")]
+        crate::memory::array_allocated(len);
 
         Variant(XLOPER12 {
             xltype: xltypeMulti,
@@ -222,25 +230,7 @@ impl Variant {
     /// Gets the count of rows and columns. Scalars are treated as 1x1. Missing values are
     /// treated as 0x0.
     pub fn dim(&self) -> (usize, usize) {
-        match self.0.xltype & xltypeMask {
-            xltypeMulti => {
-                self.0.val.as_array(self.0.xltype)
-                    .map(|arr| arr.dim())
-                    .unwrap_or((0, 0))
-            },
-            xltypeSRef => {
-                self.0.val.as_sref(self.0.xltype)
-                    .map(|sref| sref.dim())
-                    .unwrap_or((0, 0))
-            },
-            xltypeRef => {
-                self.0.val.as_mref(self.0.xltype)
-                    .and_then(|mref| get_mref_dim_safe(mref.lpmref))
-                    .unwrap_or((0, 0))
-            },
-            xltypeMissing => (0, 0),
-            _ => (1, 1),
-        }
+        xloper_dim(&self.0)
     }
 
     /// Gets the element at the given column and row. If this is a scalar, treat it as a one-element
@@ -316,11 +306,15 @@ impl Drop for Variant {
                 // We have a 16bit string that was originally allocated as a vector
                 // but then forgotten. Reconstruct the vector, so its drop method
                 // will clean up the memory for us.
-                if let Some(ptr) = self.0.val.as_str_ptr(self.0.xltype) {
+                if let Some(ptr) = self.0.val.as_str_ptr(self.0.xltype)
+                    && !ptr.is_null()
+                {
                     unsafe {
                         let len = *ptr as usize + 1;
                         let cap = len;
                         Vec::from_raw_parts(ptr, len, cap);
+                        #[cfg(feature = "memory-tracking")]
+                        crate::memory::string_freed(len);
                     }
                 }
             }
@@ -328,12 +322,16 @@ impl Drop for Variant {
                 // We have an array that was originally allocated as a vector of
                 // Variant but then forgotten. Reconstruct the vector, so its drop method
                 // will clean up the vector and its elements for us.
-                if let Some(array) = self.0.val.as_array(self.0.xltype) {
+                if let Some(array) = self.0.val.as_array(self.0.xltype)
+                    && array.len() > 0
+                {
                     unsafe {
                         let p = array.lparray as *mut Variant;
-                        let len = (array.rows * array.columns) as usize;
+                        let len = array.len();
                         let cap = len;
                         Vec::from_raw_parts(p, len, cap);
+                        #[cfg(feature = "memory-tracking")]
+                        crate::memory::array_freed(len);
                     }
                 }
             }
@@ -358,7 +356,9 @@ impl Clone for Variant {
             xltypeStr_xlbitDLLFree => {
                 // We have a 16bit string that was originally allocated as a vector
                 // but then forgotten. Reconstruct the vector, so we can clone it.
-                if let Some(ptr) = copy.0.val.as_str_ptr(copy.0.xltype) {
+                if let Some(ptr) = copy.0.val.as_str_ptr(copy.0.xltype)
+                    && !ptr.is_null()
+                {
                     unsafe {
                         let len = *ptr as usize + 1;
                         let cap = len;
@@ -369,16 +369,20 @@ impl Clone for Variant {
                         // now forget everything -- we do not want either string deallocated
                         mem::forget(string_vec);
                         mem::forget(cloned);
+                        #[cfg(feature = "memory-tracking")]
+                        crate::memory::string_allocated(len);
                     }
                 }
             }
             xltypeMulti_xlbitDLLFree => {
                 // We have an array that was originally allocated as a vector
                 // but then forgotten. Reconstruct the vector, so we can clone it.
-                if let Some(array) = self.0.val.as_array(self.0.xltype) {
+                if let Some(array) = self.0.val.as_array(self.0.xltype)
+                    && array.len() > 0
+                {
                     unsafe {
                         let p = array.lparray as *mut Variant;
-                        let len = (array.rows * array.columns) as usize;
+                        let len = array.len();
                         let cap = len;
                         let array_vec = Vec::from_raw_parts(p, len, cap);
                         let mut cloned = array_vec.clone();
@@ -387,6 +391,8 @@ impl Clone for Variant {
                         // now forget everything -- we do not want either string deallocated
                         mem::forget(array_vec);
                         mem::forget(cloned);
+                        #[cfg(feature = "memory-tracking")]
+                        crate::memory::array_allocated(len);
                     }
                 }
             }
@@ -532,6 +538,7 @@ impl From<&xloper12> for String {
                 .unwrap_or_default(),
             xltypeStr => {
                 v.val.as_str_ptr(v.xltype)
+                    .filter(|ptr| !ptr.is_null())
                     .and_then(|ptr| unsafe {
                         let cstr_len = *ptr as usize;
                         let cstr_slice = slice::from_raw_parts(ptr.offset(1), cstr_len);
@@ -646,67 +653,75 @@ impl<'a> TryFrom<&'a Variant> for Vec<f64> {
     type Error = XLAddError;
     
     fn try_from(v: &'a Variant) -> Result<Vec<f64>, Self::Error> {
-        let (cols, rows) = v.dim();
-        let mut res = Vec::with_capacity(cols * rows);
-        
-        if cols == 1 && rows == 1 {
-            res.push(f64::try_from(v)?);
-        } else if let Some(array) = v.0.val.as_array(v.0.xltype) {
-            for j in 0..rows {
-                for i in 0..cols {
-                    let index = j * cols + i;
-                    if let Some(xloper) = array.get(index) {
-                        let val = match xloper.xltype & xltypeMask {
-                            xltypeNum => xloper.val.as_num(xloper.xltype)
-                                .ok_or_else(|| XLAddError::F64ConversionFailed(
-                                    format!("Failed to get number at [{}, {}]", i, j)
-                                ))?,
-                            xltypeInt => xloper.val.as_int(xloper.xltype)
-                                .map(|i| i as f64)
-                                .ok_or_else(|| XLAddError::F64ConversionFailed(
-                                    format!("Failed to get integer at [{}, {}]", i, j)
-                                ))?,
-                            _ => return Err(XLAddError::F64ConversionFailed(
-                                format!("Invalid type at [{}, {}]", i, j)
-                            ))
-                        };
-                        res.push(val);
-                    } else {
-                        return Err(XLAddError::F64ConversionFailed(
-                            format!("Failed to access element at [{}, {}]", i, j)
-                        ));
-                    }
+        numbers_from(&v.0)
+    }
+}
+
+fn numbers_from(v: &XLOPER12) -> Result<Vec<f64>, XLAddError> {
+    let (cols, rows) = xloper_dim(v);
+    let mut res = Vec::with_capacity(cols * rows);
+    
+    if cols == 1 && rows == 1 {
+        res.push(number_from(v)?);
+    } else if let Some(array) = v.val.as_array(v.xltype) {
+        for j in 0..rows {
+            for i in 0..cols {
+                let index = j * cols + i;
+                if let Some(xloper) = array.get(index) {
+                    let val = match xloper.xltype & xltypeMask {
+                        xltypeNum => xloper.val.as_num(xloper.xltype)
+                            .ok_or_else(|| XLAddError::F64ConversionFailed(
+                                format!("Failed to get number at [{}, {}]", i, j)
+                            ))?,
+                        xltypeInt => xloper.val.as_int(xloper.xltype)
+                            .map(|i| i as f64)
+                            .ok_or_else(|| XLAddError::F64ConversionFailed(
+                                format!("Failed to get integer at [{}, {}]", i, j)
+                            ))?,
+                        _ => return Err(XLAddError::F64ConversionFailed(
+                            format!("Invalid type at [{}, {}]", i, j)
+                        ))
+                    };
+                    res.push(val);
+                } else {
+                    return Err(XLAddError::F64ConversionFailed(
+                        format!("Failed to access element at [{}, {}]", i, j)
+                    ));
                 }
             }
-        } else {
-            return Err(XLAddError::F64ConversionFailed("Not an array".to_string()));
         }
-        Ok(res)
+    } else {
+        return Err(XLAddError::F64ConversionFailed("Not an array".to_string()));
     }
+    Ok(res)
 }
 
 /// Converts a variant into a string array filling the missing or invalid with f64::NAN.
 /// This is so that you can handle those appropriately for your application (for example fill with the mean value or 0)
 impl<'a> From<&'a Variant> for Vec<String> {
     fn from(v: &'a Variant) -> Vec<String> {
-        let (x, y) = v.dim();
-        let mut res = Vec::with_capacity(x * y);
-        if x == 1 && y == 1 {
-            res.push(String::from(v));
-        } else if let Some(array) = v.0.val.as_array(v.0.xltype) {
-            for j in 0..y {
-                for i in 0..x {
-                    let index = j * x + i;
-                    if let Some(xloper) = array.get(index) {
-                        res.push(String::from(xloper));
-                    } else {
-                        res.push(String::new());
-                    }
+        strings_from(&v.0)
+    }
+}
+
+fn strings_from(v: &XLOPER12) -> Vec<String> {
+    let (x, y) = xloper_dim(v);
+    let mut res = Vec::with_capacity(x * y);
+    if x == 1 && y == 1 {
+        res.push(String::from(v));
+    } else if let Some(array) = v.val.as_array(v.xltype) {
+        for j in 0..y {
+            for i in 0..x {
+                let index = j * x + i;
+                if let Some(xloper) = array.get(index) {
+                    res.push(String::from(xloper));
+                } else {
+                    res.push(String::new());
                 }
             }
         }
-        res
     }
+    res
 }
 
 // --------------------------------------------------------------------------------------------------------------------
@@ -723,53 +738,57 @@ impl TryFrom<&Variant> for bool {
     type Error = XLAddError;
     
     fn try_from(v: &Variant) -> Result<Self, Self::Error> {
-        match v.0.xltype & xltypeMask {
-            xltypeBool => v.0.val.as_bool(v.0.xltype)
-                .ok_or_else(|| XLAddError::BoolConversionFailed("Failed to extract boolean".to_string())),
-            xltypeNum => {
-                v.0.val.as_num(v.0.xltype)
-                    .and_then(|num| {
-                        if num == 0.0 {
-                            Some(false)
-                        } else if num == 1.0 {
-                            Some(true)
-                        } else {
-                            None
-                        }
-                    })
-                    .ok_or_else(|| XLAddError::BoolConversionFailed(
-                        "Number must be 0.0 or 1.0 for boolean conversion".to_string()
-                    ))
-            }
-            xltypeInt => {
-                v.0.val.as_int(v.0.xltype)
-                    .and_then(|int_val| {
-                        if int_val == 0 {
-                            Some(false)
-                        } else if int_val == 1 {
-                            Some(true)
-                        } else {
-                            None
-                        }
-                    })
-                    .ok_or_else(|| XLAddError::BoolConversionFailed(
-                        "Integer must be 0 or 1 for boolean conversion".to_string()
-                    ))
-            }
-            xltypeStr => {
-                let str_val = String::from(&v.0);
-                match str_val.to_lowercase().as_str() {
-                    "true" | "yes" | "1" => Ok(true),
-                    "false" | "no" | "0" => Ok(false),
-                    _ => Err(XLAddError::BoolConversionFailed(format!(
-                        "String '{}' is not a valid boolean", str_val
-                    )))
-                }
+        boolean_from(&v.0)
+    }
+}
+
+fn boolean_from(v: &XLOPER12) -> Result<bool, XLAddError> {
+    match v.xltype & xltypeMask {
+        xltypeBool => v.val.as_bool(v.xltype)
+            .ok_or_else(|| XLAddError::BoolConversionFailed("Failed to extract boolean".to_string())),
+        xltypeNum => {
+            v.val.as_num(v.xltype)
+                .and_then(|num| {
+                    if num == 0.0 {
+                        Some(false)
+                    } else if num == 1.0 {
+                        Some(true)
+                    } else {
+                        None
+                    }
+                })
+                .ok_or_else(|| XLAddError::BoolConversionFailed(
+                    "Number must be 0.0 or 1.0 for boolean conversion".to_string()
+                ))
+        }
+        xltypeInt => {
+            v.val.as_int(v.xltype)
+                .and_then(|int_val| {
+                    if int_val == 0 {
+                        Some(false)
+                    } else if int_val == 1 {
+                        Some(true)
+                    } else {
+                        None
+                    }
+                })
+                .ok_or_else(|| XLAddError::BoolConversionFailed(
+                    "Integer must be 0 or 1 for boolean conversion".to_string()
+                ))
+        }
+        xltypeStr => {
+            let str_val = String::from(v);
+            match str_val.to_lowercase().as_str() {
+                "true" | "yes" | "1" => Ok(true),
+                "false" | "no" | "0" => Ok(false),
+                _ => Err(XLAddError::BoolConversionFailed(format!(
+                    "String '{}' is not a valid boolean", str_val
+                )))
             }
-            xltypeErr => Err(XLAddError::BoolConversionFailed("Cannot convert Excel error to boolean".to_string())),
-            xltypeMissing | xltypeNil => Err(XLAddError::BoolConversionFailed("Cannot convert missing/nil value to boolean".to_string())),
-            _ => Err(XLAddError::BoolConversionFailed("Invalid Excel type for boolean conversion".to_string()))
         }
+        xltypeErr => Err(XLAddError::BoolConversionFailed("Cannot convert Excel error to boolean".to_string())),
+        xltypeMissing | xltypeNil => Err(XLAddError::BoolConversionFailed("Cannot convert missing/nil value to boolean".to_string())),
+        _ => Err(XLAddError::BoolConversionFailed("Invalid Excel type for boolean conversion".to_string()))
     }
 }
 
@@ -780,27 +799,31 @@ impl TryFrom<&Variant> for f64 {
     type Error = XLAddError;
     
     fn try_from(v: &Variant) -> Result<Self, Self::Error> {
-        match v.0.xltype & xltypeMask {
-            xltypeNum => v.0.val.as_num(v.0.xltype)
-                .ok_or_else(|| XLAddError::F64ConversionFailed("Failed to extract number".to_string())),
-            xltypeInt => v.0.val.as_int(v.0.xltype)
-                .map(|i| i as f64)
-                .ok_or_else(|| XLAddError::F64ConversionFailed("Failed to extract integer".to_string())),
-            xltypeStr => {
-                let str_val = String::from(&v.0);
-                str_val.parse::<f64>().map_err(|_| {
-                    XLAddError::F64ConversionFailed(format!("Cannot convert '{}' to number", str_val))
-                })
-            }
-            xltypeBool => {
-                v.0.val.as_bool(v.0.xltype)
-                    .map(|b| if b { 1.0 } else { 0.0 })
-                    .ok_or_else(|| XLAddError::F64ConversionFailed("Failed to extract boolean".to_string()))
-            }
-            xltypeErr => Err(XLAddError::F64ConversionFailed("Cannot convert Excel error to number".to_string())),
-            xltypeMissing | xltypeNil => Err(XLAddError::F64ConversionFailed("Missing or empty value - number required".to_string())),
-            _ => Err(XLAddError::F64ConversionFailed("Invalid Excel type for number conversion".to_string()))
+        number_from(&v.0)
+    }
+}
+
+fn number_from(v: &XLOPER12) -> Result<f64, XLAddError> {
+    match v.xltype & xltypeMask {
+        xltypeNum => v.val.as_num(v.xltype)
+            .ok_or_else(|| XLAddError::F64ConversionFailed("Failed to extract number".to_string())),
+        xltypeInt => v.val.as_int(v.xltype)
+            .map(|i| i as f64)
+            .ok_or_else(|| XLAddError::F64ConversionFailed("Failed to extract integer".to_string())),
+        xltypeStr => {
+            let str_val = String::from(v);
+            str_val.parse::<f64>().map_err(|_| {
+                XLAddError::F64ConversionFailed(format!("Cannot convert '{}' to number", str_val))
+            })
+        }
+        xltypeBool => {
+            v.val.as_bool(v.xltype)
+                .map(|b| if b { 1.0 } else { 0.0 })
+                .ok_or_else(|| XLAddError::F64ConversionFailed("Failed to extract boolean".to_string()))
         }
+        xltypeErr => Err(XLAddError::F64ConversionFailed("Cannot convert Excel error to number".to_string())),
+        xltypeMissing | xltypeNil => Err(XLAddError::F64ConversionFailed("Missing or empty value - number required".to_string())),
+        _ => Err(XLAddError::F64ConversionFailed("Invalid Excel type for number conversion".to_string()))
     }
 }
 
@@ -865,6 +888,8 @@ impl From<&str> for Variant {
         wstr.insert(0, wstr.len() as u16);
         wstr.shrink_to_fit();
         let p = wstr.as_mut_ptr();
+        #[cfg(feature = "memory-tracking")]
+        crate::memory::string_allocated(wstr.len());
         mem::forget(wstr);
         Variant(XLOPER12 {
             xltype: xltypeStr | xlbitDLLFree,
@@ -911,6 +936,8 @@ impl From<Vec<Vec<f64>>> for Variant {
         
         let lparray = flat_variants.as_mut_ptr() as LPXLOPER12;
         mem::forget(flat_variants);
+        #[cfg(feature = "memory-tracking")]
+        crate::memory::array_allocated(rows * cols);
         
         Variant(XLOPER12 {
             xltype: xltypeMulti | xlbitDLLFree,
@@ -943,6 +970,8 @@ impl From<Vec<f64>> for Variant {
         let lparray = variants.as_mut_ptr() as LPXLOPER12;
         let columns = variants.len();
         mem::forget(variants);
+        #[cfg(feature = "memory-tracking")]
+        crate::memory::array_allocated(columns);
         
         Variant(XLOPER12 {
             xltype: xltypeMulti | xlbitDLLFree,
@@ -971,6 +1000,8 @@ impl From<Vec<&str>> for Variant {
         if rows == 0 || columns == 0 {
             Variant::from_err(xlerrNull)
         } else {
+            #[cfg(feature = "memory-tracking")]
+            crate::memory::array_allocated(rows * columns);
             Variant(XLOPER12 {
                 xltype: xltypeMulti | xlbitDLLFree,
                 val: Xloper12Value {
@@ -987,6 +1018,44 @@ impl From<Vec<&str>> for Variant {
 
 // Complex arrays
 
+// Construct 2d variant array from rows of variants. Rows shorter than the longest row are
+// padded with #NA, in the same way as concat.
+impl From<Vec<Vec<Variant>>> for Variant {
+    fn from(rows: Vec<Vec<Variant>>) -> Variant {
+        let columns = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+        let row_count = rows.len();
+        if row_count == 0 || columns == 0 {
+            return Variant::from_err(xlerrNull);
+        }
+        if row_count > XL_MAX_ROWS as usize || columns > XL_MAX_COLS as usize {
+            return Self::from("#ERR resulting array is too big");
+        }
+
+        let mut array = Vec::with_capacity(row_count * columns);
+        for row in rows {
+            let len = row.len();
+            array.extend(row);
+            array.extend((len..columns).map(|_| Variant::from_err(xlerrNA)));
+        }
+
+        let lparray = array.as_mut_ptr() as LPXLOPER12;
+        mem::forget(array);
+        #[cfg(feature = "memory-tracking")]
+        crate::memory::array_allocated(row_count * columns);
+
+        Variant(XLOPER12 {
+            xltype: xltypeMulti | xlbitDLLFree,
+            val: Xloper12Value {
+                array: Xloper12Array {
+                    lparray,
+                    rows: row_count as i32,
+                    columns: columns as i32,
+                },
+            },
+        })
+    }
+}
+
 // Construct 2d variant array from (string,f64)
 impl From<Vec<(String, f64)>> for Variant {
     fn from(arr: Vec<(String, f64)>) -> Variant {
@@ -1003,6 +1072,40 @@ impl From<Vec<(String, f64)>> for Variant {
         if rows == 0 || columns == 0 {
             Variant::from_err(xlerrNull)
         } else {
+            #[cfg(feature = "memory-tracking")]
+            crate::memory::array_allocated(rows * columns);
+            Variant(XLOPER12 {
+                xltype: xltypeMulti | xlbitDLLFree,
+                val: Xloper12Value {
+                    array: Xloper12Array {
+                        lparray,
+                        rows: std::cmp::min(XL_MAX_ROWS, rows as i32),
+                        columns: std::cmp::min(XL_MAX_COLS, columns as i32),
+                    },
+                },
+            })
+        }
+    }
+}
+
+// Construct 2d variant array from (string,string), e.g. a label/value table
+impl From<Vec<(String, String)>> for Variant {
+    fn from(arr: Vec<(String, String)>) -> Variant {
+        let mut array = Vec::new();
+        arr.iter().for_each(|v| {
+            array.push(Variant::from(v.0.as_str()));
+            array.push(Variant::from(v.1.as_str()))
+        });
+
+        let lparray = array.as_mut_ptr() as LPXLOPER12;
+        mem::forget(array);
+        let rows = arr.len();
+        let columns = 2;
+        if rows == 0 || columns == 0 {
+            Variant::from_err(xlerrNull)
+        } else {
+            #[cfg(feature = "memory-tracking")]
+            crate::memory::array_allocated(rows * columns);
             Variant(XLOPER12 {
                 xltype: xltypeMulti | xlbitDLLFree,
                 val: Xloper12Value {
@@ -1032,6 +1135,8 @@ impl From<Vec<(Variant, f64)>> for Variant {
         if rows == 0 || columns == 0 {
             Variant::from_err(xlerrNull)
         } else {
+            #[cfg(feature = "memory-tracking")]
+            crate::memory::array_allocated(rows * columns);
             Variant(XLOPER12 {
                 xltype: xltypeMulti | xlbitDLLFree,
                 val: Xloper12Value {
@@ -1050,12 +1155,48 @@ impl From<Vec<(Variant, f64)>> for Variant {
 // 8. EXCEL SPECIFIC CONVERSIONS
 // --------------------------------------------------------------------------------------------------------------------
 
-/// Construct a LPXlOPER12 from a Variant. This is just a cast to the underlying union contained within a pointer
-///  that we pass back to Excel. Excel will clean up the pointer after us
+/// How many scalar results each thread holds at once. Excel copies a result as soon as the
+/// function returns, so one would do; the rest cover a function that returns while
+/// another result is still being read, such as through xlUDF.
+const SCALAR_RESULT_SLOTS: usize = 8;
+
+thread_local! {
+    static SCALAR_RESULTS: UnsafeCell<[XLOPER12; SCALAR_RESULT_SLOTS]> =
+        const { UnsafeCell::new([XLOPER12 { xltype: xltypeNil, val: Xloper12Value { w: 0 } }; SCALAR_RESULT_SLOTS]) };
+    static NEXT_SCALAR_RESULT: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Copies a number, boolean or error into the next of this thread's result slots and
+/// returns a pointer to it. These own no memory, so they need no xlbitDLLFree and Excel
+/// does not call xlAutoFree12 for them. Returns None for anything else.
+fn scalar_result(v: &Variant) -> Option<LPXLOPER12> {
+    if !matches!(v.0.xltype, xltypeNum | xltypeBool | xltypeErr | xltypeInt) {
+        return None;
+    }
+    let slot = NEXT_SCALAR_RESULT.with(|next| {
+        let slot = next.get();
+        next.set((slot + 1) % SCALAR_RESULT_SLOTS);
+        slot
+    });
+    Some(SCALAR_RESULTS.with(|results| unsafe {
+        let p = (*results.get()).as_mut_ptr().add(slot);
+        *p = v.0;
+        p
+    }))
+}
+
+/// Construct a LPXlOPER12 from a Variant. Numbers, booleans and errors go in a slot owned
+/// by the thread; anything else is boxed, and Excel hands it back to xlAutoFree12 once it
+/// has taken a copy if it carries xlbitDLLFree.
 impl From<Variant> for LPXLOPER12 {
     fn from(v: Variant) -> LPXLOPER12 {
+        if let Some(p) = scalar_result(&v) {
+            return p;
+        }
         // allocate Variant and return its pointer (as *mut xloper12)
         let p: *mut Variant = Box::into_raw(Box::new(v));
+        #[cfg(feature = "memory-tracking")]
+        crate::memory::returned();
         p.cast() // *mut XLOPER12
     }
 }
@@ -1065,6 +1206,29 @@ impl From<Variant> for LPXLOPER12 {
 // 9. UTILITY FUNCTIONS
 // --------------------------------------------------------------------------------------------------------------------
 
+// Gets the count of rows and columns of an XLOPER12, as Variant::dim
+fn xloper_dim(v: &XLOPER12) -> (usize, usize) {
+    match v.xltype & xltypeMask {
+        xltypeMulti => {
+            v.val.as_array(v.xltype)
+                .map(|arr| arr.dim())
+                .unwrap_or((0, 0))
+        },
+        xltypeSRef => {
+            v.val.as_sref(v.xltype)
+                .map(|sref| sref.dim())
+                .unwrap_or((0, 0))
+        },
+        xltypeRef => {
+            v.val.as_mref(v.xltype)
+                .and_then(|mref| get_mref_dim_safe(mref.lpmref))
+                .unwrap_or((0, 0))
+        },
+        xltypeMissing => (0, 0),
+        _ => (1, 1),
+    }
+}
+
 // Gets the array size of a multi-cell reference. If the reference is badly formed, returns None
 fn get_mref_dim_safe(mref: *const XLMREF12) -> Option<(usize, usize)> {
     if mref.is_null() {
@@ -1078,4 +1242,111 @@ fn get_mref_dim_safe(mref: *const XLMREF12) -> Option<(usize, usize)> {
         }
         Some((*mref).reftbl[0].dim())
     }
-}
\ No newline at end of file
+}
+// --------------------------------------------------------------------------------------------------------------------
+// 10. BORROWED ARGUMENTS
+// --------------------------------------------------------------------------------------------------------------------
+
+/// A borrowed view of an XLOPER12 that Excel owns, such as an argument to one of our
+/// functions. Conversions read Excel's memory in place, so a large array argument is
+/// converted straight into the Rust type without being copied into a Variant first.
+/// The generated wrappers of `#[xl_func]` convert their arguments through this.
+#[derive(Clone, Copy)]
+pub struct VariantRef<'a>(&'a XLOPER12);
+
+impl<'a> VariantRef<'a> {
+    /// Borrows an XLOPER12.
+    ///
+    /// # Safety
+    ///
+    /// The pointer must be valid, and stay valid and unchanged for `'a`. An argument Excel
+    /// passes to a function is, until the function returns.
+    pub unsafe fn from_ptr(xloper: LPXLOPER12) -> VariantRef<'a> {
+        VariantRef(unsafe { &*xloper })
+    }
+
+    /// The type of the value, without the ownership bits
+    pub fn xltype(&self) -> u32 {
+        self.0.xltype & xltypeMask
+    }
+
+    pub fn is_missing_or_null(&self) -> bool {
+        matches!(self.xltype(), xltypeMissing | xltypeNil)
+    }
+
+    /// Gets the count of rows and columns, as [`Variant::dim`]
+    pub fn dim(&self) -> (usize, usize) {
+        xloper_dim(self.0)
+    }
+
+    /// Borrows the element at the given column and row of an array, or a scalar as a
+    /// one-element array. Returns None if the column or row is out of bounds.
+    pub fn at(&self, column: usize, row: usize) -> Option<VariantRef<'a>> {
+        if self.xltype() != xltypeMulti {
+            return (column == 0 && row == 0).then_some(*self);
+        }
+        self.0.val.as_array(self.0.xltype).and_then(|array| array.get_2d(row, column)).map(VariantRef)
+    }
+
+    /// Copies the value into a Variant that does not own any of its data, as
+    /// `Variant::from(LPXLOPER12)` does
+    pub fn to_variant(&self) -> Variant {
+        let mut result = Variant(*self.0);
+        result.0.xltype &= xltypeMask; // no ownership bits
+        result
+    }
+}
+
+impl From<VariantRef<'_>> for Variant {
+    fn from(v: VariantRef<'_>) -> Variant {
+        v.to_variant()
+    }
+}
+
+impl From<VariantRef<'_>> for String {
+    fn from(v: VariantRef<'_>) -> String {
+        String::from(v.0)
+    }
+}
+
+impl TryFrom<VariantRef<'_>> for f64 {
+    type Error = XLAddError;
+
+    fn try_from(v: VariantRef<'_>) -> Result<Self, Self::Error> {
+        number_from(v.0)
+    }
+}
+
+impl TryFrom<VariantRef<'_>> for bool {
+    type Error = XLAddError;
+
+    fn try_from(v: VariantRef<'_>) -> Result<Self, Self::Error> {
+        boolean_from(v.0)
+    }
+}
+
+impl From<VariantRef<'_>> for i32 {
+    fn from(v: VariantRef<'_>) -> i32 {
+        number_from(v.0).unwrap_or(0.0) as i32
+    }
+}
+
+impl From<VariantRef<'_>> for u32 {
+    fn from(v: VariantRef<'_>) -> u32 {
+        number_from(v.0).unwrap_or(0.0).max(0.0) as u32
+    }
+}
+
+impl TryFrom<VariantRef<'_>> for Vec<f64> {
+    type Error = XLAddError;
+
+    fn try_from(v: VariantRef<'_>) -> Result<Self, Self::Error> {
+        numbers_from(v.0)
+    }
+}
+
+impl From<VariantRef<'_>> for Vec<String> {
+    fn from(v: VariantRef<'_>) -> Vec<String> {
+        strings_from(v.0)
+    }
+}
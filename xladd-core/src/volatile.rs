@@ -0,0 +1,26 @@
+//! Volatility decided at run time. Registering a function as volatile makes Excel
+//! recalculate it on every calculation, whatever its arguments. A function registered as
+//! a macro sheet equivalent, with `#[xl_func(macro_type)]` or a type string ending in `#`,
+//! can instead call [`set_volatile`] each time it runs, for example to stay volatile only
+//! while its "live" argument is TRUE:
+//!
+//! #[xl_func(macro_type)]
+//! fn quote(ticker: String, live: bool) -> f64 {
+//!     volatile::set_volatile(live);
+//!     last_price(&ticker)
+//! }
+//!
+//! The setting belongs to the calling cell, and lasts until the function sets it again.
+//! Macro sheet equivalents cannot be thread-safe.
+
+use crate::entrypoint::excel12;
+use crate::variant::Variant;
+use crate::xlcall::xlfVolatile;
+use log::trace;
+
+/// Makes the calling cell volatile or not. Only has an effect in a function registered
+/// as a macro sheet equivalent.
+pub fn set_volatile(volatile: bool) {
+    let result = excel12(xlfVolatile, &mut [Variant::from(volatile)]);
+    trace!("Volatile({}): result = {}", volatile, result);
+}
@@ -0,0 +1,110 @@
+//! Wall-clock limits on worksheet functions. A function marked `#[xl_func(timeout = 30)]`
+//! runs on a thread of its own while the calling thread waits at most that many seconds;
+//! if the function has not finished by then, the cell gets a timeout message and the call
+//! is logged, so one bad input cannot hang Excel indefinitely.
+//!
+//! Threads cannot be stopped from outside, so a function that overran keeps running until
+//! it returns, and its result is thrown away. Long loops should check [`should_stop`] and
+//! give up early once it returns true.
+//!
+//! Arguments and results of such functions must be `Send`, as they cross threads.
+
+use crate::guard;
+use log::warn;
+
+use std::cell::RefCell;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How long xlAutoClose waits for overrunning functions to give up
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Stop flags of the functions running on watchdog threads
+static STOP_FLAGS: Mutex<Vec<Arc<AtomicBool>>> = Mutex::new(Vec::new());
+/// Watchdog threads that have not finished
+static RUNNING: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// Set on a watchdog thread once its function has overrun
+    static STOP: RefCell<Option<Arc<AtomicBool>>> = const { RefCell::new(None) };
+}
+
+/// A function that did not finish within its time limit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimedOut {
+    pub function: &'static str,
+    pub limit: Duration,
+}
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#TIMEOUT: {} did not finish within {:?}", self.function, self.limit)
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// Whether the current function has overrun its time limit, or the add-in is closing.
+/// Always false outside functions with a timeout.
+pub fn should_stop() -> bool {
+    STOP.with(|stop| stop.borrow().as_ref().is_some_and(|stop| stop.load(Ordering::Relaxed)))
+}
+
+/// Runs `work` on a new thread, waiting up to `limit` for it to finish. A panic in the
+/// work is passed on to the caller.
+pub fn run<R: Send + 'static>(
+    function: &'static str,
+    limit: Duration,
+    work: impl FnOnce() -> R + Send + 'static,
+) -> Result<R, TimedOut> {
+    let stop = Arc::new(AtomicBool::new(false));
+    let (sender, receiver) = channel();
+    let thread_stop = stop.clone();
+    RUNNING.fetch_add(1, Ordering::SeqCst);
+    let spawned = std::thread::Builder::new().name(format!("xladd-watchdog-{}", function)).spawn(move || {
+        STOP.with(|stop| *stop.borrow_mut() = Some(thread_stop));
+        let result = panic::catch_unwind(AssertUnwindSafe(|| guard::guarded(work)));
+        // The caller may have given up waiting already
+        let _ = sender.send(result);
+        RUNNING.fetch_sub(1, Ordering::SeqCst);
+    });
+    if let Err(e) = spawned {
+        RUNNING.fetch_sub(1, Ordering::SeqCst);
+        panic!("cannot start a thread for {}: {}", function, e);
+    }
+    {
+        let mut flags = STOP_FLAGS.lock().unwrap_or_else(|e| e.into_inner());
+        flags.retain(|flag| Arc::strong_count(flag) > 1);
+        flags.push(stop.clone());
+    }
+    match receiver.recv_timeout(limit) {
+        Ok(Ok(value)) => Ok(value),
+        Ok(Err(payload)) => panic::resume_unwind(payload),
+        Err(RecvTimeoutError::Disconnected) => panic!("{} ended without a result", function),
+        Err(RecvTimeoutError::Timeout) => {
+            stop.store(true, Ordering::Relaxed);
+            warn!("{} overran its time limit of {:?}; it is left to finish in the background", function, limit);
+            Err(TimedOut { function, limit })
+        }
+    }
+}
+
+/// Signals every overrunning function to stop and waits a while for them to finish, as
+/// they must not outlive the xll. This is called from xlAutoClose.
+pub fn shutdown() {
+    for flag in STOP_FLAGS.lock().unwrap_or_else(|e| e.into_inner()).drain(..) {
+        flag.store(true, Ordering::Relaxed);
+    }
+    let start = Instant::now();
+    while RUNNING.load(Ordering::SeqCst) > 0 && start.elapsed() < SHUTDOWN_TIMEOUT {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let running = RUNNING.load(Ordering::SeqCst);
+    if running > 0 {
+        warn!("{} timed-out functions are still running as the add-in closes", running);
+    }
+}
@@ -0,0 +1,100 @@
+//! A welcome notice with the add-in's version and where to get help, shown once a session
+//! after the functions are registered. It is off unless `show` is set under `[welcome]` in
+//! the [settings](crate::config). The user can tick "Don't show this again", which holds
+//! until the version changes, so the notice comes back once after each update.
+//!
+//! Call [`show_after`] from xlAutoOpen with what the registration did:
+//!
+//! let summary = reg.register_all_functions();
+//! xladd_core::welcome::show_after(&summary);
+
+use crate::about;
+use crate::cache;
+use crate::config;
+use crate::dialog::Dialog;
+use crate::registrator::{CommandRegistration, RegistrationSummary};
+use crate::scheduler;
+use log::{debug, warn};
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// How long after startup the notice is shown, as Excel is not ready for dialogs while it
+/// is still opening the add-in
+const DELAY: Duration = Duration::from_secs(1);
+
+static SHOWN: AtomicBool = AtomicBool::new(false);
+
+/// Shows the notice shortly, if the settings ask for it, some functions were registered,
+/// it has not been shown this session and the user has not dismissed this version
+pub fn show_after(summary: &RegistrationSummary) {
+    if !config::current().welcome.show || summary.registered == 0 || is_dismissed() {
+        return;
+    }
+    if SHOWN.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    scheduler::schedule_in(DELAY, show);
+}
+
+/// Shows the notice now, whatever the settings say. This can only be called from a
+/// command.
+pub fn show() {
+    let config = config::current();
+    let build = about::build();
+    let mut dialog = Dialog::new(&format!("Welcome to {}", build.name)).label(&format!("Version {}", build.version));
+    if let Some(message) = &config.welcome.message {
+        for line in message.lines() {
+            dialog = dialog.label(line);
+        }
+    }
+    if let Some(support_url) = &config.welcome.support_url {
+        dialog = dialog.label(&format!("Help and support: {}", support_url));
+    }
+    let answer = dialog.check_box("dismiss", "Don't show this again", is_dismissed()).show();
+    if let Some(answer) = answer {
+        set_dismissed(answer.checked("dismiss"));
+    }
+}
+
+/// Whether the user has dismissed the notice for this version
+pub fn is_dismissed() -> bool {
+    std::fs::read_to_string(state_path()).is_ok_and(|version| version.trim() == about::build().version)
+}
+
+/// Records that the user has dismissed the notice for this version, or forgets it
+pub fn set_dismissed(dismissed: bool) {
+    let path = state_path();
+    let result = if dismissed {
+        path.parent().map_or(Ok(()), std::fs::create_dir_all).and_then(|()| std::fs::write(&path, about::build().version))
+    } else if path.exists() {
+        std::fs::remove_file(&path)
+    } else {
+        Ok(())
+    };
+    match result {
+        Ok(()) => debug!("welcome notice dismissed: {}", dismissed),
+        Err(e) => warn!("cannot record the welcome notice in {}: {}", path.display(), e),
+    }
+}
+
+/// %LOCALAPPDATA%\<xll name>\welcome.txt, holding the version the notice was dismissed for
+fn state_path() -> PathBuf {
+    let base = std::env::var_os("LOCALAPPDATA").map_or_else(std::env::temp_dir, PathBuf::from);
+    base.join(cache::addin_name()).join("welcome.txt")
+}
+
+/// Command that shows the welcome notice
+#[unsafe(no_mangle)]
+pub extern "system" fn xl_welcome() -> i32 {
+    show();
+    1
+}
+
+inventory::submit! {
+    CommandRegistration {
+        xl_name: "xl_welcome",
+        shortcut: "",
+    }
+}
@@ -0,0 +1,146 @@
+//! State shared between functions within one workbook. Each open workbook has its own
+//! keyed store, so cached intermediate results computed for one file are never seen by
+//! another file open in the same session. A workbook's store is dropped when it closes:
+//! with the `events` feature this happens as Excel closes it, otherwise call
+//! [`prune_closed`] from a command, for example on a timer.
+//!
+//! # Example
+//!
+//! #[xl_func]
+//! fn portfolio_value(date: f64) -> Result<f64, Box<dyn Error>> {
+//!     let state = WorkbookState::current().ok_or("no calling workbook")?;
+//!     let prices = state.get_or_insert_with("prices", load_prices);
+//!     Ok(prices.value_at(date))
+//! }
+
+use crate::entrypoint::excel12;
+use crate::variant::Variant;
+use crate::xlcall::{xlSheetNm, xlfCaller, xlfDocuments, xlfGetDocument};
+
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+type Value = Arc<dyn Any + Send + Sync>;
+
+/// GET.DOCUMENT type number for the name of the active workbook
+const GET_DOCUMENT_WORKBOOK_NAME: i32 = 88;
+/// DOCUMENTS type number for all open workbooks, including add-ins
+const DOCUMENTS_ALL: i32 = 3;
+
+/// Stores by lower-cased workbook name, as Excel compares workbook names without case
+static STORES: Mutex<BTreeMap<String, BTreeMap<String, Value>>> = Mutex::new(BTreeMap::new());
+
+fn stores() -> MutexGuard<'static, BTreeMap<String, BTreeMap<String, Value>>> {
+    STORES.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// The store of one workbook
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkbookState {
+    workbook: String,
+}
+
+impl WorkbookState {
+    /// The store of the workbook containing the calling cell, or of the active workbook
+    /// when called from a command
+    pub fn current() -> Option<WorkbookState> {
+        calling_workbook().map(WorkbookState::named)
+    }
+
+    /// The store of the named workbook, such as "Book1.xlsx"
+    pub fn named(workbook: impl AsRef<str>) -> WorkbookState {
+        WorkbookState { workbook: workbook.as_ref().to_lowercase() }
+    }
+
+    /// The workbook name, in lower case
+    pub fn workbook(&self) -> &str {
+        &self.workbook
+    }
+
+    /// Looks up a value. Returns None if there is nothing stored under `key` or it is of a
+    /// different type.
+    pub fn get<T: Any + Send + Sync>(&self, key: &str) -> Option<Arc<T>> {
+        let value = stores().get(&self.workbook)?.get(key)?.clone();
+        value.downcast::<T>().ok()
+    }
+
+    /// Stores a value, replacing anything already stored under `key`
+    pub fn set<T: Any + Send + Sync>(&self, key: &str, value: T) {
+        stores().entry(self.workbook.clone()).or_default().insert(key.to_string(), Arc::new(value));
+    }
+
+    /// Looks up a value, calculating and storing it first if it is missing. The store is
+    /// not locked while `f` runs, so two callers may both calculate it; the last one wins.
+    pub fn get_or_insert_with<T: Any + Send + Sync>(&self, key: &str, f: impl FnOnce() -> T) -> Arc<T> {
+        if let Some(value) = self.get::<T>(key) {
+            return value;
+        }
+        let value = Arc::new(f());
+        stores()
+            .entry(self.workbook.clone())
+            .or_default()
+            .insert(key.to_string(), value.clone());
+        value
+    }
+
+    /// Removes a value. Returns false if nothing was stored under `key`.
+    pub fn remove(&self, key: &str) -> bool {
+        stores().get_mut(&self.workbook).is_some_and(|store| store.remove(key).is_some())
+    }
+
+    /// Removes everything stored for this workbook
+    pub fn clear(&self) {
+        stores().remove(&self.workbook);
+    }
+
+    /// The keys stored for this workbook, in order
+    pub fn keys(&self) -> Vec<String> {
+        stores().get(&self.workbook).map(|store| store.keys().cloned().collect()).unwrap_or_default()
+    }
+}
+
+/// Works out the workbook of the calling cell from its sheet name, such as "[Book1.xlsx]Sheet1"
+fn calling_workbook() -> Option<String> {
+    let caller = excel12(xlfCaller, &mut []);
+    let name = if caller.is_ref() {
+        String::from(&excel12(xlSheetNm, &mut [caller]))
+    } else {
+        String::from(&excel12(xlfGetDocument, &mut [Variant::from(GET_DOCUMENT_WORKBOOK_NAME)]))
+    };
+    let workbook = match name.strip_prefix('[') {
+        Some(rest) => rest.split_once(']').map_or(rest, |(workbook, _)| workbook),
+        None => name.as_str(),
+    };
+    if workbook.is_empty() {
+        None
+    } else {
+        Some(workbook.to_string())
+    }
+}
+
+/// Drops the stores of workbooks that are no longer open. This must be called from a command.
+pub fn prune_closed() {
+    let open = excel12(xlfDocuments, &mut [Variant::from(DOCUMENTS_ALL)]);
+    let (columns, rows) = open.dim();
+    let open: Vec<String> = (0..rows)
+        .flat_map(|row| (0..columns).map(move |column| (column, row)))
+        .map(|(column, row)| String::from(&open.at(column, row)).to_lowercase())
+        .collect();
+    stores().retain(|workbook, _| open.contains(workbook));
+}
+
+/// Drops every workbook's store
+pub fn clear_all() {
+    stores().clear();
+}
+
+/// Drops each workbook's store as it closes. This is called from Reg::register_all_commands.
+#[cfg(feature = "events")]
+pub(crate) fn watch_workbook_close() {
+    crate::events::on_workbook_before_close(|workbook| {
+        if let Some(name) = workbook.get("Name").ok().and_then(|n| n.to_string_value()) {
+            WorkbookState::named(name).clear();
+        }
+    });
+}
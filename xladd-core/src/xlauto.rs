@@ -3,6 +3,18 @@
 
 use crate::xlcall::LPXLOPER12;
 use crate::variant::Variant;
+use crate::background;
+use crate::cache;
+use crate::commands;
+use crate::console;
+use crate::debounce;
+use crate::handles;
+use crate::menu;
+use crate::namespace;
+use crate::registrator;
+use crate::scheduler;
+use crate::watchdog;
+use crate::workbook_state;
 
 // pub extern "stdcall" fn xlAutoOpen() implemented in lib.rs as it calls the 
 // registration of all used defined functions
@@ -12,10 +24,45 @@ pub extern "system" fn xlAutoFree12(px_free: LPXLOPER12) {
     // Rebuild the Box<Variant> so Variant::drop runs and frees string/array memory
     // let _ = unsafe { Box::<Variant>::from_raw(px_free.cast()) };
     let _ = unsafe { Box::<Variant>::from_raw(px_free.cast()) };
+    #[cfg(feature = "memory-tracking")]
+    crate::memory::freed_by_excel();
+}
+
+/// Called by Excel for a function it needs that is not registered yet, which happens
+/// when registration is deferred and the function is used before its batch comes round
+#[unsafe(no_mangle)]
+pub extern "system" fn xlAutoRegister12(px_name: LPXLOPER12) -> LPXLOPER12 {
+    let name = String::from(&Variant::from(px_name));
+    LPXLOPER12::from(registrator::register_on_demand(&name))
 }
 
 /// Excel exit point - called when Excel unloads the add-in
 #[unsafe(no_mangle)]
 pub extern "system" fn xlAutoClose() -> i32 {
+    #[cfg(feature = "hot-reload")]
+    crate::hot_reload::stop();
+    background::shutdown();
+    // Excel would otherwise try to run our timer command after we are unloaded
+    scheduler::cancel_all();
+    registrator::clear_deferred();
+    namespace::leave();
+    commands::unbind_all_keys();
+    menu::remove_all_menus();
+    #[cfg(feature = "async")]
+    crate::async_udf::shutdown();
+    watchdog::shutdown();
+    #[cfg(feature = "rayon")]
+    crate::pool::shutdown();
+    #[cfg(feature = "events")]
+    crate::events::disconnect();
+    #[cfg(feature = "ribbon")]
+    crate::ribbon::uninstall_ribbon();
+    #[cfg(feature = "rtd")]
+    crate::rtd::uninstall_rtd();
+    cache::clear();
+    debounce::clear();
+    handles::clear();
+    workbook_state::clear_all();
+    console::close();
     1 // Success
 }
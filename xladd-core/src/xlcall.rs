@@ -40,15 +40,46 @@ pub const xlerrNum: u32 = 36;
 pub const xlerrNA: u32 = 42;
 pub const xlerrGettingData: u32 = 43;
 pub const xltypeMulti: u32 = 64;
+pub const xlfNow: u32 = 74;
+pub const xlfSetName: u32 = 88;
+pub const xlfCaller: u32 = 89;
+pub const xlfDocuments: u32 = 93;
+pub const xlfInput: u32 = 104;
 pub const xltypeMissing: u32 = 128;
 pub const xlfRegister: u32 = 149;
+pub const xlfAddMenu: u32 = 152;
+pub const xlfAddCommand: u32 = 153;
+pub const xlfDeleteMenu: u32 = 158;
+pub const xlfDialogBox: u32 = 161;
+pub const xlfGetWorkspace: u32 = 186;
+pub const xlfGetDocument: u32 = 188;
+pub const xlfUnregister: u32 = 201;
+pub const xlfVolatile: u32 = 237;
+pub const xlUDF: u32 = 255;
+pub const xlfEvaluate: u32 = 257;
+pub const xlfRegisterId: u32 = 267;
+pub const xlfRtd: u32 = 379;
 pub const xltypeNil: u32 = 256;
 pub const xltypeSRef: u32 = 1024;
 pub const xltypeInt: u32 = 2048;
 pub const xlbitXLFree: u32 = 4096;
 pub const xlbitDLLFree: u32 = 16384;
+pub const xlCoerce: u32 = 16386;
+pub const xlSheetNm: u32 = 16389;
+pub const xlAbort: u32 = 16390;
+pub const xlGetHwnd: u32 = 16392;
 pub const xlGetName: u32 = 16393;
+pub const xlAsyncReturn: u32 = 16400;
+pub const xlEventRegister: u32 = 16401;
 pub const xlFree: u32 = 16384;
+pub const xlCommand: u32 = 32768;
+pub const xlcCalculation: u32 = 32 | xlCommand;
+pub const xlcCalculateNow: u32 = 33 | xlCommand;
+pub const xlcOnKey: u32 = 114 | xlCommand;
+pub const xlcAlert: u32 = 118 | xlCommand;
+pub const xlcMessage: u32 = 122 | xlCommand;
+pub const xlcEcho: u32 = 141 | xlCommand;
+pub const xlcOnTime: u32 = 148 | xlCommand;
 
 pub const xltypeMask: u32 = !(xlbitDLLFree | xlbitXLFree);
 
@@ -102,10 +133,11 @@ pub type XLREF12 = xlref12;
 pub type LPXLREF12 = *mut xlref12;
 
 impl xlref12 {
-    /// Get dimensions as (columns, rows)
+    /// Get dimensions as (columns, rows). A reference whose last row or column is before
+    /// its first is treated as empty.
     pub fn dim(&self) -> (usize, usize) {
-        let rows = 1 + (self.rwLast - self.rwFirst) as usize;
-        let cols = 1 + (self.colLast - self.colFirst) as usize;
+        let rows = (1 + self.rwLast as i64 - self.rwFirst as i64).max(0) as usize;
+        let cols = (1 + self.colLast as i64 - self.colFirst as i64).max(0) as usize;
         (cols, rows)
     }
 }
@@ -295,14 +327,20 @@ pub struct Xloper12Array {
 }
 
 impl Xloper12Array {
-    /// Get dimensions safely
+    /// Get dimensions safely. An array with no elements, a negative dimension or no
+    /// element pointer is treated as 0x0.
     pub fn dim(&self) -> (usize, usize) {
-        (self.columns as usize, self.rows as usize)
+        if self.lparray.is_null() || self.rows <= 0 || self.columns <= 0 {
+            (0, 0)
+        } else {
+            (self.columns as usize, self.rows as usize)
+        }
     }
     
     /// Get total number of elements
     pub fn len(&self) -> usize {
-        (self.rows * self.columns) as usize
+        let (columns, rows) = self.dim();
+        columns * rows
     }
     
     /// Safely get element at index
@@ -316,8 +354,9 @@ impl Xloper12Array {
     
     /// Safely get element at (row, col)
     pub fn get_2d(&self, row: usize, col: usize) -> Option<&xloper12> {
-        if row < self.rows as usize && col < self.columns as usize {
-            let index = row * self.columns as usize + col;
+        let (columns, rows) = self.dim();
+        if row < rows && col < columns {
+            let index = row * columns + col;
             self.get(index)
         } else {
             None
@@ -0,0 +1,240 @@
+//! Registration, the wrappers #[xl_func] generates and the add-in's calls into Excel,
+//! tested against the mock Excel host. Run with
+//!
+//! cargo test -p xladd-core --features testing
+
+use xladd_core::background;
+use xladd_core::cache;
+use xladd_core::guard;
+use xladd_core::handles;
+use xladd_core::paging;
+use xladd_core::mock_excel::{MockExcel, SHEET};
+use xladd_core::progress::ProgressReporter;
+use xladd_core::registrator::{self, inventory, Reg};
+use xladd_core::scheduler;
+use xladd_core::testing;
+use xladd_core::variant::{Variant, VariantRef};
+use xladd_core::workbook_state::WorkbookState;
+use xladd_core::xlcall::{xlAbort, xlUDF, xlcOnTime, xlerrNA, xlerrValue, xlfCaller, xlfNow, LPXLOPER12};
+use xladd_derive::xl_func;
+
+/// Adds two numbers
+/// * a: The first number
+/// * b: The second number
+#[xl_func(category="Mock")]
+fn mock_add(a: f64, b: f64) -> f64 {
+    a + b
+}
+
+#[xl_func]
+fn mock_join(parts: Vec<String>) -> String {
+    parts.join("-")
+}
+
+#[xl_func]
+fn mock_count(steps: f64, progress: &mut ProgressReporter) -> Result<f64, String> {
+    for step in 0..steps as usize {
+        if !progress.step(step + 1, steps as usize) {
+            return Err("cancelled".to_string());
+        }
+    }
+    Ok(steps)
+}
+
+/// A copy of a function's result, which is handed back as Excel would
+fn result_of(result: LPXLOPER12) -> Variant {
+    let value = unsafe { VariantRef::from_ptr(result) }.to_variant().clone();
+    unsafe { testing::free_result(result) };
+    value
+}
+
+#[test]
+fn registers_functions_with_their_strings() {
+    let excel = MockExcel::install();
+    let summary = Reg::new().register_all_functions();
+    assert!(summary.failed.is_empty(), "failed: {:?}", summary.failed);
+
+    let registration = excel.registration("xl_mock_add").expect("xl_mock_add is registered");
+    assert_eq!(registration.arg_types, "QQQ");
+    assert_eq!(registration.function_text, "xl_mock_add");
+    assert_eq!(registration.argument_text, "a,b");
+    assert_eq!(registration.macro_type, 1.0);
+    assert_eq!(registration.category, "Mock");
+    assert_eq!(registration.function_help, "Adds two numbers");
+    // The last description is padded, as Excel cuts off its final character
+    assert_eq!(registration.argument_help, ["The first number", "The second number.."]);
+    assert_eq!(registrator::register_id("xl_mock_add"), Some(registration.register_id));
+}
+
+#[test]
+fn wrappers_convert_arguments_and_results() {
+    let _excel = MockExcel::install();
+    let mut a = Variant::from(1.5);
+    let mut b = Variant::from(2.5);
+    let sum = result_of(xl_mock_add(testing::arg(&mut a), testing::arg(&mut b)));
+    assert_eq!(f64::try_from(&sum).ok(), Some(4.0));
+
+    let mut parts = testing::strings(1, 3);
+    let joined = result_of(xl_mock_join(testing::arg(&mut parts)));
+    assert_eq!(String::from(&joined), "r0c0-r0c1-r0c2");
+}
+
+#[test]
+fn wrappers_report_bad_and_missing_arguments() {
+    let _excel = MockExcel::install();
+    let mut text = Variant::from("not a number");
+    let mut missing = Variant::missing();
+    let mut b = Variant::from(2.5);
+    let bad = result_of(xl_mock_add(testing::arg(&mut text), testing::arg(&mut b)));
+    assert!(String::from(&bad).starts_with("Conversion error"), "{}", bad);
+    let absent = result_of(xl_mock_add(testing::arg(&mut missing), testing::arg(&mut b)));
+    assert_eq!(String::from(&absent), "Missing argument");
+}
+
+#[test]
+fn calls_own_functions_by_register_id() {
+    let excel = MockExcel::install();
+    Reg::new().register_all_functions();
+    excel.answer(xlUDF, Variant::from(42.0)).clear_calls();
+
+    let result = registrator::call_own_function("xl_mock_add", &[Variant::from(1.0), Variant::from(2.0)]);
+    assert_eq!(f64::try_from(&result).ok(), Some(42.0));
+    let calls = excel.calls_to(xlUDF);
+    assert_eq!(calls.len(), 1);
+    assert_eq!(calls[0].args.len(), 3);
+    assert_eq!(f64::try_from(&calls[0].args[0]).ok(), registrator::register_id("xl_mock_add"));
+}
+
+#[test]
+fn finds_the_workbook_of_the_calling_cell() {
+    let excel = MockExcel::install();
+    excel.sheet(SHEET, "[Prices.xlsx]Rates").caller(0, 0);
+    let state = WorkbookState::current().expect("the caller is on a sheet");
+    assert_eq!(state.workbook(), "Prices.xlsx");
+    assert_eq!(excel.calls_to(xlfCaller).len(), 1);
+}
+
+#[test]
+fn collects_handles_no_longer_shown() {
+    let excel = MockExcel::install();
+    excel.caller(2, 3);
+    let handle = handles::insert("Curve", 42_i32);
+    excel.cell(SHEET, 2, 3, Variant::from(handle.as_str()));
+    assert_eq!(handles::collect_garbage(), 0);
+    assert!(handles::contains(&handle));
+
+    excel.cell(SHEET, 2, 3, Variant::from("something else"));
+    assert_eq!(handles::collect_garbage(), 1);
+    assert!(!handles::contains(&handle));
+}
+
+#[test]
+fn pages_through_stored_results() {
+    let excel = MockExcel::install();
+    excel.caller(0, 0);
+    let rows: Vec<Vec<Variant>> = (0..5).map(|row| vec![Variant::from(row as f64), Variant::from("x")]).collect();
+    let handle = paging::insert("Rows", Variant::from(rows));
+    assert_eq!(paging::dim(&handle).unwrap(), (5, 2));
+
+    let last = paging::page(&handle, 3, 2).unwrap();
+    assert_eq!(last.dim(), (2, 1));
+    assert_eq!(f64::try_from(&last.at(0, 0)).unwrap(), 4.0);
+    assert!(paging::page(&handle, 4, 2).is_err());
+    assert!(paging::page(&handle, 0, 2).is_err());
+
+    let mut handle_arg = Variant::from(handle.as_str());
+    let mut page_arg = Variant::from(9.0);
+    let mut page_size = Variant::missing();
+    let result = result_of(paging::xl_page(testing::arg(&mut handle_arg), testing::arg(&mut page_arg), testing::arg(&mut page_size)));
+    assert_eq!(result.to_string(), Variant::from_err(xlerrNA).to_string());
+    let mut unknown = Variant::from("Rows:999");
+    let mut first_page = Variant::missing();
+    let result = result_of(paging::xl_page(testing::arg(&mut unknown), testing::arg(&mut first_page), testing::arg(&mut page_size)));
+    assert_eq!(result.to_string(), Variant::from_err(xlerrValue).to_string());
+}
+
+#[test]
+fn esc_cancels_long_running_functions() {
+    let excel = MockExcel::install();
+    let mut steps = Variant::from(3.0);
+    let finished = result_of(xl_mock_count(testing::arg(&mut steps)));
+    assert_eq!(f64::try_from(&finished).ok(), Some(3.0));
+    assert_eq!(excel.calls_to(xlAbort).len(), 1);
+
+    excel.answer(xlAbort, Variant::from(true));
+    let cancelled = result_of(xl_mock_count(testing::arg(&mut steps)));
+    assert_eq!(String::from(&cancelled), "cancelled");
+}
+
+#[test]
+fn cache_keys_tell_arguments_apart() {
+    let key = |mut value: Variant| cache::key(&[testing::arg(&mut value)]);
+    assert_eq!(key(Variant::from("rate")), key(Variant::from("rate")));
+    assert_ne!(key(Variant::from("rate")), key(Variant::from("rates")));
+    assert_ne!(key(Variant::from(1.0)), key(Variant::from("1")));
+    assert_eq!(key(testing::strings(2, 3)), key(testing::strings(2, 3)));
+    assert_ne!(key(testing::strings(2, 3)), key(testing::strings(3, 2)));
+    assert_ne!(key(Variant::from_err(xlerrNA)), key(Variant::from_err(xlerrValue)));
+}
+
+#[test]
+fn panicking_background_work_gives_value_errors() {
+    let _excel = MockExcel::install();
+    let wait_for = |key: &str| {
+        let start = std::time::Instant::now();
+        while !background::is_ready(key) {
+            assert!(start.elapsed() < std::time::Duration::from_secs(10), "{} never finished", key);
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+    };
+    let count = std::thread::available_parallelism().map_or(2, |n| n.get());
+    // More panics than workers, so the pool would be empty if they ended the threads
+    for i in 0..=count {
+        let key = format!("panics|{}", i);
+        let calculating = background::compute(&key, || -> f64 { panic!("bad job") });
+        assert_eq!(String::from(&calculating), background::CALCULATING);
+        wait_for(&key);
+        assert_eq!(background::compute(&key, || 0.0).to_string(), "#VALUE");
+    }
+    background::compute("after", || 42.0);
+    wait_for("after");
+    assert_eq!(f64::try_from(&background::compute("after", || 0.0)).ok(), Some(42.0));
+    background::clear();
+}
+
+#[test]
+fn timers_survive_early_ticks_and_panics() {
+    let excel = MockExcel::install();
+    let second = 1.0 / 86_400.0;
+    excel.answer(xlfNow, Variant::from(100.0));
+    let id = scheduler::schedule_in(std::time::Duration::from_secs(60), || panic!("bad timer"));
+    let due = 100.0 + 60.0 * second;
+
+    // Excel runs the command half a second early, so the timer must be asked for again
+    excel.answer(xlfNow, Variant::from(due - 0.5 * second)).clear_calls();
+    assert_eq!(scheduler::xl_timer_tick(), 1);
+    let calls = excel.calls_to(xlcOnTime);
+    assert_eq!(calls.len(), 1);
+    assert_eq!(f64::try_from(&calls[0].args[0]).ok(), Some(due));
+
+    // Once due, the panic is caught rather than unwinding into Excel
+    excel.answer(xlfNow, Variant::from(due));
+    assert_eq!(scheduler::xl_timer_tick(), 1);
+    assert!(!scheduler::cancel(id));
+}
+
+#[test]
+fn faults_in_functions_become_errors() {
+    assert_eq!(guard::protect(|| 42), Ok(42));
+    assert_eq!(guard::protect(|| -> i32 { panic!("bad input") }), Err("bad input".to_string()));
+    #[cfg(target_env = "msvc")]
+    {
+        let null = std::hint::black_box(std::ptr::null::<u64>());
+        let fault = guard::protect(|| unsafe { std::ptr::read_volatile(null) }).unwrap_err();
+        assert!(fault.starts_with("access violation (0xC0000005)"), "{}", fault);
+        // The thread carries on, and faults again the next time
+        assert!(guard::protect(|| unsafe { std::ptr::read_volatile(null) }).is_err());
+        let payload = std::panic::catch_unwind(|| guard::guarded(|| unsafe { std::ptr::read_volatile(null) })).unwrap_err();
+        assert_eq!(payload.downcast_ref::<guard::StructuredException>().map(|fault| fault.code), Some(0xC000_0005));
+    }
+}
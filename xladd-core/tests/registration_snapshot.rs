@@ -0,0 +1,64 @@
+//! The registration snapshot, and checking it against a golden file
+
+use std::path::PathBuf;
+use xladd_core::manifest::{self, ManifestError};
+use xladd_core::registrator::inventory;
+use xladd_derive::xl_func;
+
+/// Discounts a cash flow
+/// * amount: The amount paid
+/// * rate: The annual rate
+#[xl_func(category="Snapshot")]
+fn snapshot_discount(amount: f64, rate: f64) -> f64 {
+    amount / (1.0 + rate)
+}
+
+/// A golden file of its own for each test, removed when the test ends
+struct GoldenFile(PathBuf);
+
+impl GoldenFile {
+    fn new(name: &str) -> GoldenFile {
+        let path = std::env::temp_dir().join(format!("xladd-{}-{}.json", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        GoldenFile(path)
+    }
+}
+
+impl Drop for GoldenFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+#[test]
+fn snapshot_is_stable_and_sorted() {
+    let snapshot = manifest::snapshot();
+    assert_eq!(snapshot, manifest::snapshot());
+    assert!(snapshot.contains("\"xl_snapshot_discount\""));
+    assert!(snapshot.contains("\"arg_types\": \"QQQ\""));
+    assert!(snapshot.contains("\"description\": \"The annual rate..\""));
+    let names: Vec<&str> = snapshot.lines().filter(|line| line.starts_with("  \"")).collect();
+    let mut sorted = names.clone();
+    sorted.sort();
+    assert_eq!(names, sorted);
+}
+
+#[test]
+fn check_snapshot_records_then_catches_changes() {
+    if std::env::var_os(manifest::UPDATE_SNAPSHOTS_VAR).is_some() {
+        return;
+    }
+    let golden = GoldenFile::new("snapshot");
+    manifest::check_snapshot(&golden.0).expect("a missing golden file is written");
+    assert!(golden.0.exists());
+    manifest::check_snapshot(&golden.0).expect("the registrations have not changed");
+
+    let text = std::fs::read_to_string(&golden.0).unwrap();
+    std::fs::write(&golden.0, text.replace("Discounts a cash flow", "Discounts a payment")).unwrap();
+    match manifest::check_snapshot(&golden.0) {
+        Err(ManifestError::SnapshotChanged(_, changes)) => {
+            assert_eq!(changes, "changed xl_snapshot_discount: description");
+        }
+        other => panic!("expected a changed snapshot, got {:?}", other),
+    }
+}
@@ -24,6 +24,21 @@ pub fn xl_func(attr: TokenStream, input: TokenStream) -> TokenStream {
                                 &mut single_threaded, &mut param_descriptions_from_attr);
     }
     
+    // cache, cache_ttl = seconds, cache_capacity = count, cache_persist
+    let cache = parse_cache_attributes(&attr_str);
+    let pool = attr_str.split(',').any(|option| option.trim() == "pool");
+    // timeout = seconds
+    let timeout = parse_timeout_attribute(&attr_str);
+    // debounce = milliseconds
+    let debounce = parse_debounce_attribute(&attr_str);
+    let resize = attr_str.split(',').any(|option| option.trim() == "resize");
+    // Registered as a macro sheet equivalent, which may call set_volatile
+    let macro_type = attr_str.split(',').any(|option| option.trim() == "macro_type");
+    // group = "name", for switching functions on and off at runtime
+    let group = parse_attr_value(&attr_str, "group");
+    // help = "https://...", opened by "Help on this function"
+    let help_topic = parse_attr_value(&attr_str, "help").unwrap_or_default();
+
     // Extract function name
     let fn_name = &input_fn.sig.ident;
     
@@ -43,11 +58,18 @@ pub fn xl_func(attr: TokenStream, input: TokenStream) -> TokenStream {
     let mut param_names = Vec::new();
     let mut param_types = Vec::new();
     let mut param_descriptions = std::collections::HashMap::new();
+    // Arguments of the user function, in order; a progress reporter is made by the wrapper
+    let mut call_args = Vec::new();
     
     for input in &input_fn.sig.inputs {
         if let FnArg::Typed(pat_type) = input {
+            if is_progress_reporter(&pat_type.ty) {
+                call_args.push(quote! { &mut xladd_core::progress::ProgressReporter::new(#xl_fn_name_str) });
+                continue;
+            }
             if let Pat::Ident(pat_ident) = pat_type.pat.as_ref() {
                 let param_name = &pat_ident.ident;
+                call_args.push(quote! { #param_name });
                 param_names.push(param_name);
                 param_types.push(&pat_type.ty);
                 
@@ -135,15 +157,18 @@ pub fn xl_func(attr: TokenStream, input: TokenStream) -> TokenStream {
     let arg_conversions = param_names.iter().zip(param_types.iter()).map(|(name, ty)| {
         quote! {
             let #name = {
-                let variant = xladd_core::variant::Variant::from(#name);
+                // Excel owns the argument until we return, so read it where it is
+                let variant = unsafe { xladd_core::variant::VariantRef::from_ptr(#name) };
                 if variant.is_missing_or_null() {
+                    xl_call_span.fail("Missing argument");
                     return xladd_core::xlcall::LPXLOPER12::from(
                         xladd_core::variant::Variant::from("Missing argument")
                     );
                 }
-                match std::convert::TryInto::<#ty>::try_into(&variant) {
+                match std::convert::TryInto::<#ty>::try_into(variant) {
                     Ok(val) => val,
                     Err(e) => {
+                        xl_call_span.fail(&e.to_string());
                         return xladd_core::xlcall::LPXLOPER12::from(
                             // xladd_core::variant::Variant::from(format!("Conversion error: {}", e))
                             xladd_core::variant::Variant::from(&format!("Conversion error: {}", e)) 
@@ -159,8 +184,26 @@ pub fn xl_func(attr: TokenStream, input: TokenStream) -> TokenStream {
         quote! { #name: xladd_core::xlcall::LPXLOPER12 }
     });
     
-    // Generate function call arguments
-    let call_args = param_names.iter().map(|name| quote! { #name });
+    // Functions marked `pool` run on the add-in's shared thread pool
+    let user_call = if pool {
+        quote! { xladd_core::pool::install(move || #fn_name(#(#call_args),*)) }
+    } else {
+        quote! { #fn_name(#(#call_args),*) }
+    };
+    // and those with a timeout on a thread of their own, which is abandoned if it overruns
+    let user_call = match timeout {
+        Some(seconds) => quote! {
+            match xladd_core::watchdog::run(#xl_fn_name_str, std::time::Duration::from_secs_f64(#seconds), move || #user_call) {
+                Ok(result) => result,
+                Err(timed_out) => {
+                    let message = timed_out.to_string();
+                    xl_call_span.fail(&message);
+                    return xladd_core::xlcall::LPXLOPER12::from(xladd_core::variant::Variant::from(&message));
+                }
+            }
+        },
+        None => user_call,
+    };
     
     // Extract return type to determine if it's a Result
     let return_type = &input_fn.sig.output;
@@ -177,26 +220,93 @@ pub fn xl_func(attr: TokenStream, input: TokenStream) -> TokenStream {
         _ => false,
     };
 
+    // Cached functions look up their arguments first, and store successful results
+    let (cache_lookup, cache_store) = match cache {
+        Some((ttl, capacity, persist)) => {
+            let ttl = match ttl {
+                Some(seconds) => quote! { Some(std::time::Duration::from_secs(#seconds)) },
+                None => quote! { None },
+            };
+            let capacity = match capacity {
+                Some(capacity) => quote! { #capacity as usize },
+                None => quote! { xladd_core::cache::CacheConfig::default().capacity },
+            };
+            let lookup = quote! {
+                let xl_cache = xladd_core::cache::CacheConfig {
+                    ttl: #ttl,
+                    capacity: #capacity,
+                    persist: #persist,
+                    version: env!("CARGO_PKG_VERSION"),
+                };
+                let xl_cache_key = xladd_core::cache::key(&[#(#param_names),*]);
+                if let Some(cached) = xladd_core::cache::get(#xl_fn_name_str, xl_cache, &xl_cache_key) {
+                    return xladd_core::xlcall::LPXLOPER12::from(cached);
+                }
+            };
+            let store = quote! {
+                xladd_core::cache::insert(#xl_fn_name_str, xl_cache, xl_cache_key, &result);
+            };
+            (lookup, store)
+        }
+        None => (quote! {}, quote! {}),
+    };
+
+    // Debounced functions hand back their last result for the same arguments while it is
+    // recent, and remember every result, errors included
+    let (debounce_lookup, debounce_store) = match debounce {
+        Some(milliseconds) => {
+            let lookup = quote! {
+                let xl_debounce = std::time::Duration::from_millis(#milliseconds);
+                let xl_debounce_key = xladd_core::cache::key(&[#(#param_names),*]);
+                if let Some(recent) = xladd_core::debounce::recent(#xl_fn_name_str, xl_debounce, &xl_debounce_key) {
+                    return xladd_core::xlcall::LPXLOPER12::from(recent);
+                }
+            };
+            let store = quote! {
+                xladd_core::debounce::record(#xl_fn_name_str, xl_debounce, xl_debounce_key, &result);
+            };
+            (lookup, store)
+        }
+        None => (quote! {}, quote! {}),
+    };
+
+    // Array results of functions marked `resize` grow their calling range to fit, in
+    // versions of Excel without dynamic arrays
+    let resize_result = if resize {
+        quote! { let result = xladd_core::resize::resize(result); }
+    } else {
+        quote! {}
+    };
+
     // Generate different wrapper code based on return type
     let function_call = if is_result_type {
         // For Result<T, E> return types
         quote! {
-            match #fn_name(#(#call_args),*) {
+            match #user_call {
                 Ok(result) => {
-                    xladd_core::xlcall::LPXLOPER12::from(xladd_core::variant::Variant::from(result))
+                    let result = xladd_core::variant::Variant::from(result);
+                    #cache_store
+                    #debounce_store
+                    #resize_result
+                    xladd_core::xlcall::LPXLOPER12::from(result)
                 }
                 Err(e) => {
-                    xladd_core::xlcall::LPXLOPER12::from(
-                        xladd_core::variant::Variant::from(&e.to_string())
-                    )
+                    let message = e.to_string();
+                    xl_call_span.fail(&xladd_core::describe_error!(e));
+                    let result = xladd_core::variant::Variant::from(&message);
+                    #debounce_store
+                    xladd_core::xlcall::LPXLOPER12::from(result)
                 }
             }
         }
     } else {
         // For direct return types (f64, Vec<f64>, etc.)
         quote! {
-            let result = #fn_name(#(#call_args),*);
-            xladd_core::xlcall::LPXLOPER12::from(xladd_core::variant::Variant::from(result))
+            let result = xladd_core::variant::Variant::from(#user_call);
+            #cache_store
+            #debounce_store
+            #resize_result
+            xladd_core::xlcall::LPXLOPER12::from(result)
         }
     };
     
@@ -207,6 +317,14 @@ pub fn xl_func(attr: TokenStream, input: TokenStream) -> TokenStream {
     if !single_threaded {
         reg_string.push('$'); // Thread-safe marker
     }
+    if macro_type {
+        if !single_threaded {
+            return syn::Error::new_spanned(&input_fn.sig, "macro_type functions cannot be threadsafe")
+                .to_compile_error()
+                .into();
+        }
+        reg_string.push('#'); // Macro sheet equivalent marker
+    }
     
     // Generate the parameter names string for registration
     let param_names_str = param_names.iter()
@@ -252,6 +370,100 @@ pub fn xl_func(attr: TokenStream, input: TokenStream) -> TokenStream {
         }
     });
     
+    // Async functions get an extra async handle argument and return nothing; the result
+    // is delivered later through xlAsyncReturn
+    if input_fn.sig.asyncness.is_some() {
+        if cache.is_some() || pool || timeout.is_some() || debounce.is_some() || resize || macro_type {
+            return syn::Error::new_spanned(&input_fn.sig, "cache, pool, timeout, debounce, resize and macro_type are not supported on async functions")
+                .to_compile_error()
+                .into();
+        }
+        let async_conversions = param_names.iter().zip(param_types.iter()).map(|(name, ty)| {
+            quote! {
+                let #name = {
+                    let variant = unsafe { xladd_core::variant::VariantRef::from_ptr(#name) };
+                    if variant.is_missing_or_null() {
+                        xl_call_span.fail("Missing argument");
+                        handle.complete(xladd_core::variant::Variant::from("Missing argument"));
+                        return;
+                    }
+                    match std::convert::TryInto::<#ty>::try_into(variant) {
+                        Ok(val) => val,
+                        Err(e) => {
+                            xl_call_span.fail(&e.to_string());
+                            handle.complete(xladd_core::variant::Variant::from(&format!("Conversion error: {}", e)));
+                            return;
+                        }
+                    }
+                };
+            }
+        });
+        let xl_args = param_names.iter().map(|name| {
+            quote! { #name: xladd_core::xlcall::LPXLOPER12 }
+        });
+        let to_variant = if is_result_type {
+            quote! {
+                match result {
+                    Ok(result) => Ok(xladd_core::variant::Variant::from(result)),
+                    Err(e) => Err((e.to_string(), xladd_core::describe_error!(e))),
+                }
+            }
+        } else {
+            quote! { Ok(xladd_core::variant::Variant::from(result)) }
+        };
+
+        let mut async_reg_string = ">".to_string();
+        async_reg_string.extend(param_names.iter().map(|_| 'Q'));
+        async_reg_string.push('X');
+        if !single_threaded {
+            async_reg_string.push('$');
+        }
+
+        let group_registration = group_registration(&group, &xl_fn_name_str);
+        let expanded = quote! {
+            // The original user function (unchanged)
+            #input_fn
+
+            // Excel wrapper function, which starts the work and returns straight away
+            #[unsafe(no_mangle)]
+            extern "system" fn #xl_fn_name(#(#xl_args,)* async_handle: xladd_core::xlcall::LPXLOPER12) {
+                let started = xladd_core::guard::protect(|| {
+                    let handle = unsafe { xladd_core::async_udf::AsyncHandle::from_raw(async_handle) };
+                    let xl_call_span = xladd_core::instrument::CallSpan::new_async(#xl_fn_name_str, &[#(#param_names),*]);
+                    #(#async_conversions)*
+                    xladd_core::async_udf::spawn(handle, xl_call_span.run(async move {
+                        let result = #fn_name(#(#call_args),*).await;
+                        #to_variant
+                    }));
+                });
+                // A panic before the work started; it has been logged, so just end the call
+                if started.is_err() {
+                    let handle = unsafe { xladd_core::async_udf::AsyncHandle::from_raw(async_handle) };
+                    handle.complete(xladd_core::variant::Variant::from_err(xladd_core::xlcall::xlerrValue));
+                }
+            }
+
+            static #static_args_name: &[xladd_core::registrator::ArgInfo] = &[#(#arg_infos),*];
+
+            inventory::submit! {
+                xladd_core::registrator::FunctionRegistration {
+                    xl_name: #xl_fn_name_str,
+                    arg_types: #async_reg_string,
+                    arg_names: #param_names_str,
+                    category: #category,
+                    description: #excel_description,
+                    arg_infos: #static_args_name,
+                    help_topic: #help_topic,
+                }
+            }
+
+            #group_registration
+        };
+        return TokenStream::from(expanded);
+    }
+
+    let group_registration = group_registration(&group, &xl_fn_name_str);
+
     // Generate the complete macro output
     let expanded = quote! {
         // The original user function (unchanged)
@@ -260,11 +472,21 @@ pub fn xl_func(attr: TokenStream, input: TokenStream) -> TokenStream {
         // Excel wrapper function
         #[unsafe(no_mangle)]
         extern "system" fn #xl_fn_name(#(#xl_args),*) -> xladd_core::xlcall::LPXLOPER12 {
-            // Convert arguments from Excel types to Rust types
-            #(#arg_conversions)*
-            
-            // Call the original function with appropriate error handling
-            #function_call
+            // Span around the call, which does nothing unless xladd-core's tracing feature is on
+            let xl_call_span = xladd_core::instrument::CallSpan::new(#xl_fn_name_str, &[#(#param_names),*]);
+            let _entered = xl_call_span.enter();
+
+            // A panic or access violation gives #VALUE! rather than crashing Excel
+            xl_call_span.protect(|| {
+                #cache_lookup
+                #debounce_lookup
+
+                // Convert arguments from Excel types to Rust types
+                #(#arg_conversions)*
+
+                // Call the original function with appropriate error handling
+                #function_call
+            })
         }
         
         // Create a static array of argument info with proper UPPER_CASE naming
@@ -279,13 +501,297 @@ pub fn xl_func(attr: TokenStream, input: TokenStream) -> TokenStream {
                 category: #category,
                 description: #excel_description,
                 arg_infos: #static_args_name,
+                help_topic: #help_topic,
             }
         }
+
+        #group_registration
     };
     
     TokenStream::from(expanded)
 }
 
+#[proc_macro_attribute]
+pub fn xl_command(attr: TokenStream, input: TokenStream) -> TokenStream {
+    let input_fn = parse_macro_input!(input as ItemFn);
+
+    // Parse attribute parameters like: prefix="my", rename="RepriceAll", shortcut="^+R"
+    let mut prefix = "xl".to_string();
+    let mut rename = String::new();
+    let mut shortcut = String::new();
+    parse_xl_command_attributes(&attr.to_string(), &mut prefix, &mut rename, &mut shortcut);
+
+    let fn_name = &input_fn.sig.ident;
+    if !input_fn.sig.inputs.is_empty() {
+        return syn::Error::new_spanned(&input_fn.sig.inputs, "xl_command functions cannot take arguments")
+            .to_compile_error()
+            .into();
+    }
+
+    // Generate Excel command name
+    let excel_cmd_name = if !rename.is_empty() {
+        rename
+    } else {
+        format!("{}_{}", prefix, fn_name)
+    };
+    let xl_cmd_name = quote::format_ident!("{}", excel_cmd_name);
+    let xl_cmd_name_str = xl_cmd_name.to_string();
+
+    // Commands returning a Result report failures in an alert box
+    let is_result_type = match &input_fn.sig.output {
+        syn::ReturnType::Type(_, ty) => {
+            if let syn::Type::Path(type_path) = ty.as_ref() {
+                type_path.path.segments.first()
+                    .map(|seg| seg.ident == "Result")
+                    .unwrap_or(false)
+            } else {
+                false
+            }
+        }
+        _ => false,
+    };
+
+    let command_call = if is_result_type {
+        quote! {
+            match #fn_name() {
+                Ok(_) => 1,
+                Err(e) => {
+                    xladd_core::commands::alert(&format!("{} failed: {}", #xl_cmd_name_str, e));
+                    0
+                }
+            }
+        }
+    } else {
+        quote! {
+            #fn_name();
+            1
+        }
+    };
+
+    let expanded = quote! {
+        // The original user function (unchanged)
+        #input_fn
+
+        // Excel command wrapper
+        #[unsafe(no_mangle)]
+        extern "system" fn #xl_cmd_name() -> i32 {
+            #command_call
+        }
+
+        // Auto-register this command when the module loads
+        inventory::submit! {
+            xladd_core::registrator::CommandRegistration {
+                xl_name: #xl_cmd_name_str,
+                shortcut: #shortcut,
+            }
+        }
+    };
+
+    TokenStream::from(expanded)
+}
+
+#[proc_macro_attribute]
+pub fn xl_rtd(attr: TokenStream, input: TokenStream) -> TokenStream {
+    let input_fn = parse_macro_input!(input as ItemFn);
+
+    // Parse attribute parameters like: topic="price", prefix="my", rename="Price", category="Market data"
+    let attr_str = attr.to_string();
+    let fn_name = &input_fn.sig.ident;
+    let topic = parse_attr_value(&attr_str, "topic").unwrap_or_else(|| fn_name.to_string());
+    let prefix = parse_attr_value(&attr_str, "prefix").unwrap_or_else(|| "xl".to_string());
+    let category = parse_attr_value(&attr_str, "category").unwrap_or_default();
+    let help_topic = parse_attr_value(&attr_str, "help").unwrap_or_default();
+    let excel_fn_name = parse_attr_value(&attr_str, "rename").unwrap_or_else(|| format!("{}_{}", prefix, fn_name));
+    let xl_fn_name = quote::format_ident!("{}", excel_fn_name);
+    let xl_fn_name_str = xl_fn_name.to_string();
+    let start_fn_name = quote::format_ident!("__xl_rtd_start_{}", fn_name);
+
+    // A final TopicSink parameter means the function publishes values itself; otherwise it
+    // returns an iterator or stream of values that is driven on its own thread
+    let mut params: Vec<(&syn::Ident, &syn::Type)> = Vec::new();
+    for input in &input_fn.sig.inputs {
+        if let FnArg::Typed(pat_type) = input
+            && let Pat::Ident(pat_ident) = pat_type.pat.as_ref()
+        {
+            params.push((&pat_ident.ident, pat_type.ty.as_ref()));
+        }
+    }
+    let takes_sink = params.last().map(|(_, ty)| {
+        if let syn::Type::Path(type_path) = ty {
+            type_path.path.segments.last().map(|seg| seg.ident == "TopicSink").unwrap_or(false)
+        } else {
+            false
+        }
+    }).unwrap_or(false);
+    if takes_sink {
+        params.pop();
+    }
+    let returns_stream = match &input_fn.sig.output {
+        syn::ReturnType::Type(_, ty) => quote!(#ty).to_string().contains("Stream"),
+        syn::ReturnType::Default => {
+            if !takes_sink {
+                return syn::Error::new_spanned(&input_fn.sig, "xl_rtd functions must take a TopicSink or return an iterator or stream")
+                    .to_compile_error()
+                    .into();
+            }
+            false
+        }
+    };
+
+    // Topic arguments arrive as strings and are parsed into the parameter types
+    let arg_parsing = params.iter().map(|(name, ty)| {
+        let name_str = name.to_string();
+        quote! {
+            let #name = match args.next().map(|arg| arg.parse::<#ty>()) {
+                Some(Ok(val)) => val,
+                _ => {
+                    sink.update(format!("#ERR invalid argument {}", #name_str));
+                    return;
+                }
+            };
+        }
+    });
+    let call_args: Vec<_> = params.iter().map(|(name, _)| quote! { #name }).collect();
+    let start_call = if takes_sink {
+        quote! { #fn_name(#(#call_args,)* sink); }
+    } else if returns_stream {
+        quote! { xladd_core::rtd::spawn_stream(#fn_name(#(#call_args),*), sink); }
+    } else {
+        quote! { xladd_core::rtd::spawn_iter(#fn_name(#(#call_args),*), sink); }
+    };
+
+    // Worksheet function that calls RTD for the topic
+    let xl_args = params.iter().map(|(name, _)| quote! { #name: xladd_core::xlcall::LPXLOPER12 });
+    let arg_strings = params.iter().map(|(name, _)| {
+        quote! { String::from(&xladd_core::variant::Variant::from(#name)) }
+    });
+    let mut reg_string = params.iter().map(|_| "Q").collect::<String>();
+    reg_string.push('Q');
+    let param_names_str = params.iter().map(|(name, _)| name.to_string()).collect::<Vec<_>>().join(",");
+    let description = input_fn.attrs.iter()
+        .filter_map(|attr| match &attr.meta {
+            syn::Meta::NameValue(nv) if attr.path().is_ident("doc") => match &nv.value {
+                syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Str(lit_str), .. }) => Some(lit_str.value().trim().to_string()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+    let description = if description.is_empty() {
+        format!("Real-time values of the {} topic", topic)
+    } else {
+        description.chars().take(255).collect()
+    };
+    let arg_infos = params.iter().map(|(name, _)| {
+        let name_str = name.to_string();
+        let description = format!("Parameter {}", name_str);
+        quote! {
+            xladd_core::registrator::ArgInfo {
+                name: #name_str,
+                description: #description,
+                excel_type: "Q",
+            }
+        }
+    });
+    let static_args_name = quote::format_ident!("ARGS_{}", fn_name.to_string().to_uppercase());
+
+    let expanded = quote! {
+        // The original user function (unchanged)
+        #input_fn
+
+        // Starts the topic when Excel connects to it
+        fn #start_fn_name(args: &[String], sink: xladd_core::rtd::TopicSink) {
+            let mut args = args.iter();
+            #(#arg_parsing)*
+            #start_call
+        }
+
+        inventory::submit! {
+            xladd_core::rtd::RtdRegistration {
+                topic: #topic,
+                start: #start_fn_name,
+            }
+        }
+
+        // Excel wrapper function
+        #[unsafe(no_mangle)]
+        extern "system" fn #xl_fn_name(#(#xl_args),*) -> xladd_core::xlcall::LPXLOPER12 {
+            let args: Vec<String> = vec![#(#arg_strings),*];
+            xladd_core::xlcall::LPXLOPER12::from(xladd_core::rtd::rtd(#topic, &args))
+        }
+
+        static #static_args_name: &[xladd_core::registrator::ArgInfo] = &[#(#arg_infos),*];
+
+        inventory::submit! {
+            xladd_core::registrator::FunctionRegistration {
+                xl_name: #xl_fn_name_str,
+                arg_types: #reg_string,
+                arg_names: #param_names_str,
+                category: #category,
+                description: #description,
+                arg_infos: #static_args_name,
+                help_topic: #help_topic,
+            }
+        }
+    };
+
+    TokenStream::from(expanded)
+}
+
+/// Puts a function in the group named by its attribute, if there is one
+fn group_registration(group: &Option<String>, xl_fn_name_str: &str) -> proc_macro2::TokenStream {
+    match group {
+        Some(group) => quote! {
+            inventory::submit! {
+                xladd_core::groups::FunctionGroup {
+                    xl_name: #xl_fn_name_str,
+                    group: #group,
+                }
+            }
+        },
+        None => quote! {},
+    }
+}
+
+/// Finds the quoted value of `key="value"` or `key = "value"` in an attribute
+fn parse_attr_value(attr_str: &str, key: &str) -> Option<String> {
+    let start = attr_str.find(&format!("{}=\"", key)).or_else(|| attr_str.find(&format!("{} = \"", key)))?;
+    let start = start + attr_str[start..].find('"')? + 1;
+    let end = attr_str[start..].find('"')?;
+    Some(attr_str[start..start + end].to_string())
+}
+
+/// Parse xl_command attribute parameters
+fn parse_xl_command_attributes(
+    attr_str: &str,
+    prefix: &mut String,
+    rename: &mut String,
+    shortcut: &mut String,
+) {
+    if let Some(start) = attr_str.find("prefix=\"").or_else(|| attr_str.find("prefix = \"")) {
+        let start = start + attr_str[start..].find('"').unwrap() + 1;
+        if let Some(end) = attr_str[start..].find('"') {
+            *prefix = attr_str[start..start + end].to_string();
+        }
+    }
+
+    if let Some(start) = attr_str.find("rename=\"").or_else(|| attr_str.find("rename = \"")) {
+        let start = start + attr_str[start..].find('"').unwrap() + 1;
+        if let Some(end) = attr_str[start..].find('"') {
+            *rename = attr_str[start..start + end].to_string();
+        }
+    }
+
+    if let Some(start) = attr_str.find("shortcut=\"").or_else(|| attr_str.find("shortcut = \"")) {
+        let start = start + attr_str[start..].find('"').unwrap() + 1;
+        if let Some(end) = attr_str[start..].find('"') {
+            *shortcut = attr_str[start..start + end].to_string();
+        }
+    }
+}
+
 /// Parse xl_func attribute parameters including param descriptions
 fn parse_xl_func_attributes(
     attr_str: &str,
@@ -349,4 +855,69 @@ fn parse_xl_func_attributes(
             }
         }
     }
-}
\ No newline at end of file
+}
+
+/// Parses the caching options of `#[xl_func]`: `cache` on its own, or `cache_ttl = seconds`,
+/// `cache_capacity = count` and `cache_persist`, any of which also turns caching on.
+/// Returns None if the function is not cached, otherwise the time to live and capacity if
+/// given, and whether results are persisted to disk.
+fn parse_cache_attributes(attr_str: &str) -> Option<(Option<u64>, Option<u64>, bool)> {
+    let number = |name: &str| -> Option<u64> {
+        let start = attr_str.find(name)? + name.len();
+        let rest = attr_str[start..].trim_start().strip_prefix('=')?.trim_start();
+        let digits: String = rest.chars().take_while(|c| c.is_ascii_digit()).collect();
+        digits.parse().ok()
+    };
+    let ttl = number("cache_ttl");
+    let capacity = number("cache_capacity");
+    let flag = attr_str.split(',').any(|option| option.trim() == "cache");
+    let persist = attr_str.split(',').any(|option| option.trim() == "cache_persist");
+    if flag || persist || ttl.is_some() || capacity.is_some() {
+        Some((ttl, capacity, persist))
+    } else {
+        None
+    }
+}
+
+/// Parses `timeout = seconds` of `#[xl_func]`, which may have a fractional part
+fn parse_timeout_attribute(attr_str: &str) -> Option<f64> {
+    let seconds = match attribute_value(attr_str, "timeout")? {
+        syn::Lit::Int(int) => int.base10_parse().ok()?,
+        syn::Lit::Float(float) => float.base10_parse().ok()?,
+        _ => return None,
+    };
+    Some(seconds).filter(|seconds: &f64| *seconds > 0.0)
+}
+
+/// The literal given to `name = value` among the comma separated options of an attribute.
+/// The options are parsed as meta items, so a name is never matched inside another name
+/// or a string.
+fn attribute_value(attr_str: &str, name: &str) -> Option<syn::Lit> {
+    let parser = syn::punctuated::Punctuated::<syn::Meta, syn::Token![,]>::parse_terminated;
+    let options = syn::parse::Parser::parse_str(parser, attr_str).ok()?;
+    options.into_iter().find_map(|option| match option {
+        syn::Meta::NameValue(syn::MetaNameValue { path, value: syn::Expr::Lit(literal), .. }) if path.is_ident(name) => {
+            Some(literal.lit)
+        }
+        _ => None,
+    })
+}
+
+/// Whether a parameter is `&mut ProgressReporter`, which the wrapper supplies itself
+fn is_progress_reporter(ty: &syn::Type) -> bool {
+    if let syn::Type::Reference(reference) = ty
+        && reference.mutability.is_some()
+        && let syn::Type::Path(type_path) = reference.elem.as_ref()
+    {
+        return type_path.path.segments.last().is_some_and(|segment| segment.ident == "ProgressReporter");
+    }
+    false
+}
+
+/// Parses `debounce = milliseconds` of `#[xl_func]`
+fn parse_debounce_attribute(attr_str: &str) -> Option<u64> {
+    let syn::Lit::Int(milliseconds) = attribute_value(attr_str, "debounce")? else {
+        return None;
+    };
+    milliseconds.base10_parse().ok().filter(|milliseconds| *milliseconds > 0)
+}
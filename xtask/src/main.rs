@@ -0,0 +1,260 @@
+//! Build tasks run with `cargo xtask`. So far there is one, which turns the add-in's
+//! cdylib into something that can be deployed:
+//!
+//! cargo xtask package
+//! cargo xtask package --target x86_64-pc-windows-msvc --out C:\Deploy\Actuarial
+//!
+//! It builds the add-in in release for 64-bit and 32-bit Excel, with a Windows version
+//! resource and icon linked in, and copies each build to `dist/` as an `.xll`. The
+//! settings file is copied beside them, and the IntelliSense XML beside each one under
+//! its name, if the package names them. They are set under `[package.metadata.xll]` in
+//! the add-in's Cargo.toml, all optional, with paths relative to the package:
+//!
+//! [package.metadata.xll]
+//! name = "Actuarial"                      # file name of the xll; the package name if unset
+//! product = "Actuarial Functions"         # product name in the version resource
+//! company = "Acme Ltd"
+//! copyright = "Copyright 2026 Acme Ltd"
+//! icon = "assets/addin.ico"
+//! config = "addin.toml"
+//! intellisense = "assets/Actuarial.intellisense.xml"
+//!
+//! The resource is compiled with rc.exe or llvm-rc for MSVC targets and windres for GNU
+//! ones. `--no-resource` builds without it where none of those is installed.
+
+mod resource;
+
+use serde_json::Value;
+use thiserror::Error;
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitCode};
+
+const USAGE: &str = "\
+usage: cargo xtask package [options]
+
+options:
+    --target <triple>    build for this target; repeat for several
+                         (default: x86_64-pc-windows-msvc and i686-pc-windows-msvc)
+    --package <name>     the add-in package (default: the workspace's cdylib)
+    --out <dir>          where to put the add-in (default: dist in the workspace)
+    --debug              build without optimisations
+    --no-resource        leave out the version resource and icon";
+
+const DEFAULT_TARGETS: [&str; 2] = ["x86_64-pc-windows-msvc", "i686-pc-windows-msvc"];
+
+/// The name xladd_core::config looks for next to the xll
+const CONFIG_FILE_NAME: &str = "addin.toml";
+
+#[derive(Debug, Error)]
+pub enum PackageError {
+    #[error("{0}")]
+    Usage(String),
+    #[error("Cannot run {0}: {1}")]
+    Spawn(String, std::io::Error),
+    #[error("{0} failed with {1}")]
+    Failed(String, std::process::ExitStatus),
+    #[error("Unexpected output from cargo metadata: {0}")]
+    Metadata(String),
+    #[error("No package in the workspace builds a cdylib; name one with --package")]
+    NoAddin,
+    #[error("Cannot write {0}: {1}")]
+    Write(PathBuf, std::io::Error),
+    #[error("Cannot copy {0}: {1}")]
+    Copy(PathBuf, std::io::Error),
+    #[error("No resource compiler found; tried {0}. Install one, or pass --no-resource")]
+    NoResourceCompiler(String),
+}
+
+#[derive(Debug)]
+struct Options {
+    targets: Vec<String>,
+    package: Option<String>,
+    out: Option<PathBuf>,
+    release: bool,
+    resource: bool,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Options, PackageError> {
+        let mut options = Options { targets: Vec::new(), package: None, out: None, release: true, resource: true };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().cloned().ok_or_else(|| PackageError::Usage(format!("{} needs a value", arg)));
+            match arg.as_str() {
+                "--target" => options.targets.push(value()?),
+                "--package" | "-p" => options.package = Some(value()?),
+                "--out" => options.out = Some(PathBuf::from(value()?)),
+                "--debug" => options.release = false,
+                "--no-resource" => options.resource = false,
+                _ => return Err(PackageError::Usage(format!("unknown option {}", arg))),
+            }
+        }
+        if options.targets.is_empty() {
+            options.targets = DEFAULT_TARGETS.iter().map(|target| target.to_string()).collect();
+        }
+        Ok(options)
+    }
+}
+
+/// The add-in package, and what `[package.metadata.xll]` says about it
+#[derive(Debug)]
+pub struct Addin {
+    pub package: String,
+    /// The name of the library, which names the dll
+    pub lib_name: String,
+    pub version: String,
+    pub description: String,
+    pub workspace_root: PathBuf,
+    pub target_dir: PathBuf,
+    /// The file name of the xll, without the bitness or extension
+    pub name: String,
+    pub product: String,
+    pub company: String,
+    pub copyright: String,
+    pub icon: Option<PathBuf>,
+    pub config: Option<PathBuf>,
+    pub intellisense: Option<PathBuf>,
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.split_first() {
+        Some((task, rest)) if task == "package" => Options::parse(rest).and_then(|options| package(&options)),
+        Some((task, _)) => Err(PackageError::Usage(format!("unknown task {}", task))),
+        None => Err(PackageError::Usage("no task given".to_string())),
+    };
+    match result {
+        Ok(files) => {
+            for file in files {
+                println!("{}", file.display());
+            }
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("error: {}", e);
+            if matches!(e, PackageError::Usage(_)) {
+                eprintln!("\n{}", USAGE);
+            }
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Builds and copies the add-in for every target, returning the files written
+fn package(options: &Options) -> Result<Vec<PathBuf>, PackageError> {
+    let addin = addin(options.package.as_deref())?;
+    let out = options.out.clone().unwrap_or_else(|| addin.workspace_root.join("dist"));
+    std::fs::create_dir_all(&out).map_err(|e| PackageError::Write(out.clone(), e))?;
+    let mut files = Vec::new();
+    for target in &options.targets {
+        let xll_name = format!("{}-{}.xll", addin.name, architecture(target));
+        let dll = build(&addin, target, &xll_name, options)?;
+        let xll = out.join(&xll_name);
+        copy(&dll, &xll)?;
+        files.push(xll.clone());
+        if let Some(intellisense) = &addin.intellisense {
+            let beside = xll.with_extension("intellisense.xml");
+            copy(intellisense, &beside)?;
+            files.push(beside);
+        }
+    }
+    if let Some(config) = &addin.config {
+        let beside = out.join(CONFIG_FILE_NAME);
+        copy(config, &beside)?;
+        files.push(beside);
+    }
+    Ok(files)
+}
+
+/// Builds the add-in for one target, linking in the version resource, and returns the dll
+fn build(addin: &Addin, target: &str, xll_name: &str, options: &Options) -> Result<PathBuf, PackageError> {
+    let mut cargo = Command::new(std::env::var_os("CARGO").unwrap_or_else(|| "cargo".into()));
+    cargo.current_dir(&addin.workspace_root);
+    if options.resource {
+        let work = addin.target_dir.join("xtask").join(target);
+        std::fs::create_dir_all(&work).map_err(|e| PackageError::Write(work.clone(), e))?;
+        let script = resource::write_script(addin, &work, xll_name, !options.release)?;
+        let compiled = resource::compile(&script, target)?;
+        // cargo rustc passes the flags after -- to the add-in crate only
+        cargo.args(["rustc", "--lib", "--package", &addin.package, "--target", target]);
+        if options.release {
+            cargo.arg("--release");
+        }
+        cargo.arg("--").arg("-C").arg(format!("link-arg={}", compiled.display()));
+    } else {
+        cargo.args(["build", "--lib", "--package", &addin.package, "--target", target]);
+        if options.release {
+            cargo.arg("--release");
+        }
+    }
+    run(&mut cargo, "cargo")?;
+    let profile = if options.release { "release" } else { "debug" };
+    Ok(addin.target_dir.join(target).join(profile).join(format!("{}.dll", addin.lib_name)))
+}
+
+/// Finds the add-in package with cargo metadata
+fn addin(package: Option<&str>) -> Result<Addin, PackageError> {
+    let cargo = std::env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+    let output = Command::new(cargo)
+        .args(["metadata", "--format-version", "1", "--no-deps"])
+        .output()
+        .map_err(|e| PackageError::Spawn("cargo metadata".to_string(), e))?;
+    if !output.status.success() {
+        return Err(PackageError::Failed("cargo metadata".to_string(), output.status));
+    }
+    let metadata: Value = serde_json::from_slice(&output.stdout).map_err(|e| PackageError::Metadata(e.to_string()))?;
+    let packages = metadata["packages"].as_array().ok_or_else(|| PackageError::Metadata("no packages".to_string()))?;
+    let cdylib = |target: &Value| target["crate_types"].as_array().is_some_and(|types| types.iter().any(|t| t == "cdylib"));
+    let found = packages.iter().find(|candidate| match package {
+        Some(package) => candidate["name"] == package,
+        None => candidate["targets"].as_array().is_some_and(|targets| targets.iter().any(cdylib)),
+    });
+    let found = found.ok_or(PackageError::NoAddin)?;
+    let text = |value: &Value| value.as_str().unwrap_or_default().to_string();
+    let name = text(&found["name"]);
+    let dir = Path::new(found["manifest_path"].as_str().unwrap_or_default()).parent().map(Path::to_path_buf).unwrap_or_default();
+    let lib_name = found["targets"]
+        .as_array()
+        .and_then(|targets| targets.iter().find(|target| cdylib(target)))
+        .map_or_else(|| name.replace('-', "_"), |target| text(&target["name"]).replace('-', "_"));
+    let xll = &found["metadata"]["xll"];
+    let setting = |key: &str| xll[key].as_str().filter(|value| !value.is_empty()).map(str::to_string);
+    let path = |key: &str| setting(key).map(|path| dir.join(path));
+    let authors: Vec<String> = found["authors"].as_array().map(|authors| authors.iter().map(text).collect()).unwrap_or_default();
+    Ok(Addin {
+        lib_name,
+        version: text(&found["version"]),
+        description: text(&found["description"]),
+        workspace_root: PathBuf::from(text(&metadata["workspace_root"])),
+        target_dir: PathBuf::from(text(&metadata["target_directory"])),
+        name: setting("name").unwrap_or_else(|| name.clone()),
+        product: setting("product").unwrap_or_else(|| name.clone()),
+        company: setting("company").unwrap_or_else(|| authors.join(", ")),
+        copyright: setting("copyright").unwrap_or_default(),
+        icon: path("icon"),
+        config: path("config"),
+        intellisense: path("intellisense"),
+        package: name,
+    })
+}
+
+/// The architecture part of the xll name, as Excel's own installers name them
+fn architecture(target: &str) -> &str {
+    match target.split('-').next().unwrap_or_default() {
+        "x86_64" => "x64",
+        "i686" | "i586" => "x86",
+        "aarch64" => "arm64",
+        other => other,
+    }
+}
+
+fn copy(from: &Path, to: &Path) -> Result<(), PackageError> {
+    std::fs::copy(from, to).map(|_| ()).map_err(|e| PackageError::Copy(from.to_path_buf(), e))
+}
+
+/// Runs a command, failing if it cannot be started or does not succeed
+pub fn run(command: &mut Command, name: &str) -> Result<(), PackageError> {
+    let status = command.status().map_err(|e| PackageError::Spawn(name.to_string(), e))?;
+    if status.success() { Ok(()) } else { Err(PackageError::Failed(name.to_string(), status)) }
+}
@@ -0,0 +1,110 @@
+//! The Windows version resource, which Explorer shows under Properties > Details and IT
+//! inventory tools read, and the icon. The script is written without `#include
+//! <winver.h>`, so it compiles without the Windows SDK headers on the include path.
+
+use crate::{Addin, PackageError};
+
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// VOS_NT_WINDOWS32
+const FILE_OS: &str = "0x40004";
+/// VFT_DLL
+const FILE_TYPE: &str = "0x2";
+/// VS_FF_DEBUG
+const DEBUG_FLAG: &str = "0x1";
+
+/// Writes the resource script for one xll and returns its path
+pub fn write_script(addin: &Addin, dir: &Path, xll_name: &str, debug: bool) -> Result<PathBuf, PackageError> {
+    let numbers = version_numbers(&addin.version);
+    let description = if addin.description.is_empty() { &addin.product } else { &addin.description };
+    let strings = [
+        ("CompanyName", addin.company.as_str()),
+        ("FileDescription", description),
+        ("FileVersion", &addin.version),
+        ("InternalName", &addin.name),
+        ("LegalCopyright", &addin.copyright),
+        ("OriginalFilename", xll_name),
+        ("ProductName", &addin.product),
+        ("ProductVersion", &addin.version),
+    ];
+    let mut lines = vec![
+        "1 VERSIONINFO".to_string(),
+        format!("FILEVERSION {}", numbers),
+        format!("PRODUCTVERSION {}", numbers),
+        "FILEFLAGSMASK 0x3F".to_string(),
+        format!("FILEFLAGS {}", if debug { DEBUG_FLAG } else { "0x0" }),
+        format!("FILEOS {}", FILE_OS),
+        format!("FILETYPE {}", FILE_TYPE),
+        "FILESUBTYPE 0x0".to_string(),
+        "BEGIN".to_string(),
+        "    BLOCK \"StringFileInfo\"".to_string(),
+        "    BEGIN".to_string(),
+        // US English, Unicode
+        "        BLOCK \"040904B0\"".to_string(),
+        "        BEGIN".to_string(),
+    ];
+    for (key, value) in strings.iter().filter(|(_, value)| !value.is_empty()) {
+        lines.push(format!("            VALUE \"{}\", \"{}\"", key, quoted(value)));
+    }
+    lines.extend(
+        ["        END", "    END", "    BLOCK \"VarFileInfo\"", "    BEGIN", "        VALUE \"Translation\", 0x409, 1200", "    END", "END"]
+            .map(str::to_string),
+    );
+    if let Some(icon) = &addin.icon {
+        lines.push(format!("1 ICON \"{}\"", quoted(&icon.display().to_string())));
+    }
+    let path = dir.join("addin.rc");
+    std::fs::write(&path, lines.join("\n") + "\n").map_err(|e| PackageError::Write(path.clone(), e))?;
+    Ok(path)
+}
+
+/// Compiles a resource script into something the target's linker takes: a .res file for
+/// MSVC, or a COFF object for GNU
+pub fn compile(script: &Path, target: &str) -> Result<PathBuf, PackageError> {
+    let gnu = target.ends_with("-gnu") || target.ends_with("-gnullvm");
+    let compiled = script.with_extension(if gnu { "res.o" } else { "res" });
+    let mut tried = Vec::new();
+    let attempts: Vec<(String, Vec<String>)> = if gnu {
+        let arch = target.split('-').next().unwrap_or_default();
+        let format = if arch == "x86_64" { "pe-x86-64" } else { "pe-i386" };
+        let args = |extra: &[&str]| {
+            let mut args: Vec<String> = extra.iter().map(|arg| arg.to_string()).collect();
+            args.extend([script.display().to_string(), "-O".into(), "coff".into(), "-o".into(), compiled.display().to_string()]);
+            args
+        };
+        vec![
+            (format!("{}-w64-mingw32-windres", arch), args(&[])),
+            ("windres".to_string(), args(&["--target", format])),
+            ("llvm-windres".to_string(), args(&["--target", format])),
+        ]
+    } else {
+        let args = |first: &str| vec![first.to_string(), "/fo".to_string(), compiled.display().to_string(), script.display().to_string()];
+        // The script includes nothing, so llvm-rc needs no preprocessor
+        vec![("rc".to_string(), args("/nologo")), ("llvm-rc".to_string(), args("-no-cpp"))]
+    };
+    for (program, args) in attempts {
+        match Command::new(&program).args(&args).status() {
+            Ok(status) if status.success() => return Ok(compiled),
+            Ok(status) => return Err(PackageError::Failed(program, status)),
+            Err(e) if e.kind() == ErrorKind::NotFound => tried.push(program),
+            Err(e) => return Err(PackageError::Spawn(program, e)),
+        }
+    }
+    Err(PackageError::NoResourceCompiler(tried.join(", ")))
+}
+
+/// The four numbers of a version resource from a semver version, so 1.4.2-beta.1 gives
+/// 1,4,2,0
+fn version_numbers(version: &str) -> String {
+    let core = version.split(['-', '+']).next().unwrap_or_default();
+    let mut numbers: Vec<u16> = core.split('.').map(|part| part.parse().unwrap_or(0)).collect();
+    numbers.resize(4, 0);
+    numbers.iter().map(u16::to_string).collect::<Vec<_>>().join(",")
+}
+
+/// A string as it goes between quotes in a resource script
+fn quoted(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\"\"")
+}